@@ -0,0 +1,307 @@
+use crate::config::AllocationQueueConfig;
+use crate::model::errors::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Queue that limits the number of allocations executing at the same time. Requests that can't be
+/// executed immediately wait in a bounded queue for a free slot.
+pub struct AllocationQueue {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: AtomicUsize,
+    max_queued: AtomicUsize,
+    n_queued: AtomicUsize,
+
+    /// Number of slots that are still to be retired after decreasing the maximum number of
+    /// concurrent allocations, retired as the allocations executing in them finish.
+    n_retiring: Arc<AtomicUsize>,
+}
+
+/// A slot for executing an allocation, released when dropped.
+pub struct AllocationSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    n_retiring: Arc<AtomicUsize>,
+
+    /// Position in the queue when the request arrived. Zero means that the request didn't wait.
+    pub queue_position: usize,
+}
+
+impl Drop for AllocationSlot {
+    /// Releases the slot, or retires it if the queue has slots to retire.
+    fn drop(&mut self) {
+        let retire = self
+            .n_retiring
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if let (true, Some(permit)) = (retire, self.permit.take()) {
+            permit.forget();
+        }
+    }
+}
+
+impl AllocationQueue {
+    /// Creates a new [AllocationQueue] given the configuration.
+    pub fn new(config: &AllocationQueueConfig) -> AllocationQueue {
+        if config.max_concurrent_allocations < 1 {
+            panic!(
+                "Maximum number of concurrent allocations must be at least 1. You provided {}.",
+                config.max_concurrent_allocations
+            )
+        }
+
+        AllocationQueue {
+            semaphore: Arc::new(Semaphore::new(config.max_concurrent_allocations)),
+            max_concurrent: AtomicUsize::new(config.max_concurrent_allocations),
+            max_queued: AtomicUsize::new(config.max_queued_allocations),
+            n_queued: AtomicUsize::new(0),
+            n_retiring: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Changes the limits of the queue without interrupting the allocations in progress. When the
+    /// maximum number of concurrent allocations decreases, the free surplus slots are retired
+    /// immediately and the others as soon as the allocations executing in them finish. When it
+    /// increases, the slots still to be retired are kept before adding new ones.
+    pub fn resize(&self, config: &AllocationQueueConfig) -> Result<(), Error> {
+        if config.max_concurrent_allocations < 1 {
+            return Err(Error {
+                code: "maximum-concurrent-allocations-must-be-positive".to_string(),
+                message: format!(
+                    "Maximum number of concurrent allocations must be at least 1. You provided {}.",
+                    config.max_concurrent_allocations
+                ),
+            });
+        }
+
+        self.max_queued
+            .store(config.max_queued_allocations, Ordering::SeqCst);
+        let previous = self
+            .max_concurrent
+            .swap(config.max_concurrent_allocations, Ordering::SeqCst);
+        if config.max_concurrent_allocations > previous {
+            let increase = config.max_concurrent_allocations - previous;
+            let n_retiring = self
+                .n_retiring
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    Some(n.saturating_sub(increase))
+                })
+                .expect("Update of the slots to retire always succeeds.");
+            self.semaphore
+                .add_permits(increase - n_retiring.min(increase));
+        } else if config.max_concurrent_allocations < previous {
+            let mut surplus = previous - config.max_concurrent_allocations;
+            while surplus > 0 {
+                match self.semaphore.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => break,
+                }
+                surplus -= 1;
+            }
+            self.n_retiring.fetch_add(surplus, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Number of allocations currently waiting for a free slot.
+    pub fn n_queued(&self) -> usize {
+        self.n_queued.load(Ordering::SeqCst)
+    }
+
+    /// Waits for a free slot for executing an allocation. Fails immediately if the queue is full.
+    pub async fn acquire(&self) -> Result<AllocationSlot, Error> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(AllocationSlot {
+                permit: Some(permit),
+                n_retiring: self.n_retiring.clone(),
+                queue_position: 0,
+            });
+        }
+
+        let queue_position = self.n_queued.fetch_add(1, Ordering::SeqCst) + 1;
+        let queued = QueuedAllocation(&self.n_queued);
+        let max_queued = self.max_queued.load(Ordering::SeqCst);
+        if queue_position > max_queued {
+            return Err(Error {
+                code: "allocation-queue-is-full".to_string(),
+                message: format!(
+                    "There are already {} allocations waiting for execution, which is the maximum. \
+                    Try again later.",
+                    max_queued
+                ),
+            });
+        }
+
+        let permit = self.semaphore.clone().acquire_owned().await;
+        drop(queued);
+
+        Ok(AllocationSlot {
+            permit: Some(permit.expect("Allocation queue semaphore is never closed.")),
+            n_retiring: self.n_retiring.clone(),
+            queue_position,
+        })
+    }
+}
+
+/// Allocation waiting in the queue, which leaves the queue when dropped. Since the request handler
+/// is dropped when the client disconnects, the allocation may leave the queue without a slot.
+struct QueuedAllocation<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedAllocation<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_acquire_waits_in_queue_and_rejects_when_full() {
+        let queue = AllocationQueue::new(&AllocationQueueConfig {
+            max_concurrent_allocations: 1,
+            max_queued_allocations: 1,
+        });
+
+        // First request executes immediately
+        let first = queue.acquire().await.unwrap();
+        assert_eq!(first.queue_position, 0);
+
+        // Second request waits in the queue until the first one is done
+        let second = queue.acquire();
+        tokio::pin!(second);
+        assert!(futures_poll_once(second.as_mut()).await.is_none());
+        assert_eq!(queue.n_queued(), 1);
+
+        // Third request is rejected since the queue is full
+        let e = queue.acquire().await.err().unwrap();
+        assert_eq!(e.code, "allocation-queue-is-full");
+
+        drop(first);
+        let second = second.await.unwrap();
+        assert_eq!(second.queue_position, 1);
+        assert_eq!(queue.n_queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_acquire_leaves_queue() {
+        let queue = AllocationQueue::new(&AllocationQueueConfig {
+            max_concurrent_allocations: 1,
+            max_queued_allocations: 1,
+        });
+        let slot = queue.acquire().await.unwrap();
+
+        // Request is cancelled while waiting, e.g. because the client disconnected
+        let waiting = tokio::time::timeout(Duration::from_millis(10), queue.acquire()).await;
+        assert!(waiting.is_err());
+        assert_eq!(queue.n_queued(), 0);
+
+        // Queue accepts a waiting request again
+        let next = queue.acquire();
+        tokio::pin!(next);
+        assert!(futures_poll_once(next.as_mut()).await.is_none());
+        assert_eq!(queue.n_queued(), 1);
+        drop(slot);
+        assert_eq!(next.await.unwrap().queue_position, 1);
+        assert_eq!(queue.n_queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resize_keeps_allocations_in_progress() {
+        let queue = AllocationQueue::new(&AllocationQueueConfig {
+            max_concurrent_allocations: 2,
+            max_queued_allocations: 0,
+        });
+        let first = queue.acquire().await.unwrap();
+        let second = queue.acquire().await.unwrap();
+
+        // Shrinking to one slot waits for both allocations to finish before admitting another one
+        queue
+            .resize(&AllocationQueueConfig {
+                max_concurrent_allocations: 1,
+                max_queued_allocations: 1,
+            })
+            .unwrap();
+        drop(first);
+        let third = queue.acquire();
+        tokio::pin!(third);
+        assert!(futures_poll_once(third.as_mut()).await.is_none());
+        drop(second);
+        assert_eq!(third.await.unwrap().queue_position, 1);
+
+        // Growing admits more allocations immediately
+        queue
+            .resize(&AllocationQueueConfig {
+                max_concurrent_allocations: 3,
+                max_queued_allocations: 1,
+            })
+            .unwrap();
+        let _slots = [
+            queue.acquire().await.unwrap(),
+            queue.acquire().await.unwrap(),
+        ];
+        assert!(queue.resize(&AllocationQueueConfig::default()).is_ok());
+        assert_eq!(
+            queue
+                .resize(&AllocationQueueConfig {
+                    max_concurrent_allocations: 0,
+                    max_queued_allocations: 1,
+                })
+                .unwrap_err()
+                .code,
+            "maximum-concurrent-allocations-must-be-positive"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shrink_and_grow_while_slots_are_held() {
+        let queue = AllocationQueue::new(&AllocationQueueConfig {
+            max_concurrent_allocations: 3,
+            max_queued_allocations: 1,
+        });
+        let first = queue.acquire().await.unwrap();
+        let second = queue.acquire().await.unwrap();
+        let resize = |max_concurrent_allocations| {
+            queue
+                .resize(&AllocationQueueConfig {
+                    max_concurrent_allocations,
+                    max_queued_allocations: 1,
+                })
+                .unwrap()
+        };
+
+        // The free slot is retired immediately, so a request waits without being blocked by the
+        // slot still to be retired
+        resize(1);
+        let third = queue.acquire();
+        tokio::pin!(third);
+        assert!(futures_poll_once(third.as_mut()).await.is_none());
+
+        // Growing before the held slot is retired keeps it instead of adding a new one
+        resize(2);
+        drop(first);
+        let third = third.await.unwrap();
+        assert_eq!(third.queue_position, 1);
+
+        // Exactly two allocations execute at the same time
+        let fourth = queue.acquire();
+        tokio::pin!(fourth);
+        assert!(futures_poll_once(fourth.as_mut()).await.is_none());
+        drop(second);
+        let _fourth = fourth.await.unwrap();
+        drop(third);
+        let _fifth = queue.acquire().await.unwrap();
+        assert_eq!(0, queue.semaphore.available_permits());
+    }
+
+    /// Polls the future once and returns its output if it's ready.
+    async fn futures_poll_once<F: std::future::Future + Unpin>(future: F) -> Option<F::Output> {
+        tokio::select! {
+            biased;
+            output = future => Some(output),
+            _ = std::future::ready(()) => None,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::model::allocations::{AllocationDiff, FractionChange, StoredAllocation};
+use crate::model::company::Ticker;
+use crate::model::errors::Error;
+use crate::model::portfolio::AllocationInput;
+use crate::model::responses::AllocationResponse;
+use crate::storage::{load, save, Collection, Storage};
+use chrono::Utc;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Store of the allocations performed by the server, identified by increasing ids starting at 1.
+/// Each allocation is persisted as a document in the storage and loaded on demand, such that the
+/// memory of the server doesn't grow with the number of allocations.
+pub struct AllocationStore {
+    storage: Arc<dyn Storage>,
+    last_id: AtomicU64,
+}
+
+impl AllocationStore {
+    /// Creates a new [AllocationStore] on top of the storage, continuing after the largest
+    /// persisted id.
+    pub fn new(storage: Arc<dyn Storage>) -> Result<AllocationStore, Error> {
+        let last_id = storage
+            .list(Collection::Allocations)?
+            .iter()
+            .filter_map(|(key, _)| key.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+
+        Ok(AllocationStore {
+            storage,
+            last_id: AtomicU64::new(last_id),
+        })
+    }
+
+    /// Returns the allocation with the given id, if any and if it was recorded for the given API
+    /// key. Since the ids are sequential, allocations of other API keys are treated as missing.
+    pub fn get(&self, id: u64, api_key: &str) -> Result<Option<StoredAllocation>, Error> {
+        Ok(self
+            .get_shared(id)?
+            .filter(|allocation| allocation.api_key.as_deref() == Some(api_key)))
+    }
+
+    /// Returns the allocation with the given id regardless of its API key, for the recipients of a
+    /// signed share link.
+    pub fn get_shared(&self, id: u64) -> Result<Option<StoredAllocation>, Error> {
+        load(
+            self.storage.as_ref(),
+            Collection::Allocations,
+            &id.to_string(),
+        )
+    }
+
+    /// Records the allocation of the input for the API key and returns its id.
+    pub fn record(
+        &self,
+        api_key: &str,
+        input: AllocationInput,
+        response: AllocationResponse,
+    ) -> Result<u64, Error> {
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let allocation = StoredAllocation {
+            id,
+            api_key: Some(api_key.to_string()),
+            created_at: Utc::now(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            input,
+            response,
+        };
+        save(
+            self.storage.as_ref(),
+            Collection::Allocations,
+            &id.to_string(),
+            &allocation,
+        )?;
+
+        Ok(id)
+    }
+}
+
+/// Compares two allocations of the same input, e.g. the stored one and the one re-run with the
+/// current code.
+pub fn allocation_diff(
+    original: &AllocationResponse,
+    rerun: &AllocationResponse,
+) -> AllocationDiff {
+    let fractions = |response: &AllocationResponse| -> HashMap<Ticker, f64> {
+        response
+            .result
+            .iter()
+            .flat_map(|r| r.allocations.iter())
+            .map(|tf| (tf.ticker.clone(), tf.fraction))
+            .collect()
+    };
+    let original_fractions = fractions(original);
+    let new_fractions = fractions(rerun);
+
+    // Tickers are ordered by their appearance in the original allocation, followed by new ones
+    let tickers = original
+        .result
+        .iter()
+        .chain(rerun.result.iter())
+        .flat_map(|r| r.allocations.iter())
+        .map(|tf| tf.ticker.clone())
+        .unique();
+    let fraction_changes = tickers
+        .map(|ticker| {
+            let original_fraction = original_fractions.get(&ticker).copied().unwrap_or(0.0);
+            let new_fraction = new_fractions.get(&ticker).copied().unwrap_or(0.0);
+            FractionChange {
+                ticker,
+                original_fraction,
+                new_fraction,
+                change: new_fraction - original_fraction,
+            }
+        })
+        .collect_vec();
+
+    AllocationDiff {
+        max_absolute_fraction_change: fraction_changes
+            .iter()
+            .map(|c| c.change.abs())
+            .fold(0.0, f64::max),
+        fraction_changes,
+        expected_log_growth_change: original.result.as_ref().zip(rerun.result.as_ref()).map(
+            |(o, r)| r.solver_metadata.expected_log_growth - o.solver_metadata.expected_log_growth,
+        ),
+        error_changed: original.error.as_ref().map(|e| &e.code)
+            != rerun.error.as_ref().map(|e| &e.code),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::memory_storage::MemoryStorage;
+
+    fn input() -> AllocationInput {
+        serde_yaml::from_str(
+            "
+            candidates:
+              - name: A
+                ticker: A
+                description: Business A
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: 0.5
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability: 0.5
+            ",
+        )
+        .unwrap()
+    }
+
+    fn error_response(code: &str) -> AllocationResponse {
+        AllocationResponse {
+            result: None,
+            validation_problems: None,
+            error: Some(Error {
+                code: code.to_string(),
+                message: "".to_string(),
+            }),
+            logs: None,
+        }
+    }
+
+    #[test]
+    fn test_record_and_get() {
+        let store = AllocationStore::new(Arc::new(MemoryStorage::default())).unwrap();
+
+        assert_eq!(1, store.record("k", input(), error_response("a")).unwrap());
+        assert_eq!(2, store.record("k", input(), error_response("b")).unwrap());
+
+        let stored = store.get(2, "k").unwrap().unwrap();
+        assert_eq!(2, stored.id);
+        assert_eq!(env!("CARGO_PKG_VERSION"), stored.crate_version);
+        assert_eq!("A", stored.input.candidates[0].ticker);
+        assert_eq!("b", stored.response.error.unwrap().code);
+        assert!(store.get(3, "k").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_allocations_of_other_api_keys_are_missing() {
+        let store = AllocationStore::new(Arc::new(MemoryStorage::default())).unwrap();
+        store.record("k", input(), error_response("a")).unwrap();
+
+        assert!(store.get(1, "other").unwrap().is_none());
+        assert_eq!(1, store.get_shared(1).unwrap().unwrap().id);
+    }
+
+    #[test]
+    fn test_persisted_allocations_are_loaded() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+
+        AllocationStore::new(storage.clone())
+            .unwrap()
+            .record("k", input(), error_response("a"))
+            .unwrap();
+        let store = AllocationStore::new(storage).unwrap();
+
+        let stored = store.get(1, "k").unwrap().unwrap();
+        assert_eq!("a", stored.response.error.unwrap().code);
+        assert_eq!(2, store.record("k", input(), error_response("b")).unwrap());
+    }
+
+    #[test]
+    fn test_allocation_diff() {
+        let diff = allocation_diff(&error_response("a"), &error_response("a"));
+        assert!(diff.fraction_changes.is_empty());
+        assert_eq!(0.0, diff.max_absolute_fraction_change);
+        assert!(diff.expected_log_growth_change.is_none());
+        assert!(!diff.error_changed);
+
+        assert!(allocation_diff(&error_response("a"), &error_response("b")).error_changed);
+    }
+}
@@ -1,10 +1,21 @@
-use crate::model::company::Ticker;
+use crate::model::analysis_options::{AnalysisOptions, DetailLevel};
+use crate::model::company::{self, Ticker};
 use crate::model::errors::Error;
-use crate::model::portfolio::Portfolio;
-use crate::model::responses::ProbabilityAndReturns;
+use crate::model::events::PortfolioEvent;
+use crate::model::portfolio::{MacroScenario, Portfolio, PortfolioCompany};
+use crate::model::responses::{
+    CompoundGrowth, ErrorBound, Estimate, EventOutcome, HistogramBin, InflationAdjustedReturns,
+    NumericalErrors, ProbabilityAndReturns, ReturnAndProbability, ReturnDistribution,
+    SampledStatistics, TagConditionalOutcome,
+};
+use crate::model::scenario::Scenario;
+use itertools::Itertools;
 use ordered_float::OrderedFloat;
-use slog::{info, Logger};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use slog::{info, warn, Logger};
 use std::collections::HashMap;
+use std::f64::consts::LN_2;
 
 /// An outcome consists of its probability and portfolio return
 #[derive(Debug)]
@@ -14,20 +25,146 @@ pub struct Outcome {
     pub company_returns: HashMap<Ticker, f64>,
 }
 
-/// Returns all possible outcomes (expected portfolio return and associated probability)
-pub fn all_outcomes(portfolio: &Portfolio) -> Result<Vec<Outcome>, Error> {
-    // Number of different outcomes is a product of number of all scenarios for all companies
-    let n_outcomes = if !portfolio.companies.is_empty() {
-        portfolio
-            .companies
+/// Portfolios with more outcomes aren't enumerated, see [all_outcomes]
+pub const MAX_OUTCOMES: usize = 50000;
+
+/// Number of outcomes sampled from portfolios with too many outcomes if not given
+pub const DEFAULT_N_SAMPLES: usize = 100000;
+
+/// Confidence level of the intervals of the statistics estimated from sampled outcomes, as the
+/// number of standard errors (95%)
+const CONFIDENCE_Z: f64 = 1.96;
+
+/// Probability of the tail of the worst sampled outcomes, whose statistics are estimated
+pub const TAIL_PROBABILITY: f64 = 0.05;
+
+/// Macro scenario of the portfolio, in which the companies are independent. Companies whose
+/// scenarios aren't placed in macro scenarios take part in every macro scenario with all their
+/// scenarios, so a portfolio without macro scenarios consists of a single macro scenario.
+struct MacroBranch {
+    /// Name of the macro scenario, or none for the single one of a portfolio without them
+    name: Option<String>,
+    probability: f64,
+    /// Indices of the scenarios of each company in the macro scenario, with the probabilities of
+    /// the scenarios conditional on the macro scenario.
+    scenarios: Vec<Vec<(usize, f64)>>,
+}
+
+impl MacroBranch {
+    /// Returns the number of outcomes in the macro scenario, saturating at the largest number.
+    fn n_outcomes(&self) -> usize {
+        self.scenarios
             .iter()
-            .map(|pc| pc.company.scenarios.len())
-            .product()
-    } else {
-        0
+            .map(|s| s.len())
+            .fold(1, usize::saturating_mul)
+    }
+}
+
+/// Returns the macro scenarios of the portfolio, in the order in which they first appear among the
+/// scenarios of the companies. The probability of a macro scenario is the declared one of the
+/// portfolio, or if it isn't declared, the total probability of the scenarios placed in it averaged
+/// over the companies placing scenarios in macro scenarios. The probabilities are normalized to
+/// sum up to one over the macro scenarios in which any scenario is placed. A company without
+/// scenarios in a macro scenario (e.g. after removing the negligible ones) takes part in it with
+/// all its scenarios.
+fn macro_branches(portfolio: &Portfolio) -> Vec<MacroBranch> {
+    let all_scenarios = |c: &company::Company| {
+        c.scenarios
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (i, s.probability))
+            .collect_vec()
     };
+    let names = portfolio
+        .companies
+        .iter()
+        .flat_map(|pc| pc.company.scenarios.iter())
+        .filter_map(|s| s.macro_scenario.as_deref())
+        .unique()
+        .collect_vec();
+    if names.is_empty() {
+        return vec![MacroBranch {
+            name: None,
+            probability: 1.0,
+            scenarios: portfolio
+                .companies
+                .iter()
+                .map(|pc| all_scenarios(&pc.company))
+                .collect(),
+        }];
+    }
+
+    let mut branches = names
+        .iter()
+        .map(|name| {
+            let mut company_probabilities: Vec<f64> = Vec::new();
+            let scenarios = portfolio
+                .companies
+                .iter()
+                .map(|pc| {
+                    let c = &pc.company;
+                    if c.scenarios.iter().all(|s| s.macro_scenario.is_none()) {
+                        return all_scenarios(c);
+                    }
+                    let placed = c
+                        .scenarios
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, s)| s.macro_scenario.as_deref() == Some(*name))
+                        .map(|(i, s)| (i, s.probability))
+                        .collect_vec();
+                    let probability: f64 = placed.iter().map(|(_, p)| p).sum();
+                    company_probabilities.push(probability);
+                    if probability <= 0.0 {
+                        return all_scenarios(c);
+                    }
+                    placed
+                        .into_iter()
+                        .map(|(i, p)| (i, p / probability))
+                        .collect()
+                })
+                .collect();
+            let declared_probability = portfolio
+                .macro_scenarios
+                .iter()
+                .flatten()
+                .find(|m| m.name == *name)
+                .map(|m| m.probability);
+            MacroBranch {
+                name: Some(name.to_string()),
+                probability: declared_probability.unwrap_or_else(|| {
+                    company_probabilities.iter().sum::<f64>() / company_probabilities.len() as f64
+                }),
+                scenarios,
+            }
+        })
+        .collect_vec();
 
-    if n_outcomes > 50000 {
+    let total: f64 = branches.iter().map(|b| b.probability).sum();
+    branches.iter_mut().for_each(|b| b.probability /= total);
+    branches
+}
+
+/// Returns the number of different outcomes, which is the product of the numbers of scenarios of
+/// all companies summed over the macro scenarios, saturating at the largest number
+pub fn n_outcomes(portfolio: &Portfolio) -> usize {
+    if portfolio.companies.is_empty() {
+        return 0;
+    }
+
+    macro_branches(portfolio)
+        .iter()
+        .map(|b| b.n_outcomes())
+        .fold(0, usize::saturating_add)
+}
+
+/// Returns all possible outcomes (expected portfolio return and associated probability). Within
+/// each macro scenario, the companies are independent, so the probability of an outcome is the
+/// probability of the macro scenario times the product of the conditional probabilities of the
+/// scenarios of all companies.
+pub fn all_outcomes(portfolio: &Portfolio) -> Result<Vec<Outcome>, Error> {
+    let n_outcomes = n_outcomes(portfolio);
+    if n_outcomes > MAX_OUTCOMES {
         return Err(Error {
             code: "more-than-fifty-thousand-outcomes".to_string(),
             message: format!(
@@ -41,61 +178,242 @@ pub fn all_outcomes(portfolio: &Portfolio) -> Result<Vec<Outcome>, Error> {
     // Mutable data that's populated/modified within the loop below
     // 1. Vectors for all outcomes
     let mut outcomes: Vec<Outcome> = Vec::with_capacity(n_outcomes);
+    if portfolio.companies.is_empty() {
+        return Ok(outcomes);
+    }
 
-    // 2. Helper vectors keeping track of current indices for scenarios of all companies
-    let mut scenario_indices: Vec<usize> = vec![0; portfolio.companies.len()];
-    let n_scenarios: Vec<usize> = portfolio
-        .companies
-        .iter()
-        .map(|pc| pc.company.scenarios.len())
-        .collect();
+    for branch in macro_branches(portfolio) {
+        // 2. Helper vectors keeping track of current indices for scenarios of all companies
+        let mut scenario_indices: Vec<usize> = vec![0; portfolio.companies.len()];
+        let n_branch_outcomes = branch.n_outcomes();
 
-    // Start filling in outcomes until all are collected
-    while outcomes.len() != n_outcomes {
-        // 1. Calculate the outcome by summing up scenarios for all companies
-        // Note: Probability is initialized with 1.0 since we multiply to get joint probability
-        let mut outcome = Outcome {
-            weighted_return: 0.0,
-            probability: 1.0,
-            company_returns: HashMap::with_capacity(portfolio.companies.len()),
-        };
+        // Start filling in outcomes until all of the macro scenario are collected
+        for _ in 0..n_branch_outcomes {
+            // 1. Calculate the outcome by summing up scenarios for all companies
+            // Note: Probability is initialized with the probability of the macro scenario since we
+            // multiply to get joint probability
+            let mut outcome = Outcome {
+                weighted_return: 0.0,
+                probability: branch.probability,
+                company_returns: HashMap::with_capacity(portfolio.companies.len()),
+            };
 
-        portfolio
-            .companies
-            .iter()
-            .enumerate()
-            .for_each(|(ticker_id, pc)| {
-                let scenario_id = scenario_indices[ticker_id];
-                let c = &pc.company;
-                let s = &c.scenarios[scenario_id];
+            portfolio
+                .companies
+                .iter()
+                .enumerate()
+                .for_each(|(ticker_id, pc)| {
+                    let (scenario_id, probability) =
+                        branch.scenarios[ticker_id][scenario_indices[ticker_id]];
+                    let c = &pc.company;
+                    let s = &c.scenarios[scenario_id];
+
+                    let company_return = s.scenario_return(c.market_cap);
+                    outcome.weighted_return += pc.fraction * company_return;
+                    outcome.probability *= probability;
+                    outcome
+                        .company_returns
+                        .insert(c.ticker.clone(), company_return);
+                });
+
+            // 2. Append the calculated outcome to the list of outcomes
+            outcomes.push(outcome);
+
+            // 3. Increment a single index to prepare for the next iteration
+            for (i, scenario_id) in scenario_indices.iter_mut().enumerate() {
+                if *scenario_id + 1 == branch.scenarios[i].len() {
+                    // We have exhausted the index for this company, set to zero and continue the
+                    // loop in order to start incrementing the next index
+                    *scenario_id = 0;
+                    continue;
+                } else {
+                    // Increment the first non-overflowing index and break out
+                    *scenario_id += 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(outcomes)
+}
+
+/// Samples outcomes by drawing a scenario of each company independently according to the scenario
+/// probabilities, for portfolios with too many outcomes to enumerate them. With macro scenarios,
+/// the macro scenario is drawn first and the scenarios of the companies are drawn conditional on
+/// it. Each sampled outcome has the probability one over the number of samples, such that the
+/// functions taking outcomes (e.g. [cumulative_probability_of_loss]) estimate their statistics
+/// from the samples. The same seed gives the same samples.
+pub fn sampled_outcomes(portfolio: &Portfolio, n_samples: usize, seed: u64) -> Vec<Outcome> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let probability = 1.0 / n_samples as f64;
+    let branches = macro_branches(portfolio);
+    let branch_probabilities = branches
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (i, b.probability))
+        .collect_vec();
 
-                let company_return = s.scenario_return(c.market_cap);
+    (0..n_samples)
+        .map(|_| {
+            let mut outcome = Outcome {
+                weighted_return: 0.0,
+                probability,
+                company_returns: HashMap::with_capacity(portfolio.companies.len()),
+            };
+            // Without macro scenarios, the draws are the same as for independent companies
+            let branch = match branches.len() {
+                1 => &branches[0],
+                _ => match sample_scenario(&branch_probabilities, rng.gen::<f64>()) {
+                    Some(b) => &branches[b],
+                    None => return outcome,
+                },
+            };
+            for (pc, scenarios) in portfolio.companies.iter().zip(&branch.scenarios) {
+                let c = &pc.company;
+                let Some(s) = sample_scenario(scenarios, rng.gen::<f64>()) else {
+                    continue;
+                };
+                let company_return = c.scenarios[s].scenario_return(c.market_cap);
                 outcome.weighted_return += pc.fraction * company_return;
-                outcome.probability *= s.probability;
                 outcome
                     .company_returns
                     .insert(c.ticker.clone(), company_return);
-            });
+            }
+            outcome
+        })
+        .collect()
+}
 
-        // 2. Append the calculated outcome to the list of outcomes
-        outcomes.push(outcome);
+/// Returns the index of the scenario in which the uniformly distributed draw falls when the
+/// scenarios partition the unit interval by their probabilities. The last scenario takes up the
+/// rest of the interval in case the probabilities sum up to slightly less than 1.
+fn sample_scenario(scenarios: &[(usize, f64)], draw: f64) -> Option<usize> {
+    let mut cumulative_probability = 0.0;
+    scenarios
+        .iter()
+        .find(|(_, p)| {
+            cumulative_probability += p;
+            draw < cumulative_probability
+        })
+        .or(scenarios.last())
+        .map(|(i, _)| *i)
+}
 
-        // 3. Increment a single index to prepare for the next iteration
-        for (i, scenario_id) in scenario_indices.iter_mut().enumerate() {
-            if *scenario_id + 1 == n_scenarios[i] {
-                // We have exhausted the index for this company, set to zero and continue the
-                // loop in order to start incrementing the next index
-                *scenario_id = 0;
-                continue;
-            } else {
-                // Increment the first non-overflowing index and break out
-                *scenario_id += 1;
-                break;
+/// Estimates the expected return, the probability of loss and the statistics of the tail of the
+/// worst outcomes from the sampled outcomes, with 95% confidence intervals.
+pub fn sampled_statistics(outcomes: &[Outcome], seed: u64) -> SampledStatistics {
+    let n = outcomes.len() as f64;
+    let returns = outcomes
+        .iter()
+        .map(|o| o.weighted_return)
+        .sorted_by_key(|r| OrderedFloat(*r))
+        .collect_vec();
+
+    let mean_estimate = |values: &[f64]| -> Estimate {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        estimate(mean, (variance / n).sqrt())
+    };
+
+    let probability_of_loss = returns.iter().filter(|r| **r < 0.0).count() as f64 / n;
+
+    // Interval of the quantile from the order statistics, whose rank is binomially distributed
+    let quantile_rank = TAIL_PROBABILITY * n;
+    let rank_error = CONFIDENCE_Z * (n * TAIL_PROBABILITY * (1.0 - TAIL_PROBABILITY)).sqrt();
+    let order_statistic = |rank: f64| returns[(rank.max(0.0) as usize).min(returns.len() - 1)];
+    let tail = &returns[..(quantile_rank.ceil() as usize).clamp(1, returns.len())];
+
+    SampledStatistics {
+        n_samples: outcomes.len(),
+        seed,
+        expected_return: mean_estimate(&returns),
+        probability_of_loss: estimate(
+            probability_of_loss,
+            (probability_of_loss * (1.0 - probability_of_loss) / n).sqrt(),
+        ),
+        tail_quantile: Estimate {
+            value: order_statistic(quantile_rank),
+            lower: order_statistic(quantile_rank - rank_error),
+            upper: order_statistic(quantile_rank + rank_error),
+        },
+        expected_tail_return: mean_estimate(tail),
+    }
+}
+
+/// Returns the distribution of the portfolio return over the outcomes. Without the number of bins,
+/// outcomes with the same return are merged and the distinct returns are sorted. With the number
+/// of bins, the returns are binned into a histogram of equally wide bins between the lowest and
+/// the highest return.
+pub fn return_distribution(outcomes: &[Outcome], n_bins: Option<usize>) -> ReturnDistribution {
+    let returns = outcomes
+        .iter()
+        .map(|o| (o.weighted_return, o.probability))
+        .sorted_by_key(|(r, _)| OrderedFloat(*r))
+        .collect_vec();
+
+    match n_bins {
+        None => ReturnDistribution {
+            outcomes: Some(
+                returns
+                    .into_iter()
+                    .map(|(portfolio_return, probability)| ReturnAndProbability {
+                        portfolio_return,
+                        probability,
+                    })
+                    .coalesce(|a, b| {
+                        if (b.portfolio_return - a.portfolio_return).abs() < company::TOLERANCE {
+                            Ok(ReturnAndProbability {
+                                portfolio_return: a.portfolio_return,
+                                probability: a.probability + b.probability,
+                            })
+                        } else {
+                            Err((a, b))
+                        }
+                    })
+                    .collect(),
+            ),
+            histogram: None,
+        },
+        Some(n_bins) => {
+            let n_bins = n_bins.max(1);
+            let (lowest, highest) = match (returns.first(), returns.last()) {
+                (Some((lowest, _)), Some((highest, _))) => (*lowest, *highest),
+                _ => (0.0, 0.0),
+            };
+            let width = (highest - lowest) / n_bins as f64;
+            let mut bins = (0..n_bins)
+                .map(|i| HistogramBin {
+                    lower: lowest + i as f64 * width,
+                    upper: lowest + (i + 1) as f64 * width,
+                    probability: 0.0,
+                })
+                .collect_vec();
+            for (r, probability) in returns {
+                let bin = if width > 0.0 {
+                    (((r - lowest) / width) as usize).min(n_bins - 1)
+                } else {
+                    0
+                };
+                bins[bin].probability += probability;
+            }
+
+            ReturnDistribution {
+                outcomes: None,
+                histogram: Some(bins),
             }
         }
     }
+}
 
-    Ok(outcomes)
+/// Returns the estimate with the confidence interval given by its standard error.
+fn estimate(value: f64, standard_error: f64) -> Estimate {
+    Estimate {
+        value,
+        lower: value - CONFIDENCE_Z * standard_error,
+        upper: value + CONFIDENCE_Z * standard_error,
+    }
 }
 
 /// Calculates expected return of a portfolio
@@ -121,6 +439,225 @@ pub fn expected_return(portfolio: &Portfolio, logger: &Logger) -> f64 {
     expected_return
 }
 
+/// Largest number of meaningful decimal places, i.e. the precision of a double
+const MAX_MEANINGFUL_DECIMAL_PLACES: u32 = 15;
+
+/// Returns the bound of the rounding error of a floating-point sum of the given number of terms,
+/// each of which is a product, given the sum of their magnitudes (Higham, Accuracy and Stability
+/// of Numerical Algorithms, section 4.2).
+fn summation_error_bound(n_terms: usize, sum_of_magnitudes: f64) -> f64 {
+    let n_roundings = (n_terms + 1) as f64 * 0.5 * f64::EPSILON;
+    n_roundings / (1.0 - n_roundings) * sum_of_magnitudes
+}
+
+/// Returns the error bound together with the number of decimal places it leaves meaningful.
+fn error_bound(error_bound: f64) -> ErrorBound {
+    let meaningful_decimal_places = if error_bound > 0.0 {
+        (-error_bound.log10())
+            .floor()
+            .clamp(0.0, MAX_MEANINGFUL_DECIMAL_PLACES as f64) as u32
+    } else {
+        MAX_MEANINGFUL_DECIMAL_PLACES
+    };
+    ErrorBound {
+        error_bound,
+        meaningful_decimal_places,
+    }
+}
+
+/// Calculates the bounds of the numerical errors of the expected return and, given the outcomes,
+/// the cumulative probability of loss. The fractions are assumed to be known within the given
+/// tolerance, e.g. the solver tolerance for allocated fractions or zero for given ones. Outcomes
+/// whose return is within its error bound of zero may be counted as a loss or not, so their
+/// probabilities are added to the error bound of the probability of loss.
+pub fn numerical_errors(
+    portfolio: &Portfolio,
+    outcomes: Option<&[Outcome]>,
+    fraction_tolerance: f64,
+) -> NumericalErrors {
+    let n_scenarios: usize = portfolio
+        .companies
+        .iter()
+        .map(|pc| pc.company.scenarios.len())
+        .sum();
+    let (weighted_magnitude, scenario_magnitude) = portfolio
+        .companies
+        .iter()
+        .flat_map(|pc| {
+            pc.company.scenarios.iter().map(|s| {
+                let r = s.probability_weighted_return(pc.company.market_cap).abs();
+                (pc.fraction.abs() * r, r)
+            })
+        })
+        .fold((0.0, 0.0), |(w, r), (wi, ri)| (w + wi, r + ri));
+    let expected_return = error_bound(
+        summation_error_bound(n_scenarios, weighted_magnitude)
+            + fraction_tolerance * scenario_magnitude,
+    );
+
+    let cumulative_probability_of_loss = outcomes.map(|outcomes| {
+        let n_companies = portfolio.companies.len();
+        let (ambiguous_probability, total_probability) =
+            outcomes.iter().fold((0.0, 0.0), |(a, t), o| {
+                let (weighted_magnitude, return_magnitude) = portfolio
+                    .companies
+                    .iter()
+                    .map(|pc| {
+                        let r = o.company_returns[&pc.company.ticker].abs();
+                        (pc.fraction.abs() * r, r)
+                    })
+                    .fold((0.0, 0.0), |(w, r), (wi, ri)| (w + wi, r + ri));
+                let return_error = summation_error_bound(n_companies, weighted_magnitude)
+                    + fraction_tolerance * return_magnitude;
+                let is_ambiguous = o.weighted_return.abs() < return_error;
+                (
+                    a + if is_ambiguous { o.probability } else { 0.0 },
+                    t + o.probability,
+                )
+            });
+        error_bound(
+            summation_error_bound(outcomes.len(), total_probability) + ambiguous_probability,
+        )
+    });
+
+    NumericalErrors {
+        expected_return,
+        cumulative_probability_of_loss,
+    }
+}
+
+/// Tolerance used when cross-checking analytical moments against the ones from enumerated outcomes
+const MOMENTS_CROSS_CHECK_TOLERANCE: f64 = 1e-8;
+
+/// Mean and variance of the weighted portfolio return
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Moments {
+    pub mean: f64,
+    pub variance: f64,
+}
+
+/// Calculates mean and variance of the weighted portfolio return in closed form, company by
+/// company, without enumerating all outcomes. Since companies are independent within a macro
+/// scenario, the conditional mean is the fraction-weighted sum of company means and the conditional
+/// variance is the sum of company variances weighted by squared fractions. The variance adds the
+/// variance of the conditional means across the macro scenarios (law of total variance).
+pub fn analytical_moments(portfolio: &Portfolio) -> Moments {
+    let branch_moments = macro_branches(portfolio)
+        .iter()
+        .map(|branch| {
+            let moments = portfolio.companies.iter().zip(&branch.scenarios).fold(
+                Moments {
+                    mean: 0.0,
+                    variance: 0.0,
+                },
+                |moments, (pc, scenarios)| {
+                    let market_cap = pc.company.market_cap;
+                    let (mean, second_moment) =
+                        scenarios
+                            .iter()
+                            .fold((0.0, 0.0), |(mean, second_moment), (i, p)| {
+                                let r = pc.company.scenarios[*i].scenario_return(market_cap);
+                                (mean + p * r, second_moment + p * r * r)
+                            });
+
+                    Moments {
+                        mean: moments.mean + pc.fraction * mean,
+                        variance: moments.variance
+                            + pc.fraction.powi(2) * (second_moment - mean * mean),
+                    }
+                },
+            );
+            (branch.probability, moments)
+        })
+        .collect_vec();
+
+    // A single macro scenario keeps the moments exactly as calculated
+    if let [(_, moments)] = branch_moments[..] {
+        return moments;
+    }
+    let mean: f64 = branch_moments.iter().map(|(p, m)| p * m.mean).sum();
+    Moments {
+        mean,
+        variance: branch_moments
+            .iter()
+            .map(|(p, m)| p * (m.variance + (m.mean - mean).powi(2)))
+            .sum(),
+    }
+}
+
+/// Diversification metrics of the portfolio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diversification {
+    pub effective_number_of_positions: f64,
+    pub max_weight: f64,
+    pub gross_exposure: f64,
+    pub net_exposure: f64,
+}
+
+/// Calculates diversification metrics from the fractions of the portfolio. The weights are the
+/// absolute fractions relative to the gross exposure (sum of absolute fractions), such that short
+/// positions count towards diversification as well. The effective number of positions is the
+/// inverse of the Herfindahl index of the weights, and the maximum weight is the largest absolute
+/// fraction. The net exposure is the sum of fractions, negative for short positions.
+pub fn diversification(portfolio: &Portfolio) -> Diversification {
+    let gross_exposure: f64 = portfolio.companies.iter().map(|pc| pc.fraction.abs()).sum();
+    let sum_of_squares: f64 = portfolio
+        .companies
+        .iter()
+        .map(|pc| pc.fraction.powi(2))
+        .sum();
+
+    Diversification {
+        effective_number_of_positions: if sum_of_squares > 0.0 {
+            gross_exposure.powi(2) / sum_of_squares
+        } else {
+            0.0
+        },
+        max_weight: portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.fraction.abs())
+            .fold(0.0, f64::max),
+        gross_exposure,
+        net_exposure: portfolio.companies.iter().map(|pc| pc.fraction).sum(),
+    }
+}
+
+/// Calculates mean and variance of the weighted portfolio return from all enumerated outcomes
+pub fn enumerated_moments(outcomes: &[Outcome]) -> Moments {
+    let mean: f64 = outcomes
+        .iter()
+        .map(|o| o.probability * o.weighted_return)
+        .sum();
+    let variance: f64 = outcomes
+        .iter()
+        .map(|o| o.probability * (o.weighted_return - mean).powi(2))
+        .sum();
+
+    Moments { mean, variance }
+}
+
+/// Cross-checks analytical moments against the moments calculated from enumerated outcomes and
+/// logs a warning in case of a discrepancy. Returns true if the moments agree.
+pub fn cross_check_moments(portfolio: &Portfolio, outcomes: &[Outcome], logger: &Logger) -> bool {
+    let analytical = analytical_moments(portfolio);
+    let enumerated = enumerated_moments(outcomes);
+
+    let agree = (analytical.mean - enumerated.mean).abs() < MOMENTS_CROSS_CHECK_TOLERANCE
+        && (analytical.variance - enumerated.variance).abs() < MOMENTS_CROSS_CHECK_TOLERANCE;
+    if !agree {
+        warn!(
+            logger,
+            "Analytical moments {:?} differ from the moments of enumerated outcomes {:?}. Check \
+            whether the scenario probabilities of all companies sum up to 1.",
+            analytical,
+            enumerated
+        );
+    }
+
+    agree
+}
+
 /// Finds the worst case outcome in a portfolio.
 pub fn worst_case_outcome(portfolio: &Portfolio, logger: &Logger) -> ProbabilityAndReturns {
     if portfolio.companies.is_empty() {
@@ -131,27 +668,43 @@ pub fn worst_case_outcome(portfolio: &Portfolio, logger: &Logger) -> Probability
         logger,
         "Searching for the worst case outcome in a portfolio."
     );
-    // Fraction may be negative (shorting), take it into account when finding the minimum.
-    let mut worst_case_probability = 1.0;
-    let mut worst_case_return = 0.0;
-    let mut worst_case_probability_weighted_return = 0.0;
-    portfolio.companies.iter().for_each(|c| {
-        let worst_scenario = c
-            .company
-            .scenarios
+    // Fraction may be negative (shorting), take it into account when finding the minimum. Each
+    // macro scenario has its own worst case, of which the one with the lowest probability weighted
+    // return is taken.
+    let (worst_case_probability, worst_case_return, worst_case_probability_weighted_return) =
+        macro_branches(portfolio)
             .iter()
-            .min_by_key(|s| OrderedFloat(s.probability_weighted_return(c.company.market_cap)))
-            .unwrap_or_else(|| {
-                panic!(
-                    "Did not manage to find worst-case scenario for the company {:?}.",
-                    c.company.ticker
-                )
-            });
-        let weighted_return = c.fraction * worst_scenario.scenario_return(c.company.market_cap);
-        worst_case_probability *= worst_scenario.probability;
-        worst_case_return += weighted_return;
-        worst_case_probability_weighted_return += worst_scenario.probability * weighted_return;
-    });
+            .map(|branch| {
+                let mut probability = branch.probability;
+                let mut portfolio_return = 0.0;
+                let mut probability_weighted_return = 0.0;
+                portfolio
+                    .companies
+                    .iter()
+                    .zip(&branch.scenarios)
+                    .for_each(|(c, scenarios)| {
+                        let market_cap = c.company.market_cap;
+                        let (worst_scenario, p) = scenarios
+                            .iter()
+                            .map(|(i, p)| (&c.company.scenarios[*i], *p))
+                            .min_by_key(|(s, p)| OrderedFloat(p * s.scenario_return(market_cap)))
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "Did not manage to find worst-case scenario for the company \
+                                    {:?}.",
+                                    c.company.ticker
+                                )
+                            });
+                        let weighted_return =
+                            c.fraction * worst_scenario.scenario_return(market_cap);
+                        probability *= p;
+                        portfolio_return += weighted_return;
+                        probability_weighted_return += branch.probability * p * weighted_return;
+                    });
+                (probability, portfolio_return, probability_weighted_return)
+            })
+            .min_by_key(|(_, _, r)| OrderedFloat(*r))
+            .expect("Portfolio has at least one macro scenario.");
 
     info!(
         logger,
@@ -170,13 +723,9 @@ pub fn worst_case_outcome(portfolio: &Portfolio, logger: &Logger) -> Probability
     }
 }
 
-/// Calculates the cumulative probability of losing money
+/// Calculates the cumulative probability of losing money, i.e. of a return below zero
 pub fn cumulative_probability_of_loss(outcomes: &[Outcome], logger: &Logger) -> f64 {
-    let cumulative_probability_of_loss = outcomes
-        .iter()
-        .filter(|o| o.weighted_return < 0.0)
-        .map(|o| o.probability)
-        .sum();
+    let cumulative_probability_of_loss = probability_of_return_below(outcomes, 0.0);
 
     info!(
         logger,
@@ -187,15 +736,542 @@ pub fn cumulative_probability_of_loss(outcomes: &[Outcome], logger: &Logger) ->
     cumulative_probability_of_loss
 }
 
+/// Confidence level of the value at risk and the conditional value at risk in the analysis
+pub const VALUE_AT_RISK_CONFIDENCE: f64 = 0.95;
+
+/// Returns the outcome returns with their probabilities, sorted from the worst to the best return.
+fn sorted_returns(outcomes: &[Outcome]) -> Vec<(f64, f64)> {
+    outcomes
+        .iter()
+        .map(|o| (o.weighted_return, o.probability))
+        .sorted_by_key(|(r, _)| OrderedFloat(*r))
+        .collect()
+}
+
+/// Calculates the value at risk at the confidence level (e.g. 0.95), i.e. the loss that isn't
+/// exceeded with the probability given by the confidence level. It's the negative of the lowest
+/// portfolio return whose cumulative probability reaches one minus the confidence level, so a
+/// negative value at risk means that even the tail of the outcomes makes money.
+pub fn value_at_risk(outcomes: &[Outcome], confidence: f64) -> f64 {
+    let tail_probability = 1.0 - confidence;
+    let returns = sorted_returns(outcomes);
+    let mut cumulative_probability = 0.0;
+    for (portfolio_return, probability) in &returns {
+        cumulative_probability += probability;
+        if cumulative_probability >= tail_probability - company::TOLERANCE {
+            return -portfolio_return;
+        }
+    }
+
+    -returns.last().map_or(0.0, |(r, _)| *r)
+}
+
+/// Calculates the conditional value at risk (expected shortfall) at the confidence level (e.g.
+/// 0.95), i.e. the expected loss in the worst outcomes whose probabilities sum up to one minus the
+/// confidence level. The outcome at the boundary of the tail is only partially taken into account.
+pub fn conditional_value_at_risk(outcomes: &[Outcome], confidence: f64) -> f64 {
+    let tail_probability = 1.0 - confidence;
+    let returns = sorted_returns(outcomes);
+    if tail_probability <= 0.0 {
+        return -returns.first().map_or(0.0, |(r, _)| *r);
+    }
+
+    let mut remaining_probability = tail_probability;
+    let mut tail_return = 0.0;
+    for (portfolio_return, probability) in returns {
+        let probability_in_tail = probability.min(remaining_probability);
+        tail_return += probability_in_tail * portfolio_return;
+        remaining_probability -= probability_in_tail;
+        if remaining_probability <= 0.0 {
+            break;
+        }
+    }
+
+    -tail_return / (tail_probability - remaining_probability)
+}
+
+/// Calculates the probability that the portfolio return falls below the threshold return, e.g.
+/// the return of a benchmark or -0.1 for the probability of losing more than 10%.
+pub fn probability_of_return_below(outcomes: &[Outcome], threshold: f64) -> f64 {
+    outcomes
+        .iter()
+        .filter(|o| o.weighted_return < threshold)
+        .map(|o| o.probability)
+        .sum()
+}
+
+/// Returns the index of the scenario of each company in the outcome with the given index among
+/// [all_outcomes]. All outcomes iterate through the macro scenarios slowest and through the
+/// scenarios of the first company fastest, so within a macro scenario the scenario of each company
+/// is a digit of the outcome index in a mixed radix system.
+pub fn outcome_scenario_indices(portfolio: &Portfolio, outcome_index: usize) -> Vec<usize> {
+    let mut remainder = outcome_index;
+    let branches = macro_branches(portfolio);
+    let branch = branches
+        .iter()
+        .find(|b| {
+            let n_branch_outcomes = b.n_outcomes();
+            if remainder < n_branch_outcomes {
+                return true;
+            }
+            remainder -= n_branch_outcomes;
+            false
+        })
+        .unwrap_or(&branches[0]);
+
+    branch
+        .scenarios
+        .iter()
+        .map(|scenarios| {
+            let (scenario_index, _) = scenarios[remainder % scenarios.len()];
+            remainder /= scenarios.len();
+            scenario_index
+        })
+        .collect()
+}
+
+/// Calculates the expected logarithmic growth rate of capital, which is the quantity maximized by
+/// the Kelly criterion. Outcomes that wipe out all capital make the growth rate negative infinity.
+pub fn expected_log_growth(outcomes: &[Outcome]) -> f64 {
+    outcomes
+        .iter()
+        .map(|o| {
+            if 1.0 + o.weighted_return > 0.0 {
+                o.probability * (1.0 + o.weighted_return).ln()
+            } else {
+                f64::NEG_INFINITY
+            }
+        })
+        .sum()
+}
+
+/// Largest share of the expected logarithmic growth that comes from a single joint outcome or a
+/// single scenario of a company.
+#[derive(Debug)]
+pub struct GrowthConcentration {
+    /// Share of the positive contributions to the expected logarithmic growth.
+    pub share: f64,
+    /// Tickers and theses of the scenarios behind the contribution, e.g. "A: Head".
+    pub theses: Vec<String>,
+}
+
+/// Returns the joint outcome with the largest contribution to the expected logarithmic growth,
+/// where an outcome contributes its probability times its logarithmic growth. The contribution is
+/// relative to the sum of positive contributions rather than the expected growth itself, which is
+/// usually a small difference between large positive and negative contributions. Outcomes must be
+/// in the order of [all_outcomes]. Returns None if no outcome grows the capital.
+pub fn dominant_outcome(
+    portfolio: &Portfolio,
+    outcomes: &[Outcome],
+) -> Option<GrowthConcentration> {
+    let contributions: Vec<f64> = outcomes
+        .iter()
+        .map(|o| {
+            if o.weighted_return > 0.0 {
+                o.probability * (1.0 + o.weighted_return).ln()
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    let total: f64 = contributions.iter().sum();
+    let (index, contribution) = contributions
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, c)| OrderedFloat(**c))?;
+    if total <= 0.0 {
+        return None;
+    }
+
+    let theses = portfolio
+        .companies
+        .iter()
+        .zip(outcome_scenario_indices(portfolio, index))
+        .map(|(pc, s)| format!("{}: {}", pc.company.ticker, pc.company.scenarios[s].thesis))
+        .collect();
+
+    Some(GrowthConcentration {
+        share: contribution / total,
+        theses,
+    })
+}
+
+/// Returns the scenario of a company with the largest contribution to the expected logarithmic
+/// growth, where a scenario contributes its probability times the logarithmic growth of the
+/// portfolio if only that company moved. As for [dominant_outcome], the contribution is relative
+/// to the sum of positive contributions. Returns None if no scenario grows the capital.
+pub fn dominant_scenario(portfolio: &Portfolio) -> Option<GrowthConcentration> {
+    let contributions: Vec<(String, f64)> = portfolio
+        .companies
+        .iter()
+        .flat_map(|pc| {
+            pc.company.scenarios.iter().map(|s| {
+                let growth = pc.fraction * s.scenario_return(pc.company.market_cap);
+                let contribution = if growth > 0.0 {
+                    s.probability * (1.0 + growth).ln()
+                } else {
+                    0.0
+                };
+                (format!("{}: {}", pc.company.ticker, s.thesis), contribution)
+            })
+        })
+        .collect();
+
+    let total: f64 = contributions.iter().map(|(_, c)| c).sum();
+    let (thesis, contribution) = contributions
+        .into_iter()
+        .max_by_key(|(_, c)| OrderedFloat(*c))?;
+    if total <= 0.0 {
+        return None;
+    }
+
+    Some(GrowthConcentration {
+        share: contribution / total,
+        theses: vec![thesis],
+    })
+}
+
+/// Returns the probability of the selected scenarios of each company in the macro scenario, where
+/// a company without a selection takes part with all its scenarios and a probability of one. Within
+/// the macro scenario the companies are independent, so the probability of the selection is the
+/// product of the conditional probabilities of the selected scenarios of the companies.
+fn selection_probability_in_branch(
+    portfolio: &Portfolio,
+    branch: &MacroBranch,
+    selected: &[Option<Vec<usize>>],
+) -> f64 {
+    portfolio
+        .companies
+        .iter()
+        .zip(&branch.scenarios)
+        .zip(selected)
+        .filter_map(|((_, scenarios), selected)| {
+            selected.as_ref().map(|selected| {
+                scenarios
+                    .iter()
+                    .filter(|(i, _)| selected.contains(i))
+                    .map(|(_, p)| p)
+                    .sum::<f64>()
+            })
+        })
+        .product()
+}
+
+/// Calculates the probability of the event and the expected portfolio return given that it occurs.
+/// Within each macro scenario the companies are independent, so the probability of the event is
+/// the product of the conditional probabilities of the selected scenarios of each company in the
+/// event, and the return of each company is its conditional expected return over the selected
+/// scenarios (or all its scenarios if it isn't in the event). Both are summed over the macro
+/// scenarios weighted by their probabilities. Tickers of the event that aren't in the portfolio are
+/// ignored.
+pub fn event_outcome(portfolio: &Portfolio, event: &PortfolioEvent) -> Result<EventOutcome, Error> {
+    let portfolio = Portfolio {
+        companies: portfolio
+            .companies
+            .iter()
+            .map(|pc| PortfolioCompany {
+                company: pc.company.with_status_quo_scenario(),
+                ..pc.clone()
+            })
+            .collect(),
+        macro_scenarios: portfolio.macro_scenarios.clone(),
+    };
+
+    let mut selected = Vec::with_capacity(portfolio.companies.len());
+    for pc in &portfolio.companies {
+        let company = &pc.company;
+        let indices = event.scenarios.get(&company.ticker).map(|theses| {
+            company
+                .scenarios
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| theses.contains(&s.thesis))
+                .map(|(i, _)| i)
+                .collect_vec()
+        });
+        if let Some(indices) = &indices {
+            let company_probability: f64 = indices
+                .iter()
+                .map(|i| company.scenarios[*i].probability)
+                .sum();
+            if company_probability <= 0.0 {
+                return Err(Error {
+                    code: "event-has-zero-probability".to_string(),
+                    message: format!(
+                        "Selected scenarios of {} in event {} have zero probability.",
+                        company.ticker, event.name
+                    ),
+                });
+            }
+        }
+        selected.push(indices);
+    }
+
+    let mut probability = 0.0;
+    let mut probability_weighted_return = 0.0;
+    for branch in macro_branches(&portfolio) {
+        let branch_probability =
+            branch.probability * selection_probability_in_branch(&portfolio, &branch, &selected);
+        if branch_probability <= 0.0 {
+            continue;
+        }
+
+        let expected_return: f64 = portfolio
+            .companies
+            .iter()
+            .zip(&branch.scenarios)
+            .zip(&selected)
+            .map(|((pc, scenarios), selected)| {
+                let market_cap = pc.company.market_cap;
+                let scenarios = scenarios
+                    .iter()
+                    .filter(|(i, _)| selected.as_ref().map_or(true, |s| s.contains(i)))
+                    .collect_vec();
+                let probability_weighted_return: f64 = scenarios
+                    .iter()
+                    .map(|(i, p)| p * pc.company.scenarios[*i].scenario_return(market_cap))
+                    .sum();
+                let company_return = match selected {
+                    Some(_) => {
+                        probability_weighted_return / scenarios.iter().map(|(_, p)| p).sum::<f64>()
+                    }
+                    None => probability_weighted_return,
+                };
+                pc.fraction * company_return
+            })
+            .sum();
+        probability += branch_probability;
+        probability_weighted_return += branch_probability * expected_return;
+    }
+
+    if probability <= 0.0 {
+        return Err(Error {
+            code: "event-has-zero-probability".to_string(),
+            message: format!(
+                "Selected scenarios in event {} are in different macro scenarios, so the event has \
+                zero probability.",
+                event.name
+            ),
+        });
+    }
+
+    Ok(EventOutcome {
+        name: event.name.clone(),
+        probability,
+        expected_portfolio_return: probability_weighted_return / probability,
+    })
+}
+
+/// Returns the portfolio conditioned on the scenario tag being realized, together with the
+/// probability of the tag. Companies with a scenario with the tag are restricted to the scenarios
+/// with the tag, whose probabilities are rescaled to sum up to one, while the other companies keep
+/// all their scenarios. Within each macro scenario the companies are independent, so the
+/// probability of the tag in it is the product of the conditional probabilities of the scenarios
+/// with the tag of each such company. The probability of the tag sums these over the macro
+/// scenarios weighted by their probabilities, and the macro scenarios of the conditioned portfolio
+/// are weighted by their probabilities given the tag.
+pub fn tag_conditional_portfolio(
+    portfolio: &Portfolio,
+    tag: &str,
+) -> Result<(Portfolio, f64), Error> {
+    if !portfolio
+        .companies
+        .iter()
+        .any(|pc| pc.company.scenarios.iter().any(|s| s.has_tag(tag)))
+    {
+        return Err(Error {
+            code: "unknown-tag-in-analysis".to_string(),
+            message: format!("No scenario of the portfolio has the tag {tag}. Check your input."),
+        });
+    }
+
+    let mut selected = Vec::with_capacity(portfolio.companies.len());
+    let mut companies = Vec::with_capacity(portfolio.companies.len());
+    for pc in &portfolio.companies {
+        let tagged = pc
+            .company
+            .scenarios
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.has_tag(tag))
+            .collect_vec();
+        if tagged.is_empty() {
+            selected.push(None);
+            companies.push(pc.clone());
+            continue;
+        }
+
+        let company_probability: f64 = tagged.iter().map(|(_, s)| s.probability).sum();
+        if company_probability <= 0.0 {
+            return Err(Error {
+                code: "tag-has-zero-probability".to_string(),
+                message: format!(
+                    "Scenarios of {} with the tag {tag} have zero probability.",
+                    pc.company.ticker
+                ),
+            });
+        }
+        selected.push(Some(tagged.iter().map(|(i, _)| *i).collect_vec()));
+
+        let mut company = pc.company.clone();
+        company.scenarios = tagged
+            .into_iter()
+            .map(|(_, s)| Scenario {
+                probability: s.probability / company_probability,
+                ..s.clone()
+            })
+            .collect();
+        companies.push(PortfolioCompany {
+            company,
+            ..pc.clone()
+        });
+    }
+
+    let branches = macro_branches(portfolio)
+        .into_iter()
+        .map(|b| {
+            let probability =
+                b.probability * selection_probability_in_branch(portfolio, &b, &selected);
+            (b.name, probability)
+        })
+        .collect_vec();
+    let probability: f64 = branches.iter().map(|(_, p)| p).sum();
+    if probability <= 0.0 {
+        return Err(Error {
+            code: "tag-has-zero-probability".to_string(),
+            message: format!(
+                "Scenarios with the tag {tag} are in different macro scenarios, so the tag has \
+                zero probability."
+            ),
+        });
+    }
+
+    // The macro scenarios are declared with their probabilities given the tag, since the ones of
+    // the portfolio are unconditional
+    let macro_scenarios = branches
+        .into_iter()
+        .map(|(name, p)| {
+            name.map(|name| MacroScenario {
+                name,
+                probability: p / probability,
+            })
+        })
+        .collect::<Option<Vec<MacroScenario>>>();
+    let conditioned = Portfolio {
+        companies,
+        macro_scenarios,
+    };
+    Ok((conditioned, probability))
+}
+
+/// Calculates the probability of the scenario tag and the expected portfolio return given that it
+/// is realized, together with the distribution of the portfolio return given the tag unless the
+/// analysis is on the summary detail level. The distribution is estimated from sampled outcomes
+/// like in the rest of the analysis if the conditioned portfolio has too many outcomes.
+pub fn tag_conditional_outcome(
+    portfolio: &Portfolio,
+    tag: &str,
+    options: &AnalysisOptions,
+) -> Result<TagConditionalOutcome, Error> {
+    let (conditioned, probability) = tag_conditional_portfolio(portfolio, tag)?;
+    let expected_portfolio_return = analytical_moments(&conditioned).mean;
+
+    let distribution = if options.detail_level.unwrap_or_default() < DetailLevel::Standard {
+        None
+    } else if n_outcomes(&conditioned) > MAX_OUTCOMES {
+        let n_samples = options.n_samples.unwrap_or(DEFAULT_N_SAMPLES).max(1);
+        let outcomes = sampled_outcomes(&conditioned, n_samples, options.seed.unwrap_or_default());
+        Some(return_distribution(&outcomes, options.n_bins))
+    } else {
+        Some(return_distribution(
+            &all_outcomes(&conditioned)?,
+            options.n_bins,
+        ))
+    };
+
+    Ok(TagConditionalOutcome {
+        tag: tag.to_string(),
+        probability,
+        expected_portfolio_return,
+        distribution,
+    })
+}
+
+/// Adjusts the nominal expected return and logarithmic growth for the expected inflation. The real
+/// return deflates the nominal one by the inflation, and the real growth subtracts the logarithmic
+/// growth of the price level from the nominal one. The inflation must be greater than -1.
+pub fn inflation_adjusted(
+    expected_inflation: f64,
+    expected_nominal_return: f64,
+    expected_nominal_log_growth: Option<f64>,
+) -> Result<InflationAdjustedReturns, Error> {
+    if expected_inflation <= -1.0 {
+        return Err(Error {
+            code: "invalid-expected-inflation".to_string(),
+            message: format!(
+                "Expected inflation must be greater than -1. You provided {expected_inflation}."
+            ),
+        });
+    }
+
+    let price_level = 1.0 + expected_inflation;
+    Ok(InflationAdjustedReturns {
+        expected_inflation,
+        expected_nominal_return,
+        expected_real_return: (1.0 + expected_nominal_return) / price_level - 1.0,
+        expected_nominal_log_growth,
+        expected_real_log_growth: expected_nominal_log_growth.map(|g| g - price_level.ln()),
+    })
+}
+
+/// Compounds the expected logarithmic growth rate per horizon of the scenarios into the geometric
+/// return and the doubling time, and annualizes them if the length of the horizon in years is
+/// given, which must then be positive.
+pub fn compound_growth(
+    expected_log_growth: f64,
+    horizon_in_years: Option<f64>,
+) -> Result<CompoundGrowth, Error> {
+    if let Some(horizon) = horizon_in_years.filter(|h| *h <= 0.0 || !h.is_finite()) {
+        return Err(Error {
+            code: "invalid-horizon-in-years".to_string(),
+            message: format!("Horizon in years must be positive. You provided {horizon}."),
+        });
+    }
+
+    let doubling_time = (expected_log_growth > 0.0).then(|| LN_2 / expected_log_growth);
+    let annualized_log_growth_rate = horizon_in_years.map(|h| expected_log_growth / h);
+    Ok(CompoundGrowth {
+        geometric_return: expected_log_growth.exp() - 1.0,
+        doubling_time,
+        horizon_in_years,
+        annualized_log_growth_rate,
+        annualized_geometric_return: annualized_log_growth_rate.map(|g| g.exp() - 1.0),
+        doubling_time_in_years: doubling_time.zip(horizon_in_years).map(|(d, h)| d * h),
+    })
+}
+
+/// Calculates the L2 (ridge) penalty on the allocation fractions of the portfolio, which is
+/// subtracted from the expected logarithmic growth when the solver is regularized.
+pub fn regularization_penalty(portfolio: &Portfolio, regularization: f64) -> f64 {
+    0.5 * regularization
+        * portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.fraction * pc.fraction)
+            .sum::<f64>()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::env::create_test_logger;
     use crate::model::company;
     use crate::model::company::Company;
-    use crate::model::portfolio::{Portfolio, PortfolioCompany};
+    use crate::model::portfolio::{MacroScenario, Portfolio, PortfolioCompany};
     use crate::model::scenario::Scenario;
     use crate::utils::assert_close;
+    use crate::validation::result::ValidationResult;
 
     impl PartialEq<Self> for Outcome {
         fn eq(&self, other: &Self) -> bool {
@@ -218,16 +1294,21 @@ mod test {
                         ticker: "A".to_string(),
                         description: "Something we should never invest into".to_string(),
                         market_cap: 1e6,
+                        auto_status_quo: None,
+                        units: None,
+                        last_reviewed: None,
                         scenarios: vec![
                             Scenario {
                                 thesis: "Head".to_string(),
                                 intrinsic_value: 2e6,
                                 probability: 0.5,
+                                ..Default::default()
                             },
                             Scenario {
                                 thesis: "Tail".to_string(),
                                 intrinsic_value: 0.0,
                                 probability: 0.5,
+                                ..Default::default()
                             },
                         ],
                     },
@@ -240,16 +1321,21 @@ mod test {
                         ticker: "B".to_string(),
                         description: "A not-so-fair coin flip".to_string(),
                         market_cap: 1e6,
+                        auto_status_quo: None,
+                        units: None,
+                        last_reviewed: None,
                         scenarios: vec![
                             Scenario {
                                 thesis: "Head".to_string(),
                                 intrinsic_value: 2e6,
                                 probability: 0.6,
+                                ..Default::default()
                             },
                             Scenario {
                                 thesis: "Tail".to_string(),
                                 intrinsic_value: 0.0,
                                 probability: 0.4,
+                                ..Default::default()
                             },
                         ],
                     },
@@ -262,27 +1348,34 @@ mod test {
                         ticker: "C".to_string(),
                         description: "Shouldn't lose money here because of xyz".to_string(),
                         market_cap: 1e8,
+                        auto_status_quo: None,
+                        units: None,
+                        last_reviewed: None,
                         scenarios: vec![
                             Scenario {
                                 thesis: "Double".to_string(),
                                 intrinsic_value: 2e8,
                                 probability: 0.3,
+                                ..Default::default()
                             },
                             Scenario {
                                 thesis: "50 percent up".to_string(),
                                 intrinsic_value: 1.5e8,
                                 probability: 0.3,
+                                ..Default::default()
                             },
                             Scenario {
                                 thesis: "Same as now".to_string(),
                                 intrinsic_value: 1e8,
                                 probability: 0.4,
+                                ..Default::default()
                             },
                         ],
                     },
                     fraction: 0.5,
                 },
             ],
+            ..Default::default()
         };
 
         test_portfolio
@@ -297,21 +1390,27 @@ mod test {
                     ticker: "A".to_string(),
                     description: "Something we should never invest into".to_string(),
                     market_cap: 1e6,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
                     scenarios: vec![
                         Scenario {
                             thesis: "Head".to_string(),
                             intrinsic_value: 2e6,
                             probability: 0.5,
+                            ..Default::default()
                         },
                         Scenario {
                             thesis: "Tail".to_string(),
                             intrinsic_value: 0.0,
                             probability: 0.5,
+                            ..Default::default()
                         },
                     ],
                 },
                 fraction: 1.0,
             }],
+            ..Default::default()
         };
 
         let logger = create_test_logger();
@@ -331,21 +1430,27 @@ mod test {
                     ticker: "B".to_string(),
                     description: "A not-so-fair coin flip".to_string(),
                     market_cap: 1e6,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
                     scenarios: vec![
                         Scenario {
                             thesis: "Head".to_string(),
                             intrinsic_value: 2e6,
                             probability: 0.8,
+                            ..Default::default()
                         },
                         Scenario {
                             thesis: "Tail".to_string(),
                             intrinsic_value: 0.0,
                             probability: 0.2,
+                            ..Default::default()
                         },
                     ],
                 },
                 fraction: 1.0,
             }],
+            ..Default::default()
         };
 
         let logger = create_test_logger();
@@ -371,7 +1476,7 @@ mod test {
     #[test]
     fn test_all_outcomes_no_assets() {
         // Create an empty portfolio and attempt to calculate all outcomes, which fails
-        let test_portfolio = Portfolio { companies: vec![] };
+        let test_portfolio = Portfolio::default();
         let all_outcomes = all_outcomes(&test_portfolio).unwrap();
 
         assert_eq!(all_outcomes, vec![]);
@@ -380,7 +1485,7 @@ mod test {
     #[test]
     fn test_all_outcomes_too_many_assets_and_scenarios() {
         // Create a portfolio with 16 companies, each with 2 scenarios
-        let mut test_portfolio: Portfolio = Portfolio { companies: vec![] };
+        let mut test_portfolio: Portfolio = Portfolio::default();
         for i in 0..16 {
             test_portfolio.companies.push(PortfolioCompany {
                 company: Company {
@@ -388,16 +1493,21 @@ mod test {
                     ticker: format!("{i}"),
                     description: format!("{i}"),
                     market_cap: 1e6,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
                     scenarios: vec![
                         Scenario {
                             thesis: "Head".to_string(),
                             intrinsic_value: 2e6,
                             probability: 0.5,
+                            ..Default::default()
                         },
                         Scenario {
                             thesis: "Tail".to_string(),
                             intrinsic_value: 0.0,
                             probability: 0.5,
+                            ..Default::default()
                         },
                     ],
                 },
@@ -576,4 +1686,627 @@ mod test {
 
         assert_close!(0.22, cumulative_probability_of_loss, company::TOLERANCE);
     }
+
+    #[test]
+    fn test_numerical_errors() {
+        let test_portfolio = get_test_portfolio_with_three_assets();
+        let all_outcomes = all_outcomes(&test_portfolio).unwrap();
+
+        // Only the rounding errors of the sums for given fractions
+        let errors = numerical_errors(&test_portfolio, Some(&all_outcomes), 0.0);
+        assert!(errors.expected_return.error_bound < 1e-14);
+        assert_eq!(15, errors.expected_return.meaningful_decimal_places);
+
+        // The outcome that breaks even (both coins lose what the third asset wins) may be rounded
+        // to a loss, so its probability of 0.06 is uncertain
+        let probability_of_loss = errors.cumulative_probability_of_loss.unwrap();
+        assert_close!(0.06, probability_of_loss.error_bound, 1e-14);
+        assert_eq!(1, probability_of_loss.meaningful_decimal_places);
+
+        // Solver tolerance of the fractions dominates for allocated fractions
+        let errors = numerical_errors(&test_portfolio, None, 1e-5);
+        assert!(errors.expected_return.error_bound > 1e-5);
+        assert_eq!(4, errors.expected_return.meaningful_decimal_places);
+        assert!(errors.cumulative_probability_of_loss.is_none());
+    }
+
+    #[test]
+    fn test_probability_of_return_below() {
+        let outcomes =
+            [(0.2, 0.9), (-0.5, 0.02), (-0.1, 0.08)].map(|(weighted_return, probability)| {
+                Outcome {
+                    weighted_return,
+                    probability,
+                    company_returns: HashMap::new(),
+                }
+            });
+
+        assert_close!(
+            0.1,
+            probability_of_return_below(&outcomes, 0.0),
+            company::TOLERANCE
+        );
+        assert_close!(
+            0.02,
+            probability_of_return_below(&outcomes, -0.1),
+            company::TOLERANCE
+        );
+        assert_close!(
+            1.0,
+            probability_of_return_below(&outcomes, 0.3),
+            company::TOLERANCE
+        );
+    }
+
+    #[test]
+    fn test_value_at_risk() {
+        let outcomes =
+            [(0.2, 0.9), (-0.5, 0.02), (-0.1, 0.08)].map(|(weighted_return, probability)| {
+                Outcome {
+                    weighted_return,
+                    probability,
+                    company_returns: HashMap::new(),
+                }
+            });
+
+        // The worst 5% of outcomes are the loss of 50% and 3% of the loss of 10%
+        assert_close!(0.1, value_at_risk(&outcomes, 0.95), company::TOLERANCE);
+        assert_close!(
+            (0.02 * 0.5 + 0.03 * 0.1) / 0.05,
+            conditional_value_at_risk(&outcomes, 0.95),
+            company::TOLERANCE
+        );
+
+        // The worst half of the outcomes make money
+        assert_close!(-0.2, value_at_risk(&outcomes, 0.5), company::TOLERANCE);
+        assert_close!(
+            (0.02 * 0.5 + 0.08 * 0.1 - 0.4 * 0.2) / 0.5,
+            conditional_value_at_risk(&outcomes, 0.5),
+            company::TOLERANCE
+        );
+        assert_close!(
+            0.5,
+            conditional_value_at_risk(&outcomes, 1.0),
+            company::TOLERANCE
+        );
+    }
+
+    #[test]
+    fn test_sampled_outcomes_approximate_all_outcomes() {
+        let logger = create_test_logger();
+        let test_portfolio = get_test_portfolio_with_three_assets();
+
+        let sampled = sampled_outcomes(&test_portfolio, 10000, 42);
+        assert_eq!(10000, sampled.len());
+        assert_eq!(
+            sampled[..10]
+                .iter()
+                .map(|o| o.weighted_return)
+                .collect_vec(),
+            sampled_outcomes(&test_portfolio, 10, 42)
+                .iter()
+                .map(|o| o.weighted_return)
+                .collect_vec()
+        );
+
+        let statistics = sampled_statistics(&sampled, 42);
+        assert!(statistics.expected_return.lower < 0.285);
+        assert!(0.285 < statistics.expected_return.upper);
+        assert!(statistics.probability_of_loss.lower < 0.22);
+        assert!(0.22 < statistics.probability_of_loss.upper);
+        assert_close!(
+            statistics.probability_of_loss.value,
+            cumulative_probability_of_loss(&sampled, &logger),
+            company::TOLERANCE
+        );
+        assert!(statistics.expected_tail_return.value <= statistics.tail_quantile.value);
+    }
+
+    #[test]
+    fn test_return_distribution() {
+        let logger = create_test_logger();
+        let test_portfolio = get_test_portfolio_with_three_assets();
+        let all_outcomes = all_outcomes(&test_portfolio).unwrap();
+
+        let outcomes = return_distribution(&all_outcomes, None).outcomes.unwrap();
+        assert!(outcomes.len() <= all_outcomes.len());
+        assert!(outcomes
+            .windows(2)
+            .all(|w| w[0].portfolio_return < w[1].portfolio_return));
+        assert_close!(
+            1.0,
+            outcomes.iter().map(|o| o.probability).sum::<f64>(),
+            company::TOLERANCE
+        );
+        assert_close!(
+            cumulative_probability_of_loss(&all_outcomes, &logger),
+            outcomes
+                .iter()
+                .filter(|o| o.portfolio_return < 0.0)
+                .map(|o| o.probability)
+                .sum::<f64>(),
+            company::TOLERANCE
+        );
+
+        let histogram = return_distribution(&all_outcomes, Some(4))
+            .histogram
+            .unwrap();
+        assert_eq!(4, histogram.len());
+        assert_close!(
+            outcomes[0].portfolio_return,
+            histogram[0].lower,
+            company::TOLERANCE
+        );
+        assert_close!(
+            outcomes.last().unwrap().portfolio_return,
+            histogram[3].upper,
+            company::TOLERANCE
+        );
+        assert_close!(
+            1.0,
+            histogram.iter().map(|b| b.probability).sum::<f64>(),
+            company::TOLERANCE
+        );
+    }
+
+    #[test]
+    fn test_analytical_moments() {
+        let test_portfolio = get_test_portfolio_with_three_assets();
+        let moments = analytical_moments(&test_portfolio);
+
+        assert_close!(0.285, moments.mean, company::TOLERANCE);
+        assert_close!(0.169525, moments.variance, company::TOLERANCE);
+    }
+
+    #[test]
+    fn test_analytical_moments_match_enumerated_moments() {
+        let logger = create_test_logger();
+        let test_portfolio = get_test_portfolio_with_three_assets();
+        let all_outcomes = all_outcomes(&test_portfolio).unwrap();
+
+        let enumerated = enumerated_moments(&all_outcomes);
+        assert_close!(0.285, enumerated.mean, company::TOLERANCE);
+        assert_close!(0.169525, enumerated.variance, company::TOLERANCE);
+        assert!(cross_check_moments(&test_portfolio, &all_outcomes, &logger));
+    }
+
+    /// Returns the portfolio with three assets, where A and B are correlated by macro scenarios:
+    /// both win in an expansion, while A loses and B mostly loses in a recession.
+    fn get_test_portfolio_with_macro_scenarios() -> Portfolio {
+        let mut portfolio = get_test_portfolio_with_three_assets();
+        let a = &mut portfolio.companies[0].company.scenarios;
+        a[0].macro_scenario = Some("expansion".to_string());
+        a[1].macro_scenario = Some("recession".to_string());
+        let b = &mut portfolio.companies[1].company.scenarios;
+        b.insert(
+            1,
+            Scenario {
+                thesis: "Wins despite the recession".to_string(),
+                probability: 0.1,
+                macro_scenario: Some("recession".to_string()),
+                ..b[0].clone()
+            },
+        );
+        b[0].probability = 0.5;
+        b[0].macro_scenario = Some("expansion".to_string());
+        b[2].macro_scenario = Some("recession".to_string());
+        portfolio
+    }
+
+    #[test]
+    fn test_outcomes_with_macro_scenarios() {
+        let logger = create_test_logger();
+        let independent = get_test_portfolio_with_three_assets();
+        let portfolio = get_test_portfolio_with_macro_scenarios();
+
+        // Expansion combines one scenario of A and B, recession one of A and two of B
+        let outcomes = all_outcomes(&portfolio).unwrap();
+        assert_eq!(3 + 2 * 3, n_outcomes(&portfolio));
+        assert_eq!(n_outcomes(&portfolio), outcomes.len());
+        assert_close!(
+            1.0,
+            outcomes.iter().map(|o| o.probability).sum::<f64>(),
+            company::TOLERANCE
+        );
+        assert_eq!(vec![1, 1, 0], outcome_scenario_indices(&portfolio, 3));
+        let both_lose: f64 = outcomes
+            .iter()
+            .filter(|o| o.company_returns["A"] < 0.0 && o.company_returns["B"] < 0.0)
+            .map(|o| o.probability)
+            .sum();
+        assert_close!(0.4, both_lose, company::TOLERANCE);
+
+        // Marginals are unchanged, while the covariance of A and B of 0.8 adds to the variance
+        let moments = analytical_moments(&portfolio);
+        assert_close!(0.285, moments.mean, company::TOLERANCE);
+        assert_close!(
+            0.169525 + 2.0 * 0.2 * 0.3 * 0.8,
+            moments.variance,
+            company::TOLERANCE
+        );
+        assert!(cross_check_moments(&portfolio, &outcomes, &logger));
+
+        let worst_case = worst_case_outcome(&portfolio, &logger);
+        let independent_worst_case = worst_case_outcome(&independent, &logger);
+        assert_close!(
+            independent_worst_case.portfolio_return,
+            worst_case.portfolio_return,
+            company::TOLERANCE
+        );
+        assert_close!(
+            2.0 * independent_worst_case.probability,
+            worst_case.probability,
+            company::TOLERANCE
+        );
+
+        let sampled = sampled_outcomes(&portfolio, 10000, 42);
+        let sampled_both_lose = sampled
+            .iter()
+            .filter(|o| o.company_returns["A"] < 0.0 && o.company_returns["B"] < 0.0)
+            .count() as f64
+            / sampled.len() as f64;
+        assert_close!(0.4, sampled_both_lose, 0.02);
+    }
+
+    #[test]
+    fn test_outcomes_with_declared_macro_scenarios() {
+        // Scenarios of A in the expansion have the probability 0.7, while B places 0.5 in it
+        let mut portfolio = get_test_portfolio_with_macro_scenarios();
+        let a = &mut portfolio.companies[0].company.scenarios;
+        a[0].probability = 0.7;
+        a[1].probability = 0.3;
+        let probability_of_a_winning = |portfolio: &Portfolio| -> f64 {
+            all_outcomes(portfolio)
+                .unwrap()
+                .iter()
+                .filter(|o| o.company_returns["A"] > 0.0)
+                .map(|o| o.probability)
+                .sum()
+        };
+
+        // Without declared macro scenarios, their probabilities are averaged over the companies
+        assert_close!(
+            0.6,
+            probability_of_a_winning(&portfolio),
+            company::TOLERANCE
+        );
+
+        portfolio.macro_scenarios = Some(vec![
+            MacroScenario {
+                name: "expansion".to_string(),
+                probability: 0.5,
+            },
+            MacroScenario {
+                name: "recession".to_string(),
+                probability: 0.5,
+            },
+        ]);
+        assert_close!(
+            0.5,
+            probability_of_a_winning(&portfolio),
+            company::TOLERANCE
+        );
+    }
+
+    #[test]
+    fn test_diversification() {
+        let mut test_portfolio = get_test_portfolio_with_three_assets();
+        let metrics = diversification(&test_portfolio);
+
+        assert_close!(
+            1.0 / 0.38,
+            metrics.effective_number_of_positions,
+            company::TOLERANCE
+        );
+        assert_close!(0.5, metrics.max_weight, company::TOLERANCE);
+        assert_close!(1.0, metrics.gross_exposure, company::TOLERANCE);
+        assert_close!(1.0, metrics.net_exposure, company::TOLERANCE);
+
+        // Short positions contribute to the gross exposure, but reduce the net exposure
+        test_portfolio.companies[2].fraction = -0.5;
+        let metrics = diversification(&test_portfolio);
+
+        assert_close!(
+            1.0 / 0.38,
+            metrics.effective_number_of_positions,
+            company::TOLERANCE
+        );
+        assert_close!(0.5, metrics.max_weight, company::TOLERANCE);
+        assert_close!(1.0, metrics.gross_exposure, company::TOLERANCE);
+        assert_close!(0.0, metrics.net_exposure, company::TOLERANCE);
+    }
+
+    #[test]
+    fn test_expected_log_growth() {
+        let mut test_portfolio: Portfolio = Portfolio {
+            companies: vec![PortfolioCompany {
+                company: Company {
+                    name: "Fair coin flip".to_string(),
+                    ticker: "A".to_string(),
+                    description: "Something we should never invest into".to_string(),
+                    market_cap: 1e6,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
+                    scenarios: vec![
+                        Scenario {
+                            thesis: "Head".to_string(),
+                            intrinsic_value: 2e6,
+                            probability: 0.5,
+                            ..Default::default()
+                        },
+                        Scenario {
+                            thesis: "Tail".to_string(),
+                            intrinsic_value: 0.0,
+                            probability: 0.5,
+                            ..Default::default()
+                        },
+                    ],
+                },
+                fraction: 0.5,
+            }],
+            ..Default::default()
+        };
+
+        let outcomes = all_outcomes(&test_portfolio).unwrap();
+        assert_close!(
+            0.5 * 1.5_f64.ln() + 0.5 * 0.5_f64.ln(),
+            expected_log_growth(&outcomes),
+            company::TOLERANCE
+        );
+
+        // Betting everything on a fair coin flip eventually wipes out all capital
+        test_portfolio.companies[0].fraction = 1.0;
+        let outcomes = all_outcomes(&test_portfolio).unwrap();
+        assert_eq!(f64::NEG_INFINITY, expected_log_growth(&outcomes));
+    }
+
+    #[test]
+    fn test_growth_concentration() {
+        let test_portfolio = get_test_portfolio_with_three_assets();
+
+        // Head of B with 30% allocation contributes the most among the scenarios with upside
+        let scenario = dominant_scenario(&test_portfolio).unwrap();
+        let total =
+            0.5 * 1.2_f64.ln() + 0.6 * 1.3_f64.ln() + 0.3 * 1.5_f64.ln() + 0.3 * 1.25_f64.ln();
+        assert_close!(
+            0.6 * 1.3_f64.ln() / total,
+            scenario.share,
+            company::TOLERANCE
+        );
+        assert_eq!(vec!["B: Head".to_string()], scenario.theses);
+
+        // The outcome where everything doubles contributes the most
+        let outcomes = all_outcomes(&test_portfolio).unwrap();
+        let outcome = dominant_outcome(&test_portfolio, &outcomes).unwrap();
+        assert_eq!(
+            vec![
+                "A: Head".to_string(),
+                "B: Head".to_string(),
+                "C: Double".to_string()
+            ],
+            outcome.theses
+        );
+        assert_close!(0.2304299, outcome.share, 1e-6);
+    }
+
+    #[test]
+    fn test_inflation_adjusted() {
+        let adjusted = inflation_adjusted(0.05, 0.26, Some(0.2)).unwrap();
+        assert_close!(0.2, adjusted.expected_real_return, company::TOLERANCE);
+        assert_close!(
+            0.2 - 1.05_f64.ln(),
+            adjusted.expected_real_log_growth.unwrap(),
+            company::TOLERANCE
+        );
+        assert!(inflation_adjusted(0.05, 0.26, None)
+            .unwrap()
+            .expected_real_log_growth
+            .is_none());
+        assert_eq!(
+            "invalid-expected-inflation",
+            inflation_adjusted(-1.0, 0.26, None).unwrap_err().code
+        );
+    }
+
+    #[test]
+    fn test_compound_growth() {
+        let growth = compound_growth(2.0_f64.ln() / 4.0, Some(2.0)).unwrap();
+        assert_close!(
+            2.0_f64.powf(0.25) - 1.0,
+            growth.geometric_return,
+            company::TOLERANCE
+        );
+        assert_close!(4.0, growth.doubling_time.unwrap(), company::TOLERANCE);
+        assert_close!(
+            2.0_f64.powf(0.125) - 1.0,
+            growth.annualized_geometric_return.unwrap(),
+            company::TOLERANCE
+        );
+        assert_close!(
+            8.0,
+            growth.doubling_time_in_years.unwrap(),
+            company::TOLERANCE
+        );
+
+        let ruin = compound_growth(f64::NEG_INFINITY, None).unwrap();
+        assert_eq!(-1.0, ruin.geometric_return);
+        assert!(ruin.doubling_time.is_none());
+        assert!(ruin.annualized_log_growth_rate.is_none());
+        assert_eq!(
+            "invalid-horizon-in-years",
+            compound_growth(0.1, Some(0.0)).unwrap_err().code
+        );
+    }
+
+    #[test]
+    fn test_event_outcome() {
+        let portfolio = get_test_portfolio_with_three_assets();
+        let event = |scenarios: &[(&str, &[&str])]| PortfolioEvent {
+            name: "Event".to_string(),
+            scenarios: scenarios
+                .iter()
+                .map(|(t, theses)| {
+                    (
+                        t.to_string(),
+                        theses.iter().map(|s| s.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        };
+
+        let recession = event(&[("A", &["Tail"]), ("B", &["Tail"]), ("C", &["Same as now"])]);
+        let outcome = event_outcome(&portfolio, &recession).unwrap();
+        assert_close!(0.08, outcome.probability, company::TOLERANCE);
+        assert_close!(-0.5, outcome.expected_portfolio_return, company::TOLERANCE);
+
+        // Companies outside the event contribute their expected return
+        let outcome = event_outcome(&portfolio, &event(&[("A", &["Head"])])).unwrap();
+        assert_close!(0.5, outcome.probability, company::TOLERANCE);
+        assert_close!(
+            0.2 + 0.3 * 0.2 + 0.5 * 0.45,
+            outcome.expected_portfolio_return,
+            company::TOLERANCE
+        );
+
+        let companies = portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.company.clone())
+            .collect::<Vec<Company>>();
+        let codes = event(&[("A", &["Crash"]), ("D", &["Tail"])])
+            .validate_against(&companies)
+            .into_iter()
+            .map(|v| match v {
+                ValidationResult::PROBLEM(p) => p.code,
+                ValidationResult::OK => String::new(),
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            vec!["unknown-tickers-in-event", "unknown-theses-in-event"],
+            codes
+        );
+    }
+
+    #[test]
+    fn test_tag_conditional_outcome() {
+        let mut portfolio = get_test_portfolio_with_three_assets();
+        for pc in portfolio.companies.iter_mut().take(2) {
+            pc.company.scenarios[1].tags = Some(vec!["recession".to_string()]);
+        }
+        let options = AnalysisOptions {
+            distribution: Some(true),
+            ..AnalysisOptions::default()
+        };
+
+        // Both A and B lose everything, while C is unaffected
+        let outcome = tag_conditional_outcome(&portfolio, "recession", &options).unwrap();
+        assert_close!(0.5 * 0.4, outcome.probability, company::TOLERANCE);
+        assert_close!(
+            -0.2 - 0.3 + 0.5 * 0.45,
+            outcome.expected_portfolio_return,
+            company::TOLERANCE
+        );
+        let distribution = outcome.distribution.unwrap().outcomes.unwrap();
+        assert_eq!(
+            portfolio.companies[2].company.scenarios.len(),
+            distribution.len()
+        );
+        assert_close!(
+            1.0,
+            distribution.iter().map(|o| o.probability).sum::<f64>(),
+            company::TOLERANCE
+        );
+
+        let summary = AnalysisOptions {
+            detail_level: Some(DetailLevel::Summary),
+            ..AnalysisOptions::default()
+        };
+        let outcome = tag_conditional_outcome(&portfolio, "recession", &summary).unwrap();
+        assert!(outcome.distribution.is_none());
+
+        let error = tag_conditional_outcome(&portfolio, "pandemic", &options).unwrap_err();
+        assert_eq!("unknown-tag-in-analysis", error.code);
+    }
+
+    #[test]
+    fn test_event_and_tag_with_macro_scenarios() {
+        // A and B both lose only in the recession
+        let company = |ticker: &str| PortfolioCompany {
+            company: Company {
+                name: ticker.to_string(),
+                ticker: ticker.to_string(),
+                description: "Cyclical".to_string(),
+                market_cap: 1e6,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
+                scenarios: vec![
+                    Scenario {
+                        thesis: "Grows".to_string(),
+                        intrinsic_value: 1.5e6,
+                        probability: 0.7,
+                        macro_scenario: Some("expansion".to_string()),
+                        ..Default::default()
+                    },
+                    Scenario {
+                        thesis: "Loses".to_string(),
+                        intrinsic_value: 0.5e6,
+                        probability: 0.3,
+                        macro_scenario: Some("recession".to_string()),
+                        ..Default::default()
+                    },
+                ],
+            },
+            fraction: 0.5,
+        };
+        let mut portfolio = Portfolio {
+            companies: vec![company("A"), company("B")],
+            macro_scenarios: Some(vec![
+                MacroScenario {
+                    name: "expansion".to_string(),
+                    probability: 0.7,
+                },
+                MacroScenario {
+                    name: "recession".to_string(),
+                    probability: 0.3,
+                },
+            ]),
+        };
+
+        let both_lose = PortfolioEvent {
+            name: "Both lose".to_string(),
+            scenarios: HashMap::from([
+                ("A".to_string(), vec!["Loses".to_string()]),
+                ("B".to_string(), vec!["Loses".to_string()]),
+            ]),
+        };
+        let outcome = event_outcome(&portfolio, &both_lose).unwrap();
+        assert_close!(0.3, outcome.probability, company::TOLERANCE);
+        assert_close!(-0.5, outcome.expected_portfolio_return, company::TOLERANCE);
+
+        let a_loses_b_grows = PortfolioEvent {
+            name: "Only A loses".to_string(),
+            scenarios: HashMap::from([
+                ("A".to_string(), vec!["Loses".to_string()]),
+                ("B".to_string(), vec!["Grows".to_string()]),
+            ]),
+        };
+        let error = event_outcome(&portfolio, &a_loses_b_grows).unwrap_err();
+        assert_eq!("event-has-zero-probability", error.code);
+
+        // Conditioning on the recession of A puts B into the recession as well
+        portfolio.companies[0].company.scenarios[1].tags = Some(vec!["recession".to_string()]);
+        let (conditioned, probability) =
+            tag_conditional_portfolio(&portfolio, "recession").unwrap();
+        assert_close!(0.3, probability, company::TOLERANCE);
+        let macro_scenarios = conditioned.macro_scenarios.clone().unwrap();
+        assert_eq!("recession", macro_scenarios[1].name);
+        assert_close!(1.0, macro_scenarios[1].probability, company::TOLERANCE);
+
+        let outcome =
+            tag_conditional_outcome(&portfolio, "recession", &AnalysisOptions::default()).unwrap();
+        assert_close!(0.3, outcome.probability, company::TOLERANCE);
+        assert_close!(-0.5, outcome.expected_portfolio_return, company::TOLERANCE);
+    }
 }
@@ -0,0 +1,375 @@
+use crate::allocate;
+use crate::kelly_allocation::FinancingRates;
+use crate::model::backtest::{BacktestCheckpoint, BacktestRow, NamedSnapshot};
+use crate::model::errors::Error;
+use crate::model::responses::{AllocationResponse, TickerAndFraction};
+use crate::validation::result::ValidationResult;
+use itertools::Itertools;
+use slog::{info, Logger};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::thread::available_parallelism;
+
+/// Default number of periods processed between two checkpoints, e.g. a year of monthly snapshots
+pub const DEFAULT_CHUNK_SIZE: usize = 12;
+
+/// Allocation of a period of the backtest and the return it realized until the next snapshot.
+struct PeriodResult {
+    allocations: Vec<TickerAndFraction>,
+    expected_return: f64,
+    realized_return: f64,
+}
+
+/// Runs the backtest of the snapshots, holding the allocation to the candidates of each snapshot
+/// until the next snapshot, and appends one row per period to the output as NDJSON. The periods are
+/// processed in chunks of the given size, allocating the periods of a chunk concurrently, and the
+/// progress is checkpointed after each chunk. If the checkpoint exists, the backtest resumes after
+/// the last completed period, truncating the rows that an interrupted chunk wrote after the
+/// checkpoint. Returns the checkpoint after the last period.
+pub fn run_backtest(
+    snapshots: &[NamedSnapshot],
+    chunk_size: usize,
+    checkpoint_path: &Path,
+    output_path: &Path,
+    logger: &Logger,
+) -> Result<BacktestCheckpoint, Error> {
+    if chunk_size == 0 {
+        return Err(Error {
+            code: "invalid-chunk-size".to_string(),
+            message: "Chunk size of the backtest must be at least 1.".to_string(),
+        });
+    }
+
+    let n_periods = snapshots.len().saturating_sub(1);
+    let mut checkpoint = match load_checkpoint(checkpoint_path)? {
+        Some(checkpoint) => {
+            let last_period = checkpoint
+                .completed_periods
+                .checked_sub(1)
+                .and_then(|i| snapshots.get(i))
+                .map(|s| s.name.clone());
+            if checkpoint.completed_periods > n_periods || checkpoint.last_period != last_period {
+                return Err(Error {
+                    code: "backtest-checkpoint-mismatch".to_string(),
+                    message: format!(
+                        "Checkpoint {} doesn't match the snapshots, which changed since it was \
+                        written. Remove the checkpoint and the output to start over.",
+                        checkpoint_path.display()
+                    ),
+                });
+            }
+            info!(
+                logger,
+                "Resuming the backtest after {} of {n_periods} periods.",
+                checkpoint.completed_periods
+            );
+            checkpoint
+        }
+        None => BacktestCheckpoint {
+            completed_periods: 0,
+            last_period: None,
+            cumulative_wealth: 1.0,
+            output_length: 0,
+        },
+    };
+
+    let mut output = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_path)
+        .map_err(|e| io_error(output_path, e))?;
+    let output_length = output
+        .metadata()
+        .map_err(|e| io_error(output_path, e))?
+        .len();
+    if output_length < checkpoint.output_length {
+        return Err(Error {
+            code: "backtest-checkpoint-mismatch".to_string(),
+            message: format!(
+                "Output {} is shorter than recorded in the checkpoint {}, so rows of completed \
+                periods are missing. Remove the checkpoint and the output to start over.",
+                output_path.display(),
+                checkpoint_path.display()
+            ),
+        });
+    }
+    output
+        .set_len(checkpoint.output_length)
+        .map_err(|e| io_error(output_path, e))?;
+
+    let periods = (checkpoint.completed_periods..n_periods).collect_vec();
+    for chunk in periods.chunks(chunk_size) {
+        let results = backtest_chunk(snapshots, chunk, logger);
+        for (&i, result) in chunk.iter().zip(results) {
+            let row = match result {
+                Ok(r) => {
+                    checkpoint.cumulative_wealth *= 1.0 + r.realized_return;
+                    BacktestRow {
+                        period: snapshots[i].name.clone(),
+                        allocations: r.allocations,
+                        expected_return: Some(r.expected_return),
+                        realized_return: Some(r.realized_return),
+                        cumulative_wealth: checkpoint.cumulative_wealth,
+                        error: None,
+                    }
+                }
+                Err(e) => BacktestRow {
+                    period: snapshots[i].name.clone(),
+                    allocations: vec![],
+                    expected_return: None,
+                    realized_return: None,
+                    cumulative_wealth: checkpoint.cumulative_wealth,
+                    error: Some(e),
+                },
+            };
+            let line = serde_json::to_string(&row).map_err(|e| Error {
+                code: "failed-to-serialize-result".to_string(),
+                message: format!("Failed to serialize the row of period {}: {e}", row.period),
+            })?;
+            writeln!(output, "{line}").map_err(|e| io_error(output_path, e))?;
+        }
+        output.sync_data().map_err(|e| io_error(output_path, e))?;
+
+        let last = *chunk.last().expect("Chunks are never empty.");
+        checkpoint.completed_periods = last + 1;
+        checkpoint.last_period = Some(snapshots[last].name.clone());
+        checkpoint.output_length = output
+            .metadata()
+            .map_err(|e| io_error(output_path, e))?
+            .len();
+        save_checkpoint(checkpoint_path, &checkpoint)?;
+        info!(
+            logger,
+            "Completed {} of {n_periods} periods of the backtest.", checkpoint.completed_periods
+        );
+    }
+
+    Ok(checkpoint)
+}
+
+/// Allocates the periods with the given indices concurrently, on as many threads as there are
+/// CPUs, and returns their results in the order of the indices.
+fn backtest_chunk(
+    snapshots: &[NamedSnapshot],
+    period_indices: &[usize],
+    logger: &Logger,
+) -> Vec<Result<PeriodResult, Error>> {
+    let n_threads = available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = period_indices.len().div_ceil(n_threads);
+
+    std::thread::scope(|scope| {
+        let handles = period_indices
+            .chunks(chunk_size)
+            .map(|indices| {
+                scope.spawn(move || {
+                    indices
+                        .iter()
+                        .map(|&i| backtest_period(&snapshots[i], &snapshots[i + 1], logger))
+                        .collect::<Vec<Result<PeriodResult, Error>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Backtest of a chunk of periods panicked."))
+            .collect()
+    })
+}
+
+/// Allocates to the candidates of the snapshot and realizes the return of the allocation from the
+/// market caps of the allocated candidates in the next snapshot. The cash earns the risk-free rate
+/// of the snapshot, or costs its margin interest rate if the allocation is levered.
+fn backtest_period(
+    snapshot: &NamedSnapshot,
+    next: &NamedSnapshot,
+    logger: &Logger,
+) -> Result<PeriodResult, Error> {
+    info!(logger, "Backtesting period {}.", snapshot.name);
+    let AllocationResponse {
+        result,
+        validation_problems,
+        error,
+        ..
+    } = allocate(snapshot.input.clone(), logger);
+    let result = result.ok_or_else(|| {
+        error.unwrap_or_else(|| Error {
+            code: "allocation-failed".to_string(),
+            message: format!(
+                "Allocation of period {} failed: {}",
+                snapshot.name,
+                validation_problems
+                    .iter()
+                    .flatten()
+                    .filter_map(|v| match v {
+                        ValidationResult::PROBLEM(p) => Some(p.message.as_str()),
+                        _ => None,
+                    })
+                    .join(" ")
+            ),
+        })
+    })?;
+
+    let market_cap = |s: &NamedSnapshot, ticker: &str| {
+        s.input
+            .candidates
+            .iter()
+            .find(|c| c.ticker == ticker)
+            .map(|c| c.market_cap)
+    };
+    let mut realized_return = 0.0;
+    let mut missing_tickers: Vec<&str> = vec![];
+    for allocation in result.allocations.iter().filter(|a| a.fraction != 0.0) {
+        match (
+            market_cap(snapshot, &allocation.ticker),
+            market_cap(next, &allocation.ticker),
+        ) {
+            (Some(start), Some(end)) => {
+                realized_return += allocation.fraction * (end / start - 1.0);
+            }
+            _ => missing_tickers.push(&allocation.ticker),
+        }
+    }
+    if !missing_tickers.is_empty() {
+        return Err(Error {
+            code: "missing-realized-return".to_string(),
+            message: format!(
+                "Candidates {} of period {} are missing from the next snapshot {}, so their \
+                realized returns are unknown.",
+                missing_tickers.join(", "),
+                snapshot.name,
+                next.name
+            ),
+        });
+    }
+    let financing_rates = FinancingRates {
+        risk_free_rate: snapshot.input.risk_free_rate.unwrap_or(0.0),
+        margin_interest_rate: snapshot.input.margin_interest_rate,
+    };
+    realized_return += financing_rates.cash_return(result.cash);
+
+    Ok(PeriodResult {
+        allocations: result.allocations,
+        expected_return: result.analysis.expected_return,
+        realized_return,
+    })
+}
+
+/// Returns the checkpoint persisted at the path, or None if there's none yet.
+fn load_checkpoint(path: &Path) -> Result<Option<BacktestCheckpoint>, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| Error {
+            code: "invalid-backtest-checkpoint".to_string(),
+            message: format!("Failed to deserialize checkpoint {}: {e}", path.display()),
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+/// Writes the checkpoint into a temporary file first, such that an interruption while writing
+/// doesn't corrupt the previous checkpoint.
+fn save_checkpoint(path: &Path, checkpoint: &BacktestCheckpoint) -> Result<(), Error> {
+    let content = serde_json::to_string(checkpoint).map_err(|e| Error {
+        code: "failed-to-serialize-result".to_string(),
+        message: format!("Failed to serialize the checkpoint: {e}"),
+    })?;
+    let temporary_path = path.with_extension("tmp");
+    std::fs::write(&temporary_path, content).map_err(|e| io_error(&temporary_path, e))?;
+    std::fs::rename(&temporary_path, path).map_err(|e| io_error(path, e))
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: "backtest-io-error".to_string(),
+        message: format!("Failed to access {}: {e}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::create_test_logger;
+    use crate::utils::assert_close;
+
+    /// Snapshot of a coin that doubles or halves, with the given market cap.
+    fn snapshot(name: &str, market_cap: f64) -> NamedSnapshot {
+        NamedSnapshot {
+            name: name.to_string(),
+            input: serde_yaml::from_str(&format!(
+                "
+                long_only: true
+                candidates:
+                  - name: Coin
+                    ticker: C
+                    description: Doubles or halves
+                    market_cap: {market_cap}
+                    scenarios:
+                      - thesis: Doubles
+                        intrinsic_value: {}
+                        probability: 0.5
+                      - thesis: Halves
+                        intrinsic_value: {}
+                        probability: 0.5
+                ",
+                2.0 * market_cap,
+                0.5 * market_cap
+            ))
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_resume_backtest() {
+        let logger = create_test_logger();
+        let directory =
+            std::env::temp_dir().join(format!("charlie_test_backtest_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let checkpoint_path = directory.join("checkpoint.json");
+        let output_path = directory.join("backtest.ndjson");
+        let snapshots = [
+            snapshot("2024-01", 100.0),
+            snapshot("2024-02", 200.0),
+            snapshot("2024-03", 100.0),
+        ];
+
+        // Interrupted after the first chunk of one period, while writing the row of the second
+        let checkpoint =
+            run_backtest(&snapshots[..2], 1, &checkpoint_path, &output_path, &logger).unwrap();
+        assert_eq!(1, checkpoint.completed_periods);
+        assert_close!(1.5, checkpoint.cumulative_wealth, 1e-4);
+        let mut output = OpenOptions::new().append(true).open(&output_path).unwrap();
+        write!(output, "{{\"period\":\"2024-02\"").unwrap();
+
+        let checkpoint =
+            run_backtest(&snapshots, 1, &checkpoint_path, &output_path, &logger).unwrap();
+        assert_eq!(2, checkpoint.completed_periods);
+        assert_eq!(Some("2024-02".to_string()), checkpoint.last_period);
+        let rows = std::fs::read_to_string(&output_path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str::<BacktestRow>(l).unwrap())
+            .collect_vec();
+        assert_eq!(
+            vec!["2024-01", "2024-02"],
+            rows.iter().map(|r| r.period.as_str()).collect_vec()
+        );
+        assert_close!(0.5, rows[0].realized_return.unwrap(), 1e-4);
+        assert_close!(-0.25, rows[1].realized_return.unwrap(), 1e-4);
+        assert_close!(1.125, rows[1].cumulative_wealth, 1e-4);
+
+        // Changed snapshots don't match the checkpoint
+        let e = run_backtest(
+            &[snapshot("2023-12", 100.0), snapshot("2024-01", 100.0)],
+            1,
+            &checkpoint_path,
+            &output_path,
+            &logger,
+        )
+        .unwrap_err();
+        assert_eq!("backtest-checkpoint-mismatch", e.code);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use crate::analyze;
+use crate::model::analysis_options::AnalysisOptions;
+use crate::model::batch_analysis::{BatchAnalysisRow, NamedPortfolio};
+use crate::model::errors::Error;
+use crate::model::responses::AnalysisResponse;
+use slog::{info, Logger};
+use std::thread::available_parallelism;
+
+/// Analyzes the portfolios concurrently, on as many threads as there are CPUs, and returns the key
+/// statistics of each portfolio in the order of the input.
+pub fn analyze_batch(
+    portfolios: Vec<NamedPortfolio>,
+    options: &AnalysisOptions,
+    logger: &Logger,
+) -> Vec<BatchAnalysisRow> {
+    if portfolios.is_empty() {
+        return vec![];
+    }
+
+    let n_threads = available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = portfolios.len().div_ceil(n_threads);
+    info!(
+        logger,
+        "Analyzing {} portfolios on up to {n_threads} threads.",
+        portfolios.len()
+    );
+
+    std::thread::scope(|scope| {
+        let handles = portfolios
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|p| batch_analysis_row(p, options, logger))
+                        .collect::<Vec<BatchAnalysisRow>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|h| {
+                h.join()
+                    .expect("Analysis of a batch of portfolios panicked.")
+            })
+            .collect()
+    })
+}
+
+/// Analyzes a single portfolio of the batch and summarizes the analysis into a row.
+fn batch_analysis_row(
+    named: &NamedPortfolio,
+    options: &AnalysisOptions,
+    logger: &Logger,
+) -> BatchAnalysisRow {
+    info!(logger, "Analyzing portfolio {}.", named.name);
+    let portfolio = &named.portfolio;
+    let AnalysisResponse { result, error } = analyze(
+        portfolio.portfolio.clone(),
+        portfolio.events.as_deref().unwrap_or_default(),
+        options,
+        logger,
+    );
+
+    match result {
+        Some(r) => BatchAnalysisRow {
+            name: named.name.clone(),
+            expected_return: Some(r.expected_return),
+            volatility: Some(r.variance_of_return.sqrt()),
+            cumulative_probability_of_loss: r.cumulative_probability_of_loss,
+            worst_case_return: Some(r.worst_case_outcome.portfolio_return),
+            effective_number_of_positions: Some(r.effective_number_of_positions),
+            gross_exposure: Some(r.gross_exposure),
+            error: None,
+        },
+        None => failed_batch_analysis_row(
+            &named.name,
+            error.unwrap_or_else(|| Error {
+                code: "analysis-failed".to_string(),
+                message: "The analysis did not return a result.".to_string(),
+            }),
+        ),
+    }
+}
+
+/// Row of a portfolio that couldn't be analyzed, e.g. because its file couldn't be read.
+pub fn failed_batch_analysis_row(name: &str, error: Error) -> BatchAnalysisRow {
+    BatchAnalysisRow {
+        name: name.to_string(),
+        expected_return: None,
+        volatility: None,
+        cumulative_probability_of_loss: None,
+        worst_case_return: None,
+        effective_number_of_positions: None,
+        gross_exposure: None,
+        error: Some(error),
+    }
+}
+
+/// Formats the rows as a plain text table with one row per portfolio, for comparing the portfolios
+/// at a glance. Returns and probabilities are in percent, and missing statistics are left empty.
+pub fn summary_table(rows: &[BatchAnalysisRow]) -> String {
+    let percent =
+        |value: Option<f64>| value.map_or(String::new(), |v| format!("{:.1}%", 100.0 * v));
+    let number = |value: Option<f64>| value.map_or(String::new(), |v| format!("{v:.2}"));
+
+    let header = [
+        "Portfolio",
+        "Expected return",
+        "Volatility",
+        "P(loss)",
+        "Worst case",
+        "Positions",
+        "Gross exposure",
+        "Error",
+    ]
+    .map(String::from)
+    .to_vec();
+    let cells = rows
+        .iter()
+        .map(|r| {
+            vec![
+                r.name.clone(),
+                percent(r.expected_return),
+                percent(r.volatility),
+                percent(r.cumulative_probability_of_loss),
+                percent(r.worst_case_return),
+                number(r.effective_number_of_positions),
+                percent(r.gross_exposure),
+                r.error.as_ref().map_or(String::new(), |e| e.code.clone()),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    let widths = (0..header.len())
+        .map(|i| {
+            std::iter::once(&header)
+                .chain(cells.iter())
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<usize>>();
+    let format_row = |row: &Vec<String>| {
+        row.iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, width))| match i {
+                // Names and errors are aligned to the left, numbers to the right
+                0 | 7 => format!("{cell:<width$}"),
+                _ => format!("{cell:>width$}"),
+            })
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    std::iter::once(&header)
+        .chain(cells.iter())
+        .map(format_row)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::create_test_logger;
+
+    fn named_portfolio(name: &str, fraction: f64) -> NamedPortfolio {
+        serde_yaml::from_str(&format!(
+            "
+            name: {name}
+            companies:
+              - company:
+                  name: A
+                  ticker: A
+                  description: Business A
+                  market_cap: 100.0
+                  scenarios:
+                    - thesis: Down
+                      intrinsic_value: 50.0
+                      probability: 0.5
+                    - thesis: Up
+                      intrinsic_value: 200.0
+                      probability: 0.5
+                fraction: {fraction}
+            "
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_analyze_batch_keeps_order_and_errors() {
+        let logger = create_test_logger();
+        let mut portfolios = (1..=5)
+            .map(|i| named_portfolio(&format!("p{i}"), 0.2 * i as f64))
+            .collect::<Vec<NamedPortfolio>>();
+        portfolios[2].portfolio.events =
+            Some(serde_yaml::from_str("[{name: Unknown, scenarios: {X: [Down]}}]").unwrap());
+
+        let rows = analyze_batch(portfolios, &AnalysisOptions::default(), &logger);
+
+        assert_eq!(
+            vec!["p1", "p2", "p3", "p4", "p5"],
+            rows.iter().map(|r| r.name.as_str()).collect::<Vec<&str>>()
+        );
+        assert!((rows[4].expected_return.unwrap() - 0.25).abs() < 1e-9);
+        assert!((rows[0].worst_case_return.unwrap() + 0.1).abs() < 1e-9);
+        assert_eq!(Some(0.5), rows[1].cumulative_probability_of_loss);
+        assert!(rows[2].expected_return.is_none());
+        assert!(rows[2].error.is_some());
+    }
+
+    #[test]
+    fn test_summary_table() {
+        let rows = vec![
+            BatchAnalysisRow {
+                name: "aggressive".to_string(),
+                expected_return: Some(0.25),
+                volatility: Some(0.75),
+                cumulative_probability_of_loss: Some(0.5),
+                worst_case_return: Some(-0.5),
+                effective_number_of_positions: Some(1.0),
+                gross_exposure: Some(1.0),
+                error: None,
+            },
+            failed_batch_analysis_row(
+                "broken",
+                Error {
+                    code: "invalid-portfolio".to_string(),
+                    message: "".to_string(),
+                },
+            ),
+        ];
+
+        let table = summary_table(&rows);
+        let lines = table.lines().collect::<Vec<&str>>();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("Portfolio   Expected return"));
+        assert!(lines[1].starts_with("aggressive            25.0%"));
+        assert!(lines[1].contains("-50.0%"));
+        assert!(lines[2].starts_with("broken"));
+        assert!(lines[2].ends_with("invalid-portfolio"));
+    }
+}
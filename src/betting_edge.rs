@@ -0,0 +1,100 @@
+use crate::model::company::Company;
+use crate::model::responses::CandidateBettingEdge;
+
+/// Returns the edge and the odds implied by the scenarios of the candidate, approximating them
+/// with a bet that is won with the upside scenarios and lost with the downside scenarios. The Kelly
+/// fraction maximizes p ln(1 + b f) + q ln(1 - a f) for the odds b and the loss fraction a, which
+/// gives f = (p b - q a) / (a b (p + q)), i.e. the classic p - q / b for a = 1 and p + q = 1.
+pub fn betting_edge(company: &Company) -> CandidateBettingEdge {
+    // The residual probability of the scenarios is the implicit status quo, i.e. a push
+    let mut returns = company
+        .scenarios
+        .iter()
+        .map(|s| (s.probability, s.scenario_return(company.market_cap)))
+        .collect::<Vec<(f64, f64)>>();
+    let residual_probability = 1.0 - returns.iter().map(|(p, _)| p).sum::<f64>();
+    if residual_probability > 0.0 {
+        returns.push((residual_probability, 0.0));
+    }
+
+    let edge: f64 = returns.iter().map(|(p, r)| p * r).sum();
+    let variance = returns.iter().map(|(p, r)| p * (r - edge).powi(2)).sum();
+
+    let (probability_of_win, expected_win) = returns
+        .iter()
+        .filter(|(_, r)| *r > 0.0)
+        .fold((0.0, 0.0), |(p_sum, r_sum), (p, r)| {
+            (p_sum + p, r_sum + p * r)
+        });
+    let (probability_of_loss, expected_loss) = returns
+        .iter()
+        .filter(|(_, r)| *r < 0.0)
+        .fold((0.0, 0.0), |(p_sum, r_sum), (p, r)| {
+            (p_sum + p, r_sum - p * r)
+        });
+    let conditional = |expected: f64, probability: f64| {
+        if probability > 0.0 {
+            expected / probability
+        } else {
+            0.0
+        }
+    };
+    let odds = conditional(expected_win, probability_of_win);
+    let loss_fraction = conditional(expected_loss, probability_of_loss);
+
+    let kelly_fraction = (odds > 0.0 && loss_fraction > 0.0).then(|| {
+        (probability_of_win * odds - probability_of_loss * loss_fraction)
+            / (loss_fraction * odds * (probability_of_win + probability_of_loss))
+    });
+
+    CandidateBettingEdge {
+        ticker: company.ticker.clone(),
+        edge,
+        variance,
+        probability_of_win,
+        odds,
+        probability_of_loss,
+        loss_fraction,
+        kelly_fraction,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::bets::Bet;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_betting_edge_of_a_bet() {
+        // A 2-to-1 bet won with probability 0.4, with a push of 0.1 and half the stake lost
+        let bet = Bet {
+            name: "B".to_string(),
+            probability_of_win: 0.4,
+            odds: 2.0,
+            probability_of_loss: Some(0.5),
+            loss_fraction: Some(0.5),
+        };
+        let edge = betting_edge(&bet.to_company());
+        assert_close!(0.4 * 2.0 - 0.5 * 0.5, edge.edge, 1e-12);
+        assert_close!(
+            0.4 * 2.0_f64.powi(2) + 0.5 * 0.5_f64.powi(2) - edge.edge.powi(2),
+            edge.variance,
+            1e-12
+        );
+        assert_close!(0.4, edge.probability_of_win, 1e-12);
+        assert_close!(2.0, edge.odds, 1e-12);
+        assert_close!(0.5, edge.probability_of_loss, 1e-12);
+        assert_close!(0.5, edge.loss_fraction, 1e-12);
+        assert_close!(0.55 / 0.9, edge.kelly_fraction.unwrap(), 1e-12);
+
+        // Without a push and with the whole stake lost, the fraction is the classic p - q / b
+        let classic = Bet {
+            probability_of_loss: None,
+            loss_fraction: None,
+            ..bet
+        };
+        let edge = betting_edge(&classic.to_company());
+        assert_close!(0.4 - 0.6 / 2.0, edge.kelly_fraction.unwrap(), 1e-12);
+    }
+}
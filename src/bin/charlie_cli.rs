@@ -1,11 +1,37 @@
+use charlie::analysis::all_outcomes;
+use charlie::backtest::{run_backtest, DEFAULT_CHUNK_SIZE};
+use charlie::batch_analysis::{analyze_batch, failed_batch_analysis_row, summary_table};
+use charlie::consensus::{blend_analyst_estimates, BlendedEstimates};
+use charlie::elicitation::elicit_probabilities;
 use charlie::env::create_logger;
-use charlie::model::portfolio::{AllocationInput, Portfolio};
-use charlie::{allocate, analyze};
+use charlie::model::allocation_options::AllocationOptions;
+use charlie::model::analysis_options::{AnalysisOptions, DetailLevel};
+use charlie::model::backtest::NamedSnapshot;
+use charlie::model::batch_analysis::{BatchAnalysisRow, NamedPortfolio};
+use charlie::model::bets::BetsInput;
+use charlie::model::company::Company;
+use charlie::model::errors::Error;
+use charlie::model::events::PortfolioWithEvents;
+use charlie::model::portfolio::{AllocationInput, Portfolio, PortfolioCompany, PortfolioMerge};
+use charlie::model::remote_input::{FetchLimits, RemoteInput, RemoteInputFormat};
+use charlie::model::responses::TickerAndFraction;
+use charlie::outcome_diagram::{outcome_diagram, DiagramFormat, MAX_DIAGRAM_OUTCOMES};
+use charlie::run_artifacts::{
+    timestamped_run_dir, write_response, write_run_artifacts, OutputFormat,
+};
+use charlie::scenario_templates::expand_scenario_templates;
+use charlie::solver_state::write_solver_state;
+use charlie::symbology::Symbology;
+use charlie::validation::result::{Severity, ValidationResult};
+use charlie::{allocate, allocate_bets, allocate_with_options, analyze, validate_company};
 use clap::Parser;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use slog::Level::Info;
 use slog::{info, warn, Logger};
+use std::collections::BTreeMap;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 /// Arguments to the command line interface.
@@ -13,15 +39,111 @@ use std::str::FromStr;
 struct CliArgs {
     /// Action that we want to perform via the CLI.
     action: Action,
-    /// Path to .yaml file that contains the input for the action.
-    input_file_path: PathBuf,
+    /// Path to .yaml or .json file that contains the input for the action, or an HTTP(S) URL from
+    /// which the input is fetched. For the batch action, path to a directory of portfolio files,
+    /// and for the backtest action, path to a directory of allocation inputs as snapshots.
+    input_file_path: String,
+    /// Format of the input file: yaml or json. Detected from the extension of the file if not
+    /// specified, defaulting to yaml.
+    #[arg(long)]
+    format: Option<InputFileFormat>,
+    /// Level of detail of the analysis: summary, standard (default) or full.
+    #[arg(long)]
+    detail_level: Option<DetailLevel>,
+    /// Comma-separated tickers restricting the analysis to a sub-portfolio, with fractions held
+    /// fixed.
+    #[arg(long)]
+    tickers: Option<String>,
+    /// Expected annual inflation, e.g. 0.03, for reporting the expected return and growth of the
+    /// analysis in real terms. Overrides the expected inflation of the allocation input.
+    #[arg(long)]
+    expected_inflation: Option<f64>,
+    /// Length of the horizon of the scenarios in years, e.g. 3, for reporting the compound growth
+    /// of the analysis per year.
+    #[arg(long)]
+    horizon_in_years: Option<f64>,
+    /// Number of outcomes sampled in the analysis of portfolios with more than 50,000 outcomes.
+    #[arg(long)]
+    n_samples: Option<usize>,
+    /// Seed of the outcomes sampled in the analysis, for reproducible estimates.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Return of a benchmark, e.g. 0.08, for reporting the probability of underperforming it in the
+    /// analysis. Overrides the benchmark of the allocation input.
+    #[arg(long)]
+    benchmark_return: Option<f64>,
+    /// Return threshold, e.g. -0.1, for reporting the probability of a portfolio return below it
+    /// in the analysis, e.g. of losing more than 10%.
+    #[arg(long, allow_hyphen_values = true)]
+    return_threshold: Option<f64>,
+    /// Comma-separated scenario tags, e.g. recession, for reporting the portfolio return
+    /// conditional on each tag being realized in the analysis.
+    #[arg(long)]
+    conditional_tags: Option<String>,
+    /// Print the iterations of the solver for the unconstrained problem of the allocated
+    /// candidates in the allocate action, for up to five allocated candidates.
+    #[arg(long)]
+    trace: bool,
+    /// Path of a file into which the allocate action writes the state of the solver as JSON, i.e.
+    /// the input and the solution of each system, for reproducing the allocation exactly later.
+    #[arg(long)]
+    solver_state: Option<PathBuf>,
+    /// Path of a file into which the allocate and analyze actions write the whole response, for
+    /// use in scripts. The rebalance action writes the trades, and the backtest action appends its
+    /// rows as NDJSON, by default to backtest.ndjson in the output directory.
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Format of the output file: yaml, json or csv (one row per field of the response). Detected
+    /// from the extension of the output file if not specified, defaulting to json.
+    #[arg(long)]
+    output_format: Option<OutputFormat>,
+    /// Directory where a timestamped directory with the artifacts of the run action is created, and
+    /// where the backtest action writes its output by default.
+    #[arg(long, default_value = "runs")]
+    out_dir: PathBuf,
+    /// Number of periods of the backtest action processed between two checkpoints.
+    #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+    chunk_size: usize,
+    /// Path of the checkpoint of the backtest action, from which an interrupted backtest resumes.
+    /// Defaults to the output path with the checkpoint extension.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Value of the Authorization header sent when fetching the input from a URL.
+    #[arg(long)]
+    auth_header: Option<String>,
+    /// Format of the input fetched from a URL: yaml (default) or google_sheets_csv. The latter
+    /// only provides the candidates for the allocation, without any constraints.
+    #[arg(long)]
+    input_format: Option<RemoteInputFormat>,
+    /// Format of the diagram of the diagram action: mermaid (default) or dot (Graphviz).
+    #[arg(long)]
+    diagram_format: Option<DiagramFormat>,
+    /// Number of the most probable joint outcomes shown in the diagram of the diagram action.
+    #[arg(long, default_value_t = MAX_DIAGRAM_OUTCOMES)]
+    max_outcomes: usize,
+    /// Path to a symbology file with the known securities, one per line with the canonical ticker
+    /// followed by comma-separated aliases. The tickers of the candidates of the allocate and run
+    /// actions are validated against it.
+    #[arg(long)]
+    symbology: Option<String>,
 }
 
 /// Collections of actions exposed via the CLI.
 #[derive(Clone)]
 enum Action {
     Allocate,
+    Bets,
     Analyze,
+    Merge,
+    Diagram,
+    Run,
+    Elicit,
+    Expand,
+    Committee,
+    ValidateCompany,
+    Batch,
+    Backtest,
+    Rebalance,
 }
 
 impl FromStr for Action {
@@ -30,17 +152,148 @@ impl FromStr for Action {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "allocate" => Ok(Action::Allocate),
+            "bets" => Ok(Action::Bets),
             "analyze" => Ok(Action::Analyze),
+            "merge" => Ok(Action::Merge),
+            "diagram" => Ok(Action::Diagram),
+            "run" => Ok(Action::Run),
+            "elicit" => Ok(Action::Elicit),
+            "expand" => Ok(Action::Expand),
+            "committee" => Ok(Action::Committee),
+            "validate-company" => Ok(Action::ValidateCompany),
+            "batch" => Ok(Action::Batch),
+            "backtest" => Ok(Action::Backtest),
+            "rebalance" => Ok(Action::Rebalance),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Expected \"allocate\", \"bets\", \"analyze\", \"merge\", \"diagram\", \"run\", \
+                \"elicit\", \"expand\", \"committee\", \"validate-company\", \"batch\", \
+                \"backtest\" or \"rebalance\" as action, got {}",
+            )),
+        }
+    }
+}
+
+/// Format of the input file. The content of a json file is converted into yaml, such that all
+/// actions work on the same yaml content.
+#[derive(Clone, Copy, PartialEq)]
+enum InputFileFormat {
+    Yaml,
+    Json,
+}
+
+impl FromStr for InputFileFormat {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" => Ok(InputFileFormat::Yaml),
+            "json" => Ok(InputFileFormat::Json),
             _ => Err(std::io::Error::new(
                 ErrorKind::InvalidInput,
-                "Expected \"allocate\" or \"analyze\" as action, got {}",
+                format!("Expected \"yaml\" or \"json\" as format, got {s}"),
             )),
         }
     }
 }
 
+impl InputFileFormat {
+    /// Detects the format from the extension of the file, or returns None if the extension is
+    /// neither .yaml, .yml nor .json.
+    fn from_path(path: &Path) -> Option<InputFileFormat> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(InputFileFormat::Yaml),
+            "json" => Some(InputFileFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Converts the content of a file in this format into yaml.
+    fn to_yaml(self, content: String) -> Result<String, Error> {
+        match self {
+            InputFileFormat::Yaml => Ok(content),
+            InputFileFormat::Json => serde_json::from_str::<serde_json::Value>(&content)
+                .map(|value| serde_yaml::to_string(&value).unwrap())
+                .map_err(|e| Error {
+                    code: "invalid-json-input".to_string(),
+                    message: format!("Failed to parse the json input: {e}."),
+                }),
+        }
+    }
+}
+
+/// Output file into which the whole response of an action is written.
+struct OutputFile {
+    path: PathBuf,
+    format: OutputFormat,
+}
+
+impl OutputFile {
+    /// Writes the response into the output file, failing if it can't be written.
+    fn write<T: Serialize>(&self, logger: &Logger, response: &T) {
+        info!(logger, "Writing the response into {}.", self.path.display());
+        if let Err(e) = write_response(&self.path, self.format, response) {
+            panic!("{}", e.message)
+        }
+    }
+}
+
+/// Validates the tickers of the candidates in the yaml content against the symbology file, logging
+/// the warnings and failing on errors, e.g. two tickers of the same security.
+fn validate_symbology(logger: &Logger, path: &str, yaml_file_content: &str) {
+    let symbology = Symbology::load(path).unwrap_or_else(|e| panic!("{}", e.message));
+    let input: AllocationInput = serde_yaml::from_str(yaml_file_content).unwrap();
+    let tickers = input
+        .candidates
+        .iter()
+        .map(|c| c.ticker.clone())
+        .collect::<Vec<String>>();
+
+    let mut errors = vec![];
+    for result in symbology.validate(&tickers) {
+        if let ValidationResult::PROBLEM(problem) = result {
+            match problem.severity {
+                Severity::WARNING => warn!(logger, "{}", problem.message),
+                Severity::ERROR => errors.push(problem.message),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        panic!("{}", errors.join(" "))
+    }
+}
+
+/// Deserializes the yaml content into a single company and validates it on its own, printing out
+/// the validation problems. Fails if there is at least one error.
+fn validate_company_action(logger: &Logger, yaml_file_content: String) {
+    info!(
+        logger,
+        "Deserializing input file content to a Company object."
+    );
+    let company: Company = serde_yaml::from_str(&yaml_file_content).unwrap();
+
+    let validation_problems = validate_company(&company, logger);
+    info!(
+        logger,
+        "Validation problems are:\n{}",
+        serde_yaml::to_string(&validation_problems).unwrap()
+    );
+    let has_errors = validation_problems
+        .iter()
+        .any(|vr| matches!(vr, ValidationResult::PROBLEM(p) if p.severity == Severity::ERROR));
+    if has_errors {
+        panic!("Company {} has validation errors.", company.ticker)
+    }
+}
+
 /// Deserializes the yaml content into the allocation input and performs the allocation.
-fn allocate_action(logger: &Logger, yaml_file_content: String) {
+fn allocate_action(
+    logger: &Logger,
+    yaml_file_content: String,
+    options: AllocationOptions,
+    output: Option<OutputFile>,
+    solver_state_path: Option<PathBuf>,
+) {
     info!(
         logger,
         "Deserializing input file content to an AllocationInput object."
@@ -51,25 +304,114 @@ fn allocate_action(logger: &Logger, yaml_file_content: String) {
         logger,
         "Started calculating optimal portfolio allocation for {:?}.", input
     );
+    let allocation_response = allocate_with_options(input, &options, logger);
+    if let Some(output) = &output {
+        output.write(logger, &allocation_response);
+    }
+    if allocation_response.error.is_some() {
+        panic!("{}", allocation_response.error.unwrap().message)
+    };
+
+    let allocation_result = allocation_response.result.unwrap();
+    if let (Some(path), Some(solver_state)) = (&solver_state_path, &allocation_result.solver_state)
+    {
+        info!(logger, "Writing the solver state into {}.", path.display());
+        if let Err(e) = write_solver_state(path, solver_state) {
+            panic!("{}", e.message)
+        }
+    }
+    if let Some(trace) = &allocation_result.solver_trace {
+        info!(
+            logger,
+            "Solver iterations:\n{}",
+            serde_yaml::to_string(trace).unwrap()
+        );
+    }
+    let result = serde_yaml::to_string(&allocation_result.allocations).unwrap();
+    info!(logger, "Optimal portfolio is:\n{}", result);
+}
+
+/// Deserializes the yaml content into the allocation input and performs the allocation, returning
+/// the trades from the current weights of the input to the allocation instead of the allocation.
+fn rebalance_action(logger: &Logger, yaml_file_content: String, output: Option<OutputFile>) {
+    info!(
+        logger,
+        "Deserializing input file content to an AllocationInput object."
+    );
+    let input: AllocationInput = serde_yaml::from_str(&yaml_file_content).unwrap();
+    if input.current_weights.is_none() {
+        warn!(
+            logger,
+            "No current weights given, rebalancing from a portfolio fully in cash."
+        );
+    }
+    let input = AllocationInput {
+        current_weights: Some(input.current_weights.clone().unwrap_or_default()),
+        ..input
+    };
+
     let allocation_response = allocate(input, logger);
     if allocation_response.error.is_some() {
         panic!("{}", allocation_response.error.unwrap().message)
     };
 
+    let rebalancing = allocation_response.result.unwrap().rebalancing.unwrap();
+    if let Some(output) = &output {
+        output.write(logger, &rebalancing);
+    }
+    info!(
+        logger,
+        "Trades with a turnover of {} are:\n{}",
+        rebalancing.turnover,
+        serde_yaml::to_string(&rebalancing.trades).unwrap()
+    );
+}
+
+/// Deserializes the yaml content into the bets and performs the allocation.
+fn bets_action(logger: &Logger, yaml_file_content: String) {
+    info!(
+        logger,
+        "Deserializing input file content to a BetsInput object."
+    );
+    let input: BetsInput = serde_yaml::from_str(&yaml_file_content).unwrap();
+
+    info!(
+        logger,
+        "Started calculating optimal allocation for {} bets.",
+        input.bets.len()
+    );
+    let allocation_response = allocate_bets(input, logger);
+    if allocation_response.error.is_some() {
+        panic!("{}", allocation_response.error.unwrap().message)
+    };
+
     let result = serde_yaml::to_string(&allocation_response.result.unwrap().allocations).unwrap();
-    info!(logger, "Optimal portfolio is:\n{}", result);
+    info!(logger, "Optimal allocation is:\n{}", result);
 }
 
 /// Deserializes the yaml content into the analysis input and performs the analysis.
-fn analyze_action(logger: &Logger, yaml_file_content: String) {
+fn analyze_action(
+    logger: &Logger,
+    yaml_file_content: String,
+    options: AnalysisOptions,
+    output: Option<OutputFile>,
+) {
     info!(
         logger,
-        "Deserializing input file content to a Portfolio object."
+        "Deserializing input file content to a PortfolioWithEvents object."
     );
-    let input: Portfolio = serde_yaml::from_str(&yaml_file_content.to_string()).unwrap();
+    let input: PortfolioWithEvents = serde_yaml::from_str(&yaml_file_content.to_string()).unwrap();
 
     info!(logger, "Analyzing the portfolio.");
-    let analysis_response = analyze(input, logger);
+    let analysis_response = analyze(
+        input.portfolio,
+        &input.events.unwrap_or_default(),
+        &options,
+        logger,
+    );
+    if let Some(output) = &output {
+        output.write(logger, &analysis_response);
+    }
     if analysis_response.error.is_some() {
         panic!("{}", analysis_response.error.unwrap().message)
     };
@@ -78,39 +420,589 @@ fn analyze_action(logger: &Logger, yaml_file_content: String) {
     info!(logger, "Portfolio statistics are:\n{}", result);
 }
 
-fn main() {
-    let logger = create_logger(Info);
-    info!(logger, "Parsing command line arguments...");
-    let args: CliArgs = CliArgs::parse();
-    let input_file_path: PathBuf = args.input_file_path;
+/// Deserializes the yaml content into the portfolios, merges them and analyzes the merged
+/// portfolio.
+fn merge_action(logger: &Logger, yaml_file_content: String, options: AnalysisOptions) {
+    info!(
+        logger,
+        "Deserializing input file content to a PortfolioMerge object."
+    );
+    let input: PortfolioMerge = serde_yaml::from_str(&yaml_file_content).unwrap();
 
-    if input_file_path.extension().is_none() {
-        warn!(
-            logger,
-            "Did not find the extension for the input file. Input file {} must be in yaml format.",
-            input_file_path.display()
-        );
-    } else if input_file_path.extension().unwrap() != "yaml" {
+    info!(logger, "Merging {} portfolios.", input.portfolios.len());
+    let merged = match Portfolio::merge_all(&input.portfolios) {
+        Ok(p) => p,
+        Err(e) => panic!("{}", e.message),
+    };
+    let fractions = merged
+        .companies
+        .iter()
+        .map(|pc| TickerAndFraction {
+            ticker: pc.company.ticker.clone(),
+            fraction: pc.fraction,
+        })
+        .collect::<Vec<TickerAndFraction>>();
+    info!(
+        logger,
+        "Merged portfolio is:\n{}",
+        serde_yaml::to_string(&fractions).unwrap()
+    );
+
+    info!(logger, "Analyzing the merged portfolio.");
+    let analysis_response = analyze(merged, &input.events.unwrap_or_default(), &options, logger);
+    if analysis_response.error.is_some() {
+        panic!("{}", analysis_response.error.unwrap().message)
+    };
+
+    let result = serde_yaml::to_string(&analysis_response.result.unwrap()).unwrap();
+    info!(logger, "Merged portfolio statistics are:\n{}", result);
+}
+
+/// Deserializes the yaml content into the portfolio and prints the diagram of its outcome tree to
+/// the standard output, such that it can be redirected into a file.
+fn diagram_action(
+    logger: &Logger,
+    yaml_file_content: String,
+    format: DiagramFormat,
+    max_outcomes: usize,
+) {
+    info!(
+        logger,
+        "Deserializing input file content to a Portfolio object."
+    );
+    let input: Portfolio = serde_yaml::from_str(&yaml_file_content).unwrap();
+
+    info!(logger, "Rendering the outcome tree as {:?}.", format);
+    match outcome_diagram(&input, format, max_outcomes) {
+        Ok(diagram) => print!("{diagram}"),
+        Err(e) => panic!("{}", e.message),
+    }
+}
+
+/// Deserializes the yaml content into the allocation input, performs the allocation, analyzes the
+/// allocated portfolio and writes all the artifacts into a timestamped directory.
+fn run_action(
+    logger: &Logger,
+    yaml_file_content: String,
+    options: AnalysisOptions,
+    out_dir: &Path,
+) {
+    info!(
+        logger,
+        "Deserializing input file content to an AllocationInput object."
+    );
+    let input: AllocationInput = serde_yaml::from_str(&yaml_file_content).unwrap();
+    let candidates = input.candidates.clone();
+    let events = input.events.clone().unwrap_or_default();
+    let macro_scenarios = input.macro_scenarios.clone();
+    let options = AnalysisOptions {
+        expected_inflation: options.expected_inflation.or(input.expected_inflation),
+        benchmark_return: options
+            .benchmark_return
+            .or(input.benchmark.as_ref().map(|b| b.expected_return)),
+        ..options
+    };
+
+    info!(logger, "Calculating optimal portfolio allocation.");
+    let allocation_response = allocate(input, logger);
+    if allocation_response.error.is_some() {
+        panic!("{}", allocation_response.error.unwrap().message)
+    };
+    let allocation_result = allocation_response.result.unwrap();
+
+    // Companies that were filtered out during the allocation are not part of the portfolio
+    let portfolio = Portfolio {
+        companies: allocation_result
+            .allocations
+            .iter()
+            .map(|tf| PortfolioCompany {
+                company: candidates
+                    .iter()
+                    .find(|c| c.ticker == tf.ticker)
+                    .unwrap()
+                    .with_status_quo_scenario(),
+                fraction: tf.fraction,
+            })
+            .collect(),
+        macro_scenarios,
+    };
+
+    info!(logger, "Analyzing the allocated portfolio.");
+    let analysis_response = analyze(portfolio.clone(), &events, &options, logger);
+    if analysis_response.error.is_some() {
+        panic!("{}", analysis_response.error.unwrap().message)
+    };
+    let outcomes = match all_outcomes(&portfolio) {
+        Ok(o) => o,
+        Err(e) => panic!("{}", e.message),
+    };
+
+    let run_dir = timestamped_run_dir(out_dir, chrono::Utc::now());
+    info!(logger, "Writing the artifacts into {}.", run_dir.display());
+    if let Err(e) = write_run_artifacts(
+        &run_dir,
+        &yaml_file_content,
+        &allocation_result,
+        &analysis_response.result.unwrap(),
+        &outcomes,
+    ) {
+        panic!("{}", e.message)
+    }
+}
+
+/// Fraction of a candidate in the consensus allocation and in the allocations of the analysts.
+#[derive(Serialize)]
+struct AnalystFractions {
+    ticker: String,
+    consensus: f64,
+    analysts: BTreeMap<String, f64>,
+}
+
+/// Allocates the input with the consensus probabilities and the input with the probabilities of
+/// each analyst, and prints out the fractions of the candidates in all the allocations.
+fn committee_action(
+    logger: &Logger,
+    yaml_file_content: String,
+    analyst_inputs: Vec<(String, String)>,
+) {
+    if analyst_inputs.is_empty() {
+        panic!("Input doesn't have probability estimates of several analysts.")
+    }
+    let allocations = |yaml_file_content: &str| {
+        let (yaml_file_content, _) = match elicit_probabilities(yaml_file_content) {
+            Ok(elicited) => elicited,
+            Err(e) => panic!("{}", e.message),
+        };
+        let input: AllocationInput = serde_yaml::from_str(&yaml_file_content).unwrap();
+        let allocation_response = allocate(input, logger);
+        if allocation_response.error.is_some() {
+            panic!("{}", allocation_response.error.unwrap().message)
+        };
+        allocation_response.result.unwrap().allocations
+    };
+
+    info!(logger, "Allocating with the consensus probabilities.");
+    let mut fractions = allocations(&yaml_file_content)
+        .into_iter()
+        .map(|tf| AnalystFractions {
+            ticker: tf.ticker,
+            consensus: tf.fraction,
+            analysts: BTreeMap::new(),
+        })
+        .collect::<Vec<AnalystFractions>>();
+    for (analyst, analyst_input) in analyst_inputs {
+        info!(logger, "Allocating with the probabilities of {analyst}.");
+        for tf in allocations(&analyst_input) {
+            match fractions.iter_mut().find(|f| f.ticker == tf.ticker) {
+                Some(f) => {
+                    f.analysts.insert(analyst.clone(), tf.fraction);
+                }
+                None => fractions.push(AnalystFractions {
+                    ticker: tf.ticker,
+                    consensus: 0.0,
+                    analysts: BTreeMap::from([(analyst.clone(), tf.fraction)]),
+                }),
+            }
+        }
+    }
+
+    info!(
+        logger,
+        "Allocations with the consensus and each analyst's probabilities are:\n{}",
+        serde_yaml::to_string(&fractions).unwrap()
+    );
+}
+
+/// Reads the content of the input file in the given format, or in the format detected from its
+/// extension, and returns it in yaml format.
+fn read_input_file(
+    logger: &Logger,
+    input_file_path: PathBuf,
+    format: Option<InputFileFormat>,
+) -> String {
+    let detected_format = InputFileFormat::from_path(&input_file_path);
+    if format.is_none() && detected_format.is_none() {
         warn!(
             logger,
-            "Input file's extension indicates that this might not be a .yaml file. Input file {} \
-            must be in yaml format.",
+            "Input file's extension indicates that this might not be a .yaml or .json file. Input \
+            file {} must be in yaml format, or pass --format json.",
             input_file_path.display()
         )
     }
 
     info!(logger, "Reading {} file.", input_file_path.display());
-    let yaml_file_content: String = std::fs::read_to_string(&input_file_path)
+    let content = std::fs::read_to_string(&input_file_path)
         .expect("Did not manage to read file passed as an argument.");
+    match format
+        .or(detected_format)
+        .unwrap_or(InputFileFormat::Yaml)
+        .to_yaml(content)
+    {
+        Ok(c) => c,
+        Err(e) => panic!("{}", e.message),
+    }
+}
+
+/// Expands the scenario templates, blends the probability estimates of several analysts and
+/// converts the qualitative likelihoods of the consensus into probabilities, logging the warnings
+/// of the conversion. Returns the preprocessed consensus together with the inputs of the analysts.
+fn preprocess_input(logger: &Logger, yaml_file_content: &str) -> Result<BlendedEstimates, Error> {
+    // Expand the scenarios given by templates into concrete scenarios
+    let yaml_file_content = expand_scenario_templates(yaml_file_content)?;
+
+    // Blend the probability estimates of several analysts into consensus probabilities
+    let blended = blend_analyst_estimates(&yaml_file_content)?;
+
+    // Convert qualitative likelihoods of the scenarios into probabilities
+    let (consensus, warnings) = elicit_probabilities(&blended.consensus)?;
+    for warning in warnings {
+        if let ValidationResult::PROBLEM(problem) = warning {
+            warn!(logger, "{}", problem.message);
+        }
+    }
+
+    Ok(BlendedEstimates {
+        consensus,
+        ..blended
+    })
+}
+
+/// Reads the yaml or json file, preprocessed like the input of the other actions, e.g. a portfolio
+/// with events.
+fn read_preprocessed_file<T: DeserializeOwned>(logger: &Logger, path: &Path) -> Result<T, Error> {
+    let content = std::fs::read_to_string(path).map_err(|e| Error {
+        code: "failed-to-read-input-file".to_string(),
+        message: format!("Did not manage to read {}: {e}.", path.display()),
+    })?;
+    let content = InputFileFormat::from_path(path)
+        .unwrap_or(InputFileFormat::Yaml)
+        .to_yaml(content)?;
+    let content = preprocess_input(logger, &content)?.consensus;
+    serde_yaml::from_str(&content).map_err(|e| Error {
+        code: "invalid-input-file".to_string(),
+        message: format!("Failed to deserialize {}: {e}", path.display()),
+    })
+}
+
+/// Returns the paths of the yaml and json files in the directory, sorted by name.
+fn input_files(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = std::fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Did not manage to read directory {}: {e}.", dir.display()))
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| InputFileFormat::from_path(p).is_some())
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    paths
+}
+
+/// Returns the name of the file without the extension, which identifies its content.
+fn file_name(path: &Path) -> String {
+    path.file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().to_string())
+}
+
+/// Analyzes all yaml and json portfolio files in the directory concurrently and prints out a summary
+/// table with one row per portfolio, named by the file name without the extension. Files that can't
+/// be read or deserialized get a row with their error.
+fn batch_action(logger: &Logger, dir: &Path, options: AnalysisOptions) {
+    let paths = input_files(dir);
+    info!(
+        logger,
+        "Found {} portfolio files in {}.",
+        paths.len(),
+        dir.display()
+    );
+
+    let portfolios = paths
+        .iter()
+        .map(|path| read_preprocessed_file(logger, path))
+        .collect::<Vec<Result<PortfolioWithEvents, Error>>>();
+    let mut rows = analyze_batch(
+        paths
+            .iter()
+            .zip(&portfolios)
+            .filter_map(|(path, portfolio)| {
+                portfolio.as_ref().ok().map(|p| NamedPortfolio {
+                    name: file_name(path),
+                    portfolio: p.clone(),
+                })
+            })
+            .collect(),
+        &options,
+        logger,
+    )
+    .into_iter();
+
+    // Rows of the files that couldn't be read are put back in the order of the files
+    let rows = paths
+        .iter()
+        .zip(portfolios)
+        .map(|(path, portfolio)| match portfolio {
+            Ok(_) => rows.next().expect("Every analyzed portfolio has a row."),
+            Err(e) => failed_batch_analysis_row(&file_name(path), e),
+        })
+        .collect::<Vec<BatchAnalysisRow>>();
+
+    info!(
+        logger,
+        "Portfolio statistics are:\n{}",
+        summary_table(&rows)
+    );
+}
+
+/// Backtests the allocation inputs in the yaml and json files of the directory as snapshots, in the
+/// order of their file names (e.g. 2024-01-31.yaml), and appends a row per period to the output as
+/// NDJSON. Progress is checkpointed after each chunk of periods, and an interrupted backtest
+/// resumes from the checkpoint when it's run again.
+fn backtest_action(
+    logger: &Logger,
+    dir: &Path,
+    chunk_size: usize,
+    checkpoint_path: &Path,
+    output_path: &Path,
+) {
+    let paths = input_files(dir);
+    info!(
+        logger,
+        "Found {} snapshot files in {}.",
+        paths.len(),
+        dir.display()
+    );
+    let snapshots = paths
+        .iter()
+        .map(|path| {
+            read_preprocessed_file(logger, path).map(|input| NamedSnapshot {
+                name: file_name(path),
+                input,
+            })
+        })
+        .collect::<Result<Vec<NamedSnapshot>, Error>>()
+        .unwrap_or_else(|e| panic!("{}", e.message));
+
+    if let Some(dir) = output_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+            panic!("Did not manage to create directory {}: {e}.", dir.display())
+        });
+    }
+    match run_backtest(&snapshots, chunk_size, checkpoint_path, output_path, logger) {
+        Ok(checkpoint) => info!(
+            logger,
+            "Backtest of {} periods finished with a cumulative wealth of {:.4}. Rows are in {}.",
+            checkpoint.completed_periods,
+            checkpoint.cumulative_wealth,
+            output_path.display()
+        ),
+        Err(e) => panic!("{}", e.message),
+    }
+}
+
+/// Fetches the input from a URL. Candidates from a Google Sheet are converted into an allocation
+/// input in yaml format.
+fn fetch_input(
+    logger: &Logger,
+    url: String,
+    auth_header: Option<String>,
+    format: Option<RemoteInputFormat>,
+) -> String {
+    info!(logger, "Fetching the input from {url}.");
+    let remote_input = RemoteInput {
+        url,
+        auth_header,
+        format,
+    };
+    let limits = FetchLimits::cli();
+    let content = match remote_input.format.unwrap_or_default() {
+        RemoteInputFormat::Yaml => remote_input.fetch(&remote_input.url, &limits),
+        RemoteInputFormat::GoogleSheetsCsv => remote_input
+            .fetch_allocation_input(&limits)
+            .map(|input| serde_yaml::to_string(&input).unwrap()),
+    };
+
+    match content {
+        Ok(c) => c,
+        Err(e) => panic!("{}", e.message),
+    }
+}
+
+fn main() {
+    let logger = create_logger(Info);
+    info!(logger, "Parsing command line arguments...");
+    let args: CliArgs = CliArgs::parse();
+    let input = args.input_file_path;
+
+    // Batch action reads a whole directory of portfolio files
+    if let Action::Batch = args.action {
+        info!(logger, "Performing analysis of a batch of portfolios.");
+        let options = AnalysisOptions {
+            detail_level: args.detail_level,
+            tickers: args.tickers,
+            expected_inflation: args.expected_inflation,
+            horizon_in_years: args.horizon_in_years,
+            n_samples: args.n_samples,
+            seed: args.seed,
+            benchmark_return: args.benchmark_return,
+            distribution: None,
+            n_bins: None,
+            conditional_tags: args.conditional_tags,
+            return_threshold: args.return_threshold,
+        };
+        batch_action(&logger, Path::new(&input), options);
+        return;
+    }
+
+    // Backtest action reads a whole directory of snapshots as well
+    if let Action::Backtest = args.action {
+        info!(logger, "Performing backtest of a directory of snapshots.");
+        let output_path = args
+            .output
+            .unwrap_or_else(|| args.out_dir.join("backtest.ndjson"));
+        let checkpoint_path = args
+            .checkpoint
+            .unwrap_or_else(|| output_path.with_extension("checkpoint"));
+        backtest_action(
+            &logger,
+            Path::new(&input),
+            args.chunk_size,
+            &checkpoint_path,
+            &output_path,
+        );
+        return;
+    }
+
+    let output = args.output.map(|path| OutputFile {
+        format: args
+            .output_format
+            .unwrap_or_else(|| OutputFormat::from_path(&path)),
+        path,
+    });
+
+    let yaml_file_content: String = if input.starts_with("http://") || input.starts_with("https://")
+    {
+        fetch_input(&logger, input, args.auth_header, args.input_format)
+    } else {
+        read_input_file(&logger, PathBuf::from(input), args.format)
+    };
+
+    let blended = match preprocess_input(&logger, &yaml_file_content) {
+        Ok(blended) => blended,
+        Err(e) => panic!("{}", e.message),
+    };
+    let yaml_file_content = blended.consensus.clone();
+
+    // Validate the tickers of the candidates against the symbology
+    if let (Some(path), Action::Allocate | Action::Run | Action::Rebalance) =
+        (&args.symbology, &args.action)
+    {
+        validate_symbology(&logger, path, &yaml_file_content);
+    }
 
     match args.action {
         Action::Allocate => {
             info!(logger, "Performing allocation.");
-            allocate_action(&logger, yaml_file_content)
+            allocate_action(
+                &logger,
+                yaml_file_content,
+                AllocationOptions {
+                    trace: Some(args.trace),
+                    solver_state: Some(args.solver_state.is_some()),
+                    ..AllocationOptions::default()
+                },
+                output,
+                args.solver_state,
+            )
+        }
+        Action::Rebalance => {
+            info!(logger, "Performing rebalancing of the current weights.");
+            rebalance_action(&logger, yaml_file_content, output)
+        }
+        Action::Bets => {
+            info!(logger, "Performing allocation on bets.");
+            bets_action(&logger, yaml_file_content)
         }
         Action::Analyze => {
             info!(logger, "Performing portfolio analysis.");
-            analyze_action(&logger, yaml_file_content)
+            let options = AnalysisOptions {
+                detail_level: args.detail_level,
+                tickers: args.tickers,
+                expected_inflation: args.expected_inflation,
+                horizon_in_years: args.horizon_in_years,
+                n_samples: args.n_samples,
+                seed: args.seed,
+                benchmark_return: args.benchmark_return,
+                distribution: None,
+                n_bins: None,
+                conditional_tags: args.conditional_tags,
+                return_threshold: args.return_threshold,
+            };
+            analyze_action(&logger, yaml_file_content, options, output)
+        }
+        Action::Merge => {
+            info!(logger, "Performing merge and analysis of portfolios.");
+            let options = AnalysisOptions {
+                detail_level: args.detail_level,
+                tickers: args.tickers,
+                expected_inflation: args.expected_inflation,
+                horizon_in_years: args.horizon_in_years,
+                n_samples: args.n_samples,
+                seed: args.seed,
+                benchmark_return: args.benchmark_return,
+                distribution: None,
+                n_bins: None,
+                conditional_tags: args.conditional_tags,
+                return_threshold: args.return_threshold,
+            };
+            merge_action(&logger, yaml_file_content, options)
+        }
+        Action::Diagram => {
+            info!(logger, "Performing export of the outcome diagram.");
+            diagram_action(
+                &logger,
+                yaml_file_content,
+                args.diagram_format.unwrap_or_default(),
+                args.max_outcomes,
+            )
+        }
+        Action::Run => {
+            info!(logger, "Performing allocation and analysis.");
+            let options = AnalysisOptions {
+                detail_level: args.detail_level,
+                tickers: args.tickers,
+                expected_inflation: args.expected_inflation,
+                horizon_in_years: args.horizon_in_years,
+                n_samples: args.n_samples,
+                seed: args.seed,
+                benchmark_return: args.benchmark_return,
+                distribution: None,
+                n_bins: None,
+                conditional_tags: args.conditional_tags,
+                return_threshold: args.return_threshold,
+            };
+            run_action(&logger, yaml_file_content, options, &args.out_dir)
+        }
+        Action::Elicit => {
+            info!(
+                logger,
+                "Input with likelihoods converted into probabilities is:\n{}", yaml_file_content
+            );
+        }
+        Action::Committee => {
+            info!(logger, "Performing allocation for each analyst.");
+            committee_action(&logger, yaml_file_content, blended.analysts)
+        }
+        Action::ValidateCompany => {
+            info!(logger, "Performing validation of a single company.");
+            validate_company_action(&logger, yaml_file_content)
+        }
+        Action::Expand => {
+            info!(
+                logger,
+                "Input with scenario templates expanded into concrete scenarios is:\n{}",
+                yaml_file_content
+            );
+        }
+        Action::Batch => unreachable!("Batch action is performed before reading the input file."),
+        Action::Backtest => {
+            unreachable!("Backtest action is performed before reading the input file.")
         }
     }
 }
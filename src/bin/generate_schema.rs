@@ -1,8 +1,8 @@
 use charlie::env::{create_logger, get_project_dir};
-use charlie::{allocate_endpoint, analyze_endpoint, demo, openapi};
-use dropshot::ApiDescription;
+use charlie::openapi_schema;
+use charlie::standalone::api_page;
 use serde_json::Value;
-use slog::{info, Level, Logger};
+use slog::{info, warn, Level, Logger};
 use std::fs;
 use std::process::Command;
 use std::str::from_utf8;
@@ -17,15 +17,16 @@ fn main() {
     );
 
     // Write the schema
+    let schema = generate_schema(&logger);
     fs::write(
         schema_file_path,
-        serde_json::to_string_pretty(&generate_schema(&logger))
+        serde_json::to_string_pretty(&schema)
             .expect("Failed to convert OpenAPI definition to pretty string."),
     )
     .expect("Failed to write the schema.");
 
     // Generate the index.html from the schema
-    generate_index(&logger);
+    generate_index(&schema, &logger);
 
     info!(logger, "Done.");
 }
@@ -36,21 +37,14 @@ fn generate_schema(logger: &Logger) -> Value {
         logger,
         "Creating JSON schema for all input and output data structures..."
     );
-    info!(logger, "Registering API endpoints.");
-    let mut api = ApiDescription::new();
-    api.register(allocate_endpoint).unwrap();
-    api.register(analyze_endpoint).unwrap();
-    api.register(openapi).unwrap();
-    api.register(demo).unwrap();
-
     info!(logger, "Generating OpenAPI JSON schema.");
-    api.openapi("Charlie", "v0")
-        .json()
-        .expect("Failed to convert OpenAPIDefinition to JSON.")
+    openapi_schema()
 }
 
-/// Generate index.html from the schema, by calling npx as a subprocess
-fn generate_index(logger: &Logger) {
+/// Generate index.html from the schema, by calling npx as a subprocess. If npx fails, e.g. without
+/// network access, the page of the standalone mode is written instead, which renders the same
+/// schema with the same ReDoc bundle in the browser, such that the index never gets stale.
+fn generate_index(schema: &Value, logger: &Logger) {
     info!(
         logger,
         "Generating index from schema in schema/openapi.json"
@@ -68,13 +62,35 @@ fn generate_index(logger: &Logger) {
         logger,
         "Running npx command to generate index: {:?}", generate_index_command
     );
-    let output = generate_index_command.output().unwrap();
+    match generate_index_command.output() {
+        Ok(output) if output.status.success() => info!(
+            logger,
+            "Output of the npx command: {}",
+            from_utf8(&output.stdout).unwrap()
+        ),
+        Ok(output) => {
+            warn!(
+                logger,
+                "npx command failed: {}",
+                from_utf8(&output.stderr).unwrap()
+            );
+            write_standalone_index(schema, logger);
+        }
+        Err(e) => {
+            warn!(logger, "Failed to run the npx command: {e}");
+            write_standalone_index(schema, logger);
+        }
+    }
+}
 
+/// Writes the page of the standalone mode with the schema embedded as index.html
+fn write_standalone_index(schema: &Value, logger: &Logger) {
+    let index_file_path = get_project_dir().join("schema").join("index.html");
     info!(
         logger,
-        "Output of the npx command: {}",
-        from_utf8(&output.stdout).unwrap()
+        "Writing the standalone page rendering the schema to {:?}", index_file_path
     );
+    fs::write(index_file_path, api_page(schema)).expect("Failed to write the index.");
 }
 
 #[cfg(test)]
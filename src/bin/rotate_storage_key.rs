@@ -0,0 +1,56 @@
+use charlie::config::ServerConfig;
+use charlie::env::{create_logger, get_project_dir};
+use charlie::storage::create_storage;
+use charlie::storage::encrypted_storage::{generate_key, reencrypt_all};
+use clap::{Parser, ValueEnum};
+use slog::{info, Level};
+use std::path::PathBuf;
+
+/// Tooling for the encryption of the stored documents at rest. A key rotation consists of:
+/// 1. Generating a new key and putting it into a new environment variable,
+/// 2. Moving the variable of the old key to the previous key variables in the configuration and
+///    setting the variable of the new key as the key variable,
+/// 3. Encrypting all documents again with the new key, after which the old key can be dropped.
+#[derive(Parser)]
+struct RotateArgs {
+    /// Action to perform: generate (print a new random key) or reencrypt (encrypt all stored
+    /// documents with the current key of the configuration).
+    action: RotateAction,
+    /// Path of the server configuration. Defaults to server_config.toml in the project directory.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Clone, ValueEnum)]
+enum RotateAction {
+    Generate,
+    Reencrypt,
+}
+
+fn main() {
+    let args = RotateArgs::parse();
+
+    match args.action {
+        RotateAction::Generate => println!("{}", generate_key()),
+        RotateAction::Reencrypt => {
+            let logger = create_logger(Level::Info);
+            let config_path = args
+                .config
+                .unwrap_or_else(|| get_project_dir().join("server_config.toml"));
+            let config =
+                ServerConfig::load(&config_path).unwrap_or_else(|e| panic!("{}", e.message));
+            if config.storage.encryption.is_none() {
+                panic!("Storage encryption is not configured in {config_path:?}.");
+            }
+
+            let storage = create_storage(&config.storage)
+                .unwrap_or_else(|e| panic!("Failed to open the storage: {}", e.message));
+            let n_documents = reencrypt_all(storage.as_ref())
+                .unwrap_or_else(|e| panic!("Failed to encrypt the documents: {}", e.message));
+            info!(
+                logger,
+                "Encrypted {n_documents} documents with the current key."
+            );
+        }
+    }
+}
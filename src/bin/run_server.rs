@@ -1,53 +1,97 @@
 use camino::Utf8PathBuf;
-use charlie::env::get_project_dir;
-use charlie::{allocate_endpoint, analyze_endpoint, demo, openapi};
-use dropshot::{
-    ApiDescription, ConfigDropshot, ConfigLogging, ConfigLoggingIfExists, ConfigLoggingLevel,
-    HttpServerStarter,
-};
+use charlie::api_description;
+use charlie::config::{LogFormat, ServerConfig};
+use charlie::config_watcher::watch_server_config;
+use charlie::env::{create_json_logger, get_project_dir};
+use charlie::server_state::ServerState;
+use charlie::standalone::StandalonePages;
+use clap::Parser;
+use dropshot::{ConfigLogging, ConfigLoggingIfExists, ConfigLoggingLevel, HttpServerStarter};
 use slog::info;
-use std::fs;
+
+#[derive(Parser)]
+struct ServerArgs {
+    /// Self-contained mode that needs no files, e.g. in a container: the configuration is read
+    /// from the environment (see HOW-TO.md), the demo and the API documentation are served from
+    /// the binary and the logs are written to the standard output in JSON by default.
+    #[arg(long)]
+    standalone: bool,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
-    // Read file containing the server config
-    let server_config_file_path = get_project_dir().join("server_config.toml");
-    let server_config_str =
-        fs::read_to_string(server_config_file_path.clone()).unwrap_or_else(|_| {
-            panic!(
-                "Did not manage to read server config file at: {:?}",
-                server_config_file_path
-            )
-        });
-    let server_config: ConfigDropshot =
-        toml::from_str(&server_config_str).expect("Failed to deserialize server config.");
+    let args = ServerArgs::parse();
 
-    // An info-level logger to a file
-    let config_logging = ConfigLogging::File {
-        level: ConfigLoggingLevel::Info,
-        path: Utf8PathBuf::from("./server.log"),
-        if_exists: ConfigLoggingIfExists::Append,
-    };
-    let log = config_logging
+    // Read the server config from the file in the project directory, or from the environment in
+    // the standalone mode
+    let server_config_file_path =
+        (!args.standalone).then(|| get_project_dir().join("server_config.toml"));
+    let server_config = match &server_config_file_path {
+        Some(path) => ServerConfig::load(path),
+        None => ServerConfig::from_env(),
+    }
+    .unwrap_or_else(|e| panic!("{}", e.message));
+    let mut server_state = ServerState::new(&server_config);
+
+    // A logger in the format of the configuration, filtered by the log level of the
+    // configuration, which can be changed while the server is running
+    let log_format = server_config.logging.format.unwrap_or(if args.standalone {
+        LogFormat::Json
+    } else {
+        LogFormat::Bunyan
+    });
+    let logger = match log_format {
+        LogFormat::Json => create_json_logger(),
+        LogFormat::Bunyan => ConfigLogging::File {
+            level: ConfigLoggingLevel::Trace,
+            path: Utf8PathBuf::from("./server.log"),
+            if_exists: ConfigLoggingIfExists::Append,
+        }
+        .to_logger("charlie")
+        .map_err(|error| format!("failed to create logger: {}", error))?,
+        LogFormat::Terminal => ConfigLogging::StderrTerminal {
+            level: ConfigLoggingLevel::Trace,
+        }
         .to_logger("charlie")
-        .map_err(|error| format!("failed to create logger: {}", error))?;
+        .map_err(|error| format!("failed to create logger: {}", error))?,
+    };
+    let log = server_state.log_level.filter(logger);
 
     // Create an API description object and register the endpoints
     info!(log, "Registering API endpoints.");
-    let mut api = ApiDescription::new();
-    api.register(openapi).unwrap();
-    api.register(allocate_endpoint).unwrap();
-    api.register(analyze_endpoint).unwrap();
-    api.register(demo).unwrap();
+    let api = api_description();
+    if args.standalone {
+        info!(
+            log,
+            "Generating the OpenAPI schema for the standalone mode."
+        );
+        server_state.standalone_pages = Some(StandalonePages::new(
+            &api.openapi("Charlie", "v0")
+                .json()
+                .map_err(|error| format!("failed to generate schema: {}", error))?,
+        ));
+    }
 
     // Set up the server.
     info!(log, "Setting up the server.");
-    let server = HttpServerStarter::new(&server_config, api, (), &log)
+    let server = HttpServerStarter::new(&server_config.dropshot, api, server_state, &log)
         .map_err(|error| format!("failed to create server: {}", error))?
         .start();
 
-    // Wait for the server to stop.  Note that there's not any code to shut down
-    // this server, so we should never get past this point.
+    // Wait for the server to stop while applying the changes of the configuration file, if any.
+    // Note that there's not any code to shut down this server, so we should never get past this
+    // point.
     info!(log, "Began serving.");
-    server.await
+    match server_config_file_path {
+        Some(path) => tokio::select! {
+            result = server.wait_for_shutdown() => result,
+            _ = watch_server_config(
+                path,
+                server_config.clone(),
+                server.app_private(),
+                &log,
+            ) => Ok(()),
+        },
+        None => server.wait_for_shutdown().await,
+    }
 }
@@ -0,0 +1,446 @@
+use crate::model::candidates::{
+    CandidateRevision, CandidateUpdate, SnapshotQuery, StoredCandidates,
+};
+use crate::model::company::Company;
+use crate::model::errors::Error;
+use crate::storage::{load_all, save, Collection, Storage};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Store of the latest candidates per workspace, together with the history of changes of each
+/// candidate. Each workspace is persisted as a document in the storage, and all workspaces are
+/// loaded when the store is created.
+pub struct CandidateStore {
+    storage: Arc<dyn Storage>,
+    workspaces: Mutex<HashMap<String, Workspace>>,
+}
+
+/// Latest candidates of a workspace and all the revisions that led to them, in the order of the
+/// updates. The history is optional such that workspaces persisted without it can be loaded.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Workspace {
+    #[serde(flatten)]
+    latest: StoredCandidates,
+    #[serde(default)]
+    history: Vec<CandidateRevision>,
+}
+
+impl CandidateStore {
+    /// Creates a new [CandidateStore] on top of the storage, loading the persisted workspaces.
+    pub fn new(storage: Arc<dyn Storage>) -> Result<CandidateStore, Error> {
+        let workspaces = load_all::<Workspace>(storage.as_ref(), Collection::Candidates)?
+            .into_iter()
+            .collect();
+
+        Ok(CandidateStore {
+            storage,
+            workspaces: Mutex::new(workspaces),
+        })
+    }
+
+    /// Returns the latest candidates of the workspace, if any.
+    pub fn get(&self, workspace: &str) -> Option<StoredCandidates> {
+        self.workspaces
+            .lock()
+            .unwrap()
+            .get(workspace)
+            .map(|w| w.latest.clone())
+    }
+
+    /// Returns all revisions of the candidate with the given ticker in the workspace, oldest
+    /// first, or None if the workspace doesn't exist.
+    pub fn history(&self, workspace: &str, ticker: &str) -> Option<Vec<CandidateRevision>> {
+        self.workspaces.lock().unwrap().get(workspace).map(|w| {
+            w.history
+                .iter()
+                .filter(|r| r.ticker == ticker)
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Reconstructs the candidates of the workspace as they were after the queried version and/or
+    /// at the queried time, from the latest revision of each candidate. The version of the snapshot
+    /// is the version of the last update that changed any of the candidates. Returns None if the
+    /// workspace doesn't exist or didn't have any candidates at that point.
+    pub fn snapshot(&self, workspace: &str, query: &SnapshotQuery) -> Option<StoredCandidates> {
+        let workspaces = self.workspaces.lock().unwrap();
+        let history = &workspaces.get(workspace)?.history;
+
+        let revisions = history
+            .iter()
+            .filter(|r| query.version.map_or(true, |v| r.version <= v))
+            .filter(|r| query.as_of.map_or(true, |t| r.updated_at <= t))
+            .collect_vec();
+        let last_revision = revisions.last()?;
+
+        // Candidates are ordered by their first appearance
+        let candidates = revisions
+            .iter()
+            .map(|r| &r.ticker)
+            .unique()
+            .filter_map(|ticker| {
+                revisions
+                    .iter()
+                    .rev()
+                    .find(|r| &r.ticker == ticker)
+                    .and_then(|r| r.company.clone())
+            })
+            .collect();
+
+        Some(StoredCandidates {
+            version: last_revision.version,
+            updated_at: Some(last_revision.updated_at),
+            candidates,
+        })
+    }
+
+    /// Applies the update to the candidates of the workspace, creating the workspace if it doesn't
+    /// exist, and returns the updated candidates.
+    pub fn update(
+        &self,
+        workspace: &str,
+        update: CandidateUpdate,
+    ) -> Result<StoredCandidates, Error> {
+        self.update_at(workspace, update, Utc::now())
+    }
+
+    /// Same as [CandidateStore::update], with the given time of the update.
+    fn update_at(
+        &self,
+        workspace: &str,
+        update: CandidateUpdate,
+        now: DateTime<Utc>,
+    ) -> Result<StoredCandidates, Error> {
+        validate_workspace_name(workspace)?;
+        validate_unique_tickers(&update)?;
+
+        let mut workspaces = self.workspaces.lock().unwrap();
+        let Workspace {
+            latest: stored,
+            mut history,
+        } = workspaces.get(workspace).cloned().unwrap_or_default();
+        if let Some(expected_version) = update.expected_version {
+            if expected_version != stored.version {
+                return Err(Error {
+                    code: "candidate-version-conflict".to_string(),
+                    message: format!(
+                        "Expected version {expected_version} of the candidates in workspace \
+                        {workspace}, but the stored version is {}.",
+                        stored.version
+                    ),
+                });
+            }
+        }
+
+        let candidates = if update.replace.unwrap_or(false) {
+            update.candidates
+        } else {
+            let mut candidates = stored.candidates.clone();
+            for company in update.candidates {
+                match candidates.iter_mut().find(|c| c.ticker == company.ticker) {
+                    Some(c) => *c = company,
+                    None => candidates.push(company),
+                }
+            }
+            candidates
+        };
+        let version = stored.version + 1;
+        history.extend(revisions(&stored.candidates, &candidates, version, now));
+
+        let updated = Workspace {
+            latest: StoredCandidates {
+                version,
+                updated_at: Some(now),
+                candidates,
+            },
+            history,
+        };
+        save(
+            self.storage.as_ref(),
+            Collection::Candidates,
+            workspace,
+            &updated,
+        )?;
+        workspaces.insert(workspace.to_string(), updated.clone());
+
+        Ok(updated.latest)
+    }
+}
+
+/// Returns the revisions for the candidates that were added or changed, followed by the ones that
+/// were removed. Note that companies are compared by their serialized values, since equality of
+/// companies only considers the tickers.
+fn revisions(
+    old: &[Company],
+    new: &[Company],
+    version: u64,
+    updated_at: DateTime<Utc>,
+) -> Vec<CandidateRevision> {
+    let changed = new
+        .iter()
+        .filter(|c| {
+            !old.iter()
+                .any(|o| serde_json::to_value(o).ok() == serde_json::to_value(c).ok())
+        })
+        .map(|c| (c.ticker.clone(), Some(c.clone())));
+    let removed = old
+        .iter()
+        .filter(|o| !new.iter().any(|c| c.ticker == o.ticker))
+        .map(|o| (o.ticker.clone(), None));
+
+    changed
+        .chain(removed)
+        .map(|(ticker, company)| CandidateRevision {
+            ticker,
+            version,
+            updated_at,
+            company,
+        })
+        .collect()
+}
+
+/// Workspace names are used as file names, so only alphanumeric characters, dashes and
+/// underscores are allowed.
+fn validate_workspace_name(workspace: &str) -> Result<(), Error> {
+    if workspace.is_empty()
+        || !workspace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error {
+            code: "invalid-workspace-name".to_string(),
+            message: format!(
+                "Workspace name can only contain alphanumeric characters, dashes and underscores. \
+                You provided {workspace}."
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// A ticker may appear only once in an update, otherwise the merge would be ambiguous.
+fn validate_unique_tickers(update: &CandidateUpdate) -> Result<(), Error> {
+    let duplicates = update
+        .candidates
+        .iter()
+        .map(|c| &c.ticker)
+        .duplicates()
+        .join(", ");
+    if !duplicates.is_empty() {
+        return Err(Error {
+            code: "duplicate-tickers-in-candidate-update".to_string(),
+            message: format!("Tickers {duplicates} appear more than once in the update."),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::Company;
+    use crate::model::scenario::Scenario;
+    use crate::storage::memory_storage::MemoryStorage;
+
+    fn company(ticker: &str, market_cap: f64) -> Company {
+        Company {
+            name: ticker.to_string(),
+            ticker: ticker.to_string(),
+            description: "".to_string(),
+            market_cap,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![Scenario {
+                thesis: "Up".to_string(),
+                intrinsic_value: 2.0 * market_cap,
+                probability: 1.0,
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn update(candidates: Vec<Company>) -> CandidateUpdate {
+        CandidateUpdate {
+            candidates,
+            replace: None,
+            expected_version: None,
+        }
+    }
+
+    #[test]
+    fn test_update_merges_candidates_by_ticker() {
+        let store = CandidateStore::new(Arc::new(MemoryStorage::default())).unwrap();
+        store
+            .update("ws", update(vec![company("A", 1.0), company("B", 2.0)]))
+            .unwrap();
+        let stored = store
+            .update("ws", update(vec![company("B", 3.0), company("C", 4.0)]))
+            .unwrap();
+
+        assert_eq!(2, stored.version);
+        assert_eq!(
+            vec![("A", 1.0), ("B", 3.0), ("C", 4.0)],
+            stored
+                .candidates
+                .iter()
+                .map(|c| (c.ticker.as_str(), c.market_cap))
+                .collect::<Vec<_>>()
+        );
+
+        let replaced = store
+            .update(
+                "ws",
+                CandidateUpdate {
+                    replace: Some(true),
+                    ..update(vec![company("D", 5.0)])
+                },
+            )
+            .unwrap();
+        assert_eq!(3, replaced.version);
+        assert_eq!(1, replaced.candidates.len());
+        assert!(store.get("other").is_none());
+    }
+
+    #[test]
+    fn test_update_with_version_conflict() {
+        let store = CandidateStore::new(Arc::new(MemoryStorage::default())).unwrap();
+        store.update("ws", update(vec![company("A", 1.0)])).unwrap();
+
+        let conflicting = CandidateUpdate {
+            expected_version: Some(0),
+            ..update(vec![company("A", 2.0)])
+        };
+        assert_eq!(
+            "candidate-version-conflict",
+            store.update("ws", conflicting).unwrap_err().code
+        );
+        assert_eq!(1.0, store.get("ws").unwrap().candidates[0].market_cap);
+    }
+
+    #[test]
+    fn test_update_with_invalid_input() {
+        let store = CandidateStore::new(Arc::new(MemoryStorage::default())).unwrap();
+
+        assert_eq!(
+            "invalid-workspace-name",
+            store
+                .update("../ws", update(vec![company("A", 1.0)]))
+                .unwrap_err()
+                .code
+        );
+        assert_eq!(
+            "duplicate-tickers-in-candidate-update",
+            store
+                .update("ws", update(vec![company("A", 1.0), company("A", 2.0)]))
+                .unwrap_err()
+                .code
+        );
+    }
+
+    #[test]
+    fn test_history_and_snapshot() {
+        let store = CandidateStore::new(Arc::new(MemoryStorage::default())).unwrap();
+        let time = |hour: u32| {
+            DateTime::parse_from_rfc3339(&format!("2024-06-01T{hour:02}:00:00Z"))
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        store
+            .update_at(
+                "ws",
+                update(vec![company("A", 1.0), company("B", 2.0)]),
+                time(9),
+            )
+            .unwrap();
+        // Unchanged company A doesn't get a new revision
+        store
+            .update_at(
+                "ws",
+                update(vec![company("A", 1.0), company("B", 3.0)]),
+                time(10),
+            )
+            .unwrap();
+        store
+            .update_at(
+                "ws",
+                CandidateUpdate {
+                    replace: Some(true),
+                    ..update(vec![company("B", 3.0), company("C", 4.0)])
+                },
+                time(11),
+            )
+            .unwrap();
+
+        let history_b = store.history("ws", "B").unwrap();
+        assert_eq!(
+            vec![1, 2],
+            history_b.iter().map(|r| r.version).collect_vec()
+        );
+        assert_eq!(3.0, history_b[1].company.as_ref().unwrap().market_cap);
+        let history_a = store.history("ws", "A").unwrap();
+        assert_eq!(
+            vec![1, 3],
+            history_a.iter().map(|r| r.version).collect_vec()
+        );
+        assert!(history_a[1].company.is_none());
+
+        let market_caps = |snapshot: StoredCandidates| {
+            snapshot
+                .candidates
+                .iter()
+                .map(|c| (c.ticker.clone(), c.market_cap))
+                .collect_vec()
+        };
+        let by_version = SnapshotQuery {
+            version: Some(2),
+            as_of: None,
+        };
+        assert_eq!(
+            vec![("A".to_string(), 1.0), ("B".to_string(), 3.0)],
+            market_caps(store.snapshot("ws", &by_version).unwrap())
+        );
+        let by_time = SnapshotQuery {
+            version: None,
+            as_of: Some(time(9) + chrono::Duration::minutes(30)),
+        };
+        let snapshot = store.snapshot("ws", &by_time).unwrap();
+        assert_eq!(1, snapshot.version);
+        assert_eq!(
+            vec![("A".to_string(), 1.0), ("B".to_string(), 2.0)],
+            market_caps(snapshot)
+        );
+        assert_eq!(
+            vec![("B".to_string(), 3.0), ("C".to_string(), 4.0)],
+            market_caps(store.snapshot("ws", &SnapshotQuery::default()).unwrap())
+        );
+
+        let before_first_update = SnapshotQuery {
+            version: None,
+            as_of: Some(time(8)),
+        };
+        assert!(store.snapshot("ws", &before_first_update).is_none());
+    }
+
+    #[test]
+    fn test_persisted_candidates_are_loaded() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+
+        CandidateStore::new(storage.clone())
+            .unwrap()
+            .update("ws", update(vec![company("A", 1.0)]))
+            .unwrap();
+        let store = CandidateStore::new(storage).unwrap();
+        let stored = store.get("ws").unwrap();
+
+        assert_eq!(1, stored.version);
+        assert_eq!("A", stored.candidates[0].ticker);
+        assert_eq!(1, store.history("ws", "A").unwrap().len());
+    }
+}
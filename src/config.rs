@@ -0,0 +1,490 @@
+use crate::model::errors::Error;
+use crate::model::remote_input::FetchLimits;
+use crate::model::usage::Quota;
+use dropshot::{ConfigDropshot, ConfigLoggingLevel};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::thread::available_parallelism;
+use std::time::Duration;
+
+/// Default maximum number of allocation requests waiting for a free slot.
+const DEFAULT_MAX_QUEUED_ALLOCATIONS: usize = 64;
+
+/// Default validity of a share link, one week.
+const DEFAULT_SHARE_EXPIRY_HOURS: u64 = 7 * 24;
+
+/// Default longest validity of a share link, 30 days.
+const DEFAULT_MAX_SHARE_EXPIRY_HOURS: u64 = 30 * 24;
+
+/// Default largest relative difference between the market cap in the input and the live one
+/// without a warning.
+const DEFAULT_MAX_MARKET_CAP_DRIFT: f64 = 0.1;
+
+/// Default timeout of fetching a remote input document.
+const DEFAULT_REMOTE_INPUT_TIMEOUT_SECONDS: u64 = 10;
+
+/// Default largest size of a remote input document, 10 MB.
+const DEFAULT_REMOTE_INPUT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Environment variable with the whole server configuration in TOML in the standalone mode.
+pub const CONFIG_VARIABLE: &str = "CHARLIE_CONFIG";
+
+/// Environment variable with the bind address in the standalone mode, e.g. `0.0.0.0:8000`.
+pub const BIND_ADDRESS_VARIABLE: &str = "CHARLIE_BIND_ADDRESS";
+
+/// Environment variable with the maximum request body size in bytes in the standalone mode.
+pub const REQUEST_BODY_MAX_BYTES_VARIABLE: &str = "CHARLIE_REQUEST_BODY_MAX_BYTES";
+
+/// Environment variable with the log level in the standalone mode, e.g. `debug`.
+pub const LOG_LEVEL_VARIABLE: &str = "CHARLIE_LOG_LEVEL";
+
+/// Bind address in the standalone mode if not given, reachable from outside of a container.
+const DEFAULT_STANDALONE_BIND_ADDRESS: &str = "0.0.0.0:8000";
+
+/// Maximum request body size in the standalone mode if not given, 1 MB.
+const DEFAULT_STANDALONE_REQUEST_BODY_MAX_BYTES: usize = 1024 * 1024;
+
+/// Server configuration consisting of the Dropshot configuration (bind address, maximum request
+/// body size, etc.) and the settings specific to this server. Dropshot settings are at the top
+/// level of the configuration file for backwards compatibility.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerConfig {
+    #[serde(flatten)]
+    pub dropshot: ConfigDropshot,
+
+    #[serde(default)]
+    pub allocation_queue: AllocationQueueConfig,
+
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    #[serde(default)]
+    pub usage: UsageConfig,
+
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+
+    #[serde(default)]
+    pub sharing: SharingConfig,
+
+    /// Provider of the live market caps. Market caps in the input aren't checked if not specified.
+    #[serde(default)]
+    pub market_data: Option<MarketDataConfig>,
+
+    #[serde(default)]
+    pub remote_input: RemoteInputConfig,
+}
+
+impl ServerConfig {
+    /// Reads the server configuration from a TOML file.
+    pub fn load(path: &Path) -> Result<ServerConfig, Error> {
+        let content = fs::read_to_string(path).map_err(|e| Error {
+            code: "failed-to-read-server-config".to_string(),
+            message: format!("Did not manage to read server config file at {path:?}: {e}."),
+        })?;
+        toml::from_str(&content).map_err(|e| Error {
+            code: "invalid-server-config".to_string(),
+            message: format!("Failed to deserialize server config at {path:?}: {e}"),
+        })
+    }
+
+    /// Reads the server configuration from the environment, for the standalone mode that doesn't
+    /// need any files. See [ServerConfig::from_variables].
+    pub fn from_env() -> Result<ServerConfig, Error> {
+        ServerConfig::from_variables(|variable| std::env::var(variable).ok())
+    }
+
+    /// Reads the server configuration from the variables given by the lookup function. The whole
+    /// configuration can be given in TOML in [CONFIG_VARIABLE], otherwise the defaults of the
+    /// standalone mode apply. The bind address, the maximum request body size and the log level can
+    /// be overridden by their own variables, which is convenient in container orchestration.
+    pub fn from_variables(lookup: impl Fn(&str) -> Option<String>) -> Result<ServerConfig, Error> {
+        let invalid_variable = |variable: &str, e: &dyn std::fmt::Display| Error {
+            code: "invalid-server-config".to_string(),
+            message: format!("Failed to read server config from {variable}: {e}"),
+        };
+
+        let mut config: ServerConfig = match lookup(CONFIG_VARIABLE) {
+            Some(content) => {
+                toml::from_str(&content).map_err(|e| invalid_variable(CONFIG_VARIABLE, &e))?
+            }
+            None => {
+                let mut config: ServerConfig =
+                    toml::from_str("").expect("All settings have defaults.");
+                config.dropshot.bind_address = DEFAULT_STANDALONE_BIND_ADDRESS
+                    .parse()
+                    .expect("Default bind address is valid.");
+                config.dropshot.request_body_max_bytes = DEFAULT_STANDALONE_REQUEST_BODY_MAX_BYTES;
+                config
+            }
+        };
+
+        if let Some(bind_address) = lookup(BIND_ADDRESS_VARIABLE) {
+            config.dropshot.bind_address = bind_address
+                .parse()
+                .map_err(|e| invalid_variable(BIND_ADDRESS_VARIABLE, &e))?;
+        }
+        if let Some(max_bytes) = lookup(REQUEST_BODY_MAX_BYTES_VARIABLE) {
+            config.dropshot.request_body_max_bytes = max_bytes
+                .parse()
+                .map_err(|e| invalid_variable(REQUEST_BODY_MAX_BYTES_VARIABLE, &e))?;
+        }
+        if let Some(level) = lookup(LOG_LEVEL_VARIABLE) {
+            config.logging.level =
+                ConfigLoggingLevel::deserialize(toml::Value::String(level.to_lowercase()))
+                    .map_err(|e| invalid_variable(LOG_LEVEL_VARIABLE, &e))?;
+        }
+
+        Ok(config)
+    }
+}
+
+/// Logging of the server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LoggingConfig {
+    /// Messages below this level are not logged.
+    #[serde(default = "default_log_level")]
+    pub level: ConfigLoggingLevel,
+
+    /// Format and destination of the log. Defaults to bunyan, or to json in the standalone mode.
+    #[serde(default)]
+    pub format: Option<LogFormat>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: default_log_level(),
+            format: None,
+        }
+    }
+}
+
+/// Format and destination of the server log.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Bunyan records appended to the server.log file, as written by Dropshot.
+    Bunyan,
+    /// One JSON object per line on the standard output, e.g. for Loki or Elastic.
+    Json,
+    /// Human-readable lines on the standard error.
+    Terminal,
+}
+
+fn default_log_level() -> ConfigLoggingLevel {
+    ConfigLoggingLevel::Info
+}
+
+/// Input templates provided by the operator of the server, next to the ones stored with the API.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TemplatesConfig {
+    /// Path of a YAML file with the preset templates, given as a mapping from the template name
+    /// to the template. A template stored with the API takes precedence over a preset with the
+    /// same name.
+    #[serde(default)]
+    pub presets: Option<String>,
+}
+
+/// Read-only links to the recorded allocations for recipients without access to the API, signed
+/// such that they can't be forged or extended.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SharingConfig {
+    /// Environment variable with the secret signing the share links. A random secret is generated
+    /// if the variable isn't set, which invalidates the links when the server restarts.
+    #[serde(default = "default_secret_variable")]
+    pub secret_variable: String,
+
+    /// Validity of a share link if the request doesn't specify it.
+    #[serde(default = "default_share_expiry_hours")]
+    pub default_expiry_hours: u64,
+
+    /// Longest validity of a share link that can be requested.
+    #[serde(default = "default_max_share_expiry_hours")]
+    pub max_expiry_hours: u64,
+}
+
+impl Default for SharingConfig {
+    fn default() -> Self {
+        SharingConfig {
+            secret_variable: default_secret_variable(),
+            default_expiry_hours: default_share_expiry_hours(),
+            max_expiry_hours: default_max_share_expiry_hours(),
+        }
+    }
+}
+
+fn default_secret_variable() -> String {
+    "CHARLIE_SHARE_SECRET".to_string()
+}
+
+fn default_share_expiry_hours() -> u64 {
+    DEFAULT_SHARE_EXPIRY_HOURS
+}
+
+fn default_max_share_expiry_hours() -> u64 {
+    DEFAULT_MAX_SHARE_EXPIRY_HOURS
+}
+
+/// Provider of the live market caps, against which the market caps of the candidates are checked
+/// before allocating, to detect allocations on stale prices.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MarketDataConfig {
+    /// URL returning the market cap of a ticker as `{"market_cap": 123.4}`, with the `{ticker}`
+    /// placeholder replaced by the ticker, e.g. `https://example.com/market-cap/{ticker}`.
+    pub url_template: String,
+
+    /// Environment variable with the value of the Authorization header sent to the provider, e.g.
+    /// "Bearer <token>". No header is sent if not specified or if the variable isn't set.
+    #[serde(default)]
+    pub auth_header_variable: Option<String>,
+
+    /// Largest relative difference between the market cap in the input and the live one without
+    /// a warning, e.g. 0.1 for 10%.
+    #[serde(default = "default_max_market_cap_drift")]
+    pub max_market_cap_drift: f64,
+}
+
+fn default_max_market_cap_drift() -> f64 {
+    DEFAULT_MAX_MARKET_CAP_DRIFT
+}
+
+/// Fetching of the allocation input from HTTP(S) URLs by the server. The server makes requests to
+/// the hosts on behalf of the clients, so it only fetches from the hosts that are explicitly
+/// allowed, and not at all by default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RemoteInputConfig {
+    /// Hosts the input is fetched from, e.g. `docs.google.com`. Redirects are only followed to
+    /// these hosts, and a host starting with `*.` allows its subdomains, e.g. the
+    /// `*.googleusercontent.com` Google Sheets redirect their exports to.
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+
+    /// Timeout of fetching a document.
+    #[serde(default = "default_remote_input_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Largest size of a document in bytes. Larger documents are rejected.
+    #[serde(default = "default_remote_input_max_bytes")]
+    pub max_bytes: u64,
+}
+
+impl Default for RemoteInputConfig {
+    fn default() -> Self {
+        RemoteInputConfig {
+            allowed_hosts: vec![],
+            timeout_seconds: default_remote_input_timeout_seconds(),
+            max_bytes: default_remote_input_max_bytes(),
+        }
+    }
+}
+
+impl RemoteInputConfig {
+    /// Returns the limits of fetching a document by the server.
+    pub fn fetch_limits(&self) -> FetchLimits {
+        FetchLimits {
+            allowed_hosts: Some(self.allowed_hosts.clone()),
+            timeout: Duration::from_secs(self.timeout_seconds),
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+fn default_remote_input_timeout_seconds() -> u64 {
+    DEFAULT_REMOTE_INPUT_TIMEOUT_SECONDS
+}
+
+fn default_remote_input_max_bytes() -> u64 {
+    DEFAULT_REMOTE_INPUT_MAX_BYTES
+}
+
+/// Storage of the documents of the server, such as the stored candidates and the recorded
+/// allocations.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+
+    /// Path of the database file for the SQLite backend, or of the directory for the files
+    /// backend. Not used by the memory backend.
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// Encryption of the stored documents. Documents are stored in plain text if not specified.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+}
+
+/// Symmetric encryption of the stored documents at rest, with keys read from environment variables
+/// (e.g. populated from a key management service) such that they're never part of the
+/// configuration file. A key is 32 random bytes encoded in base64.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncryptionConfig {
+    /// Environment variable with the key used to encrypt the documents.
+    #[serde(default = "default_key_variable")]
+    pub key_variable: String,
+
+    /// Environment variables with keys used before a key rotation, with which documents that
+    /// weren't encrypted again yet are still decrypted.
+    #[serde(default)]
+    pub previous_key_variables: Vec<String>,
+
+    /// Whether documents in plain text, e.g. stored before the encryption was enabled, and
+    /// documents encrypted by earlier versions are read, such that they can be encrypted again.
+    /// Disabled by default, since otherwise anyone with access to the backend could plant
+    /// documents.
+    #[serde(default)]
+    pub accept_legacy_documents: bool,
+}
+
+fn default_key_variable() -> String {
+    "CHARLIE_STORAGE_KEY".to_string()
+}
+
+/// Backend of the storage:
+/// - Memory: Documents are kept in memory only and lost when the server restarts,
+/// - Sqlite: Documents are stored in an SQLite database,
+/// - Files: Each document is stored in its own JSON file, within a directory per collection.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    Memory,
+    Sqlite,
+    Files,
+}
+
+/// Monthly quotas of the API keys, given in the x-api-key header of the requests.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UsageConfig {
+    /// Quota shared by the anonymous requests and the API keys without their own quota, whose usage
+    /// is accounted together. No limits if not specified.
+    #[serde(default)]
+    pub default_quota: Option<Quota>,
+
+    /// Quotas of individual API keys.
+    #[serde(default)]
+    pub quotas: HashMap<String, Quota>,
+}
+
+/// Limits for executing allocation requests. Allocation is CPU-bound, so executing too many of
+/// them at the same time slows down all of them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AllocationQueueConfig {
+    /// Maximum number of allocations executing at the same time. Defaults to the number of CPUs.
+    #[serde(default = "default_max_concurrent_allocations")]
+    pub max_concurrent_allocations: usize,
+
+    /// Maximum number of allocations waiting for execution. Requests beyond this are rejected.
+    #[serde(default = "default_max_queued_allocations")]
+    pub max_queued_allocations: usize,
+}
+
+impl Default for AllocationQueueConfig {
+    fn default() -> Self {
+        AllocationQueueConfig {
+            max_concurrent_allocations: default_max_concurrent_allocations(),
+            max_queued_allocations: default_max_queued_allocations(),
+        }
+    }
+}
+
+fn default_max_concurrent_allocations() -> usize {
+    available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+fn default_max_queued_allocations() -> usize {
+    DEFAULT_MAX_QUEUED_ALLOCATIONS
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_server_config_without_allocation_queue() {
+        let config: ServerConfig = toml::from_str(
+            "
+            bind_address = \"127.0.0.1:8000\"
+            request_body_max_bytes = 1048576
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(config.dropshot.bind_address.to_string(), "127.0.0.1:8000");
+        assert_eq!(config.dropshot.request_body_max_bytes, 1048576);
+        assert_eq!(config.allocation_queue, AllocationQueueConfig::default());
+        assert_eq!(config.storage, StorageConfig::default());
+        assert_eq!(config.usage, UsageConfig::default());
+        assert_eq!(config.logging.level, ConfigLoggingLevel::Info);
+        assert_eq!(config.templates, TemplatesConfig::default());
+        assert_eq!(config.sharing, SharingConfig::default());
+        assert_eq!(config.market_data, None);
+    }
+
+    #[test]
+    fn test_deserialize_server_config_with_allocation_queue() {
+        let config: ServerConfig = toml::from_str(
+            "
+            bind_address = \"127.0.0.1:8000\"
+
+            [allocation_queue]
+            max_concurrent_allocations = 2
+            max_queued_allocations = 10
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(config.allocation_queue.max_concurrent_allocations, 2);
+        assert_eq!(config.allocation_queue.max_queued_allocations, 10);
+    }
+
+    #[test]
+    fn test_server_config_from_variables() {
+        let variables = |pairs: &[(&str, &str)]| {
+            let pairs: HashMap<String, String> = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            move |variable: &str| pairs.get(variable).cloned()
+        };
+
+        let config = ServerConfig::from_variables(variables(&[])).unwrap();
+        assert_eq!(config.dropshot.bind_address.to_string(), "0.0.0.0:8000");
+        assert_eq!(config.dropshot.request_body_max_bytes, 1048576);
+        assert_eq!(config.storage, StorageConfig::default());
+
+        let config = ServerConfig::from_variables(variables(&[
+            (
+                CONFIG_VARIABLE,
+                "[storage]\nbackend = \"files\"\npath = \"/data\"",
+            ),
+            (BIND_ADDRESS_VARIABLE, "0.0.0.0:9000"),
+            (LOG_LEVEL_VARIABLE, "DEBUG"),
+        ]))
+        .unwrap();
+        assert_eq!(config.dropshot.bind_address.to_string(), "0.0.0.0:9000");
+        assert_eq!(config.storage.backend, StorageBackend::Files);
+        assert_eq!(config.logging.level, ConfigLoggingLevel::Debug);
+
+        let error = |pairs: &[(&str, &str)]| {
+            ServerConfig::from_variables(variables(pairs))
+                .unwrap_err()
+                .code
+        };
+        assert_eq!(
+            "invalid-server-config",
+            error(&[(BIND_ADDRESS_VARIABLE, "localhost")])
+        );
+        assert_eq!(
+            "invalid-server-config",
+            error(&[(LOG_LEVEL_VARIABLE, "loud")])
+        );
+        assert_eq!("invalid-server-config", error(&[(CONFIG_VARIABLE, "[")]));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::config::ServerConfig;
+use crate::server_state::ServerState;
+use slog::{error, info, warn, Logger};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the configuration and the presets files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Watches the server configuration file and the presets file for changes, applying the settings
+/// that don't require a restart to the running server and logging what changed. A configuration
+/// that fails to load or to apply is logged and ignored until the files change again. Never
+/// returns, so it's meant to run alongside the server.
+pub async fn watch_server_config(
+    path: PathBuf,
+    mut config: ServerConfig,
+    state: &ServerState,
+    log: &Logger,
+) {
+    let mut last_modified = modification_times(&path, &config);
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let modified = modification_times(&path, &config);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        info!(log, "Server configuration or presets changed, reloading.");
+        let new_config = match ServerConfig::load(&path) {
+            Ok(c) => c,
+            Err(e) => {
+                error!(log, "Kept the current configuration. {}", e.message);
+                continue;
+            }
+        };
+        match state.reload(&config, &new_config) {
+            Ok(changes) => {
+                for change in &changes.applied {
+                    info!(log, "{change}");
+                }
+                for change in &changes.requiring_restart {
+                    warn!(log, "{change}");
+                }
+                if changes.applied.is_empty() && changes.requiring_restart.is_empty() {
+                    info!(log, "No settings changed.");
+                }
+                // The presets file may have changed too, so its time is taken again
+                last_modified = modification_times(&path, &new_config);
+                config = new_config;
+            }
+            Err(e) => error!(log, "Kept the current configuration. {}", e.message),
+        }
+    }
+}
+
+/// Modification times of the configuration file and the presets file, if any.
+fn modification_times(
+    path: &Path,
+    config: &ServerConfig,
+) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    (
+        modified(path),
+        config
+            .templates
+            .presets
+            .as_ref()
+            .and_then(|p| modified(Path::new(p))),
+    )
+}
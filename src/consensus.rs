@@ -0,0 +1,320 @@
+use crate::elicitation::{likelihood_mapping, parse_probability, LIKELIHOOD_MAPPING_KEY};
+use crate::model::errors::Error;
+use serde_yaml::Value;
+use std::collections::{BTreeSet, HashMap};
+
+/// Key of the optional weights of the analysts in the input document. All the analysts have the
+/// same weight if not given.
+pub const ANALYST_WEIGHTS_KEY: &str = "analyst_weights";
+
+/// Input document with the probability estimates of several analysts blended into consensus
+/// probabilities, together with the input documents with the estimates of each analyst alone.
+#[derive(Clone, Debug)]
+pub struct BlendedEstimates {
+    /// Input document with the consensus probabilities, in YAML format.
+    pub consensus: String,
+
+    /// Names of the analysts with the input documents with their own estimates, sorted by the
+    /// name. Empty if the document doesn't have estimates of several analysts.
+    pub analysts: Vec<(String, String)>,
+}
+
+/// Blends the probability estimates of several analysts in a YAML (or JSON) input document, given
+/// as a mapping from the analyst name to the probability instead of a single probability of a
+/// scenario, e.g. `probability: {alice: 0.3, bob: likely}`. The consensus probability is the
+/// average of the estimates weighted by the [ANALYST_WEIGHTS_KEY] of the document, where each
+/// estimate is a number or a qualitative likelihood. Every scenario with estimates must have an
+/// estimate of every analyst. Scenarios with a single probability are left as they are.
+pub fn blend_analyst_estimates(document: &str) -> Result<BlendedEstimates, Error> {
+    let invalid_document = |e: serde_yaml::Error| Error {
+        code: "invalid-input-document".to_string(),
+        message: format!("Input document is not valid YAML or JSON: {e}"),
+    };
+    let mut root: Value = serde_yaml::from_str(document).map_err(invalid_document)?;
+
+    let weights = match &mut root {
+        Value::Mapping(root_mapping) => root_mapping.remove(ANALYST_WEIGHTS_KEY),
+        _ => None,
+    };
+    let mapping = likelihood_mapping(root.get(LIKELIHOOD_MAPPING_KEY))?;
+
+    // Estimates of each scenario in the order of the document, with their ticker and thesis
+    let mut estimates: Vec<(String, HashMap<String, f64>)> = vec![];
+    visit_estimates(&mut root, &mut |scenario, probability| {
+        let scenario_estimates: HashMap<String, Value> =
+            serde_yaml::from_value(probability.clone()).map_err(|e| Error {
+                code: "invalid-analyst-estimates".to_string(),
+                message: format!(
+                    "Estimates of scenario {scenario} must map analyst names to probabilities: \
+                    {e}."
+                ),
+            })?;
+        let scenario_estimates = scenario_estimates
+            .into_iter()
+            .map(|(analyst, estimate)| {
+                let p = match estimate {
+                    Value::String(likelihood) => parse_probability(&likelihood, &mapping)?,
+                    other => other.as_f64().ok_or_else(|| Error {
+                        code: "invalid-analyst-estimates".to_string(),
+                        message: format!(
+                            "Estimate of {analyst} for scenario {scenario} must be a number or a \
+                            likelihood."
+                        ),
+                    })?,
+                };
+                Ok((analyst, p))
+            })
+            .collect::<Result<HashMap<String, f64>, Error>>()?;
+        estimates.push((scenario.to_string(), scenario_estimates));
+        Ok(())
+    })?;
+
+    if estimates.is_empty() {
+        return Ok(BlendedEstimates {
+            consensus: serde_yaml::to_string(&root).map_err(invalid_document)?,
+            analysts: vec![],
+        });
+    }
+
+    let weights = analyst_weights(weights, &estimates)?;
+    for (scenario, scenario_estimates) in &estimates {
+        let missing = weights
+            .keys()
+            .filter(|a| !scenario_estimates.contains_key(*a))
+            .cloned()
+            .collect::<BTreeSet<String>>();
+        if !missing.is_empty() {
+            return Err(Error {
+                code: "missing-analyst-estimates".to_string(),
+                message: format!(
+                    "Analysts {} didn't estimate the probability of scenario {scenario}. Every \
+                    analyst must estimate every scenario with estimates.",
+                    missing.into_iter().collect::<Vec<String>>().join(", ")
+                ),
+            });
+        }
+    }
+
+    // Replaces the estimates of every scenario with the probability given by the function
+    let replaced = |probability_of: &dyn Fn(&HashMap<String, f64>) -> f64| {
+        let mut document = root.clone();
+        let mut scenarios = estimates.iter();
+        visit_estimates(&mut document, &mut |_, probability| {
+            let (_, scenario_estimates) =
+                scenarios.next().expect("Scenarios are visited in order.");
+            *probability = Value::from(probability_of(scenario_estimates));
+            Ok(())
+        })?;
+        serde_yaml::to_string(&document).map_err(invalid_document)
+    };
+
+    let total_weight: f64 = weights.values().sum();
+    let consensus = replaced(&|scenario_estimates| {
+        weights
+            .iter()
+            .map(|(analyst, weight)| weight * scenario_estimates[analyst])
+            .sum::<f64>()
+            / total_weight
+    })?;
+    let mut analysts = weights.keys().cloned().collect::<Vec<String>>();
+    analysts.sort();
+    let analysts = analysts
+        .into_iter()
+        .map(|analyst| {
+            let document = replaced(&|scenario_estimates| scenario_estimates[&analyst])?;
+            Ok((analyst, document))
+        })
+        .collect::<Result<Vec<(String, String)>, Error>>()?;
+
+    Ok(BlendedEstimates {
+        consensus,
+        analysts,
+    })
+}
+
+/// Returns the weights of the analysts given in the document, or the same weight for all the
+/// analysts found among the estimates. Every analyst with estimates must have a weight.
+fn analyst_weights(
+    weights: Option<Value>,
+    estimates: &[(String, HashMap<String, f64>)],
+) -> Result<HashMap<String, f64>, Error> {
+    let analysts = estimates
+        .iter()
+        .flat_map(|(_, scenario_estimates)| scenario_estimates.keys().cloned())
+        .collect::<BTreeSet<String>>();
+    let Some(weights) = weights else {
+        return Ok(analysts.into_iter().map(|a| (a, 1.0)).collect());
+    };
+
+    let weights: HashMap<String, f64> = serde_yaml::from_value(weights).map_err(|e| Error {
+        code: "invalid-analyst-weights".to_string(),
+        message: format!("Analyst weights must map analyst names to weights: {e}."),
+    })?;
+    if weights.values().any(|w| *w < 0.0) || weights.values().sum::<f64>() <= 0.0 {
+        return Err(Error {
+            code: "invalid-analyst-weights".to_string(),
+            message: "Analyst weights must be non-negative and at least one of them positive."
+                .to_string(),
+        });
+    }
+    let unknown = analysts
+        .into_iter()
+        .filter(|a| !weights.contains_key(a))
+        .collect::<Vec<String>>();
+    if !unknown.is_empty() {
+        return Err(Error {
+            code: "unknown-analysts".to_string(),
+            message: format!(
+                "Analysts {} have estimates but no weight. Add them to {ANALYST_WEIGHTS_KEY}.",
+                unknown.join(", ")
+            ),
+        });
+    }
+
+    Ok(weights)
+}
+
+/// Recursively looks for scenarios of companies whose probability is given by the estimates of
+/// several analysts, and calls the function with the name of the scenario (ticker and thesis) and
+/// its probability.
+fn visit_estimates(
+    value: &mut Value,
+    visit: &mut dyn FnMut(&str, &mut Value) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match value {
+        Value::Mapping(m) => {
+            let ticker = m
+                .get("ticker")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if let Some(Value::Sequence(scenarios)) = m.get_mut("scenarios") {
+                for scenario in scenarios.iter_mut() {
+                    let thesis = scenario
+                        .get("thesis")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string();
+                    if let Some(probability @ Value::Mapping(_)) = scenario.get_mut("probability") {
+                        visit(&format!("\"{thesis}\" of {ticker}"), probability)?;
+                    }
+                }
+            }
+            for (_, v) in m.iter_mut() {
+                visit_estimates(v, visit)?;
+            }
+        }
+        Value::Sequence(s) => {
+            for v in s.iter_mut() {
+                visit_estimates(v, visit)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::TOLERANCE;
+    use crate::model::portfolio::AllocationInput;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_blend_analyst_estimates() {
+        let document = "
+            analyst_weights:
+              alice: 3
+              bob: 1
+            candidates:
+              - name: A
+                ticker: A
+                description: Business A
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability:
+                      alice: 0.2
+                      bob: coin flip
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability:
+                      alice: 0.8
+                      bob: 0.5
+              - name: B
+                ticker: B
+                description: Business B
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: 0.4
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability: 0.6
+        ";
+
+        let blended = blend_analyst_estimates(document).unwrap();
+        let consensus: AllocationInput = serde_yaml::from_str(&blended.consensus).unwrap();
+        assert_close!(
+            0.275,
+            consensus.candidates[0].scenarios[0].probability,
+            TOLERANCE
+        );
+        assert_close!(
+            0.725,
+            consensus.candidates[0].scenarios[1].probability,
+            TOLERANCE
+        );
+        assert_close!(
+            0.4,
+            consensus.candidates[1].scenarios[0].probability,
+            TOLERANCE
+        );
+
+        assert_eq!(
+            vec!["alice", "bob"],
+            blended
+                .analysts
+                .iter()
+                .map(|(a, _)| a.as_str())
+                .collect::<Vec<&str>>()
+        );
+        let bob: AllocationInput = serde_yaml::from_str(&blended.analysts[1].1).unwrap();
+        assert_close!(0.5, bob.candidates[0].scenarios[0].probability, TOLERANCE);
+        assert_close!(0.4, bob.candidates[1].scenarios[0].probability, TOLERANCE);
+
+        // Equal weights without the analyst weights
+        let without_weights = document.replace(
+            "analyst_weights:\n              alice: 3\n              bob: 1\n            ",
+            "",
+        );
+        let blended = blend_analyst_estimates(&without_weights).unwrap();
+        let consensus: AllocationInput = serde_yaml::from_str(&blended.consensus).unwrap();
+        assert_close!(
+            0.35,
+            consensus.candidates[0].scenarios[0].probability,
+            TOLERANCE
+        );
+
+        let error = |replaced: &str, replacement: &str| {
+            blend_analyst_estimates(&document.replace(replaced, replacement))
+                .unwrap_err()
+                .code
+        };
+        assert_eq!("unknown-analysts", error("bob: 1", "carol: 1"));
+        assert_eq!(
+            "missing-analyst-estimates",
+            error("bob: 0.5", "# Bob did not estimate")
+        );
+        assert_eq!("invalid-analyst-weights", error("alice: 3", "alice: -3"));
+        assert_eq!("unknown-likelihood", error("coin flip", "perhaps"));
+
+        // Documents without estimates are left as they are
+        let blended = blend_analyst_estimates("candidates: []").unwrap();
+        assert!(blended.analysts.is_empty());
+    }
+}
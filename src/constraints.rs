@@ -1,5 +1,9 @@
 pub mod constraint;
+pub mod fully_invested_constraint;
 pub mod long_only_constraint;
 pub mod maximum_capital_loss_constraint;
+pub mod maximum_group_allocation_constraint;
 pub mod maximum_individual_allocation_constraint;
+pub mod maximum_position_downside_constraint;
 pub mod maximum_total_leverage_constraint;
+pub mod minimum_individual_allocation_constraint;
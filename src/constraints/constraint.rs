@@ -6,7 +6,10 @@ use nalgebra::DVector;
 /// to solve, compared to the equality constraint which just adds an equation to the system.
 pub trait InequalityConstraint: Constraint {}
 
-/// TODO: Declare EqualityConstraint here when the time comes.
+/// [EqualityConstraint] extends the [Constraint] interface and is used for marking purposes only.
+/// Equality constraints are always active, so each of them just adds an equation to the system
+/// without increasing the number of problems to solve. The slack variable is always zero.
+pub trait EqualityConstraint: Constraint {}
 
 /// [Constraint] is a super-trait providing the interface for calculating matrix contributions when
 /// solving the Kelly allocation problem. The only thing needed for implementing a constraint is to
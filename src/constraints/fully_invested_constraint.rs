@@ -0,0 +1,66 @@
+use crate::constraints::constraint::{Constraint, EqualityConstraint};
+use crate::model::portfolio::Portfolio;
+use nalgebra::DVector;
+
+/// [FullyInvestedConstraint] requires that the fractions sum up to exactly one, i.e. that the
+/// portfolio holds neither cash nor leverage, or to exactly another net exposure, e.g. 1.3 for a
+/// mandate that must always be 30% levered. Candidates without net exposure, e.g. pair trades,
+/// don't count towards the sum.
+#[derive(Debug)]
+pub struct FullyInvestedConstraint {
+    /// Fraction indices of the candidates without net exposure.
+    market_neutral_indices: Vec<usize>,
+    /// Required sum of the fractions.
+    net_exposure: f64,
+}
+
+impl Default for FullyInvestedConstraint {
+    fn default() -> Self {
+        FullyInvestedConstraint {
+            market_neutral_indices: vec![],
+            net_exposure: 1.0,
+        }
+    }
+}
+
+impl FullyInvestedConstraint {
+    /// Returns the constraint requiring the fractions to sum up to the given net exposure instead
+    /// of one.
+    pub fn with_net_exposure(self, net_exposure: f64) -> FullyInvestedConstraint {
+        FullyInvestedConstraint {
+            net_exposure,
+            ..self
+        }
+    }
+
+    /// Returns the constraint where the candidates with the given fraction indices don't count
+    /// towards the invested capital, since they have no net exposure.
+    pub fn excluding(self, market_neutral_indices: &[usize]) -> FullyInvestedConstraint {
+        FullyInvestedConstraint {
+            market_neutral_indices: market_neutral_indices.to_vec(),
+            ..self
+        }
+    }
+}
+
+impl EqualityConstraint for FullyInvestedConstraint {}
+
+impl Constraint for FullyInvestedConstraint {
+    fn d_constraint_d_fractions(&self, portfolio: &Portfolio) -> DVector<f64> {
+        let mut d_constraint = DVector::from_element(portfolio.companies.len(), 1.0);
+        for &i in &self.market_neutral_indices {
+            d_constraint[i] = 0.0;
+        }
+        d_constraint
+    }
+
+    fn function_value(&self, portfolio: &Portfolio, slack_variable: f64) -> f64 {
+        self.d_constraint_d_fractions(portfolio)
+            .iter()
+            .enumerate()
+            .map(|(c_i, dc_df)| dc_df * portfolio.companies[c_i].fraction)
+            .sum::<f64>()
+            + slack_variable
+            - self.net_exposure
+    }
+}
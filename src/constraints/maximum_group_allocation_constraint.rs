@@ -0,0 +1,63 @@
+use crate::constraints::constraint::{Constraint, InequalityConstraint};
+use crate::model::portfolio::Portfolio;
+use nalgebra::DVector;
+
+/// [MaximumGroupAllocationConstraint] puts a constraint (upper bound) on the sum of fractions
+/// allocated to a group of companies, e.g. two share classes of the same business.
+#[derive(Debug)]
+pub struct MaximumGroupAllocationConstraint {
+    /// Indices representing the companies (i.e. the fractions) in the group.
+    fraction_indices: Vec<usize>,
+
+    /// Maximum allocation fraction for the whole group.
+    max_allocation_fraction: f64,
+}
+
+impl MaximumGroupAllocationConstraint {
+    /// Create a new [MaximumGroupAllocationConstraint] and perform some sanity checks.
+    pub fn new(
+        fraction_indices: Vec<usize>,
+        max_allocation_fraction: f64,
+        n_companies: usize,
+    ) -> MaximumGroupAllocationConstraint {
+        if max_allocation_fraction < 0.0 {
+            panic!(
+                "Maximum group allocation fraction must be positive. You provided \
+                {max_allocation_fraction}."
+            )
+        }
+
+        if let Some(&fraction_index) = fraction_indices.iter().find(|&&i| i > n_companies - 1) {
+            panic!(
+                "You have {n_companies} companies, but provided company ID {fraction_index}. \
+            The company (fraction) ID must be smaller than the number of companies."
+            )
+        }
+
+        MaximumGroupAllocationConstraint {
+            fraction_indices,
+            max_allocation_fraction,
+        }
+    }
+}
+
+impl InequalityConstraint for MaximumGroupAllocationConstraint {}
+
+impl Constraint for MaximumGroupAllocationConstraint {
+    fn d_constraint_d_fractions(&self, portfolio: &Portfolio) -> DVector<f64> {
+        let mut derivative: DVector<f64> = DVector::zeros(portfolio.companies.len());
+        self.fraction_indices
+            .iter()
+            .for_each(|&i| derivative[i] = 1.0);
+        derivative
+    }
+
+    fn function_value(&self, portfolio: &Portfolio, slack_variable: f64) -> f64 {
+        self.fraction_indices
+            .iter()
+            .map(|&i| portfolio.companies[i].fraction)
+            .sum::<f64>()
+            + slack_variable
+            - self.max_allocation_fraction
+    }
+}
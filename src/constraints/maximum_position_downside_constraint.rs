@@ -0,0 +1,61 @@
+use crate::constraints::constraint::{Constraint, InequalityConstraint};
+use crate::model::portfolio::Portfolio;
+use nalgebra::DVector;
+
+/// [MaximumPositionDownsideConstraint] puts a constraint (risk budget) on the probability weighted
+/// worst-case return of a single position, i.e. the fraction of the company times the probability
+/// weighted return of its worst-case scenario must not be lower than minus the budget. Since the
+/// worst-case return of a candidate is negative, this is an upper bound of its fraction.
+#[derive(Debug)]
+pub struct MaximumPositionDownsideConstraint {
+    /// Index representing the company (i.e. the fraction) it constrains.
+    fraction_index: usize,
+
+    /// Maximum probability weighted loss of capital in the worst-case scenario of the company, e.g.
+    /// 0.02 for 2% of capital.
+    budget: f64,
+}
+
+impl MaximumPositionDownsideConstraint {
+    /// Create a new [MaximumPositionDownsideConstraint] and perform some sanity checks.
+    pub fn new(
+        fraction_index: usize,
+        budget: f64,
+        n_companies: usize,
+    ) -> MaximumPositionDownsideConstraint {
+        if budget <= 0.0 {
+            panic!("Position downside budget must be positive. You provided {budget}.")
+        }
+
+        if fraction_index > n_companies - 1 {
+            panic!(
+                "You have {n_companies} companies, but provided company ID {fraction_index}. \
+            The company (fraction) ID must be smaller than the number of companies."
+            )
+        }
+
+        MaximumPositionDownsideConstraint {
+            fraction_index,
+            budget,
+        }
+    }
+}
+
+impl InequalityConstraint for MaximumPositionDownsideConstraint {}
+
+impl Constraint for MaximumPositionDownsideConstraint {
+    fn d_constraint_d_fractions(&self, portfolio: &Portfolio) -> DVector<f64> {
+        let mut derivative: DVector<f64> = DVector::zeros(portfolio.companies.len());
+        derivative[self.fraction_index] = -portfolio.companies[self.fraction_index]
+            .company
+            .worst_probability_weighted_return();
+        derivative
+    }
+
+    fn function_value(&self, portfolio: &Portfolio, slack_variable: f64) -> f64 {
+        self.d_constraint_d_fractions(portfolio)[self.fraction_index]
+            * portfolio.companies[self.fraction_index].fraction
+            + slack_variable
+            - self.budget
+    }
+}
@@ -7,6 +7,8 @@ use nalgebra::DVector;
 pub struct MaximumTotalLeverageConstraint {
     /// Maximum leverage ratio, e.g. 0.0 means no leverage, while 1.0 means 100% leverage.
     max_leverage_ratio: f64,
+    /// Fraction indices of the candidates without net exposure, e.g. pair trades.
+    market_neutral_indices: Vec<usize>,
 }
 
 impl MaximumTotalLeverageConstraint {
@@ -20,7 +22,19 @@ impl MaximumTotalLeverageConstraint {
             )
         }
 
-        MaximumTotalLeverageConstraint { max_leverage_ratio }
+        MaximumTotalLeverageConstraint {
+            max_leverage_ratio,
+            market_neutral_indices: vec![],
+        }
+    }
+
+    /// Returns the constraint where the candidates with the given fraction indices don't count
+    /// towards the leverage, since they have no net exposure.
+    pub fn excluding(self, market_neutral_indices: &[usize]) -> MaximumTotalLeverageConstraint {
+        MaximumTotalLeverageConstraint {
+            market_neutral_indices: market_neutral_indices.to_vec(),
+            ..self
+        }
     }
 }
 
@@ -28,7 +42,11 @@ impl InequalityConstraint for MaximumTotalLeverageConstraint {}
 
 impl Constraint for MaximumTotalLeverageConstraint {
     fn d_constraint_d_fractions(&self, portfolio: &Portfolio) -> DVector<f64> {
-        DVector::from_element(portfolio.companies.len(), 1.0)
+        let mut d_constraint = DVector::from_element(portfolio.companies.len(), 1.0);
+        for &i in &self.market_neutral_indices {
+            d_constraint[i] = 0.0;
+        }
+        d_constraint
     }
 
     fn function_value(&self, portfolio: &Portfolio, slack_variable: f64) -> f64 {
@@ -0,0 +1,59 @@
+use crate::constraints::constraint::{Constraint, InequalityConstraint};
+use crate::model::portfolio::Portfolio;
+use nalgebra::DVector;
+
+/// [MinimumIndividualAllocationConstraint] puts a constraint (lower bound) on the amount of assets
+/// to put into a single company. Together with forcing the allocation to zero, it makes the
+/// allocation semi-continuous, i.e. either zero or at least the minimum, which is decided by the
+/// allocator for each company whose allocation ends up below the minimum.
+#[derive(Debug)]
+pub struct MinimumIndividualAllocationConstraint {
+    /// Index representing the company (i.e. the fraction) it constrains.
+    fraction_index: usize,
+
+    /// Minimum allocation fraction for this company.
+    min_allocation_fraction: f64,
+}
+
+impl MinimumIndividualAllocationConstraint {
+    /// Create a new [MinimumIndividualAllocationConstraint] and perform some sanity checks.
+    pub fn new(
+        fraction_index: usize,
+        min_allocation_fraction: f64,
+        n_companies: usize,
+    ) -> MinimumIndividualAllocationConstraint {
+        if min_allocation_fraction < 0.0 {
+            panic!(
+                "Minimum allocation fraction must not be negative. You provided \
+                {min_allocation_fraction}."
+            )
+        }
+
+        if fraction_index > n_companies - 1 {
+            panic!(
+                "You have {n_companies} companies, but provided company ID {fraction_index}. \
+            The company (fraction) ID must be smaller than the number of companies."
+            )
+        }
+
+        MinimumIndividualAllocationConstraint {
+            fraction_index,
+            min_allocation_fraction,
+        }
+    }
+}
+
+impl InequalityConstraint for MinimumIndividualAllocationConstraint {}
+
+impl Constraint for MinimumIndividualAllocationConstraint {
+    fn d_constraint_d_fractions(&self, portfolio: &Portfolio) -> DVector<f64> {
+        let mut derivative: DVector<f64> = DVector::zeros(portfolio.companies.len());
+        derivative[self.fraction_index] = -1.0;
+        derivative
+    }
+
+    fn function_value(&self, portfolio: &Portfolio, slack_variable: f64) -> f64 {
+        self.min_allocation_fraction - portfolio.companies[self.fraction_index].fraction
+            + slack_variable
+    }
+}
@@ -0,0 +1,138 @@
+use crate::model::company::{Company, TOLERANCE};
+use crate::model::responses::CandidateDataQuality;
+use crate::validation::result::{Severity, ValidationResult};
+use crate::validation::validate::Validate;
+use chrono::NaiveDate;
+
+/// Factor by which each validation warning of a candidate lowers its data quality score
+const WARNING_FACTOR: f64 = 0.75;
+
+/// Probability of the less likely side among the upside and downside scenarios from which on the
+/// scenarios of a candidate are considered balanced
+const BALANCED_SIDE_PROBABILITY: f64 = 0.2;
+
+/// Coarse probability steps, from the coarsest, e.g. all probabilities being multiples of 0.25
+/// suggests a rough guess rather than a considered estimate
+const PROBABILITY_STEPS: [f64; 4] = [0.5, 0.25, 0.1, 0.05];
+
+/// Number of days after the last review after which the staleness halves the data quality score
+const STALENESS_HALF_LIFE_DAYS: f64 = 180.0;
+
+/// Score below which the data of a candidate is flagged as weak
+const WEAK_DATA_QUALITY_SCORE: f64 = 0.5;
+
+/// Returns the data quality of the candidate on the given day, scoring how much its allocation can
+/// be trusted from its validation warnings, the balance between its upside and downside scenarios,
+/// the granularity of its probabilities and the time since its last review. The score is the
+/// product of the scores of the components, each between 0 and 1.
+pub fn data_quality(company: &Company, today: NaiveDate) -> CandidateDataQuality {
+    let warnings: Vec<String> = company
+        .validate()
+        .into_iter()
+        .filter_map(|v| match v {
+            ValidationResult::PROBLEM(p) if p.severity == Severity::WARNING => Some(p.code),
+            _ => None,
+        })
+        .collect();
+
+    let (upside_probability, downside_probability) =
+        company.scenarios.iter().fold((0.0, 0.0), |(up, down), s| {
+            let r = s.scenario_return(company.market_cap);
+            if r > 0.0 {
+                (up + s.probability, down)
+            } else if r < 0.0 {
+                (up, down + s.probability)
+            } else {
+                (up, down)
+            }
+        });
+    let scenario_balance = (f64::min(upside_probability, downside_probability)
+        / BALANCED_SIDE_PROBABILITY)
+        .clamp(0.0, 1.0);
+
+    let probability_step = PROBABILITY_STEPS.into_iter().find(|step| {
+        company.scenarios.iter().all(|s| {
+            let multiple = s.probability / step;
+            (multiple - multiple.round()).abs() < TOLERANCE.sqrt()
+        })
+    });
+
+    let days_since_review = company.last_reviewed.map(|d| (today - d).num_days().max(0));
+
+    let score = WARNING_FACTOR.powi(warnings.len() as i32)
+        * scenario_balance
+        * (1.0 - probability_step.unwrap_or(0.0))
+        * days_since_review.map_or(1.0, |d| 0.5_f64.powf(d as f64 / STALENESS_HALF_LIFE_DAYS));
+
+    CandidateDataQuality {
+        ticker: company.ticker.clone(),
+        score,
+        is_weak: score < WEAK_DATA_QUALITY_SCORE,
+        warnings,
+        scenario_balance,
+        probability_step,
+        days_since_review,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::scenario::Scenario;
+    use crate::utils::assert_close;
+
+    fn company(probabilities: &[(f64, f64)], last_reviewed: Option<NaiveDate>) -> Company {
+        Company {
+            name: "A".to_string(),
+            ticker: "A".to_string(),
+            description: "A".to_string(),
+            market_cap: 100.0,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed,
+            scenarios: probabilities
+                .iter()
+                .enumerate()
+                .map(|(i, &(intrinsic_value, probability))| Scenario {
+                    thesis: format!("Scenario {i}"),
+                    intrinsic_value,
+                    probability,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_data_quality() {
+        let today = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+
+        let considered = data_quality(&company(&[(50.0, 0.3), (180.0, 0.7)], None), today);
+        assert!(considered.warnings.is_empty());
+        assert_close!(1.0, considered.scenario_balance, 1e-12);
+        assert_eq!(Some(0.1), considered.probability_step);
+        assert_eq!(None, considered.days_since_review);
+        assert_close!(0.9, considered.score, 1e-12);
+        assert!(!considered.is_weak);
+
+        // Unlikely downside, reviewed half a year ago
+        let reviewed = NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+        let rough = data_quality(
+            &company(&[(90.0, 0.1), (200.0, 0.9)], Some(reviewed)),
+            today,
+        );
+        assert_close!(0.5, rough.scenario_balance, 1e-12);
+        assert_eq!(Some(180), rough.days_since_review);
+        assert_close!(0.5 * 0.9 * 0.5, rough.score, 1e-12);
+        assert!(rough.is_weak);
+
+        // No downside scenario and a coarse probability
+        let optimistic = data_quality(&company(&[(150.0, 0.5), (100.0, 0.5)], None), today);
+        assert_eq!(
+            vec!["company-with-no-downside-scenario"],
+            optimistic.warnings
+        );
+        assert_eq!(Some(0.5), optimistic.probability_step);
+        assert_close!(0.0, optimistic.score, 1e-12);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::model::company::TOLERANCE;
+use crate::model::errors::Error;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use itertools::Itertools;
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+/// Key of the optional mapping from likelihood words to probabilities in the input document,
+/// which extends (or overrides) the default mapping. The words map to probabilities between 0 and 1
+/// rather than odds, which are given in favour (e.g. "3:1" for 0.75) wherever they're accepted.
+pub const LIKELIHOOD_MAPPING_KEY: &str = "likelihood_mapping";
+
+/// Default mapping from likelihood words to probabilities.
+const DEFAULT_LIKELIHOOD_MAPPING: [(&str, f64); 12] = [
+    ("almost certain", 0.95),
+    ("very likely", 0.85),
+    ("likely", 0.7),
+    ("probable", 0.7),
+    ("coin flip", 0.5),
+    ("even odds", 0.5),
+    ("possible", 0.4),
+    ("unlikely", 0.25),
+    ("improbable", 0.25),
+    ("very unlikely", 0.1),
+    ("remote", 0.05),
+    ("almost impossible", 0.02),
+];
+
+/// Returns the default mapping from likelihood words to probabilities.
+pub fn default_likelihood_mapping() -> HashMap<String, f64> {
+    DEFAULT_LIKELIHOOD_MAPPING
+        .iter()
+        .map(|(word, p)| (word.to_string(), *p))
+        .collect()
+}
+
+/// Returns the default mapping from likelihood words to probabilities, extended (or overridden)
+/// with the custom mapping given under the [LIKELIHOOD_MAPPING_KEY] of an input document.
+pub fn likelihood_mapping(custom_mapping: Option<&Value>) -> Result<HashMap<String, f64>, Error> {
+    let mut mapping = default_likelihood_mapping();
+    if let Some(custom_mapping) = custom_mapping {
+        let custom_mapping: HashMap<String, f64> = serde_yaml::from_value(custom_mapping.clone())
+            .map_err(|e| Error {
+            code: "invalid-likelihood-mapping".to_string(),
+            message: format!(
+                "Likelihood mapping must map words to probabilities: {e}. Check your input."
+            ),
+        })?;
+        mapping.extend(
+            custom_mapping
+                .into_iter()
+                .map(|(word, p)| (word.trim().to_lowercase(), p)),
+        );
+    }
+
+    Ok(mapping)
+}
+
+/// Converts a qualitative likelihood into a probability. The likelihood may be a word from the
+/// mapping (e.g. "coin flip"), odds in favour (e.g. "3:1" for 0.75, unlike the betting convention
+/// of odds against), a percentage (e.g. "30%") or a number. Words are matched irrespective of case
+/// and surrounding whitespace. Probabilities that aren't finite or outside of [0, 1] are rejected.
+pub fn parse_probability(likelihood: &str, mapping: &HashMap<String, f64>) -> Result<f64, Error> {
+    let p = parse_likelihood(likelihood, mapping)?;
+    if !p.is_finite() || !(0.0..=1.0).contains(&p) {
+        return Err(Error {
+            code: "likelihood-out-of-bounds".to_string(),
+            message: format!(
+                "Likelihood \"{likelihood}\" converts into the probability {p}, which is not \
+                between 0 and 1. Check your input."
+            ),
+        });
+    }
+
+    Ok(p)
+}
+
+/// Converts a qualitative likelihood into a number, see [parse_probability].
+fn parse_likelihood(likelihood: &str, mapping: &HashMap<String, f64>) -> Result<f64, Error> {
+    let normalized = likelihood.trim().to_lowercase();
+    let unknown_likelihood = || Error {
+        code: "unknown-likelihood".to_string(),
+        message: format!(
+            "Did not manage to convert likelihood \"{likelihood}\" into a probability. Use a \
+            number, a percentage (e.g. 30%), odds in favour (e.g. 3:1 for 75%) or one of the \
+            words: {}.",
+            mapping
+                .keys()
+                .map(|k| k.as_str())
+                .sorted()
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    };
+
+    if let Some(p) = mapping.get(&normalized) {
+        return Ok(*p);
+    }
+
+    if let Some((in_favour, against)) = normalized.split_once(':') {
+        let in_favour: f64 = in_favour.trim().parse().map_err(|_| unknown_likelihood())?;
+        let against: f64 = against.trim().parse().map_err(|_| unknown_likelihood())?;
+        if in_favour < 0.0 || against < 0.0 || in_favour + against <= 0.0 {
+            return Err(unknown_likelihood());
+        }
+        return Ok(in_favour / (in_favour + against));
+    }
+
+    if let Some(percentage) = normalized.strip_suffix('%') {
+        let percentage: f64 = percentage
+            .trim()
+            .parse()
+            .map_err(|_| unknown_likelihood())?;
+        return Ok(percentage / 100.0);
+    }
+
+    normalized.parse().map_err(|_| unknown_likelihood())
+}
+
+/// Replaces the qualitative likelihoods of all scenarios in the YAML (or JSON) input document with
+/// numeric probabilities, using the default mapping extended with the mapping under the
+/// [LIKELIHOOD_MAPPING_KEY] of the document. Scenarios are found in the "scenarios" of any company
+/// in the document, so both allocation inputs and portfolios are supported.
+///
+/// Probabilities of a company with at least one converted likelihood are normalized to sum up to 1
+/// (or to at most 1 for companies with an automatic status quo scenario), with a warning. Returns
+/// the converted document in YAML format together with the warnings.
+pub fn elicit_probabilities(document: &str) -> Result<(String, Vec<ValidationResult>), Error> {
+    let invalid_document = |e: serde_yaml::Error| Error {
+        code: "invalid-input-document".to_string(),
+        message: format!("Input document is not valid YAML or JSON: {e}"),
+    };
+    let mut root: Value = serde_yaml::from_str(document).map_err(invalid_document)?;
+
+    let custom_mapping = match &mut root {
+        Value::Mapping(root_mapping) => root_mapping.remove(LIKELIHOOD_MAPPING_KEY),
+        _ => None,
+    };
+    let mapping = likelihood_mapping(custom_mapping.as_ref())?;
+
+    let mut warnings: Vec<ValidationResult> = vec![];
+    elicit_in_value(&mut root, &mapping, &mut warnings)?;
+
+    let converted = serde_yaml::to_string(&root).map_err(invalid_document)?;
+    Ok((converted, warnings))
+}
+
+/// Recursively looks for companies (mappings with scenarios) and converts their likelihoods.
+fn elicit_in_value(
+    value: &mut Value,
+    mapping: &HashMap<String, f64>,
+    warnings: &mut Vec<ValidationResult>,
+) -> Result<(), Error> {
+    match value {
+        Value::Mapping(m) => {
+            if let Some(Value::Sequence(_)) = m.get("scenarios") {
+                elicit_company(m, mapping, warnings)?;
+            }
+            for (_, v) in m.iter_mut() {
+                elicit_in_value(v, mapping, warnings)?;
+            }
+        }
+        Value::Sequence(s) => {
+            for v in s.iter_mut() {
+                elicit_in_value(v, mapping, warnings)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Converts the likelihoods of the scenarios of a single company and normalizes its probabilities
+/// if any likelihood was converted.
+fn elicit_company(
+    company: &mut serde_yaml::Mapping,
+    mapping: &HashMap<String, f64>,
+    warnings: &mut Vec<ValidationResult>,
+) -> Result<(), Error> {
+    let auto_status_quo = company
+        .get("auto_status_quo")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let ticker = company
+        .get("ticker")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let Some(Value::Sequence(scenarios)) = company.get_mut("scenarios") else {
+        return Ok(());
+    };
+
+    let mut n_converted = 0;
+    for scenario in scenarios.iter_mut() {
+        if let Some(Value::String(likelihood)) = scenario.get("probability") {
+            let p = parse_probability(likelihood, mapping)?;
+            scenario["probability"] = Value::from(p);
+            n_converted += 1;
+        }
+    }
+    if n_converted == 0 {
+        return Ok(());
+    }
+
+    let sum: f64 = scenarios
+        .iter()
+        .filter_map(|s| s.get("probability").and_then(Value::as_f64))
+        .sum();
+    let needs_normalization = sum > 1.0 + TOLERANCE || (!auto_status_quo && sum < 1.0 - TOLERANCE);
+    if needs_normalization && sum > 0.0 {
+        for scenario in scenarios.iter_mut() {
+            if let Some(p) = scenario.get("probability").and_then(Value::as_f64) {
+                scenario["probability"] = Value::from(p / sum);
+            }
+        }
+        warnings.push(ValidationResult::PROBLEM(Problem {
+            code: "elicited-probabilities-normalized".to_string(),
+            message: format!(
+                "Probabilities of scenarios for company {ticker} converted from likelihoods sum up \
+                to {sum}, so they were normalized to sum up to 1."
+            ),
+            severity: Severity::WARNING,
+            suggestion: None,
+        }));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::portfolio::AllocationInput;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_parse_probability() {
+        let mapping = default_likelihood_mapping();
+
+        assert_close!(
+            0.5,
+            parse_probability(" Coin Flip ", &mapping).unwrap(),
+            TOLERANCE
+        );
+        assert_close!(0.75, parse_probability("3:1", &mapping).unwrap(), TOLERANCE);
+        assert_close!(0.3, parse_probability("30%", &mapping).unwrap(), TOLERANCE);
+        assert_close!(
+            0.42,
+            parse_probability("0.42", &mapping).unwrap(),
+            TOLERANCE
+        );
+        assert_eq!(
+            "unknown-likelihood",
+            parse_probability("perhaps", &mapping).unwrap_err().code
+        );
+        assert_eq!(
+            "unknown-likelihood",
+            parse_probability("0:0", &mapping).unwrap_err().code
+        );
+        for likelihood in ["150%", "-0.2", "1.5", "nan", "inf", "-1:2"] {
+            assert!(parse_probability(likelihood, &mapping).is_err());
+        }
+        assert_eq!(
+            "likelihood-out-of-bounds",
+            parse_probability("150%", &mapping).unwrap_err().code
+        );
+
+        // Allowed words are listed in the same order every time
+        let message = parse_probability("perhaps", &mapping).unwrap_err().message;
+        assert!(message.ends_with(
+            "words: almost certain, almost impossible, coin flip, even odds, improbable, likely, \
+            possible, probable, remote, unlikely, very likely, very unlikely."
+        ));
+    }
+
+    #[test]
+    fn test_elicit_probabilities() {
+        let document = "
+            likelihood_mapping:
+              Perhaps: 0.3
+            candidates:
+              - name: A
+                ticker: A
+                description: Business A
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: perhaps
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability: likely
+              - name: B
+                ticker: B
+                description: Business B
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: 1:3
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability: 0.75
+        ";
+
+        let (converted, warnings) = elicit_probabilities(document).unwrap();
+        let input: AllocationInput = serde_yaml::from_str(&converted).unwrap();
+
+        // Probabilities of both companies sum up to one, so no normalization is needed
+        assert_close!(0.3, input.candidates[0].scenarios[0].probability, TOLERANCE);
+        assert_close!(0.7, input.candidates[0].scenarios[1].probability, TOLERANCE);
+        assert_close!(
+            0.25,
+            input.candidates[1].scenarios[0].probability,
+            TOLERANCE
+        );
+        assert!(warnings.is_empty());
+
+        // Probabilities of B sum up to 1.1 and are normalized
+        let (converted, warnings) =
+            elicit_probabilities(&document.replace("0.75", "very likely")).unwrap();
+        let input: AllocationInput = serde_yaml::from_str(&converted).unwrap();
+        assert_close!(
+            0.25 / 1.1,
+            input.candidates[1].scenarios[0].probability,
+            TOLERANCE
+        );
+        assert_eq!(1, warnings.len());
+        assert!(matches!(
+            &warnings[0],
+            ValidationResult::PROBLEM(p) if p.code == "elicited-probabilities-normalized"
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::analysis::Outcome;
+use crate::analysis::{all_outcomes, analytical_moments, cumulative_probability_of_loss};
+use crate::analysis::{compound_growth, expected_log_growth};
+use crate::analysis::{conditional_value_at_risk, value_at_risk, VALUE_AT_RISK_CONFIDENCE};
+use crate::analysis::{diversification, expected_return, numerical_errors, worst_case_outcome};
+use crate::kelly_allocation::{KellyAllocator, KellySolution, MAX_ITER, MIN_SCENARIO_PROBABILITY};
+use crate::model::company::Company;
+use crate::model::errors::Error;
+use crate::model::portfolio::{Portfolio, PortfolioCompany};
+use crate::model::responses::{AnalysisResult, TickerAndFraction};
+use itertools::Itertools;
+use slog::{info, warn, Logger};
+
+/// Allocation and analysis of a fixed set of candidates for embedding the library in-process, e.g.
+/// for parameter sweeps. The outcomes of the candidates are enumerated once when creating the
+/// engine and reused by every allocation and analysis, while the free functions (e.g.
+/// [crate::allocate]) enumerate them again on every call.
+pub struct CharlieEngine<'a> {
+    candidates: Vec<Company>,
+    outcomes: Vec<Outcome>,
+    logger: &'a Logger,
+}
+
+impl<'a> CharlieEngine<'a> {
+    /// Returns the engine for the candidates, with their implicit status quo scenarios made
+    /// explicit and the scenarios with negligible probabilities removed, as when allocating.
+    pub fn new(candidates: Vec<Company>, logger: &'a Logger) -> Result<CharlieEngine<'a>, Error> {
+        let candidates = candidates
+            .iter()
+            .map(|c| {
+                let (preconditioned, _) = c
+                    .with_status_quo_scenario()
+                    .without_negligible_scenarios(MIN_SCENARIO_PROBABILITY);
+                preconditioned
+            })
+            .collect_vec();
+        let portfolio = portfolio_with_fractions(&candidates, |_| 0.0);
+        let outcomes = all_outcomes(&portfolio)?;
+        info!(
+            logger,
+            "Created engine for {} candidates with {} outcomes.",
+            candidates.len(),
+            outcomes.len()
+        );
+
+        Ok(CharlieEngine {
+            candidates,
+            outcomes,
+            logger,
+        })
+    }
+
+    /// Returns the candidates of the engine, in the order of the fractions of the allocations.
+    pub fn candidates(&self) -> &[Company] {
+        &self.candidates
+    }
+
+    /// Allocates to the candidates with the constraints added by the given function to the
+    /// allocator, e.g. `|a| a.with_long_only_constraints(n)` where n is the number of candidates.
+    pub fn reallocate_with(
+        &self,
+        constraints: impl FnOnce(KellyAllocator<'a>) -> KellyAllocator<'a>,
+    ) -> Result<KellySolution, Error> {
+        constraints(KellyAllocator::new(self.logger, MAX_ITER))
+            .solve_with_outcomes(self.candidates.clone(), &self.outcomes)
+    }
+
+    /// Analyzes the portfolio of the candidates with the given weights, where candidates without a
+    /// weight aren't held. Returns an error if a weight is given for an unknown ticker.
+    pub fn analyze_weights(
+        &mut self,
+        weights: &[TickerAndFraction],
+    ) -> Result<AnalysisResult, Error> {
+        let unknown_tickers = weights
+            .iter()
+            .filter(|w| !self.candidates.iter().any(|c| c.ticker == w.ticker))
+            .map(|w| w.ticker.as_str())
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            return Err(Error {
+                code: "unknown-tickers-in-weights".to_string(),
+                message: format!(
+                    "Weights are given for tickers {}, which are not among the candidates.",
+                    unknown_tickers.join(", ")
+                ),
+            });
+        }
+
+        let portfolio = portfolio_with_fractions(&self.candidates, |c| {
+            weights
+                .iter()
+                .find(|w| w.ticker == c.ticker)
+                .map_or(0.0, |w| w.fraction)
+        });
+        for outcome in self.outcomes.iter_mut() {
+            outcome.weighted_return = portfolio
+                .companies
+                .iter()
+                .map(|pc| pc.fraction * outcome.company_returns[&pc.company.ticker])
+                .sum();
+        }
+        if self.outcomes.iter().any(|o| 1.0 + o.weighted_return <= 0.0) {
+            warn!(self.logger, "The weights may lose all capital.");
+        }
+
+        let diversification = diversification(&portfolio);
+        let log_growth = expected_log_growth(&self.outcomes);
+        Ok(AnalysisResult {
+            worst_case_outcome: worst_case_outcome(&portfolio, self.logger),
+            cumulative_probability_of_loss: Some(cumulative_probability_of_loss(
+                &self.outcomes,
+                self.logger,
+            )),
+            expected_return: expected_return(&portfolio, self.logger),
+            expected_log_growth_rate: Some(log_growth),
+            compound_growth: compound_growth(log_growth, None).ok(),
+            variance_of_return: analytical_moments(&portfolio).variance,
+            effective_number_of_positions: diversification.effective_number_of_positions,
+            max_weight: diversification.max_weight,
+            gross_exposure: diversification.gross_exposure,
+            net_exposure: diversification.net_exposure,
+            events: vec![],
+            inflation_adjusted: None,
+            sampled_statistics: None,
+            value_at_risk_95: Some(value_at_risk(&self.outcomes, VALUE_AT_RISK_CONFIDENCE)),
+            conditional_value_at_risk_95: Some(conditional_value_at_risk(
+                &self.outcomes,
+                VALUE_AT_RISK_CONFIDENCE,
+            )),
+            probability_of_underperforming_benchmark: None,
+            probability_of_return_below_threshold: None,
+            distribution: None,
+            tag_conditional_outcomes: None,
+            numerical_errors: Some(numerical_errors(&portfolio, Some(&self.outcomes), 0.0)),
+        })
+    }
+}
+
+/// Returns the portfolio of the candidates with the fraction of each given by the function.
+fn portfolio_with_fractions(
+    candidates: &[Company],
+    fraction: impl Fn(&Company) -> f64,
+) -> Portfolio {
+    Portfolio {
+        companies: candidates
+            .iter()
+            .map(|c| PortfolioCompany {
+                company: c.clone(),
+                fraction: fraction(c),
+            })
+            .collect(),
+        macro_scenarios: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::create_test_logger;
+    use crate::model::analysis_options::AnalysisOptions;
+    use crate::model::portfolio::AllocationInput;
+    use crate::utils::assert_close;
+    use crate::{allocate, analyze};
+
+    #[test]
+    fn test_engine_matches_free_functions() {
+        let logger = create_test_logger();
+        let input: AllocationInput = serde_yaml::from_str(
+            "
+            candidates:
+              - name: A
+                ticker: A
+                description: Coin flip with an edge
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Head
+                    intrinsic_value: 2.0
+                    probability: 0.6
+                  - thesis: Tail
+                    intrinsic_value: 0.5
+                    probability: 0.4
+              - name: B
+                ticker: B
+                description: Biased die
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Six
+                    intrinsic_value: 3.0
+                    probability: 0.3
+                  - thesis: Other
+                    intrinsic_value: 0.6
+                    probability: 0.7
+            ",
+        )
+        .unwrap();
+        let mut engine = CharlieEngine::new(input.candidates.clone(), &logger).unwrap();
+
+        let n_candidates = engine.candidates().len();
+        let solution = engine
+            .reallocate_with(|a| a.with_long_only_constraints(n_candidates))
+            .unwrap();
+        let expected = allocate(
+            AllocationInput {
+                long_only: Some(true),
+                ..input
+            },
+            &logger,
+        )
+        .result
+        .unwrap();
+        for (pc, allocation) in solution
+            .portfolio
+            .companies
+            .iter()
+            .zip(&expected.allocations)
+        {
+            assert_eq!(pc.company.ticker, allocation.ticker);
+            assert_close!(allocation.fraction, pc.fraction, 1e-4);
+        }
+
+        let analysis = engine.analyze_weights(&expected.allocations).unwrap();
+        let expected_analysis = analyze(
+            solution.portfolio,
+            &[],
+            &AnalysisOptions::default(),
+            &logger,
+        )
+        .result
+        .unwrap();
+        assert_close!(
+            expected_analysis.expected_return,
+            analysis.expected_return,
+            1e-4
+        );
+        assert_close!(
+            expected_analysis.cumulative_probability_of_loss.unwrap(),
+            analysis.cumulative_probability_of_loss.unwrap(),
+            1e-4
+        );
+
+        let weights = [TickerAndFraction {
+            ticker: "Z".to_string(),
+            fraction: 0.5,
+        }];
+        let e = engine.analyze_weights(&weights).unwrap_err();
+        assert_eq!("unknown-tickers-in-weights", e.code);
+    }
+}
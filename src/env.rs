@@ -1,6 +1,9 @@
-use slog::{o, Drain, Level, Logger};
+use slog::{info, o, Drain, Level, Logger, Never, OwnedKVList, Record};
 use slog_async::OverflowStrategy;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// Creates a logger object. Used in certain utilities and tests
 pub fn create_logger(level: Level) -> Logger {
@@ -16,11 +19,130 @@ pub fn create_logger(level: Level) -> Logger {
     Logger::root(drain, o!())
 }
 
+/// Creates a logger writing one JSON object per message to the standard output, which log
+/// collectors such as Loki or Elastic ingest without parsing. The key-value pairs of the messages
+/// and the loggers become fields of the objects.
+pub fn create_json_logger() -> Logger {
+    let drain = slog_json::Json::new(std::io::stdout())
+        .add_default_keys()
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain)
+        .overflow_strategy(OverflowStrategy::Block)
+        .build()
+        .fuse();
+    Logger::root(drain, o!("name" => "charlie"))
+}
+
 /// Test logger filters out everything below Warning level. For debugging tests, change to Debug
 pub fn create_test_logger() -> Logger {
     create_logger(Level::Warning)
 }
 
+/// Log level that can be changed while the logger is in use, e.g. when the server configuration is
+/// reloaded. Clones share the same level.
+#[derive(Clone, Debug)]
+pub struct LogLevel(Arc<AtomicUsize>);
+
+impl LogLevel {
+    pub fn new(level: Level) -> LogLevel {
+        LogLevel(Arc::new(AtomicUsize::new(level.as_usize())))
+    }
+
+    pub fn get(&self) -> Level {
+        Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+
+    pub fn set(&self, level: Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed)
+    }
+
+    /// Wraps the logger into one that drops the messages below this level.
+    pub fn filter(&self, logger: Logger) -> Logger {
+        let level = self.clone();
+        Logger::root(
+            logger
+                .filter(move |record| record.level().is_at_least(level.get()))
+                .fuse(),
+            o!(),
+        )
+    }
+}
+
+/// Captures the messages of a logger at or above a level, e.g. to return the messages of an
+/// allocation in its response. The messages are captured independently of the level of the
+/// wrapped logger, which still receives all messages.
+#[derive(Clone, Debug)]
+pub struct LogCapture {
+    level: Level,
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl LogCapture {
+    pub fn new(level: Level) -> LogCapture {
+        LogCapture {
+            level,
+            messages: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Wraps the logger into one that also captures the messages at or above the level.
+    pub fn logger(&self, logger: &Logger) -> Logger {
+        Logger::root(
+            slog::Duplicate::new(self.clone(), logger.clone()).fuse(),
+            o!(),
+        )
+    }
+
+    /// Returns the captured messages prefixed with their level, in the order they were logged.
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl Drain for LogCapture {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), Never> {
+        if record.level().is_at_least(self.level) {
+            self.messages.lock().unwrap().push(format!(
+                "{} {}",
+                record.level().as_short_str(),
+                record.msg()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Logs the completion of a request with the endpoint and the duration when dropped, which covers
+/// every return path of the endpoint. The request id comes with the logger of the request.
+pub struct CompletionLog {
+    logger: Logger,
+    endpoint: &'static str,
+    start: Instant,
+}
+
+impl CompletionLog {
+    pub fn new(logger: &Logger, endpoint: &'static str) -> CompletionLog {
+        CompletionLog {
+            logger: logger.clone(),
+            endpoint,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for CompletionLog {
+    fn drop(&mut self) {
+        info!(self.logger, "endpoint completed";
+            "endpoint" => self.endpoint,
+            "duration_ms" => self.start.elapsed().as_secs_f64() * 1000.0,
+        );
+    }
+}
+
 /// Gets the project directory by looking for directory where Cargo.toml is located, starting from
 /// the directory that contains the current executable file.
 pub fn get_project_dir() -> PathBuf {
@@ -45,10 +167,33 @@ pub fn get_project_dir() -> PathBuf {
 
 #[cfg(test)]
 mod test {
-    use crate::env::get_project_dir;
+    use crate::env::{create_test_logger, get_project_dir, LogCapture, LogLevel};
+    use slog::{debug, info, warn, Level};
 
     #[test]
     fn test_get_project_dir() {
         assert!(get_project_dir().join("Cargo.toml").exists());
     }
+
+    #[test]
+    fn test_log_level_is_shared_between_clones() {
+        let level = LogLevel::new(Level::Info);
+        let clone = level.clone();
+        clone.set(Level::Debug);
+        assert_eq!(level.get(), Level::Debug);
+    }
+
+    #[test]
+    fn test_log_capture() {
+        let capture = LogCapture::new(Level::Info);
+        let logger = capture.logger(&create_test_logger());
+        debug!(logger, "Not captured");
+        info!(logger, "Captured {}", 1);
+        warn!(logger, "Captured {}", 2);
+
+        assert_eq!(
+            vec!["INFO Captured 1".to_string(), "WARN Captured 2".to_string()],
+            capture.messages()
+        );
+    }
 }
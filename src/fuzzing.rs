@@ -0,0 +1,407 @@
+use crate::allocate;
+use crate::kelly_allocation::SOLVER_TOLERANCE;
+use crate::model::capital_loss::CapitalLoss;
+use crate::model::company::{Company, Ticker};
+use crate::model::errors::Error;
+use crate::model::portfolio::{AllocationInput, Portfolio, PortfolioCompany};
+use crate::model::responses::AllocationResponse;
+use crate::model::scenario::Scenario;
+use crate::objective::expected_log_growth;
+use crate::validation::result::ValidationResult;
+use crate::MIN_SCENARIO_PROBABILITY;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use slog::Logger;
+
+/// Tolerance for approximate allocations, whose constraints may be slightly violated because they
+/// were found with the penalty method.
+const APPROXIMATE_INVARIANT_TOLERANCE: f64 = 1e-3;
+
+/// Bounds of the random allocation inputs. The number of outcomes grows exponentially with the
+/// number of candidates, so both bounds should stay small.
+#[derive(Clone, Debug)]
+pub struct FuzzSettings {
+    /// At least one candidate is generated.
+    pub max_candidates: usize,
+
+    /// At least two scenarios are generated for each candidate: one downside and one upside.
+    pub max_scenarios_per_candidate: usize,
+
+    /// Randomly add the long-only, maximum individual allocation, maximum total leverage and
+    /// maximum permanent loss of capital constraints.
+    pub with_constraints: bool,
+}
+
+impl Default for FuzzSettings {
+    fn default() -> FuzzSettings {
+        FuzzSettings {
+            max_candidates: 4,
+            max_scenarios_per_candidate: 4,
+            with_constraints: true,
+        }
+    }
+}
+
+/// Generates a random valid allocation input from the seed, i.e. the same seed always generates
+/// the same input. Each candidate has exactly one downside scenario and a positive expected
+/// return, such that no candidate is filtered out, and the probabilities of its scenarios sum up
+/// to one. The random constraints are always satisfied by not allocating at all.
+pub fn random_allocation_input(seed: u64, settings: &FuzzSettings) -> AllocationInput {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let n_candidates = rng.gen_range(1..=settings.max_candidates.max(1));
+    let candidates: Vec<Company> = (0..n_candidates)
+        .map(|i| random_company(&mut rng, i, settings.max_scenarios_per_candidate.max(2)))
+        .collect();
+
+    // The maximum permanent loss of capital constraint requires the long-only constraint
+    let with_constraints = settings.with_constraints;
+    let max_permanent_loss_of_capital =
+        (with_constraints && rng.gen_bool(0.5)).then(|| CapitalLoss {
+            fraction_of_capital: rng.gen_range(0.1..0.5),
+            probability_of_loss: rng.gen_range(0.05..0.5),
+        });
+    let long_only =
+        with_constraints.then(|| max_permanent_loss_of_capital.is_some() || rng.gen_bool(0.5));
+    AllocationInput {
+        candidates,
+        long_only,
+        long_only_tickers: None,
+        max_permanent_loss_of_capital,
+        max_individual_allocation: (with_constraints && rng.gen_bool(0.5))
+            .then(|| rng.gen_range(0.2..1.0)),
+        max_individual_allocation_tickers: None,
+        ticker_max_individual_allocations: None,
+        min_individual_allocation: None,
+        max_total_leverage_ratio: (with_constraints && rng.gen_bool(0.5))
+            .then(|| rng.gen_range(0.0..1.0)),
+        ..Default::default()
+    }
+}
+
+/// Generates a company with one downside scenario and upside scenarios that are large enough to
+/// make the expected return positive.
+fn random_company(rng: &mut StdRng, index: usize, max_scenarios: usize) -> Company {
+    let market_cap: f64 = rng.gen_range(1.0..1000.0);
+    let n_upside_scenarios = rng.gen_range(1..max_scenarios);
+
+    let downside_probability: f64 = rng.gen_range(0.05..0.5);
+    let downside_return: f64 = rng.gen_range(-0.9..-0.1);
+    let min_upside_return =
+        (-1.1 * downside_probability * downside_return / (1.0 - downside_probability)).max(0.1);
+
+    // Random weights of the upside scenarios, normalized to the remaining probability. The last
+    // probability absorbs the rounding such that the sum is one.
+    let weights: Vec<f64> = (0..n_upside_scenarios)
+        .map(|_| rng.gen_range(0.1..1.0))
+        .collect();
+    let total_weight: f64 = weights.iter().sum();
+    let mut probabilities: Vec<f64> = weights
+        .iter()
+        .map(|w| (1.0 - downside_probability) * w / total_weight)
+        .collect();
+    let last_probability =
+        1.0 - downside_probability - probabilities[..n_upside_scenarios - 1].iter().sum::<f64>();
+    probabilities[n_upside_scenarios - 1] = last_probability;
+
+    let mut scenarios = vec![Scenario {
+        thesis: "Downside".to_string(),
+        intrinsic_value: market_cap * (1.0 + downside_return),
+        probability: downside_probability,
+        ..Default::default()
+    }];
+    scenarios.extend(
+        probabilities
+            .into_iter()
+            .enumerate()
+            .map(|(j, p)| Scenario {
+                thesis: format!("Upside {}", j + 1),
+                intrinsic_value: market_cap * (1.0 + rng.gen_range(min_upside_return..3.0)),
+                probability: p,
+                ..Default::default()
+            }),
+    );
+
+    Company {
+        name: format!("Fuzz {index}"),
+        ticker: format!("FUZZ{index}"),
+        description: "Randomly generated candidate.".to_string(),
+        market_cap,
+        auto_status_quo: None,
+        units: None,
+        last_reviewed: None,
+        scenarios,
+    }
+}
+
+/// Checks the invariants of the allocation of the input and returns an error listing all the
+/// violated invariants, if any:
+/// 1. The allocation succeeds and all fractions and the expected growth are finite.
+/// 2. The long-only, maximum individual allocation, maximum total leverage and maximum permanent
+///    loss of capital constraints are satisfied.
+/// 3. The expected growth is at least the growth of uniform weights over the allocated
+///    candidates, if the uniform weights satisfy the constraints as well. This is only checked
+///    without candidate groups and without regularization, which change the feasible weights and
+///    the maximized objective.
+pub fn check_invariants(
+    allocation_input: &AllocationInput,
+    allocation_response: &AllocationResponse,
+) -> Result<(), Error> {
+    let result = match (&allocation_response.result, &allocation_response.error) {
+        (Some(result), None) => result,
+        (_, Some(e)) => return Err(invariant_error(vec![e.message.clone()])),
+        (None, None) => {
+            return Err(invariant_error(
+                allocation_response
+                    .validation_problems
+                    .iter()
+                    .flatten()
+                    .filter_map(|v| match v {
+                        ValidationResult::PROBLEM(p) => Some(p.message.clone()),
+                        ValidationResult::OK => None,
+                    })
+                    .collect(),
+            ))
+        }
+    };
+
+    let tolerance = if result.solver_metadata.is_approximate {
+        APPROXIMATE_INVARIANT_TOLERANCE
+    } else {
+        SOLVER_TOLERANCE
+    };
+    let mut violations: Vec<String> = vec![];
+
+    result
+        .allocations
+        .iter()
+        .filter(|a| !a.fraction.is_finite())
+        .for_each(|a| violations.push(format!("Fraction of {} is {}.", a.ticker, a.fraction)));
+    let growth = result.solver_metadata.expected_log_growth;
+    if !growth.is_finite() {
+        violations.push(format!("Expected logarithmic growth is {growth}."));
+    }
+
+    // Preconditioned in the same way as in the allocation, such that the worst-case scenarios of
+    // the constraint on the permanent loss of capital are the same
+    let min_scenario_probability = allocation_input
+        .min_scenario_probability
+        .unwrap_or(MIN_SCENARIO_PROBABILITY);
+    let mut companies: Vec<PortfolioCompany> = vec![];
+    for allocation in &result.allocations {
+        match allocation_input
+            .candidates
+            .iter()
+            .find(|c| c.ticker == allocation.ticker)
+        {
+            Some(c) => companies.push(PortfolioCompany {
+                company: c
+                    .with_status_quo_scenario()
+                    .without_negligible_scenarios(min_scenario_probability)
+                    .0,
+                fraction: allocation.fraction,
+            }),
+            None => violations.push(format!(
+                "Allocated ticker {} is not a candidate.",
+                allocation.ticker
+            )),
+        }
+    }
+    let portfolio = Portfolio {
+        companies,
+        macro_scenarios: allocation_input.macro_scenarios.clone(),
+    };
+    violations.extend(constraint_violations(
+        allocation_input,
+        &portfolio,
+        tolerance,
+    ));
+
+    let regularized = allocation_input
+        .solver_settings
+        .as_ref()
+        .and_then(|s| s.regularization)
+        .is_some_and(|r| r > 0.0);
+    if allocation_input.candidate_groups.is_none() && !regularized && growth.is_finite() {
+        let n_companies = portfolio.companies.len() as f64;
+        let uniform = Portfolio {
+            companies: portfolio
+                .companies
+                .iter()
+                .map(|pc| PortfolioCompany {
+                    company: pc.company.clone(),
+                    fraction: 1.0 / n_companies,
+                })
+                .collect(),
+            macro_scenarios: portfolio.macro_scenarios.clone(),
+        };
+        if constraint_violations(allocation_input, &uniform, 0.0).is_empty() {
+            if let Ok(uniform_growth) = expected_log_growth(&uniform) {
+                if growth < uniform_growth - tolerance {
+                    violations.push(format!(
+                        "Expected logarithmic growth {growth} is lower than the growth \
+                        {uniform_growth} of uniform weights."
+                    ));
+                }
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(invariant_error(violations))
+    }
+}
+
+/// Generates the random allocation input of the seed, allocates it and checks the invariants of
+/// the allocation. The message of the error names the seed, such that the failure can be
+/// reproduced.
+pub fn fuzz_allocation(seed: u64, settings: &FuzzSettings, logger: &Logger) -> Result<(), Error> {
+    let allocation_input = random_allocation_input(seed, settings);
+    let allocation_response = allocate(allocation_input.clone(), logger);
+    check_invariants(&allocation_input, &allocation_response).map_err(|e| Error {
+        code: e.code,
+        message: format!("Seed {seed}: {}", e.message),
+    })
+}
+
+/// Describes the violated constraints of the input at the fractions of the portfolio.
+fn constraint_violations(
+    allocation_input: &AllocationInput,
+    portfolio: &Portfolio,
+    tolerance: f64,
+) -> Vec<String> {
+    let mut violations: Vec<String> = vec![];
+    let applies_to = |tickers: &Option<Vec<Ticker>>, ticker: &Ticker| {
+        tickers.as_ref().map_or(true, |t| t.contains(ticker))
+    };
+
+    for pc in &portfolio.companies {
+        let ticker = &pc.company.ticker;
+        if allocation_input.long_only.unwrap_or(false)
+            && applies_to(&allocation_input.long_only_tickers, ticker)
+            && pc.fraction < -tolerance
+        {
+            violations.push(format!(
+                "Fraction {} of {ticker} violates the long-only constraint.",
+                pc.fraction
+            ));
+        }
+
+        if let Some(max_f) = allocation_input.max_individual_allocation {
+            if applies_to(&allocation_input.max_individual_allocation_tickers, ticker)
+                && pc.fraction > max_f + tolerance
+            {
+                violations.push(format!(
+                    "Fraction {} of {ticker} exceeds the maximum individual allocation {max_f}.",
+                    pc.fraction
+                ));
+            }
+        }
+    }
+
+    if let Some(max_lr) = allocation_input.max_total_leverage_ratio {
+        let total: f64 = portfolio.companies.iter().map(|pc| pc.fraction).sum();
+        if total > 1.0 + max_lr + tolerance {
+            violations.push(format!(
+                "Total allocation {total} exceeds the maximum total leverage ratio {max_lr}."
+            ));
+        }
+    }
+
+    // Same worst case as in the constraint: the fraction times the smallest probability weighted
+    // return of each company
+    if let Some(capital_loss) = &allocation_input.max_permanent_loss_of_capital {
+        let max_loss = capital_loss.fraction_of_capital * capital_loss.probability_of_loss;
+        let worst_case: f64 = portfolio
+            .companies
+            .iter()
+            .map(|pc| {
+                pc.fraction
+                    * pc.company
+                        .scenarios
+                        .iter()
+                        .map(|s| s.probability_weighted_return(pc.company.market_cap))
+                        .fold(f64::INFINITY, f64::min)
+            })
+            .sum();
+        if worst_case < -max_loss - tolerance {
+            violations.push(format!(
+                "Probability weighted worst-case return {worst_case} exceeds the maximum \
+                permanent loss of capital {max_loss}."
+            ));
+        }
+    }
+
+    violations
+}
+
+fn invariant_error(violations: Vec<String>) -> Error {
+    Error {
+        code: "allocation-invariant-violated".to_string(),
+        message: violations.join(" "),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::create_test_logger;
+    use crate::model::responses::TickerAndFraction;
+    use crate::validate;
+    use crate::validation::result::Severity::ERROR;
+
+    #[test]
+    fn test_random_allocation_input_is_valid_and_reproducible() {
+        let settings = FuzzSettings::default();
+        for seed in 0..20 {
+            let allocation_input = random_allocation_input(seed, &settings);
+            assert!(allocation_input.candidates.len() <= settings.max_candidates);
+            allocation_input.candidates.iter().for_each(|c| {
+                assert!(c.scenarios.len() >= 2);
+                assert!(c.scenarios.len() <= settings.max_scenarios_per_candidate);
+            });
+            let problems: Vec<ValidationResult> =
+                validate(&allocation_input, &create_test_logger())
+                    .into_iter()
+                    .filter(|v| matches!(v, ValidationResult::PROBLEM(p) if p.severity == ERROR))
+                    .collect();
+            assert!(problems.is_empty(), "{seed}: {problems:?}");
+
+            assert_eq!(
+                serde_json::to_string(&allocation_input).unwrap(),
+                serde_json::to_string(&random_allocation_input(seed, &settings)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_fuzz_allocation() {
+        let logger = create_test_logger();
+        for seed in 0..8 {
+            assert_eq!(
+                Ok(()),
+                fuzz_allocation(seed, &FuzzSettings::default(), &logger).map_err(|e| e.message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_invariants_finds_violated_constraint() {
+        let allocation_input = AllocationInput {
+            long_only: Some(true),
+            ..random_allocation_input(0, &FuzzSettings::default())
+        };
+        let mut allocation_response = allocate(allocation_input.clone(), &create_test_logger());
+        let result = allocation_response.result.as_mut().unwrap();
+        result.allocations = vec![TickerAndFraction {
+            ticker: allocation_input.candidates[0].ticker.clone(),
+            fraction: -0.5,
+        }];
+
+        let error = check_invariants(&allocation_input, &allocation_response).unwrap_err();
+        assert_eq!("allocation-invariant-violated", error.code);
+        assert!(error.message.contains("long-only"));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::model::errors::Error;
+use crate::model::jobs::{AllocationJob, JobProgress, JobStatus};
+use crate::model::responses::AllocationResponse;
+use crate::solver_observer::{SolvedSystem, SolverIteration, SolverObserver};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of finished jobs kept in the store. The oldest finished jobs are removed when a
+/// new job would exceed it, while unfinished jobs are always kept.
+pub const MAX_FINISHED_JOBS: usize = 1000;
+
+/// Store of the allocation jobs running in the background, identified by increasing ids starting
+/// at 1. Jobs are kept in memory only, so they're lost when the server restarts, while their
+/// allocations are recorded in the allocation store.
+#[derive(Default)]
+pub struct JobStore {
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    last_id: AtomicU64,
+}
+
+/// Allocation job, updated by the task performing the allocation and observing its solver.
+pub struct Job {
+    id: u64,
+    api_key: String,
+    created_at: DateTime<Utc>,
+    progress: Mutex<JobProgress>,
+    state: Mutex<JobState>,
+}
+
+struct JobState {
+    status: JobStatus,
+    response: Option<AllocationResponse>,
+    allocation_id: Option<u64>,
+    error: Option<Error>,
+}
+
+impl JobStore {
+    /// Creates a queued job for the given API key and returns it, removing the oldest finished jobs
+    /// beyond [MAX_FINISHED_JOBS].
+    pub fn create(&self, api_key: &str) -> Arc<Job> {
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let job = Arc::new(Job {
+            id,
+            api_key: api_key.to_string(),
+            created_at: Utc::now(),
+            progress: Mutex::new(JobProgress::default()),
+            state: Mutex::new(JobState {
+                status: JobStatus::Queued,
+                response: None,
+                allocation_id: None,
+                error: None,
+            }),
+        });
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let finished = jobs
+            .values()
+            .filter(|j| j.is_finished())
+            .map(|j| j.id)
+            .collect::<Vec<u64>>();
+        for id in finished
+            .iter()
+            .take((finished.len() + 1).saturating_sub(MAX_FINISHED_JOBS))
+        {
+            jobs.remove(id);
+        }
+        jobs.insert(id, job.clone());
+
+        job
+    }
+
+    /// Returns the job with the given id, if any and if it was created with the given API key.
+    /// Since the ids are sequential, jobs of other API keys are treated as missing.
+    pub fn get(&self, id: u64, api_key: &str) -> Option<AllocationJob> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .filter(|j| j.api_key == api_key)
+            .map(|j| j.allocation_job())
+    }
+}
+
+impl Job {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Marks the job as running once the allocation got a slot in the allocation queue.
+    pub fn start(&self) {
+        self.state.lock().unwrap().status = JobStatus::Running;
+    }
+
+    /// Completes the job with the response of the allocation and the id under which it's recorded.
+    pub fn complete(&self, response: AllocationResponse, allocation_id: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.status = JobStatus::Completed;
+        state.response = Some(response);
+        state.allocation_id = allocation_id;
+    }
+
+    /// Fails the job with the error that prevented the allocation.
+    pub fn fail(&self, error: Error) {
+        let mut state = self.state.lock().unwrap();
+        state.status = JobStatus::Failed;
+        state.error = Some(error);
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.state.lock().unwrap().status,
+            JobStatus::Completed | JobStatus::Failed
+        )
+    }
+
+    fn allocation_job(&self) -> AllocationJob {
+        let state = self.state.lock().unwrap();
+        AllocationJob {
+            id: self.id,
+            status: state.status,
+            created_at: self.created_at,
+            progress: self.progress.lock().unwrap().clone(),
+            response: state.response.clone(),
+            allocation_id: state.allocation_id,
+            error: state.error.clone(),
+        }
+    }
+}
+
+/// Counts the solved systems in total and in the enumeration in progress for the progress of the
+/// job. Systems solved outside of an enumeration, i.e. by projection or by the penalty method, only
+/// count towards the total.
+impl SolverObserver for Job {
+    fn on_iteration(&self, _iteration: &SolverIteration) {}
+
+    fn on_system(&self, system: &SolvedSystem) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.n_systems_solved += 1;
+        if system.active_constraints.is_some() {
+            progress.n_enumeration_systems_solved += 1;
+        }
+    }
+
+    fn on_enumeration(&self, n_systems: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.enumeration_index += 1;
+        progress.n_enumeration_systems_solved = 0;
+        progress.n_enumeration_systems = Some(n_systems);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitvec::prelude::*;
+
+    #[test]
+    fn test_job_progress_and_eviction() {
+        let store = JobStore::default();
+        let job = store.create("key");
+        assert_eq!(1, job.id());
+        assert_eq!(JobStatus::Queued, store.get(1, "key").unwrap().status);
+        assert!(store.get(2, "key").is_none());
+        assert!(store.get(1, "other-key").is_none());
+
+        job.start();
+        let active_constraints = bitvec![0, 1];
+        let solve = |active_constraints: Option<&BitSlice>| {
+            job.on_system(&SolvedSystem {
+                tickers: &[],
+                active_constraints,
+                x: None,
+                is_viable: false,
+            })
+        };
+        // System solved by projection, followed by an enumeration of which 3 systems are solved
+        solve(None);
+        job.on_enumeration(4);
+        for _ in 0..3 {
+            solve(Some(&active_constraints));
+        }
+        let running = store.get(1, "key").unwrap();
+        assert_eq!(JobStatus::Running, running.status);
+        assert_eq!(
+            JobProgress {
+                n_systems_solved: 4,
+                enumeration_index: 1,
+                n_enumeration_systems_solved: 3,
+                n_enumeration_systems: Some(4),
+            },
+            running.progress
+        );
+
+        // The next enumeration starts counting from zero again
+        job.on_enumeration(2);
+        solve(Some(&active_constraints));
+        let progress = store.get(1, "key").unwrap().progress;
+        assert_eq!(5, progress.n_systems_solved);
+        assert_eq!(2, progress.enumeration_index);
+        assert_eq!(1, progress.n_enumeration_systems_solved);
+        assert_eq!(Some(2), progress.n_enumeration_systems);
+
+        job.fail(Error {
+            code: "allocation-queue-is-full".to_string(),
+            message: "".to_string(),
+        });
+        assert_eq!(JobStatus::Failed, store.get(1, "key").unwrap().status);
+
+        // Only the most recent finished jobs are kept, while a running one is never removed
+        let running = store.create("key");
+        for _ in 0..MAX_FINISHED_JOBS {
+            store.create("key").fail(Error {
+                code: "".to_string(),
+                message: "".to_string(),
+            });
+        }
+        store.create("key");
+        assert!(store.get(1, "key").is_none());
+        assert!(store.get(running.id(), "key").is_some());
+        assert!(store.get(3, "key").is_none());
+        assert!(store.get(4, "key").is_some());
+    }
+}
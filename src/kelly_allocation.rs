@@ -1,48 +1,317 @@
 use bitvec::order::Lsb0;
 use bitvec::slice::BitSlice;
+use bitvec::vec::BitVec;
 use bitvec::view::BitView;
 use itertools::Itertools;
 use nalgebra::{DMatrix, DVector};
-use num_traits::pow;
 use num_traits::pow::Pow;
 use ordered_float::OrderedFloat;
-use slog::{debug, info, Logger};
-
-use crate::analysis::{all_outcomes, expected_return, worst_case_outcome, Outcome};
-use crate::constraints::constraint::InequalityConstraint;
+use slog::{debug, info, warn, Logger};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::analysis::{all_outcomes, expected_log_growth, expected_return, regularization_penalty};
+use crate::analysis::{worst_case_outcome, Outcome};
+use crate::constraints::constraint::{EqualityConstraint, InequalityConstraint};
+use crate::constraints::fully_invested_constraint::FullyInvestedConstraint;
 use crate::constraints::long_only_constraint::LongOnlyConstraint;
 use crate::constraints::maximum_capital_loss_constraint::MaxCapitalLossConstraint;
+use crate::constraints::maximum_group_allocation_constraint::MaximumGroupAllocationConstraint;
 use crate::constraints::maximum_individual_allocation_constraint::MaximumIndividualAllocationConstraint;
+use crate::constraints::maximum_position_downside_constraint::MaximumPositionDownsideConstraint;
 use crate::constraints::maximum_total_leverage_constraint::MaximumTotalLeverageConstraint;
+use crate::constraints::minimum_individual_allocation_constraint::MinimumIndividualAllocationConstraint;
 use crate::model::capital_loss::CapitalLoss;
 use crate::model::company::{Company, TOLERANCE};
 use crate::model::errors::Error;
-use crate::model::portfolio::{Portfolio, PortfolioCompany};
-
-/// Tolerance for converging the solution during Newton-Raphson iteration. This is an absolute
-/// tolerance, which may need to be modified into relative tolerance due to addition of constraints.
+use crate::model::portfolio::{MacroScenario, Portfolio, PortfolioCompany};
+use crate::model::responses::TickerAndFraction;
+use crate::model::responses::{PrincipalCurvature, SolverMetadata, TickerAndCurvature};
+use crate::model::solver_settings::{SolverSettings, SolverStrategy};
+use crate::solver_observer::{
+    condition_number_estimate, SolvedSystem, SolverIteration, SolverObserver,
+};
+use crate::sparse_lu::SparseLu;
+
+/// Default tolerance for converging the solution during Newton-Raphson iteration. This is an
+/// absolute tolerance, which may need to be modified into relative tolerance due to addition of
+/// constraints. Can be changed with [SolverSettings::solver_tolerance].
 /// TODO: Think more
 pub const SOLVER_TOLERANCE: f64 = 1e-5;
 
-/// Relaxation factor used when updating solution vector in an iteration of the nonlinear loop.
-/// TODO: Relaxation factor seems to influence the results tremendously... Investigate further.
+/// Default maximum number of nonlinear systems solved for an allocation, i.e. 2^22 systems for 22
+/// inequality constraints. Can be changed with [SolverSettings::max_systems].
+pub const DEFAULT_MAX_SYSTEMS: usize = 4194304;
+
+/// Default relaxation factor used when updating solution vector in an iteration of the nonlinear
+/// loop of the penalty method, and by the plain strategy if the adaptive damping doesn't find a
+/// step that keeps the growth from decreasing. Can be changed with
+/// [SolverSettings::relaxation_factor].
 const RELAXATION_FACTOR: f64 = 0.7;
 
+/// Decrease of the growth tolerated by the adaptive damping of the plain strategy, which accounts
+/// for round-off close to the solution.
+const GROWTH_DECREASE_TOLERANCE: f64 = 1e-12;
+
 /// Maximum number of iterations for the nonlinear solver.
 pub const MAX_ITER: u32 = 100;
 
+/// Default minimum probability of a scenario that is taken into account by the solver. Scenarios
+/// with positive probabilities smaller than this are considered negligible and are removed from
+/// the candidates before solving, because they destabilize the Newton-Raphson iteration without
+/// contributing meaningfully to the growth rate.
+pub const MIN_SCENARIO_PROBABILITY: f64 = 1e-6;
+
+/// Increasing penalty factors for the violation of constraints used by the fallback penalty method.
+/// The violation of the constraints in the approximate solution is inversely proportional to the
+/// last penalty factor.
+const PENALTY_FACTORS: [f64; 5] = [1e1, 1e2, 1e3, 1e4, 1e5];
+
+/// Initial and maximum radius of the trust region for the trust region strategy.
+const INITIAL_TRUST_RADIUS: f64 = 1.0;
+const MAX_TRUST_RADIUS: f64 = 1e3;
+
+/// Fraction of the predicted decrease of the residual norm that is required to accept a step when
+/// using the line search and trust region strategies.
+const SUFFICIENT_DECREASE: f64 = 1e-4;
+
+/// Maximum number of times a step is shortened within a single iteration when using the line
+/// search and trust region strategies.
+const MAX_STEP_REDUCTIONS: u32 = 30;
+
+/// Largest condition number of the Jacobian, estimated from the pivots of its LU decomposition, for
+/// which the Newton step is considered reliable. Beyond it, rounding errors dominate the step.
+const MAX_CONDITION_NUMBER: f64 = 1e14;
+
+/// Minimum number of unknowns (fractions, Lagrange multipliers and slack variables) for which the
+/// linearized system is solved with the sparse LU decomposition instead of the dense inverse, e.g.
+/// ten candidates with the long-only and the maximum individual allocation constraints.
+const SPARSE_SOLVE_THRESHOLD: usize = 30;
+
+/// Formats the duration in seconds approximately in the most suitable unit, e.g. "about 3 hours".
+fn approximate_duration(seconds: f64) -> String {
+    let units = [
+        ("years", 365.0 * 86400.0),
+        ("days", 86400.0),
+        ("hours", 3600.0),
+        ("minutes", 60.0),
+    ];
+    match units
+        .iter()
+        .find(|(_, unit_seconds)| seconds >= 2.0 * unit_seconds)
+    {
+        Some((unit, unit_seconds)) => format!("about {:.0} {unit}", seconds / unit_seconds),
+        None => format!("about {:.0} seconds", seconds.max(1.0)),
+    }
+}
+
+/// Rates at which the allocation is financed: the cash that isn't invested earns the risk-free
+/// rate, and the capital borrowed for leverage costs the margin interest rate, which defaults to
+/// the risk-free rate. Both are zero by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FinancingRates {
+    pub risk_free_rate: f64,
+    pub margin_interest_rate: Option<f64>,
+}
+
+impl FinancingRates {
+    /// Returns the rate earned on the given cash position, or paid on it if it's negative, i.e. if
+    /// the allocation is levered.
+    pub fn rate(&self, cash: f64) -> f64 {
+        if cash < 0.0 {
+            self.margin_interest_rate.unwrap_or(self.risk_free_rate)
+        } else {
+            self.risk_free_rate
+        }
+    }
+
+    /// Returns the return of the given cash position, negative for the financing costs of leverage.
+    pub fn cash_return(&self, cash: f64) -> f64 {
+        self.rate(cash) * cash
+    }
+}
+
+/// Returns the cash position of the portfolio, i.e. 1 minus the fractions of the candidates with
+/// net exposure. Negative cash is borrowed.
+pub fn cash(portfolio: &Portfolio, market_neutral_indices: &[usize]) -> f64 {
+    1.0 - portfolio
+        .companies
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !market_neutral_indices.contains(i))
+        .map(|(_, pc)| pc.fraction)
+        .sum::<f64>()
+}
+
+/// Returns the return of the candidate with the given fraction index in the outcome in excess of
+/// the rate of the cash, which the cash taken up by the candidate would have earned or cost
+/// otherwise. Candidates without net exposure take up no cash.
+fn excess_return(
+    o: &Outcome,
+    fraction_index: usize,
+    company: &Company,
+    rate: f64,
+    market_neutral_indices: &[usize],
+) -> f64 {
+    if market_neutral_indices.contains(&fraction_index) {
+        o.company_returns[&company.ticker]
+    } else {
+        o.company_returns[&company.ticker] - rate
+    }
+}
+
+/// Returns the wealth relative to the initial capital in the outcome with the fractions of the
+/// portfolio, including the return of the cash that isn't invested or the cost of the borrowed
+/// capital.
+fn wealth(
+    o: &Outcome,
+    portfolio: &Portfolio,
+    financing_rates: FinancingRates,
+    market_neutral_indices: &[usize],
+) -> f64 {
+    1.0 + financing_rates.cash_return(cash(portfolio, market_neutral_indices))
+        + portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.fraction * o.company_returns[&pc.company.ticker])
+            .sum::<f64>()
+}
+
+/// Returns true if the portfolio loses everything in at least one of the outcomes, where the
+/// logarithm of the growth is singular. The wealth of the outcomes is recalculated with the
+/// fractions of the portfolio.
+fn may_lose_everything(
+    outcomes: &[Outcome],
+    portfolio: &Portfolio,
+    financing_rates: FinancingRates,
+    market_neutral_indices: &[usize],
+) -> bool {
+    outcomes
+        .iter()
+        .any(|o| wealth(o, portfolio, financing_rates, market_neutral_indices) < TOLERANCE)
+}
+
+/// Code of the error for non-finite values arising while solving, see [non_finite_error]
+pub const NON_FINITE_VALUE_CODE: &str = "non-finite-value-in-solver";
+
+/// Returns the error for non-finite values arising in the linearized system or the fractions
+/// while solving, which nalgebra would otherwise propagate into a nonsense solution. Returns that
+/// are finite but extreme overflow when squared in the Jacobian, so the scenario with the most
+/// extreme return is reported as the most likely cause, together with the fractions at which the
+/// values arose.
+fn check_finite(
+    jacobian: &DMatrix<f64>,
+    right_hand_side: &DVector<f64>,
+    portfolio: &Portfolio,
+) -> Result<(), Error> {
+    let is_finite = jacobian.iter().all(|v| v.is_finite())
+        && right_hand_side.iter().all(|v| v.is_finite())
+        && portfolio.companies.iter().all(|pc| pc.fraction.is_finite());
+    if is_finite {
+        return Ok(());
+    }
+
+    let fractions = portfolio
+        .companies
+        .iter()
+        .map(|pc| format!("{} {}", pc.company.ticker, pc.fraction))
+        .join(", ");
+    let cause = portfolio
+        .companies
+        .iter()
+        .flat_map(|pc| {
+            pc.company
+                .scenarios
+                .iter()
+                .map(move |s| (pc, s, s.scenario_return(pc.company.market_cap)))
+        })
+        .max_by_key(|(_, _, r)| OrderedFloat(r.abs()))
+        .map_or(String::new(), |(pc, s, r)| {
+            format!(
+                " The most extreme return of {r:e} in scenario {} of company {} is the most likely \
+                cause. Check its intrinsic value and the market cap.",
+                s.thesis, pc.company.ticker
+            )
+        });
+    Err(Error {
+        code: NON_FINITE_VALUE_CODE.to_string(),
+        message: format!(
+            "Non-finite values arose while solving at the fractions {fractions}.{cause}"
+        ),
+    })
+}
+
+/// Solution of the Kelly allocation problem.
+pub struct KellySolution {
+    pub portfolio: Portfolio,
+    /// True if the solution was approximated with the penalty method, meaning that the constraints
+    /// may be slightly violated.
+    pub is_approximate: bool,
+    /// Number of nonlinear systems solved, including the system solved with the penalty method.
+    pub n_systems: usize,
+    /// Lagrange multiplier of the fully invested or the fixed net exposure constraint, i.e. the
+    /// marginal expected logarithmic growth of one more unit of net exposure. Not given without
+    /// such a constraint or if the solution is approximate.
+    pub net_exposure_multiplier: Option<f64>,
+    /// Objective maximized by the solver at the solution, i.e. the expected logarithmic growth
+    /// including the financing of the cash, reduced by the regularization, turnover and soft
+    /// constraint penalties. Solutions for different choices of candidates are compared by it.
+    pub objective: f64,
+}
+
 /// Kelly allocator with an optional constraint for maximum loss of capital constraint. The
 /// constraint may be inactive or active, which is figured out during the solution process.
-/// TODO: Figure out why dynamic type check doesn't work on Vec<Box<dyn InequalityConstraint>>
+/// The constraints are shared, such that variants of the problem with additional constraints can be
+/// solved with clones of the allocator.
+/// TODO: Figure out why dynamic type check doesn't work on Vec<Rc<dyn InequalityConstraint>>
 ///  because that would allow to get rid of bool helper fields.
+#[derive(Clone)]
 pub struct KellyAllocator<'a> {
     logger: &'a Logger,
     max_iter: u32,
-    inequality_constraints: Vec<Box<dyn InequalityConstraint>>,
+    solver_tolerance: f64,
+    relaxation_factor: f64,
+    inequality_constraints: Vec<Rc<dyn InequalityConstraint>>,
+    equality_constraints: Vec<Rc<dyn EqualityConstraint>>,
     has_long_only_constraint: bool,
+    n_long_only_constraints: usize,
     has_max_total_leverage_constraint: bool,
     has_max_individual_allocation_constraint: bool,
+    n_max_individual_allocation_constraints: usize,
+    n_max_group_allocation_constraints: usize,
+    n_max_position_downside_constraints: usize,
+    /// Minimum allocation of the long positions, enforced after solving by forcing each position
+    /// below it either to zero or to at least the minimum.
+    min_individual_allocation: Option<f64>,
+    n_min_individual_allocation_constraints: usize,
     has_max_permanent_loss_constraint: bool,
+    has_fully_invested_constraint: bool,
+    /// Index of the fully invested constraint among the equality constraints.
+    fully_invested_index: Option<usize>,
+    /// Fraction indices of the candidates without net exposure, which don't count towards the
+    /// maximum total leverage and the fully invested constraints.
+    market_neutral_indices: Vec<usize>,
+    financing_rates: FinancingRates,
+    max_systems: usize,
+    /// Bounds of the fractions given by the long-only and maximum individual allocation
+    /// constraints, by fraction index. Used by the projected Newton method for pure box bounds.
+    lower_bounds: HashMap<usize, f64>,
+    upper_bounds: HashMap<usize, f64>,
+    strategy: SolverStrategy,
+    regularization: f64,
+    /// Strength of the L2 penalty of the trades from the current fractions, by fraction index,
+    /// see [KellyAllocator::with_turnover_penalty].
+    turnover_penalty: f64,
+    current_fractions: Vec<f64>,
+    /// Fractions by ticker the iteration starts from instead of the uniform allocation.
+    initial_fractions: Option<Vec<TickerAndFraction>>,
+    /// Declared macro scenarios weighting the outcomes, see [Portfolio::macro_scenarios].
+    macro_scenarios: Option<Vec<MacroScenario>>,
+    /// Constraints whose violations are penalized in the objective with their weights instead of
+    /// being enforced, see [KellyAllocator::with_soft_constraint].
+    soft_constraints: Vec<(Rc<dyn InequalityConstraint>, f64)>,
+    observer: Option<&'a dyn SolverObserver>,
 }
 
 impl<'a> KellyAllocator<'a> {
@@ -52,17 +321,174 @@ impl<'a> KellyAllocator<'a> {
         KellyAllocator {
             logger,
             max_iter,
+            solver_tolerance: SOLVER_TOLERANCE,
+            relaxation_factor: RELAXATION_FACTOR,
             inequality_constraints: vec![],
+            equality_constraints: vec![],
             has_long_only_constraint: false,
+            n_long_only_constraints: 0,
             has_max_total_leverage_constraint: false,
             has_max_individual_allocation_constraint: false,
+            n_max_individual_allocation_constraints: 0,
+            n_max_group_allocation_constraints: 0,
+            n_max_position_downside_constraints: 0,
+            min_individual_allocation: None,
+            n_min_individual_allocation_constraints: 0,
             has_max_permanent_loss_constraint: false,
+            has_fully_invested_constraint: false,
+            fully_invested_index: None,
+            market_neutral_indices: vec![],
+            financing_rates: FinancingRates::default(),
+            max_systems: DEFAULT_MAX_SYSTEMS,
+            lower_bounds: HashMap::new(),
+            upper_bounds: HashMap::new(),
+            strategy: SolverStrategy::default(),
+            regularization: 0.0,
+            turnover_penalty: 0.0,
+            current_fractions: vec![],
+            initial_fractions: None,
+            macro_scenarios: None,
+            soft_constraints: vec![],
+            observer: None,
+        }
+    }
+
+    /// Return a new [KellyAllocator] with an observer invoked after each Newton-Raphson iteration.
+    /// The contents of the original object are moved into the new one.
+    pub fn with_solver_observer(self, observer: &'a dyn SolverObserver) -> KellyAllocator<'a> {
+        KellyAllocator {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] with custom solver settings. The contents of the original
+    /// object are moved into the new one.
+    pub fn with_solver_settings(self, solver_settings: &SolverSettings) -> KellyAllocator<'a> {
+        let strategy = solver_settings.strategy.unwrap_or_default();
+        let regularization = solver_settings.regularization.unwrap_or(0.0);
+        if regularization < 0.0 {
+            panic!("Regularization strength cannot be negative. You provided {regularization}.")
+        }
+        let max_systems = solver_settings.max_systems.unwrap_or(DEFAULT_MAX_SYSTEMS);
+        let max_iter = solver_settings.max_iterations.unwrap_or(self.max_iter);
+        let solver_tolerance = solver_settings
+            .solver_tolerance
+            .unwrap_or(self.solver_tolerance);
+        if solver_tolerance <= 0.0 {
+            panic!("Solver tolerance must be positive. You provided {solver_tolerance}.")
+        }
+        let relaxation_factor = solver_settings
+            .relaxation_factor
+            .unwrap_or(self.relaxation_factor);
+        if relaxation_factor <= 0.0 || relaxation_factor > 1.0 {
+            panic!(
+                "Relaxation factor must be greater than 0 and at most 1. You provided \
+                {relaxation_factor}."
+            )
+        }
+        info!(
+            self.logger,
+            "Setting solver strategy to {:?}, regularization strength to {regularization} and \
+            maximum number of systems to {max_systems}.",
+            strategy
+        );
+        info!(
+            self.logger,
+            "Setting maximum number of iterations to {max_iter}, solver tolerance to \
+            {solver_tolerance} and relaxation factor to {relaxation_factor}."
+        );
+
+        if let Some(initial_fractions) = &solver_settings.initial_fractions {
+            info!(
+                self.logger,
+                "Starting from initial fractions for {} candidates.",
+                initial_fractions.len()
+            );
+        }
+
+        KellyAllocator {
+            strategy,
+            regularization,
+            max_systems,
+            max_iter,
+            solver_tolerance,
+            relaxation_factor,
+            initial_fractions: solver_settings.initial_fractions.clone(),
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] where the cash that isn't invested in the candidates earns the
+    /// given risk-free rate, e.g. 0.04 for 4% a year, instead of nothing. Leverage is borrowed at
+    /// the same rate unless a margin interest rate is given. The contents of the original object
+    /// are moved into the new one.
+    pub fn with_risk_free_rate(self, risk_free_rate: f64) -> KellyAllocator<'a> {
+        if risk_free_rate <= -1.0 {
+            panic!("Risk-free rate must be greater than -1. You provided {risk_free_rate}.")
+        }
+
+        info!(self.logger, "Setting risk-free rate to {risk_free_rate}.");
+
+        KellyAllocator {
+            financing_rates: FinancingRates {
+                risk_free_rate,
+                ..self.financing_rates
+            },
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] whose outcomes combine the scenarios placed in the given macro
+    /// scenarios with the declared probabilities of the latter. The contents of the original object
+    /// are moved into the new one.
+    pub fn with_macro_scenarios(self, macro_scenarios: &[MacroScenario]) -> KellyAllocator<'a> {
+        KellyAllocator {
+            macro_scenarios: Some(macro_scenarios.to_vec()),
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] where the capital borrowed for leverage costs the given margin
+    /// interest rate, which is subtracted from the outcomes of levered allocations. The contents of
+    /// the original object are moved into the new one.
+    pub fn with_margin_interest_rate(self, margin_interest_rate: f64) -> KellyAllocator<'a> {
+        if margin_interest_rate < self.financing_rates.risk_free_rate {
+            panic!(
+                "Margin interest rate must be at least the risk-free rate of {}. You provided \
+                {margin_interest_rate}.",
+                self.financing_rates.risk_free_rate
+            )
+        }
+
+        info!(
+            self.logger,
+            "Setting margin interest rate to {margin_interest_rate}."
+        );
+
+        KellyAllocator {
+            financing_rates: FinancingRates {
+                margin_interest_rate: Some(margin_interest_rate),
+                ..self.financing_rates
+            },
+            ..self
         }
     }
 
     /// Return a new [KellyAllocator] with a long-only constraint (no shorting), for all company
     /// candidates. The contents of the original object are moved into the new one.
     pub fn with_long_only_constraints(self, n_candidates: usize) -> KellyAllocator<'a> {
+        self.with_long_only_constraints_for(&(0..n_candidates).collect_vec(), n_candidates)
+    }
+
+    /// Return a new [KellyAllocator] with a long-only constraint (no shorting), for a subset of
+    /// company candidates given by their fraction indices. The contents of the original object are
+    /// moved into the new one.
+    pub fn with_long_only_constraints_for(
+        self,
+        fraction_indices: &[usize],
+        n_candidates: usize,
+    ) -> KellyAllocator<'a> {
         if self.has_long_only_constraint {
             panic!(
                 "Kelly allocator already initialized with long-only constraints. Did you call \
@@ -72,7 +498,8 @@ impl<'a> KellyAllocator<'a> {
 
         info!(
             self.logger,
-            "Setting long only constraint for all {n_candidates} candidates."
+            "Setting long only constraint for {} out of {n_candidates} candidates.",
+            fraction_indices.len()
         );
 
         if n_candidates < 1 {
@@ -82,22 +509,45 @@ impl<'a> KellyAllocator<'a> {
         // Fractions are always the first set of unknowns in the system.
         let mut new_constraints = self.inequality_constraints;
         new_constraints.extend(
-            (0..n_candidates)
-                .map(|i| {
-                    Box::new(LongOnlyConstraint::new(i, n_candidates))
-                        as Box<dyn InequalityConstraint>
+            fraction_indices
+                .iter()
+                .map(|&i| {
+                    Rc::new(LongOnlyConstraint::new(i, n_candidates))
+                        as Rc<dyn InequalityConstraint>
                 })
-                .collect::<Vec<Box<dyn InequalityConstraint>>>(),
+                .collect::<Vec<Rc<dyn InequalityConstraint>>>(),
         );
 
         KellyAllocator {
-            logger: self.logger,
-            max_iter: self.max_iter,
             inequality_constraints: new_constraints,
             has_long_only_constraint: true,
-            has_max_total_leverage_constraint: self.has_max_total_leverage_constraint,
-            has_max_individual_allocation_constraint: self.has_max_individual_allocation_constraint,
-            has_max_permanent_loss_constraint: self.has_max_permanent_loss_constraint,
+            lower_bounds: fraction_indices.iter().map(|&i| (i, 0.0)).collect(),
+            n_long_only_constraints: fraction_indices.len(),
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] where the candidates with the given fraction indices have no
+    /// net exposure, e.g. pair trades. Must be called before adding the maximum total leverage and
+    /// the fully invested constraints. The contents of the original object are moved into the new
+    /// one.
+    pub fn with_market_neutral_candidates(self, fraction_indices: &[usize]) -> KellyAllocator<'a> {
+        if self.has_max_total_leverage_constraint || self.has_fully_invested_constraint {
+            panic!(
+                "Kelly allocator already initialized with constraints on the net exposure. Did you \
+                call with_market_neutral_candidates after adding them?"
+            )
+        }
+
+        info!(
+            self.logger,
+            "Setting {} candidates without net exposure.",
+            fraction_indices.len()
+        );
+
+        KellyAllocator {
+            market_neutral_indices: fraction_indices.to_vec(),
+            ..self
         }
     }
 
@@ -124,8 +574,10 @@ impl<'a> KellyAllocator<'a> {
             panic!("Got {n_candidates} candidates. Can't add maximum total leverage constraint.")
         }
 
-        let constraint: Box<MaximumTotalLeverageConstraint> =
-            Box::new(MaximumTotalLeverageConstraint::new(max_total_leverage));
+        let constraint: Rc<MaximumTotalLeverageConstraint> = Rc::new(
+            MaximumTotalLeverageConstraint::new(max_total_leverage)
+                .excluding(&self.market_neutral_indices),
+        );
         info!(
             self.logger,
             "Setting maximum total leverage constraint: {:?}", constraint
@@ -136,13 +588,56 @@ impl<'a> KellyAllocator<'a> {
         new_constraints.push(constraint);
 
         KellyAllocator {
-            logger: self.logger,
-            max_iter: self.max_iter,
             inequality_constraints: new_constraints,
-            has_long_only_constraint: self.has_long_only_constraint,
             has_max_total_leverage_constraint: true,
-            has_max_individual_allocation_constraint: self.has_max_individual_allocation_constraint,
-            has_max_permanent_loss_constraint: self.has_max_permanent_loss_constraint,
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] with a constraint requiring that the fractions sum up to one,
+    /// i.e. that all capital is invested without leverage. The contents of the original object are
+    /// moved into the new one. Being an equality constraint, it doesn't increase the number of
+    /// systems to solve.
+    pub fn with_fully_invested_constraint(self, n_candidates: usize) -> KellyAllocator<'a> {
+        self.with_fixed_net_exposure_constraint(n_candidates, 1.0)
+    }
+
+    /// Same as [KellyAllocator::with_fully_invested_constraint], but requires that the fractions
+    /// sum up to the given net exposure, e.g. 1.3 for 30% leverage or 0.8 for 20% cash.
+    pub fn with_fixed_net_exposure_constraint(
+        self,
+        n_candidates: usize,
+        net_exposure: f64,
+    ) -> KellyAllocator<'a> {
+        if self.has_fully_invested_constraint {
+            panic!(
+                "Kelly allocator already initialized with fully invested constraint. Did you call \
+                with_fully_invested_constraint twice?"
+            )
+        }
+
+        if n_candidates < 1 {
+            panic!("Got {n_candidates} candidates. Can't add fully invested constraint.")
+        }
+
+        info!(
+            self.logger,
+            "Setting fully invested constraint with net exposure {net_exposure}."
+        );
+
+        let mut new_constraints = self.equality_constraints;
+        let fully_invested_index = new_constraints.len();
+        new_constraints.push(Rc::new(
+            FullyInvestedConstraint::default()
+                .with_net_exposure(net_exposure)
+                .excluding(&self.market_neutral_indices),
+        ));
+
+        KellyAllocator {
+            equality_constraints: new_constraints,
+            has_fully_invested_constraint: true,
+            fully_invested_index: Some(fully_invested_index),
+            ..self
         }
     }
 
@@ -152,6 +647,39 @@ impl<'a> KellyAllocator<'a> {
         self,
         n_candidates: usize,
         max_allocation: f64,
+    ) -> KellyAllocator<'a> {
+        self.with_maximum_individual_allocation_constraint_for(
+            &(0..n_candidates).collect_vec(),
+            n_candidates,
+            max_allocation,
+        )
+    }
+
+    /// Return a new [KellyAllocator] with a constraint for maximum allowable individual allocation,
+    /// for a subset of company candidates given by their fraction indices. The contents of the
+    /// original object are moved into the new one.
+    pub fn with_maximum_individual_allocation_constraint_for(
+        self,
+        fraction_indices: &[usize],
+        n_candidates: usize,
+        max_allocation: f64,
+    ) -> KellyAllocator<'a> {
+        self.with_maximum_individual_allocation_constraints(
+            &fraction_indices
+                .iter()
+                .map(|&i| (i, max_allocation))
+                .collect_vec(),
+            n_candidates,
+        )
+    }
+
+    /// Return a new [KellyAllocator] with a constraint for maximum allowable individual allocation
+    /// of each candidate given by its fraction index and its own maximum allocation. The contents
+    /// of the original object are moved into the new one.
+    pub fn with_maximum_individual_allocation_constraints(
+        self,
+        max_allocations: &[(usize, f64)],
+        n_candidates: usize,
     ) -> KellyAllocator<'a> {
         if self.has_max_individual_allocation_constraint {
             panic!(
@@ -162,7 +690,9 @@ impl<'a> KellyAllocator<'a> {
 
         info!(
             self.logger,
-            "Setting maximum individual allocation constraint for all {n_candidates} candidates."
+            "Setting maximum individual allocation constraint for {} out of {n_candidates} \
+            candidates.",
+            max_allocations.len()
         );
 
         if n_candidates < 1 {
@@ -172,25 +702,181 @@ impl<'a> KellyAllocator<'a> {
         // Fractions are always the first set of unknowns in the system.
         let mut new_constraints = self.inequality_constraints;
         new_constraints.extend(
-            (0..n_candidates)
-                .map(|i| {
-                    Box::new(MaximumIndividualAllocationConstraint::new(
+            max_allocations
+                .iter()
+                .map(|&(i, max_allocation)| {
+                    Rc::new(MaximumIndividualAllocationConstraint::new(
                         i,
                         max_allocation,
                         n_candidates,
-                    )) as Box<dyn InequalityConstraint>
+                    )) as Rc<dyn InequalityConstraint>
                 })
-                .collect::<Vec<Box<dyn InequalityConstraint>>>(),
+                .collect::<Vec<Rc<dyn InequalityConstraint>>>(),
         );
 
         KellyAllocator {
-            logger: self.logger,
-            max_iter: self.max_iter,
             inequality_constraints: new_constraints,
-            has_long_only_constraint: self.has_long_only_constraint,
-            has_max_total_leverage_constraint: self.has_max_total_leverage_constraint,
             has_max_individual_allocation_constraint: true,
-            has_max_permanent_loss_constraint: self.has_max_permanent_loss_constraint,
+            n_max_individual_allocation_constraints: max_allocations.len(),
+            upper_bounds: max_allocations.iter().copied().collect(),
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] where each long position is either zero or at least the given
+    /// minimum allocation, since tiny fractions can't be implemented in practice. This constraint
+    /// isn't convex, so it is enforced after solving, see [KellyAllocator::solve_with_outcomes].
+    /// The contents of the original object are moved into the new one.
+    pub fn with_minimum_individual_allocation_constraint(
+        self,
+        min_allocation: f64,
+    ) -> KellyAllocator<'a> {
+        if self.min_individual_allocation.is_some() {
+            panic!(
+                "Kelly allocator already initialized with minimum individual allocation \
+                constraint. Did you call with_minimum_individual_allocation_constraint twice?"
+            )
+        }
+
+        info!(
+            self.logger,
+            "Setting minimum individual allocation constraint of {min_allocation}."
+        );
+
+        KellyAllocator {
+            min_individual_allocation: Some(min_allocation),
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] with a constraint on the probability weighted worst-case
+    /// return of each position given by its fraction index and budget. Candidates without a
+    /// downside are skipped since they can't lose capital. Like the maximum individual allocation,
+    /// the constraints are upper bounds of the fractions, so they must be added after the maximum
+    /// individual allocation constraint. The contents of the original object are moved into the new
+    /// one.
+    pub fn with_maximum_position_downside_constraints(
+        self,
+        budgets: &[(usize, f64)],
+        candidates: &[Company],
+    ) -> KellyAllocator<'a> {
+        if self.n_max_position_downside_constraints > 0 {
+            panic!(
+                "Kelly allocator already initialized with maximum position downside constraints. \
+                Did you call with_maximum_position_downside_constraints twice?"
+            )
+        }
+
+        let n_candidates = candidates.len();
+        let budgets = budgets
+            .iter()
+            .filter(|(i, _)| candidates[*i].worst_probability_weighted_return() < 0.0)
+            .collect_vec();
+        info!(
+            self.logger,
+            "Setting maximum position downside constraint for {} out of {n_candidates} candidates.",
+            budgets.len()
+        );
+
+        let mut new_constraints = self.inequality_constraints;
+        let mut upper_bounds = self.upper_bounds;
+        for &&(i, budget) in &budgets {
+            new_constraints.push(Rc::new(MaximumPositionDownsideConstraint::new(
+                i,
+                budget,
+                n_candidates,
+            )));
+            let max_fraction = -budget / candidates[i].worst_probability_weighted_return();
+            let upper_bound = upper_bounds.entry(i).or_insert(f64::INFINITY);
+            *upper_bound = upper_bound.min(max_fraction);
+        }
+
+        KellyAllocator {
+            inequality_constraints: new_constraints,
+            n_max_position_downside_constraints: budgets.len(),
+            upper_bounds,
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] with a constraint for maximum allowable allocation to a group
+    /// of company candidates given by their fraction indices. May be called once for each group.
+    /// The contents of the original object are moved into the new one.
+    pub fn with_maximum_group_allocation_constraint(
+        self,
+        fraction_indices: &[usize],
+        n_candidates: usize,
+        max_allocation: f64,
+    ) -> KellyAllocator<'a> {
+        info!(
+            self.logger,
+            "Setting maximum group allocation constraint of {max_allocation} for {} out of \
+            {n_candidates} candidates.",
+            fraction_indices.len()
+        );
+
+        if n_candidates < 1 {
+            panic!("Got {n_candidates} candidates. Can't add maximum group allocation constraint.")
+        }
+
+        // Fractions are always the first set of unknowns in the system.
+        let mut new_constraints = self.inequality_constraints;
+        new_constraints.push(Rc::new(MaximumGroupAllocationConstraint::new(
+            fraction_indices.to_vec(),
+            max_allocation,
+            n_candidates,
+        )));
+
+        KellyAllocator {
+            inequality_constraints: new_constraints,
+            n_max_group_allocation_constraints: self.n_max_group_allocation_constraints + 1,
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] with a soft constraint, which may be violated at the cost of a
+    /// penalty of 0.5 * weight * violation^2 subtracted from the expected logarithmic growth.
+    /// Unlike the other constraints, it doesn't add an equation to the system and doesn't increase
+    /// the number of systems to solve. The contents of the original object are moved into the new
+    /// one.
+    pub fn with_soft_constraint(
+        self,
+        constraint: Rc<dyn InequalityConstraint>,
+        weight: f64,
+    ) -> KellyAllocator<'a> {
+        info!(self.logger, "Setting soft constraint with weight {weight}.");
+
+        let mut soft_constraints = self.soft_constraints;
+        soft_constraints.push((constraint, weight));
+        KellyAllocator {
+            soft_constraints,
+            ..self
+        }
+    }
+
+    /// Return a new [KellyAllocator] with a penalty of 0.5 * turnover_penalty * |f - w|^2
+    /// subtracted from the expected logarithmic growth, where w are the current fractions by
+    /// fraction index. Like the regularization, it doesn't change the number of systems to solve.
+    /// The contents of the original object are moved into the new one.
+    pub fn with_turnover_penalty(
+        self,
+        current_fractions: Vec<f64>,
+        turnover_penalty: f64,
+    ) -> KellyAllocator<'a> {
+        if turnover_penalty < 0.0 {
+            panic!("Turnover penalty cannot be negative. You provided {turnover_penalty}.")
+        }
+
+        info!(
+            self.logger,
+            "Setting turnover penalty of {turnover_penalty} for {} candidates.",
+            current_fractions.len()
+        );
+
+        KellyAllocator {
+            turnover_penalty,
+            current_fractions,
+            ..self
         }
     }
 
@@ -210,7 +896,7 @@ impl<'a> KellyAllocator<'a> {
         }
 
         // Note: Sign is negative by convention because this represents a loss of capital.
-        let constraint: Box<MaxCapitalLossConstraint> = Box::new(MaxCapitalLossConstraint::new(
+        let constraint: Rc<MaxCapitalLossConstraint> = Rc::new(MaxCapitalLossConstraint::new(
             -max_permanent_loss_constraint.fraction_of_capital
                 * max_permanent_loss_constraint.probability_of_loss,
         ));
@@ -223,13 +909,9 @@ impl<'a> KellyAllocator<'a> {
         new_constraints.push(constraint);
 
         KellyAllocator {
-            logger: self.logger,
-            max_iter: self.max_iter,
             inequality_constraints: new_constraints,
-            has_long_only_constraint: self.has_long_only_constraint,
-            has_max_total_leverage_constraint: self.has_max_total_leverage_constraint,
-            has_max_individual_allocation_constraint: self.has_max_individual_allocation_constraint,
             has_max_permanent_loss_constraint: true,
+            ..self
         }
     }
 
@@ -238,78 +920,408 @@ impl<'a> KellyAllocator<'a> {
     /// - M is the number of systems to solve, equal to 2^N_IC, where N_IC is the number of
     ///   inequality constraints, because each inequality constraint may be active and inactive. If
     ///   there are no inequality constraints, only one system is solved.
-    /// - N is the number of candidate companies plus the number of constraints.
+    /// - N is the number of candidate companies plus the number of constraints. Equality
+    ///   constraints are always active, so each of them adds an equation without doubling M.
+    ///
+    /// If the only constraints are long-only and maximum individual allocation constraints, which
+    /// bound the individual fractions, the allocation is found with a single projected Newton
+    /// iteration instead.
     pub fn allocate(&self, candidates: Vec<Company>) -> Result<Portfolio, Error> {
-        if self.has_max_permanent_loss_constraint && !self.has_long_only_constraint {
-            return Err(Error {
-                code: "maximum-capital-loss-constraint-works-only-with-long-only-strategy".to_string(),
-                message: "Maximum capital loss constraint can work only with long-only strategy (constraint). Either remove the capital loss constraint or add the long-only constraint.".to_string()
-            });
-        }
-
-        // Number of systems to solve is equal to 2^N_inequality_constraints
-        let n_inequality_constraints: usize = self.inequality_constraints.len();
-        let n_systems: usize = pow(2, n_inequality_constraints);
-        info!(
-            self.logger,
-            "Need to solve 2^{n_inequality_constraints} = {n_systems} systems."
-        );
-
-        // For now, refuse to solve more than 10 companies with all inequality constraints,
-        // resulting in 2^22 = 4 million nonlinear systems to solve.
-        if n_systems > pow(2, 22) {
-            return Err(Error {
-                code: "refusing-to-solve-more-than-4194304-systems".to_string(),
-                message: format!(
-                    "Solving more than 4194304 systems due to inequality constraints is \
-                    prohibited because it hasn't been tested thoroughly, although it should work. \
-                    You have {n_inequality_constraints} constraints resulting in {n_systems} \
-                    systems to solve."
-                ),
-            });
-        }
-
-        // Size of each system is equal to number of companies + number of constraints
-        let n_companies: usize = candidates.len();
-        info!(
-            self.logger,
-            "Solving the Kelly allocation problem for {n_companies} companies."
-        );
-
-        let system_size = candidates.len() + n_inequality_constraints;
-        info!(self.logger, "Size of each system is {system_size}.");
-
-        // Initial guess for fractions assumes uniform allocation across all companies
-        let uniform_fraction: f64 = 1.0 / n_companies as f64;
+        self.solve(candidates).map(|s| s.portfolio)
+    }
 
+    /// Same as [KellyAllocator::allocate], but also returns whether the solution is approximate.
+    /// If none of the systems has a viable solution, the allocation is approximated by maximizing
+    /// the growth with quadratic penalty terms for the violation of constraints. Scenarios with
+    /// negligible probabilities are expected to be removed from the candidates already, see
+    /// [Company::without_negligible_scenarios].
+    pub fn solve(&self, candidates: Vec<Company>) -> Result<KellySolution, Error> {
         // Get all outcomes for a list of candidates. Note that the fractions are not relevant here
         // since we only care about non-weighted company returns and probability.
-        let mut portfolio: Portfolio = Portfolio {
+        let portfolio: Portfolio = Portfolio {
             companies: candidates
                 .into_iter()
                 .map(|c| PortfolioCompany {
                     company: c,
-                    fraction: uniform_fraction,
+                    fraction: 0.0,
                 })
                 .collect(),
+            macro_scenarios: self.macro_scenarios.clone(),
         };
-        let outcomes: Vec<Outcome> = match all_outcomes(&portfolio) {
-            Ok(o) => o,
-            Err(e) => return Err(e),
-        };
+        let outcomes: Vec<Outcome> = all_outcomes(&portfolio)?;
+        let candidates = portfolio
+            .companies
+            .into_iter()
+            .map(|pc| pc.company)
+            .collect();
+
+        self.solve_with_outcomes(candidates, &outcomes)
+    }
 
-        // Vector for collecting all viable solutions (unknown result vectors)
-        let mut solutions: Vec<DVector<f64>> = Vec::with_capacity(n_systems);
+    /// Same as [KellyAllocator::solve], but with all outcomes of the candidates given instead of
+    /// enumerating them, which saves the enumeration when solving repeatedly for the same
+    /// candidates (see [crate::engine::CharlieEngine]). Scenarios with negligible probabilities are
+    /// expected to be removed from the candidates already.
+    ///
+    /// With a minimum individual allocation, the solution is post-processed in a loop: as long as a
+    /// long position is below the minimum, the smallest of them is decided by solving once with its
+    /// fraction forced to zero and once with its fraction bounded by the minimum, keeping the one
+    /// with the higher growth. The decisions are kept for the following positions, which greedily
+    /// approximates the search over all choices of the positions to hold with at most twice as many
+    /// solutions as there are candidates.
+    pub fn solve_with_outcomes(
+        &self,
+        candidates: Vec<Company>,
+        outcomes: &[Outcome],
+    ) -> Result<KellySolution, Error> {
+        let Some(min_allocation) = self.min_individual_allocation else {
+            return self.solve_continuous(candidates, outcomes);
+        };
 
-        // Loop through all combinations, where the unsigned integer index is used to figure out
-        // which constraint is active or inactive, based on its bit representation. Note that if
-        // there are no constraints, we still have n_systems = 1. Example with four bits for
-        // simplicity:
-        // 0 = 0000 Everything is false (inactive)
-        // 1 = 0001 First constraint is active, others are inactive
-        // 2 = 0010 Second constraint is active, others are inactive
+        let n_candidates = candidates.len();
+        let mut allocator = self.clone();
+        let mut solution = self.solve_continuous(candidates.clone(), outcomes)?;
+        let mut n_systems = solution.n_systems;
+        let mut decided_indices: Vec<usize> = vec![];
+        loop {
+            let below_minimum = solution
+                .portfolio
+                .companies
+                .iter()
+                .enumerate()
+                .filter(|(i, pc)| {
+                    !decided_indices.contains(i)
+                        && pc.fraction > SOLVER_TOLERANCE
+                        && pc.fraction < min_allocation - SOLVER_TOLERANCE
+                })
+                .min_by_key(|(_, pc)| OrderedFloat(pc.fraction));
+            let Some((i, pc)) = below_minimum else {
+                break;
+            };
+            info!(
+                self.logger,
+                "Allocation of {} to {} is below the minimum individual allocation of \
+                {min_allocation}, solving with it forced to zero and to the minimum.",
+                pc.fraction,
+                pc.company.ticker
+            );
+            decided_indices.push(i);
+
+            let mut best: Option<(f64, KellyAllocator<'a>, KellySolution)> = None;
+            let mut last_error: Option<Error> = None;
+            for is_held in [false, true] {
+                let Some(variant) =
+                    allocator.with_semi_continuous_choice(i, n_candidates, is_held, min_allocation)
+                else {
+                    continue;
+                };
+                match variant.solve_continuous(candidates.clone(), outcomes) {
+                    Ok(s) => {
+                        n_systems += s.n_systems;
+                        if best.as_ref().map_or(true, |(g, _, _)| s.objective > *g) {
+                            best = Some((s.objective, variant, s));
+                        }
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            match (best, last_error) {
+                (Some((_, variant, s)), _) => {
+                    allocator = variant;
+                    solution = s;
+                }
+                (None, e) => {
+                    return Err(e.expect("Forcing a fraction to zero is always attempted."));
+                }
+            }
+        }
+
+        Ok(KellySolution {
+            n_systems,
+            ..solution
+        })
+    }
+
+    /// Returns a clone of this allocator where the fraction with the given index is either forced
+    /// to zero or bounded from below by the minimum allocation, depending on whether the position
+    /// is held. None if the position can't be held because of a lower upper bound.
+    fn with_semi_continuous_choice(
+        &self,
+        fraction_index: usize,
+        n_candidates: usize,
+        is_held: bool,
+        min_allocation: f64,
+    ) -> Option<KellyAllocator<'a>> {
+        let mut allocator = self.clone();
+        let lower_bound = allocator
+            .lower_bounds
+            .entry(fraction_index)
+            .or_insert(f64::NEG_INFINITY);
+        let upper_bound = allocator
+            .upper_bounds
+            .entry(fraction_index)
+            .or_insert(f64::INFINITY);
+
+        let mut new_constraints: Vec<Rc<dyn InequalityConstraint>> = vec![];
+        if is_held {
+            if *upper_bound < min_allocation {
+                return None;
+            }
+            *lower_bound = lower_bound.max(min_allocation);
+            new_constraints.push(Rc::new(MinimumIndividualAllocationConstraint::new(
+                fraction_index,
+                min_allocation,
+                n_candidates,
+            )));
+        } else {
+            // Long-only candidates are already bounded by zero from below
+            if *lower_bound < 0.0 {
+                *lower_bound = 0.0;
+                new_constraints.push(Rc::new(MinimumIndividualAllocationConstraint::new(
+                    fraction_index,
+                    0.0,
+                    n_candidates,
+                )));
+            }
+            *upper_bound = upper_bound.min(0.0);
+            new_constraints.push(Rc::new(MaximumIndividualAllocationConstraint::new(
+                fraction_index,
+                0.0,
+                n_candidates,
+            )));
+        }
+
+        allocator.n_min_individual_allocation_constraints += new_constraints.len();
+        allocator.inequality_constraints.extend(new_constraints);
+        Some(allocator)
+    }
+
+    /// Solves the problem without the minimum individual allocation, see
+    /// [KellyAllocator::solve_with_outcomes]. If borrowing costs more than the risk-free rate, the
+    /// growth has a kink at zero cash. The growth is concave, so the allocation with the risk-free
+    /// rate for all cash is the solution if it holds cash, and the allocation with the margin
+    /// interest rate for all cash is the solution if it's levered. Otherwise, the solution is
+    /// fully invested.
+    fn solve_continuous(
+        &self,
+        candidates: Vec<Company>,
+        outcomes: &[Outcome],
+    ) -> Result<KellySolution, Error> {
+        let risk_free_rate = self.financing_rates.risk_free_rate;
+        let Some(margin_interest_rate) = self
+            .financing_rates
+            .margin_interest_rate
+            .filter(|&rate| rate > risk_free_rate && !self.has_fully_invested_constraint)
+        else {
+            return self.solve_at_single_rate(candidates, outcomes);
+        };
+        let at_single_rate = |rate: f64| KellyAllocator {
+            financing_rates: FinancingRates {
+                risk_free_rate: rate,
+                ..Default::default()
+            },
+            ..self.clone()
+        };
+
+        // Solutions at a single rate are compared with the objective at both rates
+        let with_objective = |s: KellySolution| KellySolution {
+            objective: self.regularized_growth(outcomes, &s.portfolio),
+            ..s
+        };
+        let unlevered =
+            at_single_rate(risk_free_rate).solve_at_single_rate(candidates.clone(), outcomes)?;
+        if cash(&unlevered.portfolio, &self.market_neutral_indices) >= 0.0 {
+            return Ok(with_objective(unlevered));
+        }
+        let levered = at_single_rate(margin_interest_rate)
+            .solve_at_single_rate(candidates.clone(), outcomes)?;
+        let n_systems = unlevered.n_systems + levered.n_systems;
+        if cash(&levered.portfolio, &self.market_neutral_indices) <= 0.0 {
+            return Ok(with_objective(KellySolution {
+                n_systems,
+                ..levered
+            }));
+        }
+
+        info!(
+            self.logger,
+            "Leverage doesn't pay off at the margin interest rate of {margin_interest_rate}, \
+            solving for the fully invested allocation."
+        );
+        let fully_invested = self
+            .clone()
+            .with_fully_invested_constraint(candidates.len())
+            .solve_at_single_rate(candidates, outcomes)?;
+        Ok(KellySolution {
+            n_systems: n_systems + fully_invested.n_systems,
+            net_exposure_multiplier: None,
+            ..fully_invested
+        })
+    }
+
+    /// Solves the problem without the minimum individual allocation for the cash earning or
+    /// costing the same rate whether the allocation is levered or not.
+    fn solve_at_single_rate(
+        &self,
+        candidates: Vec<Company>,
+        outcomes: &[Outcome],
+    ) -> Result<KellySolution, Error> {
+        // Capital loss constraint requires that none of the candidates can be shorted
+        if self.has_max_permanent_loss_constraint
+            && self.n_long_only_constraints != candidates.len()
+        {
+            return Err(Error {
+                code: "maximum-capital-loss-constraint-works-only-with-long-only-strategy".to_string(),
+                message: "Maximum capital loss constraint can work only with long-only strategy (constraint). Either remove the capital loss constraint or add the long-only constraint.".to_string()
+            });
+        }
+
+        // Number of systems to solve is equal to 2^N_inequality_constraints, which doesn't fit into
+        // an unsigned integer for 64 constraints or more
+        let n_inequality_constraints: usize = self.inequality_constraints.len();
+        let n_systems: Option<usize> = 1_usize.checked_shl(n_inequality_constraints as u32);
+        info!(
+            self.logger,
+            "Need to solve 2^{n_inequality_constraints} systems."
+        );
+
+        // Size of each system is equal to number of companies + number of constraints
+        let n_companies: usize = candidates.len();
+        info!(
+            self.logger,
+            "Solving the Kelly allocation problem for {n_companies} companies."
+        );
+
+        let system_size =
+            candidates.len() + n_inequality_constraints + self.equality_constraints.len();
+        info!(self.logger, "Size of each system is {system_size}.");
+        let tickers = candidates.iter().map(|c| c.ticker.clone()).collect_vec();
+        let on_system =
+            |active_constraints: Option<&BitSlice>, x: Option<&DVector<f64>>, is_viable: bool| {
+                if let Some(observer) = self.observer {
+                    observer.on_system(&SolvedSystem {
+                        tickers: &tickers,
+                        active_constraints,
+                        x,
+                        is_viable,
+                    });
+                }
+            };
+
+        // Initial guess for fractions assumes uniform allocation across all companies
+        let uniform_fraction: f64 = 1.0 / n_companies as f64;
+        let mut portfolio: Portfolio = Portfolio {
+            companies: candidates
+                .into_iter()
+                .map(|c| PortfolioCompany {
+                    company: c,
+                    fraction: uniform_fraction,
+                })
+                .collect(),
+            macro_scenarios: self.macro_scenarios.clone(),
+        };
+
+        // The uniform initial guess is at the singularity of the logarithm if the companies may be
+        // lost entirely at the same time (e.g. bets), in which case a share is kept in cash
+        if may_lose_everything(
+            outcomes,
+            &portfolio,
+            self.financing_rates,
+            &self.market_neutral_indices,
+        ) {
+            let initial_fraction = 1.0 / (n_companies + 1) as f64;
+            info!(
+                self.logger,
+                "Uniform allocation may lose everything, starting from {initial_fraction} instead."
+            );
+            portfolio
+                .companies
+                .iter_mut()
+                .for_each(|pc| pc.fraction = initial_fraction);
+        }
+
+        // Start from the given initial fractions instead, unless they may lose everything
+        if let Some(initial_fractions) = &self.initial_fractions {
+            let mut initial = portfolio.clone();
+            initial.companies.iter_mut().for_each(|pc| {
+                pc.fraction = initial_fractions
+                    .iter()
+                    .find(|f| f.ticker == pc.company.ticker)
+                    .map_or(0.0, |f| f.fraction)
+            });
+            if may_lose_everything(
+                outcomes,
+                &initial,
+                self.financing_rates,
+                &self.market_neutral_indices,
+            ) {
+                warn!(
+                    self.logger,
+                    "Initial fractions may lose everything, ignoring them."
+                );
+            } else {
+                portfolio = initial;
+            }
+        }
+
+        // Long-only and maximum individual allocation constraints are bounds of the fractions, for
+        // which the active constraints are found by projecting onto the bounds instead of
+        // enumerating all combinations. Enumeration is used as a fallback if this fails.
+        if self.has_only_box_bounds() {
+            match self.solve_box_bounded(portfolio.clone(), outcomes) {
+                Ok(x) => {
+                    on_system(None, Some(&x), true);
+                    portfolio
+                        .companies
+                        .iter_mut()
+                        .enumerate()
+                        .for_each(|(i, pc)| pc.fraction = x[i]);
+                    expected_return(&portfolio, self.logger);
+                    worst_case_outcome(&portfolio, self.logger);
+                    return Ok(KellySolution {
+                        objective: self.regularized_growth(outcomes, &portfolio),
+                        portfolio,
+                        is_approximate: false,
+                        n_systems: 1,
+                        net_exposure_multiplier: None,
+                    });
+                }
+                Err(e) => warn!(
+                    self.logger,
+                    "Projected Newton method failed, solving all systems instead. Error was {:?}",
+                    e
+                ),
+            }
+        }
+
+        // Refuse to solve more systems than the limit, estimating how long it would take from the
+        // time needed to solve a single system
+        let n_systems: usize = match n_systems.filter(|n| *n <= self.max_systems) {
+            Some(n) => n,
+            None => {
+                let start = Instant::now();
+                let all_inactive = BitVec::<usize, Lsb0>::repeat(false, n_inequality_constraints);
+                let _ = self.solve_system(portfolio.clone(), outcomes, &all_inactive);
+                return Err(self.too_many_systems_error(start.elapsed().as_secs_f64()));
+            }
+        };
+
+        // Vector for collecting all viable solutions (unknown result vectors)
+        let mut solutions: Vec<DVector<f64>> = Vec::with_capacity(n_systems);
+
+        // Loop through all combinations, where the unsigned integer index is used to figure out
+        // which constraint is active or inactive, based on its bit representation. Note that if
+        // there are no constraints, we still have n_systems = 1. Example with four bits for
+        // simplicity:
+        // 0 = 0000 Everything is false (inactive)
+        // 1 = 0001 First constraint is active, others are inactive
+        // 2 = 0010 Second constraint is active, others are inactive
         // ...
         let mut all_error_strings: String = "".to_string();
+        let mut warm_start: Option<Portfolio> = None;
+        if let Some(observer) = self.observer {
+            observer.on_enumeration(n_systems as u64);
+        }
         (0..n_systems).for_each(|index| {
             // Look at the bits of the integer to figure out whether a constraint is active.
             // Starting from least significant bit, indicating the status of first constraint.
@@ -331,7 +1343,38 @@ impl<'a> KellyAllocator<'a> {
                 }
             });
 
-            let result = self.solve_system(portfolio.clone(), &outcomes, is_constraint_active);
+            // Warm-start from the fractions of the previously converged system, which are usually
+            // close to the solution of this one, falling back to the initial guess if that fails
+            let result = match &warm_start {
+                Some(start) => self
+                    .solve_system(start.clone(), outcomes, is_constraint_active)
+                    .or_else(|e| {
+                        debug!(
+                            self.logger,
+                            "Warm-started system failed, starting from the initial guess. Error \
+                            was {:?}",
+                            e
+                        );
+                        self.solve_system(portfolio.clone(), outcomes, is_constraint_active)
+                    }),
+                None => self.solve_system(portfolio.clone(), outcomes, is_constraint_active),
+            };
+            if let Ok(x) = &result {
+                let mut start = portfolio.clone();
+                start
+                    .companies
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, pc)| pc.fraction = x[i]);
+                if !may_lose_everything(
+                    outcomes,
+                    &start,
+                    self.financing_rates,
+                    &self.market_neutral_indices,
+                ) {
+                    warm_start = Some(start);
+                }
+            }
 
             // Check the result and:
             // 1. If the solution is not viable, ignore it. The solution is considered not viable
@@ -342,14 +1385,19 @@ impl<'a> KellyAllocator<'a> {
             //    other good solutions to pick from. TODO: Think more about when this can happen.
             match result {
                 Ok(x) => {
-                    if (0..n_inequality_constraints).any(|c_id| {
-                        !is_constraint_active[c_id] && x[n_companies + c_id] < TOLERANCE
-                    }) {
+                    // Comparisons with NaN are false, so non-finite solutions are never viable
+                    if x.iter().any(|v| !v.is_finite())
+                        || (0..n_inequality_constraints).any(|c_id| {
+                            !is_constraint_active[c_id] && x[n_companies + c_id] < TOLERANCE
+                        })
+                    {
                         info!(
                             self.logger,
                             "Solution is not viable, skipping it. Solution vector: {x}."
                         );
+                        on_system(Some(is_constraint_active), Some(&x), false);
                     } else {
+                        on_system(Some(is_constraint_active), Some(&x), true);
                         debug!(
                             self.logger,
                             "This is a viable solution. Adding it to the list of all solutions. \
@@ -359,6 +1407,7 @@ impl<'a> KellyAllocator<'a> {
                     }
                 }
                 Err(e) => {
+                    on_system(Some(is_constraint_active), None, false);
                     all_error_strings.push_str(&format!("    {:?}: {:?}\n", index, e));
                     info!(
                         self.logger,
@@ -368,19 +1417,44 @@ impl<'a> KellyAllocator<'a> {
             }
         });
 
-        // Fail if there are no solutions
+        // Fall back to the penalty method if there are no solutions, and fail if that doesn't
+        // work either
         info!(self.logger, "Found {} viable solutions.", solutions.len());
         if solutions.is_empty() {
-            return Err(Error {
-                code: "did-not-find-a-single-viable-solution".to_string(),
-                message: format!(
-                    "Did not manage to find a single viable numerical solution. \
+            warn!(
+                self.logger,
+                "Did not find a viable solution. Approximating the solution with the penalty method."
+            );
+            return match self.solve_with_penalty(portfolio.clone(), outcomes) {
+                Ok(x) => {
+                    on_system(None, Some(&x), true);
+                    portfolio
+                        .companies
+                        .iter_mut()
+                        .enumerate()
+                        .for_each(|(i, pc)| pc.fraction = x[i]);
+                    Ok(KellySolution {
+                        objective: self.regularized_growth(outcomes, &portfolio),
+                        portfolio,
+                        is_approximate: true,
+                        n_systems: n_systems + 1,
+                        net_exposure_multiplier: None,
+                    })
+                }
+                // Non-finite values are reported as such, since they point to the input data
+                Err(e) if e.code == NON_FINITE_VALUE_CODE => Err(e),
+                Err(e) => Err(Error {
+                    code: "did-not-find-a-single-viable-solution".to_string(),
+                    message: format!(
+                        "Did not manage to find a single viable numerical solution. \
                          This may happen for multiple reasons. Check whether the input data would \
                          suggest a very strong bias towards a single/few investments. Check whether \
                          the constraints are too strict.\n\
-                         Errors in individual solutions are {}:", all_error_strings
-                ),
-            });
+                         Errors in individual solutions are {}:\n\
+                         Error of the penalty method is {:?}", all_error_strings, e
+                    ),
+                }),
+            };
         }
 
         // The best solution is considered to be the one that has a highest expected return among
@@ -428,16 +1502,18 @@ impl<'a> KellyAllocator<'a> {
             OrderedFloat(expected_return(&p, self.logger))
         });
 
-        match best_solution {
+        let net_exposure_multiplier = match best_solution {
             Some(x) => {
                 portfolio
                     .companies
                     .iter_mut()
                     .enumerate()
                     .for_each(|(i, pc)| pc.fraction = x[i]);
+                self.fully_invested_index
+                    .map(|i| x[n_companies + n_inequality_constraints + i])
             }
             None => panic!("Did not find a best solution. This should logically only happen if the solutions vector is empty, for which we fail before. If this happens, it's a bug.")
-        }
+        };
 
         // Print out some information for the final portfolio
         info!(
@@ -447,113 +1523,215 @@ impl<'a> KellyAllocator<'a> {
         expected_return(&portfolio, self.logger);
         worst_case_outcome(&portfolio, self.logger);
 
-        Ok(portfolio)
+        Ok(KellySolution {
+            objective: self.regularized_growth(outcomes, &portfolio),
+            portfolio,
+            is_approximate: false,
+            n_systems,
+            net_exposure_multiplier,
+        })
+    }
+    /// Error for more systems to solve than the limit, with an estimate of the time needed to solve
+    /// them and the constraints of the allocation input whose removal reduces the number of systems
+    /// the most. The constraints are suggested largest first until the number of systems is within
+    /// the limit.
+    fn too_many_systems_error(&self, seconds_per_system: f64) -> Error {
+        let n_constraints = self.inequality_constraints.len();
+
+        // Long-only constraints can't be removed without the capital loss constraint, which
+        // requires them
+        let mut constraint_counts = vec![
+            (
+                if self.has_max_permanent_loss_constraint {
+                    "long_only and max_permanent_loss_of_capital"
+                } else {
+                    "long_only"
+                },
+                self.n_long_only_constraints + self.has_max_permanent_loss_constraint as usize,
+            ),
+            (
+                "max_individual_allocation",
+                self.n_max_individual_allocation_constraints,
+            ),
+            ("candidate_groups", self.n_max_group_allocation_constraints),
+            (
+                "max_position_downside",
+                self.n_max_position_downside_constraints,
+            ),
+            (
+                "min_individual_allocation",
+                self.n_min_individual_allocation_constraints,
+            ),
+            (
+                "max_total_leverage_ratio",
+                self.has_max_total_leverage_constraint as usize,
+            ),
+        ];
+        constraint_counts.retain(|(_, count)| *count > 0);
+        constraint_counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        let reductions = constraint_counts
+            .iter()
+            .map(|(name, count)| {
+                format!(
+                    "removing {name} reduces the systems from 2^{n_constraints} to 2^{}",
+                    n_constraints - count
+                )
+            })
+            .join(", ");
+
+        let mut remaining = n_constraints;
+        let mut to_remove: Vec<&str> = vec![];
+        for (name, count) in &constraint_counts {
+            if 1_usize
+                .checked_shl(remaining as u32)
+                .is_some_and(|n| n <= self.max_systems)
+            {
+                break;
+            }
+            to_remove.push(name);
+            remaining -= count;
+        }
+
+        Error {
+            code: "too-many-systems-to-solve".to_string(),
+            message: format!(
+                "Solving 2^{n_constraints} systems due to {n_constraints} inequality constraints \
+                exceeds the limit of {} systems, and would take {}. Note that {reductions}. \
+                Remove {} to get within the limit, or raise the limit with max_systems in the \
+                solver settings.",
+                self.max_systems,
+                approximate_duration(seconds_per_system * 2.0_f64.powi(n_constraints as i32)),
+                to_remove.join(" and ")
+            ),
+        }
+    }
+
+    /// Solves a system given a portfolio, all outcomes and constraint activity mask with the
+    /// strategy from the solver settings. For the automatic strategy, the plain, line search and
+    /// trust region strategies are tried in that order until one of them converges.
+    fn solve_system(
+        &self,
+        portfolio: Portfolio,
+        outcomes: &[Outcome],
+        is_constraint_active: &BitSlice,
+    ) -> Result<DVector<f64>, Error> {
+        let strategies = match self.strategy {
+            SolverStrategy::Auto => vec![
+                SolverStrategy::Plain,
+                SolverStrategy::LineSearch,
+                SolverStrategy::TrustRegion,
+            ],
+            strategy => vec![strategy],
+        };
+
+        let mut result = Err(Error {
+            code: "no-solver-strategy".to_string(),
+            message: "No solver strategy to solve the system with.".to_string(),
+        });
+        for strategy in strategies {
+            result = self.solve_system_with_strategy(
+                strategy,
+                portfolio.clone(),
+                outcomes,
+                is_constraint_active,
+            );
+            match &result {
+                Ok(_) => break,
+                // Only escalate if the iteration didn't converge, since other failures (e.g. a
+                // singular Jacobian) are mostly independent of the strategy
+                Err(e) if e.code == "nonlinear-loop-didnt-converge" => info!(
+                    self.logger,
+                    "Strategy {:?} failed with error {:?}.", strategy, e
+                ),
+                Err(_) => break,
+            }
+        }
+
+        result
     }
 
     /// Solves a system given a portfolio, all outcomes and constraint activity mask. The solution
     /// is found iteratively using the Newton-Raphson method since the resulting system is
-    /// nonlinear. Constraints are added to the system based on their status (active/inactive).
-    fn solve_system(
+    /// nonlinear. The given strategy determines how the Newton step updates the solution.
+    fn solve_system_with_strategy(
         &self,
+        strategy: SolverStrategy,
         mut portfolio: Portfolio,
         outcomes: &[Outcome],
         is_constraint_active: &BitSlice,
     ) -> Result<DVector<f64>, Error> {
         let n_companies = portfolio.companies.len();
-        let n_constraints = self.inequality_constraints.len();
+        let n_constraints = self.inequality_constraints.len() + self.equality_constraints.len();
         let n = n_companies + n_constraints;
 
-        // Initialize vector of unknowns (x) with uniform fractions for companies, leaving potential
-        // lagrange multipliers and slack variables initialized to zero (if n_constraints > 0)
+        // Initialize vector of unknowns (x) with the initial fractions of the portfolio, leaving
+        // potential lagrange multipliers and slack variables initialized to zero (if
+        // n_constraints > 0)
         let mut x: DVector<f64> = DVector::from_element(n, 0.0);
-        let uniform_fraction = 1.0 / n_companies as f64;
-        (0..n_companies).for_each(|id| x[id] = uniform_fraction);
+        (0..n_companies).for_each(|id| x[id] = portfolio.companies[id].fraction);
 
+        let mut trust_radius: f64 = INITIAL_TRUST_RADIUS;
         let mut counter: u32 = 0;
         loop {
-            // Update the fractions in the portfolio for calculating Kelly function and Jacobian
-            portfolio
-                .companies
-                .iter_mut()
-                .enumerate()
-                .for_each(|(i, pc)| pc.fraction = x[i]);
-
-            let mut jacobian: DMatrix<f64> = Self::criterion_jacobian(outcomes, &portfolio);
-            let mut right_hand_side: DVector<f64> = -Self::criterion(outcomes, &portfolio);
-
-            // Extend the matrix and RHS vector if we have constraints
-            jacobian = jacobian.insert_columns(n_companies, n_constraints, 0.0);
-            jacobian = jacobian.insert_rows(n_companies, n_constraints, 0.0);
-            right_hand_side = right_hand_side.insert_rows(n_companies, n_constraints, 0.0);
+            let (jacobian, right_hand_side) =
+                self.linearized_system(&mut portfolio, outcomes, &x, is_constraint_active);
+            check_finite(&jacobian, &right_hand_side, &portfolio)?;
 
-            for cid in 0..n_constraints {
-                let constraint: &dyn InequalityConstraint =
-                    self.inequality_constraints[cid].as_ref();
-
-                let d_constraint_d_fractions: DVector<f64> =
-                    constraint.d_constraint_d_fractions(&portfolio);
-
-                let offset_cid = n_companies + cid;
-
-                // Notes on signs of contributions:
-                // 1. The constraint contributions to the Jacobian is negative, because the term
-                //    with the Lagrangian multiplier in the Lagrangian is negative since we're
-                //    seeking a local maximum.
-                // 2. The constraint contributions to the right-hand-side are positive, because of
-                //    the same reason as in 1, and because in the linearized Newton-Raphson form
-                //    the right-hand-side function value is negative. Hence, two negations make a
-                //    positive sign.
-                // This is a bit confusing, and I'm not sure how to simplify it...
-
-                // Constraint contribution is always added to the lower triangular row for this
-                // constraint, regardless whether it's active or inactive
-                for (eid, &elem) in d_constraint_d_fractions.iter().enumerate() {
-                    jacobian[(offset_cid, eid)] = -elem;
-                }
-
-                if is_constraint_active[cid] {
-                    // Lagrange multiplier value from the previous iteration
-                    let lambda = x[offset_cid];
-
-                    // For active constraint, we have:
-                    // 1. The upper triangular contribution (column) for this constraint.
-                    // 2. Diagonal element of constraint equation remains zero.
-                    // 3. The right-hand-side contribution for fraction equations.
-                    for (eid, &elem) in d_constraint_d_fractions.iter().enumerate() {
-                        jacobian[(eid, offset_cid)] = -elem;
-                        right_hand_side[eid] += lambda * elem;
+            // Solve for delta_x and update the current solution vector
+            let (delta_x, jacobian_condition_number) =
+                match Self::newton_step(&jacobian, &right_hand_side) {
+                    Ok(s) => s,
+                    Err(c) => {
+                        return Err(Error {
+                            code: "jacobian-inversion-failed".to_string(),
+                            message: format!(
+                            "Did not manage to find the numerical solution. This may happen if \
+                            the input data would suggest a very strong bias towards a single/few \
+                            investments. Check your input. The Jacobian is singular or \
+                            ill-conditioned with an estimated condition number of {c:e}."
+                        ),
+                        })
                     }
-
-                    // 4. The right-hand side contribution for the constraint equation.
-                    right_hand_side[offset_cid] += constraint.function_value(&portfolio, 0.0);
-                } else {
-                    // For inactive constraint, we have:
-                    // 1. The upper triangular column for this constraint remains 0.
-                    // 2. Diagonal element of constraint equation is always -1.
-                    // 3. The right-hand-side contribution for the constraint equations.
-                    jacobian[(offset_cid, offset_cid)] = -1.0;
-
-                    let slack_variable = x[offset_cid];
-                    right_hand_side[offset_cid] +=
-                        constraint.function_value(&portfolio, slack_variable);
+                };
+
+            // Half of the squared norm of the residual after taking a step, used as the merit
+            // function by the line search and trust region strategies
+            let mut merit_after = |step: &DVector<f64>| -> f64 {
+                let (_, trial_right_hand_side) = self.linearized_system(
+                    &mut portfolio,
+                    outcomes,
+                    &(&x + step),
+                    is_constraint_active,
+                );
+                0.5 * trial_right_hand_side.norm_squared()
+            };
+            let step: DVector<f64> = match strategy {
+                SolverStrategy::LineSearch => {
+                    self.line_search_step(&delta_x, &right_hand_side, &mut merit_after)
+                }
+                SolverStrategy::TrustRegion => self.trust_region_step(
+                    &delta_x,
+                    (&jacobian, &right_hand_side),
+                    &mut trust_radius,
+                    &mut merit_after,
+                ),
+                SolverStrategy::Plain | SolverStrategy::Auto => {
+                    let is_unconstrained =
+                        self.equality_constraints.is_empty() && is_constraint_active.not_any();
+                    let mut growth_after = |step: &DVector<f64>| -> f64 {
+                        let trial_x = &x + step;
+                        portfolio
+                            .companies
+                            .iter_mut()
+                            .enumerate()
+                            .for_each(|(i, pc)| pc.fraction = trial_x[i]);
+                        self.regularized_growth(outcomes, &portfolio)
+                    };
+                    self.damped_step(&delta_x, is_unconstrained, &mut growth_after)
                 }
-            }
-
-            // Solve for delta_x and update the current solution vector
-            let inverse_jacobian: DMatrix<f64> = match jacobian.try_inverse() {
-                Some(s) => s,
-                None => return Err(Error {
-                    code: "jacobian-inversion-failed".to_string(),
-                    message:
-                    "Did not manage to find the numerical solution. This may happen if the input \
-                        data would suggest a very strong bias towards a single/few investments. \
-                        Check your input."
-                        .to_string(),
-                }),
             };
-
-            let delta_x: DVector<f64> = inverse_jacobian * &right_hand_side;
-            x += RELAXATION_FACTOR * &delta_x;
+            x += step;
 
             // Convergence check (with Chebyshev/L-infinity norm)
             let residual = delta_x.abs().max();
@@ -561,7 +1739,16 @@ impl<'a> KellyAllocator<'a> {
                 self.logger,
                 "Residual: {residual}. Performing convergence check."
             );
-            if residual < SOLVER_TOLERANCE {
+            if let Some(observer) = self.observer {
+                observer.on_iteration(&SolverIteration {
+                    iteration: counter,
+                    x: &x,
+                    residual,
+                    condition_number: jacobian_condition_number,
+                    penalty_factor: None,
+                });
+            }
+            if residual < self.solver_tolerance {
                 info!(
                     self.logger,
                     "Newton-Raphson converged in {counter} iterations with residual {residual}."
@@ -588,29 +1775,721 @@ impl<'a> KellyAllocator<'a> {
         Ok(x)
     }
 
-    /// Calculates the Kelly criterion given all outcomes and portfolio
-    fn criterion(outcomes: &[Outcome], portfolio: &Portfolio) -> DVector<f64> {
+    /// Solves the linearized system for the Newton step with the LU decomposition of the Jacobian,
+    /// together with the condition number estimated from its pivots. Large systems are decomposed
+    /// as sparse matrices since the rows and columns of the constraints are mostly zero. Returns
+    /// the estimated condition number as the error if the Jacobian is singular or too
+    /// ill-conditioned for a reliable step.
+    fn newton_step(
+        jacobian: &DMatrix<f64>,
+        right_hand_side: &DVector<f64>,
+    ) -> Result<(DVector<f64>, f64), f64> {
+        // Nothing to solve if all the fractions are at their bounds
+        if jacobian.is_empty() {
+            return Ok((DVector::zeros(0), 1.0));
+        }
+
+        let (delta_x, estimate) = if jacobian.nrows() >= SPARSE_SOLVE_THRESHOLD {
+            let lu = SparseLu::new(jacobian).ok_or(f64::INFINITY)?;
+            (
+                lu.solve(right_hand_side),
+                condition_number_estimate(lu.pivots()),
+            )
+        } else {
+            let lu = jacobian.clone().lu();
+            let estimate = condition_number_estimate(lu.u().diagonal().iter().copied());
+            (lu.solve(right_hand_side).ok_or(estimate)?, estimate)
+        };
+
+        // Note that the comparison is false if the estimate is NaN
+        if estimate <= MAX_CONDITION_NUMBER {
+            Ok((delta_x, estimate))
+        } else {
+            Err(estimate)
+        }
+    }
+
+    /// Returns the Newton step damped adaptively: starting from the full step, the step is halved
+    /// until the wealth stays positive in all outcomes and, for systems without active constraints,
+    /// the growth doesn't decrease. Active constraints may require giving up growth to reach the
+    /// feasible fractions, so only the total loss is guarded against for them. Falls back to the
+    /// relaxed step if none of the step lengths qualifies.
+    fn damped_step(
+        &self,
+        delta_x: &DVector<f64>,
+        is_unconstrained: bool,
+        growth_after: &mut dyn FnMut(&DVector<f64>) -> f64,
+    ) -> DVector<f64> {
+        let growth = growth_after(&DVector::zeros(delta_x.len()));
+        let mut step_length: f64 = 1.0;
+        for _ in 0..MAX_STEP_REDUCTIONS {
+            let trial_growth = growth_after(&(step_length * delta_x));
+            if trial_growth.is_finite()
+                && (!is_unconstrained || trial_growth >= growth - GROWTH_DECREASE_TOLERANCE)
+            {
+                debug!(self.logger, "Damped step length: {step_length}.");
+                return step_length * delta_x;
+            }
+            step_length *= 0.5;
+        }
+
+        debug!(
+            self.logger,
+            "Did not find a damped step, taking the relaxed step instead."
+        );
+        self.relaxation_factor * delta_x
+    }
+
+    /// Returns the Newton step shortened by backtracking until the norm of the residual decreases
+    /// sufficiently (Armijo condition), or the shortest tried step if that never happens.
+    fn line_search_step(
+        &self,
+        delta_x: &DVector<f64>,
+        right_hand_side: &DVector<f64>,
+        merit_after: &mut dyn FnMut(&DVector<f64>) -> f64,
+    ) -> DVector<f64> {
+        let merit = 0.5 * right_hand_side.norm_squared();
+        let mut step_length: f64 = 1.0;
+        for _ in 0..MAX_STEP_REDUCTIONS {
+            // Note that the comparison is false if the trial merit is NaN, e.g. due to a total loss
+            let trial_merit = merit_after(&(step_length * delta_x));
+            if trial_merit <= (1.0 - 2.0 * SUFFICIENT_DECREASE * step_length) * merit {
+                break;
+            }
+            step_length *= 0.5;
+        }
+
+        debug!(self.logger, "Line search step length: {step_length}.");
+        step_length * delta_x
+    }
+
+    /// Returns a dogleg step within the trust region, combining the steepest descent step and the
+    /// Newton step for the norm of the residual. The trust radius is adapted based on the ratio of
+    /// the actual and the predicted decrease of the residual, and the step is retried with a
+    /// smaller radius until the ratio is acceptable.
+    fn trust_region_step(
+        &self,
+        delta_x: &DVector<f64>,
+        (jacobian, right_hand_side): (&DMatrix<f64>, &DVector<f64>),
+        trust_radius: &mut f64,
+        merit_after: &mut dyn FnMut(&DVector<f64>) -> f64,
+    ) -> DVector<f64> {
+        let merit = 0.5 * right_hand_side.norm_squared();
+
+        // Gradient of the merit function and the steepest descent (Cauchy) step minimizing the
+        // linear model along the gradient. Note that the function value is -right_hand_side.
+        let gradient: DVector<f64> = -jacobian.transpose() * right_hand_side;
+        let jacobian_gradient: DVector<f64> = jacobian * &gradient;
+        let cauchy_step: DVector<f64> =
+            -(gradient.norm_squared() / jacobian_gradient.norm_squared()) * &gradient;
+
+        let mut step: DVector<f64> = delta_x.clone();
+        for _ in 0..MAX_STEP_REDUCTIONS {
+            step = if delta_x.norm() <= *trust_radius {
+                delta_x.clone()
+            } else if cauchy_step.norm() >= *trust_radius {
+                -(*trust_radius / gradient.norm()) * &gradient
+            } else {
+                // Find tau in [0, 1] such that |cauchy + tau * (newton - cauchy)| = radius
+                let difference: DVector<f64> = delta_x - &cauchy_step;
+                let a = difference.norm_squared();
+                let b = 2.0 * cauchy_step.dot(&difference);
+                let c = cauchy_step.norm_squared() - *trust_radius * *trust_radius;
+                let tau = (-b + (b * b - 4.0 * a * c).sqrt()) / (2.0 * a);
+                &cauchy_step + tau * difference
+            };
+
+            let predicted_decrease =
+                merit - 0.5 * (jacobian * &step - right_hand_side).norm_squared();
+            let actual_decrease = merit - merit_after(&step);
+            let ratio = actual_decrease / predicted_decrease;
+
+            // Note that all comparisons are false if the ratio is NaN, e.g. due to a total loss
+            if ratio > 0.75 && step.norm() >= 0.99 * *trust_radius {
+                *trust_radius = (2.0 * *trust_radius).min(MAX_TRUST_RADIUS);
+            } else if ratio < 0.25 || ratio.is_nan() {
+                *trust_radius *= 0.25;
+            }
+
+            if ratio > SUFFICIENT_DECREASE {
+                break;
+            }
+        }
+
+        debug!(
+            self.logger,
+            "Trust region step norm: {}, trust radius: {}.",
+            step.norm(),
+            trust_radius
+        );
+        step
+    }
+
+    /// Assembles the Jacobian and the right-hand side of the linearized system at the solution
+    /// vector x. Constraints are added to the system based on their status (active/inactive). The
+    /// fractions in the portfolio are updated with the ones from the solution vector.
+    fn linearized_system(
+        &self,
+        portfolio: &mut Portfolio,
+        outcomes: &[Outcome],
+        x: &DVector<f64>,
+        is_constraint_active: &BitSlice,
+    ) -> (DMatrix<f64>, DVector<f64>) {
+        let n_companies = portfolio.companies.len();
+        let n_constraints = self.inequality_constraints.len();
+        let n_equality_constraints = self.equality_constraints.len();
+
+        // Update the fractions in the portfolio for calculating Kelly function and Jacobian
+        portfolio
+            .companies
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, pc)| pc.fraction = x[i]);
+
+        let mut jacobian: DMatrix<f64> = Self::criterion_jacobian(
+            outcomes,
+            portfolio,
+            self.financing_rates,
+            &self.market_neutral_indices,
+        );
+        let mut right_hand_side: DVector<f64> = -Self::criterion(
+            outcomes,
+            portfolio,
+            self.financing_rates,
+            &self.market_neutral_indices,
+        );
+        self.regularize(portfolio, &mut jacobian, &mut right_hand_side);
+
+        // Extend the matrix and RHS vector if we have constraints. Equality constraints come after
+        // the inequality constraints.
+        let n_all_constraints = n_constraints + n_equality_constraints;
+        jacobian = jacobian.insert_columns(n_companies, n_all_constraints, 0.0);
+        jacobian = jacobian.insert_rows(n_companies, n_all_constraints, 0.0);
+        right_hand_side = right_hand_side.insert_rows(n_companies, n_all_constraints, 0.0);
+
+        for cid in 0..n_constraints {
+            let constraint: &dyn InequalityConstraint = self.inequality_constraints[cid].as_ref();
+
+            let d_constraint_d_fractions: DVector<f64> =
+                constraint.d_constraint_d_fractions(portfolio);
+
+            let offset_cid = n_companies + cid;
+
+            // Notes on signs of contributions:
+            // 1. The constraint contributions to the Jacobian is negative, because the term
+            //    with the Lagrangian multiplier in the Lagrangian is negative since we're
+            //    seeking a local maximum.
+            // 2. The constraint contributions to the right-hand-side are positive, because of
+            //    the same reason as in 1, and because in the linearized Newton-Raphson form
+            //    the right-hand-side function value is negative. Hence, two negations make a
+            //    positive sign.
+            // This is a bit confusing, and I'm not sure how to simplify it...
+
+            // Constraint contribution is always added to the lower triangular row for this
+            // constraint, regardless whether it's active or inactive
+            for (eid, &elem) in d_constraint_d_fractions.iter().enumerate() {
+                jacobian[(offset_cid, eid)] = -elem;
+            }
+
+            if is_constraint_active[cid] {
+                // Lagrange multiplier value from the previous iteration
+                let lambda = x[offset_cid];
+
+                // For active constraint, we have:
+                // 1. The upper triangular contribution (column) for this constraint.
+                // 2. Diagonal element of constraint equation remains zero.
+                // 3. The right-hand-side contribution for fraction equations.
+                for (eid, &elem) in d_constraint_d_fractions.iter().enumerate() {
+                    jacobian[(eid, offset_cid)] = -elem;
+                    right_hand_side[eid] += lambda * elem;
+                }
+
+                // 4. The right-hand side contribution for the constraint equation.
+                right_hand_side[offset_cid] += constraint.function_value(portfolio, 0.0);
+            } else {
+                // For inactive constraint, we have:
+                // 1. The upper triangular column for this constraint remains 0.
+                // 2. Diagonal element of constraint equation is always -1.
+                // 3. The right-hand-side contribution for the constraint equations.
+                jacobian[(offset_cid, offset_cid)] = -1.0;
+
+                let slack_variable = x[offset_cid];
+                right_hand_side[offset_cid] += constraint.function_value(portfolio, slack_variable);
+            }
+        }
+
+        // Equality constraints contribute in the same way as active inequality constraints
+        for eq_cid in 0..n_equality_constraints {
+            let constraint: &dyn EqualityConstraint = self.equality_constraints[eq_cid].as_ref();
+            let d_constraint_d_fractions: DVector<f64> =
+                constraint.d_constraint_d_fractions(portfolio);
+            let offset_cid = n_companies + n_constraints + eq_cid;
+            let lambda = x[offset_cid];
+
+            for (eid, &elem) in d_constraint_d_fractions.iter().enumerate() {
+                jacobian[(offset_cid, eid)] = -elem;
+                jacobian[(eid, offset_cid)] = -elem;
+                right_hand_side[eid] += lambda * elem;
+            }
+            right_hand_side[offset_cid] += constraint.function_value(portfolio, 0.0);
+        }
+
+        (jacobian, right_hand_side)
+    }
+    /// True if the only constraints are bounds of the individual fractions, i.e. long-only, maximum
+    /// and minimum individual allocation and maximum position downside constraints.
+    fn has_only_box_bounds(&self) -> bool {
+        !self.inequality_constraints.is_empty()
+            && self.equality_constraints.is_empty()
+            && !self.has_max_total_leverage_constraint
+            && !self.has_max_permanent_loss_constraint
+            && self.n_max_group_allocation_constraints == 0
+    }
+
+    /// Calculates the expected logarithmic growth minus the regularization penalty for the
+    /// fractions of the portfolio. Outcomes that wipe out all capital make it negative infinity.
+    fn regularized_growth(&self, outcomes: &[Outcome], portfolio: &Portfolio) -> f64 {
+        outcomes
+            .iter()
+            .map(|o| {
+                let wealth = wealth(
+                    o,
+                    portfolio,
+                    self.financing_rates,
+                    &self.market_neutral_indices,
+                );
+                if wealth > 0.0 {
+                    o.probability * wealth.ln()
+                } else {
+                    f64::NEG_INFINITY
+                }
+            })
+            .sum::<f64>()
+            - regularization_penalty(portfolio, self.regularization)
+            - self.turnover_penalty_of(portfolio)
+            - self
+                .soft_constraints
+                .iter()
+                .map(|(c, weight)| 0.5 * weight * c.function_value(portfolio, 0.0).max(0.0).powi(2))
+                .sum::<f64>()
+    }
+
+    /// Maximizes the growth within the bounds of the fractions with the projected Newton method:
+    /// the fractions at a bound whose gradient points out of the bounds are kept fixed, the Newton
+    /// step is taken for the remaining (free) fractions, and the updated fractions are projected
+    /// onto the bounds. The step is shortened by backtracking until the growth increases
+    /// sufficiently. Each iteration solves a single linear system, so the effort grows linearly
+    /// with the number of bounds instead of exponentially.
+    fn solve_box_bounded(
+        &self,
+        mut portfolio: Portfolio,
+        outcomes: &[Outcome],
+    ) -> Result<DVector<f64>, Error> {
+        let n_companies = portfolio.companies.len();
+        let lower = |i: usize| *self.lower_bounds.get(&i).unwrap_or(&f64::NEG_INFINITY);
+        let upper = |i: usize| *self.upper_bounds.get(&i).unwrap_or(&f64::INFINITY);
+        let project = |x: &DVector<f64>| {
+            DVector::from_iterator(
+                n_companies,
+                x.iter()
+                    .enumerate()
+                    .map(|(i, f)| f.clamp(lower(i), upper(i))),
+            )
+        };
+        let set_fractions = |portfolio: &mut Portfolio, x: &DVector<f64>| {
+            portfolio
+                .companies
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, pc)| pc.fraction = x[i]);
+        };
+
+        let mut x: DVector<f64> = project(&DVector::from_iterator(
+            n_companies,
+            portfolio.companies.iter().map(|pc| pc.fraction),
+        ));
+        set_fractions(&mut portfolio, &x);
+        let mut growth = self.regularized_growth(outcomes, &portfolio);
+        if !growth.is_finite() {
+            return Err(Error {
+                code: "box-bounded-initial-guess-loses-everything".to_string(),
+                message: "Initial guess of the projected Newton method loses all capital in at \
+                    least one outcome."
+                    .to_string(),
+            });
+        }
+
+        for counter in 0..=self.max_iter {
+            let mut jacobian: DMatrix<f64> = Self::criterion_jacobian(
+                outcomes,
+                &portfolio,
+                self.financing_rates,
+                &self.market_neutral_indices,
+            );
+            let mut right_hand_side: DVector<f64> = -Self::criterion(
+                outcomes,
+                &portfolio,
+                self.financing_rates,
+                &self.market_neutral_indices,
+            );
+            self.regularize(&portfolio, &mut jacobian, &mut right_hand_side);
+            check_finite(&jacobian, &right_hand_side, &portfolio)?;
+            let gradient: DVector<f64> = -right_hand_side;
+
+            let free: Vec<usize> = (0..n_companies)
+                .filter(|&i| {
+                    !(x[i] <= lower(i) + TOLERANCE && gradient[i] < 0.0
+                        || x[i] >= upper(i) - TOLERANCE && gradient[i] > 0.0)
+                })
+                .collect();
+            let free_jacobian: DMatrix<f64> = jacobian.select_rows(&free).select_columns(&free);
+            let (free_step, free_jacobian_condition_number) =
+                match Self::newton_step(&free_jacobian, &-gradient.select_rows(&free)) {
+                    Ok(s) => s,
+                    Err(c) => {
+                        return Err(Error {
+                            code: "box-bounded-jacobian-inversion-failed".to_string(),
+                            message: format!(
+                                "Did not manage to take a projected Newton step in iteration \
+                                {counter} because the Jacobian is singular or ill-conditioned \
+                                (estimated condition number {c:e})."
+                            ),
+                        })
+                    }
+                };
+            let mut step: DVector<f64> = DVector::zeros(n_companies);
+            free.iter()
+                .enumerate()
+                .for_each(|(k, &i)| step[i] = free_step[k]);
+
+            // Residual is the change of the fractions by the projected Newton step, which vanishes
+            // at the optimum within the bounds
+            let x_newton: DVector<f64> = project(&(&x + &step));
+            let residual = (&x_newton - &x).abs().max();
+            if residual < self.solver_tolerance {
+                info!(
+                    self.logger,
+                    "Projected Newton method converged in {counter} iterations with residual \
+                    {residual}."
+                );
+                return Ok(x_newton);
+            }
+
+            let mut step_length = 1.0;
+            let mut is_accepted = false;
+            for _ in 0..=MAX_STEP_REDUCTIONS {
+                let x_trial: DVector<f64> = project(&(&x + step_length * &step));
+                set_fractions(&mut portfolio, &x_trial);
+                let trial_growth = self.regularized_growth(outcomes, &portfolio);
+                if trial_growth >= growth + SUFFICIENT_DECREASE * gradient.dot(&(&x_trial - &x)) {
+                    x = x_trial;
+                    growth = trial_growth;
+                    is_accepted = true;
+                    break;
+                }
+                step_length *= 0.5;
+            }
+            if !is_accepted {
+                return Err(Error {
+                    code: "box-bounded-line-search-failed".to_string(),
+                    message: format!(
+                        "Did not manage to increase the growth with the projected Newton method \
+                        in iteration {counter}."
+                    ),
+                });
+            }
+            set_fractions(&mut portfolio, &x);
+
+            if let Some(observer) = self.observer {
+                observer.on_iteration(&SolverIteration {
+                    iteration: counter,
+                    x: &x,
+                    residual,
+                    condition_number: free_jacobian_condition_number,
+                    penalty_factor: None,
+                });
+            }
+        }
+
+        Err(Error {
+            code: "box-bounded-nonlinear-loop-didnt-converge".to_string(),
+            message: format!(
+                "Projected Newton method did not converge in {} iterations.",
+                self.max_iter
+            ),
+        })
+    }
+
+    /// Approximates the solution by maximizing the growth with quadratic penalty terms for the
+    /// violation of constraints, i.e. by solving the unconstrained system where each violated
+    /// constraint g(f) > 0 (or g(f) != 0 for equality constraints) contributes the term
+    /// -mu * g(f) * dg/df. The system is solved with the Newton-Raphson method for increasing
+    /// penalty factors mu, starting each solution from the previous one. The constraints may
+    /// therefore be slightly violated in the final solution.
+    fn solve_with_penalty(
+        &self,
+        mut portfolio: Portfolio,
+        outcomes: &[Outcome],
+    ) -> Result<DVector<f64>, Error> {
+        let n_companies = portfolio.companies.len();
+        let mut x: DVector<f64> = DVector::from_iterator(
+            n_companies,
+            portfolio.companies.iter().map(|pc| pc.fraction),
+        );
+
+        for penalty_factor in PENALTY_FACTORS {
+            info!(
+                self.logger,
+                "Solving the penalized system with penalty factor {penalty_factor}."
+            );
+
+            let mut counter: u32 = 0;
+            loop {
+                portfolio
+                    .companies
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, pc)| pc.fraction = x[i]);
+
+                let mut jacobian: DMatrix<f64> = Self::criterion_jacobian(
+                    outcomes,
+                    &portfolio,
+                    self.financing_rates,
+                    &self.market_neutral_indices,
+                );
+                let mut right_hand_side: DVector<f64> = -Self::criterion(
+                    outcomes,
+                    &portfolio,
+                    self.financing_rates,
+                    &self.market_neutral_indices,
+                );
+                self.regularize(&portfolio, &mut jacobian, &mut right_hand_side);
+                check_finite(&jacobian, &right_hand_side, &portfolio)?;
+
+                // Only the violated constraints contribute to the system
+                for constraint in self.inequality_constraints.iter() {
+                    let violation = constraint.function_value(&portfolio, 0.0);
+                    if violation > 0.0 {
+                        let d_constraint_d_fractions: DVector<f64> =
+                            constraint.d_constraint_d_fractions(&portfolio);
+                        jacobian -= penalty_factor
+                            * &d_constraint_d_fractions
+                            * d_constraint_d_fractions.transpose();
+                        right_hand_side += penalty_factor * violation * &d_constraint_d_fractions;
+                    }
+                }
+
+                // Equality constraints are violated in both directions
+                for constraint in self.equality_constraints.iter() {
+                    let violation = constraint.function_value(&portfolio, 0.0);
+                    let d_constraint_d_fractions: DVector<f64> =
+                        constraint.d_constraint_d_fractions(&portfolio);
+                    jacobian -= penalty_factor
+                        * &d_constraint_d_fractions
+                        * d_constraint_d_fractions.transpose();
+                    right_hand_side += penalty_factor * violation * &d_constraint_d_fractions;
+                }
+
+                let (delta_x, jacobian_condition_number) =
+                    match Self::newton_step(&jacobian, &right_hand_side) {
+                        Ok(s) => s,
+                        Err(c) => {
+                            return Err(Error {
+                                code: "penalty-jacobian-inversion-failed".to_string(),
+                                message: format!(
+                                    "Did not manage to approximate the solution with penalty \
+                                    factor {penalty_factor} because the Jacobian is singular or \
+                                    ill-conditioned (estimated condition number {c:e})."
+                                ),
+                            })
+                        }
+                    };
+                x += self.relaxation_factor * &delta_x;
+
+                let residual = delta_x.abs().max();
+                if let Some(observer) = self.observer {
+                    observer.on_iteration(&SolverIteration {
+                        iteration: counter,
+                        x: &x,
+                        residual,
+                        condition_number: jacobian_condition_number,
+                        penalty_factor: Some(penalty_factor),
+                    });
+                }
+                if residual < self.solver_tolerance {
+                    info!(
+                        self.logger,
+                        "Penalized Newton-Raphson converged in {counter} iterations with residual \
+                        {residual}."
+                    );
+                    break;
+                }
+
+                if counter >= self.max_iter {
+                    return Err(Error {
+                        code: "penalty-nonlinear-loop-didnt-converge".to_string(),
+                        message: format!(
+                            "Did not manage to approximate the solution with penalty factor \
+                            {penalty_factor} in {counter} iterations."
+                        ),
+                    });
+                }
+
+                counter += 1;
+            }
+        }
+
+        Ok(x)
+    }
+
+    /// Calculates the penalty of the trades from the current fractions, see
+    /// [KellyAllocator::with_turnover_penalty].
+    fn turnover_penalty_of(&self, portfolio: &Portfolio) -> f64 {
+        if self.turnover_penalty == 0.0 {
+            return 0.0;
+        }
+
+        0.5 * self.turnover_penalty
+            * portfolio
+                .companies
+                .iter()
+                .zip(&self.current_fractions)
+                .map(|(pc, w)| (pc.fraction - w).powi(2))
+                .sum::<f64>()
+    }
+
+    /// Adds the contribution of the L2 regularization of the fractions, of the turnover penalty and
+    /// of the penalties of the violated soft constraints to the Jacobian and the right-hand side of
+    /// the Kelly criterion. The penalty -0.5 * regularization * |f|^2 adds -regularization * f to
+    /// the criterion and -regularization to the diagonal of its Jacobian, and the turnover penalty
+    /// does the same for the trades f - w. The penalty -0.5 * weight * g^2 of a soft
+    /// constraint violated by g adds -weight * g * dg/df to the criterion and
+    /// -weight * dg/df * dg/df^T to its Jacobian, like in [KellyAllocator::solve_with_penalty].
+    fn regularize(
+        &self,
+        portfolio: &Portfolio,
+        jacobian: &mut DMatrix<f64>,
+        right_hand_side: &mut DVector<f64>,
+    ) {
+        for (constraint, weight) in &self.soft_constraints {
+            let violation = constraint.function_value(portfolio, 0.0);
+            if violation > 0.0 {
+                let d_constraint_d_fractions: DVector<f64> =
+                    constraint.d_constraint_d_fractions(portfolio);
+                *jacobian -=
+                    *weight * &d_constraint_d_fractions * d_constraint_d_fractions.transpose();
+                *right_hand_side += *weight * violation * &d_constraint_d_fractions;
+            }
+        }
+
+        if self.turnover_penalty != 0.0 {
+            for (i, (pc, w)) in portfolio
+                .companies
+                .iter()
+                .zip(&self.current_fractions)
+                .enumerate()
+            {
+                jacobian[(i, i)] -= self.turnover_penalty;
+                right_hand_side[i] += self.turnover_penalty * (pc.fraction - w);
+            }
+        }
+
+        if self.regularization == 0.0 {
+            return;
+        }
+
+        for (i, pc) in portfolio.companies.iter().enumerate() {
+            jacobian[(i, i)] -= self.regularization;
+            right_hand_side[i] += self.regularization * pc.fraction;
+        }
+    }
+
+    /// Calculates the curvature of the growth surface at the solution from the Jacobian of the
+    /// Kelly criterion, together with its eigenvalues and eigenvectors, and the expected growth
+    /// with and without the regularization penalty. Note that neither the constraints nor the
+    /// regularization are taken into account for the curvatures.
+    pub fn solver_metadata(
+        outcomes: &[Outcome],
+        portfolio: &Portfolio,
+        is_approximate: bool,
+        regularization: f64,
+        n_systems: usize,
+        financing_rates: FinancingRates,
+        market_neutral_indices: &[usize],
+    ) -> SolverMetadata {
+        let jacobian: DMatrix<f64> =
+            Self::criterion_jacobian(outcomes, portfolio, financing_rates, market_neutral_indices);
+
+        let curvatures = portfolio
+            .companies
+            .iter()
+            .enumerate()
+            .map(|(i, pc)| TickerAndCurvature {
+                ticker: pc.company.ticker.clone(),
+                curvature: jacobian[(i, i)],
+            })
+            .collect_vec();
+
+        let eigen = jacobian.symmetric_eigen();
+        let principal_curvatures = eigen
+            .eigenvalues
+            .iter()
+            .zip(eigen.eigenvectors.column_iter())
+            .map(|(&curvature, direction)| PrincipalCurvature {
+                curvature,
+                direction: direction.iter().cloned().collect_vec(),
+            })
+            .sorted_by_key(|pc| OrderedFloat(pc.curvature.abs()))
+            .collect_vec();
+
+        let growth = expected_log_growth(outcomes);
+        SolverMetadata {
+            is_approximate,
+            n_systems,
+            expected_log_growth: growth,
+            regularized_expected_log_growth: growth
+                - regularization_penalty(portfolio, regularization),
+            curvatures,
+            principal_curvatures,
+        }
+    }
+
+    /// Calculates the Kelly criterion given all outcomes and portfolio, where the cash that isn't
+    /// invested in the candidates with net exposure earns the risk-free rate and the borrowed cash
+    /// costs the margin interest rate. The returns of these candidates enter in excess of the rate
+    /// of the cash position.
+    pub(crate) fn criterion(
+        outcomes: &[Outcome],
+        portfolio: &Portfolio,
+        financing_rates: FinancingRates,
+        market_neutral_indices: &[usize],
+    ) -> DVector<f64> {
+        let rate = financing_rates.rate(cash(portfolio, market_neutral_indices));
         DVector::from_iterator(
             portfolio.companies.len(),
-            portfolio.companies.iter().map(|pc_outer| {
+            portfolio.companies.iter().enumerate().map(|(i, pc_outer)| {
                 outcomes
                     .iter()
                     .map(|o| {
-                        o.probability * o.company_returns[&pc_outer.company.ticker]
-                            / (1.0
-                                + portfolio
-                                    .companies
-                                    .iter()
-                                    .map(|pc| pc.fraction * o.company_returns[&pc.company.ticker])
-                                    .sum::<f64>())
+                        o.probability
+                            * excess_return(o, i, &pc_outer.company, rate, market_neutral_indices)
+                            / wealth(o, portfolio, financing_rates, market_neutral_indices)
                     })
                     .sum::<f64>()
             }),
         )
     }
 
-    /// Calculates the Jacobian for the Kelly function given all outcomes and portfolio
-    fn criterion_jacobian(outcomes: &[Outcome], portfolio: &Portfolio) -> DMatrix<f64> {
+    /// Calculates the Jacobian for the Kelly function given all outcomes and portfolio, with the
+    /// financing of the cash as in [KellyAllocator::criterion]. The jump of the rate at zero cash
+    /// is ignored.
+    fn criterion_jacobian(
+        outcomes: &[Outcome],
+        portfolio: &Portfolio,
+        financing_rates: FinancingRates,
+        market_neutral_indices: &[usize],
+    ) -> DMatrix<f64> {
+        let rate = financing_rates.rate(cash(portfolio, market_neutral_indices));
         let n_companies: usize = portfolio.companies.len();
         let mut jacobian: DMatrix<f64> = DMatrix::zeros(n_companies, n_companies);
 
@@ -625,15 +2504,15 @@ impl<'a> KellyAllocator<'a> {
                     .iter()
                     .map(|o| {
                         o.probability
-                            * o.company_returns[&row_company.ticker]
-                            * o.company_returns[&column_company.ticker]
-                            * (1.0
-                                + portfolio
-                                    .companies
-                                    .iter()
-                                    .map(|pc| pc.fraction * o.company_returns[&pc.company.ticker])
-                                    .sum::<f64>())
-                            .pow(-2)
+                            * excess_return(o, row_index, row_company, rate, market_neutral_indices)
+                            * excess_return(
+                                o,
+                                column_index,
+                                column_company,
+                                rate,
+                                market_neutral_indices,
+                            )
+                            * wealth(o, portfolio, financing_rates, market_neutral_indices).pow(-2)
                     })
                     .sum::<f64>();
 
@@ -650,6 +2529,7 @@ impl<'a> KellyAllocator<'a> {
 #[cfg(test)]
 mod test {
     use crate::analysis::worst_case_outcome;
+    use std::cell::RefCell;
     use std::collections::HashMap;
 
     use crate::env::create_test_logger;
@@ -670,16 +2550,21 @@ mod test {
                 ticker: "A".to_string(),
                 description: "A bet with 100% upside and 50% downside, with probabilities 50-50".to_string(),
                 market_cap: 1e7,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
                 scenarios: vec![
                     Scenario {
                         thesis: "A1".to_string(),
                         intrinsic_value: 2e7,
                         probability: 0.5,
+                        ..Default::default()
                     },
                     Scenario {
                         thesis: "A2".to_string(),
                         intrinsic_value: 5e6,
                         probability: 0.5,
+                        ..Default::default()
                     },
                 ],
             },
@@ -688,16 +2573,21 @@ mod test {
                 ticker: "B".to_string(),
                 description: "A bet with 50% upside with 70% probability, and 30% downside with 30% probability".to_string(),
                 market_cap: 1e7,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
                 scenarios: vec![
                     Scenario {
                         thesis: "B1".to_string(),
                         intrinsic_value: 1.5e7,
                         probability: 0.7,
+                        ..Default::default()
                     },
                     Scenario {
                         thesis: "B2".to_string(),
                         intrinsic_value: 7e6,
                         probability: 0.3,
+                        ..Default::default()
                     },
                 ],
             },
@@ -717,6 +2607,7 @@ mod test {
                     fraction: 0.5,
                 },
             ],
+            ..Default::default()
         };
 
         let outcomes: Vec<Outcome> = vec![
@@ -746,31 +2637,581 @@ mod test {
             },
         ];
 
-        (portfolio, outcomes)
+        (portfolio, outcomes)
+    }
+
+    #[test]
+    fn test_kelly() {
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let (portfolio, outcomes): (Portfolio, Vec<Outcome>) = generate_test_data(&test_candidates);
+
+        let kelly =
+            KellyAllocator::criterion(&outcomes, &portfolio, FinancingRates::default(), &[]);
+
+        assert_close!(0.011111111, kelly[0], ASSERTION_TOLERANCE);
+        assert_close!(0.166666666, kelly[1], ASSERTION_TOLERANCE);
+    }
+
+    #[test]
+    fn test_kelly_jacobian() {
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let (portfolio, outcomes): (Portfolio, Vec<Outcome>) = generate_test_data(&test_candidates);
+
+        let jacobian = KellyAllocator::criterion_jacobian(
+            &outcomes,
+            &portfolio,
+            FinancingRates::default(),
+            &[],
+        );
+
+        assert_close!(-0.388256908, jacobian[(0, 0)], ASSERTION_TOLERANCE);
+        assert_close!(-0.007451499, jacobian[(0, 1)], ASSERTION_TOLERANCE);
+        assert_close!(-0.007451499, jacobian[(1, 0)], ASSERTION_TOLERANCE);
+        assert_close!(-0.160978836, jacobian[(1, 1)], ASSERTION_TOLERANCE);
+    }
+
+    #[test]
+    fn test_solver_metadata() {
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let (portfolio, outcomes): (Portfolio, Vec<Outcome>) = generate_test_data(&test_candidates);
+
+        let metadata = KellyAllocator::solver_metadata(
+            &outcomes,
+            &portfolio,
+            false,
+            2.0,
+            1,
+            FinancingRates::default(),
+            &[],
+        );
+
+        let sum_of_squared_fractions = portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.fraction * pc.fraction)
+            .sum::<f64>();
+        assert_close!(
+            metadata.expected_log_growth - sum_of_squared_fractions,
+            metadata.regularized_expected_log_growth,
+            ASSERTION_TOLERANCE
+        );
+
+        assert_eq!(metadata.curvatures[0].ticker, "A");
+        assert_close!(
+            -0.388256908,
+            metadata.curvatures[0].curvature,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            -0.160978836,
+            metadata.curvatures[1].curvature,
+            ASSERTION_TOLERANCE
+        );
+
+        // The flattest direction comes first and is dominated by the second company
+        let flattest = &metadata.principal_curvatures[0];
+        assert_close!(-0.160734795, flattest.curvature, ASSERTION_TOLERANCE);
+        assert!(flattest.direction[1].abs() > flattest.direction[0].abs());
+        assert_close!(
+            -0.388500949,
+            metadata.principal_curvatures[1].curvature,
+            ASSERTION_TOLERANCE
+        );
+    }
+
+    /// Tests that the regularization shrinks the fractions, and that the regularized criterion
+    /// vanishes at the solution.
+    #[test]
+    fn test_allocate_with_regularization() {
+        let logger = create_test_logger();
+        let regularization = 0.5;
+        let unregularized: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .allocate(generate_test_candidates())
+            .unwrap();
+        let regularized: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_settings(&SolverSettings {
+                strategy: None,
+                regularization: Some(regularization),
+                max_systems: None,
+                initial_fractions: None,
+                max_iterations: None,
+                solver_tolerance: None,
+                relaxation_factor: None,
+            })
+            .allocate(generate_test_candidates())
+            .unwrap();
+
+        regularized
+            .companies
+            .iter()
+            .zip(unregularized.companies.iter())
+            .for_each(|(r, u)| assert!(r.fraction.abs() < u.fraction.abs()));
+
+        let outcomes = all_outcomes(&regularized).unwrap();
+        let criterion =
+            KellyAllocator::criterion(&outcomes, &regularized, FinancingRates::default(), &[]);
+        for (i, pc) in regularized.companies.iter().enumerate() {
+            assert_close!(
+                0.0,
+                criterion[i] - regularization * pc.fraction,
+                ASSERTION_TOLERANCE
+            );
+        }
+    }
+
+    /// Observer counting the Newton-Raphson iterations and recording the size of each enumeration.
+    #[derive(Default)]
+    struct IterationCounter {
+        n_iterations: std::cell::Cell<u32>,
+        enumerations: RefCell<Vec<u64>>,
+    }
+
+    impl SolverObserver for IterationCounter {
+        fn on_iteration(&self, _iteration: &SolverIteration) {
+            self.n_iterations.set(self.n_iterations.get() + 1);
+        }
+
+        fn on_enumeration(&self, n_systems: u64) {
+            self.enumerations.borrow_mut().push(n_systems);
+        }
+    }
+
+    /// Tests that starting from the solution converges immediately to the same solution.
+    #[test]
+    fn test_allocate_with_initial_fractions() {
+        let logger = create_test_logger();
+        let uniform_start = IterationCounter::default();
+        let solution: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_observer(&uniform_start)
+            .allocate(generate_test_candidates())
+            .unwrap();
+
+        let warm_start = IterationCounter::default();
+        let warm_started: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_settings(&SolverSettings {
+                initial_fractions: Some(
+                    solution
+                        .companies
+                        .iter()
+                        .map(|pc| TickerAndFraction {
+                            ticker: pc.company.ticker.clone(),
+                            fraction: pc.fraction,
+                        })
+                        .collect(),
+                ),
+                ..SolverSettings::default()
+            })
+            .with_solver_observer(&warm_start)
+            .allocate(generate_test_candidates())
+            .unwrap();
+
+        assert!(warm_start.n_iterations.get() < uniform_start.n_iterations.get());
+        for (w, s) in warm_started.companies.iter().zip(solution.companies.iter()) {
+            assert_close!(s.fraction, w.fraction, ASSERTION_TOLERANCE);
+        }
+    }
+
+    /// Tests that the adaptive damping of the plain strategy converges quickly for a stiff single
+    /// candidate, which loses almost everything in one scenario and returns 100x in the other.
+    #[test]
+    fn test_allocate_stiff_single_candidate_with_plain_strategy() {
+        let logger = create_test_logger();
+        let candidate: Company = serde_yaml::from_str(
+            "
+            name: Stiff
+            ticker: S
+            description: Loses 99% or returns 100x with probabilities 50-50
+            market_cap: 100.0
+            scenarios:
+              - thesis: Lost
+                intrinsic_value: 1.0
+                probability: 0.5
+              - thesis: Found
+                intrinsic_value: 10000.0
+                probability: 0.5
+            ",
+        )
+        .unwrap();
+
+        let counter = IterationCounter::default();
+        let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_settings(&SolverSettings {
+                strategy: Some(SolverStrategy::Plain),
+                ..SolverSettings::default()
+            })
+            .with_solver_observer(&counter)
+            .allocate(vec![candidate])
+            .unwrap();
+
+        assert_close!(0.5, portfolio.companies[0].fraction, ASSERTION_TOLERANCE);
+        assert!(counter.n_iterations.get() < 12);
+    }
+
+    /// Tests that a soft maximum individual allocation is exceeded by less for larger weights,
+    /// where the marginal growth at the solution balances the marginal penalty.
+    #[test]
+    fn test_allocate_with_soft_constraint() {
+        let logger = create_test_logger();
+        let candidate: Company = serde_yaml::from_str(
+            "
+            name: Coin
+            ticker: C
+            description: Doubles or halves with probabilities 50-50
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+            ",
+        )
+        .unwrap();
+        let marginal_growth = |f: f64| 0.5 / (1.0 + f) - 0.25 / (1.0 - 0.5 * f);
+
+        let mut previous_fraction = 0.5;
+        for weight in [1.0, 10.0, 1000.0] {
+            let portfolio = KellyAllocator::new(&logger, MAX_ITER)
+                .with_soft_constraint(
+                    Rc::new(MaximumIndividualAllocationConstraint::new(0, 0.2, 1)),
+                    weight,
+                )
+                .allocate(vec![candidate.clone()])
+                .unwrap();
+            let f = portfolio.companies[0].fraction;
+            assert!(0.2 < f && f < previous_fraction);
+            assert_close!(marginal_growth(f), weight * (f - 0.2), ASSERTION_TOLERANCE);
+            previous_fraction = f;
+        }
+    }
+
+    #[test]
+    fn test_allocate_with_risk_free_rate() {
+        let logger = create_test_logger();
+        let candidate: Company = serde_yaml::from_str(
+            "
+            name: Coin
+            ticker: C
+            description: Doubles or halves with probabilities 50-50
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+            ",
+        )
+        .unwrap();
+
+        // With the excess returns a = 1 - r and b = -0.5 - r, the growth
+        // 0.5 ln(1 + r + f a) + 0.5 ln(1 + r + f b) is maximal at f = -(1 + r)(a + b) / (2 a b)
+        for (risk_free_rate, expected_fraction) in [(0.0, 0.5), (0.1, 0.33 / 1.08)] {
+            let portfolio = KellyAllocator::new(&logger, MAX_ITER)
+                .with_risk_free_rate(risk_free_rate)
+                .allocate(vec![candidate.clone()])
+                .unwrap();
+            assert_close!(
+                expected_fraction,
+                portfolio.companies[0].fraction,
+                ASSERTION_TOLERANCE
+            );
+        }
+    }
+
+    #[test]
+    fn test_allocate_with_margin_interest_rate() {
+        let logger = create_test_logger();
+        let candidate: Company = serde_yaml::from_str(
+            "
+            name: Coin
+            ticker: C
+            description: Gains half or loses a tenth with probabilities 50-50
+            market_cap: 1.0
+            scenarios:
+              - thesis: Gains
+                intrinsic_value: 1.5
+                probability: 0.5
+              - thesis: Loses
+                intrinsic_value: 0.9
+                probability: 0.5
+            ",
+        )
+        .unwrap();
+
+        // Levered 4 times with free borrowing, and with the excess returns a = 0.5 - m and
+        // b = -0.1 - m over the margin interest rate m, at f = -(1 + m)(a + b) / (2 a b)
+        for (margin_interest_rate, expected_fraction) in [(0.0, 4.0), (0.05, 0.315 / 0.135)] {
+            let portfolio = KellyAllocator::new(&logger, MAX_ITER)
+                .with_margin_interest_rate(margin_interest_rate)
+                .allocate(vec![candidate.clone()])
+                .unwrap();
+            assert_close!(
+                expected_fraction,
+                portfolio.companies[0].fraction,
+                ASSERTION_TOLERANCE
+            );
+        }
+
+        // Borrowing at 20% makes leverage unattractive, but the unlevered position still is
+        let portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_margin_interest_rate(0.2)
+            .allocate(vec![candidate.clone()])
+            .unwrap();
+        assert_close!(1.0, portfolio.companies[0].fraction, ASSERTION_TOLERANCE);
+    }
+
+    /// Tests that returns which are finite but overflow in the Jacobian are reported with the
+    /// scenario responsible instead of producing a nonsense allocation.
+    #[test]
+    fn test_allocate_with_overflowing_returns() {
+        let logger = create_test_logger();
+        let candidate: Company = serde_yaml::from_str(
+            "
+            name: Overflow
+            ticker: O
+            description: Returns so large that their squares overflow
+            market_cap: 1.0
+            scenarios:
+              - thesis: Lost
+                intrinsic_value: 0.5
+                probability: 0.5
+              - thesis: Unbounded
+                intrinsic_value: 1.0e160
+                probability: 0.5
+            ",
+        )
+        .unwrap();
+
+        let e = KellyAllocator::new(&logger, MAX_ITER)
+            .allocate(vec![candidate])
+            .err()
+            .unwrap();
+        assert_eq!(NON_FINITE_VALUE_CODE, e.code);
+        assert!(e.message.contains("scenario Unbounded of company O"));
     }
 
+    /// Tests that a looser solver tolerance converges in fewer iterations, and that the allocation
+    /// fails if the maximum number of iterations is too low to converge.
     #[test]
-    fn test_kelly() {
-        let test_candidates: Vec<Company> = generate_test_candidates();
-        let (portfolio, outcomes): (Portfolio, Vec<Outcome>) = generate_test_data(&test_candidates);
+    fn test_allocate_with_solver_parameters() {
+        let logger = create_test_logger();
+        let default_tolerance = IterationCounter::default();
+        let solution: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_observer(&default_tolerance)
+            .allocate(generate_test_candidates())
+            .unwrap();
 
-        let kelly = KellyAllocator::criterion(&outcomes, &portfolio);
+        let loose_tolerance = IterationCounter::default();
+        let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_settings(&SolverSettings {
+                solver_tolerance: Some(1e-2),
+                relaxation_factor: Some(0.5),
+                ..SolverSettings::default()
+            })
+            .with_solver_observer(&loose_tolerance)
+            .allocate(generate_test_candidates())
+            .unwrap();
+        assert!(loose_tolerance.n_iterations.get() < default_tolerance.n_iterations.get());
+        for (p, s) in portfolio.companies.iter().zip(solution.companies.iter()) {
+            assert_close!(s.fraction, p.fraction, 1e-2);
+        }
 
-        assert_close!(0.011111111, kelly[0], ASSERTION_TOLERANCE);
-        assert_close!(0.166666666, kelly[1], ASSERTION_TOLERANCE);
+        let e = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_settings(&SolverSettings {
+                strategy: Some(SolverStrategy::Plain),
+                max_iterations: Some(1),
+                ..SolverSettings::default()
+            })
+            .allocate(generate_test_candidates())
+            .err()
+            .unwrap();
+        assert_eq!("did-not-find-a-single-viable-solution", e.code);
     }
 
     #[test]
-    fn test_kelly_jacobian() {
-        let test_candidates: Vec<Company> = generate_test_candidates();
-        let (portfolio, outcomes): (Portfolio, Vec<Outcome>) = generate_test_data(&test_candidates);
+    fn test_refuse_to_solve_more_systems_than_maximum() {
+        let logger = create_test_logger();
+        let test_candidates = generate_test_candidates();
+        let n_candidates = test_candidates.len();
+        let error = KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_settings(&SolverSettings {
+                strategy: None,
+                regularization: None,
+                max_systems: Some(4),
+                initial_fractions: None,
+                max_iterations: None,
+                solver_tolerance: None,
+                relaxation_factor: None,
+            })
+            .with_long_only_constraints(n_candidates)
+            .with_maximum_individual_allocation_constraint(n_candidates, 0.3)
+            .with_maximum_total_leverage_constraint(n_candidates, 0.0)
+            .allocate(test_candidates)
+            .unwrap_err();
+
+        assert_eq!("too-many-systems-to-solve", error.code);
+        let n_constraints = 2 * n_candidates + 1;
+        assert!(error.message.contains(&format!(
+            "removing long_only reduces the systems from 2^{n_constraints} to 2^{}",
+            n_candidates + 1
+        )));
+        assert!(error.message.contains(&format!(
+            "removing max_total_leverage_ratio reduces the systems from 2^{n_constraints} to 2^{}",
+            n_constraints - 1
+        )));
+        assert!(error
+            .message
+            .contains("Remove long_only and max_individual_allocation to get within the limit"));
+    }
 
-        let jacobian = KellyAllocator::criterion_jacobian(&outcomes, &portfolio);
+    /// Tests that long-only and maximum individual allocation constraints for many candidates are
+    /// handled by projecting onto the bounds, which would otherwise require solving 2^20 systems.
+    #[test]
+    fn test_allocate_with_box_bounds_for_many_candidates() {
+        let candidates: Vec<Company> = (0..10)
+            .map(|i| {
+                let probability_of_win = 0.3 + 0.05 * i as f64;
+                Company {
+                    name: format!("Bet {i}"),
+                    ticker: format!("B{i}"),
+                    description: "Even-money bet".to_string(),
+                    market_cap: 1.0,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
+                    scenarios: vec![
+                        Scenario {
+                            thesis: "Win".to_string(),
+                            intrinsic_value: 2.0,
+                            probability: probability_of_win,
+                            ..Default::default()
+                        },
+                        Scenario {
+                            thesis: "Loss".to_string(),
+                            intrinsic_value: 0.5,
+                            probability: 1.0 - probability_of_win,
+                            ..Default::default()
+                        },
+                    ],
+                }
+            })
+            .collect();
 
-        assert_close!(-0.388256908, jacobian[(0, 0)], ASSERTION_TOLERANCE);
-        assert_close!(-0.007451499, jacobian[(0, 1)], ASSERTION_TOLERANCE);
-        assert_close!(-0.007451499, jacobian[(1, 0)], ASSERTION_TOLERANCE);
-        assert_close!(-0.160978836, jacobian[(1, 1)], ASSERTION_TOLERANCE);
+        let logger = create_test_logger();
+        let solution = KellyAllocator::new(&logger, MAX_ITER)
+            .with_long_only_constraints(candidates.len())
+            .with_maximum_individual_allocation_constraint(candidates.len(), 0.1)
+            .solve(candidates)
+            .unwrap();
+
+        assert_eq!(1, solution.n_systems);
+        assert!(!solution.is_approximate);
+
+        // Bets with an expected return below zero aren't shorted, the best bets are capped and the
+        // gradient of the growth vanishes for the ones in between
+        let fractions = solution
+            .portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.fraction)
+            .collect_vec();
+        assert_close!(0.0, fractions[0], ASSERTION_TOLERANCE);
+        assert_close!(0.1, fractions[9], ASSERTION_TOLERANCE);
+        let outcomes = all_outcomes(&solution.portfolio).unwrap();
+        let criterion = KellyAllocator::criterion(
+            &outcomes,
+            &solution.portfolio,
+            FinancingRates::default(),
+            &[],
+        );
+        for (i, f) in fractions.iter().enumerate() {
+            assert!((-TOLERANCE..=0.1 + TOLERANCE).contains(f));
+            if *f > ASSERTION_TOLERANCE && *f < 0.1 - ASSERTION_TOLERANCE {
+                assert_close!(0.0, criterion[i], ASSERTION_TOLERANCE);
+            }
+        }
+    }
+
+    #[test]
+    fn test_newton_step_rejects_ill_conditioned_jacobian() {
+        let right_hand_side = DVector::from_vec(vec![1.0, 1.0]);
+        let jacobian = DMatrix::from_row_slice(2, 2, &[-2.0, 0.0, 0.0, -0.5]);
+        let (delta_x, estimate) = KellyAllocator::newton_step(&jacobian, &right_hand_side).unwrap();
+        assert_close!(-0.5, delta_x[0], 1e-12);
+        assert_close!(-2.0, delta_x[1], 1e-12);
+        assert_close!(4.0, estimate, 1e-12);
+
+        let jacobian = DMatrix::from_row_slice(2, 2, &[1.0, 1.0, 1.0, 1.0 + 1e-15]);
+        let estimate = KellyAllocator::newton_step(&jacobian, &right_hand_side).unwrap_err();
+        assert!(estimate > MAX_CONDITION_NUMBER);
+    }
+
+    #[test]
+    fn test_newton_step_with_sparse_solve_for_many_constraints() {
+        let candidates: Vec<Company> = (0..10)
+            .map(|i| {
+                let probability_of_win = 0.3 + 0.05 * i as f64;
+                Company {
+                    name: format!("Bet {i}"),
+                    ticker: format!("B{i}"),
+                    description: "Even-money bet".to_string(),
+                    market_cap: 1.0,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
+                    scenarios: vec![
+                        Scenario {
+                            thesis: "Win".to_string(),
+                            intrinsic_value: 2.0,
+                            probability: probability_of_win,
+                            ..Default::default()
+                        },
+                        Scenario {
+                            thesis: "Loss".to_string(),
+                            intrinsic_value: 0.5,
+                            probability: 1.0 - probability_of_win,
+                            ..Default::default()
+                        },
+                    ],
+                }
+            })
+            .collect();
+
+        // Ten candidates with the long-only, maximum individual allocation and fully invested
+        // constraints make a system large enough to be solved with the sparse decomposition
+        let logger = create_test_logger();
+        let allocator = KellyAllocator::new(&logger, MAX_ITER)
+            .with_long_only_constraints(candidates.len())
+            .with_maximum_individual_allocation_constraint(candidates.len(), 0.2)
+            .with_fully_invested_constraint(candidates.len());
+        let mut portfolio = Portfolio {
+            companies: candidates
+                .into_iter()
+                .map(|c| PortfolioCompany {
+                    company: c,
+                    fraction: 0.1,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let outcomes = all_outcomes(&portfolio).unwrap();
+        let n = 31;
+        let x = DVector::from_fn(n, |i, _| if i < 10 { 0.1 } else { 0.01 });
+        let mut is_constraint_active = BitVec::<usize, Lsb0>::repeat(false, 20);
+        is_constraint_active.set(0, true);
+        is_constraint_active.set(19, true);
+        let (jacobian, right_hand_side) =
+            allocator.linearized_system(&mut portfolio, &outcomes, &x, &is_constraint_active);
+        assert!(jacobian.nrows() >= SPARSE_SOLVE_THRESHOLD);
+
+        let (delta_x, _) = KellyAllocator::newton_step(&jacobian, &right_hand_side).unwrap();
+        let expected_delta_x = jacobian.try_inverse().unwrap() * right_hand_side;
+        for (expected, actual) in expected_delta_x.iter().zip(delta_x.iter()) {
+            assert_close!(*expected, *actual, 1e-9);
+        }
     }
 
     /// Asserts results for a simple allocation problem with two companies, each with two scenarios.
@@ -914,6 +3355,164 @@ mod test {
         assert_close!(-0.102, risk_of_capital_loss, ASSERTION_TOLERANCE);
     }
 
+    /// Tests that a position below the minimum individual allocation is either sold or raised to the
+    /// minimum, whichever has the higher growth.
+    #[test]
+    fn test_allocate_with_minimum_individual_allocation_constraint() {
+        let logger = create_test_logger();
+
+        // Lower the edge of the second candidate such that it gets a small allocation
+        let mut test_candidates: Vec<Company> = generate_test_candidates();
+        test_candidates[1].scenarios[0].probability = 0.4;
+        test_candidates[1].scenarios[1].probability = 0.6;
+        let allocate_with_minimum = |min_allocation: f64| {
+            KellyAllocator::new(&logger, MAX_ITER)
+                .with_long_only_constraints(test_candidates.len())
+                .with_minimum_individual_allocation_constraint(min_allocation)
+                .allocate(test_candidates.clone())
+                .unwrap()
+        };
+
+        // Allocation of about 0.12 satisfies the minimum
+        let portfolio = allocate_with_minimum(0.1);
+        assert_close!(
+            0.49906,
+            portfolio.companies[0].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            0.12027,
+            portfolio.companies[1].fraction,
+            ASSERTION_TOLERANCE
+        );
+
+        // Slightly above the allocation, raising it to the minimum costs less growth than selling
+        let portfolio = allocate_with_minimum(0.15);
+        assert_close!(
+            0.49819,
+            portfolio.companies[0].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(0.15, portfolio.companies[1].fraction, ASSERTION_TOLERANCE);
+
+        // Far above the allocation, it's better not to hold the candidate at all
+        let portfolio = allocate_with_minimum(0.3);
+        assert_close!(0.5, portfolio.companies[0].fraction, ASSERTION_TOLERANCE);
+        assert_close!(0.0, portfolio.companies[1].fraction, ASSERTION_TOLERANCE);
+    }
+
+    /// Tests allocation with a maximum allocation for a group of both candidates, which is the same
+    /// as not using leverage.
+    #[test]
+    fn test_allocate_with_maximum_group_allocation_constraint() {
+        let logger = create_test_logger();
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_maximum_group_allocation_constraint(&[0, 1], test_candidates.len(), 1.0)
+            .allocate(test_candidates)
+            .unwrap();
+
+        assert_eq!(portfolio.companies.len(), 2);
+        assert_close!(
+            0.195887,
+            portfolio.companies[0].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            0.804113,
+            portfolio.companies[1].fraction,
+            ASSERTION_TOLERANCE
+        );
+    }
+
+    /// Tests that the penalty method approximates the solution with a maximum individual
+    /// allocation constraint.
+    #[test]
+    fn test_solve_with_penalty() {
+        let logger = create_test_logger();
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let (portfolio, outcomes): (Portfolio, Vec<Outcome>) = generate_test_data(&test_candidates);
+        let x = KellyAllocator::new(&logger, MAX_ITER)
+            .with_maximum_individual_allocation_constraint(test_candidates.len(), 0.3)
+            .solve_with_penalty(portfolio, &outcomes)
+            .unwrap();
+
+        assert_eq!(x.len(), 2);
+        assert_close!(0.3, x[0], 1e-4);
+        assert_close!(0.3, x[1], 1e-4);
+    }
+
+    /// Tests that all solver strategies find the same solution for a system with constraints.
+    #[test]
+    fn test_allocate_with_constraints_for_all_solver_strategies() {
+        let logger = create_test_logger();
+        for strategy in [
+            SolverStrategy::Plain,
+            SolverStrategy::LineSearch,
+            SolverStrategy::TrustRegion,
+        ] {
+            let test_candidates: Vec<Company> = generate_test_candidates();
+            let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+                .with_solver_settings(&SolverSettings {
+                    strategy: Some(strategy),
+                    regularization: None,
+                    max_systems: None,
+                    initial_fractions: None,
+                    max_iterations: None,
+                    solver_tolerance: None,
+                    relaxation_factor: None,
+                })
+                .with_long_only_constraints(test_candidates.len())
+                .with_maximum_total_leverage_constraint(test_candidates.len(), 0.0)
+                .allocate(test_candidates)
+                .unwrap();
+
+            assert_close!(
+                0.195887,
+                portfolio.companies[0].fraction,
+                ASSERTION_TOLERANCE
+            );
+            assert_close!(
+                0.804113,
+                portfolio.companies[1].fraction,
+                ASSERTION_TOLERANCE
+            );
+        }
+    }
+
+    /// Observer recording the residuals of all iterations.
+    struct ResidualRecorder {
+        residuals: RefCell<Vec<f64>>,
+    }
+
+    impl SolverObserver for ResidualRecorder {
+        fn on_iteration(&self, iteration: &SolverIteration) {
+            assert!(iteration.condition_number >= 1.0);
+            self.residuals.borrow_mut().push(iteration.residual);
+        }
+    }
+
+    /// Tests that the solver observer is invoked in each iteration until convergence.
+    #[test]
+    fn test_allocate_with_solver_observer() {
+        let logger = create_test_logger();
+        let observer = ResidualRecorder {
+            residuals: RefCell::new(vec![]),
+        };
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        KellyAllocator::new(&logger, MAX_ITER)
+            .with_solver_observer(&observer)
+            .allocate(test_candidates)
+            .unwrap();
+
+        let residuals = observer.residuals.borrow();
+        assert!(residuals.len() > 1);
+        assert!(*residuals.last().unwrap() < SOLVER_TOLERANCE);
+        assert!(residuals[..residuals.len() - 1]
+            .iter()
+            .all(|r| *r >= SOLVER_TOLERANCE));
+    }
+
     /// Tests allocation with a maximum total leverage ratio of 0 (no leverage).
     #[test]
     fn test_allocate_with_maximum_total_leverage_constraint() {
@@ -944,6 +3543,116 @@ mod test {
         assert_close!(-0.121342, risk_of_capital_loss, ASSERTION_TOLERANCE);
     }
 
+    #[test]
+    fn test_allocate_with_fully_invested_constraint() {
+        // Same allocation as with the active maximum total leverage constraint of zero, but from a
+        // single system since the equality constraint is always active
+        let logger = create_test_logger();
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let solution = KellyAllocator::new(&logger, MAX_ITER)
+            .with_fully_invested_constraint(test_candidates.len())
+            .solve(test_candidates)
+            .unwrap();
+
+        assert_eq!(1, solution.n_systems);
+        assert!(!solution.is_approximate);
+        assert_close!(
+            0.195887,
+            solution.portfolio.companies[0].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            0.804113,
+            solution.portfolio.companies[1].fraction,
+            ASSERTION_TOLERANCE
+        );
+    }
+
+    #[test]
+    fn test_allocate_with_fixed_net_exposure_constraint() {
+        let logger = create_test_logger();
+        let solve = |net_exposure: f64| {
+            let test_candidates: Vec<Company> = generate_test_candidates();
+            KellyAllocator::new(&logger, MAX_ITER)
+                .with_fixed_net_exposure_constraint(test_candidates.len(), net_exposure)
+                .solve(test_candidates)
+                .unwrap()
+        };
+        let solution = solve(0.5);
+
+        assert_eq!(1, solution.n_systems);
+        let net_exposure: f64 = solution
+            .portfolio
+            .companies
+            .iter()
+            .map(|c| c.fraction)
+            .sum();
+        assert_close!(0.5, net_exposure, ASSERTION_TOLERANCE);
+
+        // The multiplier is the marginal growth of raising the net exposure
+        let growth = |s: &KellySolution| expected_log_growth(&all_outcomes(&s.portfolio).unwrap());
+        let step = 1e-4;
+        let marginal_growth =
+            (growth(&solve(0.5 + step)) - growth(&solve(0.5 - step))) / (2.0 * step);
+        assert!(marginal_growth > 0.0);
+        assert_close!(
+            marginal_growth,
+            solution.net_exposure_multiplier.unwrap(),
+            1e-4
+        );
+    }
+
+    #[test]
+    fn test_allocate_with_maximum_position_downside_constraint() {
+        // Worst case of B is a loss of 30% with a probability of 30%, so a budget of 0.045 caps its
+        // fraction at 0.5, which is found with the projected Newton method for box bounds
+        let logger = create_test_logger();
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let solution = KellyAllocator::new(&logger, MAX_ITER)
+            .with_maximum_position_downside_constraints(&[(1, 0.045)], &test_candidates)
+            .solve(test_candidates.clone())
+            .unwrap();
+
+        assert_eq!(1, solution.n_systems);
+        assert_close!(
+            0.5,
+            solution.portfolio.companies[1].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            0.0,
+            KellyAllocator::criterion(
+                &all_outcomes(&solution.portfolio).unwrap(),
+                &solution.portfolio,
+                FinancingRates::default(),
+                &[]
+            )[0],
+            ASSERTION_TOLERANCE
+        );
+
+        // Without leverage, A gets the rest of the capital, which requires solving all systems
+        let observer = IterationCounter::default();
+        let solution = KellyAllocator::new(&logger, MAX_ITER)
+            .with_maximum_position_downside_constraints(&[(1, 0.045)], &test_candidates)
+            .with_maximum_total_leverage_constraint(test_candidates.len(), 0.0)
+            .with_solver_observer(&observer)
+            .solve(test_candidates)
+            .unwrap();
+
+        assert_eq!(4, solution.n_systems);
+        assert_eq!(vec![4], *observer.enumerations.borrow());
+        assert_close!(
+            0.5,
+            solution.portfolio.companies[0].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            0.5,
+            solution.portfolio.companies[1].fraction,
+            ASSERTION_TOLERANCE
+        );
+    }
+
     #[test]
     fn test_allocate_with_one_short_result() {
         let mut test_candidates: Vec<Company> = generate_test_candidates();
@@ -953,16 +3662,21 @@ mod test {
             description: "A bet with 50% upside and 100% downside, with probabilities 50-50"
                 .to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "Ok".to_string(),
                     intrinsic_value: 1.5e7,
                     probability: 0.5,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "Bad".to_string(),
                     intrinsic_value: 0.0,
                     probability: 0.5,
+                    ..Default::default()
                 },
             ],
         });
@@ -990,6 +3704,78 @@ mod test {
         );
     }
 
+    /// Tests that the long-only constraint applied only to the third candidate prevents shorting it,
+    /// which results in the same allocation for the first two candidates as without the third one.
+    #[test]
+    fn test_allocate_with_long_only_constraint_for_a_subset() {
+        let mut test_candidates: Vec<Company> = generate_test_candidates();
+        test_candidates.push(Company {
+            name: "Stupid investment".to_string(),
+            ticker: "SI".to_string(),
+            description: "A bet with 50% upside and 100% downside, with probabilities 50-50"
+                .to_string(),
+            market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "Ok".to_string(),
+                    intrinsic_value: 1.5e7,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Bad".to_string(),
+                    intrinsic_value: 0.0,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+            ],
+        });
+
+        let logger = create_test_logger();
+        let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+            .with_long_only_constraints_for(&[2], test_candidates.len())
+            .allocate(test_candidates)
+            .unwrap();
+
+        assert_eq!(portfolio.companies.len(), 3);
+        assert_close!(
+            0.3592684433098152,
+            portfolio.companies[0].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(
+            1.629923469755913,
+            portfolio.companies[1].fraction,
+            ASSERTION_TOLERANCE
+        );
+        assert_close!(0.0, portfolio.companies[2].fraction, ASSERTION_TOLERANCE);
+    }
+
+    /// Tests that the capital loss constraint is not supported when the long-only constraint only
+    /// applies to a subset of candidates.
+    #[test]
+    fn test_allocate_with_capital_loss_constraint_and_long_only_constraint_for_a_subset() {
+        let logger = create_test_logger();
+        let test_candidates: Vec<Company> = generate_test_candidates();
+        let e = KellyAllocator::new(&logger, MAX_ITER)
+            .with_long_only_constraints_for(&[0], test_candidates.len())
+            .with_maximum_permanent_loss_constraint(CapitalLoss {
+                probability_of_loss: 0.1,
+                fraction_of_capital: 0.2,
+            })
+            .allocate(test_candidates)
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            e.code,
+            "maximum-capital-loss-constraint-works-only-with-long-only-strategy"
+        );
+    }
+
     #[test]
     fn test_allocate_for_a_single_company() {
         let test_candidates: Vec<Company> = vec![Company {
@@ -998,16 +3784,21 @@ mod test {
             description: "A bet with 100% upside and 50% downside, with probabilities 50-50"
                 .to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "A1".to_string(),
                     intrinsic_value: 2e7,
                     probability: 0.5,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "A2".to_string(),
                     intrinsic_value: 5e6,
                     probability: 0.5,
+                    ..Default::default()
                 },
             ],
         }];
@@ -1029,31 +3820,52 @@ mod test {
             description: "A bet with 10x upside and 1% downside, with probabilities 90-10"
                 .to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "A1".to_string(),
                     intrinsic_value: 1e8,
                     probability: 0.9,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "A2".to_string(),
                     intrinsic_value: 0.99e7,
                     probability: 0.1,
+                    ..Default::default()
                 },
             ],
         }];
 
         let logger = create_test_logger();
-        let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
-            .allocate(test_candidates)
-            .unwrap();
-
-        assert_eq!(portfolio.companies.len(), 1);
-        assert_close!(
-            89.988889,
-            portfolio.companies[0].fraction,
-            ASSERTION_TOLERANCE
-        );
+        for strategy in [
+            SolverStrategy::Plain,
+            SolverStrategy::LineSearch,
+            SolverStrategy::TrustRegion,
+            SolverStrategy::Auto,
+        ] {
+            let portfolio: Portfolio = KellyAllocator::new(&logger, MAX_ITER)
+                .with_solver_settings(&SolverSettings {
+                    strategy: Some(strategy),
+                    regularization: None,
+                    max_systems: None,
+                    initial_fractions: None,
+                    max_iterations: None,
+                    solver_tolerance: None,
+                    relaxation_factor: None,
+                })
+                .allocate(test_candidates.clone())
+                .unwrap();
+
+            assert_eq!(portfolio.companies.len(), 1);
+            assert_close!(
+                89.988889,
+                portfolio.companies[0].fraction,
+                ASSERTION_TOLERANCE
+            );
+        }
     }
 
     #[test]
@@ -1080,16 +3892,21 @@ mod test {
             ticker: "BI".to_string(),
             description: "A bet with 10x upside and no downside".to_string(),
             market_cap: 1.0e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "10x upside".to_string(),
                     intrinsic_value: 1.0e8,
                     probability: 0.5,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "No downside".to_string(),
                     intrinsic_value: 1.0e7,
                     probability: 0.5,
+                    ..Default::default()
                 },
             ],
         });
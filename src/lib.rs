@@ -1,32 +1,216 @@
 extern crate core;
 
+pub mod allocation_queue;
+pub mod allocation_store;
 pub mod analysis;
+pub mod backtest;
+pub mod batch_analysis;
+pub mod betting_edge;
+pub mod candidate_store;
+pub mod config;
+pub mod config_watcher;
+pub mod consensus;
 pub mod constraints;
+pub mod data_quality;
+pub mod elicitation;
+pub mod engine;
 pub mod env;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod job_store;
 pub mod kelly_allocation;
+pub mod market_data;
 pub mod model;
+pub mod model_schemas;
+pub mod objective;
+pub mod outcome_diagram;
+pub mod response_encoding;
+pub mod run_artifacts;
+pub mod scenario_templates;
+pub mod server_state;
+pub mod sharing;
+pub mod snapshot_store;
+pub mod solver_observer;
+pub mod solver_state;
+pub mod solver_trace;
+pub mod sparse_lu;
+pub mod standalone;
+pub mod storage;
+pub mod symbology;
+pub mod usage;
 pub mod utils;
 pub mod validation;
 
-use crate::analysis::{all_outcomes, worst_case_outcome};
-use crate::analysis::{cumulative_probability_of_loss, expected_return};
-use crate::env::get_project_dir;
-use crate::kelly_allocation::{KellyAllocator, MAX_ITER};
-use crate::model::company::Company;
+use crate::allocation_queue::AllocationSlot;
+use crate::allocation_store::allocation_diff;
+use crate::analysis::numerical_errors;
+use crate::analysis::probability_of_return_below;
+use crate::analysis::{all_outcomes, worst_case_outcome, Outcome};
+use crate::analysis::{analytical_moments, cross_check_moments, diversification};
+use crate::analysis::{
+    compound_growth, dominant_outcome, dominant_scenario, event_outcome, inflation_adjusted,
+};
+use crate::analysis::{conditional_value_at_risk, value_at_risk, VALUE_AT_RISK_CONFIDENCE};
+use crate::analysis::{cumulative_probability_of_loss, expected_log_growth, expected_return};
+use crate::analysis::{n_outcomes, return_distribution, sampled_outcomes, sampled_statistics};
+use crate::analysis::{tag_conditional_outcome, DEFAULT_N_SAMPLES, MAX_OUTCOMES};
+use crate::batch_analysis::analyze_batch;
+use crate::betting_edge::betting_edge;
+use crate::constraints::constraint::{Constraint, InequalityConstraint};
+use crate::constraints::maximum_capital_loss_constraint::MaxCapitalLossConstraint;
+use crate::constraints::maximum_group_allocation_constraint::MaximumGroupAllocationConstraint;
+use crate::constraints::maximum_individual_allocation_constraint::MaximumIndividualAllocationConstraint;
+use crate::constraints::maximum_position_downside_constraint::MaximumPositionDownsideConstraint;
+use crate::constraints::maximum_total_leverage_constraint::MaximumTotalLeverageConstraint;
+use crate::data_quality::data_quality;
+use crate::env::{get_project_dir, CompletionLog, LogCapture};
+use crate::kelly_allocation::{FinancingRates, SOLVER_TOLERANCE};
+use crate::kelly_allocation::{KellyAllocator, KellySolution, MAX_ITER, MIN_SCENARIO_PROBABILITY};
+use crate::model::allocation_options::AllocationOptions;
+use crate::model::allocations::{FractionChange, RerunResponse, ShareLink, ShareRequest};
+use crate::model::analysis_options::{AnalysisOptions, DetailLevel};
+use crate::model::batch_analysis::{BatchAnalysisInput, BatchAnalysisResponse};
+use crate::model::bets::BetsInput;
+use crate::model::candidates::{
+    CandidateRevision, CandidateUpdate, SnapshotQuery, StoredCandidates,
+};
+use crate::model::company::{Company, Ticker};
 use crate::model::errors::Error;
-use crate::model::portfolio::{AllocationInput, Portfolio};
+use crate::model::events::{PortfolioEvent, PortfolioWithEvents};
+use crate::model::jobs::{AllocationJob, JobLink};
+use crate::model::pair_trades::PairTrade;
+use crate::model::portfolio::{
+    AllocationInput, Portfolio, PortfolioCompany, RiskMeasure, SoftConstraintKind, TargetRisk,
+};
+use crate::model::remote_input::RemoteInput;
 use crate::model::responses::{
-    AllocationResponse, AllocationResult, AnalysisResponse, AnalysisResult, TickerAndFraction,
+    AllocationResponse, AllocationResult, AnalysisResponse, AnalysisResult, BenchmarkComparison,
+    CappedAlternative, ConvictionSizing, DeleveragingSuggestion, EventOutcome, FilteredCandidate,
+    ProbabilityAndReturns, Rebalancing, SoftConstraintViolation, TagConditionalOutcome,
+    TargetRiskSizing, TickerAndFraction, ValidationResponse, ZeroAllocation,
 };
+use crate::model::snapshots::{AnalysisSnapshot, SnapshotHistory};
+use crate::model::template::{InputTemplate, TemplateParameters};
+use crate::model::usage::{Usage, UsageReport};
+use crate::model_schemas::{model_schema, ModelType};
+use crate::response_encoding::{encode_response, HttpResponseEncoded};
+use crate::server_state::ServerState;
+use crate::sharing::render_shared_allocation;
+use crate::solver_observer::{SolvedSystem, SolverIteration, SolverObserver};
+use crate::solver_state::SolverStateRecorder;
+use crate::solver_trace::solver_trace;
+use crate::storage::{save, Collection, STORAGE_ERROR};
+use crate::usage::ANONYMOUS_API_KEY;
 use crate::validation::result::Severity::ERROR;
-use crate::validation::result::ValidationResult;
+use crate::validation::result::{Problem, Severity, ValidationResult};
 use crate::validation::validate::Validate;
-use dropshot::{endpoint, HttpError, HttpResponseOk, RequestContext, TypedBody};
+use chrono::{DateTime, Duration, Utc};
+use dropshot::{
+    endpoint, ApiDescription, HttpError, HttpResponseAccepted, HttpResponseHeaders, HttpResponseOk,
+    HttpResponseUpdatedNoContent, Path, Query, RequestContext, TypedBody,
+};
 use http::{Response, StatusCode};
 use hyper::Body;
-use slog::{info, Logger};
-use std::collections::HashSet;
+use indexmap::{IndexMap, IndexSet};
+use itertools::Itertools;
+use ordered_float::OrderedFloat;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use slog::{info, warn, Level, Logger};
+use std::cmp::Reverse;
 use std::fs;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Header of the requests with the API key, to which the usage of the request is accounted
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Allocations whose fractions sum up to more than 1 by more than this tolerance are levered
+const LEVERAGE_TOLERANCE: f64 = 1e-6;
+
+/// Candidates with a smaller absolute fraction are considered to be allocated zero, and
+/// constraints closer than this to their bound are considered active
+const ZERO_ALLOCATION_TOLERANCE: f64 = 1e-6;
+
+/// Candidates allocated zero whose marginal growth is closer to zero than this are not held at
+/// zero by a constraint
+const MARGINAL_GROWTH_TOLERANCE: f64 = 1e-6;
+
+/// Default maximum share of the expected logarithmic growth that may come from a single joint
+/// outcome or a single scenario of a company without a warning
+const MAX_GROWTH_CONCENTRATION: f64 = 0.5;
+
+/// Allocations with a larger fraction of capital in a single candidate are considered degenerate
+/// and get a warning together with a capped alternative
+const MAX_SINGLE_CANDIDATE_FRACTION: f64 = 0.95;
+
+/// Maximum individual allocation of the alternative to a degenerate allocation
+const CAPPED_ALTERNATIVE_MAX_ALLOCATION: f64 = 0.25;
+
+/// Maximum number of choices of candidates searched for the best allocation with the maximum
+/// number of positions, beyond which the candidates are pruned by their fractions instead.
+const MAX_POSITION_CHOICES: usize = 64;
+
+/// Returns the API description with all the endpoints of the server registered, from which both
+/// the server and the OpenAPI schema are created.
+pub fn api_description() -> ApiDescription<ServerState> {
+    let mut api = ApiDescription::new();
+    api.register(openapi).unwrap();
+    api.register(get_model_schema_endpoint).unwrap();
+    api.register(allocate_endpoint).unwrap();
+    api.register(allocate_remote_endpoint).unwrap();
+    api.register(allocate_bets_endpoint).unwrap();
+    api.register(allocate_async_endpoint).unwrap();
+    api.register(get_job_endpoint).unwrap();
+    api.register(analyze_endpoint).unwrap();
+    api.register(analyze_batch_endpoint).unwrap();
+    api.register(post_snapshot_endpoint).unwrap();
+    api.register(get_snapshot_history_endpoint).unwrap();
+    api.register(demo).unwrap();
+    api.register(put_template_endpoint).unwrap();
+    api.register(allocate_template_endpoint).unwrap();
+    api.register(put_candidates_endpoint).unwrap();
+    api.register(get_candidates_endpoint).unwrap();
+    api.register(get_candidate_history_endpoint).unwrap();
+    api.register(get_candidates_snapshot_endpoint).unwrap();
+    api.register(rerun_allocation_endpoint).unwrap();
+    api.register(share_allocation_endpoint).unwrap();
+    api.register(get_shared_allocation_endpoint).unwrap();
+    api.register(get_usage_endpoint).unwrap();
+    api.register(allocate_candidates_endpoint).unwrap();
+    api.register(validate_endpoint).unwrap();
+    api.register(validate_company_endpoint).unwrap();
+    api
+}
+
+/// Returns the OpenAPI schema of all the endpoints.
+pub fn openapi_schema() -> serde_json::Value {
+    api_description()
+        .openapi("Charlie", "v0")
+        .json()
+        .expect("Failed to convert OpenAPIDefinition to JSON.")
+}
+
+/// Path parameter naming the model type of a JSON Schema
+#[derive(Deserialize, JsonSchema)]
+pub struct ModelTypePath {
+    model_type: ModelType,
+}
+
+/// JSON Schema of a single model type, e.g. for rendering an editor of a company or a scenario
+#[endpoint {
+    method = GET,
+    path = "/schema/{model_type}",
+    tags = [ "api" ],
+}]
+pub async fn get_model_schema_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<ModelTypePath>,
+) -> Result<HttpResponseOk<serde_json::Value>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_model_schema_endpoint");
+    Ok(HttpResponseOk(model_schema(path.into_inner().model_type)))
+}
 
 /// Basic front-end for simple demonstration purposes
 #[endpoint {
@@ -34,14 +218,20 @@ use std::fs;
     path = "/demo",
     tags = [ "demo" ]
 }]
-pub async fn demo(_rqctx: RequestContext<()>) -> Result<Response<Body>, HttpError> {
-    let demo_file_path = get_project_dir().join("demo").join("demo.html");
-    let demo = fs::read_to_string(demo_file_path.clone()).unwrap_or_else(|_| {
-        panic!(
-            "Did not manage to read demo.html file at: {:?}",
-            demo_file_path
-        )
-    });
+pub async fn demo(rqctx: RequestContext<ServerState>) -> Result<Response<Body>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "demo");
+    let demo = match &rqctx.context().standalone_pages {
+        Some(pages) => pages.demo.clone(),
+        None => {
+            let demo_file_path = get_project_dir().join("demo").join("demo.html");
+            fs::read_to_string(demo_file_path.clone()).unwrap_or_else(|_| {
+                panic!(
+                    "Did not manage to read demo.html file at: {:?}",
+                    demo_file_path
+                )
+            })
+        }
+    };
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -55,14 +245,20 @@ pub async fn demo(_rqctx: RequestContext<()>) -> Result<Response<Body>, HttpErro
     path = "/api",
     tags = [ "api" ]
 }]
-pub async fn openapi(_rqctx: RequestContext<()>) -> Result<Response<Body>, HttpError> {
-    let index_file_path = get_project_dir().join("schema").join("index.html");
-    let index = fs::read_to_string(index_file_path.clone()).unwrap_or_else(|_| {
-        panic!(
-            "Did not manage to read index file at: {:?}",
-            index_file_path
-        )
-    });
+pub async fn openapi(rqctx: RequestContext<ServerState>) -> Result<Response<Body>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "openapi");
+    let index = match &rqctx.context().standalone_pages {
+        Some(pages) => pages.api.clone(),
+        None => {
+            let index_file_path = get_project_dir().join("schema").join("index.html");
+            fs::read_to_string(index_file_path.clone()).unwrap_or_else(|_| {
+                panic!(
+                    "Did not manage to read index file at: {:?}",
+                    index_file_path
+                )
+            })
+        }
+    };
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -78,231 +274,3121 @@ pub async fn openapi(_rqctx: RequestContext<()>) -> Result<Response<Body>, HttpE
     tags = [ "allocate" ],
 }]
 pub async fn allocate_endpoint(
-    rqctx: RequestContext<()>,
+    rqctx: RequestContext<ServerState>,
+    query: Query<AllocationOptions>,
     body: TypedBody<AllocationInput>,
-) -> Result<HttpResponseOk<AllocationResponse>, HttpError> {
-    let allocation_result = allocate(body.into_inner(), &rqctx.log);
-    Ok(HttpResponseOk(allocation_result))
+) -> Result<AllocationHttpResponse, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "allocate_endpoint");
+    allocate_in_queue(&rqctx, body.into_inner(), query.into_inner()).await
 }
 
-/// Analyze the portfolio by calculating useful statistics
+/// Fetch the allocation input from an HTTP(S) URL (e.g. a Google Sheet exported as CSV) and
+/// calculate the optimal allocation for it. Only URLs of the hosts allowed in the server
+/// configuration are fetched, once the allocation has a slot in the allocation queue
 #[endpoint {
     method = POST,
-    path = "/analyze",
-    tags = [ "analyze" ],
+    path = "/allocate/remote",
+    tags = [ "allocate" ],
 }]
-pub async fn analyze_endpoint(
-    rqctx: RequestContext<()>,
-    body: TypedBody<Portfolio>,
-) -> Result<HttpResponseOk<AnalysisResponse>, HttpError> {
-    let analysis_result = analyze(body.into_inner(), &rqctx.log);
-    Ok(HttpResponseOk(analysis_result))
+pub async fn allocate_remote_endpoint(
+    rqctx: RequestContext<ServerState>,
+    body: TypedBody<RemoteInput>,
+) -> Result<AllocationHttpResponse, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "allocate_remote_endpoint");
+    let remote_input = body.into_inner();
+    let state = rqctx.context();
+    let limits = state.remote_input.fetch_limits();
+    let url = remote_input.document_url();
+    if !limits.allows(&url) {
+        return Err(HttpError::for_client_error(
+            Some("remote-input-url-not-allowed".to_string()),
+            StatusCode::FORBIDDEN,
+            format!("The server doesn't fetch the input from {url}, since its host isn't allowed."),
+        ));
+    }
+
+    let api_key = api_key(&rqctx);
+    state.usage.check_quota(&api_key).map_err(quota_exceeded)?;
+    let slot = acquire_allocation_slot(state, &rqctx.log)
+        .await
+        .map_err(|e| HttpError::for_unavail(Some(e.code), e.message))?;
+
+    info!(rqctx.log, "Fetching the allocation input from {url}.");
+    let fetched = tokio::task::spawn_blocking(move || remote_input.fetch_allocation_input(&limits))
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("Fetching input failed: {e}")))?;
+    let (allocation_response, allocation_id) = match fetched {
+        Ok(input) => {
+            let recorded_input = input.clone();
+            let drift_warnings = market_cap_drift_warnings(state, &input, &rqctx.log).await;
+            let allocation_response = allocate_blocking(
+                state,
+                &api_key,
+                input,
+                AllocationOptions::default(),
+                drift_warnings,
+                None,
+                rqctx.log.clone(),
+            )
+            .await
+            .map_err(|e| HttpError::for_internal_error(e.message))?;
+            let allocation_id = record_allocation(
+                state,
+                &rqctx.log,
+                &api_key,
+                recorded_input,
+                &allocation_response,
+            );
+            (allocation_response, allocation_id)
+        }
+        Err(e) => {
+            // The details may reveal the responses of the remote host, so they're only logged
+            warn!(
+                rqctx.log,
+                "Fetching the allocation input failed: {}", e.message
+            );
+            let error = Error {
+                code: e.code,
+                message: format!(
+                    "Did not manage to read the allocation input from {url}. The reason is logged \
+                    on the server."
+                ),
+            };
+            (
+                AllocationResponse {
+                    result: None,
+                    validation_problems: None,
+                    error: Some(error),
+                    logs: None,
+                },
+                None,
+            )
+        }
+    };
+
+    encode_response(
+        &rqctx.request,
+        HttpResponseHeaders::new(
+            HttpResponseOk(allocation_response),
+            AllocationQueueHeaders {
+                x_queue_position: slot.queue_position.to_string(),
+                x_allocation_id: allocation_id.map_or(String::new(), |id| id.to_string()),
+            },
+        ),
+    )
+    .await
 }
 
-/// Validate the candidates and return all problematic validations.
-pub fn validate(portfolio_candidates: &AllocationInput, logger: &Logger) -> Vec<ValidationResult> {
-    info!(logger, "Performing validation of portfolio candidates.");
-    let all_validation_results: HashSet<ValidationResult> = portfolio_candidates.validate();
+/// Calculate optimal allocation for a set of simple bets given by their probabilities of a win and
+/// a loss and their odds
+#[endpoint {
+    method = POST,
+    path = "/allocate/bets",
+    tags = [ "allocate" ],
+}]
+pub async fn allocate_bets_endpoint(
+    rqctx: RequestContext<ServerState>,
+    body: TypedBody<BetsInput>,
+) -> Result<AllocationHttpResponse, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "allocate_bets_endpoint");
+    let bets_input = body.into_inner();
+    match invalid_bets_response(&bets_input, &rqctx.log) {
+        Some(response) => {
+            encode_response(
+                &rqctx.request,
+                HttpResponseHeaders::new(
+                    HttpResponseOk(response),
+                    AllocationQueueHeaders {
+                        x_queue_position: 0.to_string(),
+                        x_allocation_id: String::new(),
+                    },
+                ),
+            )
+            .await
+        }
+        None => {
+            allocate_in_queue(
+                &rqctx,
+                bets_input.to_allocation_input(),
+                AllocationOptions::default(),
+            )
+            .await
+        }
+    }
+}
 
-    // Remove OK validation result and return
-    let validation_problems: Vec<ValidationResult> = all_validation_results
-        .into_iter()
-        .filter(|vr| vr != &ValidationResult::OK)
-        .collect();
+/// Response of the allocation endpoints, compressed if the request accepts it.
+type AllocationHttpResponse = HttpResponseEncoded<
+    HttpResponseHeaders<HttpResponseOk<AllocationResponse>, AllocationQueueHeaders>,
+>;
 
-    info!(
-        logger,
-        "Found {} validation problems.",
-        validation_problems.len()
+/// Headers of the allocation responses.
+#[derive(Serialize, JsonSchema)]
+pub struct AllocationQueueHeaders {
+    /// Position of the request in the allocation queue when it arrived. Zero means that the
+    /// allocation started immediately.
+    #[serde(rename = "x-queue-position")]
+    x_queue_position: String,
+    /// Id under which the allocation is recorded, such that it can be re-run later with
+    /// `POST /allocations/{id}/rerun`. Empty if the allocation was not recorded.
+    #[serde(rename = "x-allocation-id")]
+    x_allocation_id: String,
+}
+
+/// Performs the allocation in the allocation queue and records it in the allocation store. Failing
+/// to record the allocation doesn't fail the request, since the allocation itself succeeded.
+async fn allocate_in_queue(
+    rqctx: &RequestContext<ServerState>,
+    allocation_input: AllocationInput,
+    options: AllocationOptions,
+) -> Result<AllocationHttpResponse, HttpError> {
+    let recorded_input = allocation_input.clone();
+    let (allocation_response, queue_position) =
+        allocate_queued(rqctx, allocation_input, options).await?;
+    let allocation_id = record_allocation(
+        rqctx.context(),
+        &rqctx.log,
+        &api_key(rqctx),
+        recorded_input,
+        &allocation_response,
     );
 
-    validation_problems
+    encode_response(
+        &rqctx.request,
+        HttpResponseHeaders::new(
+            HttpResponseOk(allocation_response),
+            AllocationQueueHeaders {
+                x_queue_position: queue_position.to_string(),
+                x_allocation_id: allocation_id.map_or(String::new(), |id| id.to_string()),
+            },
+        ),
+    )
+    .await
 }
 
-/// Calculates optimal allocation for each candidate company
-pub fn allocate(allocation_input: AllocationInput, logger: &Logger) -> AllocationResponse {
-    info!(logger, "Started allocation.");
-
-    // Return immediately if there is at least one validation error
-    let validation_problems: Vec<ValidationResult> = validate(&allocation_input, logger);
-    if validation_problems.iter().any(|v| match v {
-        ValidationResult::PROBLEM(p) => p.severity == ERROR,
-        ValidationResult::OK => false,
-    }) {
-        info!(logger, "Validation problems found, returning them.");
-        return AllocationResponse {
-            result: None,
-            validation_problems: Some(validation_problems),
-            error: None,
-        };
+/// Records the allocation for the API key in the allocation store and returns its id, or None if
+/// recording failed.
+fn record_allocation(
+    state: &ServerState,
+    logger: &Logger,
+    api_key: &str,
+    allocation_input: AllocationInput,
+    allocation_response: &AllocationResponse,
+) -> Option<u64> {
+    match state
+        .allocation_store
+        .record(api_key, allocation_input, allocation_response.clone())
+    {
+        Ok(id) => {
+            info!(logger, "Recorded the allocation with id {id}.");
+            Some(id)
+        }
+        Err(e) => {
+            warn!(logger, "Failed to record the allocation: {}", e.message);
+            None
+        }
     }
+}
+
+/// Waits for a free slot in the allocation queue and performs the allocation in it. The
+/// allocation is accounted to the API key of the request, and rejected if the API key has exceeded
+/// its quota. Returns the response together with the position in the queue when the request
+/// arrived.
+async fn allocate_queued(
+    rqctx: &RequestContext<ServerState>,
+    allocation_input: AllocationInput,
+    options: AllocationOptions,
+) -> Result<(AllocationResponse, usize), HttpError> {
+    let state = rqctx.context();
+    let api_key = api_key(rqctx);
+    state.usage.check_quota(&api_key).map_err(quota_exceeded)?;
 
-    // Create a subset of all candidates that can be handled by the algorithm. We don't allow:
-    // 1. Candidates that have a negative expected return (would result in shorting),
-    // 2. Candidates that don't have any downside (would result in numerical failure because the
-    //    mathematical solution is to put an infinite amount of levered money into it)
+    let drift_warnings = market_cap_drift_warnings(state, &allocation_input, &rqctx.log).await;
+    let slot = acquire_allocation_slot(state, &rqctx.log)
+        .await
+        .map_err(|e| HttpError::for_unavail(Some(e.code), e.message))?;
+    let allocation_response = allocate_blocking(
+        state,
+        &api_key,
+        allocation_input,
+        options,
+        drift_warnings,
+        None,
+        rqctx.log.clone(),
+    )
+    .await
+    .map_err(|e| HttpError::for_internal_error(e.message))?;
+
+    Ok((allocation_response, slot.queue_position))
+}
+
+/// Waits for a free slot in the allocation queue. Fails immediately if the queue is full.
+async fn acquire_allocation_slot(
+    state: &ServerState,
+    logger: &Logger,
+) -> Result<AllocationSlot, Error> {
     info!(
         logger,
-        "Start filtering candidates that would produce undesirable results."
+        "Waiting for a free allocation slot. Currently queued allocations: {}.",
+        state.allocation_queue.n_queued()
     );
-    let mut filtered_candidates: Vec<Company> = vec![];
-    allocation_input.candidates.into_iter().for_each(|c| {
-        let downside_validation = c.validate_no_downside_scenario();
-        match &downside_validation {
-            ValidationResult::PROBLEM(problem) => info!(logger, "{}", problem.message),
-            ValidationResult::OK => (),
+    state.allocation_queue.acquire().await
+}
+
+/// Fetches the live market caps of the candidates from the market data provider, if configured,
+/// and returns the warnings about the market caps that drifted from them. Called before waiting
+/// for a slot of the allocation queue, such that a slow provider doesn't hold the slot.
+async fn market_cap_drift_warnings(
+    state: &ServerState,
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> Vec<ValidationResult> {
+    match &state.market_data {
+        Some(market_data) => {
+            market_data
+                .drift_warnings(&allocation_input.candidates, logger)
+                .await
         }
+        None => Vec::new(),
+    }
+}
 
-        let negative_expected_return_validation = c.validate_negative_expected_return();
-        match &negative_expected_return_validation {
-            ValidationResult::PROBLEM(problem) => info!(logger, "{}", problem.message),
-            ValidationResult::OK => (),
+/// Performs the allocation on a thread dedicated to blocking work, such that the allocations don't
+/// block the server, and accounts it to the API key. The drift warnings of the market caps are
+/// added to the validation problems of the response. The observer, if any, is invoked while
+/// solving. Must be called with a slot of the allocation queue.
+async fn allocate_blocking(
+    state: &ServerState,
+    api_key: &str,
+    allocation_input: AllocationInput,
+    options: AllocationOptions,
+    drift_warnings: Vec<ValidationResult>,
+    observer: Option<Arc<dyn SolverObserver + Send + Sync>>,
+    logger: Logger,
+) -> Result<AllocationResponse, Error> {
+    let (allocation_response, cpu_seconds) = tokio::task::spawn_blocking(move || {
+        let start = Instant::now();
+        let mut allocation_response = allocate_with_options_and_observer(
+            allocation_input,
+            &options,
+            observer.as_deref().map(|o| o as &dyn SolverObserver),
+            &logger,
+        );
+        if !drift_warnings.is_empty() {
+            allocation_response
+                .validation_problems
+                .get_or_insert_with(Vec::new)
+                .extend(drift_warnings);
         }
+        (allocation_response, start.elapsed().as_secs_f64())
+    })
+    .await
+    .map_err(|e| Error {
+        code: "allocation-failed".to_string(),
+        message: format!("Allocation failed: {e}"),
+    })?;
+
+    let n_systems = allocation_response.result.as_ref().map_or(0, |r| {
+        r.solver_metadata.n_systems
+            + r.deleveraging_suggestion
+                .as_ref()
+                .map_or(0, |s| s.n_systems)
+            + r.capped_alternative.as_ref().map_or(0, |a| a.n_systems)
+    });
+    state.usage.record(
+        api_key,
+        &Usage {
+            requests: 1,
+            cpu_seconds,
+            n_systems: n_systems as u64,
+        },
+    );
+
+    Ok(allocation_response)
+}
+
+/// Start the allocation in the background and return the job, whose status and progress can be
+/// polled with `GET /jobs/{id}`. Meant for constrained allocations of many candidates, which can
+/// take minutes to solve
+#[endpoint {
+    method = POST,
+    path = "/allocate/async",
+    tags = [ "allocate" ],
+}]
+pub async fn allocate_async_endpoint(
+    rqctx: RequestContext<ServerState>,
+    query: Query<AllocationOptions>,
+    body: TypedBody<AllocationInput>,
+) -> Result<HttpResponseAccepted<JobLink>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "allocate_async_endpoint");
+    let api_key = api_key(&rqctx);
+    rqctx
+        .context()
+        .usage
+        .check_quota(&api_key)
+        .map_err(quota_exceeded)?;
+
+    let job = rqctx.context().jobs.create(&api_key);
+    let id = job.id();
+    info!(rqctx.log, "Started allocation job {id}.");
+    let server = rqctx.server.clone();
+    let logger = rqctx.log.clone();
+    let allocation_input = body.into_inner();
+    let options = query.into_inner();
+    tokio::spawn(async move {
+        let state = &server.private;
+        let drift_warnings = market_cap_drift_warnings(state, &allocation_input, &logger).await;
+        let _slot = match acquire_allocation_slot(state, &logger).await {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!(logger, "Allocation job {id} failed: {}", e.message);
+                job.fail(e);
+                return;
+            }
+        };
+        job.start();
 
-        // If both of these validation are ok, add the company
-        if downside_validation == ValidationResult::OK
-            && negative_expected_return_validation == ValidationResult::OK
+        let recorded_input = allocation_input.clone();
+        let observer = job.clone() as Arc<dyn SolverObserver + Send + Sync>;
+        match allocate_blocking(
+            state,
+            &api_key,
+            allocation_input,
+            options,
+            drift_warnings,
+            Some(observer),
+            logger.clone(),
+        )
+        .await
         {
-            filtered_candidates.push(c)
-        } else {
-            info!(logger, "Filtered out candidate {} because it either has a negative expected return or it doesn't have any downside.", c.ticker)
+            Ok(response) => {
+                let allocation_id =
+                    record_allocation(state, &logger, &api_key, recorded_input, &response);
+                info!(logger, "Completed allocation job {id}.");
+                job.complete(response, allocation_id);
+            }
+            Err(e) => {
+                warn!(logger, "Allocation job {id} failed: {}", e.message);
+                job.fail(e);
+            }
         }
     });
 
-    // Return if there are no companies after filtering
-    if filtered_candidates.is_empty() {
-        info!(
-            logger,
-            "No valid candidates found after filtering, returning an error."
-        );
-        return AllocationResponse {
-            result: None,
-            validation_problems: Some(validation_problems),
-            error: Some(Error {
-                code: "no-valid-candidates-for-allocation".to_string(),
-                message: "Found no valid candidates for allocation. Check your input.".to_string(),
-            }),
-        };
-    }
+    Ok(HttpResponseAccepted(JobLink {
+        id,
+        path: format!("/jobs/{id}"),
+    }))
+}
+
+/// Path parameter of the endpoints for an allocation job.
+#[derive(Deserialize, JsonSchema)]
+pub struct JobPath {
+    id: u64,
+}
+
+/// Get the status and progress of an allocation job, with the response once it's completed. Jobs
+/// are only found with the API key that started them
+#[endpoint {
+    method = GET,
+    path = "/jobs/{id}",
+    tags = [ "allocate" ],
+}]
+pub async fn get_job_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<JobPath>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<AllocationJob>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_job_endpoint");
+    let id = path.into_inner().id;
+    let job = rqctx
+        .context()
+        .jobs
+        .get(id, &api_key(&rqctx))
+        .ok_or_else(|| HttpError::for_not_found(None, format!("Job {id} not found.")))?;
+    encode_response(&rqctx.request, HttpResponseOk(job)).await
+}
+
+/// Returns the API key of the request, given in the x-api-key header, or the anonymous API key if
+/// the header is missing.
+fn api_key(rqctx: &RequestContext<ServerState>) -> String {
+    rqctx
+        .request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map_or(ANONYMOUS_API_KEY.to_string(), |v| v.to_string())
+}
+
+fn quota_exceeded(e: Error) -> HttpError {
+    HttpError::for_client_error(Some(e.code), StatusCode::TOO_MANY_REQUESTS, e.message)
+}
+
+/// Path parameter of the endpoints for a recorded allocation.
+#[derive(Deserialize, JsonSchema)]
+pub struct AllocationPath {
+    id: u64,
+}
 
+/// Re-run a recorded allocation with the current code and compare the new response with the
+/// original one. Since the input is the same, any difference is due to changes in the code. Only
+/// the allocations recorded for the API key of the request can be re-run.
+#[endpoint {
+    method = POST,
+    path = "/allocations/{id}/rerun",
+    tags = [ "allocate" ],
+}]
+pub async fn rerun_allocation_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<AllocationPath>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<RerunResponse>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "rerun_allocation_endpoint");
+    let id = path.into_inner().id;
+    let state = rqctx.context();
+    let stored = state
+        .allocation_store
+        .get(id, &api_key(&rqctx))
+        .map_err(|e| HttpError::for_internal_error(e.message))?
+        .ok_or_else(|| HttpError::for_not_found(None, format!("Allocation {id} not found.")))?;
     info!(
-        logger,
-        "Calculating the optimal allocation for {} candidates.",
-        filtered_candidates.len()
+        rqctx.log,
+        "Re-running allocation {id} performed by version {}.", stored.crate_version
     );
-    let mut kelly_allocator = KellyAllocator::new(logger, MAX_ITER);
 
-    // Add constraints if present
-    if allocation_input.long_only.unwrap_or(false) {
-        kelly_allocator = kelly_allocator.with_long_only_constraints(filtered_candidates.len());
-    }
+    let (rerun, _) = allocate_queued(&rqctx, stored.input, AllocationOptions::default()).await?;
+    let rerun_response = HttpResponseOk(RerunResponse {
+        id,
+        original_crate_version: stored.crate_version,
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        diff: allocation_diff(&stored.response, &rerun),
+        original: stored.response,
+        rerun,
+    });
+    encode_response(&rqctx.request, rerun_response).await
+}
 
-    if allocation_input.max_permanent_loss_of_capital.is_some() {
-        let lc = allocation_input.max_permanent_loss_of_capital.unwrap();
-        kelly_allocator = kelly_allocator.with_maximum_permanent_loss_constraint(lc);
-    }
+/// Create a read-only link to a recorded allocation, valid for the given number of hours. The link
+/// renders the allocation and its analysis as an HTML page for recipients without access to the
+/// API, and its token is signed such that it can't be modified or extended. Only the allocations
+/// recorded for the API key of the request can be shared.
+#[endpoint {
+    method = POST,
+    path = "/allocations/{id}/share",
+    tags = [ "share" ],
+}]
+pub async fn share_allocation_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<AllocationPath>,
+    body: TypedBody<ShareRequest>,
+) -> Result<HttpResponseOk<ShareLink>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "share_allocation_endpoint");
+    let id = path.into_inner().id;
+    let state = rqctx.context();
+    state
+        .allocation_store
+        .get(id, &api_key(&rqctx))
+        .map_err(|e| HttpError::for_internal_error(e.message))?
+        .ok_or_else(|| HttpError::for_not_found(None, format!("Allocation {id} not found.")))?;
 
-    if allocation_input.max_individual_allocation.is_some() {
-        let max_f = allocation_input.max_individual_allocation.unwrap();
-        kelly_allocator = kelly_allocator
-            .with_maximum_individual_allocation_constraint(filtered_candidates.len(), max_f);
+    let expires_in_hours = body
+        .into_inner()
+        .expires_in_hours
+        .unwrap_or(state.sharing.default_expiry_hours);
+    if expires_in_hours == 0 || expires_in_hours > state.sharing.max_expiry_hours {
+        return Err(HttpError::for_bad_request(
+            Some("invalid-share-expiry".to_string()),
+            format!(
+                "Share links must expire within 1 to {} hours.",
+                state.sharing.max_expiry_hours
+            ),
+        ));
     }
+    let expires_at = DateTime::from_timestamp(Utc::now().timestamp(), 0)
+        .expect("Current time is a valid timestamp.")
+        + Duration::hours(expires_in_hours as i64);
+    let token = state.share_signer.sign(id, expires_at);
+    info!(
+        rqctx.log,
+        "Shared allocation {id} with a link expiring at {expires_at}."
+    );
 
-    if allocation_input.max_total_leverage_ratio.is_some() {
-        let max_lr = allocation_input.max_total_leverage_ratio.unwrap();
-        kelly_allocator = kelly_allocator
-            .with_maximum_total_leverage_constraint(filtered_candidates.len(), max_lr);
-    }
+    Ok(HttpResponseOk(ShareLink {
+        path: format!("/shared/{token}"),
+        token,
+        expires_at,
+    }))
+}
 
-    let portfolio = match kelly_allocator.allocate(filtered_candidates) {
-        Ok(p) => p,
-        Err(e) => {
-            return AllocationResponse {
-                result: None,
-                validation_problems: Some(validation_problems),
-                error: Some(e),
-            };
-        }
-    };
+/// Path parameter of the shared page.
+#[derive(Deserialize, JsonSchema)]
+pub struct SharePath {
+    token: String,
+}
 
-    info!(logger, "Allocation complete, collecting allocation result.");
-    let allocation_result: Vec<TickerAndFraction> = portfolio
-        .companies
-        .iter()
-        .map(|pc| TickerAndFraction {
-            ticker: pc.company.ticker.clone(),
-            fraction: pc.fraction,
-        })
-        .collect();
+/// Render a shared allocation as an HTML page, given the token of a valid share link.
+#[endpoint {
+    method = GET,
+    path = "/shared/{token}",
+    tags = [ "share" ],
+}]
+pub async fn get_shared_allocation_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<SharePath>,
+) -> Result<Response<Body>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_shared_allocation_endpoint");
+    let state = rqctx.context();
+    let (id, expires_at) = state
+        .share_signer
+        .verify(&path.into_inner().token, Utc::now())
+        .map_err(|e| HttpError::for_client_error(Some(e.code), StatusCode::FORBIDDEN, e.message))?;
+    let allocation = state
+        .allocation_store
+        .get_shared(id)
+        .map_err(|e| HttpError::for_internal_error(e.message))?
+        .ok_or_else(|| HttpError::for_not_found(None, format!("Allocation {id} not found.")))?;
 
-    info!(
-        logger,
-        "Getting all outcomes in order to calculate some statistics about the portfolio."
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "text/html")
+        .body(render_shared_allocation(&allocation, expires_at).into())?)
+}
+
+/// Analyze the portfolio by calculating useful statistics. The `detail_level` query parameter
+/// (summary, standard or full) controls whether expensive statistics are calculated.
+#[endpoint {
+    method = POST,
+    path = "/analyze",
+    tags = [ "analyze" ],
+}]
+pub async fn analyze_endpoint(
+    rqctx: RequestContext<ServerState>,
+    query: Query<AnalysisOptions>,
+    body: TypedBody<PortfolioWithEvents>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<AnalysisResponse>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "analyze_endpoint");
+    let api_key = api_key(&rqctx);
+    let usage = &rqctx.context().usage;
+    usage.check_quota(&api_key).map_err(quota_exceeded)?;
+
+    let start = Instant::now();
+    let input = body.into_inner();
+    let analysis_result = analyze(
+        input.portfolio,
+        &input.events.unwrap_or_default(),
+        &query.into_inner(),
+        &rqctx.log,
     );
-    let all_outcomes = match all_outcomes(&portfolio) {
-        Ok(o) => o,
-        Err(e) => {
-            info!(
-                logger,
-                "Encountered an error while getting all outcomes. Returning it."
-            );
-            return AllocationResponse {
-                result: None,
-                validation_problems: None,
-                error: Some(e),
-            };
+    usage.record(
+        &api_key,
+        &Usage {
+            requests: 1,
+            cpu_seconds: start.elapsed().as_secs_f64(),
+            n_systems: 0,
+        },
+    );
+
+    encode_response(&rqctx.request, HttpResponseOk(analysis_result)).await
+}
+
+/// Analyze many portfolios, e.g. variants of a strategy, concurrently and return the key statistics
+/// of each portfolio as one row per portfolio, for comparing them at a glance. The query parameters
+/// apply to all portfolios as in `/analyze`
+#[endpoint {
+    method = POST,
+    path = "/analyze/batch",
+    tags = [ "analyze" ],
+}]
+pub async fn analyze_batch_endpoint(
+    rqctx: RequestContext<ServerState>,
+    query: Query<AnalysisOptions>,
+    body: TypedBody<BatchAnalysisInput>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<BatchAnalysisResponse>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "analyze_batch_endpoint");
+    let api_key = api_key(&rqctx);
+    let usage = &rqctx.context().usage;
+    usage.check_quota(&api_key).map_err(quota_exceeded)?;
+
+    let start = Instant::now();
+    let portfolios = body.into_inner().portfolios;
+    let options = query.into_inner();
+    let logger = rqctx.log.clone();
+    let rows = tokio::task::spawn_blocking(move || analyze_batch(portfolios, &options, &logger))
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("Batch analysis failed: {e}")))?;
+    usage.record(
+        &api_key,
+        &Usage {
+            requests: 1,
+            cpu_seconds: start.elapsed().as_secs_f64(),
+            n_systems: 0,
+        },
+    );
+
+    encode_response(
+        &rqctx.request,
+        HttpResponseOk(BatchAnalysisResponse { rows }),
+    )
+    .await
+}
+
+/// Path parameter identifying a named portfolio with analysis snapshots
+#[derive(Deserialize, JsonSchema)]
+pub struct PortfolioNamePath {
+    name: String,
+}
+
+/// Analyze the portfolio like `/analyze` and store the analysis as the next time-stamped, versioned
+/// snapshot of the named portfolio, returning the stored snapshot
+#[endpoint {
+    method = POST,
+    path = "/portfolios/{name}/snapshots",
+    tags = [ "analyze" ],
+}]
+pub async fn post_snapshot_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<PortfolioNamePath>,
+    query: Query<AnalysisOptions>,
+    body: TypedBody<PortfolioWithEvents>,
+) -> Result<HttpResponseOk<AnalysisSnapshot>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "post_snapshot_endpoint");
+    let name = path.into_inner().name;
+    let api_key = api_key(&rqctx);
+    let usage = &rqctx.context().usage;
+    usage.check_quota(&api_key).map_err(quota_exceeded)?;
+
+    let start = Instant::now();
+    let input = body.into_inner();
+    let analysis_response = analyze(
+        input.portfolio.clone(),
+        &input.events.unwrap_or_default(),
+        &query.into_inner(),
+        &rqctx.log,
+    );
+    usage.record(
+        &api_key,
+        &Usage {
+            requests: 1,
+            cpu_seconds: start.elapsed().as_secs_f64(),
+            n_systems: 0,
+        },
+    );
+    let analysis = match analysis_response {
+        AnalysisResponse {
+            result: Some(result),
+            ..
+        } => result,
+        AnalysisResponse { error, .. } => {
+            let e = error.unwrap_or_else(|| Error {
+                code: "analysis-failed".to_string(),
+                message: "The analysis did not return a result.".to_string(),
+            });
+            return Err(HttpError::for_bad_request(Some(e.code), e.message));
         }
     };
-    let worst_case = worst_case_outcome(&portfolio, logger);
 
     info!(
-        logger,
-        "Allocation and analysis finished. Returning the allocation and analysis results."
+        rqctx.log,
+        "Recording analysis snapshot of portfolio {name}."
     );
-    AllocationResponse {
-        result: Some(AllocationResult {
-            allocations: allocation_result,
-            analysis: AnalysisResult {
-                worst_case_outcome: worst_case,
-                cumulative_probability_of_loss: cumulative_probability_of_loss(
-                    &all_outcomes,
-                    logger,
-                ),
-                expected_return: expected_return(&portfolio, logger),
-            },
-        }),
-        validation_problems: Some(validation_problems),
-        error: None,
+    match rqctx
+        .context()
+        .snapshot_store
+        .record(&name, input.portfolio, analysis)
+    {
+        Ok(snapshot) => Ok(HttpResponseOk(snapshot)),
+        Err(e) if e.code == STORAGE_ERROR => Err(HttpError::for_internal_error(e.message)),
+        Err(e) => Err(HttpError::for_bad_request(Some(e.code), e.message)),
     }
 }
 
-/// Calculates useful information about the portfolio
-pub fn analyze(portfolio: Portfolio, logger: &Logger) -> AnalysisResponse {
-    info!(
-        logger,
-        "Started portfolio analysis by getting all outcomes."
-    );
-    let all_outcomes = match all_outcomes(&portfolio) {
-        Ok(o) => o,
-        Err(e) => {
-            info!(
-                logger,
-                "Encountered an error while getting all outcomes. Returning it."
-            );
-            return AnalysisResponse {
-                result: None,
-                error: Some(e),
-            };
-        }
-    };
-    let worst_case = worst_case_outcome(&portfolio, logger);
+/// Get how the expected return, the tail risk and the weights of a named portfolio evolved across
+/// its analysis snapshots, oldest first, with the changes since the previous snapshot
+#[endpoint {
+    method = GET,
+    path = "/portfolios/{name}/snapshots",
+    tags = [ "analyze" ],
+}]
+pub async fn get_snapshot_history_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<PortfolioNamePath>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<SnapshotHistory>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_snapshot_history_endpoint");
+    let name = path.into_inner().name;
+    match rqctx.context().snapshot_store.history(&name) {
+        Some(history) => encode_response(&rqctx.request, HttpResponseOk(history)).await,
+        None => Err(HttpError::for_not_found(
+            Some("portfolio-not-found".to_string()),
+            format!("Did not find analysis snapshots of portfolio {name}."),
+        )),
+    }
+}
+
+/// Validate the allocation input without allocating, returning the same validation problems as the
+/// allocation, such that front-ends can give immediate feedback while the input is being edited
+#[endpoint {
+    method = POST,
+    path = "/validate",
+    tags = [ "validate" ],
+}]
+pub async fn validate_endpoint(
+    rqctx: RequestContext<ServerState>,
+    body: TypedBody<AllocationInput>,
+) -> Result<HttpResponseOk<ValidationResponse>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "validate_endpoint");
+    Ok(HttpResponseOk(ValidationResponse {
+        validation_problems: validate(&body.into_inner(), &rqctx.log),
+    }))
+}
+
+/// Validate a single company on its own, e.g. one file of a library of candidates, returning the
+/// same validation problems as the validation of the whole allocation input
+#[endpoint {
+    method = POST,
+    path = "/validate/company",
+    tags = [ "validate" ],
+}]
+pub async fn validate_company_endpoint(
+    rqctx: RequestContext<ServerState>,
+    body: TypedBody<Company>,
+) -> Result<HttpResponseOk<ValidationResponse>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "validate_company_endpoint");
+    Ok(HttpResponseOk(ValidationResponse {
+        validation_problems: validate_company(&body.into_inner(), &rqctx.log),
+    }))
+}
+
+/// Get the usage of the API key given in the x-api-key header per month, together with its
+/// monthly quota
+#[endpoint {
+    method = GET,
+    path = "/usage",
+    tags = [ "usage" ],
+}]
+pub async fn get_usage_endpoint(
+    rqctx: RequestContext<ServerState>,
+) -> Result<HttpResponseOk<UsageReport>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_usage_endpoint");
+    Ok(HttpResponseOk(
+        rqctx.context().usage.report(&api_key(&rqctx)),
+    ))
+}
+
+/// Path parameter identifying a stored input template
+#[derive(Deserialize, JsonSchema)]
+pub struct TemplatePath {
+    name: String,
+}
+
+/// Store a named input template with placeholders (e.g. `{{max_leverage}}`), replacing an existing
+/// template with the same name. Templates that aren't valid YAML are rejected
+#[endpoint {
+    method = PUT,
+    path = "/templates/{name}",
+    tags = [ "templates" ],
+}]
+pub async fn put_template_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<TemplatePath>,
+    body: TypedBody<InputTemplate>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "put_template_endpoint");
+    let name = path.into_inner().name;
+    let template = body.into_inner();
+    template
+        .validate()
+        .map_err(|e| HttpError::for_bad_request(Some(e.code), e.message))?;
+    info!(
+        rqctx.log,
+        "Storing template {name} with placeholders {:?}.",
+        template.placeholders()
+    );
+    let state = rqctx.context();
+    match save(
+        state.storage.as_ref(),
+        Collection::Templates,
+        &name,
+        &template,
+    ) {
+        Ok(()) => {}
+        Err(e) if e.code == STORAGE_ERROR => return Err(HttpError::for_internal_error(e.message)),
+        Err(e) => return Err(HttpError::for_bad_request(Some(e.code), e.message)),
+    }
+    state.templates.lock().unwrap().insert(name, template);
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+/// Instantiate a stored input template with the given parameters and calculate the optimal
+/// allocation for it
+#[endpoint {
+    method = POST,
+    path = "/templates/{name}/allocate",
+    tags = [ "templates" ],
+}]
+pub async fn allocate_template_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<TemplatePath>,
+    body: TypedBody<TemplateParameters>,
+) -> Result<AllocationHttpResponse, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "allocate_template_endpoint");
+    let name = path.into_inner().name;
+    let template = match rqctx.context().template(&name) {
+        Some(t) => t,
+        None => {
+            return Err(HttpError::for_not_found(
+                Some("template-not-found".to_string()),
+                format!("Did not find template {name}."),
+            ))
+        }
+    };
+
+    info!(rqctx.log, "Instantiating template {name}.");
+    match template.instantiate(&body.into_inner()) {
+        Ok(input) => allocate_in_queue(&rqctx, input, AllocationOptions::default()).await,
+        Err(e) => {
+            encode_response(
+                &rqctx.request,
+                HttpResponseHeaders::new(
+                    HttpResponseOk(AllocationResponse {
+                        result: None,
+                        validation_problems: None,
+                        error: Some(e),
+                        logs: None,
+                    }),
+                    AllocationQueueHeaders {
+                        x_queue_position: 0.to_string(),
+                        x_allocation_id: String::new(),
+                    },
+                ),
+            )
+            .await
+        }
+    }
+}
+
+/// Path parameter identifying a workspace with stored candidates
+#[derive(Deserialize, JsonSchema)]
+pub struct WorkspacePath {
+    workspace: String,
+}
+
+/// Push updated candidates into a workspace, merging them into the stored candidates by ticker
+/// (or replacing them), and return the stored candidates with their new version
+#[endpoint {
+    method = PUT,
+    path = "/candidates/{workspace}",
+    tags = [ "candidates" ],
+}]
+pub async fn put_candidates_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<WorkspacePath>,
+    body: TypedBody<CandidateUpdate>,
+) -> Result<HttpResponseOk<StoredCandidates>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "put_candidates_endpoint");
+    let workspace = path.into_inner().workspace;
+    let update = body.into_inner();
+    info!(
+        rqctx.log,
+        "Updating {} candidates in workspace {workspace}.",
+        update.candidates.len()
+    );
+
+    match rqctx.context().candidate_store.update(&workspace, update) {
+        Ok(stored) => Ok(HttpResponseOk(stored)),
+        Err(e) if e.code == "candidate-version-conflict" => Err(HttpError::for_client_error(
+            Some(e.code),
+            StatusCode::CONFLICT,
+            e.message,
+        )),
+        Err(e) if e.code == STORAGE_ERROR => Err(HttpError::for_internal_error(e.message)),
+        Err(e) => Err(HttpError::for_bad_request(Some(e.code), e.message)),
+    }
+}
+
+/// Get the latest candidates stored in a workspace
+#[endpoint {
+    method = GET,
+    path = "/candidates/{workspace}",
+    tags = [ "candidates" ],
+}]
+pub async fn get_candidates_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<WorkspacePath>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<StoredCandidates>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_candidates_endpoint");
+    let workspace = path.into_inner().workspace;
+    let stored = stored_candidates(rqctx.context(), &workspace)?;
+    encode_response(&rqctx.request, HttpResponseOk(stored)).await
+}
+
+/// Path parameters identifying a candidate in a workspace
+#[derive(Deserialize, JsonSchema)]
+pub struct WorkspaceTickerPath {
+    workspace: String,
+    ticker: Ticker,
+}
+
+/// Get all revisions of a candidate stored in a workspace, oldest first
+#[endpoint {
+    method = GET,
+    path = "/candidates/{workspace}/history/{ticker}",
+    tags = [ "candidates" ],
+}]
+pub async fn get_candidate_history_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<WorkspaceTickerPath>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<Vec<CandidateRevision>>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_candidate_history_endpoint");
+    let WorkspaceTickerPath { workspace, ticker } = path.into_inner();
+    match rqctx.context().candidate_store.history(&workspace, &ticker) {
+        Some(history) => encode_response(&rqctx.request, HttpResponseOk(history)).await,
+        None => Err(workspace_not_found(&workspace)),
+    }
+}
+
+/// Get the candidates stored in a workspace as they were after the given `version` and/or at the
+/// given time `as_of`, for reproducing past allocations
+#[endpoint {
+    method = GET,
+    path = "/candidates/{workspace}/snapshot",
+    tags = [ "candidates" ],
+}]
+pub async fn get_candidates_snapshot_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<WorkspacePath>,
+    query: Query<SnapshotQuery>,
+) -> Result<HttpResponseEncoded<HttpResponseOk<StoredCandidates>>, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "get_candidates_snapshot_endpoint");
+    let workspace = path.into_inner().workspace;
+    match rqctx
+        .context()
+        .candidate_store
+        .snapshot(&workspace, &query.into_inner())
+    {
+        Some(snapshot) => encode_response(&rqctx.request, HttpResponseOk(snapshot)).await,
+        None => Err(HttpError::for_not_found(
+            Some("snapshot-not-found".to_string()),
+            format!("Did not find candidates in workspace {workspace} at the requested point."),
+        )),
+    }
+}
+
+/// Calculate the optimal allocation for the latest candidates stored in a workspace. Candidates
+/// given in the body are merged into the stored ones by ticker, and the constraints are taken from
+/// the body
+#[endpoint {
+    method = POST,
+    path = "/candidates/{workspace}/allocate",
+    tags = [ "candidates" ],
+}]
+pub async fn allocate_candidates_endpoint(
+    rqctx: RequestContext<ServerState>,
+    path: Path<WorkspacePath>,
+    body: TypedBody<AllocationInput>,
+) -> Result<AllocationHttpResponse, HttpError> {
+    let _completion = CompletionLog::new(&rqctx.log, "allocate_candidates_endpoint");
+    let workspace = path.into_inner().workspace;
+    let stored = stored_candidates(rqctx.context(), &workspace)?;
+    info!(
+        rqctx.log,
+        "Allocating version {} of the candidates in workspace {workspace}.", stored.version
+    );
+
+    let mut input = body.into_inner();
+    let mut candidates = stored.candidates;
+    for company in input.candidates {
+        match candidates.iter_mut().find(|c| c.ticker == company.ticker) {
+            Some(c) => *c = company,
+            None => candidates.push(company),
+        }
+    }
+    input.candidates = candidates;
+
+    allocate_in_queue(&rqctx, input, AllocationOptions::default()).await
+}
+
+/// Returns the candidates stored in the workspace, or the not found error.
+fn stored_candidates(state: &ServerState, workspace: &str) -> Result<StoredCandidates, HttpError> {
+    state
+        .candidate_store
+        .get(workspace)
+        .ok_or_else(|| workspace_not_found(workspace))
+}
+
+fn workspace_not_found(workspace: &str) -> HttpError {
+    HttpError::for_not_found(
+        Some("workspace-not-found".to_string()),
+        format!("Did not find candidates in workspace {workspace}."),
+    )
+}
+
+/// Validate the candidates and return all problematic validations.
+pub fn validate(portfolio_candidates: &AllocationInput, logger: &Logger) -> Vec<ValidationResult> {
+    info!(logger, "Performing validation of portfolio candidates.");
+    // The candidates are validated with their monetary values in the same units
+    let (normalized_candidates, mut validation_problems) =
+        portfolio_candidates.with_normalized_units();
+    let all_validation_results: IndexSet<ValidationResult> = normalized_candidates.validate();
+
+    // Remove OK validation result and return
+    validation_problems.extend(
+        all_validation_results
+            .into_iter()
+            .filter(|vr| vr != &ValidationResult::OK),
+    );
+
+    info!(
+        logger,
+        "Found {} validation problems.",
+        validation_problems.len()
+    );
+
+    validation_problems
+}
+
+/// Validates a single company and returns the validation problems.
+pub fn validate_company(company: &Company, logger: &Logger) -> Vec<ValidationResult> {
+    info!(
+        logger,
+        "Performing validation of company {}.", company.ticker
+    );
+    let (normalized_company, unit_validation) = company.with_normalized_units(None);
+    let validation_problems: Vec<ValidationResult> = std::iter::once(unit_validation)
+        .chain(normalized_company.validate())
+        .filter(|vr| vr != &ValidationResult::OK)
+        .collect();
+
+    info!(
+        logger,
+        "Found {} validation problems.",
+        validation_problems.len()
+    );
+
+    validation_problems
+}
+
+/// Validates the odds and loss fractions of the bets and returns the response with the validation
+/// problems if there is at least one error. The probabilities are validated by [allocate] after
+/// the bets are converted into companies.
+fn invalid_bets_response(bets_input: &BetsInput, logger: &Logger) -> Option<AllocationResponse> {
+    let validation_problems: Vec<ValidationResult> = bets_input
+        .validate()
+        .into_iter()
+        .filter(|vr| vr != &ValidationResult::OK)
+        .collect();
+
+    if validation_problems.is_empty() {
+        return None;
+    }
+
+    info!(
+        logger,
+        "Validation problems found in the bets, returning them."
+    );
+    Some(AllocationResponse {
+        result: None,
+        validation_problems: Some(validation_problems),
+        error: None,
+        logs: None,
+    })
+}
+
+/// Calculates optimal allocation for each bet by converting the bets into companies
+pub fn allocate_bets(bets_input: BetsInput, logger: &Logger) -> AllocationResponse {
+    match invalid_bets_response(&bets_input, logger) {
+        Some(response) => response,
+        None => allocate(bets_input.to_allocation_input(), logger),
+    }
+}
+
+/// Same as [allocate], with the options of the allocation. If requested, the solver is traced for
+/// the unconstrained problem of the allocated candidates, and the log messages of the allocation
+/// are returned in the response.
+pub fn allocate_with_options(
+    allocation_input: AllocationInput,
+    options: &AllocationOptions,
+    logger: &Logger,
+) -> AllocationResponse {
+    allocate_with_options_and_observer(allocation_input, options, None, logger)
+}
+
+/// Same as [allocate_with_options], invoking the observer while solving the systems of the
+/// allocation, e.g. for reporting the progress of an allocation job.
+pub(crate) fn allocate_with_options_and_observer(
+    allocation_input: AllocationInput,
+    options: &AllocationOptions,
+    observer: Option<&dyn SolverObserver>,
+    logger: &Logger,
+) -> AllocationResponse {
+    let capture = options
+        .log_level
+        .map(|level| LogCapture::new(Level::from(level)));
+    let logger = match &capture {
+        Some(capture) => capture.logger(logger),
+        None => logger.clone(),
+    };
+
+    let candidates = allocation_input
+        .with_call_options_as_candidates()
+        .with_pair_trades_as_candidates()
+        .candidates;
+    let mut allocation_response = if options.solver_state.unwrap_or(false) {
+        let recorder = SolverStateRecorder::default();
+        let observers = SolverObservers(observer.into_iter().chain([&recorder as _]).collect());
+        let mut response =
+            allocate_with_observer(allocation_input.clone(), Some(&observers), &logger);
+        if let Some(result) = response.result.as_mut() {
+            result.solver_state =
+                Some(recorder.solver_state(allocation_input, result.allocations.clone()));
+        }
+        response
+    } else {
+        allocate_with_observer(allocation_input, observer, &logger)
+    };
+    if options.trace.unwrap_or(false) {
+        add_solver_trace(&mut allocation_response, &candidates, &logger);
+    }
+
+    allocation_response.logs = capture.map(|c| c.messages());
+    allocation_response
+}
+
+/// Observers of the same solver, invoked in order.
+struct SolverObservers<'a>(Vec<&'a dyn SolverObserver>);
+
+impl SolverObserver for SolverObservers<'_> {
+    fn on_iteration(&self, iteration: &SolverIteration) {
+        self.0.iter().for_each(|o| o.on_iteration(iteration));
+    }
+
+    fn on_system(&self, system: &SolvedSystem) {
+        self.0.iter().for_each(|o| o.on_system(system));
+    }
+}
+
+/// Adds the trace of the solver for the unconstrained problem of the allocated candidates to the
+/// result. A trace that isn't available, e.g. because too many candidates are allocated, is
+/// reported as a warning.
+fn add_solver_trace(
+    allocation_response: &mut AllocationResponse,
+    candidates: &[Company],
+    logger: &Logger,
+) {
+    let Some(result) = allocation_response.result.as_mut() else {
+        return;
+    };
+
+    let allocated_candidates = result
+        .allocations
+        .iter()
+        .filter_map(|tf| candidates.iter().find(|c| c.ticker == tf.ticker))
+        .map(|c| c.with_status_quo_scenario())
+        .collect();
+    match solver_trace(allocated_candidates, logger) {
+        Ok(trace) => result.solver_trace = Some(trace),
+        Err(e) => {
+            warn!(logger, "{}", e.message);
+            allocation_response
+                .validation_problems
+                .get_or_insert_with(Vec::new)
+                .push(ValidationResult::PROBLEM(Problem {
+                    code: e.code,
+                    message: e.message,
+                    severity: Severity::WARNING,
+                    suggestion: None,
+                }));
+        }
+    }
+}
+
+/// Calculates optimal allocation for each candidate company
+pub fn allocate(allocation_input: AllocationInput, logger: &Logger) -> AllocationResponse {
+    allocate_with_observer(allocation_input, None, logger)
+}
+
+/// Calculates optimal allocation for each candidate company, invoking the observer while solving
+/// the systems of the allocation and of its alternatives.
+pub(crate) fn allocate_with_observer(
+    allocation_input: AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    logger: &Logger,
+) -> AllocationResponse {
+    info!(logger, "Started allocation.");
+
+    // Return immediately if there is at least one validation error
+    let mut validation_problems: Vec<ValidationResult> = validate(&allocation_input, logger);
+    if validation_problems.iter().any(|v| match v {
+        ValidationResult::PROBLEM(p) => p.severity == ERROR,
+        ValidationResult::OK => false,
+    }) {
+        info!(logger, "Validation problems found, returning them.");
+        return AllocationResponse {
+            result: None,
+            validation_problems: Some(validation_problems),
+            error: None,
+            logs: None,
+        };
+    }
+
+    match allocation_result(allocation_input, observer, &mut validation_problems, logger) {
+        Ok(allocation_result) => AllocationResponse {
+            result: Some(allocation_result),
+            validation_problems: Some(validation_problems),
+            error: None,
+            logs: None,
+        },
+        Err(e) => error_response(e, validation_problems),
+    }
+}
+
+/// Returns the response of an allocation that failed with the given error, together with the
+/// validation problems collected before the failure.
+fn error_response(error: Error, validation_problems: Vec<ValidationResult>) -> AllocationResponse {
+    AllocationResponse {
+        result: None,
+        validation_problems: Some(validation_problems),
+        error: Some(error),
+        logs: None,
+    }
+}
+
+/// Allocates the validated input and analyzes the allocation, adding the warnings found along the
+/// way to the validation problems.
+fn allocation_result(
+    allocation_input: AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    validation_problems: &mut Vec<ValidationResult>,
+    logger: &Logger,
+) -> Result<AllocationResult, Error> {
+    // Monetary values are normalized into ones, the warnings are among the validation problems
+    let (allocation_input, _) = allocation_input.with_normalized_units();
+
+    // Call options are allocated to like the candidates, with the returns of their payoffs
+    let allocation_input = allocation_input.with_call_options_as_candidates();
+
+    // Pair trades are allocated to like the candidates, with the returns of their spreads
+    let pair_trades = allocation_input.pair_trades.clone().unwrap_or_default();
+    let allocation_input = allocation_input.with_pair_trades_as_candidates();
+
+    let candidates = preconditioned_candidates(&allocation_input, validation_problems, logger);
+    let (filtered_candidates, dropped_candidates) =
+        filter_candidates(candidates, &allocation_input, logger);
+
+    // Return if there are no companies after filtering
+    if filtered_candidates.is_empty() {
+        info!(
+            logger,
+            "No valid candidates found after filtering, returning an error."
+        );
+        return Err(Error {
+            code: "no-valid-candidates-for-allocation".to_string(),
+            message: "Found no valid candidates for allocation. Check your input.".to_string(),
+        });
+    }
+
+    let regularization = allocation_input
+        .solver_settings
+        .as_ref()
+        .and_then(|s| s.regularization)
+        .unwrap_or(0.0);
+    let solver_tolerance = allocation_input
+        .solver_settings
+        .as_ref()
+        .and_then(|s| s.solver_tolerance)
+        .unwrap_or(SOLVER_TOLERANCE);
+    let (allocation_input, target_risk_sizing) =
+        allocate_to_target_risk(allocation_input, &filtered_candidates, observer, logger)?;
+    let KellySolution {
+        portfolio,
+        is_approximate,
+        n_systems,
+        net_exposure_multiplier,
+        ..
+    } = best_allocation(&filtered_candidates, &allocation_input, observer, logger)?;
+
+    info!(logger, "Allocation complete, collecting allocation result.");
+    let allocation_result: Vec<TickerAndFraction> = portfolio
+        .companies
+        .iter()
+        .map(|pc| TickerAndFraction {
+            ticker: pc.company.ticker.clone(),
+            fraction: pc.fraction,
+        })
+        .collect();
+
+    info!(
+        logger,
+        "Getting all outcomes in order to calculate some statistics about the portfolio."
+    );
+    let all_outcomes = financed_outcomes(&portfolio, &allocation_input, logger).map_err(|e| {
+        info!(
+            logger,
+            "Encountered an error while getting all outcomes. Returning it."
+        );
+        e
+    })?;
+    let financing_rates = financing_rates(&allocation_input);
+    let market_neutral_indices = market_neutral_indices(&portfolio, &allocation_input);
+    let cash_return = cash_return(&portfolio, &allocation_input);
+    validation_problems.extend(growth_concentration_warnings(
+        &portfolio,
+        &all_outcomes,
+        allocation_input
+            .max_growth_concentration
+            .unwrap_or(MAX_GROWTH_CONCENTRATION),
+        logger,
+    ));
+    let worst_case = financed_worst_case_outcome(&portfolio, &allocation_input, logger);
+    let diversification = diversification(&portfolio);
+    let log_growth = expected_log_growth(&all_outcomes);
+
+    // If no leverage constraint was given and the allocation turned out to be levered, also
+    // allocate without leverage and suggest that allocation as an alternative
+    let deleveraging_suggestion = if allocation_input.max_total_leverage_ratio.is_none()
+        && diversification.net_exposure > 1.0 + LEVERAGE_TOLERANCE
+    {
+        info!(
+            logger,
+            "Allocation is levered with a net exposure of {}, allocating without leverage as well.",
+            diversification.net_exposure
+        );
+        allocate_without_leverage(
+            &filtered_candidates,
+            &allocation_input,
+            observer,
+            log_growth,
+            logger,
+        )
+    } else {
+        None
+    };
+
+    // Almost all capital in a single candidate is usually a sign of an input error, so the inputs
+    // driving it are reported together with a capped allocation for comparison
+    let concentrated_company = portfolio
+        .companies
+        .iter()
+        .find(|pc| pc.fraction.abs() > MAX_SINGLE_CANDIDATE_FRACTION);
+    let capped_alternative = match concentrated_company {
+        Some(pc) if portfolio.companies.len() > 1 => {
+            validation_problems.push(concentrated_allocation_warning(pc, logger));
+            allocate_capped_alternative(
+                &filtered_candidates,
+                &allocation_input,
+                observer,
+                log_growth,
+                logger,
+            )
+        }
+        _ => None,
+    };
+
+    let conviction_sizing = allocation_input
+        .conviction_tiers
+        .as_ref()
+        .map(|conviction_tiers| {
+            info!(
+                logger,
+                "Projecting the allocation onto the caps of the conviction tiers."
+            );
+            conviction_sizing(
+                &conviction_tiers.project(&portfolio),
+                &allocation_input,
+                log_growth,
+                logger,
+            )
+        })
+        .transpose()?;
+
+    // Reasons of the zero allocations follow from the optimality of the full Kelly portfolio
+    let full_kelly_portfolio =
+        portfolio.scaled(1.0 / allocation_input.kelly_fraction.unwrap_or(1.0));
+    let zero_allocations = zero_allocations(
+        &full_kelly_portfolio,
+        &filtered_candidates,
+        &allocation_input,
+        logger,
+    )?;
+
+    let events = allocation_input
+        .events
+        .iter()
+        .flatten()
+        .map(|e| event_outcome(&portfolio, e))
+        .collect::<Result<Vec<EventOutcome>, Error>>()?;
+
+    let portfolio_expected_return = expected_return(&portfolio, logger) + cash_return;
+    let inflation_adjusted = allocation_input
+        .expected_inflation
+        .map(|inflation| inflation_adjusted(inflation, portfolio_expected_return, Some(log_growth)))
+        .transpose()?;
+
+    info!(
+        logger,
+        "Allocation and analysis finished. Returning the allocation and analysis results."
+    );
+    let exposures =
+        (!pair_trades.is_empty()).then(|| leg_exposures(&allocation_result, &pair_trades));
+    let (cash, gross_exposure) = match &exposures {
+        Some(exposures) => (
+            1.0 - exposures.iter().map(|e| e.fraction).sum::<f64>(),
+            exposures.iter().map(|e| e.fraction.abs()).sum(),
+        ),
+        None => (
+            1.0 - diversification.net_exposure,
+            diversification.gross_exposure,
+        ),
+    };
+
+    let benchmark_comparison = allocation_input
+        .benchmark_portfolio
+        .as_ref()
+        .map(|benchmark_portfolio| {
+            info!(
+                logger,
+                "Comparing the allocation with the benchmark portfolio."
+            );
+            benchmark_comparison(
+                &portfolio,
+                &all_outcomes,
+                &benchmark_portfolio.portfolio(&portfolio),
+                &allocation_input,
+                logger,
+            )
+        })
+        .transpose()?;
+
+    // Data quality is scored on the candidates as given, without the implicit status quo scenarios
+    let today = Utc::now().date_naive();
+    let data_quality = allocation_result
+        .iter()
+        .filter_map(|a| {
+            allocation_input
+                .candidates
+                .iter()
+                .find(|c| c.ticker == a.ticker)
+        })
+        .map(|c| data_quality(c, today))
+        .collect();
+
+    let betting_edges = allocation_result
+        .iter()
+        .filter_map(|a| {
+            allocation_input
+                .candidates
+                .iter()
+                .find(|c| c.ticker == a.ticker)
+        })
+        .map(betting_edge)
+        .collect();
+
+    // Violations of the soft constraints by the allocated fractions, which are in the order of the
+    // candidates of the portfolio
+    let soft_constraint_violations = allocation_input.soft_constraints.as_ref().map(|_| {
+        let candidates = portfolio
+            .companies
+            .iter()
+            .map(|pc| pc.company.clone())
+            .collect_vec();
+        soft_constraints(&allocation_input, &candidates)
+            .into_iter()
+            .map(|(constraint, c, weight)| SoftConstraintViolation {
+                constraint,
+                weight,
+                violation: c.function_value(&portfolio, 0.0).max(0.0),
+            })
+            .collect()
+    });
+
+    let rebalancing = allocation_input
+        .current_weights
+        .as_ref()
+        .map(|current_weights| rebalancing(current_weights, &allocation_result));
+
+    Ok(AllocationResult {
+        allocations: allocation_result,
+        cash,
+        gross_exposure,
+        exposures,
+        analysis: AnalysisResult {
+            worst_case_outcome: worst_case,
+            cumulative_probability_of_loss: Some(cumulative_probability_of_loss(
+                &all_outcomes,
+                logger,
+            )),
+            expected_return: portfolio_expected_return,
+            expected_log_growth_rate: Some(log_growth),
+            compound_growth: compound_growth(log_growth, None).ok(),
+            variance_of_return: analytical_moments(&portfolio).variance,
+            effective_number_of_positions: diversification.effective_number_of_positions,
+            max_weight: diversification.max_weight,
+            gross_exposure: diversification.gross_exposure,
+            net_exposure: diversification.net_exposure,
+            events,
+            inflation_adjusted,
+            sampled_statistics: None,
+            value_at_risk_95: Some(value_at_risk(&all_outcomes, VALUE_AT_RISK_CONFIDENCE)),
+            conditional_value_at_risk_95: Some(conditional_value_at_risk(
+                &all_outcomes,
+                VALUE_AT_RISK_CONFIDENCE,
+            )),
+            probability_of_underperforming_benchmark: allocation_input
+                .benchmark
+                .as_ref()
+                .map(|b| probability_of_return_below(&all_outcomes, b.expected_return)),
+            probability_of_return_below_threshold: None,
+            distribution: None,
+            tag_conditional_outcomes: None,
+            numerical_errors: Some(numerical_errors(
+                &portfolio,
+                Some(&all_outcomes),
+                solver_tolerance,
+            )),
+        },
+        solver_metadata: KellyAllocator::solver_metadata(
+            &all_outcomes,
+            &portfolio,
+            is_approximate,
+            regularization,
+            n_systems,
+            financing_rates,
+            &market_neutral_indices,
+        ),
+        filtered_candidates: dropped_candidates,
+        zero_allocations,
+        deleveraging_suggestion,
+        conviction_sizing,
+        target_risk_sizing,
+        rebalancing,
+        benchmark_comparison,
+        capped_alternative,
+        net_exposure_multiplier,
+        solver_trace: None,
+        solver_state: None,
+        data_quality,
+        soft_constraint_violations,
+        betting_edges,
+    })
+}
+
+/// Returns the candidates with their implicit status quo scenarios made explicit, the scenarios
+/// with negligible probabilities removed and the scenarios of companies with many scenarios
+/// compressed, adding a warning to the validation problems for each changed candidate. This is
+/// done before filtering, such that a candidate whose only downside scenario is negligible or lost
+/// in the compression is filtered out.
+fn preconditioned_candidates(
+    allocation_input: &AllocationInput,
+    validation_problems: &mut Vec<ValidationResult>,
+    logger: &Logger,
+) -> Vec<Company> {
+    let min_scenario_probability = allocation_input
+        .min_scenario_probability
+        .unwrap_or(MIN_SCENARIO_PROBABILITY);
+    info!(
+        logger,
+        "Removing scenarios with probability smaller than {min_scenario_probability}."
+    );
+    let mut warn_about = |validation: ValidationResult| {
+        if let ValidationResult::PROBLEM(problem) = &validation {
+            warn!(logger, "{}", problem.message);
+            validation_problems.push(validation);
+        }
+    };
+    let candidates: Vec<Company> = allocation_input
+        .candidates
+        .iter()
+        .map(|c| {
+            let (preconditioned, validation) = c
+                .with_status_quo_scenario()
+                .without_negligible_scenarios(min_scenario_probability);
+            warn_about(validation);
+            preconditioned
+        })
+        .collect();
+
+    match allocation_input.max_scenarios_per_company {
+        Some(max_scenarios) => {
+            info!(
+                logger,
+                "Compressing scenarios of companies with more than {max_scenarios} scenarios."
+            );
+            candidates
+                .into_iter()
+                .map(|c| {
+                    let (compressed, validation) = c.with_compressed_scenarios(max_scenarios);
+                    warn_about(validation);
+                    compressed
+                })
+                .collect()
+        }
+        None => candidates,
+    }
+}
+
+/// Splits the candidates into a subset that can be handled by the algorithm and the candidates
+/// filtered out, with the reasons. We don't allow:
+/// 1. Candidates that have a negative expected return (would result in shorting),
+/// 2. Candidates that don't have any downside (would result in numerical failure because the
+///    mathematical solution is to put an infinite amount of levered money into it).
+///
+/// Candidates failing the optional screening thresholds are dropped as well, which keeps the
+/// optimization focused when there are many candidates.
+fn filter_candidates(
+    candidates: Vec<Company>,
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> (Vec<Company>, Vec<FilteredCandidate>) {
+    info!(
+        logger,
+        "Start filtering candidates that would produce undesirable results."
+    );
+    let mut filtered_candidates: Vec<Company> = vec![];
+    let mut dropped_candidates: Vec<FilteredCandidate> = vec![];
+    candidates.into_iter().for_each(|c| {
+        let validations = [
+            c.validate_no_downside_scenario(),
+            c.validate_negative_expected_return(),
+            allocation_input.screen(&c),
+        ];
+
+        // If all of these validations are ok, add the company
+        match validations.into_iter().find(|v| v != &ValidationResult::OK) {
+            Some(ValidationResult::PROBLEM(problem)) => {
+                info!(
+                    logger,
+                    "Filtered out candidate {}: {}", c.ticker, problem.message
+                );
+                dropped_candidates.push(FilteredCandidate {
+                    ticker: c.ticker,
+                    code: problem.code,
+                    message: problem.message,
+                });
+            }
+            _ => filtered_candidates.push(c),
+        }
+    });
+
+    (filtered_candidates, dropped_candidates)
+}
+
+/// Returns the input with the Kelly fraction reaching the target risk if one is given, which is
+/// searched for with the full Kelly allocation and then used like a given Kelly fraction, together
+/// with the sizing. Returns the input as is otherwise.
+fn allocate_to_target_risk(
+    allocation_input: AllocationInput,
+    filtered_candidates: &[Company],
+    observer: Option<&dyn SolverObserver>,
+    logger: &Logger,
+) -> Result<(AllocationInput, Option<TargetRiskSizing>), Error> {
+    let Some(target_risk) = allocation_input.target_risk.clone() else {
+        return Ok((allocation_input, None));
+    };
+
+    let solution = best_allocation(filtered_candidates, &allocation_input, observer, logger)?;
+    let sizing = target_risk_sizing(&solution.portfolio, &allocation_input, &target_risk, logger)?;
+    Ok((
+        AllocationInput {
+            kelly_fraction: Some(sizing.kelly_fraction),
+            ..allocation_input
+        },
+        Some(sizing),
+    ))
+}
+
+/// Allocates without leverage for the suggestion to deleverage a levered allocation with the
+/// given expected logarithmic growth. Returns no suggestion if there's no such allocation.
+fn allocate_without_leverage(
+    filtered_candidates: &[Company],
+    allocation_input: &AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    levered_expected_log_growth: f64,
+    logger: &Logger,
+) -> Option<DeleveragingSuggestion> {
+    let unlevered_input = AllocationInput {
+        max_total_leverage_ratio: Some(0.0),
+        ..allocation_input.clone()
+    };
+    match best_allocation(filtered_candidates, &unlevered_input, observer, logger).and_then(|s| {
+        deleveraging_suggestion(&s, allocation_input, levered_expected_log_growth, logger)
+    }) {
+        Ok(suggestion) => Some(suggestion),
+        Err(e) => {
+            info!(
+                logger,
+                "Did not find an allocation without leverage, no suggestion returned: {:?}", e
+            );
+            None
+        }
+    }
+}
+
+/// Allocates with the individual allocations capped at [CAPPED_ALTERNATIVE_MAX_ALLOCATION] for
+/// the alternative to a concentrated allocation with the given expected logarithmic growth.
+/// Returns no alternative if the input caps the individual allocations at least as tightly, or if
+/// there's no such allocation.
+fn allocate_capped_alternative(
+    filtered_candidates: &[Company],
+    allocation_input: &AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    concentrated_expected_log_growth: f64,
+    logger: &Logger,
+) -> Option<CappedAlternative> {
+    if allocation_input
+        .max_individual_allocation
+        .is_some_and(|m| m <= CAPPED_ALTERNATIVE_MAX_ALLOCATION)
+    {
+        return None;
+    }
+
+    let capped_input = AllocationInput {
+        max_individual_allocation: Some(CAPPED_ALTERNATIVE_MAX_ALLOCATION),
+        max_individual_allocation_tickers: None,
+        ..allocation_input.clone()
+    };
+    match best_allocation(filtered_candidates, &capped_input, observer, logger).and_then(|s| {
+        capped_alternative(
+            &s,
+            allocation_input,
+            concentrated_expected_log_growth,
+            logger,
+        )
+    }) {
+        Ok(alternative) => Some(alternative),
+        Err(e) => {
+            info!(
+                logger,
+                "Did not find a capped allocation, no alternative returned: {:?}", e
+            );
+            None
+        }
+    }
+}
+
+/// Returns the net exposure to each security, where the pair trades among the allocations are
+/// expanded into their legs, which are added to the fractions of the same securities. Securities
+/// are in the order of their first appearance.
+fn leg_exposures(
+    allocations: &[TickerAndFraction],
+    pair_trades: &[PairTrade],
+) -> Vec<TickerAndFraction> {
+    let mut exposures: IndexMap<Ticker, f64> = IndexMap::new();
+    for allocation in allocations {
+        let legs = match pair_trades.iter().find(|p| p.ticker == allocation.ticker) {
+            Some(pair_trade) => pair_trade.legs(allocation.fraction).to_vec(),
+            None => vec![allocation.clone()],
+        };
+        for leg in legs {
+            *exposures.entry(leg.ticker).or_insert(0.0) += leg.fraction;
+        }
+    }
+
+    exposures
+        .into_iter()
+        .map(|(ticker, fraction)| TickerAndFraction { ticker, fraction })
+        .collect()
+}
+
+/// Warns if a single joint outcome or a single scenario of a company contributes a larger share of
+/// the expected logarithmic growth than the maximum, which usually indicates overconfident inputs.
+/// Portfolios of a single company are skipped since their growth trivially comes from the
+/// scenarios of that company.
+fn growth_concentration_warnings(
+    portfolio: &Portfolio,
+    outcomes: &[Outcome],
+    max_share: f64,
+    logger: &Logger,
+) -> Vec<ValidationResult> {
+    if portfolio.companies.len() < 2 {
+        return vec![];
+    }
+
+    let concentrations = [
+        (
+            "growth-dominated-by-single-outcome",
+            "outcome",
+            dominant_outcome(portfolio, outcomes),
+        ),
+        (
+            "growth-dominated-by-single-scenario",
+            "scenario",
+            dominant_scenario(portfolio),
+        ),
+    ];
+    concentrations
+        .into_iter()
+        .filter_map(|(code, kind, concentration)| {
+            let concentration = concentration.filter(|c| c.share > max_share)?;
+            let message = format!(
+                "{:.0}% of the expected logarithmic growth comes from the single {kind} {}, which \
+                usually indicates overconfident inputs. Check the probabilities and intrinsic \
+                values of its scenarios.",
+                100.0 * concentration.share,
+                concentration.theses.join(", ")
+            );
+            warn!(logger, "{message}");
+            Some(ValidationResult::PROBLEM(Problem {
+                code: code.to_string(),
+                message,
+                severity: Severity::WARNING,
+                suggestion: None,
+            }))
+        })
+        .collect()
+}
+
+/// Returns the rates at which the allocation of the input is financed.
+fn financing_rates(allocation_input: &AllocationInput) -> FinancingRates {
+    FinancingRates {
+        risk_free_rate: allocation_input.risk_free_rate.unwrap_or(0.0),
+        margin_interest_rate: allocation_input.margin_interest_rate,
+    }
+}
+
+/// Returns the fraction indices of the companies of the portfolio without net exposure, i.e. the
+/// market-neutral candidates and the pair trades.
+fn market_neutral_indices(portfolio: &Portfolio, allocation_input: &AllocationInput) -> Vec<usize> {
+    portfolio
+        .companies
+        .iter()
+        .positions(|pc| {
+            allocation_input
+                .market_neutral_tickers
+                .as_ref()
+                .is_some_and(|tickers| tickers.contains(&pc.company.ticker))
+        })
+        .collect_vec()
+}
+
+/// Returns the return of the cash of the portfolio, which earns the risk-free rate if it isn't
+/// invested and costs the margin interest rate if it's borrowed.
+fn cash_return(portfolio: &Portfolio, allocation_input: &AllocationInput) -> f64 {
+    let market_neutral_indices = market_neutral_indices(portfolio, allocation_input);
+    financing_rates(allocation_input)
+        .cash_return(kelly_allocation::cash(portfolio, &market_neutral_indices))
+}
+
+/// Returns all outcomes of the portfolio with the return of its cash added to the portfolio
+/// returns, like in the objective of the solver. The moments of the outcomes are cross-checked
+/// before adding the return of the cash.
+fn financed_outcomes(
+    portfolio: &Portfolio,
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> Result<Vec<Outcome>, Error> {
+    let mut outcomes = all_outcomes(portfolio)?;
+    cross_check_moments(portfolio, &outcomes, logger);
+
+    let cash_return = cash_return(portfolio, allocation_input);
+    for outcome in outcomes.iter_mut() {
+        outcome.weighted_return += cash_return;
+    }
+    Ok(outcomes)
+}
+
+/// Returns the worst case outcome of the portfolio with the return of its cash added, see
+/// [financed_outcomes].
+fn financed_worst_case_outcome(
+    portfolio: &Portfolio,
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> ProbabilityAndReturns {
+    let cash_return = cash_return(portfolio, allocation_input);
+    let mut worst_case = worst_case_outcome(portfolio, logger);
+    worst_case.portfolio_return += cash_return;
+    worst_case.probability_weighted_return += worst_case.probability * cash_return;
+    worst_case
+}
+
+/// Explains every candidate allocated exactly zero from the constraints active at the solution and
+/// the marginal growth of the candidate, i.e. the gradient of the expected logarithmic growth.
+/// Candidates left out of the best choice from mutually exclusive groups or with the maximum number
+/// of positions are added to the portfolio at zero to calculate their marginal growth. A zero
+/// candidate with a negative marginal growth is held at zero by the long-only bound, and one with a
+/// positive marginal growth by the constraint that is active, e.g. the maximum permanent loss of
+/// capital. Otherwise, the marginal growth is genuinely not positive.
+fn zero_allocations(
+    portfolio: &Portfolio,
+    filtered_candidates: &[Company],
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> Result<Vec<ZeroAllocation>, Error> {
+    let mut extended_portfolio = portfolio.clone();
+    extended_portfolio.companies.extend(
+        filtered_candidates
+            .iter()
+            .filter(|c| !portfolio.companies.iter().any(|pc| pc.company == **c))
+            .map(|c| PortfolioCompany {
+                company: c.clone(),
+                fraction: 0.0,
+            }),
+    );
+    if extended_portfolio
+        .companies
+        .iter()
+        .all(|pc| pc.fraction.abs() > ZERO_ALLOCATION_TOLERANCE)
+    {
+        return Ok(vec![]);
+    }
+    let market_neutral_indices = market_neutral_indices(&extended_portfolio, allocation_input);
+    let gradient = KellyAllocator::criterion(
+        &all_outcomes(&extended_portfolio)?,
+        &extended_portfolio,
+        financing_rates(allocation_input),
+        &market_neutral_indices,
+    );
+
+    let is_active = |constraint: &dyn Constraint| {
+        constraint.function_value(&extended_portfolio, 0.0) > -ZERO_ALLOCATION_TOLERANCE
+    };
+    let capital_loss_active = allocation_input
+        .max_permanent_loss_of_capital
+        .as_ref()
+        .is_some_and(|lc| {
+            is_active(&MaxCapitalLossConstraint::new(
+                -lc.fraction_of_capital * lc.probability_of_loss,
+            ))
+        });
+    let leverage_active = allocation_input.max_total_leverage_ratio.is_some_and(|lr| {
+        is_active(&MaximumTotalLeverageConstraint::new(lr).excluding(&market_neutral_indices))
+    });
+    let active_group = |ticker: &Ticker| {
+        allocation_input
+            .candidate_groups
+            .iter()
+            .flatten()
+            .find(|g| {
+                g.tickers.contains(ticker)
+                    && g.max_allocation.is_some_and(|max_allocation| {
+                        let fraction_indices = extended_portfolio
+                            .companies
+                            .iter()
+                            .positions(|pc| g.tickers.contains(&pc.company.ticker))
+                            .collect_vec();
+                        is_active(&MaximumGroupAllocationConstraint::new(
+                            fraction_indices,
+                            max_allocation,
+                            extended_portfolio.companies.len(),
+                        ))
+                    })
+            })
+    };
+    let is_long_only = |ticker: &Ticker| {
+        allocation_input.long_only.unwrap_or(false)
+            && allocation_input
+                .long_only_tickers
+                .as_ref()
+                .map_or(true, |tickers| tickers.contains(ticker))
+    };
+
+    let is_mutually_exclusive = |c: &Company| {
+        allocation_input
+            .candidate_groups
+            .iter()
+            .flatten()
+            .any(|g| g.mutually_exclusive.unwrap_or(false) && g.tickers.contains(&c.ticker))
+    };
+
+    let zero_allocations = filtered_candidates
+        .iter()
+        .filter_map(|c| {
+            let i = extended_portfolio
+                .companies
+                .iter()
+                .position(|pc| pc.company == *c)?;
+            let fraction = extended_portfolio.companies[i].fraction;
+            if fraction.abs() > ZERO_ALLOCATION_TOLERANCE {
+                return None;
+            }
+
+            let marginal_growth = gradient[i];
+            let (code, reason) = if i >= portfolio.companies.len() && is_mutually_exclusive(c) {
+                (
+                    "not-chosen-from-mutually-exclusive-group",
+                    "another candidate of its mutually exclusive group was chosen".to_string(),
+                )
+            } else if i >= portfolio.companies.len() {
+                (
+                    "maximum-number-of-positions-reached",
+                    "the maximum number of positions is taken by other candidates".to_string(),
+                )
+            } else if marginal_growth < -MARGINAL_GROWTH_TOLERANCE && is_long_only(&c.ticker) {
+                (
+                    "long-only-bound-binding",
+                    "the long-only bound binds: a short position would add growth".to_string(),
+                )
+            } else if marginal_growth > MARGINAL_GROWTH_TOLERANCE && capital_loss_active {
+                (
+                    "capital-loss-constraint-binding",
+                    "the maximum permanent loss of capital is reached, although buying it would \
+                    add growth"
+                        .to_string(),
+                )
+            } else if marginal_growth > MARGINAL_GROWTH_TOLERANCE && leverage_active {
+                (
+                    "leverage-constraint-binding",
+                    "the maximum total leverage is reached, although buying it would add growth"
+                        .to_string(),
+                )
+            } else if let Some(group) =
+                active_group(&c.ticker).filter(|_| marginal_growth > MARGINAL_GROWTH_TOLERANCE)
+            {
+                (
+                    "group-allocation-constraint-binding",
+                    format!(
+                        "the maximum allocation of group {} is reached, although buying it would \
+                        add growth",
+                        group.name
+                    ),
+                )
+            } else if marginal_growth > MARGINAL_GROWTH_TOLERANCE
+                && allocation_input.min_individual_allocation.is_some()
+            {
+                (
+                    "below-minimum-individual-allocation",
+                    "buying less than the minimum individual allocation would add growth, but \
+                    buying the minimum would add less than holding none"
+                        .to_string(),
+                )
+            } else {
+                (
+                    "negative-marginal-growth",
+                    "buying it would not add growth given the rest of the allocation".to_string(),
+                )
+            };
+            let message = format!(
+                "Candidate {} is allocated zero because {reason}. Its marginal growth is \
+                {marginal_growth}.",
+                c.ticker
+            );
+            info!(logger, "{message}");
+
+            Some(ZeroAllocation {
+                ticker: c.ticker.clone(),
+                code: code.to_string(),
+                message,
+                marginal_growth,
+            })
+        })
+        .collect();
+
+    Ok(zero_allocations)
+}
+
+/// Allocates separately for each choice of candidates from mutually exclusive groups and of the
+/// maximum number of positions, and returns the portfolio with the highest objective of the solver,
+/// i.e. the expected logarithmic growth including the financing of the cash, reduced by the
+/// regularization, turnover and soft constraint penalties. Returns the last error if no allocation
+/// is found for any of the choices. The fractions of the best portfolio are scaled by the Kelly
+/// fraction of the input, if given.
+fn best_allocation(
+    filtered_candidates: &[Company],
+    allocation_input: &AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    logger: &Logger,
+) -> Result<KellySolution, Error> {
+    let mut n_systems: usize = 0;
+    let mut last_error: Option<Error> = None;
+    let mut choices: Vec<Vec<Company>> = Vec::new();
+    for choice in allocation_input.exclusive_group_choices(filtered_candidates) {
+        match allocation_input.max_positions.filter(|k| *k < choice.len()) {
+            Some(max_positions) => {
+                match position_choices(choice, max_positions, allocation_input, observer, logger) {
+                    Ok((position_choices, n)) => {
+                        choices.extend(position_choices);
+                        n_systems += n;
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            None => choices.push(choice),
+        }
+    }
+    info!(
+        logger,
+        "Calculating the optimal allocation for {} choices of candidates from mutually exclusive \
+        groups and with the maximum number of positions.",
+        choices.len()
+    );
+    let mut best_solution: Option<(KellySolution, f64)> = None;
+    for choice in choices {
+        let result =
+            allocate_candidates(choice, allocation_input, observer, logger).and_then(|s| {
+                check_underperformance_probability(&s.portfolio, allocation_input, logger)?;
+                Ok(s)
+            });
+        match result {
+            Ok(s) => {
+                info!(logger, "Objective of the solver is {}.", s.objective);
+                n_systems += s.n_systems;
+                if best_solution
+                    .as_ref()
+                    .map_or(true, |(_, g)| s.objective > *g)
+                {
+                    let objective = s.objective;
+                    best_solution = Some((s, objective));
+                }
+            }
+            Err(e) => {
+                info!(
+                    logger,
+                    "Did not find an allocation for this choice: {:?}", e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match (best_solution, last_error) {
+        (Some((s, _)), _) => {
+            // Fractional Kelly scales the optimal fractions down, keeping the rest in cash
+            let portfolio = match allocation_input.kelly_fraction {
+                Some(kelly_fraction) => s.portfolio.scaled(kelly_fraction),
+                None => s.portfolio,
+            };
+            Ok(KellySolution {
+                portfolio: match allocation_input.max_turnover {
+                    Some(max_turnover) => {
+                        limited_turnover(portfolio, allocation_input, max_turnover, logger)
+                    }
+                    None => portfolio,
+                },
+                n_systems,
+                ..s
+            })
+        }
+        (None, e) => Err(e.unwrap_or_else(|| Error {
+            code: "no-allocation-found".to_string(),
+            message: "Did not find an allocation for any choice of candidates.".to_string(),
+        })),
+    }
+}
+
+/// Moves the fractions only partway from the current weights to the allocation if the trades
+/// exceed the maximum turnover. The growth is concave, so it increases all the way to the optimal
+/// allocation, and the constraints hold on the way if the current weights satisfy them.
+fn limited_turnover(
+    portfolio: Portfolio,
+    allocation_input: &AllocationInput,
+    max_turnover: f64,
+    logger: &Logger,
+) -> Portfolio {
+    let turnover: f64 = portfolio
+        .companies
+        .iter()
+        .map(|pc| (pc.fraction - allocation_input.current_weight_of(&pc.company.ticker)).abs())
+        .sum();
+    if turnover <= max_turnover {
+        return portfolio;
+    }
+
+    let share = max_turnover / turnover;
+    info!(
+        logger,
+        "Turnover of {turnover} exceeds the maximum of {max_turnover}, trading {share} of the way \
+        to the allocation."
+    );
+    Portfolio {
+        companies: portfolio
+            .companies
+            .into_iter()
+            .map(|pc| {
+                let current_weight = allocation_input.current_weight_of(&pc.company.ticker);
+                PortfolioCompany {
+                    fraction: current_weight + share * (pc.fraction - current_weight),
+                    ..pc
+                }
+            })
+            .collect(),
+        macro_scenarios: portfolio.macro_scenarios,
+    }
+}
+
+/// Collects the trades from the current weights to the allocation, ordered by the current weights
+/// followed by the new positions. Holdings that aren't allocated are sold completely.
+fn rebalancing(
+    current_weights: &[TickerAndFraction],
+    allocations: &[TickerAndFraction],
+) -> Rebalancing {
+    let fraction_of = |weights: &[TickerAndFraction], ticker: &Ticker| -> f64 {
+        weights
+            .iter()
+            .filter(|w| &w.ticker == ticker)
+            .map(|w| w.fraction)
+            .sum()
+    };
+
+    let trades = current_weights
+        .iter()
+        .chain(allocations)
+        .map(|w| w.ticker.clone())
+        .unique()
+        .map(|ticker| {
+            let original_fraction = fraction_of(current_weights, &ticker);
+            let new_fraction = fraction_of(allocations, &ticker);
+            FractionChange {
+                ticker,
+                original_fraction,
+                new_fraction,
+                change: new_fraction - original_fraction,
+            }
+        })
+        .filter(|trade| trade.change != 0.0)
+        .collect_vec();
+
+    Rebalancing {
+        turnover: trades.iter().map(|t| t.change.abs()).sum(),
+        trades,
+    }
+}
+
+/// Returns the choices of the given number of candidates among the candidates of the choice,
+/// together with the number of systems solved to find them. All choices are returned if there
+/// aren't more than [MAX_POSITION_CHOICES], otherwise only the candidates with the largest absolute
+/// fractions in the allocation to all candidates of the choice are kept, pruning the ones
+/// contributing the least.
+fn position_choices(
+    choice: Vec<Company>,
+    max_positions: usize,
+    allocation_input: &AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    logger: &Logger,
+) -> Result<(Vec<Vec<Company>>, usize), Error> {
+    let n_choices: f64 = (0..max_positions)
+        .map(|i| (choice.len() - i) as f64 / (i + 1) as f64)
+        .product();
+    if n_choices <= MAX_POSITION_CHOICES as f64 {
+        return Ok((choice.into_iter().combinations(max_positions).collect(), 0));
+    }
+
+    info!(
+        logger,
+        "Too many choices of {max_positions} out of {} candidates, keeping the candidates with the \
+        largest fractions instead.",
+        choice.len()
+    );
+    let solution = allocate_candidates(choice.clone(), allocation_input, observer, logger)?;
+    let kept_tickers = solution
+        .portfolio
+        .companies
+        .iter()
+        .sorted_by_key(|pc| Reverse(OrderedFloat(pc.fraction.abs())))
+        .take(max_positions)
+        .map(|pc| pc.company.ticker.clone())
+        .collect_vec();
+    let kept = choice
+        .into_iter()
+        .filter(|c| kept_tickers.contains(&c.ticker))
+        .collect_vec();
+    Ok((vec![kept], solution.n_systems))
+}
+
+/// Rejects the allocation if the probability that its return falls below the return of the
+/// benchmark exceeds the maximum of the input. The probability is calculated for the fractions that
+/// end up being allocated, i.e. after scaling them by the Kelly fraction, including the return of
+/// the cash like the probability reported with the allocation.
+fn check_underperformance_probability(
+    portfolio: &Portfolio,
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> Result<(), Error> {
+    let Some((benchmark_return, max_probability)) =
+        allocation_input.benchmark.as_ref().and_then(|b| {
+            b.max_underperformance_probability
+                .map(|max_p| (b.expected_return, max_p))
+        })
+    else {
+        return Ok(());
+    };
+
+    let portfolio = portfolio.scaled(allocation_input.kelly_fraction.unwrap_or(1.0));
+    let outcomes = financed_outcomes(&portfolio, allocation_input, logger)?;
+    let probability = probability_of_return_below(&outcomes, benchmark_return);
+    if probability > max_probability {
+        return Err(Error {
+            code: "underperformance-probability-exceeded".to_string(),
+            message: format!(
+                "Probability of {:.1}% that the allocation returns less than the benchmark return \
+                 of {:.1}% exceeds the maximum of {:.1}%.",
+                100.0 * probability,
+                100.0 * benchmark_return,
+                100.0 * max_probability
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Searches for the largest Kelly fraction whose scaled allocation doesn't exceed the target risk
+/// level. The risk includes the return of cash, like the analysis of the allocation.
+fn target_risk_sizing(
+    portfolio: &Portfolio,
+    allocation_input: &AllocationInput,
+    target_risk: &TargetRisk,
+    logger: &Logger,
+) -> Result<TargetRiskSizing, Error> {
+    let risk = |kelly_fraction: f64| -> Result<f64, Error> {
+        let outcomes =
+            financed_outcomes(&portfolio.scaled(kelly_fraction), allocation_input, logger)?;
+        Ok(match target_risk.measure {
+            RiskMeasure::ProbabilityOfLoss => {
+                probability_of_return_below(&outcomes, -target_risk.loss.unwrap_or(0.0))
+            }
+            RiskMeasure::ConditionalValueAtRisk => conditional_value_at_risk(
+                &outcomes,
+                target_risk
+                    .confidence_level
+                    .unwrap_or(VALUE_AT_RISK_CONFIDENCE),
+            ),
+        })
+    };
+
+    let full_kelly_risk = risk(1.0)?;
+    if full_kelly_risk <= target_risk.level {
+        info!(
+            logger,
+            "Full Kelly allocation with a risk of {full_kelly_risk} is within the target risk."
+        );
+        return Ok(TargetRiskSizing {
+            kelly_fraction: 1.0,
+            risk: full_kelly_risk,
+        });
+    }
+
+    // The risk doesn't decrease with the Kelly fraction, so the largest fraction within the target
+    // is the lower end of the bisected interval
+    let (mut lower, mut upper) = (0.0, 1.0);
+    while upper - lower > SOLVER_TOLERANCE {
+        let middle = 0.5 * (lower + upper);
+        if risk(middle)? <= target_risk.level {
+            lower = middle;
+        } else {
+            upper = middle;
+        }
+    }
+    if lower == 0.0 {
+        return Err(Error {
+            code: "target-risk-unattainable".to_string(),
+            message: format!(
+                "Target risk level of {} can't be reached by scaling the allocation down, since \
+                even small fractions of the Kelly allocation exceed it. A probability of any loss \
+                doesn't change with the scale of the allocation.",
+                target_risk.level
+            ),
+        });
+    }
+
+    info!(
+        logger,
+        "Kelly fraction of {lower} reaches the target risk level of {}.", target_risk.level
+    );
+    Ok(TargetRiskSizing {
+        kelly_fraction: lower,
+        risk: risk(lower)?,
+    })
+}
+
+/// Summarizes the allocation without leverage and compares its expected logarithmic growth with
+/// the growth of the levered allocation, both including the return of the cash.
+fn deleveraging_suggestion(
+    solution: &KellySolution,
+    allocation_input: &AllocationInput,
+    levered_expected_log_growth: f64,
+    logger: &Logger,
+) -> Result<DeleveragingSuggestion, Error> {
+    let portfolio = &solution.portfolio;
+    let outcomes = financed_outcomes(portfolio, allocation_input, logger)?;
+    let expected_log_growth = expected_log_growth(&outcomes);
+    let diversification = diversification(portfolio);
+
+    Ok(DeleveragingSuggestion {
+        allocations: portfolio
+            .companies
+            .iter()
+            .map(|pc| TickerAndFraction {
+                ticker: pc.company.ticker.clone(),
+                fraction: pc.fraction,
+            })
+            .collect(),
+        cash: 1.0 - diversification.net_exposure,
+        gross_exposure: diversification.gross_exposure,
+        expected_return: expected_return(portfolio, logger)
+            + cash_return(portfolio, allocation_input),
+        expected_log_growth,
+        worst_case_outcome: financed_worst_case_outcome(portfolio, allocation_input, logger),
+        fraction_of_levered_growth: expected_log_growth / levered_expected_log_growth,
+        n_systems: solution.n_systems,
+    })
+}
+
+/// Warns that almost all capital is allocated to a single candidate, explaining the edge and the
+/// downside of the candidate that drive the allocation.
+fn concentrated_allocation_warning(pc: &PortfolioCompany, logger: &Logger) -> ValidationResult {
+    let company = &pc.company.with_status_quo_scenario();
+    let worst_scenario = company
+        .scenarios
+        .iter()
+        .min_by_key(|s| OrderedFloat(s.scenario_return(company.market_cap)))
+        .expect("Filtered candidates have at least one downside scenario.");
+    let message = format!(
+        "{:.0}% of capital is allocated to {}, which is usually a sign of an input error. The \
+        allocation is driven by its expected return of {:.1}% and its worst scenario {} with a \
+        return of {:.1}% and a probability of {:.1}%. Check the probabilities and intrinsic values \
+        of its scenarios, and compare with the capped alternative.",
+        100.0 * pc.fraction,
+        company.ticker,
+        100.0 * company.expected_return(),
+        worst_scenario.thesis,
+        100.0 * worst_scenario.scenario_return(company.market_cap),
+        100.0 * worst_scenario.probability
+    );
+    warn!(logger, "{message}");
+    ValidationResult::PROBLEM(Problem {
+        code: "allocation-concentrated-in-single-candidate".to_string(),
+        message,
+        severity: Severity::WARNING,
+        suggestion: None,
+    })
+}
+
+/// Summarizes the allocation with a capped individual allocation and compares its expected
+/// logarithmic growth with the growth of the concentrated allocation, both including the return of
+/// the cash.
+fn capped_alternative(
+    solution: &KellySolution,
+    allocation_input: &AllocationInput,
+    concentrated_expected_log_growth: f64,
+    logger: &Logger,
+) -> Result<CappedAlternative, Error> {
+    let portfolio = &solution.portfolio;
+    let expected_log_growth =
+        expected_log_growth(&financed_outcomes(portfolio, allocation_input, logger)?);
+
+    Ok(CappedAlternative {
+        max_individual_allocation: CAPPED_ALTERNATIVE_MAX_ALLOCATION,
+        allocations: portfolio
+            .companies
+            .iter()
+            .map(|pc| TickerAndFraction {
+                ticker: pc.company.ticker.clone(),
+                fraction: pc.fraction,
+            })
+            .collect(),
+        cash: 1.0 - diversification(portfolio).net_exposure,
+        expected_return: expected_return(portfolio, logger)
+            + cash_return(portfolio, allocation_input),
+        expected_log_growth,
+        worst_case_outcome: financed_worst_case_outcome(portfolio, allocation_input, logger),
+        fraction_of_concentrated_growth: expected_log_growth / concentrated_expected_log_growth,
+        n_systems: solution.n_systems,
+    })
+}
+
+/// Collects the allocation projected onto the conviction tiers together with the growth given up
+/// compared to the Kelly allocation, both including the return of the cash.
+fn conviction_sizing(
+    projected: &Portfolio,
+    allocation_input: &AllocationInput,
+    kelly_expected_log_growth: f64,
+    logger: &Logger,
+) -> Result<ConvictionSizing, Error> {
+    let expected_log_growth =
+        expected_log_growth(&financed_outcomes(projected, allocation_input, logger)?);
+
+    Ok(ConvictionSizing {
+        allocations: projected
+            .companies
+            .iter()
+            .map(|pc| TickerAndFraction {
+                ticker: pc.company.ticker.clone(),
+                fraction: pc.fraction,
+            })
+            .collect(),
+        cash: 1.0 - diversification(projected).net_exposure,
+        expected_log_growth,
+        growth_give_up: kelly_expected_log_growth - expected_log_growth,
+        fraction_of_kelly_growth: expected_log_growth / kelly_expected_log_growth,
+    })
+}
+
+/// Compares the allocated portfolio, whose outcomes including the return of the cash are given,
+/// with the benchmark portfolio of the same companies, whose cash earns or costs the same rates.
+fn benchmark_comparison(
+    portfolio: &Portfolio,
+    outcomes: &[Outcome],
+    benchmark: &Portfolio,
+    allocation_input: &AllocationInput,
+    logger: &Logger,
+) -> Result<BenchmarkComparison, Error> {
+    let benchmark_outcomes = financed_outcomes(benchmark, allocation_input, logger)?;
+    let benchmark_expected_log_growth = expected_log_growth(&benchmark_outcomes);
+    let benchmark_conditional_value_at_risk_95 =
+        conditional_value_at_risk(&benchmark_outcomes, VALUE_AT_RISK_CONFIDENCE);
+
+    Ok(BenchmarkComparison {
+        benchmark_allocations: benchmark
+            .companies
+            .iter()
+            .map(|pc| TickerAndFraction {
+                ticker: pc.company.ticker.clone(),
+                fraction: pc.fraction,
+            })
+            .collect(),
+        benchmark_expected_return: expected_return(benchmark, logger)
+            + cash_return(benchmark, allocation_input),
+        benchmark_expected_log_growth,
+        expected_log_growth_difference: expected_log_growth(outcomes)
+            - benchmark_expected_log_growth,
+        benchmark_worst_case_outcome: financed_worst_case_outcome(
+            benchmark,
+            allocation_input,
+            logger,
+        ),
+        benchmark_conditional_value_at_risk_95,
+        conditional_value_at_risk_95_difference: conditional_value_at_risk(
+            outcomes,
+            VALUE_AT_RISK_CONFIDENCE,
+        ) - benchmark_conditional_value_at_risk_95,
+        weight_differences: portfolio
+            .companies
+            .iter()
+            .zip(benchmark.companies.iter())
+            .map(|(pc, b)| TickerAndFraction {
+                ticker: pc.company.ticker.clone(),
+                fraction: pc.fraction - b.fraction,
+            })
+            .collect(),
+    })
+}
+
+/// Calculates optimal allocation for the given candidates, applying the constraints from the
+/// allocation input
+fn allocate_candidates(
+    filtered_candidates: Vec<Company>,
+    allocation_input: &AllocationInput,
+    observer: Option<&dyn SolverObserver>,
+    logger: &Logger,
+) -> Result<KellySolution, Error> {
+    info!(
+        logger,
+        "Calculating the optimal allocation for {} candidates.",
+        filtered_candidates.len()
+    );
+    let mut kelly_allocator = KellyAllocator::new(logger, MAX_ITER);
+
+    if let Some(solver_settings) = &allocation_input.solver_settings {
+        kelly_allocator = kelly_allocator.with_solver_settings(solver_settings);
+    }
+
+    if let Some(risk_free_rate) = allocation_input.risk_free_rate {
+        kelly_allocator = kelly_allocator.with_risk_free_rate(risk_free_rate);
+    }
+
+    if let Some(margin_interest_rate) = allocation_input.margin_interest_rate {
+        kelly_allocator = kelly_allocator.with_margin_interest_rate(margin_interest_rate);
+    }
+
+    if let Some(macro_scenarios) = &allocation_input.macro_scenarios {
+        kelly_allocator = kelly_allocator.with_macro_scenarios(macro_scenarios);
+    }
+
+    if let Some(turnover_penalty) = allocation_input.turnover_penalty {
+        let current_fractions = filtered_candidates
+            .iter()
+            .map(|c| allocation_input.current_weight_of(&c.ticker))
+            .collect();
+        kelly_allocator =
+            kelly_allocator.with_turnover_penalty(current_fractions, turnover_penalty);
+    }
+
+    // Add constraints if present. Constraints that apply to a subset of tickers are mapped onto
+    // the fraction indices of the filtered candidates, skipping the tickers that were filtered out.
+    let fraction_indices = |tickers: &Vec<Ticker>| -> Vec<usize> {
+        filtered_candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| tickers.contains(&c.ticker))
+            .map(|(i, _)| i)
+            .collect()
+    };
+
+    if allocation_input.long_only.unwrap_or(false) {
+        kelly_allocator = match &allocation_input.long_only_tickers {
+            Some(tickers) => kelly_allocator.with_long_only_constraints_for(
+                &fraction_indices(tickers),
+                filtered_candidates.len(),
+            ),
+            None => kelly_allocator.with_long_only_constraints(filtered_candidates.len()),
+        };
+    }
+
+    // Soft constraints are penalized in the objective below instead of being enforced
+    let is_hard = |kind: SoftConstraintKind| {
+        !allocation_input
+            .soft_constraints
+            .iter()
+            .flatten()
+            .any(|c| c.constraint == kind)
+    };
+
+    if let Some(lc) = allocation_input
+        .max_permanent_loss_of_capital
+        .as_ref()
+        .filter(|_| is_hard(SoftConstraintKind::MaxPermanentLossOfCapital))
+    {
+        kelly_allocator = kelly_allocator.with_maximum_permanent_loss_constraint(lc.clone());
+    }
+
+    // Candidates with a cap of their own are constrained even without the maximum for all of them
+    let max_allocations: Vec<(usize, f64)> = filtered_candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| {
+            allocation_input
+                .max_individual_allocation_of(&c.ticker)
+                .map(|max_f| (i, max_f))
+        })
+        .collect();
+    if !max_allocations.is_empty() && is_hard(SoftConstraintKind::MaxIndividualAllocation) {
+        kelly_allocator = kelly_allocator.with_maximum_individual_allocation_constraints(
+            &max_allocations,
+            filtered_candidates.len(),
+        );
+    }
+
+    // Minimum of the fractions that end up being allocated, i.e. after scaling by the Kelly
+    // fraction
+    if let Some(min_f) = allocation_input.min_individual_allocation {
+        kelly_allocator = kelly_allocator.with_minimum_individual_allocation_constraint(
+            min_f / allocation_input.kelly_fraction.unwrap_or(1.0),
+        );
+    }
+
+    // Added after the maximum individual allocation since both are upper bounds of the fractions
+    if let Some(position_downside) = allocation_input
+        .max_position_downside
+        .as_ref()
+        .filter(|_| is_hard(SoftConstraintKind::MaxPositionDownside))
+    {
+        let budgets: Vec<(usize, f64)> = filtered_candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| position_downside.budget_of(&c.ticker).map(|b| (i, b)))
+            .collect();
+        kelly_allocator = kelly_allocator
+            .with_maximum_position_downside_constraints(&budgets, &filtered_candidates);
+    }
+
+    if let Some(tickers) = &allocation_input.market_neutral_tickers {
+        kelly_allocator =
+            kelly_allocator.with_market_neutral_candidates(&fraction_indices(tickers));
+    }
+
+    if let Some(max_lr) = allocation_input
+        .max_total_leverage_ratio
+        .filter(|_| is_hard(SoftConstraintKind::MaxTotalLeverageRatio))
+    {
+        kelly_allocator = kelly_allocator
+            .with_maximum_total_leverage_constraint(filtered_candidates.len(), max_lr);
+    }
+
+    if allocation_input.fully_invested.unwrap_or(false) {
+        kelly_allocator = kelly_allocator.with_fully_invested_constraint(filtered_candidates.len());
+    } else if let Some(net_exposure) = allocation_input.fixed_net_exposure {
+        kelly_allocator = kelly_allocator
+            .with_fixed_net_exposure_constraint(filtered_candidates.len(), net_exposure);
+    }
+
+    // Group caps only need a constraint if there is at least one candidate of the group left
+    for group in allocation_input.candidate_groups.iter().flatten() {
+        if let Some(max_f) = group
+            .max_allocation
+            .filter(|_| is_hard(SoftConstraintKind::MaxGroupAllocation))
+        {
+            let group_indices = fraction_indices(&group.tickers);
+            if !group_indices.is_empty() {
+                kelly_allocator = kelly_allocator.with_maximum_group_allocation_constraint(
+                    &group_indices,
+                    filtered_candidates.len(),
+                    max_f,
+                );
+            }
+        }
+    }
+
+    for (_, constraint, weight) in soft_constraints(allocation_input, &filtered_candidates) {
+        kelly_allocator = kelly_allocator.with_soft_constraint(constraint, weight);
+    }
+
+    if let Some(observer) = observer {
+        kelly_allocator = kelly_allocator.with_solver_observer(observer);
+    }
+
+    kelly_allocator.solve(filtered_candidates)
+}
+
+/// Returns the constraints of the input designated as soft for the candidates in the order of the
+/// fractions, together with their names and weights. Constraints that apply to a subset of tickers
+/// skip the tickers that aren't among the candidates, like the hard constraints.
+fn soft_constraints(
+    allocation_input: &AllocationInput,
+    candidates: &[Company],
+) -> Vec<(String, Rc<dyn InequalityConstraint>, f64)> {
+    let n_candidates = candidates.len();
+    let fraction_indices = |tickers: Option<&Vec<Ticker>>| -> Vec<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| tickers.map_or(true, |t| t.contains(&c.ticker)))
+            .map(|(i, _)| i)
+            .collect()
+    };
+    let market_neutral_indices = match &allocation_input.market_neutral_tickers {
+        Some(tickers) => fraction_indices(Some(tickers)),
+        None => vec![],
+    };
+
+    let mut constraints: Vec<(String, Rc<dyn InequalityConstraint>, f64)> = vec![];
+    for soft_constraint in allocation_input.soft_constraints.iter().flatten() {
+        let name = soft_constraint.constraint.name();
+        let weight = soft_constraint.weight;
+        match soft_constraint.constraint {
+            SoftConstraintKind::MaxIndividualAllocation => {
+                for (i, c) in candidates.iter().enumerate() {
+                    let Some(max_f) = allocation_input.max_individual_allocation_of(&c.ticker)
+                    else {
+                        continue;
+                    };
+                    constraints.push((
+                        format!("{name} of {}", c.ticker),
+                        Rc::new(MaximumIndividualAllocationConstraint::new(
+                            i,
+                            max_f,
+                            n_candidates,
+                        )),
+                        weight,
+                    ));
+                }
+            }
+            SoftConstraintKind::MaxPositionDownside => {
+                let Some(position_downside) = &allocation_input.max_position_downside else {
+                    continue;
+                };
+                // Candidates without a downside can't lose capital, like for the hard constraints
+                for (i, c) in candidates.iter().enumerate() {
+                    let Some(budget) = position_downside.budget_of(&c.ticker) else {
+                        continue;
+                    };
+                    if c.worst_probability_weighted_return() < 0.0 {
+                        constraints.push((
+                            format!("{name} of {}", c.ticker),
+                            Rc::new(MaximumPositionDownsideConstraint::new(
+                                i,
+                                budget,
+                                n_candidates,
+                            )),
+                            weight,
+                        ));
+                    }
+                }
+            }
+            SoftConstraintKind::MaxTotalLeverageRatio => {
+                if let Some(max_lr) = allocation_input.max_total_leverage_ratio {
+                    constraints.push((
+                        name.to_string(),
+                        Rc::new(
+                            MaximumTotalLeverageConstraint::new(max_lr)
+                                .excluding(&market_neutral_indices),
+                        ),
+                        weight,
+                    ));
+                }
+            }
+            SoftConstraintKind::MaxGroupAllocation => {
+                for group in allocation_input.candidate_groups.iter().flatten() {
+                    let group_indices = fraction_indices(Some(&group.tickers));
+                    if let (Some(max_f), false) = (group.max_allocation, group_indices.is_empty()) {
+                        constraints.push((
+                            format!("{name} of {}", group.name),
+                            Rc::new(MaximumGroupAllocationConstraint::new(
+                                group_indices,
+                                max_f,
+                                n_candidates,
+                            )),
+                            weight,
+                        ));
+                    }
+                }
+            }
+            SoftConstraintKind::MaxPermanentLossOfCapital => {
+                if let Some(lc) = &allocation_input.max_permanent_loss_of_capital {
+                    // Sign is negative by convention because this represents a loss of capital
+                    constraints.push((
+                        name.to_string(),
+                        Rc::new(MaxCapitalLossConstraint::new(
+                            -lc.fraction_of_capital * lc.probability_of_loss,
+                        )),
+                        weight,
+                    ));
+                }
+            }
+        }
+    }
+
+    constraints
+}
+
+/// Calculates useful information about the portfolio, including the probabilities and expected
+/// portfolio returns of the named events. Statistics that require enumerating all outcomes are
+/// skipped on the summary detail level, making it suitable for large portfolios. The analysis may
+/// be restricted to a sub-portfolio with the tickers of the analysis options.
+pub fn analyze(
+    portfolio: Portfolio,
+    events: &[PortfolioEvent],
+    options: &AnalysisOptions,
+    logger: &Logger,
+) -> AnalysisResponse {
+    // Scenarios outside of the macro scenarios would be left out of the outcomes
+    let macro_scenario_problem = portfolio
+        .validate_macro_scenarios()
+        .into_iter()
+        .find_map(|v| match v {
+            ValidationResult::PROBLEM(p) => Some(p),
+            ValidationResult::OK => None,
+        });
+    if let Some(problem) = macro_scenario_problem {
+        return AnalysisResponse {
+            result: None,
+            error: Some(Error {
+                code: problem.code,
+                message: problem.message,
+            }),
+        };
+    }
+
+    // Make the implicit status quo scenarios explicit
+    let portfolio = Portfolio {
+        companies: portfolio
+            .companies
+            .into_iter()
+            .map(|pc| PortfolioCompany {
+                company: pc.company.with_status_quo_scenario(),
+                ..pc
+            })
+            .collect(),
+        macro_scenarios: portfolio.macro_scenarios,
+    };
+
+    // Events are validated against the whole portfolio, since they may refer to companies outside
+    // of the analyzed sub-portfolio
+    let companies = portfolio
+        .companies
+        .iter()
+        .map(|pc| pc.company.clone())
+        .collect::<Vec<Company>>();
+    let event_problem = events
+        .iter()
+        .flat_map(|e| e.validate_against(&companies))
+        .find_map(|v| match v {
+            ValidationResult::PROBLEM(p) => Some(p),
+            ValidationResult::OK => None,
+        });
+    if let Some(problem) = event_problem {
+        return AnalysisResponse {
+            result: None,
+            error: Some(Error {
+                code: problem.code,
+                message: problem.message,
+            }),
+        };
+    }
+
+    // Restrict the analysis to the sub-portfolio of the given tickers, keeping their fractions
+    let portfolio = match options.ticker_filter() {
+        Some(tickers) => {
+            let unknown_tickers = tickers
+                .iter()
+                .filter(|t| !companies.iter().any(|c| &c.ticker == *t))
+                .cloned()
+                .collect::<Vec<Ticker>>();
+            if !unknown_tickers.is_empty() {
+                return AnalysisResponse {
+                    result: None,
+                    error: Some(Error {
+                        code: "unknown-tickers-in-analysis".to_string(),
+                        message: format!(
+                            "Tickers {} to analyze are not found in the portfolio. Check your \
+                            input.",
+                            unknown_tickers.join(", ")
+                        ),
+                    }),
+                };
+            }
+            info!(
+                logger,
+                "Restricting the analysis to the sub-portfolio of {}.",
+                tickers.join(", ")
+            );
+            Portfolio {
+                companies: portfolio
+                    .companies
+                    .into_iter()
+                    .filter(|pc| tickers.contains(&pc.company.ticker))
+                    .collect(),
+                macro_scenarios: portfolio.macro_scenarios,
+            }
+        }
+        None => portfolio,
+    };
+
+    let detail_level = options.detail_level.unwrap_or_default();
+    info!(
+        logger,
+        "Started portfolio analysis with {:?} detail level.", detail_level
+    );
+
+    let n_outcomes = n_outcomes(&portfolio);
+    let mut sampled = None;
+    let outcomes = if detail_level >= DetailLevel::Standard && n_outcomes > MAX_OUTCOMES {
+        // Too many outcomes to enumerate, so the statistics are estimated from sampled outcomes
+        let n_samples = options.n_samples.unwrap_or(DEFAULT_N_SAMPLES).max(1);
+        let seed = options.seed.unwrap_or_default();
+        info!(
+            logger,
+            "Sampling {n_samples} of {n_outcomes} outcomes with seed {seed}."
+        );
+        let outcomes = sampled_outcomes(&portfolio, n_samples, seed);
+        sampled = Some(sampled_statistics(&outcomes, seed));
+        Some(outcomes)
+    } else if detail_level >= DetailLevel::Standard {
+        info!(logger, "Getting all outcomes.");
+        match all_outcomes(&portfolio) {
+            Ok(o) => {
+                cross_check_moments(&portfolio, &o, logger);
+                Some(o)
+            }
+            Err(e) => {
+                info!(
+                    logger,
+                    "Encountered an error while getting all outcomes. Returning it."
+                );
+                return AnalysisResponse {
+                    result: None,
+                    error: Some(e),
+                };
+            }
+        }
+    } else {
+        None
+    };
+
+    // Statistics that require all (or sampled) outcomes
+    let outcomes = outcomes.as_deref();
+    let cumulative_probability_of_loss = match &sampled {
+        Some(statistics) => Some(statistics.probability_of_loss.value),
+        None => outcomes.map(|o| cumulative_probability_of_loss(o, logger)),
+    };
+    let log_growth = outcomes.map(expected_log_growth);
+    let value_at_risk_95 = outcomes.map(|o| value_at_risk(o, VALUE_AT_RISK_CONFIDENCE));
+    let conditional_value_at_risk_95 =
+        outcomes.map(|o| conditional_value_at_risk(o, VALUE_AT_RISK_CONFIDENCE));
+    let probability_of_underperforming_benchmark = outcomes
+        .zip(options.benchmark_return)
+        .map(|(o, b)| probability_of_return_below(o, b));
+    let probability_of_return_below_threshold = outcomes
+        .zip(options.return_threshold)
+        .map(|(o, t)| probability_of_return_below(o, t));
+    let distribution = outcomes
+        .filter(|_| {
+            options
+                .distribution
+                .unwrap_or(detail_level >= DetailLevel::Full)
+        })
+        .map(|o| return_distribution(o, options.n_bins));
+    let worst_case = worst_case_outcome(&portfolio, logger);
+    let diversification = diversification(&portfolio);
+
+    let events = match events
+        .iter()
+        .map(|e| event_outcome(&portfolio, e))
+        .collect::<Result<Vec<EventOutcome>, Error>>()
+    {
+        Ok(events) => events,
+        Err(e) => {
+            return AnalysisResponse {
+                result: None,
+                error: Some(e),
+            };
+        }
+    };
+
+    let tag_conditional_outcomes = match options
+        .tag_filter()
+        .iter()
+        .map(|tag| tag_conditional_outcome(&portfolio, tag, options))
+        .collect::<Result<Vec<TagConditionalOutcome>, Error>>()
+    {
+        Ok(outcomes) => (!outcomes.is_empty()).then_some(outcomes),
+        Err(e) => {
+            return AnalysisResponse {
+                result: None,
+                error: Some(e),
+            };
+        }
+    };
+
+    let portfolio_expected_return = expected_return(&portfolio, logger);
+    let inflation_adjusted = match options
+        .expected_inflation
+        .map(|inflation| inflation_adjusted(inflation, portfolio_expected_return, log_growth))
+        .transpose()
+    {
+        Ok(inflation_adjusted) => inflation_adjusted,
+        Err(e) => {
+            return AnalysisResponse {
+                result: None,
+                error: Some(e),
+            };
+        }
+    };
+
+    let compound_growth = match log_growth
+        .map(|g| compound_growth(g, options.horizon_in_years))
+        .transpose()
+    {
+        Ok(compound_growth) => compound_growth,
+        Err(e) => {
+            return AnalysisResponse {
+                result: None,
+                error: Some(e),
+            };
+        }
+    };
+
+    // Given fractions are exact, and the sampled probability of loss has its confidence interval
+    let numerical_errors =
+        numerical_errors(&portfolio, outcomes.filter(|_| sampled.is_none()), 0.0);
 
     info!(logger, "Analysis complete, returning.");
     AnalysisResponse {
         result: Some(AnalysisResult {
             worst_case_outcome: worst_case,
-            cumulative_probability_of_loss: cumulative_probability_of_loss(&all_outcomes, logger),
-            expected_return: expected_return(&portfolio, logger),
+            cumulative_probability_of_loss,
+            expected_return: portfolio_expected_return,
+            expected_log_growth_rate: log_growth,
+            compound_growth,
+            variance_of_return: analytical_moments(&portfolio).variance,
+            effective_number_of_positions: diversification.effective_number_of_positions,
+            max_weight: diversification.max_weight,
+            gross_exposure: diversification.gross_exposure,
+            net_exposure: diversification.net_exposure,
+            events,
+            inflation_adjusted,
+            sampled_statistics: sampled,
+            value_at_risk_95,
+            conditional_value_at_risk_95,
+            probability_of_underperforming_benchmark,
+            probability_of_return_below_threshold,
+            distribution,
+            tag_conditional_outcomes,
+            numerical_errors: Some(numerical_errors),
         }),
         error: None,
     }
@@ -0,0 +1,255 @@
+use crate::config::MarketDataConfig;
+use crate::model::company::{Company, Ticker};
+use crate::model::errors::Error;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use slog::{warn, Logger};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Placeholder of the ticker in the URL template of the provider.
+const TICKER_PLACEHOLDER: &str = "{ticker}";
+
+/// Characters of the ticker that are percent-encoded in the URL, i.e. all but the unreserved ones,
+/// such that a ticker can't add path segments, a query or a fragment to the request to the
+/// provider. Dots are kept for tickers like BRK.B, hence tickers of only dots, which would be dot
+/// segments even if encoded, are rejected.
+const TICKER_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Longest time to wait for the market cap of a ticker, after which it's skipped.
+const MARKET_CAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fetches the live market caps from the provider given in the configuration.
+#[derive(Clone, Debug)]
+pub struct MarketDataProvider {
+    config: MarketDataConfig,
+    client: reqwest::Client,
+}
+
+/// Response of the provider for a single ticker.
+#[derive(Deserialize)]
+struct MarketCapResponse {
+    market_cap: f64,
+}
+
+impl MarketDataProvider {
+    pub fn new(config: &MarketDataConfig) -> MarketDataProvider {
+        MarketDataProvider {
+            config: config.clone(),
+            client: reqwest::Client::builder()
+                .timeout(MARKET_CAP_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// URL of the market cap of the ticker, with the ticker percent-encoded. Fails for an empty
+    /// ticker or one of only dots, which would change the path of the URL instead of naming a
+    /// ticker.
+    fn url(&self, ticker: &str) -> Result<String, Error> {
+        if ticker.chars().all(|c| c == '.') {
+            return Err(Error {
+                code: "invalid-market-data-ticker".to_string(),
+                message: format!(
+                    "Did not fetch the market cap of \"{ticker}\", which isn't a valid ticker."
+                ),
+            });
+        }
+
+        let ticker = utf8_percent_encode(ticker, TICKER_ENCODE_SET).to_string();
+        Ok(self
+            .config
+            .url_template
+            .replace(TICKER_PLACEHOLDER, &ticker))
+    }
+
+    /// Fetches the live market cap of the ticker.
+    pub async fn market_cap(&self, ticker: &str) -> Result<f64, Error> {
+        let failed = |e: reqwest::Error| Error {
+            code: "failed-to-fetch-market-cap".to_string(),
+            message: format!("Did not manage to fetch the market cap of {ticker}: {e}."),
+        };
+        let mut request = self.client.get(self.url(ticker)?);
+        if let Some(auth_header) = self
+            .config
+            .auth_header_variable
+            .as_ref()
+            .and_then(|v| std::env::var(v).ok())
+        {
+            request = request.header(reqwest::header::AUTHORIZATION, auth_header);
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(failed)?
+            .json::<MarketCapResponse>()
+            .await
+            .map(|r| r.market_cap)
+            .map_err(failed)
+    }
+
+    /// Fetches the live market caps of the candidates concurrently and returns a warning for each
+    /// candidate whose market cap drifted from the live one by more than the configured threshold.
+    /// Market caps that can't be fetched in time are logged and skipped, such that an unavailable
+    /// provider doesn't prevent the allocation.
+    pub async fn drift_warnings(
+        &self,
+        candidates: &[Company],
+        logger: &Logger,
+    ) -> Vec<ValidationResult> {
+        let mut requests = JoinSet::new();
+        for ticker in candidates.iter().map(|c| c.ticker.clone()) {
+            let provider = self.clone();
+            requests.spawn(async move {
+                let market_cap = provider.market_cap(&ticker).await;
+                (ticker, market_cap)
+            });
+        }
+
+        let mut live_market_caps = HashMap::<Ticker, f64>::new();
+        while let Some(response) = requests.join_next().await {
+            match response {
+                Ok((ticker, Ok(market_cap))) => {
+                    live_market_caps.insert(ticker, market_cap);
+                }
+                Ok((_, Err(e))) => warn!(logger, "{}", e.message),
+                Err(e) => warn!(logger, "Fetching a market cap failed: {e}"),
+            }
+        }
+
+        market_cap_drift_warnings(
+            candidates,
+            &live_market_caps,
+            self.config.max_market_cap_drift,
+        )
+    }
+}
+
+/// Returns a warning for each candidate whose market cap differs from the live one by more than
+/// the maximum relative drift, with the change of its expected return if it were valued at the live
+/// market cap. The intrinsic values of the scenarios, including the status quo, are kept.
+pub fn market_cap_drift_warnings(
+    candidates: &[Company],
+    live_market_caps: &HashMap<Ticker, f64>,
+    max_drift: f64,
+) -> Vec<ValidationResult> {
+    candidates
+        .iter()
+        .filter_map(|c| {
+            let live_market_cap = *live_market_caps.get(&c.ticker)?;
+            let drift = c.market_cap / live_market_cap - 1.0;
+            if live_market_cap <= 0.0 || drift.abs() <= max_drift {
+                return None;
+            }
+
+            let company = c.with_status_quo_scenario();
+            let expected_return = company.expected_return();
+            let live_expected_return = Company {
+                market_cap: live_market_cap,
+                ..company
+            }
+            .expected_return();
+
+            Some(ValidationResult::PROBLEM(Problem {
+                code: "market-cap-differs-from-live-value".to_string(),
+                message: format!(
+                    "Market cap of {} in the input is {}, which differs from the live market cap \
+                    of {live_market_cap} by {:.1}%. At the live market cap, the expected return \
+                    changes from {:.1}% to {:.1}%.",
+                    c.ticker,
+                    c.market_cap,
+                    100.0 * drift,
+                    100.0 * expected_return,
+                    100.0 * live_expected_return
+                ),
+                severity: Severity::WARNING,
+                suggestion: None,
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn company(ticker: &str, market_cap: f64) -> Company {
+        serde_yaml::from_str(&format!(
+            "
+            name: {ticker}
+            ticker: {ticker}
+            description: Business {ticker}
+            market_cap: {market_cap}
+            scenarios:
+              - thesis: Down
+                intrinsic_value: 50.0
+                probability: 0.5
+              - thesis: Up
+                intrinsic_value: 200.0
+                probability: 0.5
+            "
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ticker_is_percent_encoded_in_url() {
+        let provider = MarketDataProvider::new(&MarketDataConfig {
+            url_template: "https://example.com/market-cap/{ticker}?currency=USD".to_string(),
+            auth_header_variable: None,
+            max_market_cap_drift: 0.1,
+        });
+
+        assert_eq!(
+            "https://example.com/market-cap/BRK.B?currency=USD",
+            provider.url("BRK.B").unwrap()
+        );
+        assert_eq!(
+            "https://example.com/market-cap/..%2F..%2Fadmin%3Fx%3D1%23?currency=USD",
+            provider.url("../../admin?x=1#").unwrap()
+        );
+        assert_eq!(
+            "https://example.com/market-cap/...A?currency=USD",
+            provider.url("...A").unwrap()
+        );
+
+        // Tickers of only dots would be dot segments of the path, even if percent-encoded
+        for ticker in ["", ".", ".."] {
+            assert_eq!(
+                "invalid-market-data-ticker",
+                provider.url(ticker).unwrap_err().code
+            );
+        }
+    }
+
+    #[test]
+    fn test_market_cap_drift_warnings() {
+        let candidates = vec![
+            company("A", 100.0),
+            company("B", 100.0),
+            company("C", 100.0),
+        ];
+        let live_market_caps = HashMap::from([("A".to_string(), 105.0), ("B".to_string(), 125.0)]);
+
+        let warnings = market_cap_drift_warnings(&candidates, &live_market_caps, 0.1);
+
+        // A is within the threshold and C has no live market cap
+        assert_eq!(1, warnings.len());
+        let ValidationResult::PROBLEM(problem) = &warnings[0] else {
+            panic!("Expected a problem.");
+        };
+        assert_eq!("market-cap-differs-from-live-value", problem.code);
+        assert_eq!(Severity::WARNING, problem.severity);
+        assert!(problem.message.contains("of B"));
+        assert!(problem.message.contains("by -20.0%"));
+        assert!(problem.message.contains("from 25.0% to 0.0%"));
+    }
+}
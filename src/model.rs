@@ -1,6 +1,23 @@
+pub mod allocation_options;
+pub mod allocations;
+pub mod analysis_options;
+pub mod backtest;
+pub mod batch_analysis;
+pub mod bets;
+pub mod call_options;
+pub mod candidates;
 pub mod capital_loss;
 pub mod company;
 pub mod errors;
+pub mod events;
+pub mod jobs;
+pub mod pair_trades;
 pub mod portfolio;
+pub mod remote_input;
 pub mod responses;
 pub mod scenario;
+pub mod snapshots;
+pub mod solver_settings;
+pub mod solver_state;
+pub mod template;
+pub mod usage;
@@ -0,0 +1,44 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use slog::Level;
+
+/// Options for the allocation.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct AllocationOptions {
+    /// Whether to include the iterations of the solver for the unconstrained problem of the
+    /// allocated candidates, e.g. for animating how the solver converges. Only available for up to
+    /// five allocated candidates. Defaults to false.
+    #[serde(default)]
+    pub trace: Option<bool>,
+
+    /// Whether to include the state of the solver, i.e. the input and the solution of each system,
+    /// for reproducing the allocation exactly later. Defaults to false.
+    #[serde(default)]
+    pub solver_state: Option<bool>,
+
+    /// Level at or above which the log messages of the allocation are returned in the response,
+    /// independently of the log level of the server. No messages are returned if not specified.
+    #[serde(default)]
+    pub log_level: Option<CapturedLogLevel>,
+}
+
+/// Level of the log messages returned in the response.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CapturedLogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl From<CapturedLogLevel> for Level {
+    fn from(level: CapturedLogLevel) -> Level {
+        match level {
+            CapturedLogLevel::Debug => Level::Debug,
+            CapturedLogLevel::Info => Level::Info,
+            CapturedLogLevel::Warning => Level::Warning,
+            CapturedLogLevel::Error => Level::Error,
+        }
+    }
+}
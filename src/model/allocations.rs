@@ -0,0 +1,79 @@
+use crate::model::company::Ticker;
+use crate::model::portfolio::AllocationInput;
+use crate::model::responses::AllocationResponse;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// An allocation performed by the server, recorded with its input and response such that it can be
+/// re-run later, e.g. after upgrading the server.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct StoredAllocation {
+    pub id: u64,
+    /// API key for which the allocation was performed, None for the allocations recorded before the
+    /// key was stored, which are then only available through share links.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Version of the crate that performed the allocation.
+    pub crate_version: String,
+    pub input: AllocationInput,
+    pub response: AllocationResponse,
+}
+
+/// Response of re-running a stored allocation with the current code, with the original and the new
+/// response and the differences between them. Since the input is unchanged, any difference is due
+/// to the code.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct RerunResponse {
+    pub id: u64,
+    /// Version of the crate that performed the original allocation.
+    pub original_crate_version: String,
+    /// Version of the crate that re-ran the allocation.
+    pub crate_version: String,
+    pub original: AllocationResponse,
+    pub rerun: AllocationResponse,
+    pub diff: AllocationDiff,
+}
+
+/// Differences between two allocations of the same input.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AllocationDiff {
+    /// Change of the fraction of each ticker allocated in either of the allocations. A ticker
+    /// missing from one of the allocations has the fraction 0 there.
+    pub fraction_changes: Vec<FractionChange>,
+    /// Largest absolute change of a fraction.
+    pub max_absolute_fraction_change: f64,
+    /// Change of the expected logarithmic growth, None unless both allocations have a result.
+    pub expected_log_growth_change: Option<f64>,
+    /// True if only one of the allocations ended with an error, or if the error codes differ.
+    pub error_changed: bool,
+}
+
+/// Original and new fraction of a ticker.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct FractionChange {
+    pub ticker: Ticker,
+    pub original_fraction: f64,
+    pub new_fraction: f64,
+    pub change: f64,
+}
+
+/// Request of a read-only share link to a recorded allocation.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ShareRequest {
+    /// Validity of the link in hours. Defaults to the validity given in the server configuration.
+    #[serde(default)]
+    pub expires_in_hours: Option<u64>,
+}
+
+/// Read-only link to a recorded allocation, rendered as an HTML page for recipients without access
+/// to the API.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ShareLink {
+    /// Signed token of the link, which can't be modified without invalidating it.
+    pub token: String,
+    /// Path of the shared page on the server.
+    pub path: String,
+    pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,170 @@
+use crate::model::company::Ticker;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+/// Options for the portfolio analysis.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct AnalysisOptions {
+    #[serde(default)]
+    pub detail_level: Option<DetailLevel>,
+
+    /// Comma-separated tickers restricting the analysis to the sub-portfolio of these companies,
+    /// with their fractions held fixed, such that the rest of the capital is treated as cash. The
+    /// whole portfolio is analyzed if not specified.
+    #[serde(default)]
+    pub tickers: Option<String>,
+
+    /// Expected annual inflation, e.g. 0.03. If given, the analysis also reports the expected
+    /// return and growth in real terms.
+    #[serde(default)]
+    pub expected_inflation: Option<f64>,
+
+    /// Length of the horizon of the scenarios in years, e.g. 3. If given, the analysis also
+    /// reports the compound growth of the portfolio per year.
+    #[serde(default)]
+    pub horizon_in_years: Option<f64>,
+
+    /// Number of outcomes sampled if the portfolio has more than 50,000 outcomes, which are too
+    /// many to enumerate. Defaults to 100,000.
+    #[serde(default)]
+    pub n_samples: Option<usize>,
+
+    /// Seed of the sampled outcomes, such that the estimates are reproducible. Defaults to 0.
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Return of a benchmark over the horizon of the scenarios, e.g. 0.08. If given, the analysis
+    /// also reports the probability of underperforming the benchmark.
+    #[serde(default)]
+    pub benchmark_return: Option<f64>,
+
+    /// Return threshold, e.g. -0.1. If given, the analysis also reports the probability of a
+    /// portfolio return below it, e.g. of losing more than 10%, which is often a more useful
+    /// question than the probability of losing anything at all.
+    #[serde(default)]
+    pub return_threshold: Option<f64>,
+
+    /// Whether to include the distribution of the portfolio return in the analysis, for plotting
+    /// it. Requires the standard detail level or higher. Defaults to true on the full detail level
+    /// and to false otherwise.
+    #[serde(default)]
+    pub distribution: Option<bool>,
+
+    /// Number of equally wide bins of the histogram of the portfolio return. The distribution is
+    /// returned as the distinct returns with their probabilities if not specified.
+    #[serde(default)]
+    pub n_bins: Option<usize>,
+
+    /// Comma-separated scenario tags, e.g. "recession". For each tag, the analysis also reports the
+    /// portfolio return conditional on the tag being realized, i.e. every company with a scenario
+    /// with the tag ends up in one of its scenarios with the tag.
+    #[serde(default)]
+    pub conditional_tags: Option<String>,
+}
+
+impl AnalysisOptions {
+    /// Returns the tickers of the sub-portfolio to analyze, or None if the whole portfolio is
+    /// analyzed.
+    pub fn ticker_filter(&self) -> Option<Vec<Ticker>> {
+        let tickers = self
+            .tickers
+            .iter()
+            .flat_map(|t| t.split(','))
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<Ticker>>();
+        (!tickers.is_empty()).then_some(tickers)
+    }
+
+    /// Returns the scenario tags to condition the analysis on.
+    pub fn tag_filter(&self) -> Vec<String> {
+        self.conditional_tags
+            .iter()
+            .flat_map(|t| t.split(','))
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+}
+
+/// Level of detail of the portfolio analysis, controlling whether expensive statistics are
+/// calculated:
+/// - Summary: Only statistics that can be calculated analytically, company by company, without
+///   enumerating all outcomes. Suitable for very large portfolios.
+/// - Standard: Additionally, statistics that require enumerating all outcomes, such as the
+///   cumulative probability of loss. This is the default.
+/// - Full: Additionally, the distribution of the portfolio return, unless it's explicitly
+///   excluded.
+#[derive(
+    Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DetailLevel {
+    Summary,
+    #[default]
+    Standard,
+    Full,
+}
+
+impl FromStr for DetailLevel {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "summary" => Ok(DetailLevel::Summary),
+            "standard" => Ok(DetailLevel::Standard),
+            "full" => Ok(DetailLevel::Full),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected \"summary\", \"standard\" or \"full\" as detail level, got {s}"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detail_levels_are_ordered() {
+        assert!(DetailLevel::Summary < DetailLevel::Standard);
+        assert!(DetailLevel::Standard < DetailLevel::Full);
+    }
+
+    #[test]
+    fn test_ticker_filter() {
+        let options = |tickers: Option<&str>| AnalysisOptions {
+            detail_level: None,
+            tickers: tickers.map(|t| t.to_string()),
+            expected_inflation: None,
+            horizon_in_years: None,
+            n_samples: None,
+            seed: None,
+            benchmark_return: None,
+            return_threshold: None,
+            distribution: None,
+            n_bins: None,
+            conditional_tags: None,
+        };
+
+        assert_eq!(None, options(None).ticker_filter());
+        assert_eq!(None, options(Some(" , ")).ticker_filter());
+        assert_eq!(
+            Some(vec!["A".to_string(), "B".to_string()]),
+            options(Some("A, B,")).ticker_filter()
+        );
+    }
+
+    #[test]
+    fn test_detail_level_from_str() {
+        assert_eq!(
+            DetailLevel::from_str("summary").unwrap(),
+            DetailLevel::Summary
+        );
+        assert_eq!(DetailLevel::from_str("full").unwrap(), DetailLevel::Full);
+        assert!(DetailLevel::from_str("everything").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::model::errors::Error;
+use crate::model::portfolio::AllocationInput;
+use crate::model::responses::TickerAndFraction;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Allocation input as of the start of a period of the backtest, identified by a name such as the
+/// name of its file, e.g. 2024-03-31. The market caps of the candidates are those at the time of
+/// the snapshot.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct NamedSnapshot {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub input: AllocationInput,
+}
+
+/// Allocation to the candidates of a snapshot held until the next snapshot, together with the
+/// return it realized, or the error of the period. The capital is held in cash during a period
+/// with an error.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct BacktestRow {
+    /// Name of the snapshot at the start of the period.
+    pub period: String,
+    pub allocations: Vec<TickerAndFraction>,
+    pub expected_return: Option<f64>,
+    /// Return of the allocation from the market caps of the allocated candidates in the next
+    /// snapshot, including the return of the cash.
+    pub realized_return: Option<f64>,
+    /// Wealth at the end of the period relative to the initial capital, compounding the realized
+    /// returns of all periods so far.
+    pub cumulative_wealth: f64,
+    pub error: Option<Error>,
+}
+
+/// Progress of a backtest persisted after each chunk of periods, from which an interrupted
+/// backtest is resumed.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct BacktestCheckpoint {
+    /// Number of periods whose rows are written to the output.
+    pub completed_periods: usize,
+    /// Name of the last completed period, for checking that the snapshots didn't change since.
+    pub last_period: Option<String>,
+    pub cumulative_wealth: f64,
+    /// Length of the output in bytes after the rows of the completed periods. Rows written after
+    /// it by an interrupted chunk are truncated when resuming.
+    pub output_length: u64,
+}
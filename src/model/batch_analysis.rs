@@ -0,0 +1,41 @@
+use crate::model::errors::Error;
+use crate::model::events::PortfolioWithEvents;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Portfolios analyzed together, e.g. variants of a strategy, each identified by its name.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct BatchAnalysisInput {
+    pub portfolios: Vec<NamedPortfolio>,
+}
+
+/// Portfolio with its events, identified by a name such as the name of its file.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct NamedPortfolio {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub portfolio: PortfolioWithEvents,
+}
+
+/// Key statistics of each analyzed portfolio, in the order of the input.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct BatchAnalysisResponse {
+    pub rows: Vec<BatchAnalysisRow>,
+}
+
+/// Key statistics of a single portfolio of the batch, or the error of its analysis. An error of
+/// one portfolio doesn't prevent the analysis of the others.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct BatchAnalysisRow {
+    pub name: String,
+    pub expected_return: Option<f64>,
+    /// Square root of the variance of the portfolio return.
+    pub volatility: Option<f64>,
+    /// Not calculated on the summary detail level, since it requires enumerating all outcomes.
+    pub cumulative_probability_of_loss: Option<f64>,
+    pub worst_case_return: Option<f64>,
+    pub effective_number_of_positions: Option<f64>,
+    pub gross_exposure: Option<f64>,
+    pub error: Option<Error>,
+}
@@ -0,0 +1,218 @@
+use crate::model::company::Company;
+use crate::model::portfolio::AllocationInput;
+use crate::model::scenario::Scenario;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use crate::validation::validate::Validate;
+use indexmap::IndexSet;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Thesis of the scenario in which a bet is won
+pub const WIN_THESIS: &str = "Win";
+
+/// Thesis of the scenario in which a bet is lost
+pub const LOSS_THESIS: &str = "Loss";
+
+/// A simple bet that pays out the odds on a win and loses the stake (or a part of it) on a loss.
+/// If the probabilities of a win and a loss sum up to less than 1, the residual probability is a
+/// push in which the stake is returned.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Bet {
+    /// Name of the bet, which must be unique and identifies the bet in the allocation.
+    pub name: String,
+
+    pub probability_of_win: f64,
+
+    /// Net odds, i.e. the profit per unit staked on a win, e.g. 2.0 for a 2-to-1 bet (decimal odds
+    /// of 3.0).
+    pub odds: f64,
+
+    /// Probability of a loss. Defaults to 1 minus the probability of a win, i.e. no push.
+    #[serde(default)]
+    pub probability_of_loss: Option<f64>,
+
+    /// Fraction of the stake lost on a loss. Defaults to 1, i.e. the whole stake is lost.
+    #[serde(default)]
+    pub loss_fraction: Option<f64>,
+}
+
+/// Allocation input in the classic format of bets with win and loss probabilities and odds, as an
+/// alternative to companies with scenarios. Only the constraints that are meaningful for bets are
+/// supported.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct BetsInput {
+    pub bets: Vec<Bet>,
+
+    #[serde(default)]
+    pub long_only: Option<bool>,
+
+    #[serde(default)]
+    pub max_individual_allocation: Option<f64>,
+
+    #[serde(default)]
+    pub max_total_leverage_ratio: Option<f64>,
+}
+
+impl Bet {
+    /// Converts the bet into a company with a market cap of 1, a win scenario with the intrinsic
+    /// value of 1 plus the odds and a loss scenario with the intrinsic value of 1 minus the loss
+    /// fraction. The push is represented by the automatic status quo scenario.
+    pub fn to_company(&self) -> Company {
+        let probability_of_loss = self
+            .probability_of_loss
+            .unwrap_or(1.0 - self.probability_of_win);
+
+        Company {
+            name: self.name.clone(),
+            ticker: self.name.clone(),
+            description: format!("Bet with net odds of {} to 1", self.odds),
+            market_cap: 1.0,
+            auto_status_quo: self.probability_of_loss.map(|_| true),
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: WIN_THESIS.to_string(),
+                    intrinsic_value: 1.0 + self.odds,
+                    probability: self.probability_of_win,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: LOSS_THESIS.to_string(),
+                    intrinsic_value: 1.0 - self.loss_fraction.unwrap_or(1.0),
+                    probability: probability_of_loss,
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+}
+
+impl BetsInput {
+    /// Converts the bets into an allocation input with the same constraints.
+    pub fn to_allocation_input(&self) -> AllocationInput {
+        AllocationInput {
+            candidates: self.bets.iter().map(|b| b.to_company()).collect(),
+            long_only: self.long_only,
+            long_only_tickers: None,
+            max_permanent_loss_of_capital: None,
+            max_individual_allocation: self.max_individual_allocation,
+            max_individual_allocation_tickers: None,
+            ticker_max_individual_allocations: None,
+            min_individual_allocation: None,
+            max_total_leverage_ratio: self.max_total_leverage_ratio,
+            ..Default::default()
+        }
+    }
+}
+
+impl Validate for BetsInput {
+    /// Validates the odds and loss fractions of the bets. The probabilities and the constraints
+    /// are validated together with the converted allocation input.
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        for bet in &self.bets {
+            if bet.odds <= 0.0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "bet-odds-must-be-positive".to_string(),
+                    message: format!(
+                        "Odds of bet {} must be positive. You provided {}.",
+                        bet.name, bet.odds
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+
+            if let Some(loss_fraction) = bet.loss_fraction {
+                if loss_fraction <= 0.0 || loss_fraction > 1.0 {
+                    validation_results.insert(ValidationResult::PROBLEM(Problem {
+                        code: "bet-loss-fraction-out-of-bounds".to_string(),
+                        message: format!(
+                            "Loss fraction of bet {} must be between 0 (exclusive) and 1 \
+                            (inclusive). You provided {loss_fraction}.",
+                            bet.name
+                        ),
+                        severity: Severity::ERROR,
+                        suggestion: None,
+                    }));
+                }
+            }
+        }
+
+        validation_results
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::TOLERANCE;
+    use crate::utils::assert_close;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_bet_to_company() {
+        let bet = Bet {
+            name: "Coin".to_string(),
+            probability_of_win: 0.6,
+            odds: 1.0,
+            probability_of_loss: None,
+            loss_fraction: None,
+        };
+        let company = bet.to_company();
+
+        assert_eq!("Coin", company.ticker);
+        assert_eq!(1.0, company.market_cap);
+        assert_eq!(None, company.auto_status_quo);
+        assert_eq!(2.0, company.scenarios[0].intrinsic_value);
+        assert_eq!(0.0, company.scenarios[1].intrinsic_value);
+        assert_close!(0.4, company.scenarios[1].probability, TOLERANCE);
+
+        // Residual probability is a push, where half of the stake is lost on a loss
+        let company = Bet {
+            probability_of_loss: Some(0.3),
+            loss_fraction: Some(0.5),
+            ..bet
+        }
+        .to_company()
+        .with_status_quo_scenario();
+        assert_eq!(3, company.scenarios.len());
+        assert_eq!(0.5, company.scenarios[1].intrinsic_value);
+        assert_close!(0.1, company.scenarios[2].probability, TOLERANCE);
+    }
+
+    #[test]
+    fn test_validate_bets() {
+        let input: BetsInput = serde_yaml::from_str(
+            "
+            bets:
+              - name: A
+                probability_of_win: 0.5
+                odds: 0.0
+              - name: B
+                probability_of_win: 0.5
+                odds: 2.0
+                loss_fraction: 1.5
+            ",
+        )
+        .unwrap();
+
+        let codes = input
+            .validate()
+            .into_iter()
+            .filter_map(|v| match v {
+                ValidationResult::PROBLEM(p) => Some(p.code),
+                ValidationResult::OK => None,
+            })
+            .collect::<HashSet<String>>();
+        assert_eq!(
+            HashSet::from([
+                "bet-odds-must-be-positive".to_string(),
+                "bet-loss-fraction-out-of-bounds".to_string()
+            ]),
+            codes
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::model::company::{Company, Ticker};
+use crate::model::scenario::Scenario;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use crate::validation::validate::Validate;
+use indexmap::IndexSet;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A call option or a warrant on a business, whose downside is limited to the premium and whose
+/// upside is leveraged. The scenarios of the underlying business are converted into the payoffs of
+/// the option at expiry, i.e. the option is worthless in the scenarios where the value of a share
+/// ends up below the strike.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CallOption {
+    pub name: String,
+
+    /// Ticker of the option, which must be unique among all candidates and options.
+    pub ticker: Ticker,
+
+    /// Business underlying the option, with its market cap and scenarios. Note that the underlying
+    /// is not allocated to unless it's a candidate as well, in which case it's treated as
+    /// independent of the option.
+    pub underlying: Company,
+
+    /// Current price of a share of the underlying, which relates the strike and the premium to the
+    /// market cap of the underlying.
+    pub share_price: f64,
+
+    /// Strike price per share.
+    pub strike: f64,
+
+    /// Price of the option per share of the underlying, i.e. the capital at risk.
+    pub premium: f64,
+}
+
+impl CallOption {
+    /// Converts the option into a company with the premium as the market cap and the payoff per
+    /// share as the intrinsic value of each scenario, such that the return of a scenario is the
+    /// payoff divided by the premium minus one. The implicit status quo scenario of the underlying
+    /// is converted as well. The scenarios keep the tags and the macro scenarios of the underlying,
+    /// such that the option is correlated with it.
+    pub fn to_company(&self) -> Company {
+        let underlying = self.underlying.with_status_quo_scenario();
+
+        Company {
+            name: self.name.clone(),
+            ticker: self.ticker.clone(),
+            description: format!(
+                "Call option on {} with a strike of {} and a premium of {}",
+                underlying.ticker, self.strike, self.premium
+            ),
+            market_cap: self.premium,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: underlying.last_reviewed,
+            scenarios: underlying
+                .scenarios
+                .iter()
+                .map(|s| Scenario {
+                    thesis: s.thesis.clone(),
+                    intrinsic_value: self.payoff(s.intrinsic_value, underlying.market_cap),
+                    probability: s.probability,
+                    tags: s.tags.clone(),
+                    macro_scenario: s.macro_scenario.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Calculates the payoff per share at expiry if the underlying is worth the intrinsic value.
+    fn payoff(&self, intrinsic_value: f64, market_cap: f64) -> f64 {
+        (self.share_price * intrinsic_value / market_cap - self.strike).max(0.0)
+    }
+}
+
+impl Validate for CallOption {
+    /// Validates the terms of the option. The scenarios are validated together with the candidates
+    /// after the option is converted into a company.
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let positive_terms = [
+            ("share price", self.share_price),
+            ("premium", self.premium),
+            ("market cap of the underlying", self.underlying.market_cap),
+        ];
+        for (term, value) in positive_terms {
+            if value <= 0.0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: format!("option-{}-must-be-positive", term.replace(' ', "-")),
+                    message: format!(
+                        "The {term} of option {} must be positive. You provided {value}.",
+                        self.ticker
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if self.strike < 0.0 {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "option-strike-cannot-be-negative".to_string(),
+                message: format!(
+                    "The strike of option {} cannot be negative. You provided {}.",
+                    self.ticker, self.strike
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::TOLERANCE;
+    use crate::utils::assert_close;
+    use std::collections::HashSet;
+
+    fn call_option() -> CallOption {
+        serde_yaml::from_str(
+            "
+            name: ABC call
+            ticker: ABC 2026-12 C12
+            underlying:
+              name: ABC
+              ticker: ABC
+              description: Underlying business
+              market_cap: 1000.0
+              auto_status_quo: true
+              scenarios:
+                - thesis: Bankruptcy
+                  intrinsic_value: 0.0
+                  probability: 0.2
+                - thesis: Growth
+                  intrinsic_value: 2000.0
+                  probability: 0.5
+            share_price: 10.0
+            strike: 12.0
+            premium: 1.0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_call_option_to_company() {
+        let company = call_option().to_company();
+
+        assert_eq!("ABC 2026-12 C12", company.ticker);
+        assert_eq!(1.0, company.market_cap);
+        assert_eq!(None, company.auto_status_quo);
+        assert_eq!(3, company.scenarios.len());
+
+        // Premium is lost in the bankruptcy and in the status quo, where the share stays at 10
+        // below the strike of 12, while the share price of 20 pays out 8 times the premium
+        assert_eq!(
+            -1.0,
+            company.scenarios[0].scenario_return(company.market_cap)
+        );
+        assert_close!(
+            7.0,
+            company.scenarios[1].scenario_return(company.market_cap),
+            TOLERANCE
+        );
+        assert_eq!(
+            -1.0,
+            company.scenarios[2].scenario_return(company.market_cap)
+        );
+        assert_close!(0.3, company.scenarios[2].probability, TOLERANCE);
+    }
+
+    #[test]
+    fn test_call_option_keeps_tags_and_macro_scenarios() {
+        let mut option = call_option();
+        option.underlying.auto_status_quo = None;
+        option.underlying.scenarios[0].tags = Some(vec!["recession".to_string()]);
+        option.underlying.scenarios[0].macro_scenario = Some("recession".to_string());
+        option.underlying.scenarios[1].macro_scenario = Some("expansion".to_string());
+        let company = option.to_company();
+
+        assert_eq!(
+            Some(vec!["recession".to_string()]),
+            company.scenarios[0].tags
+        );
+        assert_eq!(
+            Some("recession".to_string()),
+            company.scenarios[0].macro_scenario
+        );
+        assert_eq!(None, company.scenarios[1].tags);
+        assert_eq!(
+            Some("expansion".to_string()),
+            company.scenarios[1].macro_scenario
+        );
+    }
+
+    #[test]
+    fn test_validate_call_option() {
+        let option = CallOption {
+            share_price: 0.0,
+            strike: -1.0,
+            premium: -1.0,
+            ..call_option()
+        };
+
+        let codes = option
+            .validate()
+            .into_iter()
+            .filter_map(|v| match v {
+                ValidationResult::PROBLEM(p) => Some(p.code),
+                ValidationResult::OK => None,
+            })
+            .collect::<HashSet<String>>();
+        assert_eq!(
+            HashSet::from([
+                "option-share-price-must-be-positive".to_string(),
+                "option-premium-must-be-positive".to_string(),
+                "option-strike-cannot-be-negative".to_string()
+            ]),
+            codes
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use crate::model::company::{Company, Ticker};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Update of the candidates stored in a workspace, pushed by external systems such as valuation
+/// models. By default, the companies are merged into the stored candidates by ticker, i.e. a
+/// company replaces the stored company with the same ticker and new tickers are added.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CandidateUpdate {
+    pub candidates: Vec<Company>,
+
+    /// If true, the stored candidates are replaced by the given ones instead of merged.
+    #[serde(default)]
+    pub replace: Option<bool>,
+
+    /// If given, the update is rejected unless the stored candidates have this version, which
+    /// prevents concurrent updates from silently overwriting each other.
+    #[serde(default)]
+    pub expected_version: Option<u64>,
+}
+
+/// Candidates stored in a workspace. The version is incremented with every update, starting at 1
+/// for the first one.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct StoredCandidates {
+    pub version: u64,
+    /// Time of the update that produced this version.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub candidates: Vec<Company>,
+}
+
+/// Change of a single candidate in a workspace, recorded whenever an update adds, changes (e.g.
+/// the market cap or the scenarios) or removes the company.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CandidateRevision {
+    pub ticker: Ticker,
+    /// Version of the workspace produced by the update.
+    pub version: u64,
+    pub updated_at: DateTime<Utc>,
+    /// The company after the update, None if the update removed it.
+    pub company: Option<Company>,
+}
+
+/// Query selecting a past state of the candidates in a workspace. The snapshot contains the
+/// candidates as they were after the given version and/or at the given time. The latest candidates
+/// are returned if neither is given.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct SnapshotQuery {
+    #[serde(default)]
+    pub version: Option<u64>,
+    #[serde(default)]
+    pub as_of: Option<DateTime<Utc>>,
+}
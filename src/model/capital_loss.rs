@@ -1,9 +1,9 @@
 use crate::model::company;
 use crate::validation::result::{Problem, Severity, ValidationResult};
 use crate::validation::validate::Validate;
+use indexmap::IndexSet;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
 
 /// Loss of capital is defined by two numbers: probability of the loss happening and the amount
 /// lost. The data model is used in a constraint for modelling maximum allowable loss of capital.
@@ -17,8 +17,8 @@ pub struct CapitalLoss {
 
 impl Validate for CapitalLoss {
     /// Does all validations
-    fn validate(&self) -> HashSet<ValidationResult> {
-        let mut validation_results: HashSet<ValidationResult> = HashSet::new();
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
 
         validation_results.insert(self.validate_fraction_of_capital_bounds());
         validation_results.insert(self.validate_probability_of_loss_bounds());
@@ -38,6 +38,7 @@ impl CapitalLoss {
                     self.probability_of_loss
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             });
         }
 
@@ -49,6 +50,7 @@ impl CapitalLoss {
                     self.probability_of_loss
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             });
         }
 
@@ -67,6 +69,7 @@ impl CapitalLoss {
                     self.fraction_of_capital
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             });
         }
 
@@ -80,6 +83,7 @@ impl CapitalLoss {
                     self.fraction_of_capital
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             });
         }
 
@@ -104,6 +108,7 @@ mod test {
                 message: "Zero or negative probability of loss is not allowed. Probability is 0."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 
@@ -121,6 +126,7 @@ mod test {
                     "Zero or negative probability of loss is not allowed. Probability is -0.2."
                         .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 
@@ -137,6 +143,7 @@ mod test {
                 message: "Probability of loss greater than 1 is not allowed. Probability is 1.2."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 
@@ -155,6 +162,7 @@ mod test {
                     worst-case scenario. Fraction is 0."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 
@@ -173,6 +181,7 @@ mod test {
                     worst-case scenario. Fraction is -0.3."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 
@@ -191,6 +200,7 @@ mod test {
                     Fraction is 2.2."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 }
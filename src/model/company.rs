@@ -1,10 +1,15 @@
+use chrono::NaiveDate;
+use indexmap::IndexSet;
+use itertools::Itertools;
+use nalgebra::{DMatrix, DVector};
+use ordered_float::OrderedFloat;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 
-use crate::model::scenario::Scenario;
-use crate::validation::result::{Problem, Severity, ValidationResult};
+use crate::model::scenario::{Scenario, Valuation};
+use crate::validation::result::{Problem, Severity, Suggestion, ValidationResult};
 use crate::validation::validate::Validate;
 
 pub type Ticker = String;
@@ -12,6 +17,32 @@ pub type Ticker = String;
 /// Tolerance for comparing floats
 pub(crate) const TOLERANCE: f64 = 1e-10;
 
+/// Thesis of the implicit status quo scenario, where nothing changes
+pub const STATUS_QUO_THESIS: &str = "Status quo";
+
+/// Unit in which the monetary values of a company are given, e.g. millions for a market cap of 250
+/// meaning 250 million.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MonetaryUnit {
+    Ones,
+    Thousands,
+    Millions,
+    Billions,
+}
+
+impl MonetaryUnit {
+    /// Number of ones in one unit.
+    pub fn factor(&self) -> f64 {
+        match self {
+            MonetaryUnit::Ones => 1.0,
+            MonetaryUnit::Thousands => 1e3,
+            MonetaryUnit::Millions => 1e6,
+            MonetaryUnit::Billions => 1e9,
+        }
+    }
+}
+
 /// A company with some basic information relevant for investment and a set of possible scenarios
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Company {
@@ -19,6 +50,23 @@ pub struct Company {
     pub ticker: Ticker,
     pub description: String,
     pub market_cap: f64,
+
+    /// Generate an implicit status quo scenario with the intrinsic value equal to the market cap,
+    /// absorbing the residual probability when the probabilities of all scenarios sum up to less
+    /// than 1. False if not specified.
+    #[serde(default)]
+    pub auto_status_quo: Option<bool>,
+
+    /// Unit of the market cap and of the monetary values of all scenarios, which are normalized
+    /// into ones before the allocation. The unit given for the whole input if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<MonetaryUnit>,
+
+    /// Date on which the scenarios were last reviewed, which flags stale inputs in the data
+    /// quality of the candidate. Not flagged if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_reviewed: Option<NaiveDate>,
+
     pub scenarios: Vec<Scenario>,
 }
 
@@ -41,10 +89,11 @@ impl Hash for Company {
 }
 
 impl Validate for Company {
-    fn validate(&self) -> HashSet<ValidationResult> {
-        let mut validation_results: HashSet<ValidationResult> = HashSet::new();
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
 
         validation_results.insert(self.validate_at_least_one_scenario());
+        validation_results.extend(self.validate_finite_values());
         validation_results.insert(self.validate_all_scenarios_unique());
         validation_results.insert(self.validate_probabilities_sum_up_to_one());
         validation_results.insert(self.validate_negative_expected_return());
@@ -66,12 +115,68 @@ impl Company {
                     self.name, self.ticker
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             })
         } else {
             ValidationResult::OK
         }
     }
 
+    /// Validates that the market cap is positive and that the market cap, the values of all
+    /// scenarios and their returns are finite. NaN would pass all other validations since every
+    /// comparison with it is false, and end up in the solver.
+    fn validate_finite_values(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+        if !(self.market_cap.is_finite() && self.market_cap > 0.0) {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "market-cap-must-be-positive-and-finite".to_string(),
+                message: format!(
+                    "Market cap of company {} must be a positive finite number, but is {}.",
+                    self.ticker, self.market_cap
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+            return validation_results;
+        }
+
+        for scenario in &self.scenarios {
+            let valuation = scenario.valuation.clone().unwrap_or_default();
+            let values = [
+                ("intrinsic value", Some(scenario.intrinsic_value)),
+                ("probability", Some(scenario.probability)),
+                ("earnings", valuation.earnings),
+                ("multiple", valuation.multiple),
+                ("free cash flow", valuation.fcf),
+                ("free cash flow yield", valuation.fcf_yield),
+            ];
+            let problem = match values
+                .iter()
+                .find(|(_, v)| v.is_some_and(|v| !v.is_finite()))
+            {
+                Some((name, Some(value))) => Some(format!("a non-finite {name} of {value}")),
+                _ => {
+                    let scenario_return = scenario.scenario_return(self.market_cap);
+                    (!scenario_return.is_finite())
+                        .then(|| format!("a non-finite return of {scenario_return}"))
+                }
+            };
+            if let Some(problem) = problem {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "non-finite-value-in-scenario".to_string(),
+                    message: format!(
+                        "Scenario {} of company {} has {problem}. Check your input.",
+                        scenario.thesis, self.ticker
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        validation_results
+    }
+
     /// Validates that all scenarios have a unique thesis
     fn validate_all_scenarios_unique(&self) -> ValidationResult {
         let n_unique_scenarios =
@@ -85,13 +190,15 @@ impl Company {
                     self.name
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             })
         } else {
             ValidationResult::OK
         }
     }
 
-    /// Validates that all probabilities across all scenarios sum up close to 1
+    /// Validates that all probabilities across all scenarios sum up close to 1, or to at most 1 if
+    /// the residual probability is absorbed by an implicit status quo scenario
     fn validate_probabilities_sum_up_to_one(&self) -> ValidationResult {
         let sum: f64 = self
             .scenarios
@@ -99,17 +206,76 @@ impl Company {
             .map(|scenario| scenario.probability)
             .sum();
 
-        if (sum - 1.0).abs() > TOLERANCE {
+        if self.auto_status_quo.unwrap_or(false) {
+            if sum > 1.0 + TOLERANCE {
+                ValidationResult::PROBLEM(Problem {
+                    code: "probabilities-for-all-scenarios-exceed-one".to_string(),
+                    message: format!("Probabilities of all scenarios for company {name} exceed 1, leaving no probability for the status quo scenario. Sum = {sum}.", name = self.name),
+                    severity: Severity::ERROR,
+                    suggestion: self.rescaled_probabilities(sum),
+                })
+            } else {
+                ValidationResult::OK
+            }
+        } else if (sum - 1.0).abs() > TOLERANCE {
             ValidationResult::PROBLEM(Problem {
                 code: "probabilities-for-all-scenarios-do-not-sum-up-to-one".to_string(),
                 message: format!("Probabilities of all scenarios for company {name} do not sum up to 1. Sum = {sum}.", name = self.name),
                 severity: Severity::ERROR,
+                suggestion: self.rescaled_probabilities(sum),
             })
         } else {
             ValidationResult::OK
         }
     }
 
+    /// Suggests the probabilities of the scenarios rescaled proportionally such that they sum up
+    /// to 1, which keeps their relative likelihoods. None if they can't be rescaled.
+    fn rescaled_probabilities(&self, sum: f64) -> Option<Suggestion> {
+        (sum > 0.0 && self.scenarios.iter().all(|s| s.probability >= 0.0)).then(|| {
+            Suggestion::ScenarioProbabilities {
+                ticker: self.ticker.clone(),
+                probabilities: self.scenarios.iter().map(|s| s.probability / sum).collect(),
+            }
+        })
+    }
+
+    /// Expected return of the company, i.e. the probability weighted return over all scenarios.
+    pub fn expected_return(&self) -> f64 {
+        self.scenarios
+            .iter()
+            .map(|s| s.probability_weighted_return(self.market_cap))
+            .sum()
+    }
+
+    /// Total probability of the scenarios in which the company loses value.
+    pub fn downside_probability(&self) -> f64 {
+        self.scenarios
+            .iter()
+            .filter(|s| s.scenario_return(self.market_cap) < 0.0)
+            .map(|s| s.probability)
+            .sum()
+    }
+
+    /// Ratio between the probability weighted upside and the probability weighted downside (as a
+    /// positive number). Infinite if the company has no downside.
+    pub fn upside_downside_ratio(&self) -> f64 {
+        let (upside, downside) = self.scenarios.iter().fold((0.0, 0.0), |(up, down), s| {
+            let r = s.probability_weighted_return(self.market_cap);
+            if r > 0.0 {
+                (up + r, down)
+            } else {
+                (up, down - r)
+            }
+        });
+
+        if downside > 0.0 {
+            upside / downside
+        } else {
+            f64::INFINITY
+        }
+    }
+
     /// Return a validation warning if a company has a negative expected return. Within this
     /// framework where no shorting is strongly encouraged, this doesn't make sense.
     pub fn validate_negative_expected_return(&self) -> ValidationResult {
@@ -129,6 +295,7 @@ impl Company {
                     self.ticker
                 ),
                 severity: Severity::WARNING,
+                suggestion: None,
             })
         } else {
             ValidationResult::OK
@@ -153,15 +320,231 @@ impl Company {
                     self.ticker
                 ),
                 severity: Severity::WARNING,
+                suggestion: None,
             })
         } else {
             ValidationResult::OK
         }
     }
 
+    /// Returns the smallest probability weighted return among the scenarios, i.e. the probability
+    /// weighted return of the worst-case scenario.
+    pub fn worst_probability_weighted_return(&self) -> f64 {
+        self.scenarios
+            .iter()
+            .map(|s| s.probability_weighted_return(self.market_cap))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Returns a copy of this company with an explicit status quo scenario, whose intrinsic value
+    /// is equal to the market cap, absorbing the residual probability if the company declares an
+    /// automatic status quo scenario. Otherwise, returns an unchanged copy.
+    pub fn with_status_quo_scenario(&self) -> Company {
+        let residual_probability: f64 =
+            1.0 - self.scenarios.iter().map(|s| s.probability).sum::<f64>();
+        if !self.auto_status_quo.unwrap_or(false) || residual_probability < TOLERANCE {
+            return self.clone();
+        }
+
+        let mut company = self.clone();
+        company.scenarios.push(Scenario {
+            thesis: STATUS_QUO_THESIS.to_string(),
+            intrinsic_value: self.market_cap,
+            probability: residual_probability,
+            ..Default::default()
+        });
+        company
+    }
+
+    /// Returns a copy of this company with all monetary values given in ones, using the unit of the
+    /// company or the given default unit. Since only the ratios of the intrinsic values and the
+    /// market cap enter the returns, intrinsic values given in different units than the market cap
+    /// would silently produce absurd returns. If the expected intrinsic value differs from the
+    /// market cap by a factor of more than about 300, the intrinsic values are assumed to be given
+    /// in a different unit and are scaled by the nearest power of a thousand, together with a
+    /// validation warning.
+    pub fn with_normalized_units(
+        &self,
+        default_units: Option<MonetaryUnit>,
+    ) -> (Company, ValidationResult) {
+        let unit_factor = self.units.or(default_units).map_or(1.0, |u| u.factor());
+        let expected_intrinsic_value: f64 = self
+            .scenarios
+            .iter()
+            .map(|s| s.probability * s.intrinsic_value)
+            .sum();
+
+        // Powers of a thousand between the expected intrinsic value and the market cap, where even
+        // extreme bets don't come close to a factor of 300
+        let ratio = expected_intrinsic_value / self.market_cap;
+        let mismatch = if ratio > 0.0 && ratio.is_finite() && ratio.log10().abs() > 2.5 {
+            (ratio.log10() / 3.0).round() as i32
+        } else {
+            0
+        };
+        let scenario_factor = unit_factor / 1e3_f64.powi(mismatch);
+
+        let company = Company {
+            market_cap: unit_factor * self.market_cap,
+            units: None,
+            scenarios: self
+                .scenarios
+                .iter()
+                .map(|s| Scenario {
+                    intrinsic_value: scenario_factor * s.intrinsic_value,
+                    valuation: s.valuation.as_ref().map(|v| Valuation {
+                        earnings: v.earnings.map(|e| scenario_factor * e),
+                        fcf: v.fcf.map(|f| scenario_factor * f),
+                        ..v.clone()
+                    }),
+                    ..s.clone()
+                })
+                .collect(),
+            ..self.clone()
+        };
+
+        if mismatch == 0 {
+            return (company, ValidationResult::OK);
+        }
+
+        let validation_result = ValidationResult::PROBLEM(Problem {
+            code: "inconsistent-monetary-units".to_string(),
+            message: format!(
+                "Expected intrinsic value of company {} is {ratio:.1e} times its market cap, which \
+                suggests that they are given in different units. The intrinsic values were scaled \
+                by {:.0e}. Give the market cap and the intrinsic values in the same units.",
+                self.ticker,
+                1e3_f64.powi(-mismatch)
+            ),
+            severity: Severity::WARNING,
+            suggestion: None,
+        });
+
+        (company, validation_result)
+    }
+
+    /// Returns a copy of this company without scenarios whose positive probability is smaller than
+    /// the given minimum probability, together with a validation warning if any scenario was
+    /// removed. Probabilities of the remaining scenarios are rescaled such that their sum remains
+    /// the same as before the removal. Extremely small probabilities paired with extreme returns
+    /// pass the validation, but make the nonlinear solver unstable.
+    pub fn without_negligible_scenarios(
+        &self,
+        min_probability: f64,
+    ) -> (Company, ValidationResult) {
+        let (negligible, retained): (Vec<&Scenario>, Vec<&Scenario>) = self
+            .scenarios
+            .iter()
+            .partition(|s| s.probability > 0.0 && s.probability < min_probability);
+
+        let retained_sum: f64 = retained.iter().map(|s| s.probability).sum();
+        if negligible.is_empty() || retained_sum < TOLERANCE {
+            return (self.clone(), ValidationResult::OK);
+        }
+
+        let negligible_sum: f64 = negligible.iter().map(|s| s.probability).sum();
+        let scale = (retained_sum + negligible_sum) / retained_sum;
+
+        let company = Company {
+            scenarios: retained
+                .into_iter()
+                .map(|s| Scenario {
+                    probability: scale * s.probability,
+                    ..s.clone()
+                })
+                .collect(),
+            ..self.clone()
+        };
+
+        let validation_result = ValidationResult::PROBLEM(Problem {
+            code: "negligible-scenario-probability-removed".to_string(),
+            message: format!(
+                "Removed {} scenario(s) of company {} with probability smaller than {}: {}. \
+                Probabilities of the remaining scenarios were rescaled accordingly.",
+                negligible.len(),
+                self.ticker,
+                min_probability,
+                negligible.iter().map(|s| s.thesis.as_str()).join(", ")
+            ),
+            severity: Severity::WARNING,
+            suggestion: None,
+        });
+
+        (company, validation_result)
+    }
+
+    /// Returns a copy of this company where the scenarios are compressed into at most the given
+    /// number of scenarios, together with a validation warning if the scenarios were compressed.
+    /// The compressed scenarios match the first 2N - 1 moments of the return distribution, where N
+    /// is the number of compressed scenarios. They are the nodes and weights of the Gaussian
+    /// quadrature for the discrete distribution of returns. Note that the compressed scenarios lie
+    /// within the range of the original returns, such that the worst-case scenario becomes milder.
+    /// Scenarios placed in macro scenarios aren't compressed, since that would lose their
+    /// correlation with the other companies.
+    pub fn with_compressed_scenarios(&self, max_scenarios: usize) -> (Company, ValidationResult) {
+        if self.scenarios.len() <= max_scenarios
+            || max_scenarios == 0
+            || self.scenarios.iter().any(|s| s.macro_scenario.is_some())
+        {
+            return (self.clone(), ValidationResult::OK);
+        }
+
+        let returns = self
+            .scenarios
+            .iter()
+            .map(|s| s.scenario_return(self.market_cap))
+            .collect_vec();
+        let probabilities = self.scenarios.iter().map(|s| s.probability).collect_vec();
+
+        // The number of compressed scenarios can't exceed the number of distinct returns
+        let n_distinct_returns = returns
+            .iter()
+            .zip(probabilities.iter())
+            .filter(|(_, &p)| p > 0.0)
+            .map(|(&r, _)| OrderedFloat(r))
+            .unique()
+            .count();
+        let n_scenarios = max_scenarios.min(n_distinct_returns);
+
+        let company = Company {
+            scenarios: gaussian_quadrature(&returns, &probabilities, n_scenarios)
+                .into_iter()
+                .enumerate()
+                .map(|(i, (r, p))| Scenario {
+                    thesis: format!(
+                        "Compressed scenario {} of {n_scenarios} matching {} moments of the \
+                        original {} scenarios.",
+                        i + 1,
+                        2 * n_scenarios - 1,
+                        self.scenarios.len()
+                    ),
+                    intrinsic_value: (1.0 + r) * self.market_cap,
+                    probability: p,
+                    ..Default::default()
+                })
+                .collect(),
+            ..self.clone()
+        };
+
+        let validation_result = ValidationResult::PROBLEM(Problem {
+            code: "scenarios-compressed".to_string(),
+            message: format!(
+                "Compressed {} scenarios of company {} into {n_scenarios} scenarios matching the \
+                first {} moments of the return distribution.",
+                self.scenarios.len(),
+                self.ticker,
+                2 * n_scenarios - 1
+            ),
+            severity: Severity::WARNING,
+            suggestion: None,
+        });
+
+        (company, validation_result)
+    }
+
     /// Validate all scenarios individually
-    fn validate_all_scenarios(&self) -> HashSet<ValidationResult> {
-        let mut validation_results: HashSet<ValidationResult> = HashSet::new();
+    fn validate_all_scenarios(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
 
         self.scenarios
             .iter()
@@ -171,6 +554,58 @@ impl Company {
     }
 }
 
+/// Calculates the N-point Gaussian quadrature (values and probabilities) for a discrete
+/// distribution, which matches the first 2N - 1 moments of the distribution. The recurrence
+/// coefficients of the orthogonal polynomials are calculated with the Stieltjes procedure, and the
+/// quadrature is obtained from the eigenvalues and eigenvectors of the Jacobi matrix (Golub-Welsch
+/// algorithm). The number of points must not exceed the number of distinct values with a positive
+/// probability.
+fn gaussian_quadrature(values: &[f64], probabilities: &[f64], n_points: usize) -> Vec<(f64, f64)> {
+    let total_probability: f64 = probabilities.iter().sum();
+
+    // Orthogonal polynomials evaluated at all values, starting with p_-1 = 0 and p_0 = 1
+    let mut previous_polynomial: Vec<f64> = vec![0.0; values.len()];
+    let mut polynomial: Vec<f64> = vec![1.0; values.len()];
+    let mut previous_norm: f64 = 1.0;
+    let mut alphas: Vec<f64> = Vec::with_capacity(n_points);
+    let mut betas: Vec<f64> = Vec::with_capacity(n_points);
+    for k in 0..n_points {
+        let norm: f64 = (0..values.len())
+            .map(|i| probabilities[i] * polynomial[i] * polynomial[i])
+            .sum();
+        let alpha: f64 = (0..values.len())
+            .map(|i| probabilities[i] * values[i] * polynomial[i] * polynomial[i])
+            .sum::<f64>()
+            / norm;
+        let beta = if k == 0 { 0.0 } else { norm / previous_norm };
+        alphas.push(alpha);
+        betas.push(beta);
+
+        let next_polynomial = (0..values.len())
+            .map(|i| (values[i] - alpha) * polynomial[i] - beta * previous_polynomial[i])
+            .collect_vec();
+        previous_polynomial = polynomial;
+        polynomial = next_polynomial;
+        previous_norm = norm;
+    }
+
+    // Symmetric tridiagonal Jacobi matrix
+    let mut jacobi_matrix: DMatrix<f64> = DMatrix::from_diagonal(&DVector::from_vec(alphas));
+    (1..n_points).for_each(|k| {
+        jacobi_matrix[(k - 1, k)] = betas[k].sqrt();
+        jacobi_matrix[(k, k - 1)] = betas[k].sqrt();
+    });
+
+    let eigen = jacobi_matrix.symmetric_eigen();
+    eigen
+        .eigenvalues
+        .iter()
+        .zip(eigen.eigenvectors.column_iter())
+        .map(|(&value, eigenvector)| (value, total_probability * eigenvector[0] * eigenvector[0]))
+        .sorted_by_key(|(value, _)| OrderedFloat(*value))
+        .collect_vec()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -188,22 +623,27 @@ mod test {
             ticker: "SC".to_string(),
             description: "Some business that's pretty interesting.".to_string(),
             market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "Worst case liquidation value".to_string(),
                     intrinsic_value: 1e6,
                     probability: 0.6,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "Base case liquidation value".to_string(),
                     intrinsic_value: 2e6,
                     probability: 0.4,
+                    ..Default::default()
                 },
             ],
         };
         let test_str = serde_yaml::to_string(&test_company).unwrap();
 
-        assert_eq!(test_str, "name: Some Company\nticker: SC\ndescription: Some business that's pretty interesting.\nmarket_cap: 500000.0\nscenarios:\n- thesis: Worst case liquidation value\n  intrinsic_value: 1000000.0\n  probability: 0.6\n- thesis: Base case liquidation value\n  intrinsic_value: 2000000.0\n  probability: 0.4\n");
+        assert_eq!(test_str, "name: Some Company\nticker: SC\ndescription: Some business that's pretty interesting.\nmarket_cap: 500000.0\nauto_status_quo: null\nscenarios:\n- thesis: Worst case liquidation value\n  intrinsic_value: 1000000.0\n  probability: 0.6\n- thesis: Base case liquidation value\n  intrinsic_value: 2000000.0\n  probability: 0.4\n");
     }
 
     #[test]
@@ -254,6 +694,9 @@ mod test {
             ticker: "SC".to_string(),
             description: "Some business that's pretty interesting.".to_string(),
             market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![],
         };
 
@@ -263,6 +706,7 @@ mod test {
                 code: "no-scenarios-for-company".to_string(),
                 message: "No scenarios found for Some Company with ticker SC.".to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })));
     }
 
@@ -273,16 +717,21 @@ mod test {
             ticker: "SC".to_string(),
             description: "Some business that's pretty interesting.".to_string(),
             market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "Same thesis as the other one.".to_string(),
                     intrinsic_value: 1e6,
                     probability: 0.6,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "Same thesis as the other one.".to_string(),
                     intrinsic_value: 2e6,
                     probability: 0.4,
+                    ..Default::default()
                 },
             ],
         };
@@ -295,6 +744,7 @@ mod test {
                 "Not all scenarios have a unique thesis for company Some Company. Check your input."
                     .to_string(),
             severity: Severity::ERROR,
+            suggestion: None,
         })));
     }
 
@@ -305,16 +755,21 @@ mod test {
             ticker: "SC".to_string(),
             description: "Some business that's pretty interesting.".to_string(),
             market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "Worst case liquidation value.".to_string(),
                     intrinsic_value: 1e6,
                     probability: 0.5,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "Base case liquidation value.".to_string(),
                     intrinsic_value: 2e6,
                     probability: 0.3,
+                    ..Default::default()
                 },
             ],
         };
@@ -326,6 +781,10 @@ mod test {
                 message: "Probabilities of all scenarios for company Some Company do not sum up to 1. Sum = 0.8."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: Some(Suggestion::ScenarioProbabilities {
+                    ticker: "SC".to_string(),
+                    probabilities: vec![0.5 / 0.8, 0.3 / 0.8],
+                }),
             })));
     }
 
@@ -336,16 +795,21 @@ mod test {
             ticker: "SC".to_string(),
             description: "Company with negative expected return.".to_string(),
             market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "Loss.".to_string(),
                     intrinsic_value: 1e5,
                     probability: 0.5,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "Zero return.".to_string(),
                     intrinsic_value: 5e5,
                     probability: 0.5,
+                    ..Default::default()
                 },
             ],
         };
@@ -359,7 +823,53 @@ mod test {
                     current framework because we want to prohibit shorting."
                         .to_string(),
                 severity: Severity::WARNING,
+                suggestion: None,
+            })));
+    }
+
+    #[test]
+    fn test_validate_non_finite_values() {
+        let mut test_company: Company = Company {
+            name: "Some Company".to_string(),
+            ticker: "SC".to_string(),
+            description: "Company with a non-finite probability.".to_string(),
+            market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "Loss.".to_string(),
+                    intrinsic_value: 1e5,
+                    probability: f64::NAN,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Gain.".to_string(),
+                    intrinsic_value: 1e6,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        assert!(test_company
+            .validate()
+            .contains(&ValidationResult::PROBLEM(Problem {
+                code: "non-finite-value-in-scenario".to_string(),
+                message: "Scenario Loss. of company SC has a non-finite probability of NaN. Check \
+                    your input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
             })));
+
+        test_company.market_cap = 0.0;
+        assert!(test_company
+            .validate()
+            .iter()
+            .any(|v| matches!(v, ValidationResult::PROBLEM(p)
+                if p.code == "market-cap-must-be-positive-and-finite")));
     }
 
     #[test]
@@ -369,16 +879,21 @@ mod test {
             ticker: "SC".to_string(),
             description: "Company with no downside.".to_string(),
             market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![
                 Scenario {
                     thesis: "Break-even.".to_string(),
                     intrinsic_value: 5e5,
                     probability: 0.5,
+                    ..Default::default()
                 },
                 Scenario {
                     thesis: "Double.".to_string(),
                     intrinsic_value: 1e6,
                     probability: 0.5,
+                    ..Default::default()
                 },
             ],
         };
@@ -392,6 +907,7 @@ mod test {
                     you to put all your money on this company."
                     .to_string(),
                 severity: Severity::WARNING,
+                suggestion: None,
             })));
     }
 
@@ -402,6 +918,9 @@ mod test {
             ticker: "SFN".to_string(),
             description: "A description".to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![],
         };
         let test_company_2 = Company {
@@ -409,6 +928,9 @@ mod test {
             ticker: "SFN".to_string(),
             description: "A different description".to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![],
         };
 
@@ -422,6 +944,9 @@ mod test {
             ticker: "SFN".to_string(),
             description: "A description".to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![],
         };
         let test_company_2 = Company {
@@ -429,6 +954,9 @@ mod test {
             ticker: "SFN".to_string(),
             description: "A different description".to_string(),
             market_cap: 1e7,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
             scenarios: vec![],
         };
 
@@ -438,4 +966,232 @@ mod test {
             test_company_2.hash(&mut hasher)
         );
     }
+
+    #[test]
+    fn test_without_negligible_scenarios() {
+        let test_company: Company = Company {
+            name: "Some Company".to_string(),
+            ticker: "SC".to_string(),
+            description: "Company with an extremely unlikely scenario.".to_string(),
+            market_cap: 5e5,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "Extremely unlikely.".to_string(),
+                    intrinsic_value: 1e5,
+                    probability: 1e-8,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Downside.".to_string(),
+                    intrinsic_value: 2.5e5,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Upside.".to_string(),
+                    intrinsic_value: 1e6,
+                    probability: 0.5 - 1e-8,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let (preconditioned, validation_result) = test_company.without_negligible_scenarios(1e-6);
+
+        assert_eq!(preconditioned.scenarios.len(), 2);
+        assert_eq!(preconditioned.scenarios[0].thesis, "Downside.");
+        assert!(
+            (preconditioned
+                .scenarios
+                .iter()
+                .map(|s| s.probability)
+                .sum::<f64>()
+                - 1.0)
+                .abs()
+                < TOLERANCE
+        );
+        assert_eq!(
+            validation_result,
+            ValidationResult::PROBLEM(Problem {
+                code: "negligible-scenario-probability-removed".to_string(),
+                message: "Removed 1 scenario(s) of company SC with probability smaller than \
+                    0.000001: Extremely unlikely.. Probabilities of the remaining scenarios were \
+                    rescaled accordingly."
+                    .to_string(),
+                severity: Severity::WARNING,
+                suggestion: None,
+            })
+        );
+
+        // Nothing is removed when the minimum probability is zero
+        let (unchanged, validation_result) = test_company.without_negligible_scenarios(0.0);
+        assert_eq!(unchanged.scenarios.len(), 3);
+        assert_eq!(validation_result, ValidationResult::OK);
+    }
+
+    #[test]
+    fn test_with_compressed_scenarios() {
+        let test_company: Company = Company {
+            name: "Some Company".to_string(),
+            ticker: "SC".to_string(),
+            description: "Company modeled with many scenarios.".to_string(),
+            market_cap: 1e6,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: (0..10)
+                .map(|i| Scenario {
+                    thesis: format!("Scenario {i}."),
+                    intrinsic_value: 2e5 * (i + 1) as f64,
+                    probability: 0.1,
+                    ..Default::default()
+                })
+                .collect(),
+        };
+
+        let (compressed, validation_result) = test_company.with_compressed_scenarios(3);
+
+        assert_eq!(compressed.scenarios.len(), 3);
+        assert_eq!(
+            validation_result,
+            ValidationResult::PROBLEM(Problem {
+                code: "scenarios-compressed".to_string(),
+                message: "Compressed 10 scenarios of company SC into 3 scenarios matching the \
+                    first 5 moments of the return distribution."
+                    .to_string(),
+                severity: Severity::WARNING,
+                suggestion: None,
+            })
+        );
+
+        // The first five moments of the return distribution are matched
+        let moment = |company: &Company, order: i32| -> f64 {
+            company
+                .scenarios
+                .iter()
+                .map(|s| s.probability * s.scenario_return(company.market_cap).powi(order))
+                .sum()
+        };
+        (0..6).for_each(|order| {
+            assert!((moment(&test_company, order) - moment(&compressed, order)).abs() < 1e-8)
+        });
+
+        // Nothing is compressed when there are few enough scenarios
+        let (unchanged, validation_result) = test_company.with_compressed_scenarios(10);
+        assert_eq!(unchanged.scenarios.len(), 10);
+        assert_eq!(validation_result, ValidationResult::OK);
+    }
+
+    #[test]
+    fn test_with_normalized_units() {
+        let test_company: Company = serde_yaml::from_str(
+            "
+            name: Some Company
+            ticker: SC
+            description: Company given in millions.
+            market_cap: 250.0
+            units: millions
+            scenarios:
+              - thesis: Downside
+                intrinsic_value: 100.0
+                probability: 0.5
+              - thesis: Upside
+                earnings: 50.0
+                multiple: 10.0
+                probability: 0.5
+            ",
+        )
+        .unwrap();
+
+        // The unit of the company takes precedence over the default unit
+        let (normalized, validation) =
+            test_company.with_normalized_units(Some(MonetaryUnit::Thousands));
+        assert_eq!(validation, ValidationResult::OK);
+        assert_eq!(normalized.units, None);
+        assert_eq!(normalized.market_cap, 250e6);
+        assert_eq!(normalized.scenarios[0].intrinsic_value, 100e6);
+        assert_eq!(normalized.scenarios[1].intrinsic_value, 500e6);
+        assert_eq!(
+            normalized.scenarios[1].valuation.as_ref().unwrap().earnings,
+            Some(50e6)
+        );
+        assert_eq!(test_company.expected_return(), normalized.expected_return());
+
+        // Intrinsic values given in millions while the market cap is given in ones
+        let mixed = Company {
+            market_cap: 250e6,
+            units: None,
+            ..test_company.clone()
+        };
+        let (normalized, validation) = mixed.with_normalized_units(None);
+        let ValidationResult::PROBLEM(problem) = validation else {
+            panic!("Expected a validation problem.")
+        };
+        assert_eq!(problem.code, "inconsistent-monetary-units");
+        assert_eq!(problem.severity, Severity::WARNING);
+        assert_eq!(normalized.market_cap, 250e6);
+        assert_eq!(normalized.scenarios[0].intrinsic_value, 100e6);
+        assert_eq!(test_company.expected_return(), normalized.expected_return());
+    }
+
+    #[test]
+    fn test_with_status_quo_scenario() {
+        let mut test_company: Company = Company {
+            name: "Some Company".to_string(),
+            ticker: "SC".to_string(),
+            description: "Company where nothing changes most of the time.".to_string(),
+            market_cap: 5e5,
+            auto_status_quo: Some(true),
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "Downside.".to_string(),
+                    intrinsic_value: 2.5e5,
+                    probability: 0.2,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Upside.".to_string(),
+                    intrinsic_value: 1e6,
+                    probability: 0.3,
+                    ..Default::default()
+                },
+            ],
+        };
+        assert_eq!(
+            test_company.validate_probabilities_sum_up_to_one(),
+            ValidationResult::OK
+        );
+
+        let with_status_quo = test_company.with_status_quo_scenario();
+        assert_eq!(with_status_quo.scenarios.len(), 3);
+        assert_eq!(with_status_quo.scenarios[2].thesis, STATUS_QUO_THESIS);
+        assert_eq!(with_status_quo.scenarios[2].intrinsic_value, 5e5);
+        assert!((with_status_quo.scenarios[2].probability - 0.5).abs() < TOLERANCE);
+
+        // Probabilities exceeding 1 leave nothing for the status quo
+        test_company.scenarios[1].probability = 0.9;
+        assert_eq!(
+            test_company.validate_probabilities_sum_up_to_one(),
+            ValidationResult::PROBLEM(Problem {
+                code: "probabilities-for-all-scenarios-exceed-one".to_string(),
+                message: "Probabilities of all scenarios for company Some Company exceed 1, \
+                    leaving no probability for the status quo scenario. Sum = 1.1."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: Some(Suggestion::ScenarioProbabilities {
+                    ticker: "SC".to_string(),
+                    probabilities: vec![0.2 / (0.2 + 0.9), 0.9 / (0.2 + 0.9)],
+                }),
+            })
+        );
+
+        // Without the automatic status quo, the company is unchanged
+        test_company.auto_status_quo = None;
+        assert_eq!(test_company.with_status_quo_scenario().scenarios.len(), 2);
+    }
 }
@@ -0,0 +1,82 @@
+use crate::model::company::{Company, Ticker};
+use crate::model::portfolio::Portfolio;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use indexmap::IndexSet;
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Named portfolio-level event (e.g. "recession"), defined by the scenarios of the companies that
+/// occur in the event. Companies that aren't listed may be in any of their scenarios.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PortfolioEvent {
+    pub name: String,
+
+    /// Theses of the scenarios that occur in the event, by ticker. The implicit status quo
+    /// scenario can be selected with its thesis "Status quo".
+    pub scenarios: HashMap<Ticker, Vec<String>>,
+}
+
+/// Portfolio with named events to report on in the analysis.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PortfolioWithEvents {
+    #[serde(flatten)]
+    pub portfolio: Portfolio,
+
+    #[serde(default)]
+    pub events: Option<Vec<PortfolioEvent>>,
+}
+
+impl PortfolioEvent {
+    /// Validates that the event refers to existing companies and theses of their scenarios.
+    pub fn validate_against(&self, companies: &[Company]) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let unknown_tickers = self
+            .scenarios
+            .keys()
+            .filter(|t| !companies.iter().any(|c| &c.ticker == *t))
+            .sorted()
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-event".to_string(),
+                message: format!(
+                    "Tickers {} in event {} are not found among the companies. Check your input.",
+                    unknown_tickers.iter().join(", "),
+                    self.name
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        for company in companies {
+            let company = company.with_status_quo_scenario();
+            let unknown_theses = self
+                .scenarios
+                .get(&company.ticker)
+                .into_iter()
+                .flatten()
+                .filter(|thesis| !company.scenarios.iter().any(|s| &s.thesis == *thesis))
+                .collect_vec();
+            if !unknown_theses.is_empty() {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "unknown-theses-in-event".to_string(),
+                    message: format!(
+                        "Theses {} of {} in event {} are not found among its scenarios. Check \
+                        your input.",
+                        unknown_theses.iter().join(", "),
+                        company.ticker,
+                        self.name
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        validation_results
+    }
+}
@@ -0,0 +1,62 @@
+use crate::model::errors::Error;
+use crate::model::responses::AllocationResponse;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Link to an allocation job started in the background, whose status can be polled.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct JobLink {
+    pub id: u64,
+    /// Path of the status of the job on the server.
+    pub path: String,
+}
+
+/// Status and progress of an allocation job, with the response once it's completed.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AllocationJob {
+    pub id: u64,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub progress: JobProgress,
+    /// Response of the allocation, given once the job is completed.
+    #[serde(default)]
+    pub response: Option<AllocationResponse>,
+    /// Id under which the allocation is recorded, such that it can be re-run later with
+    /// `POST /allocations/{id}/rerun`. Not given if the allocation was not recorded.
+    #[serde(default)]
+    pub allocation_id: Option<u64>,
+    /// Reason why the job failed without a response, e.g. because the allocation queue was full.
+    #[serde(default)]
+    pub error: Option<Error>,
+}
+
+/// Status of an allocation job:
+/// - Queued: Waiting for a free slot in the allocation queue.
+/// - Running: Solving the systems of the allocation.
+/// - Completed: The response is available. It may still contain an error of the allocation.
+/// - Failed: The allocation couldn't be performed, e.g. because the allocation queue was full.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Progress of the solver in an allocation job. An allocation may solve several problems, e.g. with
+/// and without leverage and for the alternative allocations, each of which enumerates the 2^N
+/// combinations of its N inequality constraints unless the bounds are found by projection.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq, Eq)]
+pub struct JobProgress {
+    /// Number of systems solved so far, cumulative over every problem of the allocation.
+    pub n_systems_solved: u64,
+    /// One-based index of the enumeration in progress (or of the last one), 0 before the first.
+    pub enumeration_index: u64,
+    /// Number of systems solved so far in the enumeration in progress.
+    pub n_enumeration_systems_solved: u64,
+    /// Number of systems of the enumeration in progress, i.e. 2^N for N inequality constraints.
+    /// None before the first enumeration.
+    pub n_enumeration_systems: Option<u64>,
+}
@@ -0,0 +1,144 @@
+use crate::model::company::{Company, Ticker};
+use crate::model::responses::TickerAndFraction;
+use crate::model::scenario::Scenario;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use crate::validation::validate::Validate;
+use indexmap::IndexSet;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A pair trade, long one security and short another one in the same amount, whose scenarios are
+/// defined on the spread between the two legs instead of on each leg separately. It's allocated to
+/// as a single candidate, where the fraction of the pair is the amount in each leg. Since the legs
+/// cancel out, the pair has no net exposure and doesn't count towards the maximum total leverage
+/// and the fully invested constraints.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PairTrade {
+    pub name: String,
+
+    /// Ticker of the pair, which must be unique among all candidates.
+    pub ticker: Ticker,
+
+    /// Ticker of the security held long.
+    pub long: Ticker,
+
+    /// Ticker of the security sold short.
+    pub short: Ticker,
+
+    pub scenarios: Vec<SpreadScenario>,
+}
+
+/// Scenario of a pair trade given by the return of the spread, i.e. the return of the long leg
+/// minus the return of the short leg, e.g. 0.2 if the long leg gains 10% while the short leg loses
+/// 10%.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SpreadScenario {
+    pub thesis: String,
+    pub spread_return: f64,
+    pub probability: f64,
+}
+
+impl PairTrade {
+    /// Converts the pair into a company with a unit market cap, whose scenarios have the intrinsic
+    /// value of one plus the return of the spread, such that the return of a scenario is the return
+    /// of the spread.
+    pub fn to_company(&self) -> Company {
+        Company {
+            name: self.name.clone(),
+            ticker: self.ticker.clone(),
+            description: format!("Pair trade long {} and short {}", self.long, self.short),
+            market_cap: 1.0,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: self
+                .scenarios
+                .iter()
+                .map(|s| Scenario {
+                    thesis: s.thesis.clone(),
+                    intrinsic_value: 1.0 + s.spread_return,
+                    probability: s.probability,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the fractions of the long and the short leg for the fraction of the pair.
+    pub fn legs(&self, fraction: f64) -> [TickerAndFraction; 2] {
+        [
+            TickerAndFraction {
+                ticker: self.long.clone(),
+                fraction,
+            },
+            TickerAndFraction {
+                ticker: self.short.clone(),
+                fraction: -fraction,
+            },
+        ]
+    }
+}
+
+impl Validate for PairTrade {
+    /// Validates that the legs are different securities. The scenarios are validated together with
+    /// the candidates after the pair is converted into a company.
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        if self.long == self.short {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "pair-trade-legs-must-differ".to_string(),
+                message: format!(
+                    "Pair trade {} is long and short the same security {}.",
+                    self.ticker, self.long
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::TOLERANCE;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_pair_trade() {
+        let pair: PairTrade = serde_yaml::from_str(
+            "
+            name: Long ABC short XYZ
+            ticker: ABC/XYZ
+            long: ABC
+            short: XYZ
+            scenarios:
+              - thesis: Spread closes
+                spread_return: 0.3
+                probability: 0.6
+              - thesis: Spread widens
+                spread_return: -0.4
+                probability: 0.4
+            ",
+        )
+        .unwrap();
+        assert!(pair.validate().is_empty());
+
+        let company = pair.to_company();
+        assert_eq!("ABC/XYZ", company.ticker);
+        assert_close!(0.6 * 0.3 - 0.4 * 0.4, company.expected_return(), TOLERANCE);
+
+        let [long, short] = pair.legs(0.25);
+        assert_eq!(("ABC", 0.25), (long.ticker.as_str(), long.fraction));
+        assert_eq!(("XYZ", -0.25), (short.ticker.as_str(), short.fraction));
+
+        let same_legs = PairTrade {
+            short: "ABC".to_string(),
+            ..pair
+        };
+        assert_eq!(1, same_legs.validate().len());
+    }
+}
@@ -1,17 +1,42 @@
+use crate::model::call_options::CallOption;
 use crate::model::capital_loss::CapitalLoss;
-use crate::model::company::Company;
+use crate::model::company::{Company, MonetaryUnit, Ticker, TOLERANCE};
+use crate::model::errors::Error;
+use crate::model::events::PortfolioEvent;
+use crate::model::pair_trades::PairTrade;
+use crate::model::responses::TickerAndFraction;
+use crate::model::solver_settings::SolverSettings;
+use crate::symbology::{normalize_ticker, tickers_with_same_key};
 use crate::validation::result::{Problem, Severity, ValidationResult};
 use crate::validation::validate::Validate;
+use indexmap::IndexSet;
 use itertools::Itertools;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::RandomState;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 /// Portfolio has a list of portfolio companies.
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
 pub struct Portfolio {
     pub companies: Vec<PortfolioCompany>,
+
+    /// Macro scenarios in which the scenarios of the companies are placed, whose probabilities are
+    /// used for the outcomes. If not specified, the probability of a macro scenario is the average
+    /// over the companies of the probabilities of their scenarios placed in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub macro_scenarios: Option<Vec<MacroScenario>>,
+}
+
+/// Portfolios of several accounts (e.g. managed against the same candidates) that are merged into
+/// a single portfolio to analyze the combined exposure.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PortfolioMerge {
+    pub portfolios: Vec<Portfolio>,
+
+    /// Named portfolio-level events to report on in the analysis of the merged portfolio.
+    #[serde(default)]
+    pub events: Option<Vec<PortfolioEvent>>,
 }
 
 /// Portfolio company represents a company with an associated allocation fraction.
@@ -23,27 +48,455 @@ pub struct PortfolioCompany {
 
 /// Allocation input consists of a list of candidate companies and additional constraints.
 /// Note that the constraints are optional because the deserialization default for Option is None.
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
 pub struct AllocationInput {
     pub candidates: Vec<Company>,
 
+    /// Call options and warrants, which are allocated to like the candidates with the returns of
+    /// their payoffs, after the candidates.
+    #[serde(default)]
+    pub call_options: Option<Vec<CallOption>>,
+
+    /// Pair trades, which are allocated to like the candidates with the returns of their spreads,
+    /// after the candidates and the call options. The allocation reports the exposures with the
+    /// legs of the pairs.
+    #[serde(default)]
+    pub pair_trades: Option<Vec<PairTrade>>,
+
     #[serde(default)]
     pub long_only: Option<bool>,
 
+    /// Tickers to which the long-only constraint applies. All candidates if not specified.
+    #[serde(default)]
+    pub long_only_tickers: Option<Vec<Ticker>>,
+
     #[serde(default)]
     pub max_permanent_loss_of_capital: Option<CapitalLoss>,
 
     #[serde(default)]
     pub max_individual_allocation: Option<f64>,
 
+    /// Tickers to which the maximum individual allocation constraint applies. All candidates if not
+    /// specified.
+    #[serde(default)]
+    pub max_individual_allocation_tickers: Option<Vec<Ticker>>,
+
+    /// Maximum allocation fractions of individual candidates by ticker, overriding the maximum
+    /// individual allocation, e.g. to limit illiquid candidates more tightly than liquid ones.
+    #[serde(default)]
+    pub ticker_max_individual_allocations: Option<HashMap<Ticker, f64>>,
+
+    /// Minimum allocation of each long position, e.g. 0.02 for not holding positions smaller than
+    /// 2% of capital, which can't be implemented in practice. Each candidate is either allocated
+    /// zero or at least the minimum, whichever adds more growth. Not limited if not specified.
+    #[serde(default)]
+    pub min_individual_allocation: Option<f64>,
+
     #[serde(default)]
     pub max_total_leverage_ratio: Option<f64>,
+
+    /// Tickers of the candidates without net exposure, e.g. a pair trade modeled as a company,
+    /// which don't count towards the maximum total leverage and the fully invested constraints.
+    /// The pair trades are added automatically.
+    #[serde(default)]
+    pub market_neutral_tickers: Option<Vec<Ticker>>,
+
+    /// Require that the fractions sum up to one, i.e. invest all capital without holding cash or
+    /// borrowing. False if not specified.
+    #[serde(default)]
+    pub fully_invested: Option<bool>,
+
+    /// Require that the fractions sum up to exactly this net exposure, e.g. 1.3 for a mandate that
+    /// must be 30% levered or 0.8 for one that must hold 20% in cash. The fully invested constraint
+    /// is the same as a fixed net exposure of one. Not fixed if not specified.
+    #[serde(default)]
+    pub fixed_net_exposure: Option<f64>,
+
+    /// Maximum number of candidates to allocate to, e.g. 5 for investing in at most five of the
+    /// candidates. The best choice of candidates is searched among all choices of this many
+    /// candidates, or only the candidates with the largest fractions in the allocation to all
+    /// candidates are kept if there are too many choices. Not limited if not specified.
+    #[serde(default)]
+    pub max_positions: Option<usize>,
+
+    /// Risk budgets limiting the probability weighted worst-case loss of single positions.
+    #[serde(default)]
+    pub max_position_downside: Option<PositionDownsideBudget>,
+
+    /// Share of the optimal (full Kelly) fractions to allocate, e.g. 0.5 for half Kelly, which
+    /// gives up some growth for a lower volatility and robustness to overestimated returns. The
+    /// allocation is analyzed with the scaled fractions. Full Kelly if not specified.
+    #[serde(default)]
+    pub kelly_fraction: Option<f64>,
+
+    /// Risk level the allocation is scaled to instead of a given Kelly fraction. The largest Kelly
+    /// fraction whose allocation doesn't exceed the level is searched for. Not used if not
+    /// specified.
+    #[serde(default)]
+    pub target_risk: Option<TargetRisk>,
+
+    /// Current weights of the holdings by ticker, which the allocation rebalances. The result then
+    /// includes the trades from the current weights to the allocation. All capital is in cash if
+    /// not specified.
+    #[serde(default)]
+    pub current_weights: Option<Vec<TickerAndFraction>>,
+
+    /// Penalty of 0.5 * turnover_penalty * |f - w|^2 subtracted from the expected logarithmic
+    /// growth for the fractions f and the current weights w, a smooth stand-in for transaction
+    /// costs that keeps the allocation closer to the current weights. The choices of candidates
+    /// from mutually exclusive groups and of the maximum number of positions are compared with the
+    /// penalty subtracted as well. No penalty if not specified.
+    #[serde(default)]
+    pub turnover_penalty: Option<f64>,
+
+    /// Maximum turnover, i.e. the sum of the absolute trades of the candidates in fractions of
+    /// capital. If the allocation exceeds it, the fractions only move partway from the current
+    /// weights to the allocation. Holdings of dropped candidates are sold regardless. Not limited
+    /// if not specified.
+    #[serde(default)]
+    pub max_turnover: Option<f64>,
+
+    /// Benchmark the allocation is compared with. Its return is reported in the analysis as the
+    /// probability of underperforming the benchmark, which can also be bounded.
+    #[serde(default)]
+    pub benchmark: Option<Benchmark>,
+
+    /// Unit of the monetary values of the candidates and the underlyings of the call options that
+    /// don't specify their own units. Ones if not specified.
+    #[serde(default)]
+    pub units: Option<MonetaryUnit>,
+
+    #[serde(default)]
+    pub min_scenario_probability: Option<f64>,
+
+    /// Groups of related candidates, e.g. two share classes of the same business or two competing
+    /// bets on the same thesis.
+    #[serde(default)]
+    pub candidate_groups: Option<Vec<CandidateGroup>>,
+
+    #[serde(default)]
+    pub solver_settings: Option<SolverSettings>,
+
+    /// Companies with more scenarios are compressed into this many scenarios matching the first few
+    /// moments of their return distribution, which keeps the number of outcomes manageable. No
+    /// compression if not specified.
+    #[serde(default)]
+    pub max_scenarios_per_company: Option<usize>,
+
+    /// Candidates with a lower expected return are dropped before the optimization.
+    #[serde(default)]
+    pub min_expected_return: Option<f64>,
+
+    /// Candidates with a higher total probability of losing value are dropped before the
+    /// optimization.
+    #[serde(default)]
+    pub max_downside_probability: Option<f64>,
+
+    /// Candidates with a lower ratio between the probability weighted upside and downside are
+    /// dropped before the optimization.
+    #[serde(default)]
+    pub min_upside_downside_ratio: Option<f64>,
+
+    /// A warning is emitted if a larger share of the expected logarithmic growth of the allocation
+    /// comes from a single joint outcome or a single scenario of a company, which usually
+    /// indicates overconfident inputs. Defaults to 0.5.
+    #[serde(default)]
+    pub max_growth_concentration: Option<f64>,
+
+    /// Conviction tiers of the candidates. If given, the allocation is also projected onto the
+    /// nearest weights within the caps of the tiers.
+    #[serde(default)]
+    pub conviction_tiers: Option<ConvictionTiers>,
+
+    /// Portfolio the allocation is compared with, e.g. the equal weights of the candidates or the
+    /// current weights of an account. If given, the result reports the growth, the tail risk and
+    /// the weights of the allocation relative to the benchmark portfolio.
+    #[serde(default)]
+    pub benchmark_portfolio: Option<BenchmarkPortfolio>,
+
+    /// Named portfolio-level events, whose probabilities and expected portfolio returns are
+    /// reported in the analysis of the allocation.
+    #[serde(default)]
+    pub events: Option<Vec<PortfolioEvent>>,
+
+    /// Expected annual inflation, e.g. 0.03. If given, the analysis of the allocation also reports
+    /// the expected return and growth in real terms.
+    #[serde(default)]
+    pub expected_inflation: Option<f64>,
+
+    /// Macro scenarios shared by the candidates, e.g. a recession and an expansion. Each scenario
+    /// of a candidate may be placed in one of them, which correlates the candidates: the outcomes
+    /// of the allocation and the analysis combine the scenarios of different candidates in the same
+    /// macro scenario only. Candidates are independent if not specified.
+    #[serde(default)]
+    pub macro_scenarios: Option<Vec<MacroScenario>>,
+
+    /// Constraints of the input that may be violated at a cost, e.g. a maximum individual
+    /// allocation expressing a preference rather than a mandate. Violations are penalized in the
+    /// objective with the weights of the constraints, also when comparing the choices of candidates
+    /// from mutually exclusive groups, and reported in the result. All constraints are hard if not
+    /// specified.
+    #[serde(default)]
+    pub soft_constraints: Option<Vec<SoftConstraint>>,
+
+    /// Annual return of the cash that isn't invested in the candidates, e.g. 0.04 for a money
+    /// market fund yielding 4%. Leverage is borrowed at the same rate unless the margin interest
+    /// rate is given. If not given, cash returns nothing.
+    #[serde(default)]
+    pub risk_free_rate: Option<f64>,
+
+    /// Annual interest rate of the capital borrowed for leverage, e.g. 0.06 for a margin loan at
+    /// 6%. The financing costs are subtracted from the outcomes of levered allocations. Must be at
+    /// least the risk-free rate, which it defaults to.
+    #[serde(default)]
+    pub margin_interest_rate: Option<f64>,
+}
+
+/// Constraint of the input that may be designated as soft.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SoftConstraintKind {
+    MaxIndividualAllocation,
+    MaxPositionDownside,
+    MaxTotalLeverageRatio,
+    MaxGroupAllocation,
+    MaxPermanentLossOfCapital,
+}
+
+impl SoftConstraintKind {
+    /// Returns the name of the constraint as given in the soft constraints of the input.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoftConstraintKind::MaxIndividualAllocation => "max_individual_allocation",
+            SoftConstraintKind::MaxPositionDownside => "max_position_downside",
+            SoftConstraintKind::MaxTotalLeverageRatio => "max_total_leverage_ratio",
+            SoftConstraintKind::MaxGroupAllocation => "max_group_allocation",
+            SoftConstraintKind::MaxPermanentLossOfCapital => "max_permanent_loss_of_capital",
+        }
+    }
+}
+
+/// Designates a constraint of the input as soft. Instead of being enforced, a violation g of the
+/// constraint subtracts 0.5 * weight * g^2 from the expected logarithmic growth, so larger weights
+/// allow smaller violations, e.g. exceeding a maximum allocation by 0.01 with a weight of 100
+/// costs 0.005 of expected logarithmic growth.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SoftConstraint {
+    pub constraint: SoftConstraintKind,
+    pub weight: f64,
+}
+
+/// Macro scenario shared by the candidates, whose probabilities must sum up to one.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct MacroScenario {
+    pub name: String,
+    pub probability: f64,
+}
+
+/// A group of related candidates. If the group is mutually exclusive, at most one of its
+/// candidates is allocated to. The total allocation to the group can be capped independently.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CandidateGroup {
+    pub name: String,
+    pub tickers: Vec<Ticker>,
+
+    /// Allocate to at most one of the candidates in the group. False if not specified.
+    #[serde(default)]
+    pub mutually_exclusive: Option<bool>,
+
+    /// Maximum allocation fraction for all candidates in the group together.
+    #[serde(default)]
+    pub max_allocation: Option<f64>,
+}
+
+/// Conviction tier of a candidate, where each tier caps the allocation fraction of its candidates.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ConvictionTier {
+    High,
+    Medium,
+    Low,
+}
+
+/// Risk budgets of single positions: the fraction of a candidate times the probability weighted
+/// return of its worst-case scenario must not be lower than minus its budget, e.g. a budget of 0.02
+/// allows a probability weighted loss of 2% of capital in the worst case of each position.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PositionDownsideBudget {
+    /// Budget of all candidates without a budget of their own. Only the candidates in the ticker
+    /// budgets are constrained if not specified.
+    #[serde(default)]
+    pub budget: Option<f64>,
+
+    /// Budgets of individual candidates by ticker, overriding the budget of all candidates.
+    #[serde(default)]
+    pub ticker_budgets: Option<HashMap<Ticker, f64>>,
+}
+
+impl PositionDownsideBudget {
+    /// Returns the budget of the candidate, if any.
+    pub fn budget_of(&self, ticker: &Ticker) -> Option<f64> {
+        self.ticker_budgets
+            .as_ref()
+            .and_then(|b| b.get(ticker).copied())
+            .or(self.budget)
+    }
+}
+
+/// Measure of the risk of an allocation that a target risk level refers to.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskMeasure {
+    /// Probability that the allocation loses more than the loss of the target.
+    ProbabilityOfLoss,
+    /// Expected loss in the worst outcomes whose probabilities sum up to one minus the confidence
+    /// level of the target.
+    ConditionalValueAtRisk,
+}
+
+/// Single risk level that the allocation is scaled to, e.g. a probability of 0.1 of losing more
+/// than 20% of capital, or a conditional value at risk of 0.15 at the 95% confidence level.
+/// Scaling the fractions down doesn't change the sign of the return, so a target probability of
+/// any loss can only be reached with the return of cash.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TargetRisk {
+    pub measure: RiskMeasure,
+    pub level: f64,
+
+    /// Loss that the probability of loss refers to, e.g. 0.2 for losing more than 20% of capital.
+    /// Any loss if not specified.
+    #[serde(default)]
+    pub loss: Option<f64>,
+
+    /// Confidence level of the conditional value at risk. 0.95 if not specified.
+    #[serde(default)]
+    pub confidence_level: Option<f64>,
+}
+
+/// Benchmark with the return over the horizon of the scenarios, e.g. 0.08 for an index expected to
+/// return 8%. With the maximum underperformance probability, the allocation is only accepted if the
+/// probability that its return falls below the benchmark return is at most the maximum.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Benchmark {
+    pub expected_return: f64,
+
+    /// Maximum acceptable probability of a portfolio return below the benchmark return. Not
+    /// bounded if not specified.
+    #[serde(default)]
+    pub max_underperformance_probability: Option<f64>,
+}
+
+/// Rule-based sizing where the candidates are assigned to conviction tiers with a cap on the
+/// fraction of each candidate in the tier. Candidates without a tier are capped like the
+/// candidates with low conviction.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ConvictionTiers {
+    pub tiers: HashMap<Ticker, ConvictionTier>,
+
+    /// Maximum fraction of a candidate with high conviction. Defaults to 0.25.
+    #[serde(default)]
+    pub high_cap: Option<f64>,
+
+    /// Maximum fraction of a candidate with medium conviction. Defaults to 0.15.
+    #[serde(default)]
+    pub medium_cap: Option<f64>,
+
+    /// Maximum fraction of a candidate with low conviction. Defaults to 0.05.
+    #[serde(default)]
+    pub low_cap: Option<f64>,
+}
+
+impl ConvictionTiers {
+    /// Returns the cap of the conviction tier.
+    pub fn tier_cap(&self, tier: ConvictionTier) -> f64 {
+        match tier {
+            ConvictionTier::High => self.high_cap.unwrap_or(0.25),
+            ConvictionTier::Medium => self.medium_cap.unwrap_or(0.15),
+            ConvictionTier::Low => self.low_cap.unwrap_or(0.05),
+        }
+    }
+
+    /// Returns the cap of the candidate with the given ticker.
+    pub fn cap(&self, ticker: &Ticker) -> f64 {
+        self.tier_cap(*self.tiers.get(ticker).unwrap_or(&ConvictionTier::Low))
+    }
+
+    /// Projects the allocation onto the nearest weights (in the Euclidean sense) within the caps,
+    /// which clips each fraction to the cap of its tier, keeping its sign.
+    pub fn project(&self, portfolio: &Portfolio) -> Portfolio {
+        Portfolio {
+            companies: portfolio
+                .companies
+                .iter()
+                .map(|pc| {
+                    let cap = self.cap(&pc.company.ticker);
+                    PortfolioCompany {
+                        company: pc.company.clone(),
+                        fraction: pc.fraction.clamp(-cap, cap),
+                    }
+                })
+                .collect(),
+            macro_scenarios: portfolio.macro_scenarios.clone(),
+        }
+    }
+}
+
+/// Portfolio of the candidates the allocation is compared with:
+/// - EqualWeight: The same fraction of all allocated candidates, fully invested.
+/// - Weights: The given fractions of the candidates, e.g. the current weights of an account.
+///   Candidates without a weight are not held.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchmarkPortfolio {
+    EqualWeight,
+    Weights(Vec<TickerAndFraction>),
+}
+
+impl BenchmarkPortfolio {
+    /// Returns the benchmark portfolio of the companies of the allocated portfolio.
+    pub fn portfolio(&self, allocated: &Portfolio) -> Portfolio {
+        let n_companies = allocated.companies.len() as f64;
+        Portfolio {
+            companies: allocated
+                .companies
+                .iter()
+                .map(|pc| PortfolioCompany {
+                    company: pc.company.clone(),
+                    fraction: match self {
+                        BenchmarkPortfolio::EqualWeight => 1.0 / n_companies,
+                        BenchmarkPortfolio::Weights(weights) => weights
+                            .iter()
+                            .find(|w| w.ticker == pc.company.ticker)
+                            .map_or(0.0, |w| w.fraction),
+                    },
+                })
+                .collect(),
+            macro_scenarios: allocated.macro_scenarios.clone(),
+        }
+    }
 }
 
 impl Validate for AllocationInput {
-    /// Validates the candidates and the constraints.
-    fn validate(&self) -> HashSet<ValidationResult> {
-        let mut validation_results: HashSet<ValidationResult> = HashSet::new();
+    /// Validates the candidates and the constraints. Call options are validated by their terms,
+    /// and together with the candidates after they are converted into companies.
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        if let Some(call_options) = &self.call_options {
+            call_options
+                .iter()
+                .for_each(|o| validation_results.extend(o.validate()));
+            validation_results.extend(self.with_call_options_as_candidates().validate());
+            return validation_results;
+        }
+
+        if let Some(pair_trades) = &self.pair_trades {
+            pair_trades
+                .iter()
+                .for_each(|p| validation_results.extend(p.validate()));
+            validation_results.extend(self.with_pair_trades_as_candidates().validate());
+            return validation_results;
+        }
 
         // Validate all candidates individually
         self.candidates
@@ -66,7 +519,35 @@ impl Validate for AllocationInput {
                 )
                 .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             }));
+        } else {
+            // Tickers that differ only by their formatting (e.g. ABC.TO and ABC CN) most likely
+            // refer to the same security
+            for group in tickers_with_same_key(&tickers, |t| normalize_ticker(t)) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "tickers-differ-only-by-formatting".to_string(),
+                    message: format!(
+                        "Tickers {} differ only by their formatting and most likely refer to the \
+                        same security. Keep only one of them.",
+                        group.join(", ")
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        // NaN passes the range checks of the settings since every comparison with it is false
+        for (name, value) in self.numeric_settings() {
+            if !value.is_finite() {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "non-finite-setting".to_string(),
+                    message: format!("Setting {name} must be a finite number, but is {value}."),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
         }
 
         // Validate maximum permanent loss of capital if specified
@@ -79,6 +560,28 @@ impl Validate for AllocationInput {
             );
         }
 
+        if let Some(max_positions) = self.max_positions {
+            if max_positions == 0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-number-of-positions-must-be-positive".to_string(),
+                    message: "Maximum number of positions must be at least one.".to_string(),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if max_positions >= self.candidates.len() {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-number-of-positions-exceeds-number-of-candidates".to_string(),
+                    message: format!(
+                        "Maximum number of positions of {max_positions} has no effect since there \
+                        are only {} candidates.",
+                        self.candidates.len()
+                    ),
+                    severity: Severity::WARNING,
+                    suggestion: None,
+                }));
+            }
+        }
+
         // If the maximum permanent loss of capital is set, we must have long-only constraint
         if self.max_permanent_loss_of_capital.is_some() && !self.long_only.unwrap_or(false) {
             validation_results.insert(ValidationResult::PROBLEM(Problem {
@@ -88,6 +591,7 @@ impl Validate for AllocationInput {
                     Either remove the permanent loss constraint or use the long-only constraint."
                     .to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             }));
         }
 
@@ -101,6 +605,41 @@ impl Validate for AllocationInput {
                     )
                     .to_string(),
                     severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(ticker_caps) = &self.ticker_max_individual_allocations {
+            validation_results.extend(self.validate_ticker_max_individual_allocations(ticker_caps));
+        }
+
+        if let Some(min_f) = self.min_individual_allocation {
+            if !(min_f > 0.0 && min_f <= 1.0) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "minimum-individual-allocation-must-be-between-zero-and-one".to_string(),
+                    message: format!(
+                        "Minimum individual allocation must be positive and at most 1. You \
+                        provided {min_f}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if self
+                .max_individual_allocation
+                .is_some_and(|max_f| max_f < min_f)
+            {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "minimum-individual-allocation-exceeds-maximum-individual-allocation"
+                        .to_string(),
+                    message: format!(
+                        "Minimum individual allocation of {min_f} exceeds the maximum individual \
+                        allocation of {}, so the candidates with the maximum can only be allocated \
+                        zero.",
+                        self.max_individual_allocation.unwrap()
+                    ),
+                    severity: Severity::WARNING,
+                    suggestion: None,
                 }));
             }
         }
@@ -115,55 +654,2205 @@ impl Validate for AllocationInput {
                     )
                     .to_string(),
                     severity: Severity::ERROR,
+                    suggestion: None,
                 }));
             }
         }
 
-        validation_results
-    }
-}
+        if let Some(inflation) = self.expected_inflation {
+            if inflation <= -1.0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "invalid-expected-inflation".to_string(),
+                    message: format!(
+                        "Expected inflation must be greater than -1. You provided {inflation}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::model::scenario::Scenario;
+        if let Some(risk_free_rate) = self.risk_free_rate {
+            if risk_free_rate <= -1.0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "invalid-risk-free-rate".to_string(),
+                    message: format!(
+                        "Risk-free rate must be greater than -1. You provided {risk_free_rate}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
 
-    #[test]
-    fn test_all_tickers_must_be_unique() {
-        let duplicate_tickers = AllocationInput {
-            candidates: (0..2)
-                .map(|_| Company {
-                    name: format!("A").to_string(),
-                    ticker: format!("A").to_string(),
-                    description: format!("A").to_string(),
-                    market_cap: 1.0,
-                    scenarios: vec![
-                        Scenario {
-                            thesis: "50% down with 50% probability".to_string(),
-                            intrinsic_value: 0.5,
-                            probability: 0.5,
-                        },
-                        Scenario {
-                            thesis: "100% up with 50% probability".to_string(),
-                            intrinsic_value: 2.0,
-                            probability: 0.5,
-                        },
-                    ],
-                })
-                .collect_vec(),
-            long_only: None,
-            max_permanent_loss_of_capital: None,
-            max_individual_allocation: None,
-            max_total_leverage_ratio: None,
-        };
+        if let Some(margin_interest_rate) = self.margin_interest_rate {
+            let risk_free_rate = self.risk_free_rate.unwrap_or(0.0);
+            if margin_interest_rate < risk_free_rate {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "margin-interest-rate-below-risk-free-rate".to_string(),
+                    message: format!(
+                        "Margin interest rate must be at least the risk-free rate of \
+                        {risk_free_rate}. You provided {margin_interest_rate}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
 
-        assert!(duplicate_tickers
-            .validate()
-            .contains(&ValidationResult::PROBLEM(Problem {
-                code: "all-tickers-must-be-unique".to_string(),
-                message: "All tickers must be unique. All tickers are: A, A. Check your input."
-                    .to_string(),
-                severity: Severity::ERROR,
-            })));
+        if let Some(kelly_fraction) = self.kelly_fraction {
+            if !(kelly_fraction > 0.0 && kelly_fraction <= 1.0) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "kelly-fraction-out-of-bounds".to_string(),
+                    message: format!(
+                        "Kelly fraction must be between 0 (exclusive) and 1 (inclusive). You \
+                        provided {kelly_fraction}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if kelly_fraction < 1.0 && self.fixed_net_exposure.is_some() {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fractional-kelly-cannot-have-fixed-net-exposure".to_string(),
+                    message: format!(
+                        "Kelly fraction of {kelly_fraction} scales the net exposure down, which \
+                        contradicts the fixed net exposure. Remove one of them."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if kelly_fraction < 1.0 && self.fully_invested.unwrap_or(false) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fractional-kelly-cannot-be-fully-invested".to_string(),
+                    message: format!(
+                        "Kelly fraction of {kelly_fraction} keeps part of the capital in cash, \
+                        which contradicts the fully invested constraint. Remove one of them."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(current_weights) = &self.current_weights {
+            validation_results.extend(self.validate_current_weights(current_weights));
+        }
+
+        for (name, value) in [
+            ("Turnover penalty", self.turnover_penalty),
+            ("Maximum turnover", self.max_turnover),
+        ] {
+            if let Some(value) = value.filter(|v| *v < 0.0) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: format!(
+                        "{}-cannot-be-negative",
+                        name.to_lowercase().replace(' ', "-")
+                    ),
+                    message: format!("{name} cannot be negative. You provided {value}."),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(target_risk) = &self.target_risk {
+            validation_results.extend(self.validate_target_risk(target_risk));
+        }
+
+        if let Some(max_p) = self
+            .benchmark
+            .as_ref()
+            .and_then(|b| b.max_underperformance_probability)
+        {
+            if !(0.0..=1.0).contains(&max_p) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-underperformance-probability-out-of-bounds".to_string(),
+                    message: format!(
+                        "Maximum probability of underperforming the benchmark must be between 0 \
+                         and 1. You provided {max_p}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        // Constraint subsets must only be given together with the constraint and must refer to
+        // existing candidates
+        validation_results.extend(self.validate_constraint_subset(
+            "long-only",
+            self.long_only.unwrap_or(false),
+            &self.long_only_tickers,
+        ));
+        validation_results.extend(self.validate_constraint_subset(
+            "maximum-individual-allocation",
+            self.max_individual_allocation.is_some(),
+            &self.max_individual_allocation_tickers,
+        ));
+
+        // The maximum permanent loss constraint requires all candidates to be long-only
+        if self.max_permanent_loss_of_capital.is_some()
+            && (!self.long_only.unwrap_or(false) || self.long_only_tickers.is_some())
+        {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "maximum-permanent-loss-constraint-requires-long-only-for-all-candidates"
+                    .to_string(),
+                message: "Maximum permanent loss constraint works only when the long-only \
+                    constraint applies to all candidates. Either remove the permanent loss \
+                    constraint or set long_only without long-only tickers."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        if let Some(min_p) = self.min_scenario_probability {
+            if !(0.0..1.0).contains(&min_p) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "minimum-scenario-probability-out-of-bounds".to_string(),
+                    message: format!(
+                        "Minimum scenario probability must be between 0 (inclusive) and 1 \
+                        (exclusive). You provided {min_p}."
+                    )
+                    .to_string(),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(max_scenarios) = self.max_scenarios_per_company {
+            if max_scenarios < 2 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-scenarios-per-company-smaller-than-two".to_string(),
+                    message: format!(
+                        "Maximum number of scenarios per company must be at least 2, such that \
+                        each company keeps a downside and an upside scenario. You provided \
+                        {max_scenarios}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(max_p) = self.max_downside_probability {
+            if !(0.0..=1.0).contains(&max_p) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-downside-probability-out-of-bounds".to_string(),
+                    message: format!(
+                        "Maximum downside probability must be between 0 and 1. You provided \
+                        {max_p}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(min_ratio) = self.min_upside_downside_ratio {
+            if min_ratio < 0.0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "minimum-upside-downside-ratio-cannot-be-negative".to_string(),
+                    message: format!(
+                        "Minimum upside/downside ratio cannot be negative. You provided \
+                        {min_ratio}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(max_share) = self.max_growth_concentration {
+            if max_share <= 0.0 || max_share > 1.0 {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-growth-concentration-out-of-bounds".to_string(),
+                    message: format!(
+                        "Maximum growth concentration must be between 0 (exclusive) and 1 \
+                        (inclusive). You provided {max_share}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(conviction_tiers) = &self.conviction_tiers {
+            validation_results.extend(self.validate_conviction_tiers(conviction_tiers));
+        }
+
+        if let Some(BenchmarkPortfolio::Weights(weights)) = &self.benchmark_portfolio {
+            validation_results.extend(self.validate_benchmark_weights(weights));
+        }
+
+        if let Some(position_downside) = &self.max_position_downside {
+            validation_results.extend(self.validate_position_downside_budget(position_downside));
+        }
+
+        for event in self.events.iter().flatten() {
+            validation_results.extend(event.validate_against(&self.candidates));
+        }
+
+        if let Some(groups) = &self.candidate_groups {
+            validation_results.extend(self.validate_candidate_groups(groups));
+        }
+
+        validation_results.extend(self.validate_macro_scenarios());
+
+        if let Some(soft_constraints) = &self.soft_constraints {
+            validation_results.extend(self.validate_soft_constraints(soft_constraints));
+        }
+
+        validation_results.extend(self.validate_constraint_consistency());
+
+        if let Some(solver_settings) = &self.solver_settings {
+            validation_results.extend(solver_settings.validate());
+        }
+
+        validation_results
+    }
+}
+
+impl AllocationInput {
+    /// Returns a copy of this input where the monetary values of the candidates and the underlyings
+    /// of the call options are given in ones, together with the warnings about intrinsic values
+    /// that seem to be given in different units than the market caps.
+    pub fn with_normalized_units(&self) -> (AllocationInput, Vec<ValidationResult>) {
+        let mut validation_results: Vec<ValidationResult> = vec![];
+        let mut normalize = |company: &Company| {
+            let (normalized, validation) = company.with_normalized_units(self.units);
+            if validation != ValidationResult::OK {
+                validation_results.push(validation);
+            }
+            normalized
+        };
+
+        let candidates = self.candidates.iter().map(&mut normalize).collect();
+        let call_options = self.call_options.as_ref().map(|options| {
+            options
+                .iter()
+                .map(|o| CallOption {
+                    underlying: normalize(&o.underlying),
+                    ..o.clone()
+                })
+                .collect()
+        });
+
+        (
+            AllocationInput {
+                candidates,
+                call_options,
+                units: None,
+                ..self.clone()
+            },
+            validation_results,
+        )
+    }
+
+    /// Returns a copy of this input where the call options are converted into companies and
+    /// appended to the candidates.
+    pub fn with_call_options_as_candidates(&self) -> AllocationInput {
+        let mut candidates = self.candidates.clone();
+        candidates.extend(self.call_options.iter().flatten().map(|o| o.to_company()));
+
+        AllocationInput {
+            candidates,
+            call_options: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this input where the pair trades are converted into companies and appended
+    /// to the candidates, and their tickers are added to the market neutral tickers.
+    pub fn with_pair_trades_as_candidates(&self) -> AllocationInput {
+        let Some(pair_trades) = &self.pair_trades else {
+            return self.clone();
+        };
+
+        let mut candidates = self.candidates.clone();
+        candidates.extend(pair_trades.iter().map(|p| p.to_company()));
+        let mut market_neutral_tickers = self.market_neutral_tickers.clone().unwrap_or_default();
+        market_neutral_tickers.extend(pair_trades.iter().map(|p| p.ticker.clone()));
+
+        AllocationInput {
+            candidates,
+            pair_trades: None,
+            market_neutral_tickers: Some(market_neutral_tickers),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the maximum allocation fraction of the candidate, if any. The cap given for its
+    /// ticker takes precedence over the maximum individual allocation.
+    pub fn max_individual_allocation_of(&self, ticker: &Ticker) -> Option<f64> {
+        self.ticker_max_individual_allocations
+            .as_ref()
+            .and_then(|caps| caps.get(ticker).copied())
+            .or(self.max_individual_allocation.filter(|_| {
+                self.max_individual_allocation_tickers
+                    .as_ref()
+                    .map_or(true, |tickers| tickers.contains(ticker))
+            }))
+    }
+
+    /// Returns the current weight of the candidate, which is zero if it isn't held.
+    pub fn current_weight_of(&self, ticker: &Ticker) -> f64 {
+        self.current_weights
+            .iter()
+            .flatten()
+            .filter(|w| &w.ticker == ticker)
+            .map(|w| w.fraction)
+            .sum()
+    }
+
+    /// Screens a candidate against the optional pre-optimization thresholds (minimum expected
+    /// return, maximum downside probability and minimum upside/downside ratio). Returns a warning
+    /// describing the first threshold the candidate fails, which means it should be dropped.
+    pub fn screen(&self, candidate: &Company) -> ValidationResult {
+        let screen_problem = |code: &str, message: String| {
+            ValidationResult::PROBLEM(Problem {
+                code: code.to_string(),
+                message,
+                severity: Severity::WARNING,
+                suggestion: None,
+            })
+        };
+
+        if let Some(min_return) = self.min_expected_return {
+            let expected_return = candidate.expected_return();
+            if expected_return < min_return {
+                return screen_problem(
+                    "expected-return-below-screening-threshold",
+                    format!(
+                        "Expected return of {:.1}% for {} is below the minimum of {:.1}%.",
+                        100.0 * expected_return,
+                        candidate.ticker,
+                        100.0 * min_return
+                    ),
+                );
+            }
+        }
+
+        if let Some(max_p) = self.max_downside_probability {
+            let downside_probability = candidate.downside_probability();
+            if downside_probability > max_p + TOLERANCE {
+                return screen_problem(
+                    "downside-probability-above-screening-threshold",
+                    format!(
+                        "Downside probability of {:.1}% for {} is above the maximum of {:.1}%.",
+                        100.0 * downside_probability,
+                        candidate.ticker,
+                        100.0 * max_p
+                    ),
+                );
+            }
+        }
+
+        if let Some(min_ratio) = self.min_upside_downside_ratio {
+            let ratio = candidate.upside_downside_ratio();
+            if ratio < min_ratio {
+                return screen_problem(
+                    "upside-downside-ratio-below-screening-threshold",
+                    format!(
+                        "Upside/downside ratio of {ratio:.2} for {} is below the minimum of \
+                        {min_ratio:.2}.",
+                        candidate.ticker
+                    ),
+                );
+            }
+        }
+
+        ValidationResult::OK
+    }
+
+    /// Validates the combination of the constraints before solving, since each constraint doubles
+    /// the number of systems to solve: returns an error if the constraints leave nothing to
+    /// allocate, and warnings for constraints that can never be active given the other constraints.
+    /// Constraints with invalid values are left to the other validations.
+    fn validate_constraint_consistency(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let is_long_only = |ticker: &Ticker| {
+            self.long_only.unwrap_or(false)
+                && self
+                    .long_only_tickers
+                    .as_ref()
+                    .map_or(true, |tickers| tickers.contains(ticker))
+        };
+        let individual_cap = |ticker: &Ticker| self.max_individual_allocation_of(ticker);
+        let all_long_only = self.candidates.iter().all(|c| is_long_only(&c.ticker));
+        let max_exposure = self.max_total_leverage_ratio.map(|lr| 1.0 + lr);
+
+        if !self.candidates.is_empty()
+            && self
+                .candidates
+                .iter()
+                .all(|c| individual_cap(&c.ticker).is_some_and(|cap| cap == 0.0))
+        {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "constraints-leave-nothing-to-allocate".to_string(),
+                message: "Maximum individual allocation of 0 applies to all candidates, so \
+                    nothing can be allocated. Check your input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        let redundant_constraint = |constraint: String, reason: String| {
+            ValidationResult::PROBLEM(Problem {
+                code: "redundant-constraint".to_string(),
+                message: format!(
+                    "{constraint} can never be active because {reason}. Removing it at least \
+                    halves the number of systems to solve."
+                ),
+                severity: Severity::WARNING,
+                suggestion: None,
+            })
+        };
+
+        // Long-only fractions are bounded by the maximum total exposure, so a larger cap of an
+        // individual or a group allocation never binds
+        if let (Some(cap), Some(max_exposure)) = (self.max_individual_allocation, max_exposure) {
+            if all_long_only && cap >= max_exposure {
+                validation_results.insert(redundant_constraint(
+                    format!("Maximum individual allocation of {cap}"),
+                    format!(
+                        "the long-only allocation is limited to {max_exposure} by the maximum \
+                        total leverage ratio"
+                    ),
+                ));
+            }
+        }
+
+        for group in self.candidate_groups.iter().flatten() {
+            let Some(group_cap) = group.max_allocation else {
+                continue;
+            };
+            let member_caps = group
+                .tickers
+                .iter()
+                .map(|t| individual_cap(t).filter(|_| is_long_only(t)))
+                .collect::<Option<Vec<f64>>>();
+            let members_bound = member_caps.map(|caps| {
+                if group.mutually_exclusive.unwrap_or(false) {
+                    caps.into_iter().fold(0.0, f64::max)
+                } else {
+                    caps.into_iter().sum()
+                }
+            });
+
+            if members_bound.is_some_and(|bound| group_cap >= bound) {
+                validation_results.insert(redundant_constraint(
+                    format!("Maximum allocation of {group_cap} for group {}", group.name),
+                    "the maximum individual allocations of its long-only candidates limit the \
+                    group to at most this value"
+                        .to_string(),
+                ));
+            } else if max_exposure.is_some_and(|max_exposure| {
+                group.tickers.iter().all(is_long_only) && group_cap >= max_exposure
+            }) {
+                validation_results.insert(redundant_constraint(
+                    format!("Maximum allocation of {group_cap} for group {}", group.name),
+                    "the maximum total leverage ratio limits its long-only candidates to at most \
+                    this value"
+                        .to_string(),
+                ));
+            }
+        }
+
+        // Leverage never binds if the individual caps of the long-only candidates sum up to at
+        // most the maximum total exposure
+        if let Some(max_exposure) = max_exposure {
+            let max_long_exposure = self
+                .candidates
+                .iter()
+                .map(|c| individual_cap(&c.ticker).filter(|_| is_long_only(&c.ticker)))
+                .sum::<Option<f64>>();
+            if max_long_exposure.is_some_and(|e| !self.candidates.is_empty() && e <= max_exposure) {
+                validation_results.insert(redundant_constraint(
+                    format!("Maximum total leverage ratio of {}", max_exposure - 1.0),
+                    "the maximum individual allocations of the long-only candidates sum up to at \
+                    most the allowed exposure"
+                        .to_string(),
+                ));
+            }
+        }
+
+        // All capital can't be invested if the individual caps of the long-only candidates sum up
+        // to less than one
+        if self.fully_invested.unwrap_or(false) {
+            let max_long_exposure = self
+                .candidates
+                .iter()
+                .map(|c| individual_cap(&c.ticker).filter(|_| is_long_only(&c.ticker)))
+                .sum::<Option<f64>>();
+            if let Some(max_long_exposure) = max_long_exposure.filter(|e| *e < 1.0 - TOLERANCE) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fully-invested-constraint-cannot-be-satisfied".to_string(),
+                    message: format!(
+                        "The maximum individual allocations of the long-only candidates sum up to \
+                        {max_long_exposure}, so not all capital can be invested. Either remove the \
+                        fully invested constraint or raise the maximum individual allocation."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(net_exposure) = self.fixed_net_exposure {
+            let max_long_exposure = self
+                .candidates
+                .iter()
+                .map(|c| individual_cap(&c.ticker).filter(|_| is_long_only(&c.ticker)))
+                .sum::<Option<f64>>();
+            if !net_exposure.is_finite() {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fixed-net-exposure-must-be-finite".to_string(),
+                    message: format!(
+                        "Fixed net exposure must be a finite number. You provided {net_exposure}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if self.fully_invested.unwrap_or(false) && (net_exposure - 1.0).abs() > TOLERANCE
+            {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fixed-net-exposure-contradicts-fully-invested-constraint".to_string(),
+                    message: format!(
+                        "Fixed net exposure of {net_exposure} contradicts the fully invested \
+                        constraint, which fixes the net exposure to one. Remove one of them."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if let Some(max_exposure) = self
+                .max_total_leverage_ratio
+                .map(|r| 1.0 + r)
+                .filter(|e| net_exposure > e + TOLERANCE)
+            {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fixed-net-exposure-exceeds-maximum-leverage".to_string(),
+                    message: format!(
+                        "Fixed net exposure of {net_exposure} exceeds the exposure of \
+                        {max_exposure} allowed by the maximum total leverage ratio. Lower the \
+                        fixed net exposure or raise the maximum total leverage ratio."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            } else if let Some(max_long_exposure) =
+                max_long_exposure.filter(|e| *e < net_exposure - TOLERANCE)
+            {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "fixed-net-exposure-cannot-be-satisfied".to_string(),
+                    message: format!(
+                        "The maximum individual allocations of the long-only candidates sum up to \
+                        {max_long_exposure}, which is less than the fixed net exposure of \
+                        {net_exposure}. Either lower the fixed net exposure or raise the maximum \
+                        individual allocation."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        validation_results
+    }
+
+    /// Validates that a subset of tickers for a constraint is only given when the constraint itself
+    /// is specified, and that all the tickers in the subset are found among the candidates.
+    fn validate_constraint_subset(
+        &self,
+        constraint_name: &str,
+        has_constraint: bool,
+        tickers: &Option<Vec<Ticker>>,
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+        let tickers = match tickers {
+            Some(t) => t,
+            None => return validation_results,
+        };
+
+        if !has_constraint {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: format!("{constraint_name}-tickers-given-without-the-constraint"),
+                message: format!(
+                    "Tickers for the {constraint_name} constraint are given, but the constraint \
+                    itself is not specified. Either specify the constraint or remove the tickers."
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        let unknown_tickers = tickers
+            .iter()
+            .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: format!("unknown-tickers-for-{constraint_name}-constraint"),
+                message: format!(
+                    "Tickers {} for the {constraint_name} constraint are not found among the \
+                    candidates. Check your input.",
+                    unknown_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+
+    /// Validates that the weights of the benchmark portfolio refer to existing candidates.
+    fn validate_benchmark_weights(
+        &self,
+        weights: &[TickerAndFraction],
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let unknown_tickers = weights
+            .iter()
+            .map(|w| &w.ticker)
+            .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+            .sorted()
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-benchmark-portfolio".to_string(),
+                message: format!(
+                    "Tickers {} in the benchmark portfolio are not found among the candidates. \
+                    Check your input.",
+                    unknown_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+
+    /// Validates that the current weights refer to existing candidates, each at most once.
+    fn validate_current_weights(
+        &self,
+        current_weights: &[TickerAndFraction],
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let unknown_tickers = current_weights
+            .iter()
+            .map(|w| &w.ticker)
+            .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+            .sorted()
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-current-weights".to_string(),
+                message: format!(
+                    "Tickers {} in the current weights are not found among the candidates. Add \
+                    the holdings to the candidates, e.g. with a scenario for selling them.",
+                    unknown_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        let duplicate_tickers = current_weights
+            .iter()
+            .map(|w| &w.ticker)
+            .duplicates()
+            .sorted()
+            .collect_vec();
+        if !duplicate_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "duplicate-tickers-in-current-weights".to_string(),
+                message: format!(
+                    "Tickers {} appear more than once in the current weights. Check your input.",
+                    duplicate_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+
+    /// Validates that the conviction tiers refer to existing candidates and that the tier caps are
+    /// not negative.
+    fn validate_conviction_tiers(
+        &self,
+        conviction_tiers: &ConvictionTiers,
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let unknown_tickers = conviction_tiers
+            .tiers
+            .keys()
+            .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+            .sorted()
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-conviction-tiers".to_string(),
+                message: format!(
+                    "Tickers {} in the conviction tiers are not found among the candidates. Check \
+                    your input.",
+                    unknown_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        [
+            ConvictionTier::High,
+            ConvictionTier::Medium,
+            ConvictionTier::Low,
+        ]
+        .into_iter()
+        .filter(|tier| conviction_tiers.tier_cap(*tier) < 0.0)
+        .for_each(|tier| {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "conviction-tier-cap-cannot-be-negative".to_string(),
+                message: format!(
+                    "Cap of the {tier:?} conviction tier cannot be negative. You provided {}.",
+                    conviction_tiers.tier_cap(tier)
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        });
+
+        validation_results
+    }
+
+    /// Validates that the level of the target risk is positive, that its loss and confidence level
+    /// are within bounds and that it doesn't contradict a given Kelly fraction.
+    fn validate_target_risk(&self, target_risk: &TargetRisk) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+        let problem = |code: &str, message: String| {
+            ValidationResult::PROBLEM(Problem {
+                code: code.to_string(),
+                message,
+                severity: Severity::ERROR,
+                suggestion: None,
+            })
+        };
+
+        let level = target_risk.level;
+        let max_level = match target_risk.measure {
+            RiskMeasure::ProbabilityOfLoss => 1.0,
+            RiskMeasure::ConditionalValueAtRisk => f64::INFINITY,
+        };
+        if !(level > 0.0 && level <= max_level) {
+            validation_results.insert(problem(
+                "target-risk-level-out-of-bounds",
+                format!(
+                    "Target risk level must be positive, and at most 1 for a probability. You \
+                    provided {level}."
+                ),
+            ));
+        }
+
+        if let Some(loss) = target_risk.loss {
+            if target_risk.measure != RiskMeasure::ProbabilityOfLoss {
+                validation_results.insert(problem(
+                    "target-risk-loss-without-probability-of-loss",
+                    "Loss of the target risk only applies to the probability of loss. Remove it."
+                        .to_string(),
+                ));
+            } else if !(0.0..=1.0).contains(&loss) {
+                validation_results.insert(problem(
+                    "target-risk-loss-out-of-bounds",
+                    format!(
+                        "Loss of the target risk must be between 0 and 1. You provided {loss}."
+                    ),
+                ));
+            }
+        }
+
+        if let Some(confidence) = target_risk.confidence_level {
+            if target_risk.measure != RiskMeasure::ConditionalValueAtRisk {
+                validation_results.insert(problem(
+                    "target-risk-confidence-level-without-conditional-value-at-risk",
+                    "Confidence level of the target risk only applies to the conditional value at \
+                    risk. Remove it."
+                        .to_string(),
+                ));
+            } else if !(confidence > 0.0 && confidence < 1.0) {
+                validation_results.insert(problem(
+                    "target-risk-confidence-level-out-of-bounds",
+                    format!(
+                        "Confidence level of the target risk must be between 0 and 1 (both \
+                        exclusive). You provided {confidence}."
+                    ),
+                ));
+            }
+        }
+
+        if self.kelly_fraction.is_some() {
+            validation_results.insert(problem(
+                "target-risk-with-kelly-fraction",
+                "Target risk determines the Kelly fraction, which contradicts the given Kelly \
+                fraction. Remove one of them."
+                    .to_string(),
+            ));
+        } else if self.fully_invested.unwrap_or(false) || self.fixed_net_exposure.is_some() {
+            validation_results.insert(problem(
+                "target-risk-with-fixed-net-exposure",
+                "Target risk scales the net exposure down, which contradicts the fully invested \
+                or the fixed net exposure constraint. Remove one of them."
+                    .to_string(),
+            ));
+        }
+
+        validation_results
+    }
+
+    /// Validates that the maximum allocations by ticker refer to existing candidates and that they
+    /// are not negative.
+    fn validate_ticker_max_individual_allocations(
+        &self,
+        ticker_caps: &HashMap<Ticker, f64>,
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        let unknown_tickers = ticker_caps
+            .keys()
+            .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+            .sorted()
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-ticker-max-individual-allocations".to_string(),
+                message: format!(
+                    "Tickers {} in the maximum individual allocations by ticker are not found \
+                    among the candidates. Check your input.",
+                    unknown_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        ticker_caps
+            .iter()
+            .filter(|(_, max_f)| **max_f < 0.0)
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .for_each(|(ticker, max_f)| {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-individual-allocation-cannot-be-negative".to_string(),
+                    message: format!(
+                        "Maximum individual allocation of {ticker} cannot be negative. You \
+                        provided {max_f}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            });
+
+        validation_results
+    }
+
+    /// Validates that the budgets of the position downside refer to existing candidates and that
+    /// they are positive.
+    fn validate_position_downside_budget(
+        &self,
+        position_downside: &PositionDownsideBudget,
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+        let ticker_budgets = position_downside.ticker_budgets.clone().unwrap_or_default();
+
+        let unknown_tickers = ticker_budgets
+            .keys()
+            .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+            .sorted()
+            .collect_vec();
+        if !unknown_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-position-downside-budgets".to_string(),
+                message: format!(
+                    "Tickers {} in the position downside budgets are not found among the \
+                    candidates. Check your input.",
+                    unknown_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        position_downside
+            .budget
+            .iter()
+            .chain(ticker_budgets.values())
+            .filter(|b| **b <= 0.0)
+            .sorted_by(|a, b| a.total_cmp(b))
+            .for_each(|budget| {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "position-downside-budget-must-be-positive".to_string(),
+                    message: format!(
+                        "Position downside budget must be positive. You provided {budget}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            });
+
+        validation_results
+    }
+
+    /// Validates that the candidate groups refer to existing candidates, that the group caps are
+    /// not negative and that a candidate belongs to at most one mutually exclusive group.
+    fn validate_candidate_groups(&self, groups: &[CandidateGroup]) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        groups.iter().for_each(|g| {
+            let unknown_tickers = g
+                .tickers
+                .iter()
+                .filter(|t| !self.candidates.iter().any(|c| &c.ticker == *t))
+                .collect_vec();
+            if !unknown_tickers.is_empty() {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "unknown-tickers-in-candidate-group".to_string(),
+                    message: format!(
+                        "Tickers {} in group {} are not found among the candidates. Check your \
+                        input.",
+                        unknown_tickers.iter().join(", "),
+                        g.name
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+
+            if let Some(max_f) = g.max_allocation {
+                if max_f < 0.0 {
+                    validation_results.insert(ValidationResult::PROBLEM(Problem {
+                        code: "maximum-group-allocation-cannot-be-negative".to_string(),
+                        message: format!(
+                            "Maximum allocation for group {} cannot be negative. You provided \
+                            {max_f}.",
+                            g.name
+                        ),
+                        severity: Severity::ERROR,
+                        suggestion: None,
+                    }));
+                }
+            }
+        });
+
+        let exclusive_tickers = groups
+            .iter()
+            .filter(|g| g.mutually_exclusive.unwrap_or(false))
+            .flat_map(|g| g.tickers.iter())
+            .collect_vec();
+        let duplicate_tickers = exclusive_tickers.iter().duplicates().collect_vec();
+        if !duplicate_tickers.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "ticker-in-multiple-mutually-exclusive-groups".to_string(),
+                message: format!(
+                    "Tickers {} belong to more than one mutually exclusive group. A candidate can \
+                    belong to at most one mutually exclusive group.",
+                    duplicate_tickers.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+
+    /// Validates that the soft constraints are given in the input, at most once each, and that
+    /// their weights are positive.
+    fn validate_soft_constraints(
+        &self,
+        soft_constraints: &[SoftConstraint],
+    ) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        for soft_constraint in soft_constraints {
+            let name = soft_constraint.constraint.name();
+            let is_given = match soft_constraint.constraint {
+                SoftConstraintKind::MaxIndividualAllocation => {
+                    self.max_individual_allocation.is_some()
+                        || self.ticker_max_individual_allocations.is_some()
+                }
+                SoftConstraintKind::MaxPositionDownside => self.max_position_downside.is_some(),
+                SoftConstraintKind::MaxTotalLeverageRatio => {
+                    self.max_total_leverage_ratio.is_some()
+                }
+                SoftConstraintKind::MaxGroupAllocation => self
+                    .candidate_groups
+                    .iter()
+                    .flatten()
+                    .any(|g| g.max_allocation.is_some()),
+                SoftConstraintKind::MaxPermanentLossOfCapital => {
+                    self.max_permanent_loss_of_capital.is_some()
+                }
+            };
+            if !is_given {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "soft-constraint-not-given".to_string(),
+                    message: format!(
+                        "Constraint {name} is designated as soft, but isn't given in the input."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+
+            if !(soft_constraint.weight > 0.0 && soft_constraint.weight.is_finite()) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "soft-constraint-weight-must-be-positive".to_string(),
+                    message: format!(
+                        "Weight of soft constraint {name} must be a positive finite number. You \
+                        provided {}.",
+                        soft_constraint.weight
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        let duplicates = soft_constraints
+            .iter()
+            .map(|c| c.constraint.name())
+            .duplicates()
+            .collect_vec();
+        if !duplicates.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "duplicate-soft-constraints".to_string(),
+                message: format!(
+                    "Constraints {} are designated as soft more than once. Give a single weight \
+                    for each of them.",
+                    duplicates.join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        validation_results
+    }
+
+    /// Validates the macro scenarios against the candidates, see [validate_macro_scenarios].
+    fn validate_macro_scenarios(&self) -> IndexSet<ValidationResult> {
+        validate_macro_scenarios(self.macro_scenarios.as_deref(), &self.candidates)
+    }
+
+    /// Returns all choices of candidates to allocate to, such that each choice contains exactly
+    /// one candidate from each mutually exclusive group and all candidates not in such a group.
+    /// Candidates that are not among the given candidates (e.g. because they were filtered out) are
+    /// ignored. Returns a single choice with all candidates if there are no exclusive groups.
+    pub fn exclusive_group_choices(&self, candidates: &[Company]) -> Vec<Vec<Company>> {
+        let exclusive_groups = self
+            .candidate_groups
+            .iter()
+            .flatten()
+            .filter(|g| g.mutually_exclusive.unwrap_or(false))
+            .map(|g| {
+                candidates
+                    .iter()
+                    .filter(|c| g.tickers.contains(&c.ticker))
+                    .collect_vec()
+            })
+            .filter(|group_candidates| !group_candidates.is_empty())
+            .collect_vec();
+
+        // Note that the cartesian product of no groups is empty, hence the special case
+        if exclusive_groups.is_empty() {
+            return vec![candidates.to_vec()];
+        }
+
+        exclusive_groups
+            .iter()
+            .cloned()
+            .multi_cartesian_product()
+            .map(|chosen| {
+                candidates
+                    .iter()
+                    .filter(|c| {
+                        chosen.contains(c) || !exclusive_groups.iter().flatten().any(|gc| gc == c)
+                    })
+                    .cloned()
+                    .collect_vec()
+            })
+            .collect_vec()
+    }
+
+    /// Returns the numeric settings of the input that are given, by the names of their fields.
+    fn numeric_settings(&self) -> Vec<(String, f64)> {
+        let named = |name: &str, value: Option<f64>| value.map(|v| (name.to_string(), v));
+        [
+            named("max_individual_allocation", self.max_individual_allocation),
+            named("min_individual_allocation", self.min_individual_allocation),
+            named("max_total_leverage_ratio", self.max_total_leverage_ratio),
+            named("kelly_fraction", self.kelly_fraction),
+            named("turnover_penalty", self.turnover_penalty),
+            named("max_turnover", self.max_turnover),
+            named(
+                "target_risk.level",
+                self.target_risk.as_ref().map(|t| t.level),
+            ),
+            named(
+                "target_risk.loss",
+                self.target_risk.as_ref().and_then(|t| t.loss),
+            ),
+            named(
+                "target_risk.confidence_level",
+                self.target_risk.as_ref().and_then(|t| t.confidence_level),
+            ),
+            named("min_scenario_probability", self.min_scenario_probability),
+            named("min_expected_return", self.min_expected_return),
+            named("max_downside_probability", self.max_downside_probability),
+            named("min_upside_downside_ratio", self.min_upside_downside_ratio),
+            named("max_growth_concentration", self.max_growth_concentration),
+            named("expected_inflation", self.expected_inflation),
+            named("risk_free_rate", self.risk_free_rate),
+            named("margin_interest_rate", self.margin_interest_rate),
+            named(
+                "max_permanent_loss_of_capital.fraction_of_capital",
+                self.max_permanent_loss_of_capital
+                    .as_ref()
+                    .map(|lc| lc.fraction_of_capital),
+            ),
+            named(
+                "max_permanent_loss_of_capital.probability_of_loss",
+                self.max_permanent_loss_of_capital
+                    .as_ref()
+                    .map(|lc| lc.probability_of_loss),
+            ),
+            named(
+                "benchmark.expected_return",
+                self.benchmark.as_ref().map(|b| b.expected_return),
+            ),
+            named(
+                "solver_settings.regularization",
+                self.solver_settings.as_ref().and_then(|s| s.regularization),
+            ),
+            named(
+                "max_position_downside.budget",
+                self.max_position_downside.as_ref().and_then(|p| p.budget),
+            ),
+            named(
+                "conviction_tiers.high_cap",
+                self.conviction_tiers.as_ref().and_then(|t| t.high_cap),
+            ),
+            named(
+                "conviction_tiers.medium_cap",
+                self.conviction_tiers.as_ref().and_then(|t| t.medium_cap),
+            ),
+            named(
+                "conviction_tiers.low_cap",
+                self.conviction_tiers.as_ref().and_then(|t| t.low_cap),
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(
+            self.ticker_max_individual_allocations
+                .iter()
+                .flatten()
+                .sorted_by(|a, b| a.0.cmp(b.0))
+                .map(|(t, m)| (format!("max_individual_allocation of {t}"), *m)),
+        )
+        .chain(
+            self.max_position_downside
+                .iter()
+                .flat_map(|p| p.ticker_budgets.iter().flatten())
+                .sorted_by(|a, b| a.0.cmp(b.0))
+                .map(|(t, b)| (format!("position downside budget of {t}"), *b)),
+        )
+        .chain(self.candidate_groups.iter().flatten().filter_map(|g| {
+            g.max_allocation
+                .map(|m| (format!("max_allocation of group {}", g.name), m))
+        }))
+        .chain(
+            self.current_weights
+                .iter()
+                .flatten()
+                .map(|w| (format!("current weight of {}", w.ticker), w.fraction)),
+        )
+        .chain(self.macro_scenarios.iter().flatten().map(|m| {
+            (
+                format!("probability of macro scenario {}", m.name),
+                m.probability,
+            )
+        }))
+        .collect()
+    }
+}
+
+/// Validates that the probabilities of the macro scenarios sum up to one, that the scenarios of the
+/// companies refer to known macro scenarios, and that a company placing its scenarios in macro
+/// scenarios places all of them, with the probabilities in each macro scenario summing up to the
+/// probability of the latter. Otherwise, the scenarios outside of macro scenarios would be left out
+/// of the outcomes.
+fn validate_macro_scenarios(
+    macro_scenarios: Option<&[MacroScenario]>,
+    companies: &[Company],
+) -> IndexSet<ValidationResult> {
+    let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+    let macro_scenarios = macro_scenarios.unwrap_or_default();
+
+    if !macro_scenarios.is_empty() {
+        let sum: f64 = macro_scenarios.iter().map(|m| m.probability).sum();
+        if (sum - 1.0).abs() > TOLERANCE {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "macro-scenario-probabilities-do-not-sum-up-to-one".to_string(),
+                message: format!(
+                    "Probabilities of all macro scenarios do not sum up to 1. Sum = {sum}."
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+    }
+
+    for c in companies {
+        let unknown = c
+            .scenarios
+            .iter()
+            .filter_map(|s| s.macro_scenario.as_ref())
+            .filter(|name| !macro_scenarios.iter().any(|m| &m.name == *name))
+            .unique()
+            .collect_vec();
+        if !unknown.is_empty() {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "unknown-macro-scenario".to_string(),
+                message: format!(
+                    "Scenarios of company {} refer to macro scenarios {}, which are not among \
+                    the macro scenarios of the input. Check your input.",
+                    c.ticker,
+                    unknown.iter().join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+            continue;
+        }
+
+        let n_placed = c
+            .scenarios
+            .iter()
+            .filter(|s| s.macro_scenario.is_some())
+            .count();
+        if n_placed == 0 {
+            continue;
+        }
+        if n_placed < c.scenarios.len() || c.auto_status_quo.unwrap_or(false) {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "scenarios-outside-of-macro-scenarios".to_string(),
+                message: format!(
+                    "Company {} places only some of its scenarios in macro scenarios. Place \
+                    all of them, including the status quo, or none.",
+                    c.ticker
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+            continue;
+        }
+
+        for m in macro_scenarios {
+            let probability: f64 = c
+                .scenarios
+                .iter()
+                .filter(|s| s.macro_scenario.as_ref() == Some(&m.name))
+                .map(|s| s.probability)
+                .sum();
+            if (probability - m.probability).abs() > TOLERANCE {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "macro-scenario-probability-mismatch".to_string(),
+                    message: format!(
+                        "Probabilities of the scenarios of company {} in macro scenario {} sum \
+                        up to {probability}, but the macro scenario has the probability {}. \
+                        The probabilities of the scenarios are unconditional, i.e. the \
+                        probability of the macro scenario times the conditional one.",
+                        c.ticker, m.name, m.probability
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+    }
+
+    validation_results
+}
+
+impl Portfolio {
+    /// Validates the macro scenarios of the portfolio against its companies like the ones of an
+    /// [AllocationInput], see [validate_macro_scenarios].
+    pub fn validate_macro_scenarios(&self) -> IndexSet<ValidationResult> {
+        let companies = self
+            .companies
+            .iter()
+            .map(|pc| pc.company.clone())
+            .collect_vec();
+        validate_macro_scenarios(self.macro_scenarios.as_deref(), &companies)
+    }
+
+    /// Returns the portfolio with all fractions multiplied by the factor, e.g. the half Kelly
+    /// portfolio for the factor 0.5, where the rest of the capital is held in cash.
+    pub fn scaled(&self, factor: f64) -> Portfolio {
+        Portfolio {
+            companies: self
+                .companies
+                .iter()
+                .map(|pc| PortfolioCompany {
+                    fraction: factor * pc.fraction,
+                    ..pc.clone()
+                })
+                .collect(),
+            macro_scenarios: self.macro_scenarios.clone(),
+        }
+    }
+
+    /// Merges another portfolio into this one, e.g. to analyze the combined exposure of two
+    /// accounts managed against the same candidates. Fractions of the companies held in both
+    /// portfolios are summed, so the fractions of both portfolios should be relative to the same
+    /// capital. A company held in both portfolios must have the same metadata (name, description,
+    /// market cap and scenarios) in both, otherwise the portfolios can't be merged.
+    pub fn merge(&self, other: &Portfolio) -> Result<Portfolio, Error> {
+        let mut companies: Vec<PortfolioCompany> = self.companies.clone();
+        for pc in &other.companies {
+            match companies
+                .iter_mut()
+                .find(|c| c.company.ticker == pc.company.ticker)
+            {
+                Some(existing) => {
+                    // Company compares tickers only, so compare the serialized records instead
+                    if serde_json::to_value(&existing.company).ok()
+                        != serde_json::to_value(&pc.company).ok()
+                    {
+                        return Err(Error {
+                            code: "inconsistent-company-in-merged-portfolios".to_string(),
+                            message: format!(
+                                "Company {} is described differently in the merged portfolios. \
+                                Make sure both portfolios use the same candidates.",
+                                pc.company.ticker
+                            ),
+                        });
+                    }
+                    existing.fraction += pc.fraction;
+                }
+                None => companies.push(pc.clone()),
+            }
+        }
+
+        Ok(Portfolio {
+            companies,
+            macro_scenarios: self
+                .macro_scenarios
+                .clone()
+                .or_else(|| other.macro_scenarios.clone()),
+        })
+    }
+    /// Merges all portfolios in the given order with [Portfolio::merge].
+    pub fn merge_all(portfolios: &[Portfolio]) -> Result<Portfolio, Error> {
+        portfolios
+            .iter()
+            .try_fold(Portfolio::default(), |merged, p| merged.merge(p))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::scenario::Scenario;
+
+    #[test]
+    fn test_all_tickers_must_be_unique() {
+        let duplicate_tickers = AllocationInput {
+            candidates: (0..2)
+                .map(|_| Company {
+                    name: format!("A").to_string(),
+                    ticker: format!("A").to_string(),
+                    description: format!("A").to_string(),
+                    market_cap: 1.0,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
+                    scenarios: vec![
+                        Scenario {
+                            thesis: "50% down with 50% probability".to_string(),
+                            intrinsic_value: 0.5,
+                            probability: 0.5,
+                            ..Default::default()
+                        },
+                        Scenario {
+                            thesis: "100% up with 50% probability".to_string(),
+                            intrinsic_value: 2.0,
+                            probability: 0.5,
+                            ..Default::default()
+                        },
+                    ],
+                })
+                .collect_vec(),
+            ..Default::default()
+        };
+
+        assert!(duplicate_tickers
+            .validate()
+            .contains(&ValidationResult::PROBLEM(Problem {
+                code: "all-tickers-must-be-unique".to_string(),
+                message: "All tickers must be unique. All tickers are: A, A. Check your input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            })));
+
+        // Tickers differing only by their formatting are duplicates as well
+        let mut formatting_duplicates = duplicate_tickers.clone();
+        formatting_duplicates.candidates[0].ticker = "abc cn".to_string();
+        formatting_duplicates.candidates[1].ticker = "ABC.TO".to_string();
+        assert!(formatting_duplicates
+            .validate()
+            .contains(&ValidationResult::PROBLEM(Problem {
+                code: "tickers-differ-only-by-formatting".to_string(),
+                message: "Tickers abc cn, ABC.TO differ only by their formatting and most likely \
+                    refer to the same security. Keep only one of them."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            })));
+    }
+
+    #[test]
+    fn test_constraint_tickers_must_be_among_candidates() {
+        let input = AllocationInput {
+            candidates: vec![Company {
+                name: "A".to_string(),
+                ticker: "A".to_string(),
+                description: "A".to_string(),
+                market_cap: 1.0,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
+                scenarios: vec![
+                    Scenario {
+                        thesis: "50% down with 50% probability".to_string(),
+                        intrinsic_value: 0.5,
+                        probability: 0.5,
+                        ..Default::default()
+                    },
+                    Scenario {
+                        thesis: "100% up with 50% probability".to_string(),
+                        intrinsic_value: 2.0,
+                        probability: 0.5,
+                        ..Default::default()
+                    },
+                ],
+            }],
+            long_only: Some(true),
+            long_only_tickers: Some(vec!["A".to_string(), "B".to_string()]),
+            max_permanent_loss_of_capital: None,
+            max_individual_allocation: None,
+            max_individual_allocation_tickers: Some(vec!["A".to_string()]),
+            ..Default::default()
+        };
+
+        let validation_results = input.validate();
+        assert!(
+            validation_results.contains(&ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-for-long-only-constraint".to_string(),
+                message: "Tickers B for the long-only constraint are not found among the \
+                    candidates. Check your input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }))
+        );
+        assert!(
+            validation_results.contains(&ValidationResult::PROBLEM(Problem {
+                code: "maximum-individual-allocation-tickers-given-without-the-constraint"
+                    .to_string(),
+                message: "Tickers for the maximum-individual-allocation constraint are given, but \
+                    the constraint itself is not specified. Either specify the constraint or \
+                    remove the tickers."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_candidate_groups() {
+        let candidate = |ticker: &str| Company {
+            name: ticker.to_string(),
+            ticker: ticker.to_string(),
+            description: ticker.to_string(),
+            market_cap: 1.0,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "50% down with 50% probability".to_string(),
+                    intrinsic_value: 0.5,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "100% up with 50% probability".to_string(),
+                    intrinsic_value: 2.0,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+            ],
+        };
+        let group = |name: &str, tickers: &[&str]| CandidateGroup {
+            name: name.to_string(),
+            tickers: tickers.iter().map(|t| t.to_string()).collect_vec(),
+            mutually_exclusive: Some(true),
+            max_allocation: None,
+        };
+
+        let input = AllocationInput {
+            candidates: vec![
+                candidate("A"),
+                candidate("B"),
+                candidate("C"),
+                candidate("D"),
+            ],
+            long_only: None,
+            long_only_tickers: None,
+            max_permanent_loss_of_capital: None,
+            max_individual_allocation: None,
+            max_individual_allocation_tickers: None,
+            ticker_max_individual_allocations: None,
+            min_individual_allocation: None,
+            max_total_leverage_ratio: None,
+            fully_invested: None,
+            fixed_net_exposure: None,
+            max_positions: None,
+            max_position_downside: None,
+            kelly_fraction: None,
+            target_risk: None,
+            current_weights: None,
+            turnover_penalty: None,
+            max_turnover: None,
+            benchmark: None,
+            units: None,
+            call_options: None,
+            pair_trades: None,
+            market_neutral_tickers: None,
+            min_scenario_probability: None,
+            candidate_groups: Some(vec![group("AB", &["A", "B"]), group("CD", &["C", "D"])]),
+            ..Default::default()
+        };
+
+        let choices = input
+            .exclusive_group_choices(&input.candidates)
+            .iter()
+            .map(|choice| choice.iter().map(|c| c.ticker.clone()).join(""))
+            .collect_vec();
+        assert_eq!(choices, vec!["AC", "AD", "BC", "BD"]);
+
+        // Overlapping exclusive groups with an unknown ticker are not allowed
+        let invalid_input = AllocationInput {
+            candidate_groups: Some(vec![group("AB", &["A", "B"]), group("BE", &["B", "E"])]),
+            ..input
+        };
+        let validation_results = invalid_input.validate();
+        assert!(
+            validation_results.contains(&ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-candidate-group".to_string(),
+                message: "Tickers E in group BE are not found among the candidates. Check your \
+                    input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }))
+        );
+        assert!(
+            validation_results.contains(&ValidationResult::PROBLEM(Problem {
+                code: "ticker-in-multiple-mutually-exclusive-groups".to_string(),
+                message: "Tickers B belong to more than one mutually exclusive group. A candidate \
+                    can belong to at most one mutually exclusive group."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_constraint_consistency() {
+        let candidate = |ticker: &str| Company {
+            name: ticker.to_string(),
+            ticker: ticker.to_string(),
+            description: ticker.to_string(),
+            market_cap: 1.0,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "50% down with 50% probability".to_string(),
+                    intrinsic_value: 0.5,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "100% up with 50% probability".to_string(),
+                    intrinsic_value: 2.0,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+            ],
+        };
+        let input = AllocationInput {
+            candidates: vec![candidate("A"), candidate("B"), candidate("C")],
+            long_only: Some(true),
+            long_only_tickers: None,
+            max_permanent_loss_of_capital: None,
+            max_individual_allocation: Some(0.5),
+            max_individual_allocation_tickers: None,
+            ticker_max_individual_allocations: None,
+            min_individual_allocation: None,
+            max_total_leverage_ratio: Some(0.0),
+            fully_invested: None,
+            fixed_net_exposure: None,
+            max_positions: None,
+            max_position_downside: None,
+            kelly_fraction: None,
+            target_risk: None,
+            current_weights: None,
+            turnover_penalty: None,
+            max_turnover: None,
+            benchmark: None,
+            units: None,
+            call_options: None,
+            pair_trades: None,
+            market_neutral_tickers: None,
+            min_scenario_probability: None,
+            candidate_groups: Some(vec![CandidateGroup {
+                name: "AB".to_string(),
+                tickers: vec!["A".to_string(), "B".to_string()],
+                mutually_exclusive: None,
+                max_allocation: Some(1.0),
+            }]),
+            ..Default::default()
+        };
+
+        // The caps of A and B limit the group to 1, but together with C they can exceed leverage
+        assert_eq!(
+            input.validate_constraint_consistency(),
+            IndexSet::from([ValidationResult::PROBLEM(Problem {
+                code: "redundant-constraint".to_string(),
+                message: "Maximum allocation of 1 for group AB can never be active because the \
+                    maximum individual allocations of its long-only candidates limit the group to \
+                    at most this value. Removing it at least halves the number of systems to \
+                    solve."
+                    .to_string(),
+                severity: Severity::WARNING,
+                suggestion: None,
+            })])
+        );
+
+        // Only two candidates can never exceed the leverage of 0 with caps of 0.5
+        let two_candidates = AllocationInput {
+            candidates: vec![candidate("A"), candidate("B")],
+            candidate_groups: None,
+            ..input.clone()
+        };
+        assert_eq!(
+            two_candidates.validate_constraint_consistency(),
+            IndexSet::from([ValidationResult::PROBLEM(Problem {
+                code: "redundant-constraint".to_string(),
+                message: "Maximum total leverage ratio of 0 can never be active because the \
+                    maximum individual allocations of the long-only candidates sum up to at most \
+                    the allowed exposure. Removing it at least halves the number of systems to \
+                    solve."
+                    .to_string(),
+                severity: Severity::WARNING,
+                suggestion: None,
+            })])
+        );
+
+        // Caps of the long-only candidates summing up to less than one can't be fully invested
+        let not_fully_investable = AllocationInput {
+            candidates: vec![candidate("A"), candidate("B")],
+            max_individual_allocation: Some(0.4),
+            max_total_leverage_ratio: None,
+            fully_invested: Some(true),
+            fixed_net_exposure: None,
+            max_positions: None,
+            candidate_groups: None,
+            ..input.clone()
+        };
+        assert_eq!(
+            not_fully_investable.validate_constraint_consistency(),
+            IndexSet::from([ValidationResult::PROBLEM(Problem {
+                code: "fully-invested-constraint-cannot-be-satisfied".to_string(),
+                message: "The maximum individual allocations of the long-only candidates sum up \
+                    to 0.8, so not all capital can be invested. Either remove the fully invested \
+                    constraint or raise the maximum individual allocation."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            })])
+        );
+
+        // A cap of zero for all candidates leaves nothing to allocate
+        let nothing_to_allocate = AllocationInput {
+            max_individual_allocation: Some(0.0),
+            candidate_groups: None,
+            max_total_leverage_ratio: None,
+            ..input
+        };
+        assert_eq!(
+            nothing_to_allocate.validate_constraint_consistency(),
+            IndexSet::from([ValidationResult::PROBLEM(Problem {
+                code: "constraints-leave-nothing-to-allocate".to_string(),
+                message: "Maximum individual allocation of 0 applies to all candidates, so \
+                    nothing can be allocated. Check your input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            })])
+        );
+    }
+
+    #[test]
+    fn test_position_downside_budget() {
+        let input: AllocationInput = serde_yaml::from_str(
+            "
+            candidates:
+              - name: A
+                ticker: A
+                description: A
+                market_cap: 1.0
+                scenarios: []
+              - name: B
+                ticker: B
+                description: B
+                market_cap: 1.0
+                scenarios: []
+            max_position_downside:
+              budget: 0.02
+              ticker_budgets:
+                A: 0.05
+                B: 0.0
+                C: 0.01
+            ",
+        )
+        .unwrap();
+        let position_downside = input.max_position_downside.as_ref().unwrap();
+
+        assert_eq!(Some(0.05), position_downside.budget_of(&"A".to_string()));
+        assert_eq!(Some(0.02), position_downside.budget_of(&"D".to_string()));
+        assert_eq!(
+            input.validate_position_downside_budget(position_downside),
+            IndexSet::from([
+                ValidationResult::PROBLEM(Problem {
+                    code: "unknown-tickers-in-position-downside-budgets".to_string(),
+                    message: "Tickers C in the position downside budgets are not found among the \
+                        candidates. Check your input."
+                        .to_string(),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }),
+                ValidationResult::PROBLEM(Problem {
+                    code: "position-downside-budget-must-be-positive".to_string(),
+                    message: "Position downside budget must be positive. You provided 0."
+                        .to_string(),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                })
+            ])
+        );
+    }
+
+    #[test]
+    fn test_kelly_fraction() {
+        let input = |kelly_fraction: f64, fully_invested: bool| -> AllocationInput {
+            serde_yaml::from_str(&format!(
+                "
+                candidates: []
+                kelly_fraction: {kelly_fraction}
+                fully_invested: {fully_invested}
+                "
+            ))
+            .unwrap()
+        };
+        let codes = |input: AllocationInput| {
+            input
+                .validate()
+                .into_iter()
+                .filter_map(|r| match r {
+                    ValidationResult::PROBLEM(p) => Some(p.code),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+        };
+
+        assert!(!codes(input(0.5, false)).contains(&"kelly-fraction-out-of-bounds".to_string()));
+        assert!(!codes(input(1.0, true))
+            .contains(&"fractional-kelly-cannot-be-fully-invested".to_string()));
+        assert!(codes(input(0.0, false)).contains(&"kelly-fraction-out-of-bounds".to_string()));
+        assert!(codes(input(1.5, false)).contains(&"kelly-fraction-out-of-bounds".to_string()));
+        assert!(codes(input(0.5, true))
+            .contains(&"fractional-kelly-cannot-be-fully-invested".to_string()));
+    }
+
+    #[test]
+    fn test_max_underperformance_probability() {
+        let messages = |max_p: f64| -> Vec<String> {
+            let input: AllocationInput = serde_yaml::from_str(&format!(
+                "
+                candidates: []
+                benchmark:
+                  expected_return: 0.08
+                  max_underperformance_probability: {max_p}
+                "
+            ))
+            .unwrap();
+            input
+                .validate()
+                .into_iter()
+                .filter_map(|r| match r {
+                    ValidationResult::PROBLEM(p)
+                        if p.code == "maximum-underperformance-probability-out-of-bounds" =>
+                    {
+                        Some(p.message)
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert!(messages(0.2).is_empty());
+        assert_eq!(
+            vec![
+                "Maximum probability of underperforming the benchmark must be between 0 and 1. You \
+                 provided 1.5."
+                    .to_string()
+            ],
+            messages(1.5)
+        );
+    }
+
+    #[test]
+    fn test_regularization_must_be_finite_and_non_negative() {
+        let codes = |regularization: &str| -> Vec<String> {
+            let input: AllocationInput = serde_yaml::from_str(&format!(
+                "
+                candidates: []
+                solver_settings:
+                  regularization: {regularization}
+                "
+            ))
+            .unwrap();
+            input
+                .validate()
+                .into_iter()
+                .filter_map(|r| match r {
+                    ValidationResult::PROBLEM(p) => Some(p.code),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert!(codes("0.1").is_empty());
+        assert!(codes("-0.1").contains(&"regularization-cannot-be-negative".to_string()));
+        assert!(codes(".nan").contains(&"regularization-cannot-be-negative".to_string()));
+        assert!(codes(".nan").contains(&"non-finite-setting".to_string()));
+        assert!(codes(".inf").contains(&"non-finite-setting".to_string()));
+    }
+
+    #[test]
+    fn test_downside_budgets_and_tier_caps_must_be_finite() {
+        let messages = |settings: &str| -> Vec<String> {
+            let input: AllocationInput =
+                serde_yaml::from_str(&format!("{{candidates: [], {settings}}}")).unwrap();
+            input
+                .validate()
+                .into_iter()
+                .filter_map(|r| match r {
+                    ValidationResult::PROBLEM(p) if p.code == "non-finite-setting" => {
+                        Some(p.message)
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert!(messages("max_position_downside: {budget: 0.1}").is_empty());
+        for settings in [
+            "max_position_downside: {budget: .nan}",
+            "max_position_downside: {ticker_budgets: {A: .inf}}",
+            "conviction_tiers: {tiers: {}, high_cap: .nan}",
+            "conviction_tiers: {tiers: {}, medium_cap: .inf}",
+            "conviction_tiers: {tiers: {}, low_cap: .nan}",
+        ] {
+            assert_eq!(1, messages(settings).len(), "{settings}");
+        }
+        assert!(
+            messages("max_position_downside: {ticker_budgets: {A: .inf}}")[0]
+                .contains("position downside budget of A")
+        );
+    }
+
+    #[test]
+    fn test_screen() {
+        // Expected return of 25%, downside probability of 50% and upside/downside ratio of 2
+        let candidate = Company {
+            name: "A".to_string(),
+            ticker: "A".to_string(),
+            description: "A".to_string(),
+            market_cap: 1.0,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "50% down with 50% probability".to_string(),
+                    intrinsic_value: 0.5,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "100% up with 50% probability".to_string(),
+                    intrinsic_value: 2.0,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+            ],
+        };
+        let input = AllocationInput {
+            candidates: vec![candidate.clone()],
+            long_only: None,
+            long_only_tickers: None,
+            max_permanent_loss_of_capital: None,
+            max_individual_allocation: None,
+            max_individual_allocation_tickers: None,
+            ticker_max_individual_allocations: None,
+            min_individual_allocation: None,
+            max_total_leverage_ratio: None,
+            fully_invested: None,
+            fixed_net_exposure: None,
+            max_positions: None,
+            max_position_downside: None,
+            kelly_fraction: None,
+            target_risk: None,
+            current_weights: None,
+            turnover_penalty: None,
+            max_turnover: None,
+            benchmark: None,
+            units: None,
+            call_options: None,
+            pair_trades: None,
+            market_neutral_tickers: None,
+            min_scenario_probability: None,
+            candidate_groups: None,
+            solver_settings: None,
+            max_scenarios_per_company: None,
+            min_expected_return: Some(0.2),
+            max_downside_probability: Some(0.5),
+            min_upside_downside_ratio: Some(2.0),
+            ..Default::default()
+        };
+        assert_eq!(input.screen(&candidate), ValidationResult::OK);
+
+        let screen_code = |input: &AllocationInput| match input.screen(&candidate) {
+            ValidationResult::PROBLEM(p) => p.code,
+            ValidationResult::OK => "ok".to_string(),
+        };
+        assert_eq!(
+            screen_code(&AllocationInput {
+                min_expected_return: Some(0.3),
+                ..input.clone()
+            }),
+            "expected-return-below-screening-threshold"
+        );
+        assert_eq!(
+            screen_code(&AllocationInput {
+                max_downside_probability: Some(0.4),
+                ..input.clone()
+            }),
+            "downside-probability-above-screening-threshold"
+        );
+        assert_eq!(
+            screen_code(&AllocationInput {
+                min_upside_downside_ratio: Some(2.5),
+                ..input.clone()
+            }),
+            "upside-downside-ratio-below-screening-threshold"
+        );
+
+        // Thresholds must be within bounds
+        let invalid_input = AllocationInput {
+            max_downside_probability: Some(1.5),
+            min_upside_downside_ratio: Some(-1.0),
+            ..input
+        };
+        let codes = invalid_input
+            .validate()
+            .into_iter()
+            .filter_map(|v| match v {
+                ValidationResult::PROBLEM(p) => Some(p.code),
+                ValidationResult::OK => None,
+            })
+            .collect::<HashSet<String>>();
+        assert!(codes.contains("maximum-downside-probability-out-of-bounds"));
+        assert!(codes.contains("minimum-upside-downside-ratio-cannot-be-negative"));
+    }
+
+    #[test]
+    fn test_merge_portfolios() {
+        let company = |ticker: &str, market_cap: f64| Company {
+            name: ticker.to_string(),
+            ticker: ticker.to_string(),
+            description: ticker.to_string(),
+            market_cap,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "Down".to_string(),
+                    intrinsic_value: 0.5,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Up".to_string(),
+                    intrinsic_value: 2.0,
+                    probability: 0.5,
+                    ..Default::default()
+                },
+            ],
+        };
+        let portfolio = |companies: Vec<(Company, f64)>| Portfolio {
+            companies: companies
+                .into_iter()
+                .map(|(company, fraction)| PortfolioCompany { company, fraction })
+                .collect(),
+            ..Default::default()
+        };
+
+        let first = portfolio(vec![(company("A", 1.0), 0.2), (company("B", 1.0), 0.3)]);
+        let second = portfolio(vec![(company("B", 1.0), 0.1), (company("C", 1.0), 0.4)]);
+        let merged = first.merge(&second).unwrap();
+        assert_eq!(
+            vec![("A", 0.2), ("B", 0.4), ("C", 0.4)],
+            merged
+                .companies
+                .iter()
+                .map(|pc| (pc.company.ticker.as_str(), pc.fraction))
+                .collect_vec()
+        );
+
+        let inconsistent = portfolio(vec![(company("B", 2.0), 0.1)]);
+        assert_eq!(
+            "inconsistent-company-in-merged-portfolios",
+            first.merge(&inconsistent).unwrap_err().code
+        );
+    }
+
+    #[test]
+    fn test_conviction_tiers() {
+        let conviction_tiers: ConvictionTiers = serde_yaml::from_str(
+            "
+            tiers:
+              A: high
+              B: medium
+            medium_cap: 0.1
+            ",
+        )
+        .unwrap();
+        let portfolio = Portfolio {
+            companies: ["A", "B", "C", "D"]
+                .into_iter()
+                .zip([0.3, 0.05, -0.2, 0.01])
+                .map(|(ticker, fraction)| PortfolioCompany {
+                    company: Company {
+                        name: ticker.to_string(),
+                        ticker: ticker.to_string(),
+                        description: ticker.to_string(),
+                        market_cap: 1.0,
+                        auto_status_quo: None,
+                        units: None,
+                        last_reviewed: None,
+                        scenarios: vec![],
+                    },
+                    fraction,
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        // Fractions are clipped to the caps of the tiers, where C and D without a tier are capped
+        // like the low tier
+        assert_eq!(
+            vec![0.25, 0.05, -0.05, 0.01],
+            conviction_tiers
+                .project(&portfolio)
+                .companies
+                .iter()
+                .map(|pc| pc.fraction)
+                .collect_vec()
+        );
+    }
+
+    #[test]
+    fn test_validation_problems_are_in_input_order() {
+        let input: AllocationInput = serde_yaml::from_str(
+            "
+            candidates:
+              - name: B
+                ticker: B
+                description: B
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: 0.5
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability: 0.4
+              - name: A
+                ticker: A
+                description: A
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: 0.4
+                  - thesis: Up
+                    intrinsic_value: 2.0
+                    probability: 0.4
+            max_downside_probability: 1.5
+            ",
+        )
+        .unwrap();
+
+        let messages = input
+            .validate()
+            .into_iter()
+            .filter_map(|v| match v {
+                ValidationResult::PROBLEM(p) => Some(p.message),
+                ValidationResult::OK => None,
+            })
+            .collect_vec();
+        assert_eq!(3, messages.len());
+        assert!(messages[0].contains("company B"));
+        assert!(messages[1].contains("company A"));
+        assert!(messages[2].contains("Maximum downside probability"));
     }
 }
@@ -0,0 +1,470 @@
+use crate::model::company::{Company, Ticker};
+use crate::model::errors::Error;
+use crate::model::portfolio::AllocationInput;
+use crate::model::scenario::Scenario;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Timeout of fetching a document in the CLI.
+const CLI_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest size of a document fetched by the CLI, 100 MB.
+const CLI_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Largest number of redirects followed when fetching a document.
+const MAX_REDIRECTS: usize = 10;
+
+/// Input document fetched from an HTTP(S) URL instead of being sent in the request body or read
+/// from a local file.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct RemoteInput {
+    pub url: String,
+
+    /// Value of the Authorization header sent with the request, e.g. "Bearer <token>".
+    #[serde(default)]
+    pub auth_header: Option<String>,
+
+    /// Format of the document. Defaults to YAML (or JSON).
+    #[serde(default)]
+    pub format: Option<RemoteInputFormat>,
+}
+
+/// Format of a remote input document:
+/// - Yaml: Allocation input in YAML (or JSON) format.
+/// - GoogleSheetsCsv: Sheet with one scenario per row exported as CSV, see [candidates_from_csv].
+///   Links to the sheet (e.g. .../spreadsheets/d/<id>/edit#gid=<gid>) are converted into the CSV
+///   export links. Only the candidates are read from the sheet, without any constraints.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteInputFormat {
+    #[default]
+    Yaml,
+    GoogleSheetsCsv,
+}
+
+impl FromStr for RemoteInputFormat {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" => Ok(RemoteInputFormat::Yaml),
+            "google_sheets_csv" => Ok(RemoteInputFormat::GoogleSheetsCsv),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected \"yaml\" or \"google_sheets_csv\" as input format, got {s}"),
+            )),
+        }
+    }
+}
+
+/// Limits of fetching a remote input document. The server only fetches from the hosts allowed in
+/// its configuration, also when following redirects, while the CLI fetches from any host on behalf
+/// of its own user.
+#[derive(Clone, Debug)]
+pub struct FetchLimits {
+    /// Hosts the documents are fetched from, including every host redirected to. A host starting
+    /// with `*.` allows its subdomains, e.g. `*.googleusercontent.com`. Any host if not given.
+    pub allowed_hosts: Option<Vec<String>>,
+
+    /// Timeout of fetching a document, including reading it.
+    pub timeout: Duration,
+
+    /// Largest size of a document in bytes.
+    pub max_bytes: u64,
+}
+
+impl FetchLimits {
+    /// Limits of the CLI, which fetches from any host.
+    pub fn cli() -> FetchLimits {
+        FetchLimits {
+            allowed_hosts: None,
+            timeout: CLI_TIMEOUT,
+            max_bytes: CLI_MAX_BYTES,
+        }
+    }
+
+    /// Returns whether a document may be fetched from the given URL, i.e. whether it's an HTTP(S)
+    /// URL of an allowed host or of a subdomain allowed by a wildcard.
+    pub fn allows(&self, url: &str) -> bool {
+        let Ok(url) = reqwest::Url::parse(url) else {
+            return false;
+        };
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return false;
+        }
+
+        match (&self.allowed_hosts, url.host_str()) {
+            (None, Some(_)) => true,
+            (Some(allowed_hosts), Some(host)) => {
+                let host = host.to_ascii_lowercase();
+                allowed_hosts.iter().any(|h| {
+                    let h = h.to_ascii_lowercase();
+                    match h.strip_prefix("*.") {
+                        Some(domain) => host.strip_suffix(domain).is_some_and(|subdomain| {
+                            subdomain.len() > 1 && subdomain.ends_with('.')
+                        }),
+                        None => h == host,
+                    }
+                })
+            }
+            (_, None) => false,
+        }
+    }
+
+    /// Returns the policy of following redirects, which stops after [MAX_REDIRECTS] redirects and
+    /// at the first redirect to a URL that isn't allowed.
+    fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        let limits = self.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error(format!("more than {MAX_REDIRECTS} redirects"))
+            } else if !limits.allows(attempt.url().as_str()) {
+                let message = format!("redirect to {} isn't allowed", attempt.url());
+                attempt.error(message)
+            } else {
+                attempt.follow()
+            }
+        })
+    }
+}
+
+/// Row of a CSV document with the candidates, where each row holds a single scenario. Rows with the
+/// same ticker belong to the same company, whose name, description and market cap are taken from
+/// the first row.
+#[derive(Deserialize)]
+struct ScenarioRow {
+    ticker: Ticker,
+    name: String,
+    #[serde(default)]
+    description: String,
+    market_cap: f64,
+    thesis: String,
+    intrinsic_value: f64,
+    probability: f64,
+}
+
+impl RemoteInput {
+    /// Returns the URL the document is fetched from, which is the CSV export link for a Google
+    /// Sheet.
+    pub fn document_url(&self) -> String {
+        match self.format.unwrap_or_default() {
+            RemoteInputFormat::Yaml => self.url.clone(),
+            RemoteInputFormat::GoogleSheetsCsv => google_sheets_csv_url(&self.url),
+        }
+    }
+
+    /// Fetches the document within the limits and deserializes it into an [AllocationInput]. This
+    /// performs a blocking HTTP request.
+    pub fn fetch_allocation_input(&self, limits: &FetchLimits) -> Result<AllocationInput, Error> {
+        let document = self.fetch(&self.document_url(), limits)?;
+        match self.format.unwrap_or_default() {
+            RemoteInputFormat::Yaml => serde_yaml::from_str(&document).map_err(|e| Error {
+                code: "invalid-remote-input".to_string(),
+                message: format!(
+                    "Document at {} is not a valid allocation input: {e}.",
+                    self.url
+                ),
+            }),
+            RemoteInputFormat::GoogleSheetsCsv => Ok(AllocationInput {
+                candidates: candidates_from_csv(&document)?,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Fetches the document at the given URL as text within the limits, sending the auth header if
+    /// present.
+    pub fn fetch(&self, url: &str, limits: &FetchLimits) -> Result<String, Error> {
+        if !limits.allows(url) {
+            return Err(Error {
+                code: "remote-input-url-not-allowed".to_string(),
+                message: format!(
+                    "Only HTTP(S) URLs of the allowed hosts are supported. You provided {url}."
+                ),
+            });
+        }
+
+        let failed = |e: &dyn std::fmt::Display| Error {
+            code: "failed-to-fetch-remote-input".to_string(),
+            message: format!("Did not manage to fetch the document at {url}: {e}."),
+        };
+        let client = reqwest::blocking::Client::builder()
+            .timeout(limits.timeout)
+            .redirect(limits.redirect_policy())
+            .build()
+            .map_err(|e| failed(&e))?;
+        let mut request = client.get(url);
+        if let Some(auth_header) = &self.auth_header {
+            request = request.header(reqwest::header::AUTHORIZATION, auth_header);
+        }
+
+        let response = request
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| failed(&e))?;
+        if response.status().is_redirection() {
+            return Err(failed(&format!(
+                "redirected with status {}, which isn't followed",
+                response.status()
+            )));
+        }
+        let too_large = || Error {
+            code: "remote-input-too-large".to_string(),
+            message: format!(
+                "Document at {url} is larger than the limit of {} bytes.",
+                limits.max_bytes
+            ),
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > limits.max_bytes)
+        {
+            return Err(too_large());
+        }
+
+        let mut document = String::new();
+        response
+            .take(limits.max_bytes + 1)
+            .read_to_string(&mut document)
+            .map_err(|e| failed(&e))?;
+        if document.len() as u64 > limits.max_bytes {
+            return Err(too_large());
+        }
+
+        Ok(document)
+    }
+}
+
+/// Converts a link to a Google Sheet (e.g. https://docs.google.com/spreadsheets/d/<id>/edit#gid=0)
+/// into the link exporting the sheet as CSV. Other URLs are returned unchanged.
+pub fn google_sheets_csv_url(url: &str) -> String {
+    const SPREADSHEETS_PREFIX: &str = "https://docs.google.com/spreadsheets/d/";
+    let Some(rest) = url.strip_prefix(SPREADSHEETS_PREFIX) else {
+        return url.to_string();
+    };
+    if rest.contains("/export") {
+        return url.to_string();
+    }
+
+    let id = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let gid = url
+        .split(['#', '?', '&'])
+        .find_map(|part| part.strip_prefix("gid="))
+        .unwrap_or("0");
+
+    format!("{SPREADSHEETS_PREFIX}{id}/export?format=csv&gid={gid}")
+}
+
+/// Parses the candidates from a CSV document with a header row and one scenario per row, with
+/// columns ticker, name, description (optional), market_cap, thesis, intrinsic_value and
+/// probability. The order of the candidates and their scenarios follows the order of the rows.
+pub fn candidates_from_csv(document: &str) -> Result<Vec<Company>, Error> {
+    let mut candidates: Vec<Company> = vec![];
+    for (i, row) in csv::Reader::from_reader(document.as_bytes())
+        .deserialize::<ScenarioRow>()
+        .enumerate()
+    {
+        let row = row.map_err(|e| Error {
+            code: "invalid-csv-row".to_string(),
+            message: format!(
+                "Row {} of the CSV document is not a valid scenario: {e}. Expected columns are \
+                ticker, name, description, market_cap, thesis, intrinsic_value and probability.",
+                i + 1
+            ),
+        })?;
+
+        let scenario = Scenario {
+            thesis: row.thesis,
+            intrinsic_value: row.intrinsic_value,
+            probability: row.probability,
+            ..Default::default()
+        };
+        match candidates.iter_mut().find(|c| c.ticker == row.ticker) {
+            Some(company) => company.scenarios.push(scenario),
+            None => candidates.push(Company {
+                name: row.name,
+                ticker: row.ticker,
+                description: row.description,
+                market_cap: row.market_cap,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
+                scenarios: vec![scenario],
+            }),
+        }
+    }
+
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_candidates_from_csv() {
+        let document = "\
+ticker,name,description,market_cap,thesis,intrinsic_value,probability
+A,Company A,\"Business A, the first one\",100,Down,50,0.5
+B,Company B,,200,Down,0,0.2
+A,Company A,,100,Up,200,0.5
+B,Company B,,200,Up,400,0.8
+";
+
+        let candidates = candidates_from_csv(document).unwrap();
+
+        assert_eq!(2, candidates.len());
+        assert_eq!("A", candidates[0].ticker);
+        assert_eq!("Business A, the first one", candidates[0].description);
+        assert_eq!(2, candidates[0].scenarios.len());
+        assert_eq!("Up", candidates[0].scenarios[1].thesis);
+        assert_eq!(200.0, candidates[0].scenarios[1].intrinsic_value);
+        assert_eq!("B", candidates[1].ticker);
+        assert_eq!(200.0, candidates[1].market_cap);
+        assert_eq!(0.8, candidates[1].scenarios[1].probability);
+    }
+
+    #[test]
+    fn test_candidates_from_invalid_csv() {
+        let document = "\
+ticker,name,market_cap,thesis,intrinsic_value,probability
+A,Company A,not-a-number,Down,50,0.5
+";
+
+        assert_eq!(
+            "invalid-csv-row",
+            candidates_from_csv(document).unwrap_err().code
+        );
+    }
+
+    #[test]
+    fn test_fetch_limits() {
+        let server_limits = FetchLimits {
+            allowed_hosts: Some(vec!["docs.google.com".to_string()]),
+            timeout: Duration::from_secs(1),
+            max_bytes: 1,
+        };
+        assert!(server_limits.allows("https://docs.google.com/spreadsheets/d/abc123"));
+        assert!(server_limits.allows("https://DOCS.google.com/spreadsheets/d/abc123"));
+        assert!(!server_limits.allows("http://169.254.169.254/latest/meta-data/"));
+        assert!(!server_limits.allows("http://localhost:8000/allocations/1"));
+        assert!(!server_limits.allows("https://docs.google.com.example.com/"));
+        assert!(!server_limits.allows("file:///etc/passwd"));
+
+        // Google Sheets redirect the export to a subdomain of googleusercontent.com
+        let server_limits = FetchLimits {
+            allowed_hosts: Some(vec![
+                "docs.google.com".to_string(),
+                "*.googleusercontent.com".to_string(),
+            ]),
+            ..server_limits
+        };
+        assert!(server_limits.allows("https://doc-0s-2c-sheets.googleusercontent.com/export/x"));
+        assert!(server_limits.allows("https://DOC-0S-2C-SHEETS.googleusercontent.com/export/x"));
+        assert!(!server_limits.allows("https://googleusercontent.com/"));
+        assert!(!server_limits.allows("https://evilgoogleusercontent.com/"));
+        assert!(!server_limits.allows("https://googleusercontent.com.example.com/"));
+
+        let cli_limits = FetchLimits::cli();
+        assert!(cli_limits.allows("http://localhost:8000/input.yaml"));
+        assert!(!cli_limits.allows("file:///etc/passwd"));
+
+        // Disallowed URLs are rejected without making a request
+        let remote_input = RemoteInput {
+            url: "http://127.0.0.1:1/input.yaml".to_string(),
+            auth_header: None,
+            format: None,
+        };
+        assert_eq!(
+            "remote-input-url-not-allowed",
+            remote_input
+                .fetch_allocation_input(&server_limits)
+                .unwrap_err()
+                .code
+        );
+    }
+
+    /// Serves requests on a local port, whose number is returned. A path /<host>/<rest> redirects
+    /// to http://<host>:<port>/<rest>, and any other path returns a short document.
+    fn serve_redirects() -> u16 {
+        use std::io::Write;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut request = [0; 1024];
+                let n = stream.read(&mut request).unwrap_or_default();
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                let path = request.split(' ').nth(1).unwrap_or("/").to_string();
+                let response = match path.strip_prefix('/').and_then(|p| p.split_once('/')) {
+                    Some((host, rest)) => format!(
+                        "HTTP/1.1 302 Found\r\nLocation: http://{host}:{port}/{rest}\r\n\
+                         Connection: close\r\nContent-Length: 0\r\n\r\n"
+                    ),
+                    None => "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 8\r\n\r\n\
+                             document"
+                        .to_string(),
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_fetch_follows_only_allowed_redirects() {
+        let port = serve_redirects();
+        let limits = FetchLimits {
+            allowed_hosts: Some(vec!["127.0.0.1".to_string()]),
+            timeout: Duration::from_secs(5),
+            max_bytes: 1024,
+        };
+        let remote_input = RemoteInput {
+            url: "unused".to_string(),
+            auth_header: None,
+            format: None,
+        };
+        let fetch =
+            |path: &str| remote_input.fetch(&format!("http://127.0.0.1:{port}{path}"), &limits);
+
+        // Redirects within the allowed hosts are followed
+        assert_eq!("document", fetch("/127.0.0.1/input.yaml").unwrap());
+
+        // A redirect to another host is not followed
+        let error = fetch("/127.0.0.1/localhost/input.yaml").unwrap_err();
+        assert_eq!("failed-to-fetch-remote-input", error.code);
+        assert!(error.message.contains("isn't allowed"));
+
+        // Neither are too many redirects
+        let error = fetch(&"/127.0.0.1".repeat(MAX_REDIRECTS + 1)).unwrap_err();
+        assert_eq!("failed-to-fetch-remote-input", error.code);
+        assert!(error.message.contains("redirects"));
+        assert_eq!(
+            "document",
+            fetch(&"/127.0.0.1".repeat(MAX_REDIRECTS)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_google_sheets_csv_url() {
+        assert_eq!(
+            "https://docs.google.com/spreadsheets/d/abc123/export?format=csv&gid=42",
+            google_sheets_csv_url("https://docs.google.com/spreadsheets/d/abc123/edit#gid=42")
+        );
+        assert_eq!(
+            "https://docs.google.com/spreadsheets/d/abc123/export?format=csv&gid=0",
+            google_sheets_csv_url("https://docs.google.com/spreadsheets/d/abc123")
+        );
+
+        // Export links and other URLs are left unchanged
+        let export_url = "https://docs.google.com/spreadsheets/d/abc123/export?format=csv&gid=7";
+        assert_eq!(export_url, google_sheets_csv_url(export_url));
+        assert_eq!(
+            "https://example.com/input.csv",
+            google_sheets_csv_url("https://example.com/input.csv")
+        );
+    }
+}
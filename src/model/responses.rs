@@ -1,5 +1,7 @@
+use crate::model::allocations::FractionChange;
 use crate::model::company::Ticker;
 use crate::model::errors::Error;
+use crate::model::solver_state::SolverState;
 use crate::validation::result::ValidationResult;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -8,8 +10,14 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct AllocationResponse {
     pub result: Option<AllocationResult>,
+    /// Problems in the order in which they were found, i.e. the problems of the candidates in the
+    /// order of the candidates in the input, followed by the problems of the constraints and the
+    /// warnings of the allocation.
     pub validation_problems: Option<Vec<ValidationResult>>,
     pub error: Option<Error>,
+    /// Log messages of the allocation, if requested with the log level in the options.
+    #[serde(default)]
+    pub logs: Option<Vec<String>>,
 }
 
 /// Response of the call to the analyze endpoint.
@@ -19,19 +27,485 @@ pub struct AnalysisResponse {
     pub error: Option<Error>,
 }
 
+/// Response of the call to the validate company endpoint.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ValidationResponse {
+    pub validation_problems: Vec<ValidationResult>,
+}
+
 /// Allocation result includes tickers and their fractions.
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct AllocationResult {
+    /// Fractions of the allocated candidates in the order of the candidates in the input.
     pub allocations: Vec<TickerAndFraction>,
+    /// Implied cash position, i.e. 1 minus the sum of fractions. Negative cash means that the
+    /// allocation is levered with borrowed money. Pair trades have no net exposure.
+    pub cash: f64,
+    /// Sum of absolute fractions, including short positions, with the pair trades expanded into
+    /// their legs.
+    pub gross_exposure: f64,
+    /// Net exposure to each security with the pair trades expanded into their legs, which are
+    /// added to the fractions of the same securities among the allocations. Only given if the
+    /// input has pair trades.
+    #[serde(default)]
+    pub exposures: Option<Vec<TickerAndFraction>>,
     pub analysis: AnalysisResult,
+    pub solver_metadata: SolverMetadata,
+    /// Candidates dropped before the optimization, either because they fail the screening
+    /// thresholds or because they can't be handled by the algorithm, in the order of the input.
+    pub filtered_candidates: Vec<FilteredCandidate>,
+    /// Candidates that passed the filters but were allocated exactly zero, with the reason, in the
+    /// order of the input.
+    pub zero_allocations: Vec<ZeroAllocation>,
+    /// Allocation without leverage, suggested when no maximum total leverage ratio is given and
+    /// the allocation turns out to be levered.
+    pub deleveraging_suggestion: Option<DeleveragingSuggestion>,
+    /// Allocation projected onto the caps of the conviction tiers, if the tiers are given.
+    pub conviction_sizing: Option<ConvictionSizing>,
+    /// Kelly fraction found for the target risk and the risk of the allocation, if the target risk
+    /// is given.
+    #[serde(default)]
+    pub target_risk_sizing: Option<TargetRiskSizing>,
+    /// Trades from the current weights to the allocation, if the current weights are given.
+    #[serde(default)]
+    pub rebalancing: Option<Rebalancing>,
+    /// Allocation compared with the benchmark portfolio, if the benchmark portfolio is given.
+    #[serde(default)]
+    pub benchmark_comparison: Option<BenchmarkComparison>,
+    /// Allocation with a capped individual allocation, included for comparison when almost all
+    /// capital is allocated to a single candidate.
+    pub capped_alternative: Option<CappedAlternative>,
+    /// Lagrange multiplier of the fully invested or fixed net exposure constraint, i.e. the
+    /// marginal expected logarithmic growth of raising the net exposure. The further it is from
+    /// zero, the more growth the mandate costs compared to the unconstrained Kelly allocation.
+    /// Only given if the net exposure is fixed.
+    #[serde(default)]
+    pub net_exposure_multiplier: Option<f64>,
+    /// Iterations of the solver for the unconstrained problem of the allocated candidates, if
+    /// requested.
+    #[serde(default)]
+    pub solver_trace: Option<SolverTrace>,
+    /// State of the solver for reproducing the allocation, if requested.
+    #[serde(default)]
+    pub solver_state: Option<SolverState>,
+    /// Data quality of each allocated candidate in the order of the allocations, which flags the
+    /// allocations resting on weak inputs.
+    #[serde(default)]
+    pub data_quality: Vec<CandidateDataQuality>,
+    /// Violation of each soft constraint by the allocation, if the input designates any.
+    #[serde(default)]
+    pub soft_constraint_violations: Option<Vec<SoftConstraintViolation>>,
+    /// Edge and odds implied by the scenarios of each allocated candidate in the order of the
+    /// allocations, for checking the scenarios against the intuition of a simple bet.
+    pub betting_edges: Vec<CandidateBettingEdge>,
+}
+
+/// Violation of a soft constraint by the allocation, e.g. by how much a fraction exceeds the
+/// maximum individual allocation. Zero if the constraint is satisfied.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SoftConstraintViolation {
+    /// Constraint and the candidate or the group it applies to, e.g. "max_individual_allocation of
+    /// ABC".
+    pub constraint: String,
+    pub weight: f64,
+    pub violation: f64,
+}
+
+/// Allocation without leverage as an alternative to a levered allocation, together with the
+/// statistics needed to compare the two.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct DeleveragingSuggestion {
+    pub allocations: Vec<TickerAndFraction>,
+    pub cash: f64,
+    pub gross_exposure: f64,
+    pub expected_return: f64,
+    pub expected_log_growth: f64,
+    pub worst_case_outcome: ProbabilityAndReturns,
+    /// Expected logarithmic growth without leverage relative to the growth of the levered
+    /// allocation, e.g. 0.75 if giving up the leverage costs a quarter of the growth.
+    pub fraction_of_levered_growth: f64,
+    /// Number of nonlinear systems solved to find the allocation without leverage.
+    pub n_systems: usize,
+}
+
+/// Allocation with a maximum individual allocation as an alternative to an allocation that puts
+/// almost all capital into a single candidate, together with the statistics needed to compare the
+/// two.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CappedAlternative {
+    /// Maximum individual allocation of this allocation.
+    pub max_individual_allocation: f64,
+    pub allocations: Vec<TickerAndFraction>,
+    pub cash: f64,
+    pub expected_return: f64,
+    pub expected_log_growth: f64,
+    pub worst_case_outcome: ProbabilityAndReturns,
+    /// Expected logarithmic growth of this allocation relative to the growth of the concentrated
+    /// allocation.
+    pub fraction_of_concentrated_growth: f64,
+    /// Number of nonlinear systems solved to find this allocation.
+    pub n_systems: usize,
+}
+
+/// Rebalancing plan from the current weights to the allocation, where a positive change of a
+/// ticker is a buy and a negative change is a sell, together with the turnover, i.e. the sum of the
+/// absolute changes.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Rebalancing {
+    pub trades: Vec<FractionChange>,
+    pub turnover: f64,
+}
+
+/// Largest Kelly fraction whose allocation doesn't exceed the target risk level, together with the
+/// risk of the allocation, which is the target level unless the full Kelly allocation is below it.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TargetRiskSizing {
+    pub kelly_fraction: f64,
+    pub risk: f64,
+}
+
+/// Allocation projected onto the nearest weights within the caps of the conviction tiers,
+/// together with the expected logarithmic growth given up compared to the Kelly allocation.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ConvictionSizing {
+    pub allocations: Vec<TickerAndFraction>,
+    pub cash: f64,
+    pub expected_log_growth: f64,
+    /// Expected logarithmic growth of the Kelly allocation minus the growth of this allocation.
+    pub growth_give_up: f64,
+    /// Expected logarithmic growth of this allocation relative to the growth of the Kelly
+    /// allocation.
+    pub fraction_of_kelly_growth: f64,
+}
+
+/// Allocation compared with the benchmark portfolio of the allocation input. The differences are
+/// the statistics of the allocation minus the ones of the benchmark portfolio.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct BenchmarkComparison {
+    /// Fractions of the allocated candidates in the benchmark portfolio.
+    pub benchmark_allocations: Vec<TickerAndFraction>,
+    pub benchmark_expected_return: f64,
+    pub benchmark_expected_log_growth: f64,
+    pub expected_log_growth_difference: f64,
+    pub benchmark_worst_case_outcome: ProbabilityAndReturns,
+    /// Expected loss in the worst 5% of the outcomes of the benchmark portfolio.
+    pub benchmark_conditional_value_at_risk_95: f64,
+    pub conditional_value_at_risk_95_difference: f64,
+    /// Fraction of each allocated candidate minus its fraction in the benchmark portfolio, in the
+    /// order of the allocations.
+    pub weight_differences: Vec<TickerAndFraction>,
+}
+
+/// A candidate dropped before the optimization, with the code and the message of the reason.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct FilteredCandidate {
+    pub ticker: Ticker,
+    pub code: String,
+    pub message: String,
+}
+
+/// A candidate allocated exactly zero, with the code and the message of the reason derived from
+/// the constraints active at the solution and the marginal growth of the candidate.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ZeroAllocation {
+    pub ticker: Ticker,
+    pub code: String,
+    pub message: String,
+    /// Derivative of the expected logarithmic growth with respect to the fraction of the candidate
+    /// at the allocation. Positive if buying some of it would add growth without the constraints.
+    pub marginal_growth: f64,
+}
+
+/// Quality of the input data of a candidate, scored from its validation warnings, the balance
+/// between its upside and downside scenarios, the granularity of its probabilities and the time
+/// since its last review. The score is the product of the scores of the components.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CandidateDataQuality {
+    pub ticker: Ticker,
+    /// Score between 0 and 1, where 1 means that nothing about the input is suspicious.
+    pub score: f64,
+    /// True if the score is below 0.5, i.e. the allocation to the candidate rests on weak inputs.
+    pub is_weak: bool,
+    /// Codes of the validation warnings of the candidate, each lowering the score by a quarter.
+    pub warnings: Vec<String>,
+    /// Probability of the less likely side among the upside and downside scenarios relative to
+    /// 0.2, capped at 1. Zero if the candidate has no upside or no downside scenario.
+    pub scenario_balance: f64,
+    /// Coarsest step among 0.5, 0.25, 0.1 and 0.05 of which all probabilities are multiples,
+    /// lowering the score by the step. Not given if the probabilities are finer.
+    pub probability_step: Option<f64>,
+    /// Days since the last review of the candidate, halving the score every 180 days. Not given
+    /// if the date of the last review isn't given.
+    pub days_since_review: Option<i64>,
+}
+
+/// Edge of a candidate together with the classic Kelly decomposition of the bet approximating its
+/// scenarios with two points: the upside scenarios are merged into a win with their expected
+/// return as the odds, and the downside scenarios into a loss with their expected loss as the
+/// loss fraction. Scenarios without a return are a push in which the stake is returned.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CandidateBettingEdge {
+    pub ticker: Ticker,
+    /// Expected return of the candidate, i.e. the expected profit per unit staked.
+    pub edge: f64,
+    pub variance: f64,
+    /// Probability of the upside scenarios, p.
+    pub probability_of_win: f64,
+    /// Expected return of the upside scenarios, i.e. the net odds b. Zero without upside.
+    pub odds: f64,
+    /// Probability of the downside scenarios, q.
+    pub probability_of_loss: f64,
+    /// Expected loss of the downside scenarios as a fraction of the stake. Zero without downside.
+    pub loss_fraction: f64,
+    /// Kelly fraction of the two-point bet, i.e. p - q / b if the whole stake is lost and there's
+    /// no push. Not given if the candidate has no upside or no downside scenarios, in which case
+    /// the bet is either never worth taking or worth taking without bound.
+    pub kelly_fraction: Option<f64>,
+}
+
+/// Information about the solution found by the solver. The curvatures are second derivatives of
+/// the expected logarithmic growth at the solution, i.e. the Jacobian of the Kelly criterion. They
+/// are negative at a maximum, and the closer they are to zero, the flatter the growth surface is.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SolverMetadata {
+    /// True if no exact solution was found and the allocation was approximated with the penalty
+    /// method instead, meaning that the constraints may be slightly violated.
+    pub is_approximate: bool,
+    /// Number of nonlinear systems solved to find the allocation, over all choices of candidates
+    /// from mutually exclusive groups.
+    pub n_systems: usize,
+    /// Expected logarithmic growth of the allocation.
+    pub expected_log_growth: f64,
+    /// Expected logarithmic growth minus the regularization penalty, i.e. the objective maximized
+    /// by the solver. Same as the expected logarithmic growth without regularization.
+    pub regularized_expected_log_growth: f64,
+    /// Diagonal of the Jacobian, i.e. the curvature along each allocation fraction.
+    pub curvatures: Vec<TickerAndCurvature>,
+    /// Eigenvalues and eigenvectors of the Jacobian, sorted from the flattest to the most sharply
+    /// peaked direction.
+    pub principal_curvatures: Vec<PrincipalCurvature>,
+}
+
+/// Iterations of the Newton-Raphson method for the unconstrained allocation problem, for
+/// illustrating how the solver converges to the optimal allocation.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SolverTrace {
+    /// Tickers of the candidates in the order of the fractions of the iterations.
+    pub tickers: Vec<Ticker>,
+    pub iterations: Vec<SolverTraceIteration>,
+}
+
+/// Fractions of the candidates and the residual after a single Newton-Raphson iteration.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SolverTraceIteration {
+    pub iteration: u32,
+    pub fractions: Vec<f64>,
+    /// L-infinity norm of the Newton step, which is compared with the solver tolerance.
+    pub residual: f64,
+}
+
+/// A ticker and the curvature of the growth surface along its allocation fraction.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TickerAndCurvature {
+    pub ticker: Ticker,
+    pub curvature: f64,
+}
+
+/// Curvature of the growth surface along a direction in the space of allocation fractions. Changing
+/// the allocation along a flat direction barely changes the growth rate.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PrincipalCurvature {
+    pub curvature: f64,
+    /// Unit vector with one component per allocation, in the same order as the allocations.
+    pub direction: Vec<f64>,
 }
 
 /// Analysis result includes some statistics for a given portfolio.
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct AnalysisResult {
     pub worst_case_outcome: ProbabilityAndReturns,
-    pub cumulative_probability_of_loss: f64,
+    /// Not calculated on the summary detail level, since it requires enumerating all outcomes.
+    pub cumulative_probability_of_loss: Option<f64>,
     pub expected_return: f64,
+    /// Expected logarithmic growth of capital over the horizon of the scenarios, i.e. the quantity
+    /// maximized by the Kelly criterion. Not calculated on the summary detail level.
+    #[serde(default)]
+    pub expected_log_growth_rate: Option<f64>,
+    /// Compounded return and doubling time implied by the expected logarithmic growth rate. Not
+    /// calculated on the summary detail level.
+    #[serde(default)]
+    pub compound_growth: Option<CompoundGrowth>,
+    /// Variance of the portfolio return, calculated analytically assuming independent companies.
+    pub variance_of_return: f64,
+    /// Inverse of the Herfindahl index of the absolute fractions relative to the gross exposure.
+    pub effective_number_of_positions: f64,
+    /// Largest absolute fraction.
+    pub max_weight: f64,
+    /// Sum of absolute fractions.
+    pub gross_exposure: f64,
+    /// Sum of fractions, where short positions count negatively.
+    pub net_exposure: f64,
+    /// Probabilities and expected portfolio returns of the named events, in the order of the input.
+    pub events: Vec<EventOutcome>,
+    /// Expected return and growth in real terms, if the expected inflation is given.
+    #[serde(default)]
+    pub inflation_adjusted: Option<InflationAdjustedReturns>,
+    /// Statistics estimated from sampled outcomes, if the portfolio has too many outcomes to
+    /// enumerate them. The cumulative probability of loss is then estimated from the samples too.
+    #[serde(default)]
+    pub sampled_statistics: Option<SampledStatistics>,
+    /// Loss that isn't exceeded with a probability of 95%, i.e. the negative of the 5th percentile
+    /// of the portfolio return. Not calculated on the summary detail level.
+    #[serde(default)]
+    pub value_at_risk_95: Option<f64>,
+    /// Expected loss in the worst 5% of the outcomes (expected shortfall). Not calculated on the
+    /// summary detail level.
+    #[serde(default)]
+    pub conditional_value_at_risk_95: Option<f64>,
+    /// Probability that the portfolio return falls below the return of the benchmark, if the
+    /// benchmark is given. Not calculated on the summary detail level.
+    #[serde(default)]
+    pub probability_of_underperforming_benchmark: Option<f64>,
+    /// Probability that the portfolio return falls below the return threshold of the analysis
+    /// options, if the threshold is given. Not calculated on the summary detail level.
+    #[serde(default)]
+    pub probability_of_return_below_threshold: Option<f64>,
+    /// Distribution of the portfolio return, if requested in the analysis options. Estimated from
+    /// the sampled outcomes if the portfolio has too many outcomes to enumerate them.
+    #[serde(default)]
+    pub distribution: Option<ReturnDistribution>,
+    /// Portfolio return conditional on each of the scenario tags of the analysis options being
+    /// realized, in the order of the options.
+    #[serde(default)]
+    pub tag_conditional_outcomes: Option<Vec<TagConditionalOutcome>>,
+    /// Bounds of the numerical errors of the headline statistics.
+    #[serde(default)]
+    pub numerical_errors: Option<NumericalErrors>,
+}
+
+/// Bounds of the numerical errors of the headline statistics, from the rounding errors of the
+/// floating-point sums and, for allocations, from the solver tolerance of the fractions. Digits
+/// beyond the meaningful decimal places are noise and shouldn't be interpreted.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct NumericalErrors {
+    pub expected_return: ErrorBound,
+    /// Not calculated on the summary detail level or if the probability of loss is estimated from
+    /// sampled outcomes, whose confidence interval is given instead.
+    pub cumulative_probability_of_loss: Option<ErrorBound>,
+}
+
+/// Bound of the absolute numerical error of a statistic.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ErrorBound {
+    pub error_bound: f64,
+    /// Number of decimal places of the statistic that are meaningful given the error bound.
+    pub meaningful_decimal_places: u32,
+}
+
+/// Expected return and logarithmic growth of the portfolio adjusted for the expected inflation,
+/// alongside the nominal ones, for comparing long-horizon theses in real terms.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct InflationAdjustedReturns {
+    pub expected_inflation: f64,
+    pub expected_nominal_return: f64,
+    /// Expected return deflated by the inflation, `(1 + nominal) / (1 + inflation) - 1`.
+    pub expected_real_return: f64,
+    /// Not calculated on the summary detail level, since it requires enumerating all outcomes.
+    pub expected_nominal_log_growth: Option<f64>,
+    /// Expected logarithmic growth less the logarithm of one plus the inflation.
+    pub expected_real_log_growth: Option<f64>,
+}
+
+/// Growth of capital compounded at the expected logarithmic growth rate `g` per horizon of the
+/// scenarios, annualized if the length of the horizon in years is given.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CompoundGrowth {
+    /// Return per horizon when compounding at the growth rate, `exp(g) - 1`. It's lower than the
+    /// expected return, since the volatility of the returns drags on the compounding.
+    pub geometric_return: f64,
+    /// Number of horizons until the capital doubles, `ln(2) / g`, if the growth rate is positive.
+    #[serde(default)]
+    pub doubling_time: Option<f64>,
+    #[serde(default)]
+    pub horizon_in_years: Option<f64>,
+    /// Expected logarithmic growth per year, `g / horizon_in_years`.
+    #[serde(default)]
+    pub annualized_log_growth_rate: Option<f64>,
+    #[serde(default)]
+    pub annualized_geometric_return: Option<f64>,
+    #[serde(default)]
+    pub doubling_time_in_years: Option<f64>,
+}
+
+/// Statistics of the portfolio return estimated from outcomes sampled with the seed, for portfolios
+/// with too many outcomes to enumerate them.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SampledStatistics {
+    pub n_samples: usize,
+    pub seed: u64,
+    pub expected_return: Estimate,
+    pub probability_of_loss: Estimate,
+    /// Portfolio return at the 5th percentile of the outcomes.
+    pub tail_quantile: Estimate,
+    /// Expected portfolio return in the worst 5% of the outcomes.
+    pub expected_tail_return: Estimate,
+}
+
+/// Distribution of the portfolio return, either as the distinct returns with their probabilities
+/// or as a histogram, both sorted by return.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ReturnDistribution {
+    /// Distinct portfolio returns with their probabilities, if the distribution isn't binned.
+    #[serde(default)]
+    pub outcomes: Option<Vec<ReturnAndProbability>>,
+    /// Equally wide bins between the lowest and the highest portfolio return, if binned.
+    #[serde(default)]
+    pub histogram: Option<Vec<HistogramBin>>,
+}
+
+/// Portfolio return with its probability.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ReturnAndProbability {
+    pub portfolio_return: f64,
+    pub probability: f64,
+}
+
+/// Bin of the histogram of the portfolio return, including its lower bound. The last bin also
+/// includes its upper bound.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct HistogramBin {
+    pub lower: f64,
+    pub upper: f64,
+    pub probability: f64,
+}
+
+/// Value estimated from samples with its 95% confidence interval.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Estimate {
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Probability of a named portfolio-level event and the expected portfolio return given that the
+/// event occurs, assuming independent companies.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct EventOutcome {
+    pub name: String,
+    pub probability: f64,
+    pub expected_portfolio_return: f64,
+}
+
+/// Probability of a scenario tag being realized and the portfolio return given that it is realized,
+/// assuming independent companies.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TagConditionalOutcome {
+    pub tag: String,
+    pub probability: f64,
+    pub expected_portfolio_return: f64,
+    /// Distribution of the portfolio return given the tag. Not calculated on the summary detail
+    /// level, and estimated from sampled outcomes if there are too many outcomes to enumerate.
+    pub distribution: Option<ReturnDistribution>,
 }
 
 /// A ticker and a fraction used for minimalistic representation of the allocation calculation.
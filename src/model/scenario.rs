@@ -1,17 +1,132 @@
+use crate::model::company::TOLERANCE;
 use crate::validation::result::{Problem, Severity, ValidationResult};
 use crate::validation::validate::Validate;
+use indexmap::IndexSet;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 
 /// A scenario is represented by an investment thesis, which can be boiled down to the expected
 /// intrinsic value and the estimated probability that this scenario will play out in the future.
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+/// Instead of the intrinsic value, the input may give the valuation inputs from which it is
+/// derived, in which case both the inputs and the derived intrinsic value are serialized.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[serde(try_from = "ScenarioInput")]
 pub struct Scenario {
     pub thesis: String,
+    /// Not needed in the input if the valuation inputs are given.
+    #[serde(default)]
     pub intrinsic_value: f64,
     pub probability: f64,
+    #[serde(flatten)]
+    pub valuation: Option<Valuation>,
+    /// Tags of the scenario, e.g. "recession", shared by the scenarios of different companies that
+    /// are driven by the same cause. The analysis can be conditioned on a tag being realized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Macro scenario in which the scenario plays out, e.g. "recession". The scenarios of different
+    /// companies in the same macro scenario are realized together, while companies are otherwise
+    /// independent. The probability remains the unconditional one, such that the probabilities of
+    /// the scenarios of a company in a macro scenario sum up to the probability of the latter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub macro_scenario: Option<String>,
+}
+
+/// Simple valuation inputs from which the intrinsic value of a scenario is derived: either the
+/// earnings times the multiple, or the free cash flow divided by the free cash flow yield.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct Valuation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub earnings: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiple: Option<f64>,
+
+    /// Free cash flow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fcf: Option<f64>,
+
+    /// Free cash flow yield, e.g. 0.08 for a business valued at 12.5 times its free cash flow.
+    #[serde(default, rename = "yield", skip_serializing_if = "Option::is_none")]
+    pub fcf_yield: Option<f64>,
+}
+
+impl Valuation {
+    /// Returns the intrinsic value derived from the valuation inputs, or a message explaining why
+    /// the inputs are incomplete or ambiguous.
+    pub fn intrinsic_value(&self) -> Result<f64, String> {
+        match (self.earnings, self.multiple, self.fcf, self.fcf_yield) {
+            (Some(earnings), Some(multiple), None, None) => Ok(earnings * multiple),
+            (None, None, Some(fcf), Some(fcf_yield)) if fcf_yield > 0.0 => Ok(fcf / fcf_yield),
+            (None, None, Some(_), Some(fcf_yield)) => Err(format!(
+                "free cash flow yield must be positive, got {fcf_yield}"
+            )),
+            _ => Err("give either earnings and multiple, or fcf and yield".to_string()),
+        }
+    }
+}
+
+/// Scenario as given in the input, where the intrinsic value may be replaced by the valuation
+/// inputs.
+#[derive(Deserialize)]
+struct ScenarioInput {
+    thesis: String,
+    #[serde(default)]
+    intrinsic_value: Option<f64>,
+    probability: f64,
+    #[serde(flatten)]
+    valuation: Valuation,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    macro_scenario: Option<String>,
+}
+
+impl TryFrom<ScenarioInput> for Scenario {
+    type Error = String;
+
+    /// Derives the intrinsic value from the valuation inputs if given. An intrinsic value given
+    /// together with the valuation inputs (e.g. a serialized scenario) must match the derived one.
+    fn try_from(input: ScenarioInput) -> Result<Self, Self::Error> {
+        let thesis = input.thesis;
+        if input.valuation == Valuation::default() {
+            return match input.intrinsic_value {
+                Some(intrinsic_value) => Ok(Scenario {
+                    thesis,
+                    intrinsic_value,
+                    probability: input.probability,
+                    valuation: None,
+                    tags: input.tags,
+                    macro_scenario: input.macro_scenario,
+                }),
+                None => Err(format!(
+                    "Scenario \"{thesis}\" requires an intrinsic value or the valuation inputs: \
+                    earnings and multiple, or fcf and yield."
+                )),
+            };
+        }
+
+        let intrinsic_value = input.valuation.intrinsic_value().map_err(|message| {
+            format!("Invalid valuation inputs of scenario \"{thesis}\": {message}.")
+        })?;
+        if let Some(given) = input.intrinsic_value {
+            if (given - intrinsic_value).abs() > TOLERANCE * intrinsic_value.abs().max(1.0) {
+                return Err(format!(
+                    "Intrinsic value {given} of scenario \"{thesis}\" doesn't match the value \
+                    {intrinsic_value} derived from its valuation inputs. Give only one of them."
+                ));
+            }
+        }
+
+        Ok(Scenario {
+            thesis,
+            intrinsic_value,
+            probability: input.probability,
+            valuation: Some(input.valuation),
+            tags: input.tags,
+            macro_scenario: input.macro_scenario,
+        })
+    }
 }
 
 /// Two scenarios are considered equal if their theses are equal, irrespective of the numbers.
@@ -30,10 +145,17 @@ impl Hash for Scenario {
     }
 }
 
+impl Scenario {
+    /// Whether the scenario has the given tag.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|t| t == tag)
+    }
+}
+
 impl Validate for Scenario {
     /// Does all validations.
-    fn validate(&self) -> HashSet<ValidationResult> {
-        HashSet::from([self.validate_probability_bounds()])
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        IndexSet::from([self.validate_probability_bounds()])
     }
 }
 
@@ -58,6 +180,7 @@ impl Scenario {
                     self.probability
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             });
         }
 
@@ -69,6 +192,7 @@ impl Scenario {
                     self.probability
                 ),
                 severity: Severity::ERROR,
+                suggestion: None,
             });
         }
 
@@ -88,6 +212,7 @@ mod test {
             thesis: "Liquidation value".to_string(),
             intrinsic_value: 1e6,
             probability: 0.6,
+            ..Default::default()
         };
         let test_str = serde_yaml::to_string(&test_scenario).unwrap();
 
@@ -112,12 +237,59 @@ mod test {
         assert_eq!(test_scenario.probability, 0.6);
     }
 
+    #[test]
+    fn test_scenario_valuation_inputs() {
+        let scenario: Scenario = serde_yaml::from_str(
+            "
+            thesis: Steady earnings
+            earnings: 2.5
+            multiple: 12
+            probability: 0.6
+            ",
+        )
+        .unwrap();
+        assert_close!(30.0, scenario.intrinsic_value, TOLERANCE);
+
+        // Both the inputs and the derived intrinsic value are serialized, and read back
+        let serialized = serde_yaml::to_string(&scenario).unwrap();
+        assert_eq!(
+            "thesis: Steady earnings\nintrinsic_value: 30.0\nprobability: 0.6\nearnings: 2.5\n\
+            multiple: 12.0\n",
+            serialized
+        );
+        let read_back: Scenario = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(scenario.valuation, read_back.valuation);
+
+        let scenario: Scenario =
+            serde_yaml::from_str("{thesis: Cash cow, fcf: 4, yield: 0.08, probability: 0.4}")
+                .unwrap();
+        assert_close!(50.0, scenario.intrinsic_value, TOLERANCE);
+
+        let error = |yaml: &str| {
+            serde_yaml::from_str::<Scenario>(yaml)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("{thesis: A, probability: 0.4}").contains("requires an intrinsic value"));
+        assert!(error("{thesis: A, earnings: 1, probability: 0.4}").contains("Invalid valuation"));
+        assert!(error(
+            "{thesis: A, earnings: 1, multiple: 2, fcf: 1, yield: 0.1, probability: 0.4}"
+        )
+        .contains("Invalid valuation"));
+        assert!(error("{thesis: A, fcf: 1, yield: 0, probability: 0.4}").contains("positive"));
+        assert!(error(
+            "{thesis: A, intrinsic_value: 3, earnings: 1, multiple: 2, probability: 0.4}"
+        )
+        .contains("doesn't match"));
+    }
+
     #[test]
     fn test_scenario_return() {
         let test_scenario = Scenario {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1e6,
             probability: 0.2,
+            ..Default::default()
         };
         assert_close!(test_scenario.scenario_return(2e6), -0.5, 1e-10);
     }
@@ -128,6 +300,7 @@ mod test {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1e6,
             probability: 0.2,
+            ..Default::default()
         };
         assert_close!(test_scenario.probability_weighted_return(2e6), -0.1, 1e-10);
     }
@@ -138,13 +311,15 @@ mod test {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1e10,
             probability: -0.2,
+            ..Default::default()
         };
         assert_eq!(
             test_scenario.validate(),
-            HashSet::from([ValidationResult::PROBLEM(Problem {
+            IndexSet::from([ValidationResult::PROBLEM(Problem {
                 code: "negative-probability-for-scenario".to_string(),
                 message: "Negative probability is not allowed. Probability: -0.2".to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })])
         );
     }
@@ -155,13 +330,15 @@ mod test {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1e10,
             probability: 1.2,
+            ..Default::default()
         };
         assert_eq!(
             test_scenario.validate(),
-            HashSet::from([ValidationResult::PROBLEM(Problem {
+            IndexSet::from([ValidationResult::PROBLEM(Problem {
                 code: "probability-for-scenario-greater-than-one".to_string(),
                 message: "Probability greater than 1 is not allowed. Probability: 1.2".to_string(),
                 severity: Severity::ERROR,
+                suggestion: None,
             })])
         );
     }
@@ -172,11 +349,13 @@ mod test {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1.2e7,
             probability: 0.3,
+            ..Default::default()
         };
         let test_scenario_2 = Scenario {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1.2e8,
             probability: 0.4,
+            ..Default::default()
         };
         assert_eq!(test_scenario_1, test_scenario_2)
     }
@@ -187,11 +366,13 @@ mod test {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1.2e7,
             probability: 0.3,
+            ..Default::default()
         };
         let test_scenario_2 = Scenario {
             thesis: "Awesome thesis".to_string(),
             intrinsic_value: 1.2e8,
             probability: 0.4,
+            ..Default::default()
         };
 
         let mut hasher = DefaultHasher::new();
@@ -0,0 +1,53 @@
+use crate::model::allocations::FractionChange;
+use crate::model::portfolio::Portfolio;
+use crate::model::responses::{AnalysisResult, TickerAndFraction};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Analysis of a named portfolio at a point in time, recorded with the analyzed portfolio such that
+/// the drift of the risk can be tracked as the prices and the weights change.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AnalysisSnapshot {
+    /// Version of the snapshot within the portfolio, starting at 1.
+    pub version: u64,
+    pub created_at: DateTime<Utc>,
+    /// Version of the crate that performed the analysis.
+    pub crate_version: String,
+    pub portfolio: Portfolio,
+    pub analysis: AnalysisResult,
+}
+
+/// Evolution of a named portfolio across its snapshots, oldest first.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SnapshotHistory {
+    pub name: String,
+    pub snapshots: Vec<SnapshotSummary>,
+}
+
+/// Expected return, tail risk and weights of a snapshot, with the changes since the previous one.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SnapshotSummary {
+    pub version: u64,
+    pub created_at: DateTime<Utc>,
+    pub expected_return: f64,
+    /// Portfolio return in the worst case outcome.
+    pub worst_case_return: f64,
+    /// Not calculated on the summary detail level, since it requires enumerating all outcomes.
+    pub cumulative_probability_of_loss: Option<f64>,
+    pub weights: Vec<TickerAndFraction>,
+    /// Changes since the previous snapshot, None for the first snapshot.
+    pub changes: Option<SnapshotChanges>,
+}
+
+/// Changes between two consecutive snapshots of the same portfolio.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SnapshotChanges {
+    pub expected_return_change: f64,
+    pub worst_case_return_change: f64,
+    /// None unless the probability of loss is calculated in both snapshots.
+    pub cumulative_probability_of_loss_change: Option<f64>,
+    /// Change of the weight of each ticker in either of the snapshots. A ticker missing from one
+    /// of the snapshots has the weight 0 there.
+    pub weight_changes: Vec<FractionChange>,
+}
@@ -0,0 +1,166 @@
+use crate::model::responses::TickerAndFraction;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use crate::validation::validate::Validate;
+use indexmap::IndexSet;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound of the maximum number of iterations of the solver settings.
+const MAX_ITERATIONS: u32 = 10000;
+
+/// Upper bound of the solver tolerance of the solver settings, beyond which the fractions are too
+/// inaccurate to be useful.
+const MAX_SOLVER_TOLERANCE: f64 = 1e-2;
+
+/// Settings of the nonlinear solver used for the allocation.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct SolverSettings {
+    #[serde(default)]
+    pub strategy: Option<SolverStrategy>,
+
+    /// Strength of the L2 (ridge) penalty on the allocation fractions, i.e. the solver maximizes
+    /// the expected logarithmic growth minus 0.5 * regularization * (sum of squared fractions).
+    /// This stabilizes ill-conditioned problems and produces more diversified allocations. No
+    /// regularization if not specified.
+    #[serde(default)]
+    pub regularization: Option<f64>,
+
+    /// Maximum number of nonlinear systems to solve, which is 2 to the power of the number of
+    /// inequality constraints. Allocations exceeding it are refused with an estimate of the runtime
+    /// and the constraints to remove. Defaults to 2^22 = 4194304.
+    #[serde(default)]
+    pub max_systems: Option<usize>,
+
+    /// Maximum number of Newton-Raphson iterations for solving a single system, between 1 and
+    /// 10,000. Fewer iterations bound the runtime, more iterations help stiff inputs converge.
+    /// Defaults to 100.
+    #[serde(default)]
+    pub max_iterations: Option<u32>,
+
+    /// Absolute tolerance of the Newton step at which the iteration is considered converged,
+    /// greater than 0 and at most 0.01. Larger tolerances trade accuracy of the fractions for
+    /// fewer iterations. Defaults to 1e-5.
+    #[serde(default)]
+    pub solver_tolerance: Option<f64>,
+
+    /// Fraction of the Newton step taken by the penalty method, and by the plain strategy if the
+    /// damped step doesn't keep the growth from decreasing, greater than 0 and at most 1. Defaults
+    /// to 0.7.
+    #[serde(default)]
+    pub relaxation_factor: Option<f64>,
+
+    /// Fractions the Newton-Raphson iteration starts from, e.g. the previous allocation of the same
+    /// candidates, which reduces the number of iterations considerably for stiff inputs. Candidates
+    /// without an initial fraction start from zero. The uniform allocation if not specified.
+    #[serde(default)]
+    pub initial_fractions: Option<Vec<TickerAndFraction>>,
+}
+
+impl Validate for SolverSettings {
+    /// Does all validations
+    fn validate(&self) -> IndexSet<ValidationResult> {
+        let mut validation_results: IndexSet<ValidationResult> = IndexSet::new();
+
+        if let Some(regularization) = self.regularization {
+            if !(0.0..).contains(&regularization) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "regularization-cannot-be-negative".to_string(),
+                    message: format!(
+                        "Regularization strength cannot be negative. You provided \
+                        {regularization}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if self.max_systems == Some(0) {
+            validation_results.insert(ValidationResult::PROBLEM(Problem {
+                code: "maximum-number-of-systems-must-be-positive".to_string(),
+                message: "Maximum number of systems to solve must be at least 1.".to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }));
+        }
+
+        if let Some(max_iterations) = self.max_iterations {
+            if !(1..=MAX_ITERATIONS).contains(&max_iterations) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "maximum-number-of-iterations-out-of-range".to_string(),
+                    message: format!(
+                        "Maximum number of iterations must be between 1 and {MAX_ITERATIONS}. You \
+                        provided {max_iterations}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(solver_tolerance) = self.solver_tolerance {
+            if !(solver_tolerance > 0.0 && solver_tolerance <= MAX_SOLVER_TOLERANCE) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "solver-tolerance-out-of-range".to_string(),
+                    message: format!(
+                        "Solver tolerance must be greater than 0 and at most \
+                        {MAX_SOLVER_TOLERANCE}. You provided {solver_tolerance}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(relaxation_factor) = self.relaxation_factor {
+            if !(relaxation_factor > 0.0 && relaxation_factor <= 1.0) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "relaxation-factor-out-of-range".to_string(),
+                    message: format!(
+                        "Relaxation factor must be greater than 0 and at most 1. You provided \
+                        {relaxation_factor}."
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        if let Some(initial_fractions) = &self.initial_fractions {
+            for initial in initial_fractions.iter().filter(|f| !f.fraction.is_finite()) {
+                validation_results.insert(ValidationResult::PROBLEM(Problem {
+                    code: "initial-fraction-must-be-finite".to_string(),
+                    message: format!(
+                        "Initial fraction of {} must be a finite number. You provided {}.",
+                        initial.ticker, initial.fraction
+                    ),
+                    severity: Severity::ERROR,
+                    suggestion: None,
+                }));
+            }
+        }
+
+        validation_results
+    }
+}
+
+/// Globalization strategy of the Newton-Raphson iteration, i.e. how the Newton step is used to
+/// update the solution:
+/// - Plain: The Newton step is halved until the growth doesn't decrease, or only until the wealth
+///   stays positive in all outcomes for the systems with active constraints.
+/// - LineSearch: The Newton step is shortened by backtracking until the norm of the residual
+///   decreases sufficiently.
+/// - TrustRegion: A dogleg step between the steepest descent and the Newton step is taken within a
+///   trust region, whose radius is adapted based on how well the linear model predicts the
+///   decrease of the residual. Most robust for stiff problems, e.g. heavily levered bets.
+/// - Auto: Plain strategy, escalating to line search and then to trust region for the systems
+///   where the previous strategy fails. This is the default.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SolverStrategy {
+    Plain,
+    LineSearch,
+    TrustRegion,
+    #[default]
+    Auto,
+}
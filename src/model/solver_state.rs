@@ -0,0 +1,50 @@
+use crate::model::company::Ticker;
+use crate::model::portfolio::AllocationInput;
+use crate::model::responses::TickerAndFraction;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Snapshot of the solver for an allocation, for reproducing it exactly, e.g. months later and on
+/// another machine. The solver doesn't draw random numbers, so the input with its solver settings
+/// determines the allocation, and the solution of each system shows where a reproduction deviates.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SolverState {
+    /// Version of the library that performed the allocation.
+    pub version: String,
+    pub input: AllocationInput,
+    /// Systems solved for the allocation, in the order in which they were solved.
+    pub systems: Vec<SystemState>,
+    pub allocations: Vec<TickerAndFraction>,
+}
+
+/// Converged solution of a single system for a choice of candidates and an activity mask of the
+/// inequality constraints.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct SystemState {
+    pub tickers: Vec<Ticker>,
+    /// Activity of each inequality constraint, not given if the solver finds the active
+    /// constraints itself, i.e. with the projected Newton or the penalty method.
+    #[serde(default)]
+    pub active_constraints: Option<Vec<bool>>,
+    /// Fractions of the candidates followed by the slack variables and the Lagrange multipliers,
+    /// not given if the system couldn't be solved.
+    #[serde(default)]
+    pub solution: Option<Vec<f64>>,
+    pub is_viable: bool,
+}
+
+/// Comparison of a recorded solver state with the state of the same allocation performed again.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct SolverStateComparison {
+    /// Version of the library that performed the allocation again.
+    pub version: String,
+    /// Whether the same systems were solved with bitwise identical solutions and allocations.
+    pub is_exact: bool,
+    pub n_recorded_systems: usize,
+    pub n_reproduced_systems: usize,
+    /// Index of the first system whose tickers, activity mask or solution differs, if any.
+    #[serde(default)]
+    pub first_differing_system: Option<usize>,
+    /// Largest absolute difference of the fractions of the recorded and the reproduced allocation.
+    pub max_fraction_difference: f64,
+}
@@ -0,0 +1,353 @@
+use crate::model::errors::Error;
+use crate::model::portfolio::AllocationInput;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_yaml::value::TaggedValue;
+use serde_yaml::Value as YamlValue;
+use std::collections::{HashMap, HashSet};
+
+/// Opening delimiter of a placeholder in a template.
+const PLACEHOLDER_START: &str = "{{";
+
+/// Closing delimiter of a placeholder in a template.
+const PLACEHOLDER_END: &str = "}}";
+
+/// Delimiter of the markers standing in for the placeholders while parsing a template.
+const MARKER_DELIMITER: &str = "__";
+
+/// Input template is an allocation input in YAML (or JSON) format, with placeholders of the form
+/// `{{parameter_name}}` that are substituted with actual values before allocation. This allows
+/// keeping a large set of candidates on the server, while only varying a few parameters.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct InputTemplate {
+    pub template: String,
+}
+
+/// Parameters used to instantiate an input template, given as parameter name to value mapping.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TemplateParameters {
+    #[serde(default)]
+    pub parameters: HashMap<String, Value>,
+}
+
+impl InputTemplate {
+    /// Returns the names of all placeholders found in the template.
+    pub fn placeholders(&self) -> HashSet<String> {
+        let mut placeholders: HashSet<String> = HashSet::new();
+        let mut rest: &str = &self.template;
+        while let Some(start) = rest.find(PLACEHOLDER_START) {
+            let after_start = &rest[start + PLACEHOLDER_START.len()..];
+            match after_start.find(PLACEHOLDER_END) {
+                Some(end) => {
+                    placeholders.insert(after_start[..end].trim().to_string());
+                    rest = &after_start[end + PLACEHOLDER_END.len()..];
+                }
+                None => break,
+            }
+        }
+
+        placeholders
+    }
+
+    /// Checks that the template is valid YAML once the placeholders are given values, such that a
+    /// broken template is rejected when it's stored rather than when it's instantiated.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.parse().map(|_| ())
+    }
+
+    /// Substitutes all the placeholders with the given parameters and deserializes the result into
+    /// an [AllocationInput]. Fails if a placeholder doesn't have a parameter, or if the result is
+    /// not a valid allocation input.
+    ///
+    /// The parameters are inserted as typed values into the parsed template rather than into its
+    /// text, so a parameter can't change the structure of the input: a placeholder standing for a
+    /// whole value is replaced by the parameter as is, e.g. a number, a string or a list, while a
+    /// placeholder within a string is replaced by the parameter formatted as text.
+    pub fn instantiate(&self, parameters: &TemplateParameters) -> Result<AllocationInput, Error> {
+        let mut missing_parameters = self
+            .placeholders()
+            .into_iter()
+            .filter(|p| !parameters.parameters.contains_key(p))
+            .collect::<Vec<String>>();
+        if !missing_parameters.is_empty() {
+            missing_parameters.sort();
+            return Err(Error {
+                code: "missing-template-parameters".to_string(),
+                message: format!(
+                    "Template parameters {} are not provided. Provide a value for each placeholder \
+                    in the template.",
+                    missing_parameters.join(", ")
+                ),
+            });
+        }
+
+        let (parsed, names) = self.parse()?;
+        let substitutions = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let parameter = &parameters.parameters[name];
+                let text = match parameter {
+                    Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                let value = serde_yaml::to_value(parameter).map_err(|e| Error {
+                    code: "invalid-template-parameter".to_string(),
+                    message: format!("Template parameter {name} can't be inserted: {e}."),
+                })?;
+                Ok((marker(i), value, text))
+            })
+            .collect::<Result<Vec<Substitution>, Error>>()?;
+
+        serde_yaml::from_value(substitute(parsed, &substitutions)).map_err(|e| Error {
+            code: "invalid-instantiated-template".to_string(),
+            message: format!(
+                "Instantiated template is not a valid allocation input: {e}. Check the template \
+                and the parameters."
+            ),
+        })
+    }
+
+    /// Parses the template with each placeholder replaced by a marker, and returns the parsed
+    /// template together with the parameter names, indexed by the number of their markers.
+    fn parse(&self) -> Result<(YamlValue, Vec<String>), Error> {
+        // Replace the placeholders in a single pass through the template
+        let mut names: Vec<String> = Vec::new();
+        let mut with_markers = String::with_capacity(self.template.len());
+        let mut rest: &str = &self.template;
+        while let Some(start) = rest.find(PLACEHOLDER_START) {
+            let after_start = &rest[start + PLACEHOLDER_START.len()..];
+            let end = match after_start.find(PLACEHOLDER_END) {
+                Some(e) => e,
+                None => break,
+            };
+
+            with_markers.push_str(&rest[..start]);
+            let name = after_start[..end].trim();
+            let index = match names.iter().position(|n| n == name) {
+                Some(i) => i,
+                None => {
+                    names.push(name.to_string());
+                    names.len() - 1
+                }
+            };
+            with_markers.push_str(&marker(index));
+            rest = &after_start[end + PLACEHOLDER_END.len()..];
+        }
+        with_markers.push_str(rest);
+
+        // YAML is a superset of JSON, so both formats are supported
+        let parsed = serde_yaml::from_str(&with_markers).map_err(|e| Error {
+            code: "invalid-template".to_string(),
+            message: format!(
+                "Template is not valid YAML: {e}. A placeholder stands for a whole value, e.g. \
+                `max_total_leverage_ratio: {{{{max_leverage}}}}`, or for a part of a string."
+            ),
+        })?;
+
+        Ok((parsed, names))
+    }
+}
+
+/// Marker of a placeholder, parameter value and its text inserted into strings.
+type Substitution = (String, YamlValue, String);
+
+/// Returns the marker standing in for the placeholder with the given index while parsing. It's a
+/// plain YAML scalar, so it's parsed as a string wherever the placeholder is, and it's delimited on
+/// both sides, so no marker is a part of another one.
+fn marker(index: usize) -> String {
+    format!("{MARKER_DELIMITER}charlie_template_parameter_{index}{MARKER_DELIMITER}")
+}
+
+/// Replaces the markers in the parsed template with the parameter values, or with their text where
+/// a marker is only a part of a string.
+fn substitute(value: YamlValue, substitutions: &[Substitution]) -> YamlValue {
+    match value {
+        YamlValue::String(s) => match substitutions.iter().find(|(m, _, _)| *m == s) {
+            Some((_, parameter, _)) => parameter.clone(),
+            None => YamlValue::String(
+                substitutions
+                    .iter()
+                    .fold(s, |s, (m, _, text)| s.replace(m, text)),
+            ),
+        },
+        YamlValue::Sequence(sequence) => YamlValue::Sequence(
+            sequence
+                .into_iter()
+                .map(|v| substitute(v, substitutions))
+                .collect(),
+        ),
+        YamlValue::Mapping(mapping) => YamlValue::Mapping(
+            mapping
+                .into_iter()
+                .map(|(k, v)| (substitute(k, substitutions), substitute(v, substitutions)))
+                .collect(),
+        ),
+        YamlValue::Tagged(tagged) => YamlValue::Tagged(Box::new(TaggedValue {
+            tag: tagged.tag,
+            value: substitute(tagged.value, substitutions),
+        })),
+        v => v,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_TEMPLATE: &str = "
+        max_total_leverage_ratio: {{ max_leverage }}
+        long_only: {{long_only}}
+        candidates:
+          - name: A
+            ticker: A
+            description: Business A
+            market_cap: 1.0
+            scenarios:
+              - thesis: Down
+                intrinsic_value: 0.5
+                probability: 0.5
+              - thesis: Up
+                intrinsic_value: {{upside}}
+                probability: 0.5
+    ";
+
+    #[test]
+    fn test_placeholders() {
+        let template = InputTemplate {
+            template: TEST_TEMPLATE.to_string(),
+        };
+
+        assert_eq!(
+            template.placeholders(),
+            HashSet::from([
+                "max_leverage".to_string(),
+                "long_only".to_string(),
+                "upside".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let template = InputTemplate {
+            template: TEST_TEMPLATE.to_string(),
+        };
+        let parameters = TemplateParameters {
+            parameters: HashMap::from([
+                ("max_leverage".to_string(), Value::from(0.5)),
+                ("long_only".to_string(), Value::from(true)),
+                ("upside".to_string(), Value::from(2.0)),
+            ]),
+        };
+
+        let input = template.instantiate(&parameters).unwrap();
+
+        assert_eq!(input.max_total_leverage_ratio, Some(0.5));
+        assert_eq!(input.long_only, Some(true));
+        assert_eq!(input.candidates[0].scenarios[1].intrinsic_value, 2.0);
+    }
+
+    #[test]
+    fn test_instantiate_with_missing_parameters() {
+        let template = InputTemplate {
+            template: TEST_TEMPLATE.to_string(),
+        };
+        let parameters = TemplateParameters {
+            parameters: HashMap::from([("long_only".to_string(), Value::from(true))]),
+        };
+
+        let e = template.instantiate(&parameters).err().unwrap();
+
+        assert_eq!(e.code, "missing-template-parameters");
+        assert_eq!(
+            e.message,
+            "Template parameters max_leverage, upside are not provided. Provide a value for each \
+            placeholder in the template."
+        );
+    }
+
+    #[test]
+    fn test_instantiate_invalid_input() {
+        let template = InputTemplate {
+            template: "candidates: {{candidates}}".to_string(),
+        };
+        let parameters = TemplateParameters {
+            parameters: HashMap::from([("candidates".to_string(), Value::from("none"))]),
+        };
+
+        let e = template.instantiate(&parameters).err().unwrap();
+
+        assert_eq!(e.code, "invalid-instantiated-template");
+    }
+
+    #[test]
+    fn test_instantiate_inserts_typed_values() {
+        let template = InputTemplate {
+            template: "
+                long_only: {{long_only}}
+                candidates:
+                  - name: A
+                    ticker: A
+                    description: \"{{description}}\"
+                    market_cap: 1.0
+                    scenarios:
+                      - thesis: Down to {{downside}}
+                        intrinsic_value: {{downside}}
+                        probability: 0.5
+                      - thesis: Up
+                        intrinsic_value: 2.0
+                        probability: 0.5
+            "
+            .to_string(),
+        };
+        let parameters = |description: &str, downside: Value| TemplateParameters {
+            parameters: HashMap::from([
+                ("long_only".to_string(), Value::from(true)),
+                ("description".to_string(), Value::from(description)),
+                ("downside".to_string(), downside),
+            ]),
+        };
+
+        // A parameter can't add settings or candidates by breaking out of its value
+        let injected = "A\"\nmax_total_leverage_ratio: 10\nx: \"";
+        let input = template
+            .instantiate(&parameters(injected, Value::from(0.5)))
+            .unwrap();
+        assert_eq!(input.long_only, Some(true));
+        assert_eq!(input.max_total_leverage_ratio, None);
+        assert_eq!(input.candidates[0].description, injected);
+        assert_eq!(input.candidates[0].scenarios[0].thesis, "Down to 0.5");
+        assert_eq!(input.candidates[0].scenarios[0].intrinsic_value, 0.5);
+
+        // Strings are inserted as strings, also where a number is expected
+        let e = template
+            .instantiate(&parameters("A", Value::from("0.5")))
+            .err()
+            .unwrap();
+        assert_eq!(e.code, "invalid-instantiated-template");
+    }
+
+    #[test]
+    fn test_validate() {
+        let template = |template: &str| InputTemplate {
+            template: template.to_string(),
+        };
+
+        assert!(template(TEST_TEMPLATE).validate().is_ok());
+        assert!(
+            template("{\"kelly_fraction\": {{fraction}}, \"candidates\": []}")
+                .validate()
+                .is_ok()
+        );
+        assert_eq!(
+            template("candidates: [{{candidates}}")
+                .validate()
+                .err()
+                .unwrap()
+                .code,
+            "invalid-template"
+        );
+    }
+}
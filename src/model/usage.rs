@@ -0,0 +1,49 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::ops::AddAssign;
+
+/// Resources used by the requests of an API key.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub requests: u64,
+    /// Time spent allocating or analyzing on the threads dedicated to blocking work. The work is
+    /// CPU-bound, so this is close to the CPU time.
+    pub cpu_seconds: f64,
+    /// Number of nonlinear systems solved by the allocations.
+    pub n_systems: u64,
+}
+
+impl AddAssign<&Usage> for Usage {
+    fn add_assign(&mut self, other: &Usage) {
+        self.requests += other.requests;
+        self.cpu_seconds += other.cpu_seconds;
+        self.n_systems += other.n_systems;
+    }
+}
+
+/// Monthly limits of the usage of an API key. Requests are rejected once any of the limits is
+/// reached, until the next month (UTC). No limit if not specified.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct Quota {
+    #[serde(default)]
+    pub max_requests: Option<u64>,
+    #[serde(default)]
+    pub max_cpu_seconds: Option<f64>,
+    #[serde(default)]
+    pub max_n_systems: Option<u64>,
+}
+
+/// Usage of a single month, e.g. 2024-06.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct MonthlyUsage {
+    pub month: String,
+    pub usage: Usage,
+}
+
+/// Usage of an API key per month, oldest first, together with its quota.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct UsageReport {
+    pub api_key: String,
+    pub months: Vec<MonthlyUsage>,
+    pub quota: Option<Quota>,
+}
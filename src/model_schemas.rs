@@ -0,0 +1,56 @@
+use crate::model::call_options::CallOption;
+use crate::model::capital_loss::CapitalLoss;
+use crate::model::company::Company;
+use crate::model::pair_trades::PairTrade;
+use crate::model::portfolio::{AllocationInput, CandidateGroup, PositionDownsideBudget};
+use crate::model::scenario::Scenario;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Model types whose JSON Schemas are served individually, e.g. for front-ends rendering an editor
+/// for each type from its schema.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelType {
+    AllocationInput,
+    Company,
+    Scenario,
+    CallOption,
+    PairTrade,
+    CapitalLoss,
+    CandidateGroup,
+    PositionDownsideBudget,
+}
+
+/// Returns the JSON Schema of the model type, generated at runtime such that it always matches the
+/// model, including the definitions of all the types it refers to.
+pub fn model_schema(model_type: ModelType) -> Value {
+    let schema = match model_type {
+        ModelType::AllocationInput => schema_for!(AllocationInput),
+        ModelType::Company => schema_for!(Company),
+        ModelType::Scenario => schema_for!(Scenario),
+        ModelType::CallOption => schema_for!(CallOption),
+        ModelType::PairTrade => schema_for!(PairTrade),
+        ModelType::CapitalLoss => schema_for!(CapitalLoss),
+        ModelType::CandidateGroup => schema_for!(CandidateGroup),
+        ModelType::PositionDownsideBudget => schema_for!(PositionDownsideBudget),
+    };
+    serde_json::to_value(schema).expect("Failed to convert JSON Schema to JSON.")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_model_schema() {
+        let schema = model_schema(ModelType::Company);
+        assert_eq!("Company", schema["title"]);
+        assert!(schema["properties"]["scenarios"].is_object());
+        assert!(schema["definitions"]["Scenario"].is_object());
+
+        let schema = model_schema(ModelType::CapitalLoss);
+        assert_eq!("CapitalLoss", schema["title"]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::analysis;
+use crate::analysis::all_outcomes;
+use crate::kelly_allocation::{FinancingRates, KellyAllocator};
+use crate::model::errors::Error;
+use crate::model::portfolio::Portfolio;
+
+/// Calculates the expected logarithmic growth rate of capital at the fractions of the portfolio,
+/// i.e. the objective maximized by the allocation. Together with [growth_gradient], this allows
+/// external optimizers to use the model as an objective function without running the solver.
+pub fn expected_log_growth(portfolio: &Portfolio) -> Result<f64, Error> {
+    Ok(analysis::expected_log_growth(&all_outcomes(portfolio)?))
+}
+
+/// Calculates the gradient of the expected logarithmic growth rate with respect to the fractions
+/// of the portfolio, in the order of its companies. The gradient vanishes at the unconstrained
+/// Kelly allocation.
+pub fn growth_gradient(portfolio: &Portfolio) -> Result<Vec<f64>, Error> {
+    let outcomes = all_outcomes(portfolio)?;
+    Ok(
+        KellyAllocator::criterion(&outcomes, portfolio, FinancingRates::default(), &[])
+            .iter()
+            .copied()
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::Company;
+    use crate::model::portfolio::PortfolioCompany;
+    use crate::model::scenario::Scenario;
+    use crate::utils::assert_close;
+
+    fn coin_flip(ticker: &str, fraction: f64) -> PortfolioCompany {
+        PortfolioCompany {
+            company: Company {
+                name: ticker.to_string(),
+                ticker: ticker.to_string(),
+                description: ticker.to_string(),
+                market_cap: 1.0,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
+                scenarios: vec![
+                    Scenario {
+                        thesis: "Head".to_string(),
+                        intrinsic_value: 2.0,
+                        probability: 0.6,
+                        ..Default::default()
+                    },
+                    Scenario {
+                        thesis: "Tail".to_string(),
+                        intrinsic_value: 0.0,
+                        probability: 0.4,
+                        ..Default::default()
+                    },
+                ],
+            },
+            fraction,
+        }
+    }
+
+    #[test]
+    fn test_expected_log_growth_and_gradient() {
+        // Kelly fraction of a single even-money bet with a 60% chance of winning is 0.2
+        let kelly = Portfolio {
+            companies: vec![coin_flip("A", 0.2)],
+            ..Default::default()
+        };
+        assert_close!(
+            0.6 * 1.2_f64.ln() + 0.4 * 0.8_f64.ln(),
+            expected_log_growth(&kelly).unwrap(),
+            1e-12
+        );
+        assert_close!(0.0, growth_gradient(&kelly).unwrap()[0], 1e-12);
+
+        // Gradient matches central finite differences of the growth
+        let portfolio = Portfolio {
+            companies: vec![coin_flip("A", 0.3), coin_flip("B", 0.1)],
+            ..Default::default()
+        };
+        let gradient = growth_gradient(&portfolio).unwrap();
+        let step = 1e-6;
+        for (i, derivative) in gradient.iter().enumerate() {
+            let mut forward = portfolio.clone();
+            forward.companies[i].fraction += step;
+            let mut backward = portfolio.clone();
+            backward.companies[i].fraction -= step;
+            assert_close!(
+                (expected_log_growth(&forward).unwrap() - expected_log_growth(&backward).unwrap())
+                    / (2.0 * step),
+                *derivative,
+                1e-8
+            );
+        }
+    }
+}
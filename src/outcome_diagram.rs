@@ -0,0 +1,233 @@
+use crate::analysis::{all_outcomes, outcome_scenario_indices};
+use crate::model::errors::Error;
+use crate::model::portfolio::{Portfolio, PortfolioCompany};
+use itertools::Itertools;
+use ordered_float::OrderedFloat;
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+/// Default number of the most probable joint outcomes shown in the diagram.
+pub const MAX_DIAGRAM_OUTCOMES: usize = 10;
+
+/// Format of the outcome diagram:
+/// - Mermaid: Flowchart that renders e.g. in Markdown on GitLab and GitHub.
+/// - Dot: Graphviz graph that renders e.g. with `dot -Tsvg`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiagramFormat {
+    #[default]
+    Mermaid,
+    Dot,
+}
+
+impl FromStr for DiagramFormat {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mermaid" => Ok(DiagramFormat::Mermaid),
+            "dot" => Ok(DiagramFormat::Dot),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected \"mermaid\" or \"dot\" as diagram format, got {s}"),
+            )),
+        }
+    }
+}
+
+/// Node of the diagram with its label split into lines.
+struct Node {
+    id: String,
+    lines: Vec<String>,
+}
+
+/// Renders the outcome tree of the portfolio as a diagram: each company links to its scenarios,
+/// annotated with their probabilities and returns, and each scenario links to the joint outcomes
+/// it is part of, annotated with their probabilities and portfolio returns. Since the number of
+/// joint outcomes grows exponentially with the number of companies, only the given number of the
+/// most probable outcomes is shown.
+pub fn outcome_diagram(
+    portfolio: &Portfolio,
+    format: DiagramFormat,
+    max_outcomes: usize,
+) -> Result<String, Error> {
+    // Make the implicit status quo scenarios explicit
+    let portfolio = &Portfolio {
+        companies: portfolio
+            .companies
+            .iter()
+            .map(|pc| PortfolioCompany {
+                company: pc.company.with_status_quo_scenario(),
+                fraction: pc.fraction,
+            })
+            .collect(),
+        macro_scenarios: portfolio.macro_scenarios.clone(),
+    };
+    let outcomes = all_outcomes(portfolio)?;
+
+    let mut nodes: Vec<Node> = vec![];
+    let mut edges: Vec<(String, String)> = vec![];
+    for (c_id, pc) in portfolio.companies.iter().enumerate() {
+        let company_id = format!("c{c_id}");
+        nodes.push(Node {
+            id: company_id.clone(),
+            lines: vec![
+                pc.company.ticker.clone(),
+                format!("fraction {:.1}%", 100.0 * pc.fraction),
+            ],
+        });
+
+        for (s_id, s) in pc.company.scenarios.iter().enumerate() {
+            let scenario_id = format!("c{c_id}s{s_id}");
+            nodes.push(Node {
+                id: scenario_id.clone(),
+                lines: vec![
+                    s.thesis.clone(),
+                    format!(
+                        "p = {:.3}, return {:+.1}%",
+                        s.probability,
+                        100.0 * s.scenario_return(pc.company.market_cap)
+                    ),
+                ],
+            });
+            edges.push((company_id.clone(), scenario_id));
+        }
+    }
+
+    // Most probable outcomes first, keeping the order of all outcomes for equal probabilities
+    let shown_outcomes = outcomes
+        .iter()
+        .enumerate()
+        .sorted_by_key(|(_, o)| OrderedFloat(-o.probability))
+        .take(max_outcomes);
+    for (o_id, o) in shown_outcomes {
+        let outcome_id = format!("o{o_id}");
+        nodes.push(Node {
+            id: outcome_id.clone(),
+            lines: vec![
+                format!("Outcome {}", o_id + 1),
+                format!(
+                    "p = {:.4}, portfolio return {:+.1}%",
+                    o.probability,
+                    100.0 * o.weighted_return
+                ),
+            ],
+        });
+        for (c_id, s_id) in outcome_scenario_indices(portfolio, o_id)
+            .into_iter()
+            .enumerate()
+        {
+            edges.push((format!("c{c_id}s{s_id}"), outcome_id.clone()));
+        }
+    }
+
+    Ok(match format {
+        DiagramFormat::Mermaid => mermaid(&nodes, &edges),
+        DiagramFormat::Dot => dot(&nodes, &edges),
+    })
+}
+
+fn mermaid(nodes: &[Node], edges: &[(String, String)]) -> String {
+    let mut diagram = "flowchart LR\n".to_string();
+    for node in nodes {
+        let label = node
+            .lines
+            .iter()
+            .map(|l| l.replace('"', "#quot;"))
+            .join("<br/>");
+        diagram.push_str(&format!("    {}[\"{label}\"]\n", node.id));
+    }
+    for (from, to) in edges {
+        diagram.push_str(&format!("    {from} --> {to}\n"));
+    }
+    diagram
+}
+
+fn dot(nodes: &[Node], edges: &[(String, String)]) -> String {
+    let mut diagram = "digraph outcomes {\n    rankdir=LR;\n    node [shape=box];\n".to_string();
+    for node in nodes {
+        let label = node
+            .lines
+            .iter()
+            .map(|l| l.replace('\\', "\\\\").replace('"', "\\\""))
+            .join("\\n");
+        diagram.push_str(&format!("    {} [label=\"{label}\"];\n", node.id));
+    }
+    for (from, to) in edges {
+        diagram.push_str(&format!("    {from} -> {to};\n"));
+    }
+    diagram.push_str("}\n");
+    diagram
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::Company;
+    use crate::model::scenario::Scenario;
+
+    fn coin_flips() -> Portfolio {
+        Portfolio {
+            companies: ["A", "B"]
+                .into_iter()
+                .map(|ticker| PortfolioCompany {
+                    company: Company {
+                        name: ticker.to_string(),
+                        ticker: ticker.to_string(),
+                        description: ticker.to_string(),
+                        market_cap: 1.0,
+                        auto_status_quo: None,
+                        units: None,
+                        last_reviewed: None,
+                        scenarios: vec![
+                            Scenario {
+                                thesis: "\"Head\"".to_string(),
+                                intrinsic_value: 2.0,
+                                probability: 0.6,
+                                ..Default::default()
+                            },
+                            Scenario {
+                                thesis: "Tail".to_string(),
+                                intrinsic_value: 0.5,
+                                probability: 0.4,
+                                ..Default::default()
+                            },
+                        ],
+                    },
+                    fraction: 0.5,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mermaid_outcome_diagram() {
+        let diagram = outcome_diagram(&coin_flips(), DiagramFormat::Mermaid, 2).unwrap();
+
+        assert!(diagram.starts_with("flowchart LR\n"));
+        assert!(diagram.contains("    c0[\"A<br/>fraction 50.0%\"]\n"));
+        assert!(diagram.contains("    c0s0[\"#quot;Head#quot;<br/>p = 0.600, return +100.0%\"]\n"));
+        assert!(diagram.contains("    c0 --> c0s1\n"));
+
+        // Only the two most probable outcomes are shown, i.e. both heads followed by tail of A
+        // and head of B
+        assert!(
+            diagram.contains("    o0[\"Outcome 1<br/>p = 0.3600, portfolio return +100.0%\"]\n")
+        );
+        assert!(diagram.contains("    c0s1 --> o1\n"));
+        assert!(diagram.contains("    c1s0 --> o1\n"));
+        assert!(!diagram.contains("o2"));
+    }
+
+    #[test]
+    fn test_dot_outcome_diagram() {
+        let diagram = outcome_diagram(&coin_flips(), DiagramFormat::Dot, 10).unwrap();
+
+        assert!(diagram.starts_with("digraph outcomes {\n"));
+        assert!(
+            diagram.contains("    c0s0 [label=\"\\\"Head\\\"\\np = 0.600, return +100.0%\"];\n")
+        );
+        assert!(diagram.contains("    c1s1 -> o3;\n"));
+        assert!(diagram.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,293 @@
+use dropshot::{ApiEndpointResponse, HttpError, HttpResponse, RequestInfo};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, ETAG, IF_NONE_MATCH, VARY};
+use http::{HeaderValue, Method, Response, StatusCode};
+use hyper::body::Bytes;
+use hyper::Body;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::marker::PhantomData;
+
+/// Responses shorter than this are sent uncompressed, since compressing them saves little.
+const MIN_COMPRESSED_LENGTH: usize = 1024;
+
+/// Content codings supported for compressing the responses, in the order of preference among the
+/// codings accepted with the same quality.
+const CONTENT_CODINGS: [&str; 3] = ["br", "gzip", "deflate"];
+
+/// Quality of the brotli compression, lower than the maximum of 11 which is too slow for responses
+/// compressed on the fly.
+const BROTLI_QUALITY: u32 = 5;
+
+/// Base two logarithm of the window size of the brotli compression.
+const BROTLI_WINDOW: u32 = 22;
+
+/// Response of type `T` with a weak ETag derived from its body, compressed according to the
+/// Accept-Encoding header of the request. It's documented in the OpenAPI schema as `T`.
+pub struct HttpResponseEncoded<T> {
+    response: Response<Body>,
+    response_type: PhantomData<fn() -> T>,
+}
+
+impl<T: HttpResponse> HttpResponse for HttpResponseEncoded<T> {
+    fn to_result(self) -> Result<Response<Body>, HttpError> {
+        Ok(self.response)
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        T::response_metadata()
+    }
+}
+
+/// Adds an ETag to a successful response and compresses its body with the supported content coding
+/// that the request accepts with the highest quality. A GET request whose If-None-Match header
+/// matches the ETag gets an empty response with status 304 (not modified) instead.
+pub async fn encode_response<T: HttpResponse>(
+    request: &RequestInfo,
+    response: T,
+) -> Result<HttpResponseEncoded<T>, HttpError> {
+    let (mut parts, body) = response.to_result()?.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("Failed to read the response: {e}")))?;
+
+    if parts.status.is_success() {
+        let etag = etag(&body);
+        parts.headers.insert(ETAG, etag.clone());
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("accept-encoding"));
+
+        let is_get = request.method() == Method::GET || request.method() == Method::HEAD;
+        if is_get && matches_etag(request.headers().get(IF_NONE_MATCH), &etag) {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove(http::header::CONTENT_TYPE);
+            return Ok(encoded(Response::from_parts(parts, Body::empty())));
+        }
+    }
+
+    let coding = accepted_coding(request.headers().get(ACCEPT_ENCODING));
+    let body = match coding {
+        Some(coding) if body.len() >= MIN_COMPRESSED_LENGTH => {
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(coding));
+            compress(&body, coding)
+                .map_err(|e| HttpError::for_internal_error(format!("Failed to compress: {e}")))?
+        }
+        _ => body.to_vec(),
+    };
+
+    Ok(encoded(Response::from_parts(parts, Body::from(body))))
+}
+
+fn encoded<T>(response: Response<Body>) -> HttpResponseEncoded<T> {
+    HttpResponseEncoded {
+        response,
+        response_type: PhantomData,
+    }
+}
+
+/// Weak ETag of the uncompressed body, which is the same for all the content codings. It's derived
+/// from the first 128 bits of the SHA-256 of the body, which are stable across Rust versions and
+/// server restarts, unlike the hashers of the standard library.
+fn etag(body: &Bytes) -> HeaderValue {
+    let digest: String = Sha256::digest(body)[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    HeaderValue::from_str(&format!("W/\"{digest}-{:x}\"", body.len()))
+        .expect("ETag consists of visible ASCII characters.")
+}
+
+/// Whether the If-None-Match header lists the ETag, using the weak comparison.
+fn matches_etag(if_none_match: Option<&HeaderValue>, etag: &HeaderValue) -> bool {
+    let Some(if_none_match) = if_none_match.and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let opaque_tag = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque_tag(etag.to_str().unwrap_or_default());
+
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque_tag(tag) == etag)
+}
+
+/// The supported content coding that the Accept-Encoding header accepts with the highest quality,
+/// preferring the earlier codings of [CONTENT_CODINGS] among equal qualities. Codings with a
+/// quality of zero are refused.
+fn accepted_coding(accept_encoding: Option<&HeaderValue>) -> Option<&'static str> {
+    let accept_encoding = accept_encoding.and_then(|v| v.to_str().ok())?;
+    let accepted = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parameters = item.split(';').map(str::trim);
+            let coding = parameters.next()?.to_ascii_lowercase();
+            let quality = parameters
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse::<f64>().unwrap_or(0.0));
+            Some((coding, quality))
+        })
+        .collect::<Vec<(String, f64)>>();
+
+    CONTENT_CODINGS
+        .into_iter()
+        .filter_map(|coding| {
+            accepted
+                .iter()
+                .find(|(a, _)| a == coding)
+                .map(|(_, quality)| (coding, *quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold(
+            None,
+            |best: Option<(&'static str, f64)>, (coding, quality)| match best {
+                Some((_, best_quality)) if best_quality >= quality => best,
+                _ => Some((coding, quality)),
+            },
+        )
+        .map(|(coding, _)| coding)
+}
+
+fn compress(body: &[u8], coding: &str) -> std::io::Result<Vec<u8>> {
+    match coding {
+        "br" => {
+            let mut encoder =
+                brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(body)?;
+            Ok(encoder.into_inner())
+        }
+        "gzip" => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        _ => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dropshot::HttpResponseOk;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> RequestInfo {
+        let mut request = hyper::Request::builder()
+            .method(method)
+            .uri("/candidates/a");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        RequestInfo::from(request.body(()).unwrap())
+    }
+
+    async fn respond(request: &RequestInfo) -> (http::response::Parts, Bytes) {
+        let response = encode_response(request, HttpResponseOk(vec![1.5; 1000]))
+            .await
+            .unwrap()
+            .to_result()
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        (parts, hyper::body::to_bytes(body).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let (parts, plain) = respond(&request(Method::GET, &[])).await;
+        assert!(parts.headers.get(CONTENT_ENCODING).is_none());
+
+        let (parts, compressed) = respond(&request(
+            Method::GET,
+            &[("accept-encoding", "br;q=0.2, gzip;q=0.5")],
+        ))
+        .await;
+        assert_eq!(parts.headers.get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert!(compressed.len() < plain.len() / 10);
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+
+        // Refused codings are not used
+        let (parts, _) = respond(&request(
+            Method::GET,
+            &[("accept-encoding", "gzip;q=0, deflate")],
+        ))
+        .await;
+        assert_eq!(parts.headers.get(CONTENT_ENCODING).unwrap(), "deflate");
+    }
+
+    #[tokio::test]
+    async fn test_brotli_compression() {
+        let (_, plain) = respond(&request(Method::GET, &[])).await;
+
+        let (parts, compressed) = respond(&request(
+            Method::GET,
+            &[("accept-encoding", "gzip, deflate, br")],
+        ))
+        .await;
+        assert_eq!(parts.headers.get(CONTENT_ENCODING).unwrap(), "br");
+        assert!(compressed.len() < plain.len() / 10);
+        let mut decompressed = Vec::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+
+        // Codings are ranked by their quality before the order of preference
+        let accepted = |accept_encoding: &str| {
+            accepted_coding(Some(&HeaderValue::from_str(accept_encoding).unwrap()))
+        };
+        assert_eq!(Some("gzip"), accepted("br;q=0.5, gzip;q=0.8"));
+        assert_eq!(Some("br"), accepted("deflate;q=0.5, br;q=0.5"));
+        assert_eq!(Some("br"), accepted("br"));
+        assert_eq!(None, accepted("br;q=0, identity"));
+    }
+
+    #[tokio::test]
+    async fn test_etag() {
+        let (parts, _) = respond(&request(Method::GET, &[("accept-encoding", "gzip")])).await;
+        let etag = parts
+            .headers
+            .get(ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with("W/\""));
+
+        // Same ETag regardless of the compression
+        let (parts, _) = respond(&request(Method::GET, &[])).await;
+        assert_eq!(parts.headers.get(ETAG).unwrap(), etag.as_str());
+
+        let if_none_match = format!("\"other\", {}", etag.trim_start_matches("W/"));
+        let (parts, body) =
+            respond(&request(Method::GET, &[("if-none-match", &if_none_match)])).await;
+        assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+        assert!(body.is_empty());
+
+        // Only GET requests are answered with not modified
+        let (parts, body) = respond(&request(Method::POST, &[("if-none-match", &etag)])).await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert!(!body.is_empty());
+
+        let (parts, _) = respond(&request(Method::GET, &[("if-none-match", "\"other\"")])).await;
+        assert_eq!(parts.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_etag_is_stable() {
+        assert_eq!(
+            etag(&Bytes::from_static(b"abc")),
+            "W/\"ba7816bf8f01cfea414140de5dae2223-3\""
+        );
+    }
+}
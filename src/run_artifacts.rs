@@ -0,0 +1,401 @@
+use crate::analysis::Outcome;
+use crate::model::company::Ticker;
+use crate::model::errors::Error;
+use crate::model::responses::{AllocationResult, AnalysisResult};
+use parquet::basic::{Repetition, Type as PhysicalType};
+use parquet::data_type::DoubleType;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Names of the files written into the directory of a run.
+pub const INPUT_FILE_NAME: &str = "input.yaml";
+pub const ALLOCATION_FILE_NAME: &str = "allocation.json";
+pub const ANALYSIS_FILE_NAME: &str = "analysis.json";
+pub const REPORT_FILE_NAME: &str = "report.html";
+pub const OUTCOMES_FILE_NAME: &str = "outcomes.parquet";
+
+/// Format of a response written into an output file:
+/// - Yaml and Json: The whole response,
+/// - Csv: One row per field of the response with the path of the field (e.g.
+///   `result.allocations.0.fraction`) and its value, such that scripts don't need to handle the
+///   nesting. Fields without a value are left out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Yaml,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" => Ok(OutputFormat::Yaml),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected \"yaml\", \"json\" or \"csv\" as output format, got {s}"),
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Detects the format from the extension of the file, defaulting to JSON.
+    pub fn from_path(path: &Path) -> OutputFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => OutputFormat::Yaml,
+            Some("csv") => OutputFormat::Csv,
+            _ => OutputFormat::Json,
+        }
+    }
+}
+
+/// Writes the response into the file in the given format.
+pub fn write_response<T: Serialize>(
+    path: &Path,
+    format: OutputFormat,
+    response: &T,
+) -> Result<(), Error> {
+    let content = match format {
+        OutputFormat::Yaml => serde_yaml::to_string(response).map_err(|e| Error {
+            code: "failed-to-serialize-result".to_string(),
+            message: format!("Failed to serialize the result to YAML: {e}"),
+        })?,
+        OutputFormat::Json => to_json(response)?,
+        OutputFormat::Csv => to_csv(response)?,
+    };
+
+    std::fs::write(path, content).map_err(|e| Error {
+        code: "failed-to-write-output".to_string(),
+        message: format!("Failed to write {}: {e}", path.display()),
+    })
+}
+
+/// Writes the artifacts of a run (allocation followed by the analysis of the allocated portfolio)
+/// into the given directory, which is created if it doesn't exist:
+/// - Copy of the input file,
+/// - Allocation and analysis results as JSON,
+/// - HTML report summarizing both results,
+/// - All outcomes of the portfolio as parquet, with the probability, portfolio return and return
+///   of each company as columns.
+pub fn write_run_artifacts(
+    run_dir: &Path,
+    input_file_content: &str,
+    allocation_result: &AllocationResult,
+    analysis_result: &AnalysisResult,
+    outcomes: &[Outcome],
+) -> Result<(), Error> {
+    std::fs::create_dir_all(run_dir).map_err(|e| io_error(run_dir, e))?;
+
+    write_file(&run_dir.join(INPUT_FILE_NAME), input_file_content)?;
+    write_file(
+        &run_dir.join(ALLOCATION_FILE_NAME),
+        &to_json(allocation_result)?,
+    )?;
+    write_file(
+        &run_dir.join(ANALYSIS_FILE_NAME),
+        &to_json(analysis_result)?,
+    )?;
+    write_file(
+        &run_dir.join(REPORT_FILE_NAME),
+        &html_report(allocation_result, analysis_result),
+    )?;
+
+    let tickers = allocation_result
+        .allocations
+        .iter()
+        .map(|tf| tf.ticker.clone())
+        .collect::<Vec<Ticker>>();
+    write_outcomes_parquet(&run_dir.join(OUTCOMES_FILE_NAME), &tickers, outcomes)
+}
+
+/// Returns the directory for a run started at the given time (UTC) within the output directory,
+/// e.g. runs/20240601T093000Z.
+pub fn timestamped_run_dir(out_dir: &Path, time: chrono::DateTime<chrono::Utc>) -> PathBuf {
+    out_dir.join(time.format("%Y%m%dT%H%M%SZ").to_string())
+}
+
+/// Creates a minimal standalone HTML report with the allocation and the portfolio statistics.
+pub fn html_report(
+    allocation_result: &AllocationResult,
+    analysis_result: &AnalysisResult,
+) -> String {
+    let allocation_rows: String = allocation_result
+        .allocations
+        .iter()
+        .map(|tf| table_row(&escape_html(&tf.ticker), tf.fraction))
+        .collect();
+
+    let mut statistics_rows: Vec<String> = vec![
+        table_row("Cash", allocation_result.cash),
+        table_row("Gross exposure", allocation_result.gross_exposure),
+        table_row("Net exposure", analysis_result.net_exposure),
+        table_row(
+            "Effective number of positions",
+            analysis_result.effective_number_of_positions,
+        ),
+        table_row("Maximum weight", analysis_result.max_weight),
+        table_row("Expected return", analysis_result.expected_return),
+        table_row("Variance of return", analysis_result.variance_of_return),
+        table_row(
+            "Expected logarithmic growth",
+            allocation_result.solver_metadata.expected_log_growth,
+        ),
+        table_row(
+            "Worst case return",
+            analysis_result.worst_case_outcome.portfolio_return,
+        ),
+        table_row(
+            "Worst case probability",
+            analysis_result.worst_case_outcome.probability,
+        ),
+    ];
+    if let Some(p) = analysis_result.cumulative_probability_of_loss {
+        statistics_rows.push(table_row("Cumulative probability of loss", p));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n\
+        <html>\n\
+        <head><meta charset=\"utf-8\"><title>Charlie report</title></head>\n\
+        <body>\n\
+        <h1>Allocation</h1>\n\
+        <table>\n<tr><th>Ticker</th><th>Fraction</th></tr>\n{allocation_rows}</table>\n\
+        <h1>Statistics</h1>\n\
+        <table>\n{}</table>\n\
+        </body>\n\
+        </html>\n",
+        statistics_rows.concat()
+    )
+}
+
+/// Writes the outcomes into a parquet file with a column for the probability, the portfolio return
+/// and the return of each of the given tickers.
+pub fn write_outcomes_parquet(
+    path: &Path,
+    tickers: &[Ticker],
+    outcomes: &[Outcome],
+) -> Result<(), Error> {
+    let mut columns: Vec<(String, Vec<f64>)> = vec![
+        (
+            "probability".to_string(),
+            outcomes.iter().map(|o| o.probability).collect(),
+        ),
+        (
+            "portfolio_return".to_string(),
+            outcomes.iter().map(|o| o.weighted_return).collect(),
+        ),
+    ];
+    columns.extend(tickers.iter().map(|t| {
+        (
+            format!("return_{t}"),
+            outcomes.iter().map(|o| o.company_returns[t]).collect(),
+        )
+    }));
+
+    write_parquet(path, &columns).map_err(|e| Error {
+        code: "failed-to-write-parquet".to_string(),
+        message: format!("Failed to write {}: {e}", path.display()),
+    })
+}
+
+/// Writes columns of doubles into a single row group of a parquet file.
+fn write_parquet(path: &Path, columns: &[(String, Vec<f64>)]) -> parquet::errors::Result<()> {
+    let fields = columns
+        .iter()
+        .map(|(name, _)| {
+            Type::primitive_type_builder(name, PhysicalType::DOUBLE)
+                .with_repetition(Repetition::REQUIRED)
+                .build()
+                .map(Arc::new)
+        })
+        .collect::<parquet::errors::Result<Vec<_>>>()?;
+    let schema = Arc::new(
+        Type::group_type_builder("outcomes")
+            .with_fields(fields)
+            .build()?,
+    );
+
+    let file = File::create(path)?;
+    let mut writer =
+        SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group_writer = writer.next_row_group()?;
+    for (_, values) in columns {
+        if let Some(mut column_writer) = row_group_writer.next_column()? {
+            column_writer
+                .typed::<DoubleType>()
+                .write_batch(values, None, None)?;
+            column_writer.close()?;
+        }
+    }
+    row_group_writer.close()?;
+    writer.close()?;
+
+    Ok(())
+}
+
+fn table_row(name: &str, value: f64) -> String {
+    format!("<tr><td>{name}</td><td>{value:.6}</td></tr>\n")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, Error> {
+    serde_json::to_string_pretty(value).map_err(|e| Error {
+        code: "failed-to-serialize-result".to_string(),
+        message: format!("Failed to serialize the result to JSON: {e}"),
+    })
+}
+
+/// Serializes the value into CSV with a field and a value column, one row per field.
+fn to_csv<T: Serialize>(value: &T) -> Result<String, Error> {
+    let serialization_error = |e: String| Error {
+        code: "failed-to-serialize-result".to_string(),
+        message: format!("Failed to serialize the result to CSV: {e}"),
+    };
+    let value = serde_json::to_value(value).map_err(|e| serialization_error(e.to_string()))?;
+
+    let mut fields = vec![];
+    flatten_fields("", &value, &mut fields);
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record(["field", "value"])
+        .map_err(|e| serialization_error(e.to_string()))?;
+    for (field, value) in fields {
+        writer
+            .write_record([field, value])
+            .map_err(|e| serialization_error(e.to_string()))?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| serialization_error(e.to_string()))?;
+    String::from_utf8(bytes).map_err(|e| serialization_error(e.to_string()))
+}
+
+/// Collects the paths and values of all fields with a value, where the elements of arrays are
+/// named by their index.
+fn flatten_fields(path: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+
+    match value {
+        Value::Null => {}
+        Value::Object(map) => map
+            .iter()
+            .for_each(|(key, v)| flatten_fields(&child_path(key), v, fields)),
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .for_each(|(i, v)| flatten_fields(&child_path(&i.to_string()), v, fields)),
+        Value::String(s) => fields.push((path.to_string(), s.clone())),
+        _ => fields.push((path.to_string(), value.to_string())),
+    }
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), Error> {
+    std::fs::write(path, content).map_err(|e| io_error(path, e))
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error {
+        code: "failed-to-write-run-artifacts".to_string(),
+        message: format!("Failed to write {}: {e}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_write_outcomes_parquet() {
+        let outcomes = vec![
+            Outcome {
+                weighted_return: 0.5,
+                probability: 0.4,
+                company_returns: HashMap::from([("A".to_string(), 1.0)]),
+            },
+            Outcome {
+                weighted_return: -0.25,
+                probability: 0.6,
+                company_returns: HashMap::from([("A".to_string(), -0.5)]),
+            },
+        ];
+        let path = std::env::temp_dir().join(format!(
+            "charlie_test_outcomes_{}.parquet",
+            std::process::id()
+        ));
+
+        write_outcomes_parquet(&path, &["A".to_string()], &outcomes).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(2, metadata.num_rows());
+        assert_eq!(3, metadata.schema_descr().num_columns());
+        assert_eq!("return_A", metadata.schema_descr().column(2).name());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let response = serde_json::json!({
+            "result": {
+                "allocations": [{"ticker": "A", "fraction": 0.25}],
+                "cash": 0.75,
+            },
+            "error": null,
+        });
+
+        assert_eq!(
+            "field,value\n\
+            result.allocations.0.fraction,0.25\n\
+            result.allocations.0.ticker,A\n\
+            result.cash,0.75\n",
+            to_csv(&response).unwrap()
+        );
+        assert_eq!(
+            OutputFormat::Csv,
+            OutputFormat::from_path(Path::new("out.csv"))
+        );
+        assert_eq!(
+            OutputFormat::Json,
+            OutputFormat::from_path(Path::new("out"))
+        );
+    }
+
+    #[test]
+    fn test_timestamped_run_dir() {
+        let time = chrono::DateTime::parse_from_rfc3339("2024-06-01T09:30:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        assert_eq!(
+            Path::new("runs").join("20240601T093000Z"),
+            timestamped_run_dir(Path::new("runs"), time)
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use crate::model::errors::Error;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+/// Key of a scenario given by a template from the library instead of by its intrinsic value.
+pub const TEMPLATE_KEY: &str = "template";
+
+/// Keys of a templated scenario that are passed to the expanded scenario rather than to the
+/// template.
+const SCENARIO_KEYS: [&str; 3] = [TEMPLATE_KEY, "thesis", "probability"];
+
+/// Library of the reusable scenario templates. The intrinsic value of a templated scenario is
+/// derived from the market cap of the company and the parameters of the template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScenarioTemplate {
+    /// The business is worth nothing, e.g. bankruptcy or fraud.
+    Zero,
+    /// Intrinsic value equals the market cap.
+    StatusQuo,
+    /// The business is liquidated and a part (`recovery`, defaults to 1) of its `net_assets` is
+    /// recovered.
+    Liquidation,
+    /// The business is valued at a `multiple` of its `earnings`, or, given its `current_multiple`,
+    /// at the market cap re-rated from the current multiple to the new one.
+    Multiple,
+}
+
+impl ScenarioTemplate {
+    /// All the templates in the library.
+    pub const ALL: [ScenarioTemplate; 4] = [
+        ScenarioTemplate::Zero,
+        ScenarioTemplate::StatusQuo,
+        ScenarioTemplate::Liquidation,
+        ScenarioTemplate::Multiple,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScenarioTemplate::Zero => "zero",
+            ScenarioTemplate::StatusQuo => "status_quo",
+            ScenarioTemplate::Liquidation => "liquidation",
+            ScenarioTemplate::Multiple => "multiple",
+        }
+    }
+
+    /// Names of all the parameters accepted by the template.
+    pub fn parameters(&self) -> &'static [&'static str] {
+        match self {
+            ScenarioTemplate::Zero | ScenarioTemplate::StatusQuo => &[],
+            ScenarioTemplate::Liquidation => &["net_assets", "recovery"],
+            ScenarioTemplate::Multiple => &["multiple", "earnings", "current_multiple"],
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<ScenarioTemplate, Error> {
+        ScenarioTemplate::ALL
+            .into_iter()
+            .find(|t| t.name() == name.trim().to_lowercase())
+            .ok_or_else(|| Error {
+                code: "unknown-scenario-template".to_string(),
+                message: format!(
+                    "Scenario template {name} is not in the library. Use one of: {}.",
+                    ScenarioTemplate::ALL.map(|t| t.name()).join(", ")
+                ),
+            })
+    }
+
+    /// Returns the default thesis and the intrinsic value of the scenario given the market cap of
+    /// the company and the parameters of the template.
+    pub fn expand(
+        &self,
+        market_cap: f64,
+        parameters: &HashMap<String, f64>,
+    ) -> Result<(String, f64), Error> {
+        if let Some(unknown) = parameters
+            .keys()
+            .find(|p| !self.parameters().contains(&p.as_str()))
+        {
+            return Err(Error {
+                code: "unknown-scenario-template-parameter".to_string(),
+                message: format!(
+                    "Scenario template {} doesn't have parameter {unknown}. Its parameters \
+                    are: {}.",
+                    self.name(),
+                    self.parameters().join(", ")
+                ),
+            });
+        }
+        let required = |parameter: &str| {
+            parameters.get(parameter).copied().ok_or_else(|| Error {
+                code: "missing-scenario-template-parameter".to_string(),
+                message: format!(
+                    "Scenario template {} requires parameter {parameter}.",
+                    self.name()
+                ),
+            })
+        };
+
+        match self {
+            ScenarioTemplate::Zero => Ok(("Business is worth nothing".to_string(), 0.0)),
+            ScenarioTemplate::StatusQuo => Ok((
+                "Intrinsic value equals the market cap".to_string(),
+                market_cap,
+            )),
+            ScenarioTemplate::Liquidation => {
+                let net_assets = required("net_assets")?;
+                let recovery = parameters.get("recovery").copied().unwrap_or(1.0);
+                Ok((
+                    format!("Liquidation recovering {recovery} of net assets of {net_assets}"),
+                    recovery * net_assets,
+                ))
+            }
+            ScenarioTemplate::Multiple => {
+                let multiple = required("multiple")?;
+                match (
+                    parameters.get("earnings"),
+                    parameters.get("current_multiple"),
+                ) {
+                    (Some(earnings), None) => Ok((
+                        format!("Valued at {multiple}x earnings of {earnings}"),
+                        multiple * earnings,
+                    )),
+                    (None, Some(current_multiple)) if *current_multiple > 0.0 => Ok((
+                        format!("Multiple changes from {current_multiple}x to {multiple}x"),
+                        market_cap * multiple / current_multiple,
+                    )),
+                    _ => Err(Error {
+                        code: "missing-scenario-template-parameter".to_string(),
+                        message: "Scenario template multiple requires either parameter earnings \
+                            or a positive parameter current_multiple."
+                            .to_string(),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// Expands the scenarios given by a template (e.g. `{template: multiple, multiple: 8, earnings:
+/// 120, probability: 0.3}`) into concrete scenarios with an intrinsic value, in a YAML (or JSON)
+/// input document. All the keys of a templated scenario other than `template`, `thesis` and
+/// `probability` are the parameters of the template. The thesis defaults to a description of the
+/// template with its parameters, and the probability is kept as it is, so it may also be a
+/// qualitative likelihood. Companies are looked for anywhere in the document, so both allocation
+/// inputs and portfolios are supported. Returns the expanded document in YAML format.
+pub fn expand_scenario_templates(document: &str) -> Result<String, Error> {
+    let invalid_document = |e: serde_yaml::Error| Error {
+        code: "invalid-input-document".to_string(),
+        message: format!("Input document is not valid YAML or JSON: {e}"),
+    };
+    let mut root: Value = serde_yaml::from_str(document).map_err(invalid_document)?;
+    expand_in_value(&mut root)?;
+
+    serde_yaml::to_string(&root).map_err(invalid_document)
+}
+
+/// Recursively looks for companies (mappings with scenarios) and expands their templated
+/// scenarios.
+fn expand_in_value(value: &mut Value) -> Result<(), Error> {
+    match value {
+        Value::Mapping(m) => {
+            if let Some(Value::Sequence(_)) = m.get("scenarios") {
+                expand_company(m)?;
+            }
+            for (_, v) in m.iter_mut() {
+                expand_in_value(v)?;
+            }
+        }
+        Value::Sequence(s) => {
+            for v in s.iter_mut() {
+                expand_in_value(v)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Expands the templated scenarios of a single company given its market cap.
+fn expand_company(company: &mut Mapping) -> Result<(), Error> {
+    let ticker = company
+        .get("ticker")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let market_cap = company.get("market_cap").and_then(Value::as_f64);
+    let Some(Value::Sequence(scenarios)) = company.get_mut("scenarios") else {
+        return Ok(());
+    };
+
+    for scenario in scenarios.iter_mut() {
+        let Value::Mapping(scenario) = scenario else {
+            continue;
+        };
+        let Some(template) = scenario.get(TEMPLATE_KEY) else {
+            continue;
+        };
+        let template = ScenarioTemplate::from_name(template.as_str().unwrap_or_default())?;
+        let market_cap = market_cap.ok_or_else(|| Error {
+            code: "scenario-template-requires-market-cap".to_string(),
+            message: format!(
+                "Company {ticker} must have a numeric market cap to expand scenario template {}.",
+                template.name()
+            ),
+        })?;
+
+        let mut parameters: HashMap<String, f64> = HashMap::new();
+        for (key, value) in scenario.iter() {
+            let key = key.as_str().unwrap_or_default();
+            if SCENARIO_KEYS.contains(&key) {
+                continue;
+            }
+            let value = value.as_f64().ok_or_else(|| Error {
+                code: "invalid-scenario-template-parameter".to_string(),
+                message: format!(
+                    "Parameter {key} of scenario template {} for company {ticker} must be a \
+                    number.",
+                    template.name()
+                ),
+            })?;
+            parameters.insert(key.to_string(), value);
+        }
+
+        let (thesis, intrinsic_value) = template.expand(market_cap, &parameters)?;
+        let mut expanded = Mapping::new();
+        expanded.insert(
+            Value::from("thesis"),
+            scenario
+                .get("thesis")
+                .cloned()
+                .unwrap_or(Value::from(thesis)),
+        );
+        expanded.insert(Value::from("intrinsic_value"), Value::from(intrinsic_value));
+        if let Some(probability) = scenario.get("probability") {
+            expanded.insert(Value::from("probability"), probability.clone());
+        }
+        *scenario = expanded;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::company::TOLERANCE;
+    use crate::model::portfolio::AllocationInput;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_expand_scenario_templates() {
+        let document = "
+            candidates:
+              - name: A
+                ticker: A
+                description: Business A
+                market_cap: 1000.0
+                scenarios:
+                  - template: zero
+                    probability: 0.1
+                  - template: liquidation
+                    net_assets: 800
+                    recovery: 0.5
+                    probability: 0.2
+                  - template: multiple
+                    multiple: 15
+                    earnings: 100
+                    thesis: Earnings recover
+                    probability: 0.3
+                  - template: Multiple
+                    multiple: 5
+                    current_multiple: 10
+                    probability: unlikely
+                  - thesis: Acquired
+                    intrinsic_value: 2000.0
+                    probability: 0.15
+        ";
+
+        let expanded = expand_scenario_templates(document).unwrap();
+        let input: AllocationInput =
+            serde_yaml::from_str(&expanded.replace("unlikely", "0.25")).unwrap();
+        let scenarios = &input.candidates[0].scenarios;
+
+        assert_eq!("Business is worth nothing", scenarios[0].thesis);
+        assert_close!(0.0, scenarios[0].intrinsic_value, TOLERANCE);
+        assert_eq!(
+            "Liquidation recovering 0.5 of net assets of 800",
+            scenarios[1].thesis
+        );
+        assert_close!(400.0, scenarios[1].intrinsic_value, TOLERANCE);
+        assert_eq!("Earnings recover", scenarios[2].thesis);
+        assert_close!(1500.0, scenarios[2].intrinsic_value, TOLERANCE);
+        assert_close!(0.3, scenarios[2].probability, TOLERANCE);
+        assert_eq!("Multiple changes from 10x to 5x", scenarios[3].thesis);
+        assert_close!(500.0, scenarios[3].intrinsic_value, TOLERANCE);
+        assert_close!(2000.0, scenarios[4].intrinsic_value, TOLERANCE);
+
+        let error = |replaced: &str, replacement: &str| {
+            expand_scenario_templates(&document.replace(replaced, replacement))
+                .unwrap_err()
+                .code
+        };
+        assert_eq!(
+            "unknown-scenario-template",
+            error("template: zero", "template: one")
+        );
+        assert_eq!(
+            "unknown-scenario-template-parameter",
+            error("recovery", "recovered")
+        );
+        assert_eq!(
+            "missing-scenario-template-parameter",
+            error("net_assets: 800", "# Net assets are not given")
+        );
+        assert_eq!(
+            "missing-scenario-template-parameter",
+            error("current_multiple: 10", "current_multiple: 0")
+        );
+        assert_eq!(
+            "invalid-scenario-template-parameter",
+            error("earnings: 100", "earnings: many")
+        );
+        assert_eq!(
+            "scenario-template-requires-market-cap",
+            error("market_cap: 1000.0", "market_cap: large")
+        );
+    }
+}
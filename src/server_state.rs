@@ -0,0 +1,312 @@
+use crate::allocation_queue::AllocationQueue;
+use crate::allocation_store::AllocationStore;
+use crate::candidate_store::CandidateStore;
+use crate::config::{RemoteInputConfig, ServerConfig, SharingConfig};
+use crate::env::LogLevel;
+use crate::job_store::JobStore;
+use crate::market_data::MarketDataProvider;
+use crate::model::errors::Error;
+use crate::model::template::InputTemplate;
+use crate::sharing::ShareSigner;
+use crate::snapshot_store::SnapshotStore;
+use crate::standalone::StandalonePages;
+use crate::storage::{create_storage, load_all, Collection, Storage};
+use crate::usage::UsageTracker;
+use itertools::Itertools;
+use slog::Level;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Server state shared between all the endpoints, available through the request context.
+pub struct ServerState {
+    /// Storage backend selected in the configuration, on top of which the documents are persisted.
+    pub storage: Arc<dyn Storage>,
+
+    /// Named input templates stored on the server.
+    pub templates: Mutex<HashMap<String, InputTemplate>>,
+
+    /// Named input templates read from the presets file given in the configuration.
+    pub presets: RwLock<HashMap<String, InputTemplate>>,
+
+    /// Queue limiting the number of allocations executing at the same time.
+    pub allocation_queue: AllocationQueue,
+
+    /// Latest candidates per workspace, pushed by external systems.
+    pub candidate_store: CandidateStore,
+
+    /// Allocations performed by the server, which can be re-run with the current code.
+    pub allocation_store: AllocationStore,
+
+    /// Allocation jobs running in the background, whose status can be polled.
+    pub jobs: JobStore,
+
+    /// Analysis snapshots of named portfolios, for tracking how their risk drifted.
+    pub snapshot_store: SnapshotStore,
+
+    /// Usage and quotas per API key.
+    pub usage: UsageTracker,
+
+    /// Level of the server log, shared with the logger of the server.
+    pub log_level: LogLevel,
+
+    /// Signer of the read-only share links to the recorded allocations.
+    pub share_signer: ShareSigner,
+
+    /// Validity of the share links, given in the configuration.
+    pub sharing: SharingConfig,
+
+    /// Provider of the live market caps, against which the candidates are checked if configured.
+    pub market_data: Option<MarketDataProvider>,
+
+    /// Hosts and limits of fetching the allocation input from URLs, given in the configuration.
+    pub remote_input: RemoteInputConfig,
+
+    /// Pages served instead of the files of the project directory in the standalone mode.
+    pub standalone_pages: Option<StandalonePages>,
+}
+
+impl ServerState {
+    /// Creates a server state given the server configuration, with the persisted templates,
+    /// candidates, allocations and snapshots.
+    pub fn new(config: &ServerConfig) -> ServerState {
+        let storage = create_storage(&config.storage)
+            .unwrap_or_else(|e| panic!("Failed to open the storage: {}", e.message));
+        let templates = load_all::<InputTemplate>(storage.as_ref(), Collection::Templates)
+            .unwrap_or_else(|e| panic!("Failed to load the templates: {}", e.message));
+        let presets = load_presets(config)
+            .unwrap_or_else(|e| panic!("Failed to load the presets: {}", e.message));
+
+        ServerState {
+            templates: Mutex::new(templates.into_iter().collect()),
+            presets: RwLock::new(presets),
+            allocation_queue: AllocationQueue::new(&config.allocation_queue),
+            candidate_store: CandidateStore::new(storage.clone())
+                .unwrap_or_else(|e| panic!("Failed to load the candidate store: {}", e.message)),
+            allocation_store: AllocationStore::new(storage.clone())
+                .unwrap_or_else(|e| panic!("Failed to load the allocation store: {}", e.message)),
+            jobs: JobStore::default(),
+            snapshot_store: SnapshotStore::new(storage.clone())
+                .unwrap_or_else(|e| panic!("Failed to load the snapshot store: {}", e.message)),
+            usage: UsageTracker::new(&config.usage),
+            log_level: LogLevel::new(Level::from(&config.logging.level)),
+            share_signer: ShareSigner::from_config(&config.sharing),
+            sharing: config.sharing.clone(),
+            market_data: config.market_data.as_ref().map(MarketDataProvider::new),
+            remote_input: config.remote_input.clone(),
+            standalone_pages: None,
+            storage,
+        }
+    }
+
+    /// Returns the template with the given name, looking up the stored templates before the
+    /// presets.
+    pub fn template(&self, name: &str) -> Option<InputTemplate> {
+        self.templates
+            .lock()
+            .unwrap()
+            .get(name)
+            .or(self.presets.read().unwrap().get(name))
+            .cloned()
+    }
+
+    /// Applies the settings of a changed configuration that don't require restarting the server:
+    /// the log level, the limits of the allocation queue, the quotas and the presets, which are
+    /// read again from their file. Returns a description of every change, including the ones that
+    /// only take effect after a restart. Nothing is applied if the new configuration is invalid.
+    pub fn reload(
+        &self,
+        current: &ServerConfig,
+        new: &ServerConfig,
+    ) -> Result<ConfigChanges, Error> {
+        let presets = load_presets(new)?;
+        if new.allocation_queue != current.allocation_queue {
+            self.allocation_queue.resize(&new.allocation_queue)?;
+        }
+
+        let mut changes = ConfigChanges::default();
+        if new.logging.level != current.logging.level {
+            self.log_level.set(Level::from(&new.logging.level));
+            changes.applied.push(format!(
+                "Log level changed from {:?} to {:?}.",
+                current.logging.level, new.logging.level
+            ));
+        }
+        if new.allocation_queue != current.allocation_queue {
+            changes.applied.push(format!(
+                "Allocation queue changed from {} concurrent and {} queued allocations to {} \
+                concurrent and {} queued allocations.",
+                current.allocation_queue.max_concurrent_allocations,
+                current.allocation_queue.max_queued_allocations,
+                new.allocation_queue.max_concurrent_allocations,
+                new.allocation_queue.max_queued_allocations
+            ));
+        }
+        if new.usage != current.usage {
+            self.usage.set_config(&new.usage);
+            changes.applied.push("Usage quotas changed.".to_string());
+        }
+
+        let mut current_presets = self.presets.write().unwrap();
+        let changed_presets = presets
+            .keys()
+            .chain(current_presets.keys())
+            .unique()
+            .filter(|name| {
+                presets.get(*name).map(|t| &t.template)
+                    != current_presets.get(*name).map(|t| &t.template)
+            })
+            .sorted()
+            .join(", ");
+        if !changed_presets.is_empty() {
+            changes.applied.push(format!(
+                "Presets {changed_presets} were added, changed or removed."
+            ));
+        }
+        *current_presets = presets;
+
+        if new.dropshot != current.dropshot {
+            changes.requiring_restart.push(
+                "Bind address, request body size or TLS settings changed. Restart the server to \
+                apply them."
+                    .to_string(),
+            );
+        }
+        if new.storage != current.storage {
+            changes
+                .requiring_restart
+                .push("Storage changed. Restart the server to apply it.".to_string());
+        }
+        if new.logging.format != current.logging.format {
+            changes
+                .requiring_restart
+                .push("Log format changed. Restart the server to apply it.".to_string());
+        }
+        if new.sharing != current.sharing {
+            changes
+                .requiring_restart
+                .push("Sharing changed. Restart the server to apply it.".to_string());
+        }
+        if new.market_data != current.market_data {
+            changes
+                .requiring_restart
+                .push("Market data changed. Restart the server to apply it.".to_string());
+        }
+        if new.remote_input != current.remote_input {
+            changes
+                .requiring_restart
+                .push("Remote input changed. Restart the server to apply it.".to_string());
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Changes of the server configuration found on reload.
+#[derive(Debug, Default)]
+pub struct ConfigChanges {
+    /// Changes applied to the running server.
+    pub applied: Vec<String>,
+
+    /// Changes that take effect only after restarting the server.
+    pub requiring_restart: Vec<String>,
+}
+
+/// Reads the presets from the file given in the configuration. No presets if there's no file.
+fn load_presets(config: &ServerConfig) -> Result<HashMap<String, InputTemplate>, Error> {
+    let Some(path) = &config.templates.presets else {
+        return Ok(HashMap::new());
+    };
+    let content = fs::read_to_string(path).map_err(|e| Error {
+        code: "failed-to-read-presets".to_string(),
+        message: format!("Did not manage to read the presets file at {path}: {e}."),
+    })?;
+    serde_yaml::from_str(&content).map_err(|e| Error {
+        code: "invalid-presets".to_string(),
+        message: format!("Failed to deserialize the presets file at {path}: {e}"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::LogFormat;
+    use dropshot::ConfigLoggingLevel;
+
+    #[test]
+    fn test_reload() {
+        let presets_path =
+            std::env::temp_dir().join(format!("charlie_test_presets_{}.yaml", std::process::id()));
+        fs::write(
+            &presets_path,
+            "a:\n  template: first\nb:\n  template: second\n",
+        )
+        .unwrap();
+        let config: ServerConfig = toml::from_str(&format!(
+            "
+            bind_address = \"127.0.0.1:8000\"
+
+            [allocation_queue]
+            max_concurrent_allocations = 1
+
+            [templates]
+            presets = {:?}
+            ",
+            presets_path.to_str().unwrap()
+        ))
+        .unwrap();
+        let state = ServerState::new(&config);
+        assert_eq!(state.template("a").unwrap().template, "first");
+
+        // Stored templates take precedence over the presets
+        state.templates.lock().unwrap().insert(
+            "b".to_string(),
+            InputTemplate {
+                template: "stored".to_string(),
+            },
+        );
+        assert_eq!(state.template("b").unwrap().template, "stored");
+
+        fs::write(
+            &presets_path,
+            "a:\n  template: changed\nc:\n  template: third\n",
+        )
+        .unwrap();
+        let mut new_config = config.clone();
+        new_config.logging.level = ConfigLoggingLevel::Debug;
+        new_config.allocation_queue.max_concurrent_allocations = 2;
+        new_config.dropshot.bind_address = "127.0.0.1:8001".parse().unwrap();
+        new_config.logging.format = Some(LogFormat::Json);
+        let changes = state.reload(&config, &new_config).unwrap();
+
+        assert_eq!(
+            changes.applied,
+            vec![
+                "Log level changed from Info to Debug.",
+                "Allocation queue changed from 1 concurrent and 64 queued allocations to 2 \
+                concurrent and 64 queued allocations.",
+                "Presets a, b, c were added, changed or removed.",
+            ]
+        );
+        assert_eq!(changes.requiring_restart.len(), 2);
+        assert_eq!(state.log_level.get(), Level::Debug);
+        assert_eq!(state.template("a").unwrap().template, "changed");
+        assert_eq!(state.template("b").unwrap().template, "stored");
+        assert_eq!(state.template("c").unwrap().template, "third");
+
+        // Nothing is applied if the presets can't be read
+        fs::write(&presets_path, "a: [").unwrap();
+        let invalid_config = ServerConfig {
+            logging: config.logging.clone(),
+            ..new_config.clone()
+        };
+        assert_eq!(
+            state.reload(&new_config, &invalid_config).unwrap_err().code,
+            "invalid-presets"
+        );
+        assert_eq!(state.log_level.get(), Level::Debug);
+        assert_eq!(state.template("c").unwrap().template, "third");
+
+        fs::remove_file(&presets_path).unwrap();
+    }
+}
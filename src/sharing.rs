@@ -0,0 +1,194 @@
+use crate::config::SharingConfig;
+use crate::model::allocations::StoredAllocation;
+use crate::model::errors::Error;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs and verifies the tokens of the read-only share links to recorded allocations. A token
+/// consists of the id of the allocation, the expiry as a Unix timestamp and an HMAC-SHA256
+/// signature of both, so it can be neither forged nor extended without the secret.
+pub struct ShareSigner {
+    secret: Vec<u8>,
+}
+
+impl ShareSigner {
+    pub fn new(secret: &[u8]) -> ShareSigner {
+        ShareSigner {
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// Creates a signer with the secret from the environment variable given in the configuration,
+    /// or with a random secret if the variable isn't set.
+    pub fn from_config(config: &SharingConfig) -> ShareSigner {
+        match std::env::var(&config.secret_variable) {
+            Ok(secret) => ShareSigner::new(secret.as_bytes()),
+            Err(_) => {
+                let mut secret = [0u8; 32];
+                OsRng.fill_bytes(&mut secret);
+                ShareSigner::new(&secret)
+            }
+        }
+    }
+
+    /// Returns the token of a link to the allocation that is valid until the expiry.
+    pub fn sign(&self, id: u64, expires_at: DateTime<Utc>) -> String {
+        let payload = format!("{id}.{}", expires_at.timestamp());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+
+        format!("{payload}.{signature}")
+    }
+
+    /// Returns the id of the allocation and the expiry of a valid token, or an error if the token
+    /// is malformed, has an invalid signature or has expired.
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<(u64, DateTime<Utc>), Error> {
+        let invalid_token = || Error {
+            code: "invalid-share-token".to_string(),
+            message: "Share link is invalid.".to_string(),
+        };
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid_token)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| invalid_token())?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| invalid_token())?;
+
+        let (id, expires_at) = payload.split_once('.').ok_or_else(invalid_token)?;
+        let id = id.parse::<u64>().map_err(|_| invalid_token())?;
+        let expires_at = expires_at
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .ok_or_else(invalid_token)?;
+        if expires_at <= now {
+            return Err(Error {
+                code: "expired-share-token".to_string(),
+                message: format!("Share link expired at {expires_at}."),
+            });
+        }
+
+        Ok((id, expires_at))
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length.");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+/// Renders a minimal HTML page with the allocation and its analysis for recipients of a share
+/// link, who don't have access to the API.
+pub fn render_shared_allocation(
+    allocation: &StoredAllocation,
+    expires_at: DateTime<Utc>,
+) -> String {
+    let percent = |fraction: f64| format!("{:.2}%", 100.0 * fraction);
+    let mut body = format!(
+        "<h1>Allocation {}</h1>\n<p>Performed on {} by version {}. This link expires on {}.</p>\n",
+        allocation.id,
+        allocation.created_at.format("%Y-%m-%d %H:%M UTC"),
+        escape_html(&allocation.crate_version),
+        expires_at.format("%Y-%m-%d %H:%M UTC")
+    );
+
+    match (&allocation.response.result, &allocation.response.error) {
+        (Some(result), _) => {
+            body.push_str("<table>\n<tr><th>Ticker</th><th>Fraction</th></tr>\n");
+            for tf in &result.allocations {
+                body.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&tf.ticker),
+                    percent(tf.fraction)
+                ));
+            }
+            body.push_str(&format!(
+                "<tr><td>Cash</td><td>{}</td></tr>\n</table>\n",
+                percent(result.cash)
+            ));
+
+            let analysis = &result.analysis;
+            body.push_str(&format!(
+                "<h2>Analysis</h2>\n<table>\n\
+                <tr><td>Expected return</td><td>{}</td></tr>\n\
+                <tr><td>Expected logarithmic growth</td><td>{:.4}</td></tr>\n\
+                <tr><td>Worst-case return</td><td>{} with probability {}</td></tr>\n\
+                <tr><td>Effective number of positions</td><td>{:.2}</td></tr>\n\
+                <tr><td>Gross exposure</td><td>{}</td></tr>\n</table>\n",
+                percent(analysis.expected_return),
+                result.solver_metadata.expected_log_growth,
+                percent(analysis.worst_case_outcome.portfolio_return),
+                percent(analysis.worst_case_outcome.probability),
+                analysis.effective_number_of_positions,
+                percent(analysis.gross_exposure)
+            ));
+        }
+        (None, Some(error)) => body.push_str(&format!(
+            "<p>Allocation failed: {}</p>\n",
+            escape_html(&error.message)
+        )),
+        (None, None) => body.push_str("<p>Allocation has no result.</p>\n"),
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>Allocation {}</title>\n\
+        <style>body {{ font-family: sans-serif; margin: 2em; }} \
+        td, th {{ padding: 0.2em 1em; text-align: left; }}</style>\n\
+        </head>\n<body>\n{body}</body>\n</html>\n",
+        allocation.id
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = ShareSigner::new(b"secret");
+        let now = Utc::now();
+        let expires_at = DateTime::from_timestamp(now.timestamp() + 3600, 0).unwrap();
+        let token = signer.sign(7, expires_at);
+
+        assert_eq!((7, expires_at), signer.verify(&token, now).unwrap());
+        assert_eq!(
+            "expired-share-token",
+            signer
+                .verify(&token, now + Duration::hours(2))
+                .unwrap_err()
+                .code
+        );
+
+        // Tokens can't be modified or verified with another secret
+        let extended = token.replacen(
+            &expires_at.timestamp().to_string(),
+            &(expires_at.timestamp() + 3600).to_string(),
+            1,
+        );
+        let error = |token: &str, signer: &ShareSigner| signer.verify(token, now).unwrap_err().code;
+        assert_eq!("invalid-share-token", error(&extended, &signer));
+        assert_eq!(
+            "invalid-share-token",
+            error(&token, &ShareSigner::new(b"other"))
+        );
+        assert_eq!("invalid-share-token", error("7", &signer));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::model::allocations::FractionChange;
+use crate::model::company::Ticker;
+use crate::model::errors::Error;
+use crate::model::portfolio::Portfolio;
+use crate::model::responses::{AnalysisResult, TickerAndFraction};
+use crate::model::snapshots::{
+    AnalysisSnapshot, SnapshotChanges, SnapshotHistory, SnapshotSummary,
+};
+use crate::storage::{load_all, save, Collection, Storage};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Store of the analysis snapshots of named portfolios, versioned per portfolio starting at 1.
+/// All snapshots of a portfolio are persisted as a single document in the storage, and all
+/// portfolios are loaded when the store is created.
+pub struct SnapshotStore {
+    storage: Arc<dyn Storage>,
+    portfolios: Mutex<HashMap<String, Vec<AnalysisSnapshot>>>,
+}
+
+impl SnapshotStore {
+    /// Creates a new [SnapshotStore] on top of the storage, loading the persisted snapshots.
+    pub fn new(storage: Arc<dyn Storage>) -> Result<SnapshotStore, Error> {
+        let portfolios =
+            load_all::<Vec<AnalysisSnapshot>>(storage.as_ref(), Collection::Snapshots)?
+                .into_iter()
+                .collect();
+
+        Ok(SnapshotStore {
+            storage,
+            portfolios: Mutex::new(portfolios),
+        })
+    }
+
+    /// Records the analysis of the portfolio as the next snapshot of the named portfolio and
+    /// returns the snapshot.
+    pub fn record(
+        &self,
+        name: &str,
+        portfolio: Portfolio,
+        analysis: AnalysisResult,
+    ) -> Result<AnalysisSnapshot, Error> {
+        self.record_at(name, portfolio, analysis, Utc::now())
+    }
+
+    /// Same as [SnapshotStore::record], with the given time of the snapshot.
+    fn record_at(
+        &self,
+        name: &str,
+        portfolio: Portfolio,
+        analysis: AnalysisResult,
+        now: DateTime<Utc>,
+    ) -> Result<AnalysisSnapshot, Error> {
+        validate_portfolio_name(name)?;
+
+        let mut portfolios = self.portfolios.lock().unwrap();
+        let mut snapshots = portfolios.get(name).cloned().unwrap_or_default();
+        let snapshot = AnalysisSnapshot {
+            version: snapshots.last().map_or(1, |s| s.version + 1),
+            created_at: now,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            portfolio,
+            analysis,
+        };
+        snapshots.push(snapshot.clone());
+        save(
+            self.storage.as_ref(),
+            Collection::Snapshots,
+            name,
+            &snapshots,
+        )?;
+        portfolios.insert(name.to_string(), snapshots);
+
+        Ok(snapshot)
+    }
+
+    /// Returns how the expected return, the tail risk and the weights of the named portfolio
+    /// evolved across its snapshots, or None if the portfolio has no snapshots.
+    pub fn history(&self, name: &str) -> Option<SnapshotHistory> {
+        let portfolios = self.portfolios.lock().unwrap();
+        let snapshots = portfolios.get(name)?;
+
+        let summaries = snapshots
+            .iter()
+            .enumerate()
+            .map(|(i, snapshot)| {
+                let previous = i.checked_sub(1).map(|p| &snapshots[p]);
+                SnapshotSummary {
+                    version: snapshot.version,
+                    created_at: snapshot.created_at,
+                    expected_return: snapshot.analysis.expected_return,
+                    worst_case_return: snapshot.analysis.worst_case_outcome.portfolio_return,
+                    cumulative_probability_of_loss: snapshot
+                        .analysis
+                        .cumulative_probability_of_loss,
+                    weights: weights(&snapshot.portfolio),
+                    changes: previous.map(|p| snapshot_changes(p, snapshot)),
+                }
+            })
+            .collect();
+
+        Some(SnapshotHistory {
+            name: name.to_string(),
+            snapshots: summaries,
+        })
+    }
+}
+
+/// Weights of the companies in the portfolio, in the order of the portfolio.
+fn weights(portfolio: &Portfolio) -> Vec<TickerAndFraction> {
+    portfolio
+        .companies
+        .iter()
+        .map(|pc| TickerAndFraction {
+            ticker: pc.company.ticker.clone(),
+            fraction: pc.fraction,
+        })
+        .collect()
+}
+
+/// Compares two consecutive snapshots of the same portfolio.
+fn snapshot_changes(previous: &AnalysisSnapshot, current: &AnalysisSnapshot) -> SnapshotChanges {
+    let previous_weights: HashMap<Ticker, f64> = weights(&previous.portfolio)
+        .into_iter()
+        .map(|tf| (tf.ticker, tf.fraction))
+        .collect();
+    let current_weights: HashMap<Ticker, f64> = weights(&current.portfolio)
+        .into_iter()
+        .map(|tf| (tf.ticker, tf.fraction))
+        .collect();
+
+    // Tickers are ordered by their appearance in the previous snapshot, followed by new ones
+    let weight_changes = previous
+        .portfolio
+        .companies
+        .iter()
+        .chain(current.portfolio.companies.iter())
+        .map(|pc| pc.company.ticker.clone())
+        .unique()
+        .map(|ticker| {
+            let original_fraction = previous_weights.get(&ticker).copied().unwrap_or(0.0);
+            let new_fraction = current_weights.get(&ticker).copied().unwrap_or(0.0);
+            FractionChange {
+                ticker,
+                original_fraction,
+                new_fraction,
+                change: new_fraction - original_fraction,
+            }
+        })
+        .collect();
+
+    SnapshotChanges {
+        expected_return_change: current.analysis.expected_return
+            - previous.analysis.expected_return,
+        worst_case_return_change: current.analysis.worst_case_outcome.portfolio_return
+            - previous.analysis.worst_case_outcome.portfolio_return,
+        cumulative_probability_of_loss_change: previous
+            .analysis
+            .cumulative_probability_of_loss
+            .zip(current.analysis.cumulative_probability_of_loss)
+            .map(|(p, c)| c - p),
+        weight_changes,
+    }
+}
+
+/// Portfolio names are used as storage keys, so only a safe subset of characters is allowed.
+fn validate_portfolio_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error {
+            code: "invalid-portfolio-name".to_string(),
+            message: format!(
+                "Portfolio name can only contain alphanumeric characters, dashes and underscores. \
+                You provided {name}."
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::responses::ProbabilityAndReturns;
+    use crate::storage::memory_storage::MemoryStorage;
+    use chrono::Duration;
+
+    fn portfolio(fractions: &[(&str, f64)]) -> Portfolio {
+        serde_json::from_value(serde_json::json!({
+            "companies": fractions
+                .iter()
+                .map(|(ticker, fraction)| serde_json::json!({
+                    "company": {
+                        "name": ticker,
+                        "ticker": ticker,
+                        "description": "",
+                        "market_cap": 1.0,
+                        "scenarios": [{"thesis": "Up", "intrinsic_value": 2.0, "probability": 1.0}]
+                    },
+                    "fraction": fraction
+                }))
+                .collect::<Vec<_>>()
+        }))
+        .unwrap()
+    }
+
+    fn analysis(expected_return: f64, worst_case_return: f64) -> AnalysisResult {
+        AnalysisResult {
+            worst_case_outcome: ProbabilityAndReturns {
+                probability: 0.1,
+                portfolio_return: worst_case_return,
+                probability_weighted_return: 0.1 * worst_case_return,
+            },
+            cumulative_probability_of_loss: Some(0.1),
+            expected_return,
+            expected_log_growth_rate: None,
+            compound_growth: None,
+            variance_of_return: 0.0,
+            effective_number_of_positions: 1.0,
+            max_weight: 1.0,
+            gross_exposure: 1.0,
+            net_exposure: 1.0,
+            events: vec![],
+            inflation_adjusted: None,
+            sampled_statistics: None,
+            value_at_risk_95: None,
+            conditional_value_at_risk_95: None,
+            probability_of_underperforming_benchmark: None,
+            probability_of_return_below_threshold: None,
+            distribution: None,
+            tag_conditional_outcomes: None,
+            numerical_errors: None,
+        }
+    }
+
+    #[test]
+    fn test_history_with_changes() {
+        let store = SnapshotStore::new(Arc::new(MemoryStorage::default())).unwrap();
+        let now = Utc::now();
+        store
+            .record_at(
+                "book",
+                portfolio(&[("A", 0.6), ("B", 0.4)]),
+                analysis(0.2, -0.5),
+                now,
+            )
+            .unwrap();
+        let snapshot = store
+            .record_at(
+                "book",
+                portfolio(&[("B", 0.5), ("C", 0.3)]),
+                analysis(0.15, -0.3),
+                now + Duration::days(1),
+            )
+            .unwrap();
+        assert_eq!(2, snapshot.version);
+
+        let history = store.history("book").unwrap();
+        assert_eq!("book", history.name);
+        assert_eq!(2, history.snapshots.len());
+        assert!(history.snapshots[0].changes.is_none());
+        assert_eq!(now, history.snapshots[0].created_at);
+
+        let changes = history.snapshots[1].changes.as_ref().unwrap();
+        assert!((changes.expected_return_change + 0.05).abs() < 1e-12);
+        assert!((changes.worst_case_return_change - 0.2).abs() < 1e-12);
+        assert_eq!(Some(0.0), changes.cumulative_probability_of_loss_change);
+        assert_eq!(
+            vec![("A", -0.6), ("B", 0.1), ("C", 0.3)],
+            changes
+                .weight_changes
+                .iter()
+                .map(|c| (c.ticker.as_str(), (c.change * 1e6).round() / 1e6))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(store.history("other").is_none());
+    }
+
+    #[test]
+    fn test_persisted_snapshots_are_loaded() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        SnapshotStore::new(storage.clone())
+            .unwrap()
+            .record("book", portfolio(&[("A", 1.0)]), analysis(0.2, -0.5))
+            .unwrap();
+        let store = SnapshotStore::new(storage).unwrap();
+
+        let snapshot = store
+            .record("book", portfolio(&[("A", 0.5)]), analysis(0.1, -0.2))
+            .unwrap();
+        assert_eq!(2, snapshot.version);
+        assert_eq!(2, store.history("book").unwrap().snapshots.len());
+    }
+
+    #[test]
+    fn test_invalid_portfolio_name() {
+        let store = SnapshotStore::new(Arc::new(MemoryStorage::default())).unwrap();
+        let error = store
+            .record("../book", portfolio(&[("A", 1.0)]), analysis(0.2, -0.5))
+            .unwrap_err();
+        assert_eq!("invalid-portfolio-name", error.code);
+    }
+}
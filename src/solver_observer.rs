@@ -0,0 +1,82 @@
+use crate::model::company::Ticker;
+use bitvec::slice::BitSlice;
+use nalgebra::DVector;
+
+/// Observer of the nonlinear solver, invoked after each Newton-Raphson iteration. Useful for
+/// instrumenting the solution process, e.g. when experimenting with relaxation strategies. Since
+/// the solver only holds a shared reference to the observer, implementations that record the
+/// iterations need interior mutability (e.g. a RefCell).
+pub trait SolverObserver {
+    fn on_iteration(&self, iteration: &SolverIteration);
+
+    /// Invoked after each system is solved, e.g. for recording the solution of each combination of
+    /// active constraints. Does nothing by default.
+    fn on_system(&self, _system: &SolvedSystem) {}
+
+    /// Invoked before the solver enumerates the combinations of active constraints, with the
+    /// number of systems of the enumeration (2^N for N inequality constraints). Does nothing by
+    /// default.
+    fn on_enumeration(&self, _n_systems: u64) {}
+}
+
+/// Outcome of solving a single system for a choice of candidates.
+pub struct SolvedSystem<'a> {
+    /// Tickers of the candidates, in the order of the fractions in the solution vector.
+    pub tickers: &'a [Ticker],
+
+    /// Activity of each inequality constraint in the system, or None if the active constraints are
+    /// found by the solver itself, i.e. by the projected Newton or the penalty method.
+    pub active_constraints: Option<&'a BitSlice>,
+
+    /// Converged solution vector, or None if the system couldn't be solved.
+    pub x: Option<&'a DVector<f64>>,
+
+    /// Whether the solution satisfies the inactive constraints and is a candidate for the
+    /// allocation.
+    pub is_viable: bool,
+}
+
+/// State of the nonlinear solver after a single Newton-Raphson iteration.
+pub struct SolverIteration<'a> {
+    /// Zero-based index of the iteration within the current solution of the system.
+    pub iteration: u32,
+
+    /// Solution vector after the update, i.e. fractions of the candidates followed by the slack
+    /// variables and Lagrange multipliers of the constraints (if any).
+    pub x: &'a DVector<f64>,
+
+    /// Residual used for the convergence check (L-infinity norm of the Newton step).
+    pub residual: f64,
+
+    /// Estimate of the condition number of the Jacobian from the pivots of its LU decomposition.
+    pub condition_number: f64,
+
+    /// Penalty factor if the iteration belongs to the fallback penalty method, None otherwise.
+    pub penalty_factor: Option<f64>,
+}
+
+/// Estimates the condition number of a matrix from the pivots of its LU decomposition as the ratio
+/// of the largest and the smallest pivot magnitude, which is cheap and doesn't require the inverse.
+/// Infinite if any of the pivots is zero.
+pub fn condition_number_estimate(pivots: impl Iterator<Item = f64>) -> f64 {
+    let (min, max) = pivots.fold((f64::INFINITY, 0.0_f64), |(min, max), p| {
+        (min.min(p.abs()), max.max(p.abs()))
+    });
+    max / min
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_condition_number_estimate() {
+        assert_close!(
+            4.0,
+            condition_number_estimate([2.0, -0.5].into_iter()),
+            1e-12
+        );
+        assert!(condition_number_estimate([1.0, 0.0].into_iter()).is_infinite());
+    }
+}
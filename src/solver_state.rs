@@ -0,0 +1,239 @@
+use crate::allocate_with_observer;
+use crate::model::errors::Error;
+use crate::model::portfolio::AllocationInput;
+use crate::model::responses::TickerAndFraction;
+use crate::model::solver_state::{SolverState, SolverStateComparison, SystemState};
+use crate::solver_observer::{SolvedSystem, SolverIteration, SolverObserver};
+use itertools::{EitherOrBoth, Itertools};
+use slog::{info, Logger};
+use std::cell::RefCell;
+use std::path::Path;
+
+/// Version of the library recorded in the solver state.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Observer recording the solution of each system solved for an allocation.
+#[derive(Default)]
+pub struct SolverStateRecorder {
+    systems: RefCell<Vec<SystemState>>,
+}
+
+impl SolverStateRecorder {
+    /// Returns the state of the solver with the systems recorded for the allocation of the input.
+    pub fn solver_state(
+        self,
+        input: AllocationInput,
+        allocations: Vec<TickerAndFraction>,
+    ) -> SolverState {
+        SolverState {
+            version: VERSION.to_string(),
+            input,
+            systems: self.systems.into_inner(),
+            allocations,
+        }
+    }
+}
+
+impl SolverObserver for SolverStateRecorder {
+    fn on_iteration(&self, _iteration: &SolverIteration) {}
+
+    // Non-finite solutions can't be represented in JSON, so they're recorded like failed systems
+    fn on_system(&self, system: &SolvedSystem) {
+        self.systems.borrow_mut().push(SystemState {
+            tickers: system.tickers.to_vec(),
+            active_constraints: system
+                .active_constraints
+                .map(|active| active.iter().map(|a| *a).collect()),
+            solution: system
+                .x
+                .filter(|x| x.iter().all(|v| v.is_finite()))
+                .map(|x| x.iter().copied().collect()),
+            is_viable: system.is_viable,
+        });
+    }
+}
+
+/// Writes the solver state into the file as JSON. Floating point numbers are written such that
+/// reading them back gives bitwise identical values.
+pub fn write_solver_state(path: &Path, solver_state: &SolverState) -> Result<(), Error> {
+    let content = serde_json::to_string_pretty(solver_state).map_err(|e| Error {
+        code: "failed-to-serialize-result".to_string(),
+        message: format!("Failed to serialize the solver state: {e}"),
+    })?;
+    std::fs::write(path, content).map_err(|e| Error {
+        code: "failed-to-write-output".to_string(),
+        message: format!("Failed to write {}: {e}", path.display()),
+    })
+}
+
+/// Reads the solver state written by [write_solver_state] from the file.
+pub fn read_solver_state(path: &Path) -> Result<SolverState, Error> {
+    let content = std::fs::read_to_string(path).map_err(|e| Error {
+        code: "failed-to-read-solver-state".to_string(),
+        message: format!("Failed to read {}: {e}", path.display()),
+    })?;
+    serde_json::from_str(&content).map_err(|e| Error {
+        code: "invalid-solver-state".to_string(),
+        message: format!("Failed to deserialize solver state {}: {e}", path.display()),
+    })
+}
+
+/// Performs the allocation of the recorded input again and compares the systems and the
+/// allocation with the recorded ones, which are bitwise identical if the allocation is reproduced
+/// exactly.
+pub fn reproduce_solver_state(
+    solver_state: &SolverState,
+    logger: &Logger,
+) -> Result<SolverStateComparison, Error> {
+    info!(
+        logger,
+        "Reproducing the allocation recorded with version {} using version {VERSION}.",
+        solver_state.version
+    );
+    let recorder = SolverStateRecorder::default();
+    let response = allocate_with_observer(solver_state.input.clone(), Some(&recorder), logger);
+    let allocations = match (response.result, response.error) {
+        (Some(result), _) => result.allocations,
+        (None, Some(e)) => return Err(e),
+        (None, None) => {
+            return Err(Error {
+                code: "solver-state-input-is-invalid".to_string(),
+                message: "Input of the solver state has validation errors, so the allocation \
+                    can't be reproduced."
+                    .to_string(),
+            })
+        }
+    };
+    let reproduced = recorder.solver_state(solver_state.input.clone(), allocations);
+
+    let first_differing_system = solver_state
+        .systems
+        .iter()
+        .zip_longest(&reproduced.systems)
+        .position(|systems| match systems {
+            EitherOrBoth::Both(recorded, reproduced) => !is_identical(recorded, reproduced),
+            _ => true,
+        });
+    let max_fraction_difference = solver_state
+        .allocations
+        .iter()
+        .zip_longest(&reproduced.allocations)
+        .map(|allocations| match allocations {
+            EitherOrBoth::Both(recorded, reproduced) if recorded.ticker == reproduced.ticker => {
+                (recorded.fraction - reproduced.fraction).abs()
+            }
+            EitherOrBoth::Both(recorded, reproduced) => {
+                recorded.fraction.abs().max(reproduced.fraction.abs())
+            }
+            EitherOrBoth::Left(allocation) | EitherOrBoth::Right(allocation) => {
+                allocation.fraction.abs()
+            }
+        })
+        .fold(0.0, f64::max);
+
+    Ok(SolverStateComparison {
+        version: VERSION.to_string(),
+        is_exact: first_differing_system.is_none() && max_fraction_difference == 0.0,
+        n_recorded_systems: solver_state.systems.len(),
+        n_reproduced_systems: reproduced.systems.len(),
+        first_differing_system,
+        max_fraction_difference,
+    })
+}
+
+/// Whether the systems have the same candidates, activity mask and bitwise identical solutions.
+fn is_identical(recorded: &SystemState, reproduced: &SystemState) -> bool {
+    let bits = |s: &SystemState| {
+        s.solution
+            .as_ref()
+            .map(|x| x.iter().map(|v| v.to_bits()).collect_vec())
+    };
+    recorded.tickers == reproduced.tickers
+        && recorded.active_constraints == reproduced.active_constraints
+        && recorded.is_viable == reproduced.is_viable
+        && bits(recorded) == bits(reproduced)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::allocate_with_options;
+    use crate::env::create_test_logger;
+    use crate::model::allocation_options::AllocationOptions;
+
+    #[test]
+    fn test_reproduce_solver_state() {
+        let logger = create_test_logger();
+        let input: AllocationInput = serde_yaml::from_str(
+            "
+            long_only: true
+            max_total_leverage_ratio: 0.0
+            candidates:
+              - name: A
+                ticker: A
+                description: Doubles or halves
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Doubles
+                    intrinsic_value: 2.0
+                    probability: 0.5
+                  - thesis: Halves
+                    intrinsic_value: 0.5
+                    probability: 0.5
+              - name: B
+                ticker: B
+                description: Triples or is lost
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Triples
+                    intrinsic_value: 3.0
+                    probability: 0.4
+                  - thesis: Lost
+                    intrinsic_value: 0.0
+                    probability: 0.6
+            ",
+        )
+        .unwrap();
+        let options = AllocationOptions {
+            solver_state: Some(true),
+            ..AllocationOptions::default()
+        };
+        let solver_state = allocate_with_options(input, &options, &logger)
+            .result
+            .unwrap()
+            .solver_state
+            .unwrap();
+
+        // Two long-only constraints and the leverage constraint give eight activity masks
+        assert_eq!(8, solver_state.systems.len());
+        assert!(solver_state
+            .systems
+            .iter()
+            .all(|s| s.active_constraints.as_ref().unwrap().len() == 3));
+
+        let path = std::env::temp_dir().join(format!(
+            "charlie_test_solver_state_{}.json",
+            std::process::id()
+        ));
+        write_solver_state(&path, &solver_state).unwrap();
+        let mut solver_state = read_solver_state(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let comparison = reproduce_solver_state(&solver_state, &logger).unwrap();
+        assert!(comparison.is_exact);
+        assert_eq!(8, comparison.n_reproduced_systems);
+        assert_eq!(0.0, comparison.max_fraction_difference);
+
+        // A solution differing in the last bit is reported as the first differing system
+        let index = solver_state
+            .systems
+            .iter()
+            .position(|s| s.solution.is_some())
+            .unwrap();
+        let solution = solver_state.systems[index].solution.as_mut().unwrap();
+        solution[0] = f64::from_bits(solution[0].to_bits() + 1);
+        let comparison = reproduce_solver_state(&solver_state, &logger).unwrap();
+        assert!(!comparison.is_exact);
+        assert_eq!(Some(index), comparison.first_differing_system);
+    }
+}
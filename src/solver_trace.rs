@@ -0,0 +1,127 @@
+use crate::kelly_allocation::{KellyAllocator, MAX_ITER};
+use crate::model::company::Company;
+use crate::model::errors::Error;
+use crate::model::responses::{SolverTrace, SolverTraceIteration};
+use crate::model::solver_settings::{SolverSettings, SolverStrategy};
+use crate::solver_observer::{SolverIteration, SolverObserver};
+use slog::{info, Logger};
+use std::cell::RefCell;
+
+/// Problems with more candidates are not traced, since the trace is meant to be animated.
+pub const MAX_TRACE_CANDIDATES: usize = 5;
+
+/// Observer recording the fractions and the residual of each iteration.
+struct TraceRecorder {
+    n_candidates: usize,
+    iterations: RefCell<Vec<SolverTraceIteration>>,
+}
+
+impl SolverObserver for TraceRecorder {
+    fn on_iteration(&self, iteration: &SolverIteration) {
+        self.iterations.borrow_mut().push(SolverTraceIteration {
+            iteration: iteration.iteration,
+            fractions: iteration
+                .x
+                .iter()
+                .take(self.n_candidates)
+                .copied()
+                .collect(),
+            residual: iteration.residual,
+        });
+    }
+}
+
+/// Solves the unconstrained Kelly problem for the candidates with the plain Newton-Raphson method
+/// and returns the fractions and the residual after each iteration, for illustrating how the
+/// solver converges to the optimal allocation. Constraints are deliberately left out, such that the
+/// trace shows the iteration of the Kelly criterion alone.
+pub fn solver_trace(candidates: Vec<Company>, logger: &Logger) -> Result<SolverTrace, Error> {
+    if candidates.len() > MAX_TRACE_CANDIDATES {
+        return Err(Error {
+            code: "too-many-candidates-to-trace".to_string(),
+            message: format!(
+                "The solver is only traced for up to {MAX_TRACE_CANDIDATES} candidates. You \
+                provided {}.",
+                candidates.len()
+            ),
+        });
+    }
+
+    info!(
+        logger,
+        "Tracing the solver for {} candidates.",
+        candidates.len()
+    );
+    let recorder = TraceRecorder {
+        n_candidates: candidates.len(),
+        iterations: RefCell::new(vec![]),
+    };
+    let tickers = candidates.iter().map(|c| c.ticker.clone()).collect();
+    KellyAllocator::new(logger, MAX_ITER)
+        .with_solver_settings(&SolverSettings {
+            strategy: Some(SolverStrategy::Plain),
+            regularization: None,
+            max_systems: None,
+            initial_fractions: None,
+            max_iterations: None,
+            solver_tolerance: None,
+            relaxation_factor: None,
+        })
+        .with_solver_observer(&recorder)
+        .solve(candidates)?;
+
+    Ok(SolverTrace {
+        tickers,
+        iterations: recorder.iterations.into_inner(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::create_test_logger;
+    use crate::kelly_allocation::SOLVER_TOLERANCE;
+
+    fn candidate(ticker: &str) -> Company {
+        serde_yaml::from_str(&format!(
+            "
+            name: {ticker}
+            ticker: {ticker}
+            description: Business {ticker}
+            market_cap: 100.0
+            scenarios:
+              - thesis: Down
+                intrinsic_value: 50.0
+                probability: 0.5
+              - thesis: Up
+                intrinsic_value: 200.0
+                probability: 0.5
+            "
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_solver_trace() {
+        let logger = create_test_logger();
+        let trace = solver_trace(vec![candidate("A"), candidate("B")], &logger).unwrap();
+
+        assert_eq!(vec!["A", "B"], trace.tickers);
+        assert!(trace.iterations.len() > 1);
+        assert!(trace.iterations.iter().all(|i| i.fractions.len() == 2));
+        let last = trace.iterations.last().unwrap();
+        assert!(last.residual < SOLVER_TOLERANCE);
+        assert_eq!(trace.iterations.len() as u32 - 1, last.iteration);
+    }
+
+    #[test]
+    fn test_solver_trace_with_too_many_candidates() {
+        let logger = create_test_logger();
+        let candidates = (0..=MAX_TRACE_CANDIDATES)
+            .map(|i| candidate(&format!("C{i}")))
+            .collect();
+
+        let error = solver_trace(candidates, &logger).unwrap_err();
+        assert_eq!("too-many-candidates-to-trace", error.code);
+    }
+}
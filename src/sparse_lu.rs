@@ -0,0 +1,162 @@
+use nalgebra::{DMatrix, DVector};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Relative threshold for the pivot magnitude: among the rows whose entry in the pivot column is
+/// at least this fraction of the largest one, the row with the fewest nonzeros is chosen as the
+/// pivot row, trading a bit of numerical stability for less fill-in.
+const PIVOT_THRESHOLD: f64 = 0.1;
+
+/// LU decomposition of a sparse square matrix computed with Gaussian elimination and threshold
+/// partial pivoting. Only the nonzero entries are stored, which keeps the decomposition cheap for
+/// the linearized systems with many constraints, whose constraint rows and columns are mostly zero.
+pub struct SparseLu {
+    /// Row chosen as the pivot row for each column, in the order of elimination.
+    pivot_rows: Vec<usize>,
+    /// Multipliers of the pivot row subtracted from the other rows when eliminating each column.
+    multipliers: Vec<Vec<(usize, f64)>>,
+    /// Rows of the upper triangular factor, indexed by the original row.
+    upper_rows: Vec<BTreeMap<usize, f64>>,
+}
+
+impl SparseLu {
+    /// Returns the decomposition of the given square matrix, or None if the matrix is singular.
+    pub fn new(matrix: &DMatrix<f64>) -> Option<SparseLu> {
+        assert!(matrix.is_square(), "Can't decompose a non-square matrix.");
+        let n = matrix.nrows();
+
+        let mut rows: Vec<BTreeMap<usize, f64>> = vec![BTreeMap::new(); n];
+        let mut column_rows: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+        for row in 0..n {
+            for column in 0..n {
+                let value = matrix[(row, column)];
+                if value != 0.0 {
+                    rows[row].insert(column, value);
+                    column_rows[column].insert(row);
+                }
+            }
+        }
+
+        let mut pivot_rows: Vec<usize> = Vec::with_capacity(n);
+        let mut multipliers: Vec<Vec<(usize, f64)>> = Vec::with_capacity(n);
+        for column in 0..n {
+            // Rows already chosen as pivot rows are removed from the column sets, so only the
+            // remaining rows are candidates
+            let candidates: Vec<usize> = column_rows[column].iter().copied().collect();
+            let max_magnitude = candidates
+                .iter()
+                .map(|&r| rows[r][&column].abs())
+                .fold(0.0, f64::max);
+            if max_magnitude == 0.0 {
+                return None;
+            }
+            let pivot_row = candidates
+                .iter()
+                .copied()
+                .filter(|&r| rows[r][&column].abs() >= PIVOT_THRESHOLD * max_magnitude)
+                .min_by_key(|&r| rows[r].len())
+                .expect("Row with the largest magnitude always qualifies.");
+            let pivot_entries: Vec<(usize, f64)> =
+                rows[pivot_row].iter().map(|(&c, &v)| (c, v)).collect();
+            for &(c, _) in &pivot_entries {
+                column_rows[c].remove(&pivot_row);
+            }
+
+            let pivot = rows[pivot_row][&column];
+            let mut column_multipliers: Vec<(usize, f64)> = Vec::with_capacity(candidates.len());
+            for row in candidates.into_iter().filter(|&r| r != pivot_row) {
+                let multiplier = rows[row][&column] / pivot;
+                for &(c, pivot_value) in &pivot_entries {
+                    if c == column {
+                        rows[row].remove(&c);
+                        column_rows[c].remove(&row);
+                        continue;
+                    }
+                    let value = rows[row].entry(c).or_insert(0.0);
+                    *value -= multiplier * pivot_value;
+                    column_rows[c].insert(row);
+                }
+                column_multipliers.push((row, multiplier));
+            }
+
+            pivot_rows.push(pivot_row);
+            multipliers.push(column_multipliers);
+        }
+
+        Some(SparseLu {
+            pivot_rows,
+            multipliers,
+            upper_rows: rows,
+        })
+    }
+
+    /// Returns the pivots, i.e. the diagonal of the upper triangular factor, in the order of the
+    /// unknowns.
+    pub fn pivots(&self) -> impl Iterator<Item = f64> + '_ {
+        self.pivot_rows
+            .iter()
+            .enumerate()
+            .map(|(column, &row)| self.upper_rows[row][&column])
+    }
+
+    /// Returns the solution x of the system A x = b, where A is the decomposed matrix.
+    pub fn solve(&self, right_hand_side: &DVector<f64>) -> DVector<f64> {
+        let n = self.pivot_rows.len();
+
+        // Forward substitution with the multipliers, applied in the order of elimination
+        let mut y: DVector<f64> = right_hand_side.clone();
+        for (&pivot_row, column_multipliers) in self.pivot_rows.iter().zip(&self.multipliers) {
+            let pivot_value = y[pivot_row];
+            for &(row, multiplier) in column_multipliers {
+                y[row] -= multiplier * pivot_value;
+            }
+        }
+
+        // Back substitution with the upper triangular rows, whose columns are the unknowns
+        let mut x: DVector<f64> = DVector::zeros(n);
+        for column in (0..n).rev() {
+            let pivot_row = self.pivot_rows[column];
+            let upper_row = &self.upper_rows[pivot_row];
+            let sum: f64 = upper_row
+                .range(column + 1..)
+                .map(|(&c, &value)| value * x[c])
+                .sum();
+            x[column] = (y[pivot_row] - sum) / upper_row[&column];
+        }
+
+        x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn test_solve_matches_dense_inverse() {
+        // Saddle point system with a zero diagonal block, which requires pivoting
+        let matrix = DMatrix::from_row_slice(
+            4,
+            4,
+            &[
+                -2.0, 0.5, 0.0, -1.0, //
+                0.5, -3.0, -1.0, -1.0, //
+                0.0, -1.0, 0.0, 0.0, //
+                -1.0, -1.0, 0.0, 0.0, //
+            ],
+        );
+        let right_hand_side = DVector::from_vec(vec![1.0, -2.0, 0.3, 0.5]);
+
+        let expected = matrix.clone().try_inverse().unwrap() * &right_hand_side;
+        let actual = SparseLu::new(&matrix).unwrap().solve(&right_hand_side);
+        for (e, a) in expected.iter().zip(actual.iter()) {
+            assert_close!(*e, *a, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_singular_matrix() {
+        let matrix = DMatrix::from_row_slice(3, 3, &[1.0, 2.0, 0.0, 2.0, 4.0, 0.0, 0.0, 0.0, 1.0]);
+        assert!(SparseLu::new(&matrix).is_none());
+    }
+}
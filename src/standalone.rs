@@ -0,0 +1,54 @@
+use serde_json::Value;
+
+/// Demo front-end embedded into the binary, such that the standalone mode doesn't need the demo
+/// directory.
+pub const DEMO_PAGE: &str = include_str!("../demo/demo.html");
+
+/// Pages served by the server in the standalone mode, which are embedded into the binary or
+/// generated at startup instead of being read from the project directory.
+#[derive(Clone, Debug)]
+pub struct StandalonePages {
+    pub demo: String,
+
+    /// OpenAPI documentation rendered with ReDoc from the schema generated at startup.
+    pub api: String,
+}
+
+impl StandalonePages {
+    pub fn new(schema: &Value) -> StandalonePages {
+        StandalonePages {
+            demo: DEMO_PAGE.to_string(),
+            api: api_page(schema),
+        }
+    }
+}
+
+/// Renders the OpenAPI documentation page with the schema embedded, such that ReDoc doesn't need
+/// to fetch it. The same ReDoc bundle is used as in schema/index.html.
+pub fn api_page(schema: &Value) -> String {
+    // A closing tag within a string of the schema would end the script early
+    let schema = schema.to_string().replace("</", "<\\/");
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf8\" />\n<title>Charlie</title>\n\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+        <style>body {{ padding: 0; margin: 0; }}</style>\n\
+        <script src=\"https://cdn.redoc.ly/redoc/v2.1.2/bundles/redoc.standalone.js\"></script>\n\
+        </head>\n<body>\n<div id=\"redoc\"></div>\n\
+        <script>Redoc.init({schema}, {{}}, document.getElementById(\"redoc\"));</script>\n\
+        </body>\n</html>\n"
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_api_page_embeds_schema() {
+        let page = api_page(&json!({"info": {"title": "Charlie</script>"}}));
+        assert!(page.contains("Redoc.init({\"info\":{\"title\":\"Charlie<\\/script>\"}}"));
+        assert_eq!(1, page.matches("</script>\n</body>").count());
+    }
+}
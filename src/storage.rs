@@ -0,0 +1,339 @@
+pub mod encrypted_storage;
+pub mod file_storage;
+pub mod memory_storage;
+pub mod sqlite_storage;
+
+use crate::config::{StorageBackend, StorageConfig};
+use crate::model::errors::Error;
+use crate::storage::encrypted_storage::EncryptedStorage;
+use crate::storage::file_storage::FileStorage;
+use crate::storage::memory_storage::MemoryStorage;
+use crate::storage::sqlite_storage::SqliteStorage;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Collections of documents kept by the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Collection {
+    /// Allocations (runs) recorded by the server, keyed by their id.
+    Allocations,
+    /// Candidates pushed to the server, keyed by the workspace.
+    Candidates,
+    /// Input templates (presets), keyed by their name.
+    Templates,
+    /// Background jobs, keyed by their id.
+    Jobs,
+    /// Analysis snapshots of named portfolios, keyed by the portfolio name.
+    Snapshots,
+}
+
+impl Collection {
+    /// All the collections.
+    pub const ALL: [Collection; 5] = [
+        Collection::Allocations,
+        Collection::Candidates,
+        Collection::Templates,
+        Collection::Jobs,
+        Collection::Snapshots,
+    ];
+
+    /// Name of the collection, used e.g. as the table key or the directory name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Collection::Allocations => "allocations",
+            Collection::Candidates => "candidates",
+            Collection::Templates => "templates",
+            Collection::Jobs => "jobs",
+            Collection::Snapshots => "snapshots",
+        }
+    }
+}
+
+/// Storage of the documents of the server, grouped into collections and identified by a key within
+/// the collection. Documents are opaque strings (JSON in practice), such that a backend only needs
+/// to store text. The stores on top of the storage (e.g. [crate::candidate_store::CandidateStore])
+/// take care of serialization and keep the endpoints independent of the backend.
+pub trait Storage: Send + Sync {
+    /// Returns the document stored under the key in the collection, if any.
+    fn get(&self, collection: Collection, key: &str) -> Result<Option<String>, Error>;
+
+    /// Stores the document under the key in the collection, replacing an existing one.
+    fn put(&self, collection: Collection, key: &str, document: &str) -> Result<(), Error>;
+
+    /// Returns all keys and documents in the collection, ordered by the key.
+    fn list(&self, collection: Collection) -> Result<Vec<(String, String)>, Error>;
+}
+
+/// Creates the storage backend selected in the configuration, encrypting the documents if the
+/// encryption is configured.
+pub fn create_storage(config: &StorageConfig) -> Result<Arc<dyn Storage>, Error> {
+    let path = || {
+        config.path.as_deref().ok_or_else(|| Error {
+            code: "storage-path-not-specified".to_string(),
+            message: format!(
+                "Storage backend {:?} requires a path. Check your configuration.",
+                config.backend
+            ),
+        })
+    };
+
+    let storage: Arc<dyn Storage> = match config.backend {
+        StorageBackend::Memory => Arc::new(MemoryStorage::default()),
+        StorageBackend::Sqlite => Arc::new(SqliteStorage::open(path()?)?),
+        StorageBackend::Files => Arc::new(FileStorage::new(path()?)),
+    };
+
+    Ok(match &config.encryption {
+        Some(encryption) => Arc::new(EncryptedStorage::from_config(storage, encryption)?),
+        None => storage,
+    })
+}
+
+/// Serializes the value and stores it under the key in the collection.
+pub fn save<T: Serialize>(
+    storage: &dyn Storage,
+    collection: Collection,
+    key: &str,
+    value: &T,
+) -> Result<(), Error> {
+    let document = serde_json::to_string_pretty(value).map_err(|e| Error {
+        code: "failed-to-serialize-document".to_string(),
+        message: format!("Failed to serialize {key} in {}: {e}", collection.name()),
+    })?;
+    storage.put(collection, key, &document)
+}
+
+/// Loads and deserializes the document stored under the key in the collection, if any.
+pub fn load<T: DeserializeOwned>(
+    storage: &dyn Storage,
+    collection: Collection,
+    key: &str,
+) -> Result<Option<T>, Error> {
+    storage
+        .get(collection, key)?
+        .map(|document| parse_document(collection, key, &document))
+        .transpose()
+}
+
+/// Loads and deserializes all documents in the collection, ordered by the key.
+pub fn load_all<T: DeserializeOwned>(
+    storage: &dyn Storage,
+    collection: Collection,
+) -> Result<Vec<(String, T)>, Error> {
+    storage
+        .list(collection)?
+        .into_iter()
+        .map(|(key, document)| {
+            parse_document(collection, &key, &document).map(|value| (key.clone(), value))
+        })
+        .collect()
+}
+
+fn parse_document<T: DeserializeOwned>(
+    collection: Collection,
+    key: &str,
+    document: &str,
+) -> Result<T, Error> {
+    serde_json::from_str(document).map_err(|e| Error {
+        code: "invalid-stored-document".to_string(),
+        message: format!("Stored {key} in {} is invalid: {e}", collection.name()),
+    })
+}
+
+/// Code of the errors of the storage backends.
+pub const STORAGE_ERROR: &str = "storage-error";
+
+/// Error of a storage backend, e.g. a failure to access the disk or the database.
+pub(crate) fn storage_error(message: String) -> Error {
+    Error {
+        code: STORAGE_ERROR.to_string(),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::encrypted_storage::{generate_key, reencrypt_all};
+    use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+    use aes_gcm::Aes256Gcm;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    /// Checks the behavior shared by all backends.
+    fn check_storage(storage: &dyn Storage) {
+        assert_eq!(None, storage.get(Collection::Jobs, "a").unwrap());
+        assert!(storage.list(Collection::Jobs).unwrap().is_empty());
+
+        storage.put(Collection::Jobs, "b", "2").unwrap();
+        storage.put(Collection::Jobs, "a", "1").unwrap();
+        storage.put(Collection::Templates, "a", "other").unwrap();
+        storage.put(Collection::Jobs, "b", "3").unwrap();
+
+        assert_eq!(
+            Some("1".to_string()),
+            storage.get(Collection::Jobs, "a").unwrap()
+        );
+        assert_eq!(
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "3".to_string())
+            ],
+            storage.list(Collection::Jobs).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        check_storage(&MemoryStorage::default());
+    }
+
+    #[test]
+    fn test_sqlite_storage() {
+        let path = std::env::temp_dir().join(format!(
+            "charlie_test_storage_{}.sqlite",
+            std::process::id()
+        ));
+        check_storage(&SqliteStorage::open(path.to_str().unwrap()).unwrap());
+
+        // Documents survive reopening the database
+        let reopened = SqliteStorage::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(2, reopened.list(Collection::Jobs).unwrap().len());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let directory =
+            std::env::temp_dir().join(format!("charlie_test_file_storage_{}", std::process::id()));
+        let storage = FileStorage::new(directory.to_str().unwrap());
+        check_storage(&storage);
+        assert_eq!(
+            "invalid-storage-key",
+            storage.put(Collection::Jobs, "../a", "1").unwrap_err().code
+        );
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_storage() {
+        let backend = Arc::new(MemoryStorage::default());
+        let key = generate_key();
+        let storage = EncryptedStorage::new(backend.clone(), &key, &[]).unwrap();
+        check_storage(&storage);
+
+        // Documents are encrypted in the backend
+        let encrypted = backend.get(Collection::Jobs, "a").unwrap().unwrap();
+        assert_ne!("1", encrypted);
+        assert_ne!("3", backend.list(Collection::Jobs).unwrap()[1].1);
+
+        // Documents are bound to their key, so they can't be moved to another one
+        backend.put(Collection::Jobs, "d", &encrypted).unwrap();
+        assert_eq!(
+            "failed-to-decrypt-document",
+            storage.get(Collection::Jobs, "d").unwrap_err().code
+        );
+        backend.put(Collection::Snapshots, "a", &encrypted).unwrap();
+        assert_eq!(
+            "failed-to-decrypt-document",
+            storage.get(Collection::Snapshots, "a").unwrap_err().code
+        );
+        storage.put(Collection::Jobs, "d", "4").unwrap();
+        storage.put(Collection::Snapshots, "a", "5").unwrap();
+
+        // Documents in plain text are only read when legacy documents are accepted
+        backend.put(Collection::Jobs, "c", "plain").unwrap();
+        assert_eq!(
+            "legacy-document",
+            storage.get(Collection::Jobs, "c").unwrap_err().code
+        );
+        let migrating = EncryptedStorage::new(backend.clone(), &key, &[])
+            .unwrap()
+            .with_legacy_documents_accepted(true);
+        assert_eq!(
+            Some("plain".to_string()),
+            migrating.get(Collection::Jobs, "c").unwrap()
+        );
+
+        // So are documents encrypted without associated data by earlier versions
+        let cipher = Aes256Gcm::new_from_slice(&STANDARD.decode(&key).unwrap()).unwrap();
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, b"6".as_slice()).unwrap();
+        let legacy = format!(
+            "charlie-encrypted:v1:{}",
+            STANDARD.encode([nonce.as_slice(), &ciphertext].concat())
+        );
+        backend.put(Collection::Jobs, "e", &legacy).unwrap();
+        assert_eq!(
+            "legacy-document",
+            storage.get(Collection::Jobs, "e").unwrap_err().code
+        );
+        assert_eq!(
+            Some("6".to_string()),
+            migrating.get(Collection::Jobs, "e").unwrap()
+        );
+
+        // After a key rotation, documents are decrypted with the previous key until they are
+        // encrypted again with the new one
+        let new_key = generate_key();
+        let rotated = EncryptedStorage::new(backend.clone(), &new_key, &[key])
+            .unwrap()
+            .with_legacy_documents_accepted(true);
+        assert_eq!(7, reencrypt_all(&rotated).unwrap());
+        let without_previous_key = EncryptedStorage::new(backend.clone(), &new_key, &[]).unwrap();
+        assert_eq!(
+            Some("1".to_string()),
+            without_previous_key.get(Collection::Jobs, "a").unwrap()
+        );
+        assert_ne!(
+            encrypted,
+            backend.get(Collection::Jobs, "a").unwrap().unwrap()
+        );
+        assert_eq!(
+            Some("plain".to_string()),
+            without_previous_key.get(Collection::Jobs, "c").unwrap()
+        );
+
+        // Documents can't be decrypted with an unknown key
+        let other = EncryptedStorage::new(backend, &generate_key(), &[]).unwrap();
+        assert_eq!(
+            "failed-to-decrypt-document",
+            other.get(Collection::Jobs, "a").unwrap_err().code
+        );
+        assert_eq!(
+            "invalid-encryption-key",
+            EncryptedStorage::new(Arc::new(MemoryStorage::default()), "short", &[])
+                .err()
+                .unwrap()
+                .code
+        );
+    }
+
+    #[test]
+    fn test_save_and_load_all() {
+        let storage = MemoryStorage::default();
+        save(&storage, Collection::Jobs, "a", &vec![1, 2]).unwrap();
+
+        let loaded: Vec<(String, Vec<u32>)> = load_all(&storage, Collection::Jobs).unwrap();
+        assert_eq!(vec![("a".to_string(), vec![1, 2])], loaded);
+        assert_eq!(
+            Some(vec![1, 2]),
+            load(&storage, Collection::Jobs, "a").unwrap()
+        );
+        assert_eq!(
+            None,
+            load::<Vec<u32>>(&storage, Collection::Jobs, "b").unwrap()
+        );
+
+        storage.put(Collection::Jobs, "b", "not json").unwrap();
+        assert_eq!(
+            "invalid-stored-document",
+            load_all::<Vec<u32>>(&storage, Collection::Jobs)
+                .unwrap_err()
+                .code
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::config::EncryptionConfig;
+use crate::model::errors::Error;
+use crate::storage::{Collection, Storage};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::sync::Arc;
+
+/// Prefix of the encrypted documents, followed by the nonce and the ciphertext in base64. The
+/// ciphertext is bound to the collection and the key of the document as associated data.
+const ENCRYPTED_PREFIX: &str = "charlie-encrypted:v2:";
+
+/// Prefix of the documents encrypted without associated data by earlier versions.
+const LEGACY_ENCRYPTED_PREFIX: &str = "charlie-encrypted:v1:";
+
+/// Length of the nonce of AES-GCM in bytes.
+const NONCE_LENGTH: usize = 12;
+
+/// Storage encrypting the documents with AES-256-GCM before passing them to another backend, since
+/// the theses of the candidates are confidential research. Documents are encrypted with the
+/// current key and decrypted with the current or any of the previous keys, which allows rotating
+/// the key without downtime. Each document is bound to its collection and key, such that a document
+/// moved to another key in the backend fails to decrypt. Documents in plain text or encrypted by
+/// earlier versions are rejected unless legacy documents are accepted for a migration. Note that
+/// the keys of the documents (e.g. workspace names) aren't encrypted.
+pub struct EncryptedStorage {
+    storage: Arc<dyn Storage>,
+    key: Aes256Gcm,
+    previous_keys: Vec<Aes256Gcm>,
+    accept_legacy_documents: bool,
+}
+
+impl EncryptedStorage {
+    /// Creates an encrypted storage on top of the backend given the current and the previous keys
+    /// in base64.
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        key: &str,
+        previous_keys: &[String],
+    ) -> Result<EncryptedStorage, Error> {
+        Ok(EncryptedStorage {
+            storage,
+            key: cipher(key)?,
+            previous_keys: previous_keys
+                .iter()
+                .map(|k| cipher(k))
+                .collect::<Result<Vec<Aes256Gcm>, Error>>()?,
+            accept_legacy_documents: false,
+        })
+    }
+
+    /// Also reads the documents stored in plain text before the encryption was enabled, and the
+    /// ones encrypted without associated data by earlier versions, until they're stored again.
+    pub fn with_legacy_documents_accepted(mut self, accept_legacy_documents: bool) -> Self {
+        self.accept_legacy_documents = accept_legacy_documents;
+        self
+    }
+
+    /// Creates an encrypted storage on top of the backend with the keys read from the environment
+    /// variables given in the configuration.
+    pub fn from_config(
+        storage: Arc<dyn Storage>,
+        config: &EncryptionConfig,
+    ) -> Result<EncryptedStorage, Error> {
+        let key = |variable: &String| {
+            std::env::var(variable).map_err(|_| Error {
+                code: "encryption-key-not-set".to_string(),
+                message: format!(
+                    "Storage encryption requires the key in environment variable {variable}."
+                ),
+            })
+        };
+        let previous_keys = config
+            .previous_key_variables
+            .iter()
+            .map(key)
+            .collect::<Result<Vec<String>, Error>>()?;
+
+        Ok(
+            EncryptedStorage::new(storage, &key(&config.key_variable)?, &previous_keys)?
+                .with_legacy_documents_accepted(config.accept_legacy_documents),
+        )
+    }
+
+    fn encrypt(&self, collection: Collection, key: &str, document: &str) -> Result<String, Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let associated_data = associated_data(collection, key);
+        let payload = Payload {
+            msg: document.as_bytes(),
+            aad: associated_data.as_bytes(),
+        };
+        let ciphertext = self.key.encrypt(&nonce, payload).map_err(|_| Error {
+            code: "failed-to-encrypt-document".to_string(),
+            message: format!("Failed to encrypt {key} in {}.", collection.name()),
+        })?;
+
+        Ok(format!(
+            "{ENCRYPTED_PREFIX}{}",
+            STANDARD.encode([nonce.as_slice(), &ciphertext].concat())
+        ))
+    }
+
+    fn decrypt(
+        &self,
+        collection: Collection,
+        key: &str,
+        document: String,
+    ) -> Result<String, Error> {
+        let associated_data = associated_data(collection, key);
+        let (encoded, aad) = if let Some(encoded) = document.strip_prefix(ENCRYPTED_PREFIX) {
+            (encoded, associated_data.as_bytes())
+        } else if !self.accept_legacy_documents {
+            return Err(Error {
+                code: "legacy-document".to_string(),
+                message: format!(
+                    "Document {key} in {} is in plain text or encrypted by an earlier version. Set \
+                    accept_legacy_documents in the storage encryption and run rotate_storage_key \
+                    reencrypt to encrypt it again.",
+                    collection.name()
+                ),
+            });
+        } else if let Some(encoded) = document.strip_prefix(LEGACY_ENCRYPTED_PREFIX) {
+            (encoded, &[][..])
+        } else {
+            return Ok(document);
+        };
+        let decryption_error = || Error {
+            code: "failed-to-decrypt-document".to_string(),
+            message: format!(
+                "Failed to decrypt {key} in {}. Check that its key is among the current and the \
+                previous keys of the storage encryption.",
+                collection.name()
+            ),
+        };
+
+        let encrypted = STANDARD.decode(encoded).map_err(|_| decryption_error())?;
+        if encrypted.len() < NONCE_LENGTH {
+            return Err(decryption_error());
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let plaintext = std::iter::once(&self.key)
+            .chain(&self.previous_keys)
+            .find_map(|k| {
+                k.decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad,
+                    },
+                )
+                .ok()
+            })
+            .ok_or_else(decryption_error)?;
+
+        String::from_utf8(plaintext).map_err(|_| decryption_error())
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn get(&self, collection: Collection, key: &str) -> Result<Option<String>, Error> {
+        self.storage
+            .get(collection, key)?
+            .map(|document| self.decrypt(collection, key, document))
+            .transpose()
+    }
+
+    fn put(&self, collection: Collection, key: &str, document: &str) -> Result<(), Error> {
+        self.storage
+            .put(collection, key, &self.encrypt(collection, key, document)?)
+    }
+
+    fn list(&self, collection: Collection) -> Result<Vec<(String, String)>, Error> {
+        self.storage
+            .list(collection)?
+            .into_iter()
+            .map(|(key, document)| {
+                let document = self.decrypt(collection, &key, document)?;
+                Ok((key, document))
+            })
+            .collect()
+    }
+}
+
+/// Returns a new random key in base64, to be put into the environment variable of the key.
+pub fn generate_key() -> String {
+    STANDARD.encode(Aes256Gcm::generate_key(&mut OsRng))
+}
+
+/// Stores every document of every collection again, which encrypts all of them with the current
+/// key of an encrypted storage, e.g. after a key rotation, or after enabling the encryption with
+/// legacy documents accepted.
+/// Returns the number of documents stored.
+pub fn reencrypt_all(storage: &dyn Storage) -> Result<usize, Error> {
+    let mut n_documents = 0;
+    for collection in Collection::ALL {
+        for (key, document) in storage.list(collection)? {
+            storage.put(collection, &key, &document)?;
+            n_documents += 1;
+        }
+    }
+
+    Ok(n_documents)
+}
+
+/// Associated data of the document, binding its ciphertext to the collection and the key.
+fn associated_data(collection: Collection, key: &str) -> String {
+    format!("{}/{key}", collection.name())
+}
+
+/// Cipher with the key given in base64.
+fn cipher(key: &str) -> Result<Aes256Gcm, Error> {
+    let invalid_key = || Error {
+        code: "invalid-encryption-key".to_string(),
+        message: "Storage encryption key must be 32 bytes encoded in base64.".to_string(),
+    };
+    let key = STANDARD.decode(key.trim()).map_err(|_| invalid_key())?;
+
+    Aes256Gcm::new_from_slice(&key).map_err(|_| invalid_key())
+}
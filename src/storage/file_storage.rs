@@ -0,0 +1,98 @@
+use crate::model::errors::Error;
+use crate::storage::{storage_error, Collection, Storage};
+use std::path::{Path, PathBuf};
+
+/// Extension of the files in which the documents are stored.
+const DOCUMENT_FILE_EXTENSION: &str = "json";
+
+/// Storage keeping each document in its own JSON file named after its key, within a directory per
+/// collection (e.g. candidates/ws.json).
+pub struct FileStorage {
+    directory: PathBuf,
+}
+
+impl FileStorage {
+    /// Creates a new [FileStorage] within the given directory. The directories of the collections
+    /// are created when the first document is stored.
+    pub fn new(directory: &str) -> FileStorage {
+        FileStorage {
+            directory: PathBuf::from(directory),
+        }
+    }
+
+    fn document_path(&self, collection: Collection, key: &str) -> Result<PathBuf, Error> {
+        // Keys are used as file names, so only alphanumeric characters, dashes, underscores and
+        // dots (not at the start) are allowed
+        if key.is_empty()
+            || key.starts_with('.')
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            return Err(Error {
+                code: "invalid-storage-key".to_string(),
+                message: format!(
+                    "Name {key} can only contain alphanumeric characters, dashes, underscores and \
+                    dots (not at the start)."
+                ),
+            });
+        }
+
+        Ok(self
+            .directory
+            .join(collection.name())
+            .join(format!("{key}.{DOCUMENT_FILE_EXTENSION}")))
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, collection: Collection, key: &str) -> Result<Option<String>, Error> {
+        let path = self.document_path(collection, key)?;
+        match std::fs::read_to_string(&path) {
+            Ok(document) => Ok(Some(document)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(&path, e)),
+        }
+    }
+
+    /// Writes the document into a temporary file first, such that a failure while writing doesn't
+    /// corrupt the previously stored document.
+    fn put(&self, collection: Collection, key: &str, document: &str) -> Result<(), Error> {
+        let path = self.document_path(collection, key)?;
+        let dir = self.directory.join(collection.name());
+        std::fs::create_dir_all(&dir).map_err(|e| io_error(&dir, e))?;
+
+        let temporary_path = path.with_extension("tmp");
+        std::fs::write(&temporary_path, document).map_err(|e| io_error(&temporary_path, e))?;
+        std::fs::rename(&temporary_path, &path).map_err(|e| io_error(&path, e))
+    }
+
+    fn list(&self, collection: Collection) -> Result<Vec<(String, String)>, Error> {
+        let dir = self.directory.join(collection.name());
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut documents: Vec<(String, String)> = vec![];
+        for entry in std::fs::read_dir(&dir).map_err(|e| io_error(&dir, e))? {
+            let path = entry.map_err(|e| io_error(&dir, e))?.path();
+            if path
+                .extension()
+                .is_some_and(|e| e == DOCUMENT_FILE_EXTENSION)
+            {
+                if let Some(key) = path.file_stem().and_then(|s| s.to_str()) {
+                    let document =
+                        std::fs::read_to_string(&path).map_err(|e| io_error(&path, e))?;
+                    documents.push((key.to_string(), document));
+                }
+            }
+        }
+        documents.sort();
+
+        Ok(documents)
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    storage_error(format!("Failed to access {}: {e}", path.display()))
+}
@@ -0,0 +1,46 @@
+use crate::model::errors::Error;
+use crate::storage::{Collection, Storage};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Storage keeping the documents in memory only, such that they are lost when the server restarts.
+#[derive(Default)]
+pub struct MemoryStorage {
+    collections: Mutex<HashMap<Collection, BTreeMap<String, String>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, collection: Collection, key: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .collections
+            .lock()
+            .unwrap()
+            .get(&collection)
+            .and_then(|documents| documents.get(key).cloned()))
+    }
+
+    fn put(&self, collection: Collection, key: &str, document: &str) -> Result<(), Error> {
+        self.collections
+            .lock()
+            .unwrap()
+            .entry(collection)
+            .or_default()
+            .insert(key.to_string(), document.to_string());
+        Ok(())
+    }
+
+    fn list(&self, collection: Collection) -> Result<Vec<(String, String)>, Error> {
+        Ok(self
+            .collections
+            .lock()
+            .unwrap()
+            .get(&collection)
+            .map(|documents| {
+                documents
+                    .iter()
+                    .map(|(k, d)| (k.clone(), d.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
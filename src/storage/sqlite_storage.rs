@@ -0,0 +1,78 @@
+use crate::model::errors::Error;
+use crate::storage::{storage_error, Collection, Storage};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::Mutex;
+
+/// Storage keeping the documents of all collections in a single table of an SQLite database.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at the given path, creating it and the table if they don't exist.
+    pub fn open(path: &str) -> Result<SqliteStorage, Error> {
+        let connection = Connection::open(path).map_err(|e| sqlite_error(path, e))?;
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS documents (
+                    collection TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    document TEXT NOT NULL,
+                    PRIMARY KEY (collection, key)
+                )",
+                [],
+            )
+            .map_err(|e| sqlite_error(path, e))?;
+
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, collection: Collection, key: &str) -> Result<Option<String>, Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT document FROM documents WHERE collection = ?1 AND key = ?2",
+                params![collection.name(), key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| sqlite_error(collection.name(), e))
+    }
+
+    fn put(&self, collection: Collection, key: &str, document: &str) -> Result<(), Error> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO documents (collection, key, document) VALUES (?1, ?2, ?3)
+                ON CONFLICT (collection, key) DO UPDATE SET document = excluded.document",
+                params![collection.name(), key, document],
+            )
+            .map(|_| ())
+            .map_err(|e| sqlite_error(collection.name(), e))
+    }
+
+    fn list(&self, collection: Collection) -> Result<Vec<(String, String)>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT key, document FROM documents WHERE collection = ?1 ORDER BY key")
+            .map_err(|e| sqlite_error(collection.name(), e))?;
+        let rows = statement
+            .query_map(params![collection.name()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(|e| sqlite_error(collection.name(), e))?;
+
+        rows.collect::<Result<Vec<(String, String)>, rusqlite::Error>>()
+            .map_err(|e| sqlite_error(collection.name(), e))
+    }
+}
+
+fn sqlite_error(context: &str, e: rusqlite::Error) -> Error {
+    storage_error(format!("SQLite storage failed for {context}: {e}"))
+}
@@ -0,0 +1,216 @@
+use crate::model::company::Ticker;
+use crate::model::errors::Error;
+use crate::validation::result::{Problem, Severity, ValidationResult};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fs;
+
+/// Bloomberg exchange codes (as in `ABC CN`) with the corresponding exchange suffixes (as in
+/// `ABC.TO`). An empty suffix means the US exchanges, whose tickers have no suffix.
+const EXCHANGE_SUFFIXES: [(&str, &str); 20] = [
+    ("US", ""),
+    ("UN", ""),
+    ("UW", ""),
+    ("UQ", ""),
+    ("CN", "TO"),
+    ("CT", "TO"),
+    ("LN", "L"),
+    ("GR", "DE"),
+    ("GY", "DE"),
+    ("FP", "PA"),
+    ("NA", "AS"),
+    ("IM", "MI"),
+    ("SM", "MC"),
+    ("SW", "SW"),
+    ("SS", "ST"),
+    ("DC", "CO"),
+    ("NO", "OL"),
+    ("JP", "T"),
+    ("HK", "HK"),
+    ("AU", "AX"),
+];
+
+/// Normalizes the formatting of a ticker, such that tickers of the same security written
+/// differently compare equal: surrounding whitespace is removed, letters are uppercased and a
+/// Bloomberg exchange code is replaced by the exchange suffix, e.g. `abc cn` becomes `ABC.TO`
+/// and `XYZ US Equity` becomes `XYZ`.
+pub fn normalize_ticker(ticker: &str) -> Ticker {
+    let upper = ticker.trim().to_uppercase();
+    let mut parts = upper.split_whitespace().collect_vec();
+    if parts.len() > 1 && parts.last() == Some(&"EQUITY") {
+        parts.pop();
+    }
+
+    match parts.as_slice() {
+        [symbol, exchange] => match EXCHANGE_SUFFIXES.iter().find(|(code, _)| code == exchange) {
+            Some((_, "")) => symbol.to_string(),
+            Some((_, suffix)) => format!("{symbol}.{suffix}"),
+            None => parts.join(" "),
+        },
+        _ => parts.join(" "),
+    }
+}
+
+/// Returns the groups of distinct tickers that refer to the same security according to the key,
+/// e.g. tickers that differ only by their formatting.
+pub fn tickers_with_same_key(
+    tickers: &[Ticker],
+    key: impl Fn(&Ticker) -> Ticker,
+) -> Vec<Vec<Ticker>> {
+    tickers
+        .iter()
+        .unique()
+        .into_group_map_by(|t| key(t))
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| group.into_iter().cloned().collect_vec())
+        .sorted()
+        .collect()
+}
+
+/// Known securities read from a symbology file, which has one security per line: its canonical
+/// ticker followed by comma-separated aliases, e.g. `ABC.TO, ABC CN, ABCF`. Empty lines and lines
+/// starting with `#` are ignored. Tickers are matched after normalizing their formatting.
+#[derive(Clone, Debug, Default)]
+pub struct Symbology {
+    /// Canonical ticker by the normalized canonical ticker or alias.
+    canonical: HashMap<Ticker, Ticker>,
+}
+
+impl Symbology {
+    /// Reads the symbology from a file.
+    pub fn load(path: &str) -> Result<Symbology, Error> {
+        let content = fs::read_to_string(path).map_err(|e| Error {
+            code: "failed-to-read-symbology".to_string(),
+            message: format!("Did not manage to read the symbology file at {path}: {e}."),
+        })?;
+        Symbology::parse(&content)
+    }
+
+    /// Parses the content of a symbology file. An alias can't refer to two different securities.
+    pub fn parse(content: &str) -> Result<Symbology, Error> {
+        let mut canonical: HashMap<Ticker, Ticker> = HashMap::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tickers = line.split(',').map(str::trim).collect_vec();
+            let security = tickers[0].to_string();
+            for ticker in tickers.iter().filter(|t| !t.is_empty()) {
+                match canonical.insert(normalize_ticker(ticker), security.clone()) {
+                    Some(other) if other != security => {
+                        return Err(Error {
+                            code: "invalid-symbology".to_string(),
+                            message: format!(
+                                "Ticker {ticker} refers to both {other} and {security} in the \
+                                symbology."
+                            ),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Symbology { canonical })
+    }
+
+    /// Returns the canonical ticker of the security, if it's known.
+    pub fn canonical(&self, ticker: &str) -> Option<&Ticker> {
+        self.canonical.get(&normalize_ticker(ticker))
+    }
+
+    /// Validates the tickers against the symbology: tickers of unknown securities get a warning,
+    /// and distinct tickers of the same security (e.g. a ticker and its alias) an error.
+    pub fn validate(&self, tickers: &[Ticker]) -> Vec<ValidationResult> {
+        let mut validation_results = tickers
+            .iter()
+            .unique()
+            .filter(|t| self.canonical(t).is_none())
+            .map(|t| {
+                ValidationResult::PROBLEM(Problem {
+                    code: "unknown-ticker".to_string(),
+                    message: format!("Ticker {t} is not in the symbology. Check for typos."),
+                    severity: Severity::WARNING,
+                    suggestion: None,
+                })
+            })
+            .collect_vec();
+
+        let same_security = tickers_with_same_key(tickers, |t| {
+            self.canonical(t)
+                .cloned()
+                .unwrap_or_else(|| normalize_ticker(t))
+        });
+        validation_results.extend(same_security.into_iter().map(|group| {
+            ValidationResult::PROBLEM(Problem {
+                code: "tickers-refer-to-same-security".to_string(),
+                message: format!(
+                    "Tickers {} refer to the same security according to the symbology. Keep only \
+                    one of them.",
+                    group.join(", ")
+                ),
+                severity: Severity::ERROR,
+                suggestion: None,
+            })
+        }));
+
+        validation_results
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_ticker() {
+        assert_eq!("ABC.TO", normalize_ticker(" abc.to "));
+        assert_eq!("ABC.TO", normalize_ticker("ABC CN"));
+        assert_eq!("ABC.TO", normalize_ticker("abc cn equity"));
+        assert_eq!("XYZ", normalize_ticker("XYZ US Equity"));
+        assert_eq!("VOD.L", normalize_ticker("VOD LN"));
+        assert_eq!("BRK.B", normalize_ticker("brk.b"));
+        assert_eq!("ABC XX", normalize_ticker("abc  xx"));
+
+        assert_eq!(
+            vec![vec!["ABC.TO".to_string(), "abc cn".to_string()]],
+            tickers_with_same_key(&["ABC.TO", "XYZ", "abc cn", "XYZ"].map(String::from), |t| {
+                normalize_ticker(t)
+            })
+        );
+    }
+
+    #[test]
+    fn test_symbology() {
+        let symbology = Symbology::parse(
+            "
+            # Canonical ticker, aliases
+            ABC.TO, ABCF
+            XYZ
+            ",
+        )
+        .unwrap();
+        assert_eq!("ABC.TO", symbology.canonical("abc cn").unwrap());
+        assert_eq!("ABC.TO", symbology.canonical("ABCF").unwrap());
+        assert!(symbology.canonical("ABC").is_none());
+
+        let problems = symbology.validate(&["ABC CN", "ABCF", "XYZ", "QQQ"].map(String::from));
+        let codes = problems
+            .iter()
+            .map(|p| match p {
+                ValidationResult::PROBLEM(p) => p.code.as_str(),
+                ValidationResult::OK => "ok",
+            })
+            .collect_vec();
+        assert_eq!(
+            vec!["unknown-ticker", "tickers-refer-to-same-security"],
+            codes
+        );
+
+        assert_eq!(
+            "invalid-symbology",
+            Symbology::parse("ABC, X\nXYZ, X").unwrap_err().code
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use crate::config::UsageConfig;
+use crate::model::errors::Error;
+use crate::model::usage::{MonthlyUsage, Quota, Usage, UsageReport};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+
+/// API key used for the requests without the API key header.
+pub const ANONYMOUS_API_KEY: &str = "anonymous";
+
+/// Accounting of the usage per API key and month (UTC), enforcing the configured monthly quotas.
+/// API keys without their own quota are accounted together with the anonymous requests, such that
+/// a client can't reset its quota by sending a new API key, and the usage is only kept for the
+/// configured API keys. Note that the usage is kept in memory and starts from zero when the server
+/// restarts.
+pub struct UsageTracker {
+    config: RwLock<UsageConfig>,
+    usage: Mutex<HashMap<String, BTreeMap<String, Usage>>>,
+}
+
+impl UsageTracker {
+    /// Creates a new [UsageTracker] given the configuration with the quotas.
+    pub fn new(config: &UsageConfig) -> UsageTracker {
+        UsageTracker {
+            config: RwLock::new(config.clone()),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the API key to which the usage of the given API key is accounted: the API key itself
+    /// if it has its own quota, [ANONYMOUS_API_KEY] otherwise.
+    fn account(&self, api_key: &str) -> String {
+        if self.config.read().unwrap().quotas.contains_key(api_key) {
+            api_key.to_string()
+        } else {
+            ANONYMOUS_API_KEY.to_string()
+        }
+    }
+
+    /// Returns the quota of the API key, falling back to the default quota.
+    pub fn quota(&self, api_key: &str) -> Option<Quota> {
+        let config = self.config.read().unwrap();
+        config
+            .quotas
+            .get(api_key)
+            .or(config.default_quota.as_ref())
+            .cloned()
+    }
+
+    /// Replaces the quotas with the ones of the given configuration. The usage recorded so far is
+    /// kept, so the new quotas apply to it immediately.
+    pub fn set_config(&self, config: &UsageConfig) {
+        *self.config.write().unwrap() = config.clone();
+    }
+
+    /// Fails if the API key has reached any of the limits of its quota in the current month.
+    pub fn check_quota(&self, api_key: &str) -> Result<(), Error> {
+        self.check_quota_at(api_key, Utc::now())
+    }
+
+    /// Adds the usage of a request to the usage of the API key in the current month.
+    pub fn record(&self, api_key: &str, usage: &Usage) {
+        self.record_at(api_key, usage, Utc::now())
+    }
+
+    /// Returns the usage accounted to the API key in all months with any usage.
+    pub fn report(&self, api_key: &str) -> UsageReport {
+        let api_key = self.account(api_key);
+        let months = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(&api_key)
+            .map(|months| {
+                months
+                    .iter()
+                    .map(|(month, usage)| MonthlyUsage {
+                        month: month.clone(),
+                        usage: usage.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        UsageReport {
+            quota: self.quota(&api_key),
+            api_key,
+            months,
+        }
+    }
+
+    /// Same as [UsageTracker::check_quota], at the given time.
+    fn check_quota_at(&self, api_key: &str, now: DateTime<Utc>) -> Result<(), Error> {
+        let api_key = self.account(api_key);
+        let Some(quota) = self.quota(&api_key) else {
+            return Ok(());
+        };
+        let month = month(now);
+        let usage = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(&api_key)
+            .and_then(|months| months.get(&month).cloned())
+            .unwrap_or_default();
+
+        let exceeded = [
+            quota
+                .max_requests
+                .filter(|max| usage.requests >= *max)
+                .map(|max| format!("{max} requests")),
+            quota
+                .max_cpu_seconds
+                .filter(|max| usage.cpu_seconds >= *max)
+                .map(|max| format!("{max} CPU seconds")),
+            quota
+                .max_n_systems
+                .filter(|max| usage.n_systems >= *max)
+                .map(|max| format!("{max} solved systems")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<String>>();
+
+        if exceeded.is_empty() {
+            Ok(())
+        } else {
+            Err(Error {
+                code: "usage-quota-exceeded".to_string(),
+                message: format!(
+                    "API key {api_key} reached its quota of {} in {month}. Try again next month.",
+                    exceeded.join(", ")
+                ),
+            })
+        }
+    }
+
+    /// Same as [UsageTracker::record], at the given time.
+    fn record_at(&self, api_key: &str, usage: &Usage, now: DateTime<Utc>) {
+        let api_key = self.account(api_key);
+        *self
+            .usage
+            .lock()
+            .unwrap()
+            .entry(api_key)
+            .or_default()
+            .entry(month(now))
+            .or_default() += usage;
+    }
+}
+
+/// Month of the given time, e.g. 2024-06.
+fn month(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn time(month: u32) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("2024-{month:02}-15T12:00:00Z"))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn request(cpu_seconds: f64, n_systems: u64) -> Usage {
+        Usage {
+            requests: 1,
+            cpu_seconds,
+            n_systems,
+        }
+    }
+
+    #[test]
+    fn test_usage_is_accounted_per_key_and_month() {
+        let tracker = UsageTracker::new(&UsageConfig {
+            default_quota: None,
+            quotas: HashMap::from([
+                ("a".to_string(), Quota::default()),
+                ("b".to_string(), Quota::default()),
+            ]),
+        });
+        tracker.record_at("a", &request(1.0, 4), time(5));
+        tracker.record_at("a", &request(2.0, 8), time(5));
+        tracker.record_at("a", &request(0.5, 1), time(6));
+        tracker.record_at("b", &request(3.0, 2), time(6));
+
+        let report = tracker.report("a");
+        assert_eq!(2, report.months.len());
+        assert_eq!("2024-05", report.months[0].month);
+        assert_eq!(3.0, report.months[0].usage.cpu_seconds);
+        assert_eq!(2, report.months[0].usage.requests);
+        assert_eq!(12, report.months[0].usage.n_systems);
+        assert_eq!("2024-06", report.months[1].month);
+        assert_eq!(1, report.months[1].usage.requests);
+        assert_eq!(Some(Quota::default()), report.quota);
+        assert!(tracker.report("c").months.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_accounted_as_anonymous() {
+        let tracker = UsageTracker::new(&UsageConfig::default());
+        tracker.record_at("b", &request(1.0, 4), time(5));
+        tracker.record_at("c", &request(1.0, 4), time(5));
+
+        let report = tracker.report("d");
+        assert_eq!(ANONYMOUS_API_KEY, report.api_key);
+        assert_eq!(2, report.months[0].usage.requests);
+        assert_eq!(1, tracker.usage.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_quota() {
+        let tracker = UsageTracker::new(&UsageConfig {
+            default_quota: Some(Quota {
+                max_requests: Some(2),
+                max_cpu_seconds: None,
+                max_n_systems: None,
+            }),
+            quotas: HashMap::from([(
+                "a".to_string(),
+                Quota {
+                    max_requests: None,
+                    max_cpu_seconds: None,
+                    max_n_systems: Some(10),
+                },
+            )]),
+        });
+
+        // Key with its own quota
+        tracker.record_at("a", &request(1.0, 4), time(5));
+        tracker.record_at("a", &request(1.0, 4), time(5));
+        tracker.record_at("a", &request(1.0, 4), time(5));
+        assert_eq!(
+            "usage-quota-exceeded",
+            tracker.check_quota_at("a", time(5)).unwrap_err().code
+        );
+        assert!(tracker.check_quota_at("a", time(6)).is_ok());
+
+        // Keys without their own quota share the default quota, so a new key doesn't reset it
+        tracker.record_at("b", &request(1.0, 4), time(5));
+        assert!(tracker.check_quota_at("b", time(5)).is_ok());
+        tracker.record_at("b", &request(1.0, 4), time(5));
+        assert!(tracker.check_quota_at("b", time(5)).is_err());
+        assert!(tracker.check_quota_at("c", time(5)).is_err());
+        assert!(tracker.check_quota_at(ANONYMOUS_API_KEY, time(5)).is_err());
+
+        // Changed quotas apply to the usage recorded so far
+        tracker.set_config(&UsageConfig::default());
+        assert!(tracker.check_quota_at("b", time(5)).is_ok());
+        assert!(tracker.report("b").quota.is_none());
+    }
+}
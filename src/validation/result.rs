@@ -1,5 +1,7 @@
+use crate::model::company::Ticker;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 
 type ValidationCode = String;
 
@@ -16,6 +18,61 @@ pub struct Problem {
     pub code: ValidationCode,
     pub message: String,
     pub severity: Severity,
+
+    /// Fix of the problem that a user interface can apply directly, if there is an obvious one.
+    #[serde(default)]
+    pub suggestion: Option<Suggestion>,
+}
+
+/// Structured fix of a validation problem.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Suggestion {
+    /// Corrected probabilities of all the scenarios of a company, in the order of the scenarios.
+    ScenarioProbabilities {
+        ticker: Ticker,
+        probabilities: Vec<f64>,
+    },
+}
+
+/// Probabilities are compared bitwise, which is enough for deduplicating the validation results.
+impl PartialEq for Suggestion {
+    fn eq(&self, other: &Self) -> bool {
+        let bits = |probabilities: &[f64]| {
+            probabilities
+                .iter()
+                .map(|p| p.to_bits())
+                .collect::<Vec<u64>>()
+        };
+        match (self, other) {
+            (
+                Suggestion::ScenarioProbabilities {
+                    ticker,
+                    probabilities,
+                },
+                Suggestion::ScenarioProbabilities {
+                    ticker: other_ticker,
+                    probabilities: other_probabilities,
+                },
+            ) => ticker == other_ticker && bits(probabilities) == bits(other_probabilities),
+        }
+    }
+}
+
+impl Eq for Suggestion {}
+
+impl Hash for Suggestion {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Suggestion::ScenarioProbabilities {
+                ticker,
+                probabilities,
+            } => {
+                ticker.hash(state);
+                probabilities.iter().for_each(|p| p.to_bits().hash(state));
+            }
+        }
+    }
 }
 
 /// Validation severity
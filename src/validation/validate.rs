@@ -1,7 +1,9 @@
 use crate::validation::result::ValidationResult;
-use std::collections::HashSet;
+use indexmap::IndexSet;
 
-/// A trait that returns a vector of validation results
+/// A trait that returns the validation results. The results are a set since the same problem may
+/// be found more than once, but they keep the order in which they were found (e.g. the order of
+/// the candidates in the input), such that the responses are deterministic.
 pub trait Validate {
-    fn validate(&self) -> HashSet<ValidationResult>;
+    fn validate(&self) -> IndexSet<ValidationResult>;
 }
@@ -1,16 +1,30 @@
+use charlie::allocation_store::allocation_diff;
+use charlie::config::ServerConfig;
 use charlie::env::{create_test_logger, get_project_dir};
 use charlie::kelly_allocation::{KellyAllocator, MAX_ITER, SOLVER_TOLERANCE};
+use charlie::model::allocation_options::{AllocationOptions, CapturedLogLevel};
+use charlie::model::analysis_options::{AnalysisOptions, DetailLevel};
+use charlie::model::bets::BetsInput;
 use charlie::model::capital_loss::CapitalLoss;
 use charlie::model::company::Company;
 use charlie::model::errors::Error;
-use charlie::model::portfolio::AllocationInput;
+use charlie::model::portfolio::{
+    AllocationInput, Benchmark, BenchmarkPortfolio, CandidateGroup, Portfolio, PortfolioCompany,
+    RiskMeasure, TargetRisk,
+};
 use charlie::model::responses::{AllocationResponse, AnalysisResponse, TickerAndFraction};
 use charlie::model::scenario::Scenario;
+use charlie::server_state::ServerState;
 use charlie::utils::assert_close;
-use charlie::validation::result::{Problem, Severity, ValidationResult};
-use charlie::{allocate, analyze, validate};
+use charlie::validation::result::{Problem, Severity, Suggestion, ValidationResult};
+use charlie::{
+    allocate, allocate_bets, allocate_with_options, analyze, api_description, validate,
+    validate_company,
+};
+use dropshot::HttpServerStarter;
 use itertools::Itertools;
 use slog::info;
+use std::collections::HashMap;
 
 /// Make assertion tolerance the same as the fraction tolerance (no point in more accuracy).
 const ASSERTION_TOLERANCE: f64 = SOLVER_TOLERANCE;
@@ -101,6 +115,25 @@ fn test_create_candidates_and_validate() {
     assert_eq!(validation_errors, vec![]);
 }
 
+#[test]
+fn test_validate_single_company() {
+    let logger = create_test_logger();
+    let input: AllocationInput =
+        serde_yaml::from_str(&load_test_file_content("test_data_no_constraints.yaml")).unwrap();
+    assert_eq!(validate_company(&input.candidates[0], &logger), vec![]);
+
+    // Same problems as in the validation of the whole input
+    let mut company = input.candidates[0].clone();
+    company.scenarios[0].probability = 0.5;
+    let problems = validate_company(&company, &logger);
+    assert!(!problems.is_empty());
+    let whole_input = AllocationInput {
+        candidates: vec![company],
+        ..input
+    };
+    assert_eq!(problems, validate(&whole_input, &logger));
+}
+
 #[test]
 fn test_allocate_with_validation_problems() {
     // Change the probability of the first scenario from 0.05 to 0.03
@@ -114,15 +147,34 @@ fn test_allocate_with_validation_problems() {
     assert!(allocation_response.error.is_none());
     assert!(allocation_response.result.is_none());
 
+    let mut validation_problems = allocation_response.validation_problems.unwrap();
+    assert_eq!(validation_problems.len(), 1);
+    let ValidationResult::PROBLEM(problem) = validation_problems.remove(0) else {
+        panic!("Expected a validation problem.")
+    };
     assert_eq!(
-        allocation_response.validation_problems.unwrap(),
-        vec![ValidationResult::PROBLEM(Problem {
-            code: "probabilities-for-all-scenarios-do-not-sum-up-to-one".to_string(),
-            message: "Probabilities of all scenarios for company A do not sum up to 1. Sum = 0.98."
-                .to_string(),
-            severity: Severity::ERROR,
-        })],
+        problem.code,
+        "probabilities-for-all-scenarios-do-not-sum-up-to-one"
+    );
+    assert_eq!(
+        problem.message,
+        "Probabilities of all scenarios for company A do not sum up to 1. Sum = 0.98."
     );
+    assert_eq!(problem.severity, Severity::ERROR);
+
+    // The suggested fix rescales the probabilities proportionally
+    let Some(Suggestion::ScenarioProbabilities {
+        ticker,
+        probabilities,
+    }) = problem.suggestion
+    else {
+        panic!("Expected suggested probabilities.")
+    };
+    assert_eq!(ticker, "A");
+    for (expected, actual) in [0.03, 0.3, 0.5, 0.15].iter().zip(&probabilities) {
+        assert_close!(expected / 0.98, *actual, ASSERTION_TOLERANCE);
+    }
+    assert_close!(1.0, probabilities.iter().sum::<f64>(), ASSERTION_TOLERANCE);
 }
 
 #[test]
@@ -162,6 +214,7 @@ fn test_allocate_with_no_candidates_after_filtering() {
                 current framework because we want to prohibit shorting."
                     .to_string(),
             severity: Severity::WARNING,
+            suggestion: None,
         }))
     );
     assert!(
@@ -173,6 +226,7 @@ fn test_allocate_with_no_candidates_after_filtering() {
                 money on this company."
                     .to_string(),
             severity: Severity::WARNING,
+            suggestion: None,
         }))
     );
 
@@ -185,11 +239,12 @@ fn test_allocate_with_no_candidates_after_filtering() {
     );
 }
 
-/// Tests a case that doesn't converge since we have a company which has two scenarios that would
-/// imply infinite leverage: One with extremely unlikely small downside and one with extremely
-/// likely large upside.
+/// Tests a case with a company which has two scenarios that imply extreme leverage: One with
+/// extremely unlikely small downside and one with extremely likely large upside. The iteration
+/// didn't converge with a fixed relaxation of the Newton step, but converges with the adaptive
+/// damping, and the allocation is flagged as concentrated.
 #[test]
-fn test_allocate_case_that_does_not_converge() {
+fn test_allocate_case_with_extreme_leverage() {
     let mut input: AllocationInput =
         serde_yaml::from_str(&load_test_file_content("test_data_no_constraints.yaml")).unwrap();
 
@@ -207,18 +262,78 @@ fn test_allocate_case_that_does_not_converge() {
     let logger = create_test_logger();
     let allocation_response: AllocationResponse = allocate(input, &logger);
 
-    assert_eq!(allocation_response.validation_problems.unwrap(), vec![]);
-    assert!(allocation_response.result.is_none());
+    assert!(allocation_response.error.is_none());
+    assert!(allocation_response.result.is_some());
+    assert!(allocation_response
+        .validation_problems
+        .unwrap()
+        .iter()
+        .any(|v| match v {
+            ValidationResult::PROBLEM(p) => {
+                p.code == "allocation-concentrated-in-single-candidate"
+                    && p.message.contains("allocated to F")
+            }
+            _ => false,
+        }));
+}
+
+/// Tests that a scenario with a negligible probability is removed before the solution process,
+/// which filters out a candidate whose only downside scenario is negligible instead of failing.
+#[test]
+fn test_allocate_removes_scenarios_with_negligible_probability() {
+    let mut input: AllocationInput =
+        serde_yaml::from_str(&load_test_file_content("test_data_no_constraints.yaml")).unwrap();
+
+    // Same as in the non-converging case, but with a negligible probability of the downside
+    input.candidates[5].scenarios.remove(0);
+    input.candidates[5].scenarios[0].probability = 1e-8;
+    input.candidates[5].scenarios[0].intrinsic_value = 0.99 * input.candidates[5].market_cap;
+    input.candidates[5].scenarios[1].probability = 1.0 - 1e-8;
+    input.candidates[5].scenarios[1].intrinsic_value = 100.0 * input.candidates[5].market_cap;
+
+    let logger = create_test_logger();
+    let allocation_response: AllocationResponse = allocate(input, &logger);
+
+    assert!(allocation_response.error.is_none());
+    assert!(allocation_response
+        .validation_problems
+        .unwrap()
+        .iter()
+        .any(|v| match v {
+            ValidationResult::PROBLEM(p) => p.code == "negligible-scenario-probability-removed",
+            ValidationResult::OK => false,
+        }));
+
+    let allocations = allocation_response.result.unwrap().allocations;
+    assert_eq!(allocations.len(), 5);
+    assert!(allocations.iter().all(|tf| tf.ticker != "F"));
+}
 
-    let err = allocation_response.error.unwrap();
+/// Tests that scenarios of companies with more than three scenarios are compressed before the
+/// allocation.
+#[test]
+fn test_allocate_with_compressed_scenarios() {
+    let mut input: AllocationInput =
+        serde_yaml::from_str(&load_test_file_content("test_data_no_constraints.yaml")).unwrap();
+    input.max_scenarios_per_company = Some(3);
+    let n_candidates = input.candidates.len();
+
+    let logger = create_test_logger();
+    let allocation_response: AllocationResponse = allocate(input, &logger);
 
-    assert_eq!(err.code, "did-not-find-a-single-viable-solution");
-    assert!(err
-        .message
-        .contains("Did not manage to find a single viable numerical solution."));
-    assert!(err
-        .message
-        .contains("Did not manage to find the numerical solution."));
+    assert!(allocation_response.error.is_none());
+    assert!(allocation_response
+        .validation_problems
+        .unwrap()
+        .iter()
+        .any(|v| match v {
+            ValidationResult::PROBLEM(p) => p.code == "scenarios-compressed",
+            ValidationResult::OK => false,
+        }));
+    assert_eq!(
+        allocation_response.result.unwrap().allocations.len(),
+        n_candidates
+    );
 }
 
 /// Helper function to create candidates with 5 companies that are all the same.
@@ -234,24 +349,34 @@ fn create_five_same_candidates(
                 ticker: format!("A{i}").to_string(),
                 description: format!("A{i}").to_string(),
                 market_cap: 1.0,
+                auto_status_quo: None,
+                units: None,
+                last_reviewed: None,
                 scenarios: vec![
                     Scenario {
                         thesis: "50% down with 50% probability".to_string(),
                         intrinsic_value: 0.5,
                         probability: 0.5,
+                        ..Default::default()
                     },
                     Scenario {
                         thesis: "100% up with 50% probability".to_string(),
                         intrinsic_value: 2.0,
                         probability: 0.5,
+                        ..Default::default()
                     },
                 ],
             })
             .collect_vec(),
         max_individual_allocation: None,
+        max_individual_allocation_tickers: None,
+        ticker_max_individual_allocations: None,
+        min_individual_allocation: None,
         long_only,
+        long_only_tickers: None,
         max_permanent_loss_of_capital,
         max_total_leverage_ratio,
+        ..Default::default()
     }
 }
 
@@ -263,12 +388,588 @@ fn test_allocate_all_same() {
 
     // Allocate
     let portfolio: AllocationResponse = allocate(input, &logger);
-    let tickers_and_fractions: Vec<TickerAndFraction> = portfolio.result.unwrap().allocations;
+    let allocation_result = portfolio.result.unwrap();
+    let tickers_and_fractions: Vec<TickerAndFraction> = allocation_result.allocations;
 
     // Assert that all the fractions are the same
     tickers_and_fractions
         .iter()
         .for_each(|tf| assert_close!(0.3451229, tf.fraction, ASSERTION_TOLERANCE));
+
+    // The allocation is levered, hence the cash is borrowed
+    assert_close!(
+        1.0 - 5.0 * 0.3451229,
+        allocation_result.cash,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        5.0 * 0.3451229,
+        allocation_result.gross_exposure,
+        ASSERTION_TOLERANCE
+    );
+
+    // The worst 5% of outcomes are all five candidates halving (1/32) and part of four halving
+    let analysis = &allocation_result.analysis;
+    assert_close!(
+        0.3451229,
+        analysis.value_at_risk_95.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        (2.5 * 0.3451229 / 32.0 + 0.3451229 * (0.05 - 1.0 / 32.0)) / 0.05,
+        analysis.conditional_value_at_risk_95.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+
+    // Without the leverage constraint, an allocation without leverage is suggested as well, which
+    // keeps most of the growth of the levered allocation
+    let suggestion = allocation_result.deleveraging_suggestion.unwrap();
+    for tf in &suggestion.allocations {
+        assert_close!(0.2, tf.fraction, ASSERTION_TOLERANCE);
+    }
+    assert_close!(0.0, suggestion.cash, ASSERTION_TOLERANCE);
+    assert!(suggestion.expected_log_growth < allocation_result.solver_metadata.expected_log_growth);
+    assert!(suggestion.fraction_of_levered_growth > 0.5);
+    assert!(suggestion.fraction_of_levered_growth < 1.0);
+
+    // Coin flips are balanced, but their probabilities of one half are rough guesses
+    assert_eq!(5, allocation_result.data_quality.len());
+    for quality in &allocation_result.data_quality {
+        assert_close!(1.0, quality.scenario_balance, ASSERTION_TOLERANCE);
+        assert_eq!(Some(0.5), quality.probability_step);
+        assert_close!(0.5, quality.score, ASSERTION_TOLERANCE);
+    }
+}
+
+/// Tests that half Kelly allocates half of the full Kelly fractions and keeps the rest in cash.
+#[test]
+fn test_allocate_all_same_with_half_kelly() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+    input.kelly_fraction = Some(0.5);
+
+    let allocation_result = allocate(input, &logger).result.unwrap();
+
+    allocation_result
+        .allocations
+        .iter()
+        .for_each(|tf| assert_close!(0.5 * 0.3451229, tf.fraction, ASSERTION_TOLERANCE));
+    assert_close!(
+        1.0 - 2.5 * 0.3451229,
+        allocation_result.cash,
+        ASSERTION_TOLERANCE
+    );
+}
+
+/// Tests that a pair trade is allocated to as a single candidate without counting towards the
+/// leverage, and that its legs are reported in the exposures.
+#[test]
+fn test_allocate_all_same_with_pair_trade() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, Some(0.0));
+    input.pair_trades = Some(vec![serde_yaml::from_str(
+        "
+        name: Long A0 short A1
+        ticker: A0/A1
+        long: A0
+        short: A1
+        scenarios:
+          - thesis: Spread closes
+            spread_return: 0.5
+            probability: 0.5
+          - thesis: Spread widens
+            spread_return: -0.25
+            probability: 0.5
+        ",
+    )
+    .unwrap()]);
+
+    let allocation_result = allocate(input, &logger).result.unwrap();
+    let fraction = |ticker: &str| {
+        allocation_result
+            .allocations
+            .iter()
+            .find(|tf| tf.ticker == ticker)
+            .unwrap()
+            .fraction
+    };
+
+    // The candidates still invest all capital, while the pair has no net exposure
+    let pair_fraction = fraction("A0/A1");
+    assert!(pair_fraction > 0.0);
+    assert_close!(0.0, allocation_result.cash, ASSERTION_TOLERANCE);
+
+    let exposures = allocation_result.exposures.unwrap();
+    assert_eq!(
+        vec!["A0", "A1", "A2", "A3", "A4"],
+        exposures.iter().map(|e| e.ticker.as_str()).collect_vec()
+    );
+    assert_close!(
+        fraction("A0") + pair_fraction,
+        exposures[0].fraction,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        fraction("A1") - pair_fraction,
+        exposures[1].fraction,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        exposures.iter().map(|e| e.fraction.abs()).sum::<f64>(),
+        allocation_result.gross_exposure,
+        ASSERTION_TOLERANCE
+    );
+}
+
+/// Tests that the log messages of the allocation are returned at the requested level.
+#[test]
+fn test_allocate_with_captured_logs() {
+    let logger = create_test_logger();
+    let input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    let options = AllocationOptions {
+        log_level: Some(CapturedLogLevel::Info),
+        ..AllocationOptions::default()
+    };
+    let logs = allocate_with_options(input.clone(), &options, &logger)
+        .logs
+        .unwrap();
+    assert_eq!("INFO Started allocation.", logs[0]);
+    assert!(logs.iter().all(|l| !l.starts_with("DEBG")));
+
+    let response = allocate_with_options(input, &AllocationOptions::default(), &logger);
+    assert!(response.logs.is_none());
+}
+
+/// Tests that the probability of underperforming the benchmark is reported and bounded.
+#[test]
+fn test_allocate_all_same_with_benchmark() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    // With fractions of 0.345, at least two of the five candidates must double to beat 8%
+    input.benchmark = Some(Benchmark {
+        expected_return: 0.08,
+        max_underperformance_probability: Some(0.2),
+    });
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    assert_close!(
+        6.0 / 32.0,
+        allocation_result
+            .analysis
+            .probability_of_underperforming_benchmark
+            .unwrap(),
+        ASSERTION_TOLERANCE
+    );
+
+    input.benchmark = Some(Benchmark {
+        expected_return: 0.08,
+        max_underperformance_probability: Some(0.1),
+    });
+    let error = allocate(input, &logger).error.unwrap();
+    assert_eq!("underperformance-probability-exceeded", error.code);
+    assert_eq!(
+        "Probability of 18.8% that the allocation returns less than the benchmark return of 8.0% \
+         exceeds the maximum of 10.0%.",
+        error.message
+    );
+
+    // The analysis reports the probability for the benchmark return of the options
+    let portfolio = Portfolio {
+        companies: allocation_result
+            .allocations
+            .iter()
+            .zip(create_five_same_candidates(None, None, None).candidates)
+            .map(|(tf, company)| PortfolioCompany {
+                company,
+                fraction: tf.fraction,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let options = AnalysisOptions {
+        benchmark_return: Some(0.08),
+        ..AnalysisOptions::default()
+    };
+    let analysis = analyze(portfolio, &[], &options, &logger).result.unwrap();
+    assert_close!(
+        6.0 / 32.0,
+        analysis.probability_of_underperforming_benchmark.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+}
+
+/// Tests that the allocation is compared with the equal weight and a given benchmark portfolio.
+#[test]
+fn test_allocate_all_same_with_benchmark_portfolio() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    input.benchmark_portfolio = Some(BenchmarkPortfolio::EqualWeight);
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    let comparison = allocation_result.benchmark_comparison.unwrap();
+    for (allocation, difference) in allocation_result
+        .allocations
+        .iter()
+        .zip(comparison.weight_differences.iter())
+    {
+        assert_eq!(allocation.ticker, difference.ticker);
+        assert_close!(
+            allocation.fraction - 0.2,
+            difference.fraction,
+            ASSERTION_TOLERANCE
+        );
+    }
+    assert!(comparison.expected_log_growth_difference > 0.0);
+    assert_close!(
+        comparison.benchmark_expected_log_growth + comparison.expected_log_growth_difference,
+        allocation_result.solver_metadata.expected_log_growth,
+        ASSERTION_TOLERANCE
+    );
+
+    // Candidates without a weight are not held by the benchmark portfolio
+    input.benchmark_portfolio = Some(BenchmarkPortfolio::Weights(vec![TickerAndFraction {
+        ticker: "A0".to_string(),
+        fraction: 0.5,
+    }]));
+    let comparison = allocate(input.clone(), &logger)
+        .result
+        .unwrap()
+        .benchmark_comparison
+        .unwrap();
+    assert_close!(
+        0.5,
+        comparison.benchmark_allocations[0].fraction,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        0.0,
+        comparison.benchmark_allocations[1].fraction,
+        ASSERTION_TOLERANCE
+    );
+    assert!(comparison.benchmark_conditional_value_at_risk_95 > 0.0);
+
+    input.benchmark_portfolio = Some(BenchmarkPortfolio::Weights(vec![TickerAndFraction {
+        ticker: "Z".to_string(),
+        fraction: 0.5,
+    }]));
+    let validation_problems = allocate(input, &logger).validation_problems.unwrap();
+    assert!(validation_problems.iter().any(|v| match v {
+        ValidationResult::PROBLEM(p) => p.code == "unknown-tickers-in-benchmark-portfolio",
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_allocate_all_same_with_fixed_net_exposure() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    input.fixed_net_exposure = Some(0.8);
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    assert_close!(0.2, allocation_result.cash, ASSERTION_TOLERANCE);
+    for allocation in &allocation_result.allocations {
+        assert_close!(0.16, allocation.fraction, ASSERTION_TOLERANCE);
+    }
+    assert!(allocation_result.net_exposure_multiplier.unwrap() > 0.0);
+
+    input.fixed_net_exposure = Some(1.5);
+    input.max_total_leverage_ratio = Some(0.2);
+    let validation_problems = allocate(input, &logger).validation_problems.unwrap();
+    assert!(validation_problems.iter().any(|v| match v {
+        ValidationResult::PROBLEM(p) => p.code == "fixed-net-exposure-exceeds-maximum-leverage",
+        _ => false,
+    }));
+}
+
+/// Tests that a minimum individual allocation above the Kelly fractions of three same candidates
+/// sells one of them to hold the others at the minimum, with the reason for the zero allocation.
+#[test]
+fn test_allocate_all_same_with_minimum_individual_allocation() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+    input.candidates.truncate(3);
+    input.long_only = Some(true);
+    input.min_individual_allocation = Some(0.7);
+
+    // Two of the three candidates are held at the minimum
+    let allocation_result = allocate(input, &logger).result.unwrap();
+    let held = allocation_result
+        .allocations
+        .iter()
+        .filter(|a| a.fraction > ASSERTION_TOLERANCE)
+        .collect::<Vec<_>>();
+    assert_eq!(2, held.len());
+    for allocation in held {
+        assert_close!(0.7, allocation.fraction, ASSERTION_TOLERANCE);
+    }
+    assert_eq!(1, allocation_result.zero_allocations.len());
+    assert_eq!(
+        "below-minimum-individual-allocation",
+        allocation_result.zero_allocations[0].code
+    );
+}
+
+#[test]
+fn test_allocate_with_maximum_number_of_positions() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    // All choices of two out of five candidates are searched
+    input.max_positions = Some(2);
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    let n_positions = allocation_result
+        .allocations
+        .iter()
+        .filter(|a| a.fraction.abs() > ASSERTION_TOLERANCE)
+        .count();
+    assert_eq!(2, n_positions);
+    assert_eq!(3, allocation_result.zero_allocations.len());
+    assert!(allocation_result
+        .zero_allocations
+        .iter()
+        .all(|z| z.code == "maximum-number-of-positions-reached"));
+
+    // Too many choices of four out of eight candidates, so the ones with the best bets are kept
+    input.candidates = (0..8)
+        .map(|i| Company {
+            name: format!("B{i}"),
+            ticker: format!("B{i}"),
+            description: format!("B{i}"),
+            market_cap: 1.0,
+            auto_status_quo: None,
+            units: None,
+            last_reviewed: None,
+            scenarios: vec![
+                Scenario {
+                    thesis: "Loss".to_string(),
+                    intrinsic_value: 0.5,
+                    probability: 0.5 - 0.02 * i as f64,
+                    ..Default::default()
+                },
+                Scenario {
+                    thesis: "Win".to_string(),
+                    intrinsic_value: 2.0,
+                    probability: 0.5 + 0.02 * i as f64,
+                    ..Default::default()
+                },
+            ],
+        })
+        .collect_vec();
+    input.max_positions = Some(4);
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    let kept_tickers = allocation_result
+        .allocations
+        .iter()
+        .filter(|a| a.fraction.abs() > ASSERTION_TOLERANCE)
+        .map(|a| a.ticker.clone())
+        .collect_vec();
+    assert_eq!(vec!["B4", "B5", "B6", "B7"], kept_tickers);
+
+    input.max_positions = Some(0);
+    let validation_problems = allocate(input, &logger).validation_problems.unwrap();
+    assert!(validation_problems.iter().any(|v| match v {
+        ValidationResult::PROBLEM(p) => p.code == "maximum-number-of-positions-must-be-positive",
+        _ => false,
+    }));
+}
+
+/// Tests that candidates failing the screening thresholds are dropped and reported.
+#[test]
+fn test_allocate_with_screening() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    // Make the downside of the first candidate more likely than the screen allows
+    input.candidates[0].scenarios[0].probability = 0.6;
+    input.candidates[0].scenarios[1].probability = 0.4;
+    input.max_downside_probability = Some(0.5);
+
+    let allocation_result = allocate(input, &logger).result.unwrap();
+
+    assert_eq!(4, allocation_result.allocations.len());
+    assert!(allocation_result
+        .allocations
+        .iter()
+        .all(|tf| tf.ticker != "A0"));
+    assert_eq!(1, allocation_result.filtered_candidates.len());
+    assert_eq!("A0", allocation_result.filtered_candidates[0].ticker);
+    assert_eq!(
+        "downside-probability-above-screening-threshold",
+        allocation_result.filtered_candidates[0].code
+    );
+}
+
+/// Tests that a single even-money bet is allocated the classic Kelly fraction p - q.
+#[test]
+fn test_allocate_bets() {
+    let logger = create_test_logger();
+    let input: BetsInput = serde_yaml::from_str(
+        "
+        bets:
+          - name: coin
+            probability_of_win: 0.6
+            odds: 1.0
+        long_only: true
+        ",
+    )
+    .unwrap();
+
+    let allocation_result = allocate_bets(input, &logger).result.unwrap();
+
+    assert_eq!(1, allocation_result.allocations.len());
+    assert_eq!("coin", allocation_result.allocations[0].ticker);
+    assert_close!(
+        0.2,
+        allocation_result.allocations[0].fraction,
+        ASSERTION_TOLERANCE
+    );
+}
+
+/// Tests that a call option is allocated with the returns of its payoffs, where the premium is lost
+/// if the share ends up below the strike, like a bet with the net odds of the payoff.
+#[test]
+fn test_allocate_call_option() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        candidates: []
+        call_options:
+          - name: ABC call
+            ticker: ABC C12
+            underlying:
+              name: ABC
+              ticker: ABC
+              description: Underlying business
+              market_cap: 1000.0
+              scenarios:
+                - thesis: Decline
+                  intrinsic_value: 800.0
+                  probability: 0.5
+                - thesis: Growth
+                  intrinsic_value: 2000.0
+                  probability: 0.5
+            share_price: 10.0
+            strike: 12.0
+            premium: 2.0
+        long_only: true
+        ",
+    )
+    .unwrap();
+
+    let allocation_result = allocate(input, &logger).result.unwrap();
+
+    // Net odds of 3 to 1 for the payoff of 8 on the premium of 2, so the Kelly fraction is
+    // 0.5 - 0.5 / 3
+    assert_eq!(1, allocation_result.allocations.len());
+    assert_eq!("ABC C12", allocation_result.allocations[0].ticker);
+    assert_close!(
+        1.0 / 3.0,
+        allocation_result.allocations[0].fraction,
+        ASSERTION_TOLERANCE
+    );
+}
+
+/// Tests that an allocation of almost all capital to a single candidate is reported with a warning
+/// and a capped alternative.
+#[test]
+fn test_allocate_concentrated_in_single_candidate() {
+    let logger = create_test_logger();
+    let input: BetsInput = serde_yaml::from_str(
+        "
+        bets:
+          - name: sure
+            probability_of_win: 0.99
+            odds: 1.0
+          - name: coin
+            probability_of_win: 0.55
+            odds: 1.0
+        long_only: true
+        ",
+    )
+    .unwrap();
+
+    let response = allocate_bets(input, &logger);
+    assert!(response
+        .validation_problems
+        .unwrap()
+        .iter()
+        .any(|v| match v {
+            ValidationResult::PROBLEM(p) => {
+                p.code == "allocation-concentrated-in-single-candidate"
+                    && p.message.contains("allocated to sure")
+                    && p.message
+                        .contains("worst scenario Loss with a return of -100.0%")
+            }
+            ValidationResult::OK => false,
+        }));
+
+    let allocation_result = response.result.unwrap();
+    assert!(allocation_result.allocations[0].fraction > 0.95);
+    let alternative = allocation_result.capped_alternative.unwrap();
+    assert_close!(
+        0.25,
+        alternative.allocations[0].fraction,
+        ASSERTION_TOLERANCE
+    );
+    assert!(alternative.allocations[1].fraction <= 0.25 + ASSERTION_TOLERANCE);
+    assert!(alternative.fraction_of_concentrated_growth < 1.0);
+}
+
+/// Tests that a warning is emitted when a single scenario contributes a larger share of the
+/// expected logarithmic growth than allowed.
+#[test]
+fn test_allocate_with_growth_concentration_warning() {
+    let logger = create_test_logger();
+    let has_scenario_warning = |input: AllocationInput| {
+        allocate(input, &logger)
+            .validation_problems
+            .unwrap()
+            .iter()
+            .any(|v| match v {
+                ValidationResult::PROBLEM(p) => {
+                    p.code == "growth-dominated-by-single-scenario"
+                        && p.message.contains("100% up with 50% probability")
+                }
+                ValidationResult::OK => false,
+            })
+    };
+
+    // Each upside scenario of the five same candidates contributes a fifth of the growth
+    let mut input = create_five_same_candidates(None, None, None);
+    assert!(!has_scenario_warning(input.clone()));
+
+    input.max_growth_concentration = Some(0.15);
+    assert!(has_scenario_warning(input));
+}
+
+/// Tests that the allocation is projected onto the caps of the conviction tiers, giving up some of
+/// the expected logarithmic growth.
+#[test]
+fn test_allocate_with_conviction_tiers() {
+    let logger = create_test_logger();
+    let mut input = create_five_same_candidates(Some(true), None, None);
+    input.conviction_tiers = Some(
+        serde_yaml::from_str(
+            "
+            tiers:
+              A0: high
+              A1: medium
+            ",
+        )
+        .unwrap(),
+    );
+
+    let allocation_result = allocate(input, &logger).result.unwrap();
+    let sizing = allocation_result.conviction_sizing.unwrap();
+
+    let expected_fractions = [0.25, 0.15, 0.05, 0.05, 0.05];
+    for (tf, expected) in sizing.allocations.iter().zip(expected_fractions) {
+        assert_close!(expected, tf.fraction, ASSERTION_TOLERANCE);
+    }
+    assert_close!(0.45, sizing.cash, ASSERTION_TOLERANCE);
+    assert!(sizing.growth_give_up > 0.0);
+    assert!(sizing.fraction_of_kelly_growth > 0.0 && sizing.fraction_of_kelly_growth < 1.0);
 }
 
 /// Tests that having 5 same candidate companies with no-leverage constraint produces a non-levered
@@ -280,7 +981,9 @@ fn test_allocate_all_same_with_no_leverage() {
 
     // Allocate
     let portfolio: AllocationResponse = allocate(input, &logger);
-    let tickers_and_fractions: Vec<TickerAndFraction> = portfolio.result.unwrap().allocations;
+    let allocation_result = portfolio.result.unwrap();
+    assert!(allocation_result.deleveraging_suggestion.is_none());
+    let tickers_and_fractions: Vec<TickerAndFraction> = allocation_result.allocations;
 
     // Assert that all the fractions are the same and they sum up to 1 (no leverage)
     tickers_and_fractions
@@ -323,6 +1026,496 @@ fn test_allocate_all_same_with_no_leverage_and_maximum_capital_loss_constraint()
         .for_each(|tf| assert_close!(0.02, tf.fraction, ASSERTION_TOLERANCE));
 }
 
+/// Tests that a candidate crowded out by the maximum capital loss constraint is reported with the
+/// reason of its zero allocation.
+#[test]
+fn test_allocate_and_analyze_with_expected_inflation() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(Some(true), None, None);
+    input.expected_inflation = Some(0.03);
+
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    let adjusted = allocation_result.analysis.inflation_adjusted.unwrap();
+    assert_close!(
+        allocation_result.analysis.expected_return,
+        adjusted.expected_nominal_return,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        (1.0 + adjusted.expected_nominal_return) / 1.03 - 1.0,
+        adjusted.expected_real_return,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        allocation_result.solver_metadata.expected_log_growth - 1.03_f64.ln(),
+        adjusted.expected_real_log_growth.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+
+    // The analysis reports the same figures, without the growth on the summary detail level
+    let portfolio = Portfolio {
+        companies: allocation_result
+            .allocations
+            .iter()
+            .map(|tf| PortfolioCompany {
+                company: input
+                    .candidates
+                    .iter()
+                    .find(|c| c.ticker == tf.ticker)
+                    .unwrap()
+                    .clone(),
+                fraction: tf.fraction,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let options = AnalysisOptions {
+        expected_inflation: Some(0.03),
+        ..AnalysisOptions::default()
+    };
+    let analysis_adjusted = analyze(portfolio.clone(), &[], &options, &logger)
+        .result
+        .unwrap()
+        .inflation_adjusted
+        .unwrap();
+    assert_close!(
+        adjusted.expected_real_log_growth.unwrap(),
+        analysis_adjusted.expected_real_log_growth.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+
+    let summary_options = AnalysisOptions {
+        detail_level: Some(DetailLevel::Summary),
+        ..options
+    };
+    let summary_adjusted = analyze(portfolio, &[], &summary_options, &logger)
+        .result
+        .unwrap()
+        .inflation_adjusted
+        .unwrap();
+    assert_close!(
+        adjusted.expected_real_return,
+        summary_adjusted.expected_real_return,
+        ASSERTION_TOLERANCE
+    );
+    assert!(summary_adjusted.expected_real_log_growth.is_none());
+
+    // Analysis without the expected inflation doesn't adjust
+    assert!(
+        allocate(create_five_same_candidates(Some(true), None, None), &logger)
+            .result
+            .unwrap()
+            .analysis
+            .inflation_adjusted
+            .is_none()
+    );
+}
+
+#[test]
+fn test_analyze_with_full_detail_level() {
+    let logger = create_test_logger();
+    let input: AllocationInput = create_five_same_candidates(Some(true), None, None);
+    let portfolio = Portfolio {
+        companies: input
+            .candidates
+            .into_iter()
+            .map(|company| PortfolioCompany {
+                company,
+                fraction: 0.2,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let analysis = |detail_level: DetailLevel, distribution: Option<bool>| {
+        let options = AnalysisOptions {
+            detail_level: Some(detail_level),
+            distribution,
+            ..AnalysisOptions::default()
+        };
+        analyze(portfolio.clone(), &[], &options, &logger)
+            .result
+            .unwrap()
+    };
+
+    // Full detail level includes the distribution, unless it's explicitly excluded
+    let full = analysis(DetailLevel::Full, None);
+    let total_probability: f64 = full
+        .distribution
+        .unwrap()
+        .outcomes
+        .unwrap()
+        .iter()
+        .map(|p| p.probability)
+        .sum();
+    assert_close!(1.0, total_probability, ASSERTION_TOLERANCE);
+    assert!(analysis(DetailLevel::Full, Some(false))
+        .distribution
+        .is_none());
+    assert!(analysis(DetailLevel::Standard, None).distribution.is_none());
+}
+
+#[test]
+fn test_analyze_with_scenarios_outside_of_macro_scenarios() {
+    let logger = create_test_logger();
+    let portfolio = |macro_scenarios: &str| -> Portfolio {
+        serde_yaml::from_str(&format!(
+            "
+            {macro_scenarios}
+            companies:
+              - fraction: 1.0
+                company:
+                  name: A
+                  ticker: A
+                  description: Cyclical
+                  market_cap: 1.0
+                  scenarios:
+                    - thesis: Loses
+                      intrinsic_value: 0.5
+                      probability: 0.3
+                      macro_scenario: recession
+                    - thesis: Grows
+                      intrinsic_value: 1.5
+                      probability: 0.7
+            "
+        ))
+        .unwrap()
+    };
+
+    // Only the scenario in the recession is placed in a macro scenario, so the other one would be
+    // left out of the outcomes
+    let macro_scenarios =
+        "macro_scenarios: [{name: expansion, probability: 0.7}, {name: recession, probability: 0.3}]";
+    let error = analyze(
+        portfolio(macro_scenarios),
+        &[],
+        &AnalysisOptions::default(),
+        &logger,
+    )
+    .error
+    .unwrap();
+    assert_eq!("scenarios-outside-of-macro-scenarios", error.code);
+
+    let error = analyze(portfolio(""), &[], &AnalysisOptions::default(), &logger)
+        .error
+        .unwrap();
+    assert_eq!("unknown-macro-scenario", error.code);
+
+    // Placing both scenarios gives the expected return of the company
+    let placed = portfolio(macro_scenarios).companies[0].company.scenarios[1].clone();
+    let mut placed_portfolio = portfolio(macro_scenarios);
+    placed_portfolio.companies[0].company.scenarios[1] = Scenario {
+        macro_scenario: Some("expansion".to_string()),
+        ..placed
+    };
+    let analysis = analyze(placed_portfolio, &[], &AnalysisOptions::default(), &logger)
+        .result
+        .unwrap();
+    assert_close!(0.2, analysis.expected_return, ASSERTION_TOLERANCE);
+}
+
+#[test]
+fn test_allocate_and_analyze_with_expected_log_growth_rate() {
+    let logger = create_test_logger();
+    let input: AllocationInput = create_five_same_candidates(Some(true), None, None);
+
+    // The analysis of the allocation reports the growth rate maximized by the solver
+    let allocation_result = allocate(input.clone(), &logger).result.unwrap();
+    let growth_rate = allocation_result.analysis.expected_log_growth_rate.unwrap();
+    assert_close!(
+        allocation_result.solver_metadata.expected_log_growth,
+        growth_rate,
+        ASSERTION_TOLERANCE
+    );
+    let compound_growth = allocation_result.analysis.compound_growth.unwrap();
+    assert_close!(
+        growth_rate.exp() - 1.0,
+        compound_growth.geometric_return,
+        ASSERTION_TOLERANCE
+    );
+    assert!(compound_growth.geometric_return < allocation_result.analysis.expected_return);
+    assert!(compound_growth.annualized_log_growth_rate.is_none());
+
+    // With the horizon in years, the analysis also annualizes the growth
+    let portfolio = Portfolio {
+        companies: allocation_result
+            .allocations
+            .iter()
+            .map(|tf| PortfolioCompany {
+                company: input
+                    .candidates
+                    .iter()
+                    .find(|c| c.ticker == tf.ticker)
+                    .unwrap()
+                    .clone(),
+                fraction: tf.fraction,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let options = AnalysisOptions {
+        horizon_in_years: Some(2.0),
+        ..AnalysisOptions::default()
+    };
+    let analysis = analyze(portfolio.clone(), &[], &options, &logger)
+        .result
+        .unwrap();
+    let annualized = analysis.compound_growth.unwrap();
+    assert_close!(
+        growth_rate / 2.0,
+        annualized.annualized_log_growth_rate.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        2.0 * annualized.doubling_time.unwrap(),
+        annualized.doubling_time_in_years.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+
+    let summary_options = AnalysisOptions {
+        detail_level: Some(DetailLevel::Summary),
+        ..options
+    };
+    let summary = analyze(portfolio, &[], &summary_options, &logger)
+        .result
+        .unwrap();
+    assert!(summary.expected_log_growth_rate.is_none());
+    assert!(summary.compound_growth.is_none());
+}
+
+#[test]
+fn test_allocate_with_zero_allocation_reasons() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(
+        Some(true),
+        Some(CapitalLoss {
+            fraction_of_capital: 0.5,
+            probability_of_loss: 0.05,
+        }),
+        None,
+    );
+
+    // The second candidate has a lower expected return per unit of worst-case loss
+    input.candidates.truncate(2);
+    input.candidates[1].scenarios = vec![
+        Scenario {
+            thesis: "Total loss with 20% probability".to_string(),
+            intrinsic_value: 0.0,
+            probability: 0.2,
+            ..Default::default()
+        },
+        Scenario {
+            thesis: "40% up with 80% probability".to_string(),
+            intrinsic_value: 1.4,
+            probability: 0.8,
+            ..Default::default()
+        },
+    ];
+
+    // Allocate
+    let allocation_result = allocate(input, &logger).result.unwrap();
+    assert_close!(
+        0.1,
+        allocation_result.allocations[0].fraction,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        0.0,
+        allocation_result.allocations[1].fraction,
+        ASSERTION_TOLERANCE
+    );
+
+    // Assert that buying the second candidate would add growth, but the constraint is binding
+    assert_eq!(allocation_result.zero_allocations.len(), 1);
+    let zero_allocation = &allocation_result.zero_allocations[0];
+    assert_eq!(zero_allocation.ticker, "A1");
+    assert_eq!(zero_allocation.code, "capital-loss-constraint-binding");
+    assert!(zero_allocation.marginal_growth > 0.0);
+}
+
+/// Tests that only one candidate from a mutually exclusive group is allocated to, while the group
+/// cap limits the total allocation to the other group.
+#[test]
+fn test_allocate_all_same_with_candidate_groups() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = create_five_same_candidates(None, None, None);
+    input.candidate_groups = Some(vec![
+        CandidateGroup {
+            name: "Share classes".to_string(),
+            tickers: vec!["A0".to_string(), "A1".to_string()],
+            mutually_exclusive: Some(true),
+            max_allocation: None,
+        },
+        CandidateGroup {
+            name: "Same thesis".to_string(),
+            tickers: vec!["A2".to_string(), "A3".to_string()],
+            mutually_exclusive: None,
+            max_allocation: Some(0.2),
+        },
+    ]);
+
+    // Allocate
+    let allocation_result = allocate(input, &logger).result.unwrap();
+    let tickers_and_fractions: Vec<TickerAndFraction> = allocation_result.allocations;
+
+    // Assert that exactly one of the share classes is in the portfolio and that the capped group
+    // gets 20% in total
+    assert_eq!(tickers_and_fractions.len(), 4);
+    assert_eq!(
+        tickers_and_fractions
+            .iter()
+            .filter(|tf| tf.ticker == "A0" || tf.ticker == "A1")
+            .count(),
+        1
+    );
+    assert_close!(
+        0.2,
+        tickers_and_fractions
+            .iter()
+            .filter(|tf| tf.ticker == "A2" || tf.ticker == "A3")
+            .map(|tf| tf.fraction)
+            .sum::<f64>(),
+        ASSERTION_TOLERANCE
+    );
+
+    // The other share class is reported with the reason of its zero allocation
+    assert_eq!(allocation_result.zero_allocations.len(), 1);
+    assert_eq!(
+        allocation_result.zero_allocations[0].code,
+        "not-chosen-from-mutually-exclusive-group"
+    );
+}
+
+/// Tests that the choice from a mutually exclusive group is made by the objective of the solver,
+/// which includes the risk-free return of the cash that isn't invested.
+#[test]
+fn test_allocate_with_candidate_groups_and_risk_free_rate() {
+    let logger = create_test_logger();
+    let input = |risk_free_rate: &str| -> AllocationInput {
+        serde_yaml::from_str(&format!(
+            "
+            long_only: true
+            {risk_free_rate}
+            candidate_groups:
+              - name: Either
+                tickers: [A, B]
+                mutually_exclusive: true
+            candidates:
+              - name: A
+                ticker: A
+                description: Doubles or goes bankrupt
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Doubles
+                    intrinsic_value: 2.0
+                    probability: 0.7
+                  - thesis: Bankrupt
+                    intrinsic_value: 0.0
+                    probability: 0.3
+              - name: B
+                ticker: B
+                description: Steady
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Up
+                    intrinsic_value: 1.3
+                    probability: 0.7
+                  - thesis: Down
+                    intrinsic_value: 0.8
+                    probability: 0.3
+            "
+        ))
+        .unwrap()
+    };
+    let chosen = |input: AllocationInput| -> Vec<TickerAndFraction> {
+        allocate(input, &logger)
+            .result
+            .unwrap()
+            .allocations
+            .into_iter()
+            .filter(|a| a.fraction > ASSERTION_TOLERANCE)
+            .collect()
+    };
+
+    // Without financing, the levered allocation to B grows the fastest
+    let allocations = chosen(input(""));
+    assert_eq!(1, allocations.len());
+    assert_eq!("B", allocations[0].ticker);
+    assert_close!(2.5, allocations[0].fraction, ASSERTION_TOLERANCE);
+
+    // Cash earning 10% favors the small allocation to A, although B grows faster without the
+    // return of the cash
+    let allocations = chosen(input("risk_free_rate: 0.1"));
+    assert_eq!(1, allocations.len());
+    assert_eq!("A", allocations[0].ticker);
+    assert_close!(1.0 / 3.0, allocations[0].fraction, ASSERTION_TOLERANCE);
+}
+
+/// Tests that the choice from a mutually exclusive group accounts for the turnover penalty, which
+/// the solver subtracts from the expected logarithmic growth.
+#[test]
+fn test_allocate_with_candidate_groups_and_turnover_penalty() {
+    let logger = create_test_logger();
+    let input = |turnover_penalty: &str| -> AllocationInput {
+        serde_yaml::from_str(&format!(
+            "
+            long_only: true
+            {turnover_penalty}
+            current_weights: [{{ticker: B, fraction: 0.5}}]
+            candidate_groups:
+              - name: Either
+                tickers: [A, B]
+                mutually_exclusive: true
+            candidates:
+              - name: A
+                ticker: A
+                description: Doubles or halves
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Doubles
+                    intrinsic_value: 2.0
+                    probability: 0.5
+                  - thesis: Halves
+                    intrinsic_value: 0.5
+                    probability: 0.5
+              - name: B
+                ticker: B
+                description: Held already
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Up
+                    intrinsic_value: 1.5
+                    probability: 0.6
+                  - thesis: Down
+                    intrinsic_value: 0.5
+                    probability: 0.4
+            "
+        ))
+        .unwrap()
+    };
+    let chosen = |input: AllocationInput| -> Vec<TickerAndFraction> {
+        allocate(input, &logger)
+            .result
+            .unwrap()
+            .allocations
+            .into_iter()
+            .filter(|a| a.fraction > ASSERTION_TOLERANCE)
+            .collect()
+    };
+
+    // Without the penalty, A grows the fastest
+    let allocations = chosen(input(""));
+    assert_eq!(1, allocations.len());
+    assert_eq!("A", allocations[0].ticker);
+    assert_close!(0.5, allocations[0].fraction, ASSERTION_TOLERANCE);
+
+    // Buying A is penalized so much that keeping B is better, although the penalized allocation to
+    // A still grows faster than B
+    let allocations = chosen(input("turnover_penalty: 2.0"));
+    assert_eq!(1, allocations.len());
+    assert_eq!("B", allocations[0].ticker);
+    assert!(allocations[0].fraction > 0.45 && allocations[0].fraction < 0.5);
+}
+
 /// Tests allocation for 6 candidate companies without constraints.
 #[test]
 fn test_allocate() {
@@ -342,47 +1535,67 @@ fn test_allocate() {
 
     assert_eq!(tickers_and_fractions[0].ticker, "A".to_string());
     assert_close!(
-        0.1420462,
+        0.0000271,
         tickers_and_fractions[0].fraction,
         ASSERTION_TOLERANCE
     );
 
     assert_eq!(tickers_and_fractions[1].ticker, "B".to_string());
     assert_close!(
-        0.6407610,
+        0.4656544,
         tickers_and_fractions[1].fraction,
         ASSERTION_TOLERANCE
     );
 
     assert_eq!(tickers_and_fractions[2].ticker, "C".to_string());
     assert_close!(
-        0.5871123,
+        0.4989068,
         tickers_and_fractions[2].fraction,
         ASSERTION_TOLERANCE
     );
 
     assert_eq!(tickers_and_fractions[3].ticker, "D".to_string());
     assert_close!(
-        0.2316455,
+        0.0182956,
         tickers_and_fractions[3].fraction,
         ASSERTION_TOLERANCE
     );
 
     assert_eq!(tickers_and_fractions[4].ticker, "E".to_string());
     assert_close!(
-        0.3010064,
+        0.0211351,
         tickers_and_fractions[4].fraction,
         ASSERTION_TOLERANCE
     );
 
     assert_eq!(tickers_and_fractions[5].ticker, "F".to_string());
     assert_close!(
-        0.3498925,
+        0.0058807,
         tickers_and_fractions[5].fraction,
         ASSERTION_TOLERANCE
     );
 }
 
+/// Tests that allocating the same input twice produces no differences.
+#[test]
+fn test_allocation_diff_of_same_input() {
+    let logger = create_test_logger();
+    let input: AllocationInput = create_five_same_candidates(None, None, None);
+
+    let original = allocate(input.clone(), &logger);
+    let rerun = allocate(input, &logger);
+    let diff = allocation_diff(&original, &rerun);
+
+    assert_eq!(5, diff.fraction_changes.len());
+    assert_close!(0.0, diff.max_absolute_fraction_change, ASSERTION_TOLERANCE);
+    assert_close!(
+        0.0,
+        diff.expected_log_growth_change.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+    assert!(!diff.error_changed);
+}
+
 /// Tests allocation with all constraints, but only with three candidates because having more
 /// candidates grows exponentially in complexity when we have constraints. Having just three of them
 /// is enough for the integration test.
@@ -394,8 +1607,15 @@ fn test_allocate_with_constraints() {
     let input: AllocationInput =
         serde_yaml::from_str(&load_test_file_content("test_data_with_constraints.yaml")).unwrap();
 
+    // The three maximum individual allocations of 0.3 never add up to more than 1, so the leverage
+    // constraint is only reported as redundant.
     let validation_errors: Vec<ValidationResult> = validate(&input, &logger);
-    assert_eq!(validation_errors, vec![]);
+    assert_eq!(validation_errors.len(), 1);
+    assert!(matches!(
+        &validation_errors[0],
+        ValidationResult::PROBLEM(problem)
+            if problem.code == "redundant-constraint" && problem.severity == Severity::WARNING
+    ));
 
     // Allocate
     let portfolio: AllocationResponse = allocate(input, &logger);
@@ -433,7 +1653,8 @@ fn test_analyze() {
     let portfolio = KellyAllocator::new(&logger, MAX_ITER)
         .allocate(input.candidates)
         .unwrap();
-    let analysis_response: AnalysisResponse = analyze(portfolio, &logger);
+    let analysis_response: AnalysisResponse =
+        analyze(portfolio, &[], &AnalysisOptions::default(), &logger);
     let analysis_result = analysis_response.result.unwrap();
 
     // Debug convenience: To see the output, use create_logger(Info) instead of create_test_logger()
@@ -445,25 +1666,995 @@ fn test_analyze() {
         ASSERTION_TOLERANCE
     );
     assert_close!(
-        -1.608054,
+        -0.981272,
         analysis_result.worst_case_outcome.portfolio_return,
         ASSERTION_TOLERANCE
     );
     assert_close!(
-        -0.234950,
+        -0.080892,
         analysis_result
             .worst_case_outcome
             .probability_weighted_return,
         ASSERTION_TOLERANCE
     );
     assert_close!(
-        0.1896847,
-        analysis_result.cumulative_probability_of_loss,
+        0.3300000,
+        analysis_result.cumulative_probability_of_loss.unwrap(),
         ASSERTION_TOLERANCE
     );
     assert_close!(
-        1.0971964,
+        0.7856967,
         analysis_result.expected_return,
         ASSERTION_TOLERANCE
     );
+    assert!(analysis_result.variance_of_return > 0.0);
+}
+
+/// Summary analysis calculates the statistics analytically, so it works even for portfolios with
+/// too many outcomes to enumerate, for which the standard analysis fails.
+#[test]
+fn test_analyze_summary_for_large_portfolio() {
+    let logger = create_test_logger();
+    let portfolio = Portfolio {
+        companies: (0..16)
+            .map(|i| PortfolioCompany {
+                company: Company {
+                    name: format!("{i}"),
+                    ticker: format!("{i}"),
+                    description: format!("{i}"),
+                    market_cap: 1e6,
+                    auto_status_quo: None,
+                    units: None,
+                    last_reviewed: None,
+                    scenarios: vec![
+                        Scenario {
+                            thesis: "Head".to_string(),
+                            intrinsic_value: 2e6,
+                            probability: 0.5,
+                            ..Default::default()
+                        },
+                        Scenario {
+                            thesis: "Tail".to_string(),
+                            intrinsic_value: 0.0,
+                            probability: 0.5,
+                            ..Default::default()
+                        },
+                    ],
+                },
+                fraction: 0.0625,
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    // Too many outcomes to enumerate, so the statistics are estimated from sampled outcomes
+    let sampled_options = AnalysisOptions {
+        n_samples: Some(20000),
+        seed: Some(7),
+        ..AnalysisOptions::default()
+    };
+    let sampled_result = analyze(portfolio.clone(), &[], &sampled_options, &logger)
+        .result
+        .unwrap();
+    let statistics = sampled_result.sampled_statistics.unwrap();
+    assert_eq!(20000, statistics.n_samples);
+    assert_eq!(7, statistics.seed);
+    assert!(statistics.expected_return.lower < 0.0 && 0.0 < statistics.expected_return.upper);
+    // Loss if fewer than 8 of the 16 coin flips are heads, i.e. (1 - P(8 heads)) / 2
+    let probability_of_loss = (1.0 - 12870.0 / 65536.0) / 2.0;
+    assert!(statistics.probability_of_loss.lower < probability_of_loss);
+    assert!(probability_of_loss < statistics.probability_of_loss.upper);
+    assert_eq!(
+        Some(statistics.probability_of_loss.value),
+        sampled_result.cumulative_probability_of_loss
+    );
+    // Up to 4 heads have a probability of 3.8% and up to 5 heads of 10.5%, so the 5th percentile
+    // is 5 heads, i.e. a return of -0.375
+    assert_close!(-0.375, statistics.tail_quantile.value, ASSERTION_TOLERANCE);
+    assert!(statistics.expected_tail_return.value <= statistics.tail_quantile.value);
+
+    let summary_options = AnalysisOptions {
+        detail_level: Some(DetailLevel::Summary),
+        ..AnalysisOptions::default()
+    };
+    let summary_result = analyze(portfolio.clone(), &[], &summary_options, &logger)
+        .result
+        .unwrap();
+    assert_eq!(summary_result.cumulative_probability_of_loss, None);
+    assert_close!(0.0, summary_result.expected_return, ASSERTION_TOLERANCE);
+    // Sixteen independent fair coin flips, each with variance 1 and fraction 1/16
+    assert_close!(
+        0.0625,
+        summary_result.variance_of_return,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        -1.0,
+        summary_result.worst_case_outcome.portfolio_return,
+        ASSERTION_TOLERANCE
+    );
+
+    // Sub-portfolio of two companies has few enough outcomes for the standard analysis, with the
+    // fractions held fixed
+    let subset_options = AnalysisOptions {
+        tickers: Some("3,7".to_string()),
+        ..AnalysisOptions::default()
+    };
+    let subset_result = analyze(portfolio.clone(), &[], &subset_options, &logger)
+        .result
+        .unwrap();
+    assert_close!(0.125, subset_result.gross_exposure, ASSERTION_TOLERANCE);
+    assert_close!(
+        -0.125,
+        subset_result.worst_case_outcome.portfolio_return,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        0.25,
+        subset_result.cumulative_probability_of_loss.unwrap(),
+        ASSERTION_TOLERANCE
+    );
+
+    let unknown_options = AnalysisOptions {
+        tickers: Some("3,X".to_string()),
+        ..AnalysisOptions::default()
+    };
+    assert_eq!(
+        "unknown-tickers-in-analysis",
+        analyze(portfolio, &[], &unknown_options, &logger)
+            .error
+            .unwrap()
+            .code
+    );
+}
+
+/// Starts the server on a free port with the default configuration and returns its base URL.
+fn start_test_server() -> String {
+    let config: ServerConfig = toml::from_str("bind_address = \"127.0.0.1:0\"").unwrap();
+    let server = HttpServerStarter::new(
+        &config.dropshot,
+        api_description(),
+        ServerState::new(&config),
+        &create_test_logger(),
+    )
+    .unwrap()
+    .start();
+    let url = format!("http://{}", server.local_addr());
+    // The server stops when dropped, so keep it running until the end of the test process
+    std::mem::forget(server);
+    url
+}
+
+/// Posts the JSON body to the allocate endpoint of the server and returns the status and the body.
+async fn post_allocate(url: &str, body: &str) -> (reqwest::StatusCode, String) {
+    let response = reqwest::Client::new()
+        .post(format!("{url}/allocate"))
+        .header("content-type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap();
+    (response.status(), response.text().await.unwrap())
+}
+
+/// Returns the JSON allocation input with a single candidate with the given market cap and
+/// intrinsic value of the upside scenario.
+fn single_candidate_json(market_cap: &str, upside_intrinsic_value: &str) -> String {
+    format!(
+        r#"{{"candidates": [{{
+            "name": "Extreme", "ticker": "X", "description": "Extreme values",
+            "market_cap": {market_cap},
+            "scenarios": [
+                {{"thesis": "Lost", "intrinsic_value": 0.5, "probability": 0.5}},
+                {{"thesis": "Unbounded", "intrinsic_value": {upside_intrinsic_value},
+                  "probability": 0.5}}
+            ]
+        }}]}}"#
+    )
+}
+
+#[tokio::test]
+async fn test_allocate_non_finite_values_through_http() {
+    let url = start_test_server();
+
+    // JSON can't represent NaN, so the body is rejected before reaching the validation
+    let (status, _) = post_allocate(&url, &single_candidate_json("NaN", "2.0")).await;
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, status);
+
+    // Finite inputs whose return overflows are reported with the scenario responsible
+    let (status, body) = post_allocate(&url, &single_candidate_json("1e-10", "1e308")).await;
+    assert_eq!(reqwest::StatusCode::OK, status);
+    let response: AllocationResponse = serde_json::from_str(&body).unwrap();
+    assert!(response.result.is_none());
+    let problem = response
+        .validation_problems
+        .unwrap()
+        .into_iter()
+        .find_map(|v| match v {
+            ValidationResult::PROBLEM(p) if p.code == "non-finite-value-in-scenario" => Some(p),
+            _ => None,
+        })
+        .unwrap();
+    assert!(problem.message.contains("Scenario Unbounded of company X"));
+
+    // Returns whose squares would overflow while solving are scaled back by the normalization of
+    // the units, so the allocation stays finite (see the allocator tests for the diagnostic)
+    let (status, body) = post_allocate(&url, &single_candidate_json("1.0", "1e160")).await;
+    assert_eq!(reqwest::StatusCode::OK, status);
+    let response: AllocationResponse = serde_json::from_str(&body).unwrap();
+    let result = response.result.unwrap();
+    assert!(result.allocations.iter().all(|a| a.fraction.is_finite()));
+}
+
+#[tokio::test]
+async fn test_allocate_remote_only_from_allowed_hosts() {
+    let url = start_test_server();
+
+    // No host is allowed by default, so the server doesn't make requests on behalf of clients
+    let response = reqwest::Client::new()
+        .post(format!("{url}/allocate/remote"))
+        .header("content-type", "application/json")
+        .body(r#"{"url": "http://169.254.169.254/latest/meta-data/"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::FORBIDDEN, response.status());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("remote-input-url-not-allowed"));
+}
+
+/// Posts the allocation input to the allocate endpoint with the API key and returns the id of the
+/// recorded allocation.
+async fn record_allocation(url: &str, api_key: &str) -> String {
+    let response = reqwest::Client::new()
+        .post(format!("{url}/allocate"))
+        .header("content-type", "application/json")
+        .header("x-api-key", api_key)
+        .body(single_candidate_json("1.0", "2.0"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+    response.headers()["x-allocation-id"]
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_rerun_only_allocations_of_the_api_key() {
+    let url = start_test_server();
+    let id = record_allocation(&url, "a").await;
+
+    let rerun = |api_key: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{url}/allocations/{id}/rerun"))
+            .header("x-api-key", api_key)
+            .send()
+    };
+    assert_eq!(
+        reqwest::StatusCode::NOT_FOUND,
+        rerun("b").await.unwrap().status()
+    );
+    assert_eq!(reqwest::StatusCode::OK, rerun("a").await.unwrap().status());
+}
+
+#[tokio::test]
+async fn test_share_only_allocations_of_the_api_key() {
+    let url = start_test_server();
+    let id = record_allocation(&url, "a").await;
+
+    let share = |api_key: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{url}/allocations/{id}/share"))
+            .header("content-type", "application/json")
+            .header("x-api-key", api_key)
+            .body("{}")
+            .send()
+    };
+    assert_eq!(
+        reqwest::StatusCode::NOT_FOUND,
+        share("b").await.unwrap().status()
+    );
+    assert_eq!(reqwest::StatusCode::OK, share("a").await.unwrap().status());
+}
+
+#[test]
+fn test_allocate_with_macro_scenarios() {
+    let logger = create_test_logger();
+    let input = |macro_scenarios: &str| -> AllocationInput {
+        serde_yaml::from_str(&format!(
+            "
+            long_only: true
+            {macro_scenarios}
+            candidates:
+              - name: A
+                ticker: A
+                description: Doubles or halves
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Doubles
+                    intrinsic_value: 2.0
+                    probability: 0.5
+                    macro_scenario: expansion
+                  - thesis: Halves
+                    intrinsic_value: 0.5
+                    probability: 0.5
+                    macro_scenario: recession
+              - name: B
+                ticker: B
+                description: Doubles or halves with A
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Doubles
+                    intrinsic_value: 2.0
+                    probability: 0.5
+                    macro_scenario: expansion
+                  - thesis: Halves
+                    intrinsic_value: 0.5
+                    probability: 0.5
+                    macro_scenario: recession
+            "
+        ))
+        .unwrap()
+    };
+    let correlated = input(
+        "macro_scenarios: [{name: expansion, probability: 0.5}, {name: recession, probability: 0.5}]",
+    );
+    assert!(validate(&correlated, &logger).is_empty());
+
+    // Perfectly correlated candidates are a single bet, whose Kelly fraction is 0.5
+    let result = allocate(correlated.clone(), &logger).result.unwrap();
+    let total: f64 = result.allocations.iter().map(|a| a.fraction).sum();
+    assert_close!(0.5, total, ASSERTION_TOLERANCE);
+    assert_close!(
+        0.140625,
+        result.analysis.variance_of_return,
+        ASSERTION_TOLERANCE
+    );
+
+    // Independent candidates diversify, which allows a larger total allocation
+    let mut independent = correlated.clone();
+    independent.macro_scenarios = None;
+    for c in independent.candidates.iter_mut() {
+        c.scenarios.iter_mut().for_each(|s| s.macro_scenario = None);
+    }
+    let result = allocate(independent, &logger).result.unwrap();
+    let total: f64 = result.allocations.iter().map(|a| a.fraction).sum();
+    assert!(total > 0.6);
+
+    let codes = |input: &AllocationInput| {
+        validate(input, &logger)
+            .into_iter()
+            .filter_map(|v| match v {
+                ValidationResult::PROBLEM(p) => Some(p.code),
+                ValidationResult::OK => None,
+            })
+            .collect_vec()
+    };
+    let mismatch = input(
+        "macro_scenarios: [{name: expansion, probability: 0.6}, {name: recession, probability: 0.4}]",
+    );
+    assert_eq!(
+        vec!["macro-scenario-probability-mismatch"],
+        codes(&mismatch).into_iter().unique().collect_vec()
+    );
+    assert_eq!(
+        vec!["unknown-macro-scenario", "unknown-macro-scenario"],
+        codes(&input(""))
+    );
+}
+
+/// Tests that the choice from a mutually exclusive group accounts for the penalty of violating a
+/// soft constraint, which the solver subtracts from the expected logarithmic growth.
+#[test]
+fn test_allocate_with_candidate_groups_and_soft_constraint() {
+    let logger = create_test_logger();
+    let input = |soft_constraints: &str| -> AllocationInput {
+        serde_yaml::from_str(&format!(
+            "
+            long_only: true
+            max_individual_allocation: 0.3
+            {soft_constraints}
+            candidate_groups:
+              - name: Either
+                tickers: [A, B]
+                mutually_exclusive: true
+            candidates:
+              - name: A
+                ticker: A
+                description: Triples or halves
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Triples
+                    intrinsic_value: 3.0
+                    probability: 0.5
+                  - thesis: Halves
+                    intrinsic_value: 0.5
+                    probability: 0.5
+              - name: B
+                ticker: B
+                description: Quadruples or loses most
+                market_cap: 1.0
+                scenarios:
+                  - thesis: Quadruples
+                    intrinsic_value: 4.0
+                    probability: 0.5
+                  - thesis: Loses most
+                    intrinsic_value: 0.2
+                    probability: 0.5
+            "
+        ))
+        .unwrap()
+    };
+    let chosen = |input: AllocationInput| -> Vec<TickerAndFraction> {
+        allocate(input, &logger)
+            .result
+            .unwrap()
+            .allocations
+            .into_iter()
+            .filter(|a| a.fraction > ASSERTION_TOLERANCE)
+            .collect()
+    };
+
+    // Without the maximum individual allocation, A grows the fastest
+    let mut unconstrained = input("");
+    unconstrained.max_individual_allocation = None;
+    let allocations = chosen(unconstrained);
+    assert_eq!(1, allocations.len());
+    assert_eq!("A", allocations[0].ticker);
+    assert_close!(0.75, allocations[0].fraction, ASSERTION_TOLERANCE);
+
+    // A violates the soft maximum by more than B, so B is chosen although A still grows faster
+    // before subtracting the penalty
+    let allocations = chosen(input(
+        "soft_constraints: [{constraint: max_individual_allocation, weight: 0.5}]",
+    ));
+    assert_eq!(1, allocations.len());
+    assert_eq!("B", allocations[0].ticker);
+    assert!(allocations[0].fraction > 0.3);
+}
+
+#[test]
+fn test_allocate_with_soft_maximum_individual_allocation() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        long_only: true
+        max_individual_allocation: 0.2
+        soft_constraints:
+          - constraint: max_individual_allocation
+            weight: 100.0
+        candidates:
+          - name: A
+            ticker: A
+            description: Doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+          - name: B
+            ticker: B
+            description: Gains a tenth or loses a tenth
+            market_cap: 1.0
+            scenarios:
+              - thesis: Gains
+                intrinsic_value: 1.1
+                probability: 0.55
+              - thesis: Loses
+                intrinsic_value: 0.9
+                probability: 0.45
+        ",
+    )
+    .unwrap();
+    assert!(validate(&input, &logger).is_empty());
+
+    // The preference is exceeded, and each violation is reported with the allocated fraction
+    let result = allocate(input.clone(), &logger).result.unwrap();
+    let violations = result.soft_constraint_violations.unwrap();
+    assert_eq!(
+        vec![
+            "max_individual_allocation of A",
+            "max_individual_allocation of B"
+        ],
+        violations
+            .iter()
+            .map(|v| v.constraint.as_str())
+            .collect_vec()
+    );
+    for (allocation, violation) in result.allocations.iter().zip(&violations) {
+        assert_close!(
+            (allocation.fraction - 0.2).max(0.0),
+            violation.violation,
+            ASSERTION_TOLERANCE
+        );
+    }
+    assert!(violations[0].violation > 0.0);
+
+    // The same constraint as a mandate is enforced
+    let hard = AllocationInput {
+        soft_constraints: None,
+        ..input.clone()
+    };
+    let result = allocate(hard, &logger).result.unwrap();
+    assert!(result.soft_constraint_violations.is_none());
+    assert_close!(0.2, result.allocations[0].fraction, ASSERTION_TOLERANCE);
+
+    let not_given = AllocationInput {
+        max_individual_allocation: None,
+        ..input
+    };
+    assert_eq!(
+        vec![ValidationResult::PROBLEM(Problem {
+            code: "soft-constraint-not-given".to_string(),
+            message: "Constraint max_individual_allocation is designated as soft, but isn't \
+                given in the input."
+                .to_string(),
+            severity: Severity::ERROR,
+            suggestion: None,
+        })],
+        validate(&not_given, &logger)
+    );
+}
+
+#[test]
+fn test_allocate_with_ticker_max_individual_allocations() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        long_only: true
+        max_individual_allocation: 0.4
+        ticker_max_individual_allocations:
+          B: 0.1
+        candidates:
+          - name: A
+            ticker: A
+            description: Doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+          - name: B
+            ticker: B
+            description: Illiquid, doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+        ",
+    )
+    .unwrap();
+    assert!(validate(&input, &logger).is_empty());
+
+    // The cap of B overrides the maximum individual allocation, which still caps A
+    let result = allocate(input.clone(), &logger).result.unwrap();
+    let fraction = |ticker: &str| {
+        result
+            .allocations
+            .iter()
+            .find(|a| a.ticker == ticker)
+            .unwrap()
+            .fraction
+    };
+    assert_close!(0.4, fraction("A"), ASSERTION_TOLERANCE);
+    assert_close!(0.1, fraction("B"), ASSERTION_TOLERANCE);
+
+    let invalid = AllocationInput {
+        ticker_max_individual_allocations: Some(HashMap::from([
+            ("B".to_string(), -0.1),
+            ("C".to_string(), 0.1),
+        ])),
+        ..input
+    };
+    assert_eq!(
+        vec![
+            ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-ticker-max-individual-allocations".to_string(),
+                message: "Tickers C in the maximum individual allocations by ticker are not \
+                    found among the candidates. Check your input."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }),
+            ValidationResult::PROBLEM(Problem {
+                code: "maximum-individual-allocation-cannot-be-negative".to_string(),
+                message: "Maximum individual allocation of B cannot be negative. You provided \
+                    -0.1."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }),
+        ],
+        validate(&invalid, &logger)
+    );
+}
+
+#[test]
+fn test_allocate_with_target_risk() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        target_risk:
+          measure: conditional_value_at_risk
+          level: 0.1
+        candidates:
+          - name: A
+            ticker: A
+            description: Doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+        ",
+    )
+    .unwrap();
+    assert!(validate(&input, &logger).is_empty());
+
+    // Full Kelly allocates 0.5 with a conditional value at risk of 0.25, so 40% of it reaches 0.1
+    let result = allocate(input.clone(), &logger).result.unwrap();
+    let sizing = result.target_risk_sizing.unwrap();
+    assert_close!(0.4, sizing.kelly_fraction, ASSERTION_TOLERANCE);
+    assert_close!(0.1, sizing.risk, ASSERTION_TOLERANCE);
+    assert_close!(0.2, result.allocations[0].fraction, ASSERTION_TOLERANCE);
+
+    // Losing more than 20% requires a fraction above 0.4, i.e. above 80% of full Kelly
+    let probability_of_loss = AllocationInput {
+        target_risk: Some(TargetRisk {
+            measure: RiskMeasure::ProbabilityOfLoss,
+            level: 0.1,
+            loss: Some(0.2),
+            confidence_level: None,
+        }),
+        ..input.clone()
+    };
+    let result = allocate(probability_of_loss, &logger).result.unwrap();
+    let sizing = result.target_risk_sizing.unwrap();
+    assert_close!(0.8, sizing.kelly_fraction, ASSERTION_TOLERANCE);
+    assert_eq!(0.0, sizing.risk);
+
+    // Any fraction of the coin loses with a probability of 50%
+    let any_loss = AllocationInput {
+        target_risk: Some(TargetRisk {
+            measure: RiskMeasure::ProbabilityOfLoss,
+            level: 0.1,
+            loss: None,
+            confidence_level: None,
+        }),
+        ..input.clone()
+    };
+    assert_eq!(
+        "target-risk-unattainable",
+        allocate(any_loss, &logger).error.unwrap().code
+    );
+
+    let with_kelly_fraction = AllocationInput {
+        kelly_fraction: Some(0.5),
+        ..input
+    };
+    assert_eq!(
+        vec![ValidationResult::PROBLEM(Problem {
+            code: "target-risk-with-kelly-fraction".to_string(),
+            message: "Target risk determines the Kelly fraction, which contradicts the given \
+                Kelly fraction. Remove one of them."
+                .to_string(),
+            severity: Severity::ERROR,
+            suggestion: None,
+        })],
+        validate(&with_kelly_fraction, &logger)
+    );
+}
+
+#[test]
+fn test_allocate_with_current_weights() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        current_weights:
+          - ticker: A
+            fraction: 0.2
+          - ticker: B
+            fraction: 0.1
+        candidates:
+          - name: A
+            ticker: A
+            description: Doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+          - name: B
+            ticker: B
+            description: Loses on average
+            market_cap: 1.0
+            scenarios:
+              - thesis: Gains
+                intrinsic_value: 1.1
+                probability: 0.4
+              - thesis: Loses
+                intrinsic_value: 0.9
+                probability: 0.6
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        vec!["negative-expected-return-for-a-company"],
+        validate(&input, &logger)
+            .into_iter()
+            .filter_map(|v| match v {
+                ValidationResult::PROBLEM(p) => Some(p.code),
+                ValidationResult::OK => None,
+            })
+            .collect_vec()
+    );
+
+    // B is dropped for its negative expected return, so it's sold completely
+    let rebalancing = allocate(input.clone(), &logger)
+        .result
+        .unwrap()
+        .rebalancing
+        .unwrap();
+    assert_eq!(
+        vec!["A", "B"],
+        rebalancing
+            .trades
+            .iter()
+            .map(|t| t.ticker.as_str())
+            .collect_vec()
+    );
+    assert_close!(0.3, rebalancing.trades[0].change, ASSERTION_TOLERANCE);
+    assert_close!(-0.1, rebalancing.trades[1].change, ASSERTION_TOLERANCE);
+    assert_close!(0.4, rebalancing.turnover, ASSERTION_TOLERANCE);
+
+    // The trades of the candidates are limited, while the holdings of dropped ones are still sold
+    let limited = AllocationInput {
+        max_turnover: Some(0.1),
+        ..input.clone()
+    };
+    let result = allocate(limited, &logger).result.unwrap();
+    assert_close!(0.3, result.allocations[0].fraction, ASSERTION_TOLERANCE);
+    let turnover = result.rebalancing.unwrap().turnover;
+    assert_close!(0.2, turnover, ASSERTION_TOLERANCE);
+
+    // The penalty of the trades shifts the optimality condition of the Kelly criterion
+    let penalized = AllocationInput {
+        turnover_penalty: Some(1.0),
+        ..input.clone()
+    };
+    let fraction = allocate(penalized, &logger).result.unwrap().allocations[0].fraction;
+    assert!(fraction > 0.2 && fraction < 0.5);
+    assert_close!(
+        0.0,
+        0.5 / (1.0 + fraction) - 0.25 / (1.0 - 0.5 * fraction) - (fraction - 0.2),
+        ASSERTION_TOLERANCE
+    );
+
+    let unknown = AllocationInput {
+        current_weights: Some(vec![TickerAndFraction {
+            ticker: "C".to_string(),
+            fraction: 0.1,
+        }]),
+        max_turnover: Some(-0.1),
+        ..input
+    };
+    assert_eq!(
+        vec![
+            ValidationResult::PROBLEM(Problem {
+                code: "unknown-tickers-in-current-weights".to_string(),
+                message: "Tickers C in the current weights are not found among the candidates. \
+                    Add the holdings to the candidates, e.g. with a scenario for selling them."
+                    .to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }),
+            ValidationResult::PROBLEM(Problem {
+                code: "maximum-turnover-cannot-be-negative".to_string(),
+                message: "Maximum turnover cannot be negative. You provided -0.1.".to_string(),
+                severity: Severity::ERROR,
+                suggestion: None,
+            }),
+        ],
+        // After the warning about the negative expected return of B
+        validate(&unknown, &logger)[1..].to_vec()
+    );
+}
+
+#[test]
+fn test_allocate_with_risk_free_rate() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        long_only: true
+        risk_free_rate: 0.1
+        candidates:
+          - name: A
+            ticker: A
+            description: Doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+        ",
+    )
+    .unwrap();
+    assert!(validate(&input, &logger).is_empty());
+
+    // Cash earning 10% makes the coin less attractive than the 50% allocated without it, and the
+    // return of the cash is included in the statistics
+    let result = allocate(input.clone(), &logger).result.unwrap();
+    let fraction = result.allocations[0].fraction;
+    assert_close!(0.33 / 1.08, fraction, ASSERTION_TOLERANCE);
+    assert_close!(1.0 - fraction, result.cash, ASSERTION_TOLERANCE);
+    assert_close!(
+        0.25 * fraction + 0.1 * result.cash,
+        result.analysis.expected_return,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        0.5 * (1.1 + 0.9 * fraction).ln() + 0.5 * (1.1 - 0.6 * fraction).ln(),
+        result.solver_metadata.expected_log_growth,
+        ASSERTION_TOLERANCE
+    );
+
+    let invalid = AllocationInput {
+        risk_free_rate: Some(-1.0),
+        ..input
+    };
+    assert_eq!(
+        vec![ValidationResult::PROBLEM(Problem {
+            code: "invalid-risk-free-rate".to_string(),
+            message: "Risk-free rate must be greater than -1. You provided -1.".to_string(),
+            severity: Severity::ERROR,
+            suggestion: None,
+        })],
+        validate(&invalid, &logger)
+    );
+}
+
+/// Tests that the cash of the conviction sizing and of the benchmark portfolio earns the risk-free
+/// rate like the cash of the allocation, so that their growth is comparable.
+#[test]
+fn test_allocate_with_risk_free_rate_and_alternatives() {
+    let logger = create_test_logger();
+    let mut input: AllocationInput = serde_yaml::from_str(
+        "
+        long_only: true
+        risk_free_rate: 0.1
+        conviction_tiers:
+          tiers:
+            A: medium
+        candidates:
+          - name: A
+            ticker: A
+            description: Doubles or halves
+            market_cap: 1.0
+            scenarios:
+              - thesis: Doubles
+                intrinsic_value: 2.0
+                probability: 0.5
+              - thesis: Halves
+                intrinsic_value: 0.5
+                probability: 0.5
+        ",
+    )
+    .unwrap();
+    input.benchmark_portfolio = Some(BenchmarkPortfolio::Weights(vec![TickerAndFraction {
+        ticker: "A".to_string(),
+        fraction: 0.5,
+    }]));
+    let growth = |fraction: f64| {
+        let cash_return = 0.1 * (1.0 - fraction);
+        0.5 * (1.0 + cash_return + fraction).ln() + 0.5 * (1.0 + cash_return - 0.5 * fraction).ln()
+    };
+
+    let result = allocate(input, &logger).result.unwrap();
+    let fraction = result.allocations[0].fraction;
+    assert_close!(
+        growth(fraction),
+        result.solver_metadata.expected_log_growth,
+        ASSERTION_TOLERANCE
+    );
+
+    let sizing = result.conviction_sizing.unwrap();
+    assert_close!(0.15, sizing.allocations[0].fraction, ASSERTION_TOLERANCE);
+    assert_close!(
+        growth(0.15),
+        sizing.expected_log_growth,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        growth(fraction) - growth(0.15),
+        sizing.growth_give_up,
+        ASSERTION_TOLERANCE
+    );
+
+    let comparison = result.benchmark_comparison.unwrap();
+    assert_close!(
+        growth(0.5),
+        comparison.benchmark_expected_log_growth,
+        ASSERTION_TOLERANCE
+    );
+    assert_close!(
+        0.25 * 0.5 + 0.1 * 0.5,
+        comparison.benchmark_expected_return,
+        ASSERTION_TOLERANCE
+    );
+}
+
+#[test]
+fn test_allocate_with_margin_interest_rate() {
+    let logger = create_test_logger();
+    let input: AllocationInput = serde_yaml::from_str(
+        "
+        margin_interest_rate: 0.05
+        candidates:
+          - name: A
+            ticker: A
+            description: Gains half or loses a tenth
+            market_cap: 1.0
+            scenarios:
+              - thesis: Gains
+                intrinsic_value: 1.5
+                probability: 0.5
+              - thesis: Loses
+                intrinsic_value: 0.9
+                probability: 0.5
+        ",
+    )
+    .unwrap();
+    assert!(validate(&input, &logger).is_empty());
+
+    // Borrowing at 5% reduces the leverage from 4 times, and the interest on the borrowed cash is
+    // subtracted from the expected return
+    let result = allocate(input.clone(), &logger).result.unwrap();
+    let fraction = result.allocations[0].fraction;
+    assert_close!(0.315 / 0.135, fraction, ASSERTION_TOLERANCE);
+    assert_close!(1.0 - fraction, result.cash, ASSERTION_TOLERANCE);
+    assert_close!(
+        0.2 * fraction + 0.05 * result.cash,
+        result.analysis.expected_return,
+        ASSERTION_TOLERANCE
+    );
+
+    let invalid = AllocationInput {
+        risk_free_rate: Some(0.06),
+        ..input
+    };
+    assert_eq!(
+        vec![ValidationResult::PROBLEM(Problem {
+            code: "margin-interest-rate-below-risk-free-rate".to_string(),
+            message: "Margin interest rate must be at least the risk-free rate of 0.06. You \
+                provided 0.05."
+                .to_string(),
+            severity: Severity::ERROR,
+            suggestion: None,
+        })],
+        validate(&invalid, &logger)
+    );
 }